@@ -0,0 +1,203 @@
+// async_domain_repository.rs
+// Variante asíncrona de `DomainRepository` y adaptadores en ambos sentidos
+// (feature `async`): `AsyncDomainAdapter` ejecuta un repositorio síncrono en
+// el pool bloqueante de tokio y `BlockingDomainAdapter` expone uno asíncrono
+// como `DomainRepository` para los steps del workflow.
+use crate::{DomainError, DomainRepository, Molecule, MoleculeFamily, OwnedFamilyProperty, OwnedMolecularProperty};
+use async_trait::async_trait;
+use std::sync::Arc;
+use tokio::runtime::Handle;
+use uuid::Uuid;
+
+/// Versión asíncrona de `DomainRepository`, con los mismos métodos.
+#[async_trait]
+pub trait AsyncDomainRepository: Send + Sync {
+  async fn save_family(&self, family: MoleculeFamily) -> Result<Uuid, DomainError>;
+  async fn get_family(&self, id: &Uuid) -> Result<Option<MoleculeFamily>, DomainError>;
+  async fn save_molecule(&self, molecule: Molecule) -> Result<String, DomainError>;
+  async fn save_molecules_bulk(&self, molecules: Vec<Molecule>) -> Result<Vec<Result<String, DomainError>>, DomainError>;
+  async fn get_molecule(&self, inchikey: &str) -> Result<Option<Molecule>, DomainError>;
+  async fn list_families(&self) -> Result<Vec<MoleculeFamily>, DomainError>;
+  async fn save_family_property(&self, prop: OwnedFamilyProperty) -> Result<Uuid, DomainError>;
+  async fn get_family_properties(&self, family_id: &Uuid) -> Result<Vec<OwnedFamilyProperty>, DomainError>;
+  async fn save_molecular_property(&self, prop: OwnedMolecularProperty) -> Result<Uuid, DomainError>;
+  async fn save_molecular_properties_bulk(&self,
+                                          props: Vec<OwnedMolecularProperty>)
+                                          -> Result<Vec<Result<Uuid, DomainError>>, DomainError>;
+  async fn get_molecular_properties(&self, inchikey: &str) -> Result<Vec<OwnedMolecularProperty>, DomainError>;
+  async fn list_molecules(&self) -> Result<Vec<Molecule>, DomainError>;
+  async fn delete_molecule(&self, inchikey: &str) -> Result<(), DomainError>;
+  async fn delete_family(&self, id: &Uuid) -> Result<(), DomainError>;
+  async fn add_molecule_to_family(&self, family_id: &Uuid, molecule: Molecule) -> Result<Uuid, DomainError>;
+  async fn remove_molecule_from_family(&self, family_id: &Uuid, inchikey: &str) -> Result<Uuid, DomainError>;
+}
+
+/// Adapta un `DomainRepository` síncrono a `AsyncDomainRepository` usando
+/// `tokio::task::spawn_blocking` en cada llamada. Debe usarse dentro de un
+/// runtime tokio.
+#[derive(Clone)]
+pub struct AsyncDomainAdapter {
+  inner: Arc<dyn DomainRepository>,
+}
+
+impl AsyncDomainAdapter {
+  pub fn new(inner: Arc<dyn DomainRepository>) -> Self {
+    Self { inner }
+  }
+
+  /// Repositorio síncrono envuelto.
+  pub fn inner(&self) -> &Arc<dyn DomainRepository> {
+    &self.inner
+  }
+
+  async fn run<T, F>(&self, f: F) -> Result<T, DomainError>
+    where T: Send + 'static,
+          F: FnOnce(&dyn DomainRepository) -> Result<T, DomainError> + Send + 'static
+  {
+    let inner = Arc::clone(&self.inner);
+    tokio::task::spawn_blocking(move || f(inner.as_ref())).await
+                                                          .map_err(|e| {
+                                                            DomainError::ExternalError(format!("spawn_blocking: {}", e))
+                                                          })?
+  }
+}
+
+#[async_trait]
+impl AsyncDomainRepository for AsyncDomainAdapter {
+  async fn save_family(&self, family: MoleculeFamily) -> Result<Uuid, DomainError> {
+    self.run(move |r| r.save_family(family)).await
+  }
+  async fn get_family(&self, id: &Uuid) -> Result<Option<MoleculeFamily>, DomainError> {
+    let id = *id;
+    self.run(move |r| r.get_family(&id)).await
+  }
+  async fn save_molecule(&self, molecule: Molecule) -> Result<String, DomainError> {
+    self.run(move |r| r.save_molecule(molecule)).await
+  }
+  async fn save_molecules_bulk(&self, molecules: Vec<Molecule>) -> Result<Vec<Result<String, DomainError>>, DomainError> {
+    self.run(move |r| r.save_molecules_bulk(molecules)).await
+  }
+  async fn get_molecule(&self, inchikey: &str) -> Result<Option<Molecule>, DomainError> {
+    let inchikey = inchikey.to_string();
+    self.run(move |r| r.get_molecule(&inchikey)).await
+  }
+  async fn list_families(&self) -> Result<Vec<MoleculeFamily>, DomainError> {
+    self.run(|r| r.list_families()).await
+  }
+  async fn save_family_property(&self, prop: OwnedFamilyProperty) -> Result<Uuid, DomainError> {
+    self.run(move |r| r.save_family_property(prop)).await
+  }
+  async fn get_family_properties(&self, family_id: &Uuid) -> Result<Vec<OwnedFamilyProperty>, DomainError> {
+    let family_id = *family_id;
+    self.run(move |r| r.get_family_properties(&family_id)).await
+  }
+  async fn save_molecular_property(&self, prop: OwnedMolecularProperty) -> Result<Uuid, DomainError> {
+    self.run(move |r| r.save_molecular_property(prop)).await
+  }
+  async fn save_molecular_properties_bulk(&self,
+                                          props: Vec<OwnedMolecularProperty>)
+                                          -> Result<Vec<Result<Uuid, DomainError>>, DomainError> {
+    self.run(move |r| r.save_molecular_properties_bulk(props)).await
+  }
+  async fn get_molecular_properties(&self, inchikey: &str) -> Result<Vec<OwnedMolecularProperty>, DomainError> {
+    let inchikey = inchikey.to_string();
+    self.run(move |r| r.get_molecular_properties(&inchikey)).await
+  }
+  async fn list_molecules(&self) -> Result<Vec<Molecule>, DomainError> {
+    self.run(|r| r.list_molecules()).await
+  }
+  async fn delete_molecule(&self, inchikey: &str) -> Result<(), DomainError> {
+    let inchikey = inchikey.to_string();
+    self.run(move |r| r.delete_molecule(&inchikey)).await
+  }
+  async fn delete_family(&self, id: &Uuid) -> Result<(), DomainError> {
+    let id = *id;
+    self.run(move |r| r.delete_family(&id)).await
+  }
+  async fn add_molecule_to_family(&self, family_id: &Uuid, molecule: Molecule) -> Result<Uuid, DomainError> {
+    let family_id = *family_id;
+    self.run(move |r| r.add_molecule_to_family(&family_id, molecule)).await
+  }
+  async fn remove_molecule_from_family(&self, family_id: &Uuid, inchikey: &str) -> Result<Uuid, DomainError> {
+    let (family_id, inchikey) = (*family_id, inchikey.to_string());
+    self.run(move |r| r.remove_molecule_from_family(&family_id, &inchikey)).await
+  }
+}
+
+/// Expone un `AsyncDomainRepository` como `DomainRepository` síncrono con
+/// `Handle::block_on`. Igual que `flow::BlockingFlowAdapter`, no debe usarse
+/// desde los hilos del runtime (usar hilos propios o `spawn_blocking`).
+#[derive(Clone)]
+pub struct BlockingDomainAdapter {
+  inner: Arc<dyn AsyncDomainRepository>,
+  handle: Handle,
+}
+
+impl BlockingDomainAdapter {
+  pub fn new(inner: Arc<dyn AsyncDomainRepository>, handle: Handle) -> Self {
+    Self { inner, handle }
+  }
+
+  /// Igual que `new` tomando el runtime actual (`Handle::current`).
+  pub fn from_current(inner: Arc<dyn AsyncDomainRepository>) -> Self {
+    Self::new(inner, Handle::current())
+  }
+
+  /// Repositorio asíncrono envuelto.
+  pub fn inner(&self) -> &Arc<dyn AsyncDomainRepository> {
+    &self.inner
+  }
+}
+
+impl DomainRepository for BlockingDomainAdapter {
+  fn save_family(&self, family: MoleculeFamily) -> Result<Uuid, DomainError> {
+    self.handle.block_on(self.inner.save_family(family))
+  }
+  fn get_family(&self, id: &Uuid) -> Result<Option<MoleculeFamily>, DomainError> {
+    self.handle.block_on(self.inner.get_family(id))
+  }
+  fn save_molecule(&self, molecule: Molecule) -> Result<String, DomainError> {
+    self.handle.block_on(self.inner.save_molecule(molecule))
+  }
+  fn save_molecules_bulk(&self, molecules: Vec<Molecule>) -> Result<Vec<Result<String, DomainError>>, DomainError> {
+    self.handle.block_on(self.inner.save_molecules_bulk(molecules))
+  }
+  fn get_molecule(&self, inchikey: &str) -> Result<Option<Molecule>, DomainError> {
+    self.handle.block_on(self.inner.get_molecule(inchikey))
+  }
+  fn list_families(&self) -> Result<Vec<MoleculeFamily>, DomainError> {
+    self.handle.block_on(self.inner.list_families())
+  }
+  fn save_family_property(&self, prop: OwnedFamilyProperty) -> Result<Uuid, DomainError> {
+    self.handle.block_on(self.inner.save_family_property(prop))
+  }
+  fn get_family_properties(&self, family_id: &Uuid) -> Result<Vec<OwnedFamilyProperty>, DomainError> {
+    self.handle.block_on(self.inner.get_family_properties(family_id))
+  }
+  fn save_molecular_property(&self, prop: OwnedMolecularProperty) -> Result<Uuid, DomainError> {
+    self.handle.block_on(self.inner.save_molecular_property(prop))
+  }
+  fn save_molecular_properties_bulk(&self,
+                                    props: Vec<OwnedMolecularProperty>)
+                                    -> Result<Vec<Result<Uuid, DomainError>>, DomainError> {
+    self.handle.block_on(self.inner.save_molecular_properties_bulk(props))
+  }
+  fn get_molecular_properties(&self, inchikey: &str) -> Result<Vec<OwnedMolecularProperty>, DomainError> {
+    self.handle.block_on(self.inner.get_molecular_properties(inchikey))
+  }
+  fn list_molecules(&self) -> Result<Vec<Molecule>, DomainError> {
+    self.handle.block_on(self.inner.list_molecules())
+  }
+  fn delete_molecule(&self, inchikey: &str) -> Result<(), DomainError> {
+    self.handle.block_on(self.inner.delete_molecule(inchikey))
+  }
+  fn delete_family(&self, id: &Uuid) -> Result<(), DomainError> {
+    self.handle.block_on(self.inner.delete_family(id))
+  }
+  fn add_molecule_to_family(&self, family_id: &Uuid, molecule: Molecule) -> Result<Uuid, DomainError> {
+    self.handle.block_on(self.inner.add_molecule_to_family(family_id, molecule))
+  }
+  fn remove_molecule_from_family(&self, family_id: &Uuid, inchikey: &str) -> Result<Uuid, DomainError> {
+    self.handle.block_on(self.inner.remove_molecule_from_family(family_id, inchikey))
+  }
+}
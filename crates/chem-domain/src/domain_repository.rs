@@ -1,23 +1,40 @@
 // repository.rs
 use crate::{DomainError, Molecule, MoleculeFamily};
 use serde_json::Value;
+use std::sync::Arc;
 use uuid::Uuid;
 
 pub trait DomainRepository: Send + Sync {
   fn save_family(&self, family: MoleculeFamily) -> Result<Uuid, DomainError>;
   fn get_family(&self, id: &Uuid) -> Result<Option<MoleculeFamily>, DomainError>;
   fn save_molecule(&self, molecule: Molecule) -> Result<String, DomainError>;
+  /// Guarda varias moléculas en una sola transacción. El `Err` externo indica
+  /// que no se guardó ninguna; si no, hay un resultado por molécula, en el
+  /// mismo orden (el inchikey o el motivo por el que esa se descartó).
+  fn save_molecules_bulk(&self, molecules: Vec<Molecule>) -> Result<Vec<Result<String, DomainError>>, DomainError>;
   fn get_molecule(&self, inchikey: &str) -> Result<Option<Molecule>, DomainError>;
   fn list_families(&self) -> Result<Vec<MoleculeFamily>, DomainError>;
   fn save_family_property(&self, prop: OwnedFamilyProperty) -> Result<Uuid, DomainError>;
   fn get_family_properties(&self, family_id: &Uuid) -> Result<Vec<OwnedFamilyProperty>, DomainError>;
   fn save_molecular_property(&self, prop: OwnedMolecularProperty) -> Result<Uuid, DomainError>;
+  /// Como `save_molecules_bulk`, para propiedades moleculares. Un `id` que ya
+  /// existe (o repetido en el lote) da `ValidationError` en su posición y no
+  /// impide guardar las demás.
+  fn save_molecular_properties_bulk(&self,
+                                    props: Vec<OwnedMolecularProperty>)
+                                    -> Result<Vec<Result<Uuid, DomainError>>, DomainError>;
   fn get_molecular_properties(&self, inchikey: &str) -> Result<Vec<OwnedMolecularProperty>, DomainError>;
   fn list_molecules(&self) -> Result<Vec<Molecule>, DomainError>;
   fn delete_molecule(&self, inchikey: &str) -> Result<(), DomainError>;
   fn delete_family(&self, id: &Uuid) -> Result<(), DomainError>;
   fn add_molecule_to_family(&self, family_id: &Uuid, molecule: Molecule) -> Result<Uuid, DomainError>;
   fn remove_molecule_from_family(&self, family_id: &Uuid, inchikey: &str) -> Result<Uuid, DomainError>;
+  /// Envuelve `repo` con los mismos decoradores que este (principal,
+  /// auditoría…), como `FlowRepository::rewrap`. Las implementaciones base
+  /// lo devuelven tal cual.
+  fn rewrap(&self, repo: Arc<dyn DomainRepository>) -> Arc<dyn DomainRepository> {
+    repo
+  }
 }
 
 #[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
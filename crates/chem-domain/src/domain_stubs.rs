@@ -2,7 +2,9 @@ use crate::domain_repository::DomainRepository;
 use crate::DomainError;
 use crate::{Molecule, MoleculeFamily, OwnedFamilyProperty, OwnedMolecularProperty};
 use serde_json::json;
+use serde::Serialize;
 use std::collections::HashMap;
+use std::hash::Hash;
 use std::sync::{Arc, Mutex, MutexGuard};
 use uuid::Uuid;
 pub struct DomainStubs;
@@ -20,11 +22,88 @@ impl InMemoryDomainRepository {
            family_properties: Arc::new(Mutex::new(HashMap::new())),
            molecular_properties: Arc::new(Mutex::new(HashMap::new())) }
   }
+  /// Copia de las familias, moléculas y propiedades (la unidad de trabajo
+  /// en memoria de `chem-persistence` la usa con `from_state` y `merge`).
+  pub fn checkpoint(&self) -> Result<InMemoryDomainState, DomainError> {
+    Ok(InMemoryDomainState { families: self.lock_map(&self.families, "families")?.clone(),
+                             molecules: self.lock_map(&self.molecules, "molecules")?.clone(),
+                             family_properties: self.lock_map(&self.family_properties, "family_properties")?.clone(),
+                             molecular_properties: self.lock_map(&self.molecular_properties, "molecular_properties")?
+                                                       .clone() })
+  }
+  /// Repositorio independiente con el contenido de `state`.
+  pub fn from_state(state: InMemoryDomainState) -> Self {
+    Self { families: Arc::new(Mutex::new(state.families)),
+           molecules: Arc::new(Mutex::new(state.molecules)),
+           family_properties: Arc::new(Mutex::new(state.family_properties)),
+           molecular_properties: Arc::new(Mutex::new(state.molecular_properties)) }
+  }
+  /// Aplica lo que cambió de `base` a `mine`, entrada a entrada. Devuelve
+  /// `false` sin aplicar nada si alguna de esas entradas cambió también en
+  /// `self` desde `base`.
+  pub fn merge(&self, base: &InMemoryDomainState, mine: InMemoryDomainState) -> Result<bool, DomainError> {
+    let mut families = self.lock_map(&self.families, "families")?;
+    let mut molecules = self.lock_map(&self.molecules, "molecules")?;
+    let mut family_properties = self.lock_map(&self.family_properties, "family_properties")?;
+    let mut molecular_properties = self.lock_map(&self.molecular_properties, "molecular_properties")?;
+    let (Some(f), Some(m), Some(fp), Some(mp)) =
+      (changes(&families, &base.families, &mine.families),
+       changes(&molecules, &base.molecules, &mine.molecules),
+       changes(&family_properties, &base.family_properties, &mine.family_properties),
+       changes(&molecular_properties, &base.molecular_properties, &mine.molecular_properties))
+    else {
+      return Ok(false);
+    };
+    apply_changes(&mut families, f);
+    apply_changes(&mut molecules, m);
+    apply_changes(&mut family_properties, fp);
+    apply_changes(&mut molecular_properties, mp);
+    Ok(true)
+  }
   // Helper to map poisoned mutex errors into DomainError
   fn lock_map<'a, T>(&'a self, m: &'a Mutex<T>, name: &str) -> Result<MutexGuard<'a, T>, DomainError> {
     m.lock().map_err(|e| DomainError::ExternalError(format!("Mutex '{}' poisoned: {}", name, e)))
   }
 }
+/// Entradas de `mine` que difieren de `base` (`None` si se borró), o `None`
+/// si alguna también cambió en `current` con otro valor. Se compara la
+/// serialización: la igualdad de `MoleculeFamily` ignora nombre y metadatos.
+fn changes<K: Eq + Hash + Clone, V: Serialize + Clone>(current: &HashMap<K, V>,
+                                                      base: &HashMap<K, V>,
+                                                      mine: &HashMap<K, V>)
+                                                      -> Option<Vec<(K, Option<V>)>> {
+  let json = |v: Option<&V>| v.map(|v| serde_json::to_value(v).ok());
+  let same = |a: Option<&V>, b: Option<&V>| json(a) == json(b);
+  let mut out = Vec::new();
+  for key in base.keys().chain(mine.keys().filter(|k| !base.contains_key(*k))) {
+    let (before, after) = (base.get(key), mine.get(key));
+    if same(before, after) {
+      continue;
+    }
+    let now = current.get(key);
+    if !same(now, before) && !same(now, after) {
+      return None;
+    }
+    out.push((key.clone(), after.cloned()));
+  }
+  Some(out)
+}
+fn apply_changes<K: Eq + Hash, V>(map: &mut HashMap<K, V>, changes: Vec<(K, Option<V>)>) {
+  for (key, value) in changes {
+    match value {
+      Some(v) => map.insert(key, v),
+      None => map.remove(&key),
+    };
+  }
+}
+/// Contenido de un `InMemoryDomainRepository` tomado con `checkpoint`.
+#[derive(Clone)]
+pub struct InMemoryDomainState {
+  families: HashMap<Uuid, MoleculeFamily>,
+  molecules: HashMap<String, Molecule>,
+  family_properties: HashMap<Uuid, OwnedFamilyProperty>,
+  molecular_properties: HashMap<Uuid, OwnedMolecularProperty>,
+}
 impl DomainRepository for InMemoryDomainRepository {
   fn save_family(&self, family: MoleculeFamily) -> Result<Uuid, DomainError> {
     let id = family.id();
@@ -42,6 +121,16 @@ impl DomainRepository for InMemoryDomainRepository {
     molecules.insert(key.clone(), molecule);
     Ok(key)
   }
+  fn save_molecules_bulk(&self, molecules: Vec<Molecule>) -> Result<Vec<Result<String, DomainError>>, DomainError> {
+    let mut map = self.lock_map(&self.molecules, "molecules")?;
+    Ok(molecules.into_iter()
+                .map(|m| {
+                  let key = m.inchikey().to_string();
+                  map.insert(key.clone(), m);
+                  Ok(key)
+                })
+                .collect())
+  }
   fn get_molecule(&self, inchikey: &str) -> Result<Option<Molecule>, DomainError> {
     let molecules = self.lock_map(&self.molecules, "molecules")?;
     Ok(molecules.get(inchikey).cloned())
@@ -66,6 +155,21 @@ impl DomainRepository for InMemoryDomainRepository {
     map.insert(id, prop);
     Ok(id)
   }
+  fn save_molecular_properties_bulk(&self,
+                                    props: Vec<OwnedMolecularProperty>)
+                                    -> Result<Vec<Result<Uuid, DomainError>>, DomainError> {
+    let mut map = self.lock_map(&self.molecular_properties, "molecular_properties")?;
+    Ok(props.into_iter()
+            .map(|p| {
+              let id = p.id;
+              if map.contains_key(&id) {
+                return Err(DomainError::ValidationError(format!("La propiedad molecular {} ya existe", id)));
+              }
+              map.insert(id, p);
+              Ok(id)
+            })
+            .collect())
+  }
   fn get_molecular_properties(&self, inchikey: &str) -> Result<Vec<OwnedMolecularProperty>, DomainError> {
     let map = self.lock_map(&self.molecular_properties, "molecular_properties")?;
     Ok(map.values().filter(|p| p.molecule_inchikey == inchikey).cloned().collect())
@@ -220,6 +324,37 @@ mod tests {
     Ok(())
   }
   #[test]
+  fn bulk_saves_report_per_item_results() -> Result<(), DomainError> {
+    let repo = InMemoryDomainRepository::new();
+    let m1 = crate::Molecule::from_parts("KKKKKKKKKKKKKK-LLLLLLLLLL-M",
+                                         "CCO",
+                                         "InChI=1S/C2H6O/c1-2-3/h3H,2H2,1H3",
+                                         json!({}))?;
+    let m2 = crate::Molecule::from_parts("NNNNNNNNNNNNNN-OOOOOOOOOO-P",
+                                         "CCN",
+                                         "InChI=1S/C2H7N/c1-2-3/h3H,2H2,1H3",
+                                         json!({}))?;
+    let keys = repo.save_molecules_bulk(vec![m1.clone(), m2.clone()])?;
+    assert_eq!(keys.into_iter().collect::<Result<Vec<_>, _>>()?,
+               vec![m1.inchikey().to_string(), m2.inchikey().to_string()]);
+    assert_eq!(repo.list_molecules()?.len(), 2);
+    let prop = |property_type: &str| OwnedMolecularProperty { id: uuid::Uuid::new_v4(),
+                                                              molecule_inchikey: m1.inchikey().to_string(),
+                                                              property_type: property_type.into(),
+                                                              value: json!(1.0),
+                                                              quality: None,
+                                                              preferred: true,
+                                                              value_hash: "h".into(),
+                                                              metadata: json!({}) };
+    let (logp, tpsa) = (prop("logP"), prop("TPSA"));
+    let results = repo.save_molecular_properties_bulk(vec![logp.clone(), tpsa.clone(), logp.clone()])?;
+    assert_eq!(results[0].as_ref().ok(), Some(&logp.id));
+    assert_eq!(results[1].as_ref().ok(), Some(&tpsa.id));
+    assert!(matches!(results[2], Err(DomainError::ValidationError(_))), "id repetido");
+    assert_eq!(repo.get_molecular_properties(m1.inchikey())?.len(), 2);
+    Ok(())
+  }
+  #[test]
   fn mutex_poisoning_returns_error() {
     use std::thread;
     let repo = InMemoryDomainRepository::new();
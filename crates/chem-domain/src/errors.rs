@@ -10,6 +10,8 @@ pub enum DomainError {
   ExternalError(String),
   #[error("Error de serialización: {0}")]
   SerializationError(String),
+  #[error("Operación no permitida: {0}")]
+  Forbidden(String),
 }
 
 impl From<EngineError> for DomainError {
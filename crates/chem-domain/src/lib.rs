@@ -1,3 +1,5 @@
+#[cfg(feature = "async")]
+mod async_domain_repository;
 mod domain_repository;
 mod domain_stubs;
 mod errors;
@@ -5,9 +7,11 @@ mod family_property;
 mod molecular_property;
 mod molecule;
 mod molecule_family;
+#[cfg(feature = "async")]
+pub use async_domain_repository::{AsyncDomainAdapter, AsyncDomainRepository, BlockingDomainAdapter};
 pub use domain_repository::DomainRepository;
 
-pub use domain_stubs::{DomainStubs, InMemoryDomainRepository};
+pub use domain_stubs::{DomainStubs, InMemoryDomainRepository, InMemoryDomainState};
 pub use errors::DomainError;
 pub use family_property::FamilyProperty;
 pub use molecular_property::MolecularProperty;
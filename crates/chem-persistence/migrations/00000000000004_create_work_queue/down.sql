@@ -0,0 +1,2 @@
+DROP INDEX IF EXISTS idx_work_queue_lease;
+DROP TABLE IF EXISTS work_queue;
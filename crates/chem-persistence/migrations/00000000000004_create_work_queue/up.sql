@@ -0,0 +1,11 @@
+-- Cola de trabajo para workers: un item por flow con lease renovable.
+-- Lease y encolado en milisegundos: el lease no trunca duraciones de menos
+-- de un segundo y el orden FIFO distingue items del mismo segundo.
+CREATE TABLE IF NOT EXISTS work_queue (
+  flow_id TEXT PRIMARY KEY,
+  worker_id TEXT,
+  lease_expires_at_ms BIGINT,
+  attempts BIGINT NOT NULL DEFAULT 0,
+  enqueued_at_ms BIGINT NOT NULL
+);
+CREATE INDEX IF NOT EXISTS idx_work_queue_lease ON work_queue (lease_expires_at_ms, enqueued_at_ms);
@@ -0,0 +1,9 @@
+-- Locks exclusivos por flow con expiración. Lo usa el backend SQLite; en
+-- Postgres `lock_for_update` usa advisory locks y esta tabla queda vacía.
+-- La expiración va en milisegundos para no truncar TTLs de menos de un
+-- segundo (`with_lock_ttl`).
+CREATE TABLE IF NOT EXISTS flow_locks (
+  flow_id TEXT PRIMARY KEY,
+  owner TEXT NOT NULL,
+  expires_at_ms BIGINT NOT NULL
+);
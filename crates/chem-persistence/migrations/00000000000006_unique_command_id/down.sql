@@ -0,0 +1,3 @@
+DROP INDEX IF EXISTS ux_flow_data_flow_command;
+-- NOTE: SQLite no soporta DROP COLUMN de forma portable; la columna
+-- `persisted_version` se conserva. En Postgres: ALTER TABLE flow_data DROP COLUMN persisted_version;
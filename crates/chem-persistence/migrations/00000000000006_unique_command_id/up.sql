@@ -0,0 +1,12 @@
+-- Idempotencia por command_id: versión asignada al persistir (para devolver
+-- el PersistResult original en reintentos) e índice único por flow.
+ALTER TABLE flow_data ADD COLUMN persisted_version BIGINT;
+-- Los duplicados previos conservan el registro más antiguo como dueño del
+-- command_id; al resto se le quita para poder crear el índice único.
+UPDATE flow_data SET command_id = NULL
+WHERE command_id IS NOT NULL
+  AND EXISTS (SELECT 1 FROM flow_data d2
+              WHERE d2.flow_id = flow_data.flow_id
+                AND d2.command_id = flow_data.command_id
+                AND (d2.cursor < flow_data.cursor OR (d2.cursor = flow_data.cursor AND d2.id < flow_data.id)));
+CREATE UNIQUE INDEX IF NOT EXISTS ux_flow_data_flow_command ON flow_data (flow_id, command_id);
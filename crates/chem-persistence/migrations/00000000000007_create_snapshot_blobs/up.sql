@@ -0,0 +1,7 @@
+-- Blobs de snapshot para `DbSnapshotStore` (`SNAPSHOT_STORE=db`); la
+-- columna `snapshots.state_ptr` guarda solo la `key`.
+CREATE TABLE IF NOT EXISTS snapshot_blobs (
+  key TEXT PRIMARY KEY,
+  data BYTEA NOT NULL,
+  created_at_ts BIGINT NOT NULL
+);
@@ -0,0 +1,3 @@
+DROP INDEX IF EXISTS idx_artifact_refs_key;
+DROP TABLE IF EXISTS artifact_refs;
+DROP TABLE IF EXISTS artifacts;
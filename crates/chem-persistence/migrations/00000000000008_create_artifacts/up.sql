@@ -0,0 +1,19 @@
+-- Artifacts direccionados por contenido (`ArtifactStore`): el blob vive en
+-- `ARTIFACT_DIR/<key>` con `key` = SHA-256 de su contenido.
+-- `ref_count` cuenta las retenciones de `copy_if_needed` y `last_put_ts` es
+-- el último `put` (margen de `gc_artifacts`).
+CREATE TABLE IF NOT EXISTS artifacts (
+  key TEXT PRIMARY KEY,
+  size BIGINT NOT NULL,
+  ref_count BIGINT NOT NULL DEFAULT 0,
+  last_put_ts BIGINT NOT NULL
+);
+-- Referencias desde `flow_data` (`metadata.artifacts`), por flow y cursor
+-- para copiarlas con `create_branch` y borrarlas con los pasos.
+CREATE TABLE IF NOT EXISTS artifact_refs (
+  flow_id TEXT NOT NULL,
+  cursor BIGINT NOT NULL,
+  artifact_key TEXT NOT NULL,
+  PRIMARY KEY (flow_id, cursor, artifact_key)
+);
+CREATE INDEX IF NOT EXISTS idx_artifact_refs_key ON artifact_refs (artifact_key);
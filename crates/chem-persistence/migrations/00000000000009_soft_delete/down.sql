@@ -0,0 +1,14 @@
+-- Sin la marca, lo borrado volvería a ser visible: se elimina antes.
+DROP INDEX IF EXISTS idx_flow_truncations_flow;
+DROP TABLE IF EXISTS flow_truncations;
+DELETE FROM flow_data WHERE deleted_at_ts IS NOT NULL
+  OR flow_id IN (SELECT id FROM flows WHERE deleted_at_ts IS NOT NULL);
+DELETE FROM snapshots WHERE deleted_at_ts IS NOT NULL
+  OR flow_id IN (SELECT id FROM flows WHERE deleted_at_ts IS NOT NULL);
+DELETE FROM artifact_refs WHERE flow_id IN (SELECT id FROM flows WHERE deleted_at_ts IS NOT NULL);
+DELETE FROM work_queue WHERE flow_id IN (SELECT id FROM flows WHERE deleted_at_ts IS NOT NULL);
+DELETE FROM flows WHERE deleted_at_ts IS NOT NULL;
+DROP INDEX IF EXISTS ux_flow_data_flow_command;
+CREATE UNIQUE INDEX IF NOT EXISTS ux_flow_data_flow_command ON flow_data (flow_id, command_id);
+-- NOTE: SQLite no soporta DROP COLUMN de forma portable; las columnas
+-- `deleted_at_ts` se conservan. En Postgres: ALTER TABLE flows DROP COLUMN deleted_at_ts; (ídem flow_data y snapshots)
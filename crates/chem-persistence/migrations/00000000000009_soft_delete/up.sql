@@ -0,0 +1,27 @@
+-- Borrado lógico: `deleted_at_ts` marca un flow borrado con `delete_branch`
+-- y los pasos y snapshots truncados por `delete_from_step`. Las filas
+-- marcadas no se leen y `purge_deleted` las elimina pasada la retención.
+ALTER TABLE flows ADD COLUMN deleted_at_ts BIGINT;
+ALTER TABLE flow_data ADD COLUMN deleted_at_ts BIGINT;
+ALTER TABLE snapshots ADD COLUMN deleted_at_ts BIGINT;
+-- El command_id de un paso truncado puede volver a aplicarse.
+DROP INDEX IF EXISTS ux_flow_data_flow_command;
+CREATE UNIQUE INDEX IF NOT EXISTS ux_flow_data_flow_command ON flow_data (flow_id, command_id) WHERE deleted_at_ts IS NULL;
+-- Truncados de `delete_from_step` pendientes de `undelete_steps`: las filas
+-- de `flow_data` y `snapshots` y las ramas que marcó con `deleted_at_ts`
+-- (listas JSON de ids), la versión del flow al truncar y su `parent_cursor`
+-- anterior si el truncado lo bajó. `at_us` (microsegundos) ordena los
+-- truncados de un flow; `purge_deleted` borra la fila con lo truncado.
+CREATE TABLE IF NOT EXISTS flow_truncations (
+  id TEXT PRIMARY KEY,
+  flow_id TEXT NOT NULL,
+  from_cursor BIGINT NOT NULL,
+  flow_version BIGINT NOT NULL,
+  parent_cursor BIGINT,
+  at_us BIGINT NOT NULL,
+  deleted_at_ts BIGINT NOT NULL,
+  data_ids TEXT NOT NULL,
+  snapshot_ids TEXT NOT NULL,
+  flow_ids TEXT NOT NULL
+);
+CREATE INDEX IF NOT EXISTS idx_flow_truncations_flow ON flow_truncations (flow_id, at_us);
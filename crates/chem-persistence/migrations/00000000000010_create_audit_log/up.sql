@@ -0,0 +1,17 @@
+-- Registro de auditoría (`flow::repository::AuditLog`): una fila por llamada
+-- que modificó un repositorio de flows o de dominio. Solo se inserta.
+-- `at_us` son microsegundos desde epoch, para ordenar las entradas de un
+-- mismo segundo; `before_value`/`after_value` son JSON.
+CREATE TABLE IF NOT EXISTS audit_log (
+  id TEXT PRIMARY KEY,
+  at_us BIGINT NOT NULL,
+  actor TEXT,
+  operation TEXT NOT NULL,
+  flow_id TEXT,
+  target TEXT,
+  before_value TEXT,
+  after_value TEXT
+);
+CREATE INDEX IF NOT EXISTS idx_audit_log_at ON audit_log (at_us);
+CREATE INDEX IF NOT EXISTS idx_audit_log_flow ON audit_log (flow_id, at_us);
+CREATE INDEX IF NOT EXISTS idx_audit_log_actor ON audit_log (actor, at_us);
@@ -0,0 +1,60 @@
+-- Sin `tenant_id` las filas de distintos tenants se mezclarían (y chocarían
+-- en las claves reconstruidas): solo se conserva el tenant por defecto.
+DROP INDEX IF EXISTS idx_molecular_properties_molecule;
+DROP INDEX IF EXISTS idx_family_properties_family;
+DROP INDEX IF EXISTS idx_family_members_molecule;
+DROP INDEX IF EXISTS idx_family_members_family;
+DROP INDEX IF EXISTS idx_audit_log_tenant;
+DROP INDEX IF EXISTS idx_work_queue_tenant;
+DROP INDEX IF EXISTS idx_flows_tenant;
+DROP INDEX IF EXISTS idx_artifact_refs_key;
+DROP INDEX IF EXISTS idx_flow_truncations_flow;
+DELETE FROM flows WHERE tenant_id <> '';
+DELETE FROM flow_data WHERE tenant_id <> '';
+DELETE FROM snapshots WHERE tenant_id <> '';
+DELETE FROM work_queue WHERE tenant_id <> '';
+DELETE FROM flow_locks WHERE tenant_id <> '';
+DELETE FROM snapshot_blobs WHERE tenant_id <> '';
+DELETE FROM artifact_refs WHERE tenant_id <> '';
+DELETE FROM audit_log WHERE tenant_id <> '';
+DELETE FROM family_properties WHERE tenant_id <> '';
+DELETE FROM molecular_properties WHERE tenant_id <> '';
+DELETE FROM family_members WHERE tenant_id <> '';
+DELETE FROM flow_truncations WHERE tenant_id <> '';
+CREATE INDEX IF NOT EXISTS idx_artifact_refs_key ON artifact_refs (artifact_key);
+CREATE INDEX IF NOT EXISTS idx_flow_truncations_flow ON flow_truncations (flow_id, at_us);
+CREATE TABLE artifacts_single (
+  key TEXT PRIMARY KEY,
+  size BIGINT NOT NULL,
+  ref_count BIGINT NOT NULL DEFAULT 0,
+  last_put_ts BIGINT NOT NULL
+);
+INSERT INTO artifacts_single (key, size, ref_count, last_put_ts)
+  SELECT key, size, ref_count, last_put_ts FROM artifacts WHERE tenant_id = '';
+DROP TABLE artifacts;
+ALTER TABLE artifacts_single RENAME TO artifacts;
+CREATE TABLE families_single (
+  id TEXT PRIMARY KEY,
+  name TEXT,
+  description TEXT,
+  family_hash TEXT NOT NULL,
+  provenance TEXT NOT NULL,
+  frozen BOOLEAN NOT NULL DEFAULT TRUE
+);
+INSERT INTO families_single (id, name, description, family_hash, provenance, frozen)
+  SELECT id, name, description, family_hash, provenance, frozen FROM families WHERE tenant_id = '';
+DROP TABLE families;
+ALTER TABLE families_single RENAME TO families;
+CREATE TABLE molecules_single (
+  inchikey TEXT PRIMARY KEY,
+  smiles TEXT NOT NULL,
+  inchi TEXT NOT NULL,
+  metadata TEXT,
+  structure TEXT
+);
+INSERT INTO molecules_single (inchikey, smiles, inchi, metadata, structure)
+  SELECT inchikey, smiles, inchi, metadata, structure FROM molecules WHERE tenant_id = '';
+DROP TABLE molecules;
+ALTER TABLE molecules_single RENAME TO molecules;
+-- NOTE: SQLite no soporta DROP COLUMN de forma portable; las columnas
+-- `tenant_id` de las demás tablas se conservan. En Postgres: ALTER TABLE flows DROP COLUMN tenant_id; (ídem el resto)
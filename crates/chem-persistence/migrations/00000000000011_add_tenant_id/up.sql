@@ -0,0 +1,68 @@
+-- Multi-tenant: cada fila pertenece a un tenant (`tenant_id`) y los
+-- repositorios, construidos para un tenant, solo leen y escriben las suyas.
+-- '' es el tenant por defecto, al que pasan los datos existentes.
+ALTER TABLE flows ADD COLUMN tenant_id TEXT NOT NULL DEFAULT '';
+ALTER TABLE flow_data ADD COLUMN tenant_id TEXT NOT NULL DEFAULT '';
+ALTER TABLE snapshots ADD COLUMN tenant_id TEXT NOT NULL DEFAULT '';
+ALTER TABLE work_queue ADD COLUMN tenant_id TEXT NOT NULL DEFAULT '';
+ALTER TABLE flow_locks ADD COLUMN tenant_id TEXT NOT NULL DEFAULT '';
+ALTER TABLE snapshot_blobs ADD COLUMN tenant_id TEXT NOT NULL DEFAULT '';
+ALTER TABLE artifact_refs ADD COLUMN tenant_id TEXT NOT NULL DEFAULT '';
+ALTER TABLE audit_log ADD COLUMN tenant_id TEXT NOT NULL DEFAULT '';
+ALTER TABLE family_properties ADD COLUMN tenant_id TEXT NOT NULL DEFAULT '';
+ALTER TABLE molecular_properties ADD COLUMN tenant_id TEXT NOT NULL DEFAULT '';
+ALTER TABLE family_members ADD COLUMN tenant_id TEXT NOT NULL DEFAULT '';
+ALTER TABLE flow_truncations ADD COLUMN tenant_id TEXT NOT NULL DEFAULT '';
+-- Las claves que dos tenants pueden repetir (inchikey, id de familia elegido
+-- por el caller, SHA-256 del artifact) pasan a ser (tenant_id, clave). Se
+-- reconstruyen las tablas porque SQLite no permite cambiar la clave primaria.
+CREATE TABLE molecules_by_tenant (
+  tenant_id TEXT NOT NULL DEFAULT '',
+  inchikey TEXT NOT NULL,
+  smiles TEXT NOT NULL,
+  inchi TEXT NOT NULL,
+  metadata TEXT,
+  structure TEXT,
+  PRIMARY KEY (tenant_id, inchikey)
+);
+INSERT INTO molecules_by_tenant (tenant_id, inchikey, smiles, inchi, metadata, structure)
+  SELECT '', inchikey, smiles, inchi, metadata, structure FROM molecules;
+DROP TABLE molecules;
+ALTER TABLE molecules_by_tenant RENAME TO molecules;
+CREATE TABLE families_by_tenant (
+  tenant_id TEXT NOT NULL DEFAULT '',
+  id TEXT NOT NULL,
+  name TEXT,
+  description TEXT,
+  family_hash TEXT NOT NULL,
+  provenance TEXT NOT NULL,
+  frozen BOOLEAN NOT NULL DEFAULT TRUE,
+  PRIMARY KEY (tenant_id, id)
+);
+INSERT INTO families_by_tenant (tenant_id, id, name, description, family_hash, provenance, frozen)
+  SELECT '', id, name, description, family_hash, provenance, frozen FROM families;
+DROP TABLE families;
+ALTER TABLE families_by_tenant RENAME TO families;
+CREATE TABLE artifacts_by_tenant (
+  tenant_id TEXT NOT NULL DEFAULT '',
+  key TEXT NOT NULL,
+  size BIGINT NOT NULL,
+  ref_count BIGINT NOT NULL DEFAULT 0,
+  last_put_ts BIGINT NOT NULL,
+  PRIMARY KEY (tenant_id, key)
+);
+INSERT INTO artifacts_by_tenant (tenant_id, key, size, ref_count, last_put_ts)
+  SELECT '', key, size, ref_count, last_put_ts FROM artifacts;
+DROP TABLE artifacts;
+ALTER TABLE artifacts_by_tenant RENAME TO artifacts;
+DROP INDEX IF EXISTS idx_artifact_refs_key;
+CREATE INDEX IF NOT EXISTS idx_artifact_refs_key ON artifact_refs (tenant_id, artifact_key);
+DROP INDEX IF EXISTS idx_flow_truncations_flow;
+CREATE INDEX IF NOT EXISTS idx_flow_truncations_flow ON flow_truncations (tenant_id, flow_id, at_us);
+CREATE INDEX IF NOT EXISTS idx_flows_tenant ON flows (tenant_id, deleted_at_ts);
+CREATE INDEX IF NOT EXISTS idx_work_queue_tenant ON work_queue (tenant_id, enqueued_at_ms);
+CREATE INDEX IF NOT EXISTS idx_audit_log_tenant ON audit_log (tenant_id, at_us);
+CREATE INDEX IF NOT EXISTS idx_family_members_family ON family_members (tenant_id, family_id);
+CREATE INDEX IF NOT EXISTS idx_family_members_molecule ON family_members (tenant_id, molecule_inchikey);
+CREATE INDEX IF NOT EXISTS idx_family_properties_family ON family_properties (tenant_id, family_id);
+CREATE INDEX IF NOT EXISTS idx_molecular_properties_molecule ON molecular_properties (tenant_id, molecule_inchikey);
@@ -0,0 +1,5 @@
+DROP INDEX IF EXISTS idx_flows_tenant_parent;
+DROP INDEX IF EXISTS idx_flows_tenant_created_by;
+DROP INDEX IF EXISTS idx_flows_tenant_status;
+DROP INDEX IF EXISTS idx_flows_tenant_name;
+DROP INDEX IF EXISTS idx_flows_tenant_created;
@@ -0,0 +1,8 @@
+-- Índices de `query_flows`: cada orden (fecha o nombre, desempate por id)
+-- se recorre por índice dentro del tenant, de modo que el keyset no ordena
+-- la tabla entera; status, autor y padre son los filtros más selectivos.
+CREATE INDEX IF NOT EXISTS idx_flows_tenant_created ON flows (tenant_id, created_at_ts, id);
+CREATE INDEX IF NOT EXISTS idx_flows_tenant_name ON flows (tenant_id, name, id);
+CREATE INDEX IF NOT EXISTS idx_flows_tenant_status ON flows (tenant_id, status, created_at_ts);
+CREATE INDEX IF NOT EXISTS idx_flows_tenant_created_by ON flows (tenant_id, created_by, created_at_ts);
+CREATE INDEX IF NOT EXISTS idx_flows_tenant_parent ON flows (tenant_id, parent_flow_id);
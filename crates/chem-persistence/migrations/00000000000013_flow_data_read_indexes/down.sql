@@ -0,0 +1,2 @@
+DROP INDEX IF EXISTS idx_flow_data_flow_key_cursor;
+DROP INDEX IF EXISTS idx_flow_data_flow_cursor;
@@ -0,0 +1,6 @@
+-- Índices de `query_data` y `latest_by_key`: cada tramo del historial se lee
+-- como un rango de cursores de un flow, con o sin key exacta; el segundo
+-- índice resuelve "último registro de la key" sin recorrer el flow. El
+-- prefijo de key se filtra sobre el primero.
+CREATE INDEX IF NOT EXISTS idx_flow_data_flow_cursor ON flow_data (tenant_id, flow_id, cursor);
+CREATE INDEX IF NOT EXISTS idx_flow_data_flow_key_cursor ON flow_data (tenant_id, flow_id, key, cursor);
@@ -0,0 +1,14 @@
+DROP INDEX IF EXISTS idx_molecular_properties_metadata_gin;
+DROP INDEX IF EXISTS idx_molecular_properties_value_gin;
+DROP INDEX IF EXISTS idx_family_properties_metadata_gin;
+DROP INDEX IF EXISTS idx_family_properties_value_gin;
+DROP INDEX IF EXISTS idx_flow_data_metadata_gin;
+DROP INDEX IF EXISTS idx_flow_data_payload_gin;
+DROP INDEX IF EXISTS idx_flows_metadata_gin;
+ALTER TABLE molecular_properties ALTER COLUMN metadata TYPE TEXT USING metadata::text;
+ALTER TABLE molecular_properties ALTER COLUMN value TYPE TEXT USING value::text;
+ALTER TABLE family_properties ALTER COLUMN metadata TYPE TEXT USING metadata::text;
+ALTER TABLE family_properties ALTER COLUMN value TYPE TEXT USING value::text;
+ALTER TABLE flow_data ALTER COLUMN metadata TYPE TEXT USING metadata::text;
+ALTER TABLE flow_data ALTER COLUMN payload TYPE TEXT USING payload::text;
+ALTER TABLE flows ALTER COLUMN metadata TYPE TEXT USING metadata::text;
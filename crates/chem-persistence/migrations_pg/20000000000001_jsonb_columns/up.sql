@@ -0,0 +1,18 @@
+-- Solo Postgres (`PG_MIGRATIONS`, se aplica tras las migraciones comunes):
+-- las columnas JSON pasan de texto a JSONB para que `JsonPredicate` se
+-- resuelva en SQL. Los índices GIN (`jsonb_path_ops`) cubren el operador de
+-- contención `@>` con el que se traducen los predicados `==`.
+ALTER TABLE flows ALTER COLUMN metadata TYPE JSONB USING COALESCE(NULLIF(metadata, ''), '{}')::jsonb;
+ALTER TABLE flow_data ALTER COLUMN payload TYPE JSONB USING COALESCE(NULLIF(payload, ''), 'null')::jsonb;
+ALTER TABLE flow_data ALTER COLUMN metadata TYPE JSONB USING COALESCE(NULLIF(metadata, ''), '{}')::jsonb;
+ALTER TABLE family_properties ALTER COLUMN value TYPE JSONB USING COALESCE(NULLIF(value, ''), 'null')::jsonb;
+ALTER TABLE family_properties ALTER COLUMN metadata TYPE JSONB USING COALESCE(NULLIF(metadata, ''), '{}')::jsonb;
+ALTER TABLE molecular_properties ALTER COLUMN value TYPE JSONB USING COALESCE(NULLIF(value, ''), 'null')::jsonb;
+ALTER TABLE molecular_properties ALTER COLUMN metadata TYPE JSONB USING COALESCE(NULLIF(metadata, ''), '{}')::jsonb;
+CREATE INDEX IF NOT EXISTS idx_flows_metadata_gin ON flows USING GIN (metadata jsonb_path_ops);
+CREATE INDEX IF NOT EXISTS idx_flow_data_payload_gin ON flow_data USING GIN (payload jsonb_path_ops);
+CREATE INDEX IF NOT EXISTS idx_flow_data_metadata_gin ON flow_data USING GIN (metadata jsonb_path_ops);
+CREATE INDEX IF NOT EXISTS idx_family_properties_value_gin ON family_properties USING GIN (value jsonb_path_ops);
+CREATE INDEX IF NOT EXISTS idx_family_properties_metadata_gin ON family_properties USING GIN (metadata jsonb_path_ops);
+CREATE INDEX IF NOT EXISTS idx_molecular_properties_value_gin ON molecular_properties USING GIN (value jsonb_path_ops);
+CREATE INDEX IF NOT EXISTS idx_molecular_properties_metadata_gin ON molecular_properties USING GIN (metadata jsonb_path_ops);
@@ -0,0 +1,1419 @@
+//! Backend Postgres nativo async (`tokio-postgres` + `deadpool-postgres`) que
+//! implementa `flow::AsyncFlowRepository` (feature `async-pg`).
+//!
+//! Usa las mismas tablas que `DieselFlowRepository` (las migraciones Diesel
+//! se aplican al conectar), de modo que ambos backends pueden convivir sobre
+//! la misma base. La semántica de cada operación es la de `FlowRepository`.
+use crate::flow_persistence::{
+  advisory_key, lease_ms_from_env, notify_payload, parse_notify_payload, run_pg_migrations, tenant_from_env,
+  truncated_ids, DEFAULT_TENANT, FLOW_DATA_CHANNEL,
+};
+use crate::json_query::{json_sql, JsonSql};
+use crate::snapshot_store::{snapshot_retention_from_env, snapshot_store_from_env};
+use async_trait::async_trait;
+use chrono::{TimeZone, Utc};
+use deadpool_postgres::{GenericClient, Manager, ManagerConfig, Object, Pool, RecyclingMethod};
+use diesel::{Connection, PgConnection};
+use flow::artifact_store::artifact_refs;
+use flow::domain::{FlowData, FlowDataMeta, FlowMeta, PersistResult, SnapshotMeta, WorkItem};
+use flow::errors::{FlowError, Result as FlowResult};
+use flow::lineage::{data_segments, DataSegment};
+use flow::json_predicate::JsonPredicate;
+use flow::query::{FlowDataQuery, FlowPage, FlowQuery, FlowSort};
+use flow::repository::{FlowLockGuard, SnapshotStore};
+use flow::retention::{
+  orphan_blob_keys, purge_cutoff, snapshots_to_prune, PurgeReport, SnapshotGcReport, SnapshotRetention,
+};
+use flow::snapshot_store::{decode_inline_snapshot, load_snapshot_blob};
+use flow::subscription::{ChangeFeed, FlowSubscription};
+use flow::AsyncFlowRepository;
+use serde_json::Value as JsonValue;
+use std::collections::HashSet;
+use std::sync::{Arc, Weak};
+use std::time::Duration;
+use tokio::runtime::Handle;
+use tokio::time::Instant;
+use tokio_postgres::error::SqlState;
+use tokio_postgres::types::ToSql;
+use tokio_postgres::{AsyncMessage, Client, NoTls, Row};
+use uuid::Uuid;
+// Las columnas JSONB se leen como texto (`json_col`) y se escriben con
+// `$n::text::jsonb`, porque los parámetros son `String`.
+const FLOW_COLS: &str = "id, name, status, created_by, created_at_ts, current_cursor, current_version, parent_flow_id, \
+                         parent_cursor, metadata::text AS metadata, deleted_at_ts";
+/// Columnas de `INSERT INTO flow_data`.
+const DATA_COLS: &str = "id, flow_id, cursor, key, payload, metadata, command_id, created_at_ts, persisted_version, \
+                         tenant_id";
+/// `DATA_COLS` para `SELECT`.
+const DATA_SELECT: &str = "id, flow_id, cursor, key, payload::text AS payload, metadata::text AS metadata, command_id, \
+                           created_at_ts, persisted_version, tenant_id";
+/// Valores de `INSERT INTO flow_data ({DATA_COLS})`.
+const DATA_VALUES: &str = "$1, $2, $3, $4, $5::text::jsonb, $6::text::jsonb, $7, $8, $9, $10";
+const SNAP_COLS: &str = "id, flow_id, cursor, state_ptr, metadata, created_at_ts, tenant_id";
+/// Intervalo entre reintentos mientras se espera un advisory lock.
+const LOCK_POLL_INTERVAL: Duration = Duration::from_millis(50);
+/// Tamaño por defecto del pool (`ASYNC_PG_POOL_SIZE`).
+const DEFAULT_POOL_SIZE: usize = 16;
+pub struct AsyncPgFlowRepository {
+  pool: Pool,
+  /// Tenant de todas las filas que lee y escribe (ver `connect_for_tenant`).
+  tenant: String,
+  /// Store de los blobs de snapshot (`SNAPSHOT_STORE`: `fs` o `s3`; para
+  /// `db` usar `with_snapshot_store` con
+  /// `DieselFlowRepository::db_snapshot_store`).
+  snapshot_store: Arc<dyn SnapshotStore>,
+  /// Política de `compact_snapshots`/`gc_snapshots` (`SNAPSHOT_KEEP_*`).
+  retention: SnapshotRetention,
+  /// Duración en milisegundos del lease de la cola de trabajo
+  /// (`WORK_LEASE_SECS`, por defecto `DEFAULT_WORK_LEASE_SECS`).
+  lease_ms: i64,
+  /// Configuración de conexión, para abrir la conexión dedicada de `LISTEN`.
+  config: tokio_postgres::Config,
+  /// Suscriptores de `subscribe`, alimentados desde `NOTIFY flow_data`.
+  feed: Arc<ChangeFeed>,
+  /// Cliente de la conexión `LISTEN` (se abre con la primera suscripción y
+  /// se cierra al destruir el repositorio).
+  listener: tokio::sync::Mutex<Option<Client>>,
+}
+impl AsyncPgFlowRepository {
+  /// Aplica las migraciones embebidas (con una conexión Diesel en el pool
+  /// bloqueante) y crea el pool async.
+  pub async fn connect(database_url: &str) -> FlowResult<Self> {
+    Self::connect_for_tenant(database_url, DEFAULT_TENANT).await
+  }
+  /// Como `connect`, con todas las operaciones limitadas a `tenant` (igual
+  /// que `DieselFlowRepository::new_pg_for_tenant`).
+  pub async fn connect_for_tenant(database_url: &str, tenant: &str) -> FlowResult<Self> {
+    let url = database_url.to_string();
+    tokio::task::spawn_blocking(move || {
+      let mut c = PgConnection::establish(&url).map_err(|e| FlowError::Storage(format!("db: {}", e)))?;
+      match run_pg_migrations(&mut c) {
+        Ok(applied) => eprintln!("chem-persistence (async pg): aplicadas {} migraciones embebidas", applied),
+        Err(e) => eprintln!("chem-persistence (async pg): fallo al ejecutar migraciones embebidas: {}", e),
+      }
+      Ok::<(), FlowError>(())
+    }).await
+      .map_err(|e| FlowError::Other(format!("spawn_blocking: {}", e)))??;
+    let config: tokio_postgres::Config =
+      database_url.parse().map_err(|e| FlowError::Other(format!("DATABASE_URL inválida: {}", e)))?;
+    let manager = Manager::from_config(config.clone(), NoTls, ManagerConfig { recycling_method: RecyclingMethod::Fast });
+    let pool_size = std::env::var("ASYNC_PG_POOL_SIZE").ok().and_then(|v| v.parse().ok()).unwrap_or(DEFAULT_POOL_SIZE);
+    let pool = Pool::builder(manager).max_size(pool_size).build().map_err(|e| {
+                                                                    FlowError::Storage(format!("no se pudo crear el pool \
+                                                                                                de conexiones: {}",
+                                                                                               e))
+                                                                  })?;
+    let snapshot_store = snapshot_store_from_env(None, tenant)?;
+    Ok(Self { pool,
+              tenant: tenant.to_string(),
+              snapshot_store,
+              retention: snapshot_retention_from_env(),
+              lease_ms: lease_ms_from_env(),
+              config,
+              feed: Arc::new(ChangeFeed::new()),
+              listener: tokio::sync::Mutex::new(None) })
+  }
+  pub async fn from_env() -> FlowResult<Self> {
+    dotenvy::dotenv().ok();
+    let url = std::env::var("DATABASE_URL").map_err(|_| FlowError::Other("DATABASE_URL not set".into()))?;
+    Self::connect_for_tenant(&url, &tenant_from_env()).await
+  }
+  /// Tenant al que está limitado este repositorio.
+  pub fn tenant(&self) -> &str {
+    &self.tenant
+  }
+  /// Ajusta la duración del lease de la cola de trabajo (con precisión de
+  /// milisegundos).
+  pub fn with_lease_duration(mut self, lease: Duration) -> Self {
+    self.lease_ms = lease.as_millis() as i64;
+    self
+  }
+  /// Usa `store` para los blobs de snapshot.
+  pub fn with_snapshot_store(mut self, store: Arc<dyn SnapshotStore>) -> Self {
+    self.snapshot_store = store;
+    self
+  }
+  /// Usa `policy` al compactar snapshots.
+  pub fn with_snapshot_retention(mut self, policy: SnapshotRetention) -> Self {
+    self.retention = policy;
+    self
+  }
+  /// Ejecuta una operación del `SnapshotStore` (síncrono) en el pool
+  /// bloqueante.
+  async fn with_store<T, F>(&self, f: F) -> FlowResult<T>
+    where T: Send + 'static,
+          F: FnOnce(&dyn SnapshotStore) -> FlowResult<T> + Send + 'static
+  {
+    let store = Arc::clone(&self.snapshot_store);
+    tokio::task::spawn_blocking(move || f(store.as_ref())).await
+                                                          .map_err(|e| FlowError::Other(format!("spawn_blocking: {}", e)))?
+  }
+  /// Compacta los snapshots de `flow_id`; devuelve `(snapshots, blobs)`
+  /// borrados. Las filas se borran en una transacción y los blobs después.
+  async fn compact_flow_snapshots(&self, flow_id: &Uuid) -> FlowResult<(usize, usize)> {
+    let mut client = self.client().await?;
+    let fid = flow_id.to_string();
+    let tx = client.transaction().await.map_err(map_pg_err)?;
+    if !Self::flow_exists(&tx, &self.tenant, &fid).await? {
+      return Err(FlowError::NotFound(format!("flow {}", flow_id)));
+    }
+    let branch_points: Vec<i64> = tx.query("SELECT parent_cursor FROM flows WHERE parent_flow_id = $1 AND tenant_id = $2",
+                                           &[&fid, &self.tenant])
+                                    .await
+                                    .map_err(map_pg_err)?
+                                    .iter()
+                                    .filter_map(|r| r.get::<_, Option<i64>>(0))
+                                    .collect();
+    let own = tx.query(&format!("SELECT {} FROM snapshots WHERE flow_id = $1 AND tenant_id = $2 AND deleted_at_ts IS \
+                                 NULL",
+                                SNAP_COLS),
+                       &[&fid, &self.tenant])
+                .await
+                .map_err(map_pg_err)?
+                .iter()
+                .map(snapshot_from_row)
+                .collect::<FlowResult<Vec<_>>>()?;
+    let pruned = snapshots_to_prune(&own, &branch_points, &self.retention);
+    let ids: Vec<String> = pruned.iter().map(|s| s.id.to_string()).collect();
+    tx.execute("DELETE FROM snapshots WHERE id = ANY($1) AND tenant_id = $2", &[&ids, &self.tenant])
+      .await
+      .map_err(map_pg_err)?;
+    // las ramas copian los snapshots del padre con la misma key
+    let ptrs: Vec<String> = pruned.iter().map(|s| s.state_ptr.clone()).collect();
+    let still: HashSet<String> = tx.query("SELECT DISTINCT state_ptr FROM snapshots WHERE state_ptr = ANY($1) AND \
+                                           tenant_id = $2",
+                                          &[&ptrs, &self.tenant])
+                                   .await
+                                   .map_err(map_pg_err)?
+                                   .iter()
+                                   .filter_map(|r| r.get::<_, Option<String>>(0))
+                                   .collect();
+    tx.commit().await.map_err(map_pg_err)?;
+    drop(client);
+    let keys: HashSet<String> =
+      ptrs.into_iter().filter(|k| !still.contains(k) && decode_inline_snapshot(k).is_none()).collect();
+    let blobs = keys.len();
+    self.with_store(move |store| keys.iter().try_for_each(|k| store.delete(k))).await?;
+    Ok((pruned.len(), blobs))
+  }
+  async fn client(&self) -> FlowResult<Object> {
+    self.pool.get().await.map_err(|e| FlowError::Storage(format!("pool: {}", e)))
+  }
+  async fn flow_exists(client: &impl GenericClient, tenant: &str, fid: &str) -> FlowResult<bool> {
+    let row = client.query_opt("SELECT 1 FROM flows WHERE id = $1 AND tenant_id = $2 AND deleted_at_ts IS NULL",
+                               &[&fid, &tenant])
+                    .await
+                    .map_err(map_pg_err)?;
+    Ok(row.is_some())
+  }
+  async fn current_version(client: &impl GenericClient, tenant: &str, fid: &str) -> FlowResult<Option<i64>> {
+    let row = client.query_opt("SELECT current_version FROM flows WHERE id = $1 AND tenant_id = $2 AND deleted_at_ts IS \
+                                NULL",
+                               &[&fid, &tenant])
+                    .await
+                    .map_err(map_pg_err)?;
+    Ok(row.map(|r| r.get::<_, Option<i64>>(0).unwrap_or(0)))
+  }
+  /// Tramos de `flow_data` que componen el historial de `fid` (vacío si el
+  /// flow no existe, está borrado o es de otro tenant). Ver
+  /// `flow::lineage::data_segments`.
+  async fn segments_of(client: &impl GenericClient, tenant: &str, fid: &str) -> FlowResult<Vec<DataSegment>> {
+    let live = format!("SELECT {} FROM flows WHERE id = $1 AND tenant_id = $2 AND deleted_at_ts IS NULL", FLOW_COLS);
+    let Some(row) = client.query_opt(&live, &[&fid, &tenant]).await.map_err(map_pg_err)? else {
+      return Ok(Vec::new());
+    };
+    let flow = flow_meta_from_row(&row)?;
+    let chain = Self::chain_of(client, tenant, &flow).await?;
+    Ok(data_segments(&flow, &chain))
+  }
+  /// Antepasados de `flow` (padre primero), incluidos los borrados: una rama
+  /// viva sigue leyendo a través de un padre borrado hasta que se purga. La
+  /// cadena se corta en el primer antepasado de otro tenant.
+  async fn chain_of(client: &impl GenericClient, tenant: &str, flow: &FlowMeta) -> FlowResult<Vec<FlowMeta>> {
+    let sql = format!("SELECT {} FROM flows WHERE id = $1 AND tenant_id = $2", FLOW_COLS);
+    let mut chain = Vec::new();
+    let mut seen = HashSet::from([flow.id]);
+    let mut next = flow.parent_flow_id;
+    while let Some(parent_id) = next {
+      if !seen.insert(parent_id) {
+        return Err(FlowError::Other(format!("ciclo en el linaje de {}", flow.id)));
+      }
+      let Some(row) = client.query_opt(&sql, &[&parent_id.to_string(), &tenant]).await.map_err(map_pg_err)? else {
+        break;
+      };
+      let parent = flow_meta_from_row(&row)?;
+      next = parent.parent_flow_id;
+      chain.push(parent);
+    }
+    Ok(chain)
+  }
+  /// Si el historial de `flow` pasa por pasos de un antepasado que siguen
+  /// truncados en `flow_truncations`.
+  async fn reads_truncated(client: &impl GenericClient, tenant: &str, flow: &FlowMeta) -> FlowResult<bool> {
+    let chain = Self::chain_of(client, tenant, flow).await?;
+    for seg in data_segments(flow, &chain).iter().skip(1) {
+      let Some(up_to) = seg.up_to else {
+        continue;
+      };
+      let hit = client.query_opt("SELECT 1 FROM flow_truncations WHERE tenant_id = $1 AND flow_id = $2 AND from_cursor \
+                                  <= $3 LIMIT 1",
+                                 &[&tenant, &seg.flow_id.to_string(), &up_to])
+                      .await
+                      .map_err(map_pg_err)?;
+      if hit.is_some() {
+        return Ok(true);
+      }
+    }
+    Ok(false)
+  }
+  /// Filas no truncadas de `flow_data` de `segments` con `cursor >
+  /// from_cursor`, ordenadas por cursor (con su `flow_id` físico).
+  async fn stitched_rows(client: &impl GenericClient,
+                         tenant: &str,
+                         segments: &[DataSegment],
+                         from_cursor: i64)
+                         -> FlowResult<Vec<Row>> {
+    let sql = format!("SELECT {} FROM flow_data WHERE flow_id = $1 AND cursor > $2 AND cursor <= $3 AND tenant_id = $4 \
+                       AND deleted_at_ts IS NULL",
+                      DATA_SELECT);
+    let mut rows = Vec::new();
+    for seg in segments {
+      let after = from_cursor.max(seg.after.unwrap_or(i64::MIN));
+      rows.extend(client.query(&sql, &[&seg.flow_id.to_string(), &after, &seg.up_to.unwrap_or(i64::MAX), &tenant])
+                        .await
+                        .map_err(map_pg_err)?);
+    }
+    rows.sort_by_key(|r| r.get::<_, Option<i64>>("cursor"));
+    Ok(rows)
+  }
+  /// Recalcula `current_cursor` de `fid` con el último registro visible.
+  async fn refresh_cursor(tx: &impl GenericClient, tenant: &str, fid: &str) -> FlowResult<()> {
+    let segments = Self::segments_of(tx, tenant, fid).await?;
+    let new_cursor = Self::stitched_rows(tx, tenant, &segments, 0).await?
+                                                                   .last()
+                                                                   .and_then(|r| r.get::<_, Option<i64>>("cursor"))
+                                                                   .unwrap_or(0);
+    tx.execute("UPDATE flows SET current_cursor = $2 WHERE id = $1 AND tenant_id = $3", &[&fid, &new_cursor, &tenant])
+      .await
+      .map_err(map_pg_err)?;
+    Ok(())
+  }
+  /// Filas de `segments` que cumplen `query`, filtradas, ordenadas y
+  /// limitadas por tramo; sin `with_payload` se lee `'null'` en lugar del
+  /// payload.
+  async fn queried_rows(client: &impl GenericClient,
+                        tenant: &str,
+                        segments: &[DataSegment],
+                        query: &FlowDataQuery,
+                        with_payload: bool)
+                        -> FlowResult<Vec<Row>> {
+    let cols = if with_payload {
+      DATA_SELECT.to_string()
+    } else {
+      DATA_SELECT.replace("payload::text AS payload", "'null'::text AS payload")
+    };
+    let mut rows = Vec::new();
+    for seg in segments {
+      let mut params: Vec<SqlParam> = vec![Box::new(seg.flow_id.to_string()),
+                                           Box::new(tenant.to_string()),
+                                           Box::new(query.after.unwrap_or(0).max(seg.after.unwrap_or(i64::MIN))),
+                                           Box::new(query.up_to.unwrap_or(i64::MAX).min(seg.up_to.unwrap_or(i64::MAX)))];
+      let mut conds =
+        vec!["flow_id = $1 AND tenant_id = $2 AND cursor > $3 AND cursor <= $4 AND deleted_at_ts IS NULL".to_string()];
+      if let Some(k) = &query.key {
+        conds.push(format!("key = {}", bind(&mut params, Box::new(k.clone()))));
+      }
+      if let Some(p) = &query.key_prefix {
+        let len = bind(&mut params, Box::new(p.chars().count() as i32));
+        let prefix = bind(&mut params, Box::new(p.clone()));
+        conds.push(format!("substr(key, 1, {}) = {}", len, prefix));
+      }
+      for pred in &query.json {
+        conds.push(json_cond(&mut params, pred));
+      }
+      let mut sql = format!("SELECT {} FROM flow_data WHERE {} ORDER BY cursor {}",
+                            cols,
+                            conds.join(" AND "),
+                            if query.latest_first { "DESC" } else { "ASC" });
+      if let Some(limit) = query.limit {
+        sql.push_str(&format!(" LIMIT {}", bind(&mut params, Box::new(limit as i64))));
+      }
+      let refs: Vec<&(dyn ToSql + Sync)> = params.iter().map(|p| p.as_ref() as &(dyn ToSql + Sync)).collect();
+      rows.extend(client.query(&sql, &refs).await.map_err(map_pg_err)?);
+    }
+    Ok(rows)
+  }
+  /// `query_data`/`query_data_meta`: tramos y filas en una transacción, con
+  /// el `flow_id` de la rama pedida.
+  async fn query_data_rows(&self,
+                           flow_id: &Uuid,
+                           query: &FlowDataQuery,
+                           with_payload: bool)
+                           -> FlowResult<Vec<FlowData>> {
+    query.validate()?;
+    let mut client = self.client().await?;
+    let tx = client.transaction().await.map_err(map_pg_err)?;
+    let segments = Self::segments_of(&tx, &self.tenant, &flow_id.to_string()).await?;
+    let rows = Self::queried_rows(&tx, &self.tenant, &segments, query, with_payload).await?;
+    tx.commit().await.map_err(map_pg_err)?;
+    let data = rows.iter()
+                   .map(|r| Ok(FlowData { flow_id: *flow_id, ..flow_data_from_row(r)? }))
+                   .collect::<FlowResult<Vec<_>>>()?;
+    Ok(query.order_and_limit(data))
+  }
+  /// Copia en la rama `child_id` las filas de `rows` con `cursor <= up_to`
+  /// que no tenga ya como propias, con sus `artifact_refs` (al purgar el
+  /// flow del que las heredaba).
+  async fn copy_inherited(tx: &impl GenericClient,
+                          tenant: &str,
+                          child_id: &str,
+                          rows: &[Row],
+                          up_to: i64)
+                          -> FlowResult<()> {
+    let own: HashSet<i64> = tx.query("SELECT cursor FROM flow_data WHERE flow_id = $1 AND tenant_id = $2 AND \
+                                      deleted_at_ts IS NULL",
+                                     &[&child_id, &tenant])
+                              .await
+                              .map_err(map_pg_err)?
+                              .iter()
+                              .filter_map(|r| r.get::<_, Option<i64>>(0))
+                              .collect();
+    for r in rows {
+      let cursor = r.get::<_, Option<i64>>("cursor").unwrap_or(0);
+      if cursor > up_to || own.contains(&cursor) {
+        continue;
+      }
+      tx.execute(&format!("INSERT INTO flow_data ({}) VALUES ({})", DATA_COLS, DATA_VALUES),
+                 &[&Uuid::new_v4().to_string(),
+                   &child_id,
+                   &cursor,
+                   &r.get::<_, Option<String>>("key"),
+                   &r.get::<_, Option<String>>("payload"),
+                   &r.get::<_, Option<String>>("metadata"),
+                   &r.get::<_, Option<String>>("command_id"),
+                   &r.get::<_, Option<i64>>("created_at_ts"),
+                   &r.get::<_, Option<i64>>("persisted_version"),
+                   &tenant])
+        .await
+        .map_err(map_pg_err)?;
+      for key in artifact_refs(&flow_data_from_row(r)?) {
+        tx.execute("INSERT INTO artifact_refs (flow_id, cursor, artifact_key, tenant_id) VALUES ($1, $2, $3, $4) ON \
+                    CONFLICT DO NOTHING",
+                   &[&child_id, &cursor, &key, &tenant])
+          .await
+          .map_err(map_pg_err)?;
+      }
+    }
+    Ok(())
+  }
+  /// Transacción de `persist_data`. Los errores de contrato (`NotFound`,
+  /// cursor no creciente) van en el resultado interno; los de la base en el
+  /// externo, para poder distinguir la violación del índice único.
+  async fn try_persist(client: &mut Object,
+                       tenant: &str,
+                       data: &FlowData,
+                       expected_version: i64)
+                       -> Result<FlowResult<PersistResult>, tokio_postgres::Error> {
+    let fid = data.flow_id.to_string();
+    let cmd = data.command_id.map(|u| u.to_string());
+    let tx = client.transaction().await?;
+    // `FOR UPDATE` serializa a los escritores del mismo flow entre la
+    // comprobación de versión y el update.
+    let Some(row) = tx.query_opt("SELECT current_version, current_cursor FROM flows WHERE id = $1 AND tenant_id = $2 AND \
+                                  deleted_at_ts IS NULL FOR UPDATE",
+                                 &[&fid, &tenant])
+                      .await?
+    else {
+      return Ok(Err(FlowError::NotFound(format!("flow {}", fid))));
+    };
+    let row_version = row.get::<_, Option<i64>>(0).unwrap_or(0);
+    let row_cursor = row.get::<_, Option<i64>>(1).unwrap_or(0);
+    if let Some(cmd) = &cmd {
+      let prev = tx.query_opt("SELECT persisted_version FROM flow_data WHERE flow_id = $1 AND command_id = $2 AND \
+                               tenant_id = $3 AND deleted_at_ts IS NULL LIMIT 1",
+                              &[&fid, cmd, &tenant])
+                   .await?;
+      if let Some(prev) = prev {
+        return Ok(Ok(PersistResult::Ok { new_version: prev.get::<_, Option<i64>>(0).unwrap_or(row_version) }));
+      }
+    }
+    if row_version != expected_version {
+      return Ok(Ok(PersistResult::Conflict));
+    }
+    if data.cursor <= row_cursor {
+      return Ok(Err(FlowError::Conflict(format!("cursor {} not greater than current {}", data.cursor, row_cursor))));
+    }
+    let new_version = row_version + 1;
+    tx.execute(&format!("INSERT INTO flow_data ({}) VALUES ({})", DATA_COLS, DATA_VALUES),
+               &[&data.id.to_string(),
+                 &fid,
+                 &data.cursor,
+                 &data.key,
+                 &data.payload.to_string(),
+                 &data.metadata.to_string(),
+                 &cmd,
+                 &data.created_at.timestamp(),
+                 &new_version,
+                 &tenant])
+      .await?;
+    for key in artifact_refs(data) {
+      tx.execute("INSERT INTO artifact_refs (flow_id, cursor, artifact_key, tenant_id) VALUES ($1, $2, $3, $4) ON \
+                  CONFLICT DO NOTHING",
+                 &[&fid, &data.cursor, &key, &tenant])
+        .await?;
+    }
+    tx.execute("UPDATE flows SET current_version = $2, current_cursor = $3 WHERE id = $1",
+               &[&fid, &new_version, &data.cursor])
+      .await?;
+    tx.execute("SELECT pg_notify($1, $2)",
+               &[&FLOW_DATA_CHANNEL, &notify_payload(&data.flow_id, data.cursor)])
+      .await?;
+    tx.commit().await?;
+    Ok(Ok(PersistResult::Ok { new_version }))
+  }
+  /// Toma un advisory lock de sesión sobre una conexión dedicada del pool,
+  /// reintentando hasta `timeout`. El guard libera el lock (y devuelve la
+  /// conexión al pool) desde una tarea del runtime actual.
+  async fn acquire_flow_lock(&self, flow_id: &Uuid, timeout: Duration) -> FlowResult<Option<FlowLockGuard>> {
+    let key = advisory_key(flow_id);
+    let deadline = Instant::now() + timeout;
+    let client = self.client().await?;
+    loop {
+      let row = client.query_one("SELECT pg_try_advisory_lock($1)", &[&key]).await.map_err(map_pg_err)?;
+      if row.get::<_, bool>(0) {
+        let handle = Handle::current();
+        return Ok(Some(FlowLockGuard::new(*flow_id, move || {
+                         handle.spawn(async move {
+                                 if let Err(e) = client.execute("SELECT pg_advisory_unlock($1)", &[&key]).await {
+                                   eprintln!("chem-persistence (async pg): fallo al liberar advisory lock {}: {}", key, e);
+                                 }
+                               });
+                       })));
+      }
+      let now = Instant::now();
+      if now >= deadline {
+        return Ok(None);
+      }
+      tokio::time::sleep(LOCK_POLL_INTERVAL.min(deadline - now)).await;
+    }
+  }
+}
+impl AsyncPgFlowRepository {
+  /// Abre (una vez, o de nuevo si se cerró) la conexión `LISTEN flow_data`.
+  /// Una tarea del runtime la conduce y publica en el feed cada registro
+  /// notificado; el `LISTEN` se confirma antes de volver para no perder
+  /// notificaciones posteriores a `subscribe`.
+  async fn ensure_listener(&self) -> FlowResult<()> {
+    let mut listener = self.listener.lock().await;
+    if listener.as_ref().is_some_and(|c| !c.is_closed()) {
+      return Ok(());
+    }
+    let (client, mut connection) = self.config.connect(NoTls).await.map_err(map_pg_err)?;
+    let pool = self.pool.clone();
+    let tenant = self.tenant.clone();
+    let feed = Arc::downgrade(&self.feed);
+    tokio::spawn(async move {
+      while let Some(msg) = std::future::poll_fn(|cx| connection.poll_message(cx)).await {
+        match msg {
+          Ok(AsyncMessage::Notification(n)) => publish_notified(&pool, &tenant, &feed, n.payload()).await,
+          Ok(_) => {}
+          Err(e) => {
+            eprintln!("chem-persistence (async pg): conexión LISTEN perdida: {}", e);
+            break;
+          }
+        }
+      }
+    });
+    client.batch_execute(&format!("LISTEN {}", FLOW_DATA_CHANNEL)).await.map_err(map_pg_err)?;
+    *listener = Some(client);
+    Ok(())
+  }
+}
+/// Lee el registro anunciado por una notificación y lo publica, si el flow
+/// tiene suscriptores (el canal es común a todos los tenants; solo se leen
+/// registros de `tenant`).
+async fn publish_notified(pool: &Pool, tenant: &str, feed: &Weak<ChangeFeed>, payload: &str) {
+  let (Some(feed), Some((flow_id, cursor))) = (feed.upgrade(), parse_notify_payload(payload)) else {
+    return;
+  };
+  if !feed.has_subscribers(&flow_id) {
+    return;
+  }
+  let row = match pool.get().await {
+    Ok(client) => client.query_opt(&format!("SELECT {} FROM flow_data WHERE flow_id = $1 AND cursor = $2 AND \
+                                             tenant_id = $3 AND deleted_at_ts IS NULL",
+                                            DATA_SELECT),
+                                   &[&flow_id.to_string(), &cursor, &tenant])
+                        .await
+                        .map_err(map_pg_err),
+    Err(e) => Err(FlowError::Storage(format!("pool: {}", e))),
+  };
+  match row.and_then(|r| r.as_ref().map(flow_data_from_row).transpose()) {
+    Ok(Some(data)) => feed.publish(&data),
+    Ok(None) => {}
+    Err(e) => eprintln!("chem-persistence (async pg): fallo al leer flow_data notificado: {}", e),
+  }
+}
+fn map_pg_err(e: tokio_postgres::Error) -> FlowError {
+  FlowError::Storage(format!("db: {}", e))
+}
+fn ts(secs: i64) -> chrono::DateTime<Utc> {
+  Utc.timestamp_opt(secs, 0).single().unwrap_or(Utc::now())
+}
+fn json_col(row: &Row, col: &str) -> JsonValue {
+  row.get::<_, Option<String>>(col).and_then(|s| serde_json::from_str(&s).ok()).unwrap_or(serde_json::json!({}))
+}
+fn uuid_col(row: &Row, col: &str) -> FlowResult<Uuid> {
+  let s: String = row.get(col);
+  Uuid::parse_str(&s).map_err(|e| FlowError::Storage(format!("{}: {}", col, e)))
+}
+/// Parámetro de una consulta construida en tiempo de ejecución
+/// (`query_flows`).
+type SqlParam = Box<dyn ToSql + Sync + Send>;
+/// Añade `value` a `params` y devuelve su placeholder (`$n`).
+fn bind(params: &mut Vec<SqlParam>, value: SqlParam) -> String {
+  params.push(value);
+  format!("${}", params.len())
+}
+/// Condición de `pred` (ver `json_sql`) con sus parámetros en `params`.
+fn json_cond(params: &mut Vec<SqlParam>, pred: &JsonPredicate) -> String {
+  let JsonSql { sql, params: values } = json_sql(pred, true);
+  let mut cond = sql[0].clone();
+  for (value, fragment) in values.into_iter().zip(&sql[1..]) {
+    cond.push_str(&bind(params, Box::new(value)));
+    cond.push_str(fragment);
+  }
+  cond
+}
+fn flow_meta_from_row(row: &Row) -> FlowResult<FlowMeta> {
+  Ok(FlowMeta { id: uuid_col(row, "id")?,
+                name: row.get("name"),
+                status: row.get("status"),
+                created_by: row.get("created_by"),
+                created_at: ts(row.get::<_, Option<i64>>("created_at_ts").unwrap_or(0)),
+                current_cursor: row.get::<_, Option<i64>>("current_cursor").unwrap_or(0),
+                current_version: row.get::<_, Option<i64>>("current_version").unwrap_or(0),
+                parent_flow_id: row.get::<_, Option<String>>("parent_flow_id").and_then(|s| Uuid::parse_str(&s).ok()),
+                parent_cursor: row.get("parent_cursor"),
+                metadata: json_col(row, "metadata"),
+                deleted_at: row.get::<_, Option<i64>>("deleted_at_ts").map(ts) })
+}
+fn flow_data_from_row(row: &Row) -> FlowResult<FlowData> {
+  Ok(FlowData { id: uuid_col(row, "id")?,
+                flow_id: uuid_col(row, "flow_id")?,
+                cursor: row.get::<_, Option<i64>>("cursor").unwrap_or(0),
+                key: row.get::<_, Option<String>>("key").unwrap_or_default(),
+                payload: json_col(row, "payload"),
+                metadata: json_col(row, "metadata"),
+                command_id: row.get::<_, Option<String>>("command_id").and_then(|s| Uuid::parse_str(&s).ok()),
+                created_at: ts(row.get::<_, Option<i64>>("created_at_ts").unwrap_or(0)) })
+}
+fn snapshot_from_row(row: &Row) -> FlowResult<SnapshotMeta> {
+  Ok(SnapshotMeta { id: uuid_col(row, "id")?,
+                    flow_id: uuid_col(row, "flow_id")?,
+                    cursor: row.get::<_, Option<i64>>("cursor").unwrap_or(0),
+                    state_ptr: row.get::<_, Option<String>>("state_ptr").unwrap_or_default(),
+                    metadata: json_col(row, "metadata"),
+                    created_at: ts(row.get::<_, Option<i64>>("created_at_ts").unwrap_or(0)) })
+}
+#[async_trait]
+impl AsyncFlowRepository for AsyncPgFlowRepository {
+  async fn get_flow_meta(&self, flow_id: &Uuid) -> FlowResult<FlowMeta> {
+    let client = self.client().await?;
+    let row = client.query_opt(&format!("SELECT {} FROM flows WHERE id = $1 AND tenant_id = $2 AND deleted_at_ts IS NULL",
+                                        FLOW_COLS),
+                               &[&flow_id.to_string(), &self.tenant])
+                    .await
+                    .map_err(map_pg_err)?
+                    .ok_or_else(|| FlowError::NotFound(format!("flow {}", flow_id)))?;
+    flow_meta_from_row(&row)
+  }
+  async fn create_flow(&self, name: Option<String>, status: Option<String>, metadata: JsonValue) -> FlowResult<Uuid> {
+    self.create_flow_as(name, status, metadata, None).await
+  }
+  async fn create_flow_as(&self,
+                          name: Option<String>,
+                          status: Option<String>,
+                          metadata: JsonValue,
+                          created_by: Option<String>)
+                          -> FlowResult<Uuid> {
+    let client = self.client().await?;
+    let new_id = Uuid::new_v4();
+    client.execute("INSERT INTO flows (id, name, status, created_by, created_at_ts, current_cursor, current_version, \
+                    parent_flow_id, parent_cursor, metadata, tenant_id) VALUES ($1, $2, $3, $4, $5, 0, 0, NULL, NULL, \
+                    $6::text::jsonb, $7)",
+                   &[&new_id.to_string(),
+                     &name,
+                     &status,
+                     &created_by,
+                     &Utc::now().timestamp(),
+                     &metadata.to_string(),
+                     &self.tenant])
+          .await
+          .map_err(map_pg_err)?;
+    Ok(new_id)
+  }
+  async fn persist_data(&self, data: &FlowData, expected_version: i64) -> FlowResult<PersistResult> {
+    let mut client = self.client().await?;
+    match Self::try_persist(&mut client, &self.tenant, data, expected_version).await {
+      Ok(res) => res,
+      // Carrera entre dos reintentos del mismo comando: el índice único
+      // rechaza el segundo insert y se devuelve el resultado del primero.
+      Err(e) if data.command_id.is_some() && e.code() == Some(&SqlState::UNIQUE_VIOLATION) => {
+        let fid = data.flow_id.to_string();
+        let cmd = data.command_id.map(|u| u.to_string()).unwrap_or_default();
+        let current = Self::current_version(&client, &self.tenant, &fid).await?.unwrap_or(0);
+        let row = client.query_opt("SELECT persisted_version FROM flow_data WHERE flow_id = $1 AND command_id = $2 AND \
+                                    tenant_id = $3 AND deleted_at_ts IS NULL LIMIT 1",
+                                   &[&fid, &cmd, &self.tenant])
+                        .await
+                        .map_err(map_pg_err)?
+                        .ok_or_else(|| FlowError::Storage("db: command_id duplicado sin registro previo".into()))?;
+        Ok(PersistResult::Ok { new_version: row.get::<_, Option<i64>>(0).unwrap_or(current) })
+      }
+      Err(e) => Err(FlowError::Storage(format!("db txn: {}", e))),
+    }
+  }
+  async fn read_data(&self, flow_id: &Uuid, from_cursor: i64) -> FlowResult<Vec<FlowData>> {
+    let mut client = self.client().await?;
+    let tx = client.transaction().await.map_err(map_pg_err)?;
+    let segments = Self::segments_of(&tx, &self.tenant, &flow_id.to_string()).await?;
+    let rows = Self::stitched_rows(&tx, &self.tenant, &segments, from_cursor).await?;
+    tx.commit().await.map_err(map_pg_err)?;
+    rows.iter().map(|r| Ok(FlowData { flow_id: *flow_id, ..flow_data_from_row(r)? })).collect()
+  }
+  async fn query_data(&self, flow_id: &Uuid, query: &FlowDataQuery) -> FlowResult<Vec<FlowData>> {
+    self.query_data_rows(flow_id, query, true).await
+  }
+  async fn query_data_meta(&self, flow_id: &Uuid, query: &FlowDataQuery) -> FlowResult<Vec<FlowDataMeta>> {
+    Ok(self.query_data_rows(flow_id, query, false).await?.iter().map(FlowDataMeta::from).collect())
+  }
+  async fn latest_by_key(&self, flow_id: &Uuid, key: &str) -> FlowResult<Option<FlowData>> {
+    let query = FlowDataQuery::new().key(key).latest_first().limit(1);
+    Ok(self.query_data_rows(flow_id, &query, true).await?.into_iter().next())
+  }
+  async fn subscribe(&self, flow_id: &Uuid, from_cursor: i64) -> FlowResult<FlowSubscription> {
+    {
+      let client = self.client().await?;
+      if !Self::flow_exists(&client, &self.tenant, &flow_id.to_string()).await? {
+        return Err(FlowError::NotFound(format!("flow {}", flow_id)));
+      }
+    }
+    self.ensure_listener().await?;
+    let live = self.feed.register(*flow_id);
+    let backlog = self.read_data(flow_id, from_cursor).await?;
+    Ok(FlowSubscription::new(*flow_id, from_cursor, backlog, live))
+  }
+  async fn load_latest_snapshot(&self, flow_id: &Uuid) -> FlowResult<Option<SnapshotMeta>> {
+    let client = self.client().await?;
+    if !Self::flow_exists(&client, &self.tenant, &flow_id.to_string()).await? {
+      return Ok(None);
+    }
+    let row = client.query_opt(&format!("SELECT {} FROM snapshots WHERE flow_id = $1 AND tenant_id = $2 AND deleted_at_ts \
+                                         IS NULL ORDER BY cursor DESC, created_at_ts DESC LIMIT 1",
+                                        SNAP_COLS),
+                               &[&flow_id.to_string(), &self.tenant])
+                    .await
+                    .map_err(map_pg_err)?;
+    row.as_ref().map(snapshot_from_row).transpose()
+  }
+  async fn load_snapshot_at(&self, flow_id: &Uuid, cursor: i64) -> FlowResult<Option<SnapshotMeta>> {
+    let client = self.client().await?;
+    if !Self::flow_exists(&client, &self.tenant, &flow_id.to_string()).await? {
+      return Ok(None);
+    }
+    let row = client.query_opt(&format!("SELECT {} FROM snapshots WHERE flow_id = $1 AND cursor <= $2 AND tenant_id = $3 \
+                                         AND deleted_at_ts IS NULL ORDER BY cursor DESC, created_at_ts DESC LIMIT 1",
+                                        SNAP_COLS),
+                               &[&flow_id.to_string(), &cursor, &self.tenant])
+                    .await
+                    .map_err(map_pg_err)?;
+    row.as_ref().map(snapshot_from_row).transpose()
+  }
+  async fn load_snapshot(&self, snapshot_id: &Uuid) -> FlowResult<(Vec<u8>, SnapshotMeta)> {
+    let client = self.client().await?;
+    let row = client.query_opt(&format!("SELECT {} FROM snapshots WHERE id = $1 AND tenant_id = $2", SNAP_COLS),
+                               &[&snapshot_id.to_string(), &self.tenant])
+                    .await
+                    .map_err(map_pg_err)?
+                    .ok_or_else(|| FlowError::NotFound(format!("snapshot {}", snapshot_id)))?;
+    let meta = snapshot_from_row(&row)?;
+    let ptr = meta.state_ptr.clone();
+    let bytes = self.with_store(move |store| load_snapshot_blob(store, &ptr)).await?;
+    Ok((bytes, meta))
+  }
+  async fn save_snapshot(&self, flow_id: &Uuid, cursor: i64, state_ptr: &str, metadata: JsonValue) -> FlowResult<Uuid> {
+    let client = self.client().await?;
+    let new_id = Uuid::new_v4();
+    client.execute(&format!("INSERT INTO snapshots ({}) VALUES ($1, $2, $3, $4, $5, $6, $7)", SNAP_COLS),
+                   &[&new_id.to_string(),
+                     &flow_id.to_string(),
+                     &cursor,
+                     &state_ptr,
+                     &metadata.to_string(),
+                     &Utc::now().timestamp(),
+                     &self.tenant])
+          .await
+          .map_err(map_pg_err)?;
+    Ok(new_id)
+  }
+  async fn save_snapshot_state(&self, flow_id: &Uuid, cursor: i64, state: &[u8], metadata: JsonValue) -> FlowResult<Uuid> {
+    let state = state.to_vec();
+    let key = self.with_store(move |store| store.save(&state)).await?;
+    self.save_snapshot(flow_id, cursor, &key, metadata).await
+  }
+  async fn compact_snapshots(&self, flow_id: &Uuid) -> FlowResult<usize> {
+    Ok(self.compact_flow_snapshots(flow_id).await?.0)
+  }
+  /// Igual que en `DieselFlowRepository`: compacta cada flow del tenant,
+  /// lista los blobs y solo después lee las referencias de todos los
+  /// tenants (el store es compartido).
+  async fn gc_snapshots(&self) -> FlowResult<SnapshotGcReport> {
+    let mut report = SnapshotGcReport::default();
+    for flow_id in self.list_flow_ids().await? {
+      match self.compact_flow_snapshots(&flow_id).await {
+        Ok((snaps, blobs)) => {
+          report.snapshots_removed += snaps;
+          report.blobs_removed += blobs;
+        }
+        Err(FlowError::NotFound(_)) => {}
+        Err(e) => return Err(e),
+      }
+    }
+    let blobs = self.with_store(|store| store.list()).await?;
+    let referenced: HashSet<String> = {
+      let client = self.client().await?;
+      client.query("SELECT DISTINCT state_ptr FROM snapshots", &[])
+            .await
+            .map_err(map_pg_err)?
+            .iter()
+            .filter_map(|r| r.get::<_, Option<String>>(0))
+            .collect()
+    };
+    let orphans = orphan_blob_keys(&blobs, &referenced, &self.retention, Utc::now());
+    report.blobs_removed += orphans.len();
+    self.with_store(move |store| orphans.iter().try_for_each(|k| store.delete(k))).await?;
+    Ok(report)
+  }
+  async fn create_branch(&self, parent_flow_id: &Uuid, parent_cursor: i64, metadata: JsonValue) -> FlowResult<Uuid> {
+    let mut client = self.client().await?;
+    let tx = client.transaction().await.map_err(map_pg_err)?;
+    let parent_id = parent_flow_id.to_string();
+    let Some(parent) = tx.query_opt("SELECT name, status FROM flows WHERE id = $1 AND tenant_id = $2 AND deleted_at_ts IS \
+                                     NULL",
+                                    &[&parent_id, &self.tenant])
+                         .await
+                         .map_err(map_pg_err)?
+    else {
+      return Err(FlowError::NotFound(format!("flow {}", parent_flow_id)));
+    };
+    let name = parent.get::<_, Option<String>>(0).map(|n| format!("{}_branch", n)).unwrap_or_else(|| "branch".into());
+    let status: Option<String> = parent.get(1);
+    let new_id = Uuid::new_v4();
+    let new_id_s = new_id.to_string();
+    tx.execute("INSERT INTO flows (id, name, status, created_by, created_at_ts, current_cursor, current_version, \
+                parent_flow_id, parent_cursor, metadata, tenant_id) VALUES ($1, $2, $3, NULL, $4, $5, 0, $6, $5, \
+                $7::text::jsonb, $8)",
+               &[&new_id_s,
+                 &name,
+                 &status,
+                 &Utc::now().timestamp(),
+                 &parent_cursor,
+                 &parent_id,
+                 &metadata.to_string(),
+                 &self.tenant])
+      .await
+      .map_err(map_pg_err)?;
+    // los flow_data (y sus artifact_refs) se quedan en el padre; solo se
+    // copian las filas de snapshot
+    let snaps = tx.query(&format!("SELECT {} FROM snapshots WHERE flow_id = $1 AND cursor <= $2 AND tenant_id = $3 AND \
+                                   deleted_at_ts IS NULL",
+                                  SNAP_COLS),
+                         &[&parent_id, &parent_cursor, &self.tenant])
+                  .await
+                  .map_err(map_pg_err)?;
+    for s in snaps {
+      tx.execute(&format!("INSERT INTO snapshots ({}) VALUES ($1, $2, $3, $4, $5, $6, $7)", SNAP_COLS),
+                 &[&Uuid::new_v4().to_string(),
+                   &new_id_s,
+                   &s.get::<_, Option<i64>>("cursor"),
+                   &s.get::<_, Option<String>>("state_ptr"),
+                   &s.get::<_, Option<String>>("metadata"),
+                   &s.get::<_, Option<i64>>("created_at_ts"),
+                   &self.tenant])
+        .await
+        .map_err(map_pg_err)?;
+    }
+    tx.commit().await.map_err(map_pg_err)?;
+    Ok(new_id)
+  }
+  async fn branch_exists(&self, flow_id: &Uuid) -> FlowResult<bool> {
+    let client = self.client().await?;
+    Self::flow_exists(&client, &self.tenant, &flow_id.to_string()).await
+  }
+  async fn list_children(&self, flow_id: &Uuid) -> FlowResult<Vec<FlowMeta>> {
+    let client = self.client().await?;
+    let fid = flow_id.to_string();
+    if !Self::flow_exists(&client, &self.tenant, &fid).await? {
+      return Err(FlowError::NotFound(format!("flow {}", flow_id)));
+    }
+    let rows = client.query(&format!("SELECT {} FROM flows WHERE parent_flow_id = $1 AND tenant_id = $2 AND deleted_at_ts \
+                                      IS NULL ORDER BY parent_cursor, created_at_ts, id",
+                                     FLOW_COLS),
+                            &[&fid, &self.tenant])
+                     .await
+                     .map_err(map_pg_err)?;
+    rows.iter().map(flow_meta_from_row).collect()
+  }
+  async fn count_steps(&self, flow_id: &Uuid) -> FlowResult<i64> {
+    let client = self.client().await?;
+    let fid = flow_id.to_string();
+    let Some(row) = client.query_opt("SELECT current_cursor FROM flows WHERE id = $1 AND tenant_id = $2 AND deleted_at_ts \
+                                      IS NULL",
+                                     &[&fid, &self.tenant])
+                          .await
+                          .map_err(map_pg_err)?
+    else {
+      return Ok(-1);
+    };
+    let current_cursor = row.get::<_, Option<i64>>(0).unwrap_or(0);
+    let mut count = 0;
+    for seg in Self::segments_of(&client, &self.tenant, &fid).await? {
+      let row = client.query_one("SELECT COUNT(*) FROM flow_data WHERE flow_id = $1 AND cursor > $2 AND cursor <= $3 AND \
+                                  tenant_id = $4 AND deleted_at_ts IS NULL",
+                                 &[&seg.flow_id.to_string(),
+                                   &seg.after.unwrap_or(i64::MIN),
+                                   &seg.up_to.unwrap_or(i64::MAX).min(current_cursor),
+                                   &self.tenant])
+                      .await
+                      .map_err(map_pg_err)?;
+      count += row.get::<_, i64>(0);
+    }
+    Ok(count)
+  }
+  async fn delete_branch(&self, flow_id: &Uuid) -> FlowResult<()> {
+    let mut client = self.client().await?;
+    let fid = flow_id.to_string();
+    let tx = client.transaction().await.map_err(map_pg_err)?;
+    if !Self::flow_exists(&tx, &self.tenant, &fid).await? {
+      return Err(FlowError::NotFound(format!("flow {}", flow_id)));
+    }
+    // las hijas conservan su enlace y siguen leyendo a través de esta rama
+    // hasta que se purgue
+    tx.execute("DELETE FROM work_queue WHERE flow_id = $1 AND tenant_id = $2", &[&fid, &self.tenant])
+      .await
+      .map_err(map_pg_err)?;
+    tx.execute("UPDATE flows SET deleted_at_ts = $2 WHERE id = $1 AND tenant_id = $3",
+               &[&fid, &Utc::now().timestamp(), &self.tenant])
+      .await
+      .map_err(map_pg_err)?;
+    tx.commit().await.map_err(map_pg_err)
+  }
+  /// Igual que en `DieselFlowRepository`: el truncado queda en
+  /// `flow_truncations` para `undelete_steps`.
+  async fn delete_from_step(&self, flow_id: &Uuid, from_cursor: i64) -> FlowResult<()> {
+    let mut client = self.client().await?;
+    let fid = flow_id.to_string();
+    let now = Utc::now();
+    let now_ts = now.timestamp();
+    let tx = client.transaction().await.map_err(map_pg_err)?;
+    let live = format!("SELECT {} FROM flows WHERE id = $1 AND tenant_id = $2 AND deleted_at_ts IS NULL FOR UPDATE",
+                       FLOW_COLS);
+    let Some(row) = tx.query_opt(&live, &[&fid, &self.tenant]).await.map_err(map_pg_err)? else {
+      return Err(FlowError::NotFound(format!("flow {}", flow_id)));
+    };
+    let flow = flow_meta_from_row(&row)?;
+    // descendientes que leen desde from_cursor: cada tramo heredado llega
+    // hasta el menor parent_cursor del camino
+    let mut affected = Vec::new();
+    let mut seen = HashSet::from([fid.clone()]);
+    let mut frontier = vec![fid.clone()];
+    while let Some(parent) = frontier.pop() {
+      let children = tx.query("SELECT id, deleted_at_ts FROM flows WHERE parent_flow_id = $1 AND parent_cursor >= $2 AND \
+                               tenant_id = $3",
+                              &[&parent, &from_cursor, &self.tenant])
+                       .await
+                       .map_err(map_pg_err)?;
+      for child in children {
+        let child_id: String = child.get(0);
+        if !seen.insert(child_id.clone()) {
+          continue;
+        }
+        if child.get::<_, Option<i64>>(1).is_none() {
+          affected.push(child_id.clone());
+        }
+        frontier.push(child_id);
+      }
+    }
+    let ids_of = |rows: Vec<Row>| rows.iter().map(|r| r.get::<_, String>(0)).collect::<Vec<_>>();
+    let data_ids = ids_of(tx.query("UPDATE flow_data SET deleted_at_ts = $3 WHERE flow_id = $1 AND cursor >= $2 AND \
+                                    tenant_id = $4 AND deleted_at_ts IS NULL RETURNING id",
+                                   &[&fid, &from_cursor, &now_ts, &self.tenant])
+                            .await
+                            .map_err(map_pg_err)?);
+    let snapshot_ids = ids_of(tx.query("UPDATE snapshots SET deleted_at_ts = $3 WHERE flow_id = $1 AND cursor >= $2 AND \
+                                        tenant_id = $4 AND deleted_at_ts IS NULL RETURNING id",
+                                       &[&fid, &from_cursor, &now_ts, &self.tenant])
+                                .await
+                                .map_err(map_pg_err)?);
+    tx.execute("UPDATE flows SET deleted_at_ts = $2 WHERE id = ANY($1) AND tenant_id = $3",
+               &[&affected, &now_ts, &self.tenant])
+      .await
+      .map_err(map_pg_err)?;
+    tx.execute("DELETE FROM work_queue WHERE flow_id = ANY($1) AND tenant_id = $2", &[&affected, &self.tenant])
+      .await
+      .map_err(map_pg_err)?;
+    // en una rama, el tramo heredado no puede pasar de from_cursor - 1
+    let parent_cursor = flow.parent_cursor.filter(|pc| flow.parent_flow_id.is_some() && *pc >= from_cursor);
+    if parent_cursor.is_some() {
+      tx.execute("UPDATE flows SET parent_cursor = $2 WHERE id = $1 AND tenant_id = $3",
+                 &[&fid, &(from_cursor - 1), &self.tenant])
+        .await
+        .map_err(map_pg_err)?;
+    }
+    Self::refresh_cursor(&tx, &self.tenant, &fid).await?;
+    let ids_json = |ids: &[String]| serde_json::json!(ids).to_string();
+    tx.execute("INSERT INTO flow_truncations (id, tenant_id, flow_id, from_cursor, flow_version, parent_cursor, at_us, \
+                deleted_at_ts, data_ids, snapshot_ids, flow_ids) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11)",
+               &[&Uuid::new_v4().to_string(),
+                 &self.tenant,
+                 &fid,
+                 &from_cursor,
+                 &flow.current_version,
+                 &parent_cursor,
+                 &now.timestamp_micros(),
+                 &now_ts,
+                 &ids_json(&data_ids),
+                 &ids_json(&snapshot_ids),
+                 &ids_json(&affected)])
+      .await
+      .map_err(map_pg_err)?;
+    tx.commit().await.map_err(map_pg_err)
+  }
+  /// Igual que en `DieselFlowRepository`, en una sola transacción.
+  async fn undelete_steps(&self, flow_id: &Uuid) -> FlowResult<()> {
+    let mut client = self.client().await?;
+    let fid = flow_id.to_string();
+    let tx = client.transaction().await.map_err(map_pg_err)?;
+    let Some(flow) = tx.query_opt("SELECT current_version FROM flows WHERE id = $1 AND tenant_id = $2 AND deleted_at_ts IS \
+                                   NULL FOR UPDATE",
+                                  &[&fid, &self.tenant])
+                       .await
+                       .map_err(map_pg_err)?
+    else {
+      return Err(FlowError::NotFound(format!("flow {}", flow_id)));
+    };
+    let Some(record) = tx.query_opt("SELECT id, flow_version, parent_cursor, data_ids, snapshot_ids, flow_ids FROM \
+                                     flow_truncations WHERE tenant_id = $1 AND flow_id = $2 ORDER BY at_us DESC LIMIT 1",
+                                    &[&self.tenant, &fid])
+                         .await
+                         .map_err(map_pg_err)?
+    else {
+      return Err(FlowError::NotFound(format!("pasos truncados del flow {}", flow_id)));
+    };
+    if record.get::<_, i64>(1) != flow.get::<_, i64>(0) {
+      return Err(FlowError::Conflict(format!("el flow {} persistió pasos después de truncar", flow_id)));
+    }
+    tx.execute("DELETE FROM flow_truncations WHERE id = $1", &[&record.get::<_, String>(0)])
+      .await
+      .map_err(map_pg_err)?;
+    let ids = |col: usize| truncated_ids(&record.get::<_, String>(col));
+    for (sql, col) in [("UPDATE flow_data SET deleted_at_ts = NULL WHERE id = ANY($1) AND tenant_id = $2", 3),
+                       ("UPDATE snapshots SET deleted_at_ts = NULL WHERE id = ANY($1) AND tenant_id = $2", 4)]
+    {
+      tx.execute(sql, &[&ids(col), &self.tenant]).await.map_err(map_pg_err)?;
+    }
+    if let Some(pc) = record.get::<_, Option<i64>>(2) {
+      tx.execute("UPDATE flows SET parent_cursor = $2 WHERE id = $1 AND tenant_id = $3", &[&fid, &pc, &self.tenant])
+        .await
+        .map_err(map_pg_err)?;
+    }
+    // vuelven las ramas borradas con el truncado que no lean otros pasos
+    // truncados
+    let deleted = format!("SELECT {} FROM flows WHERE id = $1 AND tenant_id = $2 AND deleted_at_ts IS NOT NULL",
+                          FLOW_COLS);
+    for child in ids(5) {
+      let Some(row) = tx.query_opt(&deleted, &[&child, &self.tenant]).await.map_err(map_pg_err)? else {
+        continue;
+      };
+      if !Self::reads_truncated(&tx, &self.tenant, &flow_meta_from_row(&row)?).await? {
+        tx.execute("UPDATE flows SET deleted_at_ts = NULL WHERE id = $1 AND tenant_id = $2", &[&child, &self.tenant])
+          .await
+          .map_err(map_pg_err)?;
+      }
+    }
+    Self::refresh_cursor(&tx, &self.tenant, &fid).await?;
+    tx.commit().await.map_err(map_pg_err)
+  }
+  async fn undelete(&self, flow_id: &Uuid) -> FlowResult<()> {
+    let client = self.client().await?;
+    let fid = flow_id.to_string();
+    let sql = format!("SELECT {} FROM flows WHERE id = $1 AND tenant_id = $2", FLOW_COLS);
+    let Some(row) = client.query_opt(&sql, &[&fid, &self.tenant]).await.map_err(map_pg_err)? else {
+      return Err(FlowError::NotFound(format!("flow {}", flow_id)));
+    };
+    if row.get::<_, Option<i64>>("deleted_at_ts").is_some() {
+      if Self::reads_truncated(&client, &self.tenant, &flow_meta_from_row(&row)?).await? {
+        return Err(FlowError::Conflict(format!("el historial del flow {} pasa por pasos truncados", flow_id)));
+      }
+      client.execute("UPDATE flows SET deleted_at_ts = NULL WHERE id = $1 AND tenant_id = $2", &[&fid, &self.tenant])
+            .await
+            .map_err(map_pg_err)?;
+    }
+    Ok(())
+  }
+  async fn list_deleted_flows(&self) -> FlowResult<Vec<FlowMeta>> {
+    let client = self.client().await?;
+    let rows = client.query(&format!("SELECT {} FROM flows WHERE tenant_id = $1 AND deleted_at_ts IS NOT NULL ORDER BY \
+                                      deleted_at_ts, id",
+                                     FLOW_COLS),
+                            &[&self.tenant])
+                     .await
+                     .map_err(map_pg_err)?;
+    rows.iter().map(flow_meta_from_row).collect()
+  }
+  /// Igual que en `DieselFlowRepository`, en una sola transacción.
+  async fn purge_deleted(&self, retention: Duration) -> FlowResult<PurgeReport> {
+    let cutoff = purge_cutoff(retention, Utc::now()).timestamp();
+    let mut client = self.client().await?;
+    let tx = client.transaction().await.map_err(map_pg_err)?;
+    // el padre de una rama con un truncado pendiente que recortó su tramo
+    // heredado se conserva
+    let flow_ids: Vec<String> = tx.query("SELECT id FROM flows WHERE deleted_at_ts <= $1 AND tenant_id = $2 AND id NOT IN \
+                                          (SELECT f.parent_flow_id FROM flows f JOIN flow_truncations t ON t.flow_id = f.id \
+                                          AND t.tenant_id = f.tenant_id WHERE f.tenant_id = $2 AND t.deleted_at_ts > $1 \
+                                          AND t.parent_cursor IS NOT NULL AND f.parent_flow_id IS NOT NULL)",
+                                         &[&cutoff, &self.tenant])
+                                  .await
+                                  .map_err(map_pg_err)?
+                                  .iter()
+                                  .map(|r| r.get(0))
+                                  .collect();
+    // las hijas que quedan reciben copias de lo que heredaban a través de un
+    // flow purgado y quedan huérfanas
+    let survivors = tx.query(&format!("SELECT {} FROM flows WHERE parent_flow_id = ANY($1) AND NOT (id = ANY($1)) AND \
+                                       tenant_id = $2",
+                                      FLOW_COLS),
+                             &[&flow_ids, &self.tenant])
+                      .await
+                      .map_err(map_pg_err)?
+                      .iter()
+                      .map(flow_meta_from_row)
+                      .collect::<FlowResult<Vec<_>>>()?;
+    let mut copies = Vec::with_capacity(survivors.len());
+    for child in &survivors {
+      let segments = data_segments(child, &Self::chain_of(&tx, &self.tenant, child).await?);
+      copies.push(Self::stitched_rows(&tx, &self.tenant, &segments[1..], 0).await?);
+    }
+    for (child, rows) in survivors.iter().zip(&copies) {
+      let child_id = child.id.to_string();
+      Self::copy_inherited(&tx, &self.tenant, &child_id, rows, child.parent_cursor.unwrap_or(0)).await?;
+      tx.execute("UPDATE flows SET parent_flow_id = NULL, parent_cursor = NULL WHERE id = $1 AND tenant_id = $2",
+                 &[&child_id, &self.tenant])
+        .await
+        .map_err(map_pg_err)?;
+    }
+    for sql in ["DELETE FROM flow_data WHERE flow_id = ANY($1)",
+                "DELETE FROM snapshots WHERE flow_id = ANY($1)",
+                "DELETE FROM artifact_refs WHERE flow_id = ANY($1)",
+                "DELETE FROM work_queue WHERE flow_id = ANY($1)",
+                "DELETE FROM flows WHERE id = ANY($1)"]
+    {
+      tx.execute(sql, &[&flow_ids]).await.map_err(map_pg_err)?;
+    }
+    tx.execute("DELETE FROM flow_truncations WHERE tenant_id = $3 AND (flow_id = ANY($1) OR deleted_at_ts <= $2)",
+               &[&flow_ids, &cutoff, &self.tenant])
+      .await
+      .map_err(map_pg_err)?;
+    let truncated = tx.query("DELETE FROM flow_data WHERE deleted_at_ts <= $1 AND tenant_id = $2 RETURNING flow_id, \
+                              cursor",
+                             &[&cutoff, &self.tenant])
+                      .await
+                      .map_err(map_pg_err)?;
+    let cursors: HashSet<(String, i64)> =
+      truncated.iter().map(|r| (r.get(0), r.get::<_, Option<i64>>(1).unwrap_or(0))).collect();
+    // las referencias del cursor se recalculan con los registros que quedan
+    for (fid, cursor) in &cursors {
+      tx.execute("DELETE FROM artifact_refs WHERE flow_id = $1 AND cursor = $2", &[fid, cursor])
+        .await
+        .map_err(map_pg_err)?;
+      let rows = tx.query(&format!("SELECT {} FROM flow_data WHERE flow_id = $1 AND cursor = $2", DATA_SELECT),
+                          &[fid, cursor])
+                   .await
+                   .map_err(map_pg_err)?;
+      for row in rows {
+        for key in artifact_refs(&flow_data_from_row(&row)?) {
+          tx.execute("INSERT INTO artifact_refs (flow_id, cursor, artifact_key, tenant_id) VALUES ($1, $2, $3, $4) ON \
+                      CONFLICT DO NOTHING",
+                     &[fid, cursor, &key, &self.tenant])
+            .await
+            .map_err(map_pg_err)?;
+        }
+      }
+    }
+    let snapshots =
+      tx.execute("DELETE FROM snapshots WHERE deleted_at_ts <= $1 AND tenant_id = $2", &[&cutoff, &self.tenant])
+        .await
+        .map_err(map_pg_err)? as usize;
+    tx.commit().await.map_err(map_pg_err)?;
+    Ok(PurgeReport { flows: flow_ids.len(), records: truncated.len(), snapshots })
+  }
+  async fn rewrite_data(&self, rows: &[FlowData]) -> FlowResult<usize> {
+    let mut client = self.client().await?;
+    let tx = client.transaction().await.map_err(map_pg_err)?;
+    for row in rows {
+      let id = row.id.to_string();
+      let Some(target) = tx.query_opt("UPDATE flow_data SET payload = $2::text::jsonb, metadata = $3::text::jsonb WHERE \
+                                       id = $1 AND tenant_id = $4 AND deleted_at_ts IS NULL RETURNING flow_id, cursor",
+                                      &[&id, &row.payload.to_string(), &row.metadata.to_string(), &self.tenant])
+                           .await
+                           .map_err(map_pg_err)?
+      else {
+        return Err(FlowError::NotFound(format!("flow_data {}", row.id)));
+      };
+      let (fid, cursor): (String, i64) = (target.get(0), target.get::<_, Option<i64>>(1).unwrap_or_default());
+      tx.execute("DELETE FROM artifact_refs WHERE flow_id = $1 AND cursor = $2", &[&fid, &cursor])
+        .await
+        .map_err(map_pg_err)?;
+      for key in artifact_refs(row) {
+        tx.execute("INSERT INTO artifact_refs (flow_id, cursor, artifact_key, tenant_id) VALUES ($1, $2, $3, $4) ON \
+                    CONFLICT DO NOTHING",
+                   &[&fid, &cursor, &key, &self.tenant])
+          .await
+          .map_err(map_pg_err)?;
+      }
+    }
+    tx.commit().await.map_err(map_pg_err)?;
+    Ok(rows.len())
+  }
+  async fn lock_for_update(&self,
+                           flow_id: &Uuid,
+                           expected_version: i64,
+                           timeout: Duration)
+                           -> FlowResult<Option<FlowLockGuard>> {
+    let fid = flow_id.to_string();
+    if Self::current_version(&self.client().await?, &self.tenant, &fid).await?.is_none() {
+      return Err(FlowError::NotFound(format!("flow {}", flow_id)));
+    }
+    let Some(guard) = self.acquire_flow_lock(flow_id, timeout).await? else {
+      return Err(FlowError::Conflict(format!("timeout esperando lock del flow {}", flow_id)));
+    };
+    let version = Self::current_version(&self.client().await?, &self.tenant, &fid).await?;
+    Ok(if version == Some(expected_version) { Some(guard) } else { None })
+  }
+  async fn enqueue_work(&self, flow_id: &Uuid) -> FlowResult<()> {
+    let client = self.client().await?;
+    let fid = flow_id.to_string();
+    if !Self::flow_exists(&client, &self.tenant, &fid).await? {
+      return Err(FlowError::NotFound(format!("flow {}", flow_id)));
+    }
+    client.execute("INSERT INTO work_queue (flow_id, worker_id, lease_expires_at_ms, attempts, enqueued_at_ms, \
+                    tenant_id) VALUES ($1, NULL, NULL, 0, $2, $3) ON CONFLICT (flow_id) DO NOTHING",
+                   &[&fid, &Utc::now().timestamp_millis(), &self.tenant])
+          .await
+          .map_err(map_pg_err)?;
+    Ok(())
+  }
+  async fn claim_work(&self, worker_id: &str) -> FlowResult<Option<WorkItem>> {
+    let mut client = self.client().await?;
+    let now_ms = Utc::now().timestamp_millis();
+    let lease_until = now_ms + self.lease_ms;
+    let tx = client.transaction().await.map_err(map_pg_err)?;
+    let Some(row) = tx.query_opt("SELECT flow_id, attempts FROM work_queue WHERE tenant_id = $2 AND (worker_id IS NULL OR \
+                                  lease_expires_at_ms <= $1) ORDER BY enqueued_at_ms, flow_id LIMIT 1 \
+                                  FOR UPDATE SKIP LOCKED",
+                                 &[&now_ms, &self.tenant])
+                      .await
+                      .map_err(map_pg_err)?
+    else {
+      return Ok(None);
+    };
+    let fid: String = row.get(0);
+    let attempts = row.get::<_, i64>(1) + 1;
+    tx.execute("UPDATE work_queue SET worker_id = $2, lease_expires_at_ms = $3, attempts = $4 WHERE flow_id = $1",
+               &[&fid, &worker_id, &lease_until, &attempts])
+      .await
+      .map_err(map_pg_err)?;
+    tx.commit().await.map_err(map_pg_err)?;
+    let flow_uuid = Uuid::parse_str(&fid).map_err(|e| FlowError::Storage(format!("work_queue flow_id: {}", e)))?;
+    let last_cursor = client.query_opt("SELECT current_cursor FROM flows WHERE id = $1 AND tenant_id = $2",
+                                       &[&fid, &self.tenant])
+                            .await
+                            .map_err(map_pg_err)?
+                            .and_then(|r| r.get::<_, Option<i64>>(0))
+                            .unwrap_or(0);
+    drop(client);
+    let snapshot_ptr = self.load_latest_snapshot(&flow_uuid).await?.map(|s| s.state_ptr);
+    Ok(Some(WorkItem { flow_id: flow_uuid,
+                       last_cursor,
+                       snapshot_ptr,
+                       worker_id: worker_id.to_string(),
+                       lease_expires_at: Utc.timestamp_millis_opt(lease_until).single().unwrap_or(Utc::now()),
+                       attempts }))
+  }
+  async fn heartbeat_work(&self, flow_id: &Uuid, worker_id: &str) -> FlowResult<bool> {
+    let client = self.client().await?;
+    let lease_until = Utc::now().timestamp_millis() + self.lease_ms;
+    let n = client.execute("UPDATE work_queue SET lease_expires_at_ms = $3 WHERE flow_id = $1 AND worker_id = $2 AND \
+                            tenant_id = $4",
+                           &[&flow_id.to_string(), &worker_id, &lease_until, &self.tenant])
+                  .await
+                  .map_err(map_pg_err)?;
+    Ok(n > 0)
+  }
+  async fn complete_work(&self, flow_id: &Uuid, worker_id: &str) -> FlowResult<bool> {
+    let client = self.client().await?;
+    let n = client.execute("DELETE FROM work_queue WHERE flow_id = $1 AND worker_id = $2 AND tenant_id = $3",
+                           &[&flow_id.to_string(), &worker_id, &self.tenant])
+                  .await
+                  .map_err(map_pg_err)?;
+    Ok(n > 0)
+  }
+  async fn get_flow_status(&self, flow_id: &Uuid) -> FlowResult<Option<String>> {
+    let client = self.client().await?;
+    let row = client.query_opt("SELECT status FROM flows WHERE id = $1 AND tenant_id = $2 AND deleted_at_ts IS NULL",
+                               &[&flow_id.to_string(), &self.tenant])
+                    .await
+                    .map_err(map_pg_err)?;
+    Ok(row.and_then(|r| r.get::<_, Option<String>>(0)))
+  }
+  async fn set_flow_status(&self, flow_id: &Uuid, new_status: Option<String>) -> FlowResult<FlowMeta> {
+    let client = self.client().await?;
+    let row = client.query_opt(&format!("UPDATE flows SET status = $2 WHERE id = $1 AND tenant_id = $3 AND deleted_at_ts IS \
+                                         NULL RETURNING {}",
+                                        FLOW_COLS),
+                               &[&flow_id.to_string(), &new_status, &self.tenant])
+                    .await
+                    .map_err(map_pg_err)?
+                    .ok_or_else(|| FlowError::NotFound(format!("flow {}", flow_id)))?;
+    flow_meta_from_row(&row)
+  }
+  async fn get_meta(&self, flow_id: &Uuid, key: &str) -> FlowResult<JsonValue> {
+    let client = self.client().await?;
+    let row = client.query_opt("SELECT metadata::text AS metadata FROM flows WHERE id = $1 AND tenant_id = $2 AND \
+                                deleted_at_ts IS NULL",
+                               &[&flow_id.to_string(), &self.tenant])
+                    .await
+                    .map_err(map_pg_err)?
+                    .ok_or_else(|| FlowError::NotFound(format!("flow {}", flow_id)))?;
+    Ok(json_col(&row, "metadata").get(key).cloned().unwrap_or(JsonValue::Null))
+  }
+  async fn set_meta(&self, flow_id: &Uuid, key: &str, value: JsonValue) -> FlowResult<()> {
+    let mut client = self.client().await?;
+    let fid = flow_id.to_string();
+    let tx = client.transaction().await.map_err(map_pg_err)?;
+    let row = tx.query_opt("SELECT metadata::text AS metadata FROM flows WHERE id = $1 AND tenant_id = $2 AND \
+                            deleted_at_ts IS NULL FOR UPDATE",
+                           &[&fid, &self.tenant])
+                .await
+                .map_err(map_pg_err)?
+                .ok_or_else(|| FlowError::NotFound(format!("flow {}", flow_id)))?;
+    let mut meta = json_col(&row, "metadata");
+    if !meta.is_object() {
+      meta = serde_json::json!({});
+    }
+    if let Some(obj) = meta.as_object_mut() {
+      obj.insert(key.to_string(), value);
+    }
+    tx.execute("UPDATE flows SET metadata = $2::text::jsonb WHERE id = $1", &[&fid, &meta.to_string()])
+      .await
+      .map_err(map_pg_err)?;
+    tx.commit().await.map_err(map_pg_err)
+  }
+  async fn del_meta(&self, flow_id: &Uuid, key: &str) -> FlowResult<()> {
+    let mut client = self.client().await?;
+    let fid = flow_id.to_string();
+    let tx = client.transaction().await.map_err(map_pg_err)?;
+    let row = tx.query_opt("SELECT metadata::text AS metadata FROM flows WHERE id = $1 AND tenant_id = $2 AND \
+                            deleted_at_ts IS NULL FOR UPDATE",
+                           &[&fid, &self.tenant])
+                .await
+                .map_err(map_pg_err)?
+                .ok_or_else(|| FlowError::NotFound(format!("flow {}", flow_id)))?;
+    let mut meta = json_col(&row, "metadata");
+    if let Some(obj) = meta.as_object_mut() {
+      obj.remove(key);
+    }
+    tx.execute("UPDATE flows SET metadata = $2::text::jsonb WHERE id = $1", &[&fid, &meta.to_string()])
+      .await
+      .map_err(map_pg_err)?;
+    tx.commit().await.map_err(map_pg_err)
+  }
+  async fn list_flow_ids(&self) -> FlowResult<Vec<Uuid>> {
+    let client = self.client().await?;
+    let rows = client.query("SELECT id FROM flows WHERE tenant_id = $1 AND deleted_at_ts IS NULL", &[&self.tenant])
+                     .await
+                     .map_err(map_pg_err)?;
+    Ok(rows.iter().filter_map(|r| Uuid::parse_str(r.get::<_, &str>(0)).ok()).collect())
+  }
+  async fn query_flows(&self, query: &FlowQuery) -> FlowResult<FlowPage> {
+    query.validate()?;
+    let mut params: Vec<SqlParam> = vec![Box::new(self.tenant.clone())];
+    let mut conds = vec!["tenant_id = $1".to_string(), "deleted_at_ts IS NULL".to_string()];
+    if let Some(s) = &query.status {
+      conds.push(format!("status = {}", bind(&mut params, Box::new(s.clone()))));
+    }
+    if let Some(c) = &query.created_by {
+      conds.push(format!("created_by = {}", bind(&mut params, Box::new(c.clone()))));
+    }
+    if let Some(p) = query.parent_flow_id {
+      conds.push(format!("parent_flow_id = {}", bind(&mut params, Box::new(p.to_string()))));
+    }
+    if let Some(since) = query.created_since {
+      conds.push(format!("created_at_ts >= {}", bind(&mut params, Box::new(since.timestamp()))));
+    }
+    if let Some(until) = query.created_until {
+      conds.push(format!("created_at_ts < {}", bind(&mut params, Box::new(until.timestamp()))));
+    }
+    for pred in query.json_predicates() {
+      conds.push(json_cond(&mut params, &pred));
+    }
+    if let Some(p) = &query.name_prefix {
+      let len = bind(&mut params, Box::new(p.chars().count() as i32));
+      let prefix = bind(&mut params, Box::new(p.clone()));
+      conds.push(format!("substr(name, 1, {}) = {}", len, prefix));
+    }
+    let (key_col, dir, op) = match query.sort {
+      FlowSort::CreatedAsc => ("created_at_ts", "ASC", ">"),
+      FlowSort::CreatedDesc => ("created_at_ts", "DESC", "<"),
+      FlowSort::NameAsc => ("COALESCE(name, '')", "ASC", ">"),
+      FlowSort::NameDesc => ("COALESCE(name, '')", "DESC", "<"),
+    };
+    if let Some(c) = &query.after {
+      let key = if query.sort.by_name() {
+        bind(&mut params, Box::new(c.name.clone().unwrap_or_default()))
+      } else {
+        bind(&mut params, Box::new(c.created_at.timestamp()))
+      };
+      let cid = bind(&mut params, Box::new(c.id.to_string()));
+      conds.push(format!("({k} {op} {key} OR ({k} = {key} AND id {op} {cid}))", k = key_col));
+    }
+    let page_size = query.page_size();
+    let limit = bind(&mut params, Box::new(page_size as i64 + 1));
+    let sql = format!("SELECT {} FROM flows WHERE {} ORDER BY {} {dir}, id {dir} LIMIT {}",
+                      FLOW_COLS,
+                      conds.join(" AND "),
+                      key_col,
+                      limit);
+    let refs: Vec<&(dyn ToSql + Sync)> = params.iter().map(|p| p.as_ref() as &(dyn ToSql + Sync)).collect();
+    let client = self.client().await?;
+    let rows = client.query(&sql, &refs).await.map_err(map_pg_err)?;
+    let flows = rows.iter().map(flow_meta_from_row).collect::<FlowResult<Vec<_>>>()?;
+    Ok(FlowPage::from_rows(flows, page_size))
+  }
+  async fn dump_tables_for_debug(&self) -> FlowResult<(Vec<FlowMeta>, Vec<FlowData>)> {
+    let client = self.client().await?;
+    let flows = client.query(&format!("SELECT {} FROM flows WHERE tenant_id = $1 AND deleted_at_ts IS NULL", FLOW_COLS),
+                             &[&self.tenant])
+                      .await
+                      .map_err(map_pg_err)?;
+    let data = client.query(&format!("SELECT {} FROM flow_data d WHERE tenant_id = $1 AND deleted_at_ts IS NULL AND \
+                                      EXISTS (SELECT 1 FROM flows f WHERE f.id = d.flow_id AND f.deleted_at_ts IS NULL)",
+                                     DATA_SELECT),
+                            &[&self.tenant])
+                     .await
+                     .map_err(map_pg_err)?;
+    Ok((flows.iter().map(flow_meta_from_row).collect::<FlowResult<_>>()?,
+        data.iter().map(flow_data_from_row).collect::<FlowResult<_>>()?))
+  }
+}
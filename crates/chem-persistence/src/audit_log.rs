@@ -0,0 +1,259 @@
+//! Auditoría para los backends SQL.
+//!
+//! - `DbAuditLog`: `flow::repository::AuditLog` sobre la tabla `audit_log`
+//!   (migración `00000000000010_create_audit_log`), en la misma base que los
+//!   flows.
+//! - `AuditedDomainRepository`: decorador de cualquier `DomainRepository` que
+//!   anota cada llamada que modifica el dominio, como hace
+//!   `flow::audit::AuditedFlowRepository` con los flows.
+use crate::flow_persistence::{DbConn, DbPool};
+use crate::schema::audit_log::dsl as audit_dsl;
+use chem_domain::{DomainError, DomainRepository, Molecule, MoleculeFamily, OwnedFamilyProperty, OwnedMolecularProperty};
+use chrono::{TimeZone, Utc};
+use diesel::prelude::*;
+use flow::audit::{audit_entry, AuditQuery};
+use flow::domain::AuditEntry;
+use flow::errors::{FlowError, Result as FlowResult};
+use flow::repository::AuditLog;
+use serde_json::{json, Value as JsonValue};
+use std::sync::Arc;
+use uuid::Uuid;
+#[derive(Debug, Queryable, Insertable)]
+#[diesel(table_name = crate::schema::audit_log)]
+struct AuditRow {
+  id: String,
+  at_us: i64,
+  actor: Option<String>,
+  operation: String,
+  flow_id: Option<String>,
+  target: Option<String>,
+  before_value: Option<String>,
+  after_value: Option<String>,
+  tenant_id: String,
+}
+fn audit_entry_from_row(row: AuditRow) -> FlowResult<AuditEntry> {
+  let json_opt = |s: Option<String>| s.and_then(|s| serde_json::from_str::<JsonValue>(&s).ok());
+  Ok(AuditEntry { id: Uuid::parse_str(&row.id).map_err(|e| FlowError::Storage(format!("audit_log id: {}", e)))?,
+                  at: Utc.timestamp_micros(row.at_us).single().unwrap_or(Utc::now()),
+                  actor: row.actor,
+                  operation: row.operation,
+                  flow_id: row.flow_id.and_then(|s| Uuid::parse_str(&s).ok()),
+                  target: row.target,
+                  before: json_opt(row.before_value),
+                  after: json_opt(row.after_value) })
+}
+/// `AuditLog` en la tabla `audit_log`; anota y consulta solo las entradas
+/// de un tenant.
+#[derive(Clone)]
+pub struct DbAuditLog {
+  pool: Arc<DbPool>,
+  tenant: String,
+}
+impl DbAuditLog {
+  pub(crate) fn from_pool(pool: Arc<DbPool>, tenant: String) -> Self {
+    Self { pool, tenant }
+  }
+  fn conn(&self) -> FlowResult<r2d2::PooledConnection<diesel::r2d2::ConnectionManager<DbConn>>> {
+    self.pool.get().map_err(|e| FlowError::Storage(format!("pool: {}", e)))
+  }
+}
+impl AuditLog for DbAuditLog {
+  fn append(&self, entry: &AuditEntry) -> FlowResult<()> {
+    let row = AuditRow { id: entry.id.to_string(),
+                         at_us: entry.at.timestamp_micros(),
+                         actor: entry.actor.clone(),
+                         operation: entry.operation.clone(),
+                         flow_id: entry.flow_id.map(|f| f.to_string()),
+                         target: entry.target.clone(),
+                         before_value: entry.before.as_ref().map(|v| v.to_string()),
+                         after_value: entry.after.as_ref().map(|v| v.to_string()),
+                         tenant_id: self.tenant.clone() };
+    let mut conn = self.conn()?;
+    diesel::insert_into(audit_dsl::audit_log).values(&row)
+                                             .execute(&mut conn)
+                                             .map_err(|e| FlowError::Storage(format!("db: {}", e)))?;
+    Ok(())
+  }
+  fn query(&self, query: &AuditQuery) -> FlowResult<Vec<AuditEntry>> {
+    let mut q = audit_dsl::audit_log.filter(audit_dsl::tenant_id.eq(self.tenant.clone())).into_boxed();
+    if let Some(actor) = &query.actor {
+      q = q.filter(audit_dsl::actor.eq(actor.clone()));
+    }
+    if let Some(flow_id) = query.flow_id {
+      q = q.filter(audit_dsl::flow_id.eq(flow_id.to_string()));
+    }
+    if let Some(since) = query.since {
+      q = q.filter(audit_dsl::at_us.ge(since.timestamp_micros()));
+    }
+    if let Some(until) = query.until {
+      q = q.filter(audit_dsl::at_us.lt(until.timestamp_micros()));
+    }
+    if let Some(limit) = query.limit {
+      q = q.limit(limit as i64);
+    }
+    let mut conn = self.conn()?;
+    let rows = q.order((audit_dsl::at_us.asc(), audit_dsl::id.asc()))
+                .load::<AuditRow>(&mut conn)
+                .map_err(|e| FlowError::Storage(format!("db: {}", e)))?;
+    rows.into_iter().map(audit_entry_from_row).collect()
+  }
+}
+/// `DomainRepository` que audita las llamadas de `inner` en `log`, con las
+/// mismas reglas que `AuditedFlowRepository`: solo operaciones que modifican
+/// algo y terminan bien, y `before` leído justo antes de la llamada.
+///
+/// Las entradas no llevan `flow_id`; `target` identifica el objeto como
+/// `family:<id>`, `molecule:<inchikey>`, `family_property:<id>` o
+/// `molecular_property:<id>`.
+pub struct AuditedDomainRepository {
+  inner: Arc<dyn DomainRepository>,
+  log: Arc<dyn AuditLog>,
+  actor: Option<String>,
+}
+impl AuditedDomainRepository {
+  pub fn new(inner: Arc<dyn DomainRepository>, log: Arc<dyn AuditLog>) -> Self {
+    Self { inner, log, actor: None }
+  }
+  /// Actor que se anota en las entradas (usuario o servicio).
+  pub fn with_actor(mut self, actor: impl Into<String>) -> Self {
+    self.actor = Some(actor.into());
+    self
+  }
+  pub fn audit_log(&self) -> &Arc<dyn AuditLog> {
+    &self.log
+  }
+  fn record(&self,
+            operation: &str,
+            target: String,
+            before: Option<JsonValue>,
+            after: Option<JsonValue>)
+            -> Result<(), DomainError> {
+    self.log
+        .append(&audit_entry(self.actor.as_deref(), operation, None, Some(target), before, after))
+        .map_err(|e| DomainError::ExternalError(format!("audit log: {}", e)))
+  }
+}
+fn to_json<T: serde::Serialize>(value: &T) -> Option<JsonValue> {
+  serde_json::to_value(value).ok()
+}
+impl DomainRepository for AuditedDomainRepository {
+  fn rewrap(&self, repo: Arc<dyn DomainRepository>) -> Arc<dyn DomainRepository> {
+    Arc::new(AuditedDomainRepository { inner: self.inner.rewrap(repo), log: self.log.clone(), actor: self.actor.clone() })
+  }
+  fn save_family(&self, family: MoleculeFamily) -> Result<Uuid, DomainError> {
+    let before = self.inner.get_family(&family.id()).ok().flatten();
+    let after = to_json(&family);
+    let id = self.inner.save_family(family)?;
+    self.record("save_family",
+                format!("family:{}", id),
+                before.as_ref().and_then(to_json),
+                after)?;
+    Ok(id)
+  }
+  fn get_family(&self, id: &Uuid) -> Result<Option<MoleculeFamily>, DomainError> {
+    self.inner.get_family(id)
+  }
+  fn save_molecule(&self, molecule: Molecule) -> Result<String, DomainError> {
+    let before = self.inner.get_molecule(molecule.inchikey()).ok().flatten();
+    let after = to_json(&molecule);
+    let key = self.inner.save_molecule(molecule)?;
+    self.record("save_molecule",
+                format!("molecule:{}", key),
+                before.as_ref().and_then(to_json),
+                after)?;
+    Ok(key)
+  }
+  /// Una entrada `save_molecule` por cada molécula guardada, como si se
+  /// hubieran guardado de una en una, pero sin `before`: leer cada molécula
+  /// antes anularía el guardado en bloque.
+  ///
+  /// Las entradas se anotan después de la transacción del lote (el log no
+  /// comparte conexión con `inner`), así que son best-effort: si falla una,
+  /// el lote ya está guardado, faltan las entradas siguientes y se devuelve
+  /// el error.
+  fn save_molecules_bulk(&self, molecules: Vec<Molecule>) -> Result<Vec<Result<String, DomainError>>, DomainError> {
+    let afters: Vec<Option<JsonValue>> = molecules.iter().map(to_json).collect();
+    let results = self.inner.save_molecules_bulk(molecules)?;
+    for (res, after) in results.iter().zip(afters) {
+      if let Ok(key) = res {
+        self.record("save_molecule", format!("molecule:{}", key), None, after)?;
+      }
+    }
+    Ok(results)
+  }
+  fn get_molecule(&self, inchikey: &str) -> Result<Option<Molecule>, DomainError> {
+    self.inner.get_molecule(inchikey)
+  }
+  fn list_families(&self) -> Result<Vec<MoleculeFamily>, DomainError> {
+    self.inner.list_families()
+  }
+  fn save_family_property(&self, prop: OwnedFamilyProperty) -> Result<Uuid, DomainError> {
+    let after = to_json(&prop);
+    let id = self.inner.save_family_property(prop)?;
+    self.record("save_family_property", format!("family_property:{}", id), None, after)?;
+    Ok(id)
+  }
+  fn get_family_properties(&self, family_id: &Uuid) -> Result<Vec<OwnedFamilyProperty>, DomainError> {
+    self.inner.get_family_properties(family_id)
+  }
+  fn save_molecular_property(&self, prop: OwnedMolecularProperty) -> Result<Uuid, DomainError> {
+    let after = to_json(&prop);
+    let id = self.inner.save_molecular_property(prop)?;
+    self.record("save_molecular_property", format!("molecular_property:{}", id), None, after)?;
+    Ok(id)
+  }
+  /// Una entrada `save_molecular_property` por cada propiedad guardada,
+  /// best-effort como en `save_molecules_bulk`.
+  fn save_molecular_properties_bulk(&self,
+                                    props: Vec<OwnedMolecularProperty>)
+                                    -> Result<Vec<Result<Uuid, DomainError>>, DomainError> {
+    let afters: Vec<Option<JsonValue>> = props.iter().map(to_json).collect();
+    let results = self.inner.save_molecular_properties_bulk(props)?;
+    for (res, after) in results.iter().zip(afters) {
+      if let Ok(id) = res {
+        self.record("save_molecular_property", format!("molecular_property:{}", id), None, after)?;
+      }
+    }
+    Ok(results)
+  }
+  fn get_molecular_properties(&self, inchikey: &str) -> Result<Vec<OwnedMolecularProperty>, DomainError> {
+    self.inner.get_molecular_properties(inchikey)
+  }
+  fn list_molecules(&self) -> Result<Vec<Molecule>, DomainError> {
+    self.inner.list_molecules()
+  }
+  fn delete_molecule(&self, inchikey: &str) -> Result<(), DomainError> {
+    let before = self.inner.get_molecule(inchikey).ok().flatten();
+    self.inner.delete_molecule(inchikey)?;
+    self.record("delete_molecule",
+                format!("molecule:{}", inchikey),
+                before.as_ref().and_then(to_json),
+                None)
+  }
+  fn delete_family(&self, id: &Uuid) -> Result<(), DomainError> {
+    let before = self.inner.get_family(id).ok().flatten();
+    self.inner.delete_family(id)?;
+    self.record("delete_family",
+                format!("family:{}", id),
+                before.as_ref().and_then(to_json),
+                None)
+  }
+  /// `after` lleva el id de la nueva versión de la familia.
+  fn add_molecule_to_family(&self, family_id: &Uuid, molecule: Molecule) -> Result<Uuid, DomainError> {
+    let inchikey = molecule.inchikey().to_string();
+    let new_id = self.inner.add_molecule_to_family(family_id, molecule)?;
+    self.record("add_molecule_to_family",
+                format!("family:{}", family_id),
+                None,
+                Some(json!({ "inchikey": inchikey, "family_id": new_id })))?;
+    Ok(new_id)
+  }
+  fn remove_molecule_from_family(&self, family_id: &Uuid, inchikey: &str) -> Result<Uuid, DomainError> {
+    let new_id = self.inner.remove_molecule_from_family(family_id, inchikey)?;
+    self.record("remove_molecule_from_family",
+                format!("family:{}", family_id),
+                Some(json!({ "inchikey": inchikey })),
+                Some(json!({ "family_id": new_id })))?;
+    Ok(new_id)
+  }
+}
@@ -1,22 +1,36 @@
 // repository.rs
+use crate::audit_log::DbAuditLog;
+#[cfg(all(feature = "pg", not(test)))]
+use crate::flow_persistence::PG_MIGRATIONS;
+use crate::flow_persistence::{tenant_from_env, DEFAULT_TENANT};
+use crate::json_query::{diesel_filter, JsonText};
 use crate::schema;
 use crate::schema::families::dsl as families_dsl;
 use crate::schema::family_members::dsl as fm_dsl;
 use crate::schema::family_properties::dsl as fp_dsl;
 use crate::schema::molecular_properties::dsl as molecular_properties_dsl;
 use crate::schema::molecules::dsl as molecules_dsl;
-use chem_domain::{DomainError, DomainRepository, Molecule, MoleculeFamily};
+use crate::unit_of_work::{DbConnGuard, SharedConn};
+use chem_domain::{DomainError, DomainRepository, Molecule, MoleculeFamily, OwnedFamilyProperty, OwnedMolecularProperty};
 use diesel::prelude::*;
 use diesel::r2d2::{ConnectionManager, Pool, PooledConnection};
 use diesel::result::Error as DieselError;
 use diesel_migrations::{embed_migrations, EmbeddedMigrations, MigrationHarness};
+use flow::json_predicate::JsonPredicate;
 // ...existing code...
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::sync::Arc;
 use uuid::Uuid;
 
 pub const MIGRATIONS: EmbeddedMigrations = embed_migrations!("./migrations");
 
+/// Columnas JSON de las tablas de propiedades que admiten `JsonPredicate`.
+pub const PROPERTY_JSON_COLUMNS: &[&str] = &["value", "metadata"];
+
+/// Filas por sentencia en las inserciones en bloque; con 9 columnas queda por
+/// debajo del límite de 999 parámetros de las versiones antiguas de SQLite.
+const BULK_CHUNK_ROWS: usize = 100;
+
 #[cfg(all(feature = "pg", not(test)))]
 type DbPool = Pool<ConnectionManager<PgConnection>>;
 #[cfg(any(test, not(feature = "pg")))]
@@ -26,14 +40,24 @@ type DbConn = PgConnection;
 #[cfg(any(test, not(feature = "pg")))]
 type DbConn = SqliteConnection;
 
-/// Repo Diesel que implementa `DomainRepository`.
+/// Repo Diesel que implementa `DomainRepository`, limitado a las filas de un
+/// tenant (columna `tenant_id`).
 pub struct DieselDomainRepository {
   pool: Arc<DbPool>,
+  tenant: String,
+  /// Conexión de una `DieselUnitOfWork` (ver `DieselFlowRepository`).
+  shared: Option<Arc<SharedConn>>,
 }
 
 // Convenience constructors exposed by the crate root (lib.rs)
-#[cfg(all(feature = "pg", not(test)))]
+/// Repositorio de `CHEM_DB_URL`/`DATABASE_URL` para el tenant de `TENANT_ID`.
 pub fn new_from_env() -> Result<DieselDomainRepository, DomainError> {
+  dotenvy::dotenv().ok();
+  new_from_env_for_tenant(&tenant_from_env())
+}
+
+#[cfg(all(feature = "pg", not(test)))]
+pub fn new_from_env_for_tenant(tenant: &str) -> Result<DieselDomainRepository, DomainError> {
   dotenvy::dotenv().ok();
   let url =
     std::env::var("CHEM_DB_URL").or_else(|_| std::env::var("DATABASE_URL")).map_err(|_| {
@@ -45,19 +69,19 @@ pub fn new_from_env() -> Result<DieselDomainRepository, DomainError> {
   if !(url.starts_with("postgres") || url.starts_with("postgresql://") || url.contains("@")) {
     return Err(DomainError::ExternalError("chem-persistence: CHEM_DB_URL does not look like Postgres URL".to_string()));
   }
-  Ok(DieselDomainRepository::new(&url))
+  Ok(DieselDomainRepository::new_for_tenant(&url, tenant))
 }
 
 #[cfg(test)]
-pub fn new_from_env() -> Result<DieselDomainRepository, DomainError> {
+pub fn new_from_env_for_tenant(tenant: &str) -> Result<DieselDomainRepository, DomainError> {
   dotenvy::dotenv().ok();
   let url = std::env::var("CHEM_DB_URL").or_else(|_| std::env::var("DATABASE_URL"))
                                         .unwrap_or_else(|_| "file:memdb1?mode=memory&cache=shared".into());
-  Ok(DieselDomainRepository::new(&url))
+  Ok(DieselDomainRepository::new_for_tenant(&url, tenant))
 }
 
 #[cfg(all(not(feature = "pg"), not(test)))]
-pub fn new_from_env() -> Result<DieselDomainRepository, DomainError> {
+pub fn new_from_env_for_tenant(tenant: &str) -> Result<DieselDomainRepository, DomainError> {
   dotenvy::dotenv().ok();
   let url =
     std::env::var("CHEM_DB_URL").or_else(|_| std::env::var("DATABASE_URL")).map_err(|_| {
@@ -68,7 +92,7 @@ pub fn new_from_env() -> Result<DieselDomainRepository, DomainError> {
                                                                             })?;
   let url_l = url.to_lowercase();
   if url_l.starts_with("file:") || url_l.contains("mode=memory") || url_l.contains("sqlite") {
-    return Ok(DieselDomainRepository::new(&url));
+    return Ok(DieselDomainRepository::new_for_tenant(&url, tenant));
   }
   Err(DomainError::ExternalError("chem-persistence was compiled without 'pg' feature; enable 'pg' to use Postgres".to_string()))
 }
@@ -88,12 +112,17 @@ pub fn new_sqlite_for_test() -> Result<DieselDomainRepository, DomainError> {
 
 impl DieselDomainRepository {
   pub fn new(database_url: &str) -> Self {
+    Self::new_for_tenant(database_url, DEFAULT_TENANT)
+  }
+
+  /// Como `new`, con todas las lecturas y escrituras limitadas a `tenant`.
+  pub fn new_for_tenant(database_url: &str, tenant: &str) -> Self {
     #[cfg(any(test, not(feature = "pg")))]
     let manager = ConnectionManager::<SqliteConnection>::new(database_url);
     #[cfg(all(feature = "pg", not(test)))]
     let manager = ConnectionManager::<PgConnection>::new(database_url);
     let pool = Pool::builder().max_size(4).build(manager).expect("no se pudo crear el pool de conexiones");
-    let repo = DieselDomainRepository { pool: Arc::new(pool) };
+    let repo = DieselDomainRepository { pool: Arc::new(pool), tenant: tenant.to_string(), shared: None };
     if let Ok(mut c) = repo.conn_raw() {
       #[cfg(any(test, not(feature = "pg")))]
       {
@@ -101,6 +130,8 @@ impl DieselDomainRepository {
         let _ = diesel::sql_query("PRAGMA busy_timeout = 5000;").execute(&mut c);
       }
       let _ = c.run_pending_migrations(MIGRATIONS);
+      #[cfg(all(feature = "pg", not(test)))]
+      let _ = c.run_pending_migrations(PG_MIGRATIONS);
     }
     repo
   }
@@ -109,20 +140,73 @@ impl DieselDomainRepository {
     self.pool.get()
   }
 
-  fn conn(&self) -> Result<PooledConnection<ConnectionManager<DbConn>>, DomainError> {
-    self.conn_raw().map_err(|e| DomainError::ExternalError(format!("pool: {}", e)))
+  fn conn(&self) -> Result<DbConnGuard<'_>, DomainError> {
+    match &self.shared {
+      Some(shared) => shared.lock().map_err(DomainError::ExternalError),
+      None => self.conn_raw().map(DbConnGuard::Pooled).map_err(|e| DomainError::ExternalError(format!("pool: {}", e))),
+    }
+  }
+
+  /// Copia del repositorio que trabaja sobre la conexión de `shared`.
+  pub(crate) fn in_unit_of_work(&self, shared: Arc<SharedConn>) -> Self {
+    DieselDomainRepository { pool: Arc::clone(&self.pool), tenant: self.tenant.clone(), shared: Some(shared) }
+  }
+
+  /// Tenant al que está limitado este repositorio.
+  pub fn tenant(&self) -> &str {
+    &self.tenant
+  }
+
+  /// Registro de auditoría en la tabla `audit_log` sobre el pool y el tenant
+  /// de este repositorio, para `AuditedDomainRepository`.
+  pub fn db_audit_log(&self) -> DbAuditLog {
+    DbAuditLog::from_pool(Arc::clone(&self.pool), self.tenant.clone())
+  }
+
+  fn molecule_row(&self, m: &Molecule) -> MoleculeRow {
+    MoleculeRow { inchikey: m.inchikey().to_string(),
+                  smiles: m.smiles().to_string(),
+                  inchi: m.inchi().to_string(),
+                  metadata: m.metadata().to_string(),
+                  structure: m.metadata().get("structure").and_then(|v| serde_json::to_string(v).ok()),
+                  tenant_id: self.tenant.clone() }
+  }
+
+  fn molecular_property_row(&self, prop: OwnedMolecularProperty) -> MolecularPropertyRow {
+    MolecularPropertyRow { id: prop.id.to_string(),
+                           molecule_inchikey: prop.molecule_inchikey,
+                           property_type: prop.property_type,
+                           value: JsonText(prop.value.to_string()),
+                           quality: prop.quality,
+                           preferred: prop.preferred,
+                           value_hash: prop.value_hash,
+                           metadata: JsonText(prop.metadata.to_string()),
+                           tenant_id: self.tenant.clone() }
+  }
+
+  fn family_row(&self, family: &MoleculeFamily) -> FamilyRow {
+    FamilyRow { id: family.id().to_string(),
+                name: family.name().map(|s| s.to_string()),
+                description: family.description().map(|s| s.to_string()),
+                family_hash: family.family_hash().to_string(),
+                provenance: family.provenance().to_string(),
+                frozen: family.is_frozen(),
+                tenant_id: self.tenant.clone() }
   }
 }
 
 // Diesel row structs for the chemical tables
+// `treat_none_as_default_value = false`: los `None` se insertan como NULL, lo
+// que permite las inserciones de varias filas en SQLite (no admite DEFAULT).
 #[derive(Debug, Queryable, Insertable, AsChangeset)]
-#[diesel(table_name = schema::molecules)]
+#[diesel(table_name = schema::molecules, treat_none_as_default_value = false)]
 struct MoleculeRow {
   pub inchikey: String,
   pub smiles: String,
   pub inchi: String,
   pub metadata: String,
   pub structure: Option<String>,
+  pub tenant_id: String,
 }
 
 #[derive(Debug, Queryable, Insertable, AsChangeset)]
@@ -134,6 +218,7 @@ struct FamilyRow {
   pub family_hash: String,
   pub provenance: String,
   pub frozen: bool,
+  pub tenant_id: String,
 }
 
 #[derive(Debug, Queryable, Insertable, AsChangeset)]
@@ -142,24 +227,26 @@ struct FamilyPropertyRow {
   pub id: String,
   pub family_id: String,
   pub property_type: String,
-  pub value: String,
+  pub value: JsonText,
   pub quality: Option<String>,
   pub preferred: bool,
   pub value_hash: String,
-  pub metadata: String,
+  pub metadata: JsonText,
+  pub tenant_id: String,
 }
 
 #[derive(Debug, Queryable, Insertable, AsChangeset)]
-#[diesel(table_name = schema::molecular_properties)]
+#[diesel(table_name = schema::molecular_properties, treat_none_as_default_value = false)]
 struct MolecularPropertyRow {
   pub id: String,
   pub molecule_inchikey: String,
   pub property_type: String,
-  pub value: String,
+  pub value: JsonText,
   pub quality: Option<String>,
   pub preferred: bool,
   pub value_hash: String,
-  pub metadata: String,
+  pub metadata: JsonText,
+  pub tenant_id: String,
 }
 
 #[derive(Debug, Queryable, Insertable)]
@@ -168,31 +255,67 @@ struct FamilyMemberRow {
   pub id: String,
   pub family_id: String,
   pub molecule_inchikey: String,
+  pub tenant_id: String,
 }
 
 fn map_db_err<T>(res: std::result::Result<T, DieselError>) -> Result<T, DomainError> {
   res.map_err(|e| DomainError::ExternalError(format!("db: {}", e)))
 }
 
+fn parse_uuid(s: &str) -> Result<Uuid, DomainError> {
+  Uuid::parse_str(s).map_err(|e| DomainError::ExternalError(format!("invalid uuid: {}", e)))
+}
+
+fn family_property_from_row(r: FamilyPropertyRow) -> Result<OwnedFamilyProperty, DomainError> {
+  Ok(OwnedFamilyProperty { id: parse_uuid(&r.id)?,
+                           family_id: parse_uuid(&r.family_id)?,
+                           property_type: r.property_type,
+                           value: serde_json::from_str(&r.value).unwrap_or(serde_json::json!({})),
+                           quality: r.quality,
+                           preferred: r.preferred,
+                           value_hash: r.value_hash,
+                           metadata: serde_json::from_str(&r.metadata).unwrap_or(serde_json::json!({})) })
+}
+
+fn molecular_property_from_row(r: MolecularPropertyRow) -> Result<OwnedMolecularProperty, DomainError> {
+  Ok(OwnedMolecularProperty { id: parse_uuid(&r.id)?,
+                              molecule_inchikey: r.molecule_inchikey,
+                              property_type: r.property_type,
+                              value: serde_json::from_str(&r.value).unwrap_or(serde_json::json!({})),
+                              quality: r.quality,
+                              preferred: r.preferred,
+                              value_hash: r.value_hash,
+                              metadata: serde_json::from_str(&r.metadata).unwrap_or(serde_json::json!({})) })
+}
+
+fn check_property_predicates(predicates: &[JsonPredicate]) -> Result<(), DomainError> {
+  predicates.iter()
+            .try_for_each(|p| p.check_column(PROPERTY_JSON_COLUMNS))
+            .map_err(|e| DomainError::ValidationError(e.to_string()))
+}
+
 impl DieselDomainRepository {
   // Helper to load a single family by ID
   fn load_family(&self, conn: &mut DbConn, id: &Uuid) -> Result<Option<MoleculeFamily>, DomainError> {
     let id_s = id.to_string();
-    let opt = families_dsl::families.filter(families_dsl::id.eq(&id_s))
+    let opt = families_dsl::families.filter(families_dsl::tenant_id.eq(&self.tenant))
+                                    .filter(families_dsl::id.eq(&id_s))
                                     .first::<FamilyRow>(conn)
                                     .optional()
                                     .map_err(|e| DomainError::ExternalError(format!("db: {}", e)))?;
 
     if let Some(r) = opt {
       // Load all member inchikeys for this family
-      let inchikeys: Vec<String> = fm_dsl::family_members.filter(fm_dsl::family_id.eq(&id_s))
+      let inchikeys: Vec<String> = fm_dsl::family_members.filter(fm_dsl::tenant_id.eq(&self.tenant))
+                                                         .filter(fm_dsl::family_id.eq(&id_s))
                                                          .select(fm_dsl::molecule_inchikey)
                                                          .load(conn)
                                                          .map_err(|e| DomainError::ExternalError(format!("db: {}", e)))?;
 
       // Load all molecules in one query using IN
       let molecule_rows: Vec<MoleculeRow> =
-        molecules_dsl::molecules.filter(molecules_dsl::inchikey.eq_any(&inchikeys))
+        molecules_dsl::molecules.filter(molecules_dsl::tenant_id.eq(&self.tenant))
+                                .filter(molecules_dsl::inchikey.eq_any(&inchikeys))
                                 .load(conn)
                                 .map_err(|e| DomainError::ExternalError(format!("db: {}", e)))?;
 
@@ -228,18 +351,13 @@ impl DieselDomainRepository {
   // Helper to persist a family (upsert logic)
   fn persist_family(&self, conn: &mut DbConn, family: &MoleculeFamily) -> Result<Uuid, DomainError> {
     let id_s = family.id().to_string();
-    let family_row = FamilyRow { id: id_s.clone(),
-                                 name: family.name().map(|s| s.to_string()),
-                                 description: family.description().map(|s| s.to_string()),
-                                 family_hash: family.family_hash().to_string(),
-                                 provenance: family.provenance().to_string(),
-                                 frozen: family.is_frozen() };
+    let family_row = self.family_row(family);
 
     // Use Diesel's upsert: insert or update on conflict
     #[cfg(feature = "pg")]
     {
       map_db_err(diesel::insert_into(schema::families::table).values(&family_row)
-                                                             .on_conflict(schema::families::id)
+                                                             .on_conflict((schema::families::tenant_id, schema::families::id))
                                                              .do_update()
                                                              .set(&family_row)
                                                              .execute(conn))?;
@@ -251,19 +369,16 @@ impl DieselDomainRepository {
     }
 
     // Delete existing members
-    map_db_err(diesel::delete(fm_dsl::family_members.filter(fm_dsl::family_id.eq(&id_s))).execute(conn))?;
+    map_db_err(diesel::delete(fm_dsl::family_members.filter(fm_dsl::tenant_id.eq(&self.tenant))
+                                          .filter(fm_dsl::family_id.eq(&id_s))).execute(conn))?;
 
     // Insert molecules with on_conflict_do_nothing
     for m in family.molecules() {
-      let mr = MoleculeRow { inchikey: m.inchikey().to_string(),
-                             smiles: m.smiles().to_string(),
-                             inchi: m.inchi().to_string(),
-                             metadata: m.metadata().to_string(),
-                             structure: m.metadata().get("structure").and_then(|v| serde_json::to_string(v).ok()) };
+      let mr = self.molecule_row(m);
       #[cfg(feature = "pg")]
       {
         let _ = diesel::insert_into(schema::molecules::table).values(&mr)
-                                                             .on_conflict(schema::molecules::inchikey)
+                                                             .on_conflict((schema::molecules::tenant_id, schema::molecules::inchikey))
                                                              .do_nothing()
                                                              .execute(conn);
       }
@@ -271,12 +386,13 @@ impl DieselDomainRepository {
       {
         // SQLite: ignore errors or use INSERT OR IGNORE
         let _ =
-          diesel::sql_query("INSERT OR IGNORE INTO molecules (inchikey, smiles, inchi, metadata, structure) VALUES (?, ?, \
-                             ?, ?, ?)").bind::<diesel::sql_types::Text, _>(mr.inchikey)
+          diesel::sql_query("INSERT OR IGNORE INTO molecules (inchikey, smiles, inchi, metadata, structure, tenant_id) VALUES \
+                             (?, ?, ?, ?, ?, ?)").bind::<diesel::sql_types::Text, _>(mr.inchikey)
                                        .bind::<diesel::sql_types::Text, _>(mr.smiles)
                                        .bind::<diesel::sql_types::Text, _>(mr.inchi)
                                        .bind::<diesel::sql_types::Text, _>(mr.metadata)
                                        .bind::<diesel::sql_types::Nullable<diesel::sql_types::Text>, _>(mr.structure)
+                                       .bind::<diesel::sql_types::Text, _>(mr.tenant_id)
                                        .execute(conn);
       }
     }
@@ -285,12 +401,45 @@ impl DieselDomainRepository {
     for m in family.molecules() {
       let fm = FamilyMemberRow { id: Uuid::new_v4().to_string(),
                                  family_id: id_s.clone(),
-                                 molecule_inchikey: m.inchikey().to_string() };
+                                 molecule_inchikey: m.inchikey().to_string(),
+                                 tenant_id: self.tenant.clone() };
       map_db_err(diesel::insert_into(schema::family_members::table).values(&fm).execute(conn))?;
     }
 
     Uuid::parse_str(&id_s).map_err(|e| DomainError::ExternalError(format!("invalid uuid: {}", e)))
   }
+
+  /// Propiedades moleculares del tenant que cumplen todos los `predicates`
+  /// (sobre `value` o `metadata`, p. ej. `metadata.method == "xtb"`),
+  /// filtradas en la base: JSONB con índice GIN en Postgres, `json_extract`
+  /// en SQLite.
+  pub fn find_molecular_properties(&self,
+                                   predicates: &[JsonPredicate])
+                                   -> Result<Vec<OwnedMolecularProperty>, DomainError> {
+    check_property_predicates(predicates)?;
+    use molecular_properties_dsl as mp_dsl;
+    let mut q = mp_dsl::molecular_properties.filter(mp_dsl::tenant_id.eq(self.tenant.clone()))
+                                            .order(mp_dsl::id.asc())
+                                            .into_boxed();
+    for pred in predicates {
+      q = q.filter(diesel_filter(pred));
+    }
+    let mut conn = self.conn()?;
+    map_db_err(q.load::<MolecularPropertyRow>(&mut *conn))?.into_iter().map(molecular_property_from_row).collect()
+  }
+
+  /// Como `find_molecular_properties`, sobre las propiedades de familia.
+  pub fn find_family_properties(&self, predicates: &[JsonPredicate]) -> Result<Vec<OwnedFamilyProperty>, DomainError> {
+    check_property_predicates(predicates)?;
+    let mut q = fp_dsl::family_properties.filter(fp_dsl::tenant_id.eq(self.tenant.clone()))
+                                         .order(fp_dsl::id.asc())
+                                         .into_boxed();
+    for pred in predicates {
+      q = q.filter(diesel_filter(pred));
+    }
+    let mut conn = self.conn()?;
+    map_db_err(q.load::<FamilyPropertyRow>(&mut *conn))?.into_iter().map(family_property_from_row).collect()
+  }
 }
 
 impl DomainRepository for DieselDomainRepository {
@@ -314,39 +463,55 @@ impl DomainRepository for DieselDomainRepository {
 
   fn save_molecule(&self, molecule: Molecule) -> Result<String, DomainError> {
     let mut conn = self.conn()?;
-    let mr = MoleculeRow { inchikey: molecule.inchikey().to_string(),
-                           smiles: molecule.smiles().to_string(),
-                           inchi: molecule.inchi().to_string(),
-                           metadata: molecule.metadata().to_string(),
-                           structure: molecule.metadata().get("structure").and_then(|v| serde_json::to_string(v).ok()) };
+    let mr = self.molecule_row(&molecule);
 
     #[cfg(feature = "pg")]
     {
       map_db_err(diesel::insert_into(schema::molecules::table).values(&mr)
-                                                              .on_conflict(schema::molecules::inchikey)
+                                                              .on_conflict((schema::molecules::tenant_id, schema::molecules::inchikey))
                                                               .do_nothing()
-                                                              .execute(&mut conn))?;
+                                                              .execute(&mut *conn))?;
     }
     #[cfg(not(feature = "pg"))]
     {
       let res =
-        diesel::sql_query("INSERT OR IGNORE INTO molecules (inchikey, smiles, inchi, metadata, structure) VALUES (?, ?, \
-                           ?, ?, ?)").bind::<diesel::sql_types::Text, _>(mr.inchikey.clone())
+        diesel::sql_query("INSERT OR IGNORE INTO molecules (inchikey, smiles, inchi, metadata, structure, tenant_id) VALUES \
+                           (?, ?, ?, ?, ?, ?)").bind::<diesel::sql_types::Text, _>(mr.inchikey.clone())
                                      .bind::<diesel::sql_types::Text, _>(mr.smiles)
                                      .bind::<diesel::sql_types::Text, _>(mr.inchi)
                                      .bind::<diesel::sql_types::Text, _>(mr.metadata)
                                      .bind::<diesel::sql_types::Nullable<diesel::sql_types::Text>, _>(mr.structure.clone())
-                                     .execute(&mut conn);
+                                     .bind::<diesel::sql_types::Text, _>(mr.tenant_id)
+                                     .execute(&mut *conn);
       map_db_err(res)?;
     }
 
     Ok(molecule.inchikey().to_string())
   }
 
+  /// Inserciones de varias filas (`ON CONFLICT DO NOTHING`, como
+  /// `save_molecule`) dentro de una transacción.
+  fn save_molecules_bulk(&self, molecules: Vec<Molecule>) -> Result<Vec<Result<String, DomainError>>, DomainError> {
+    let rows: Vec<MoleculeRow> = molecules.iter().map(|m| self.molecule_row(m)).collect();
+    let mut conn = self.conn()?;
+    map_db_err(conn.transaction::<_, DieselError, _>(|conn| {
+                     for chunk in rows.chunks(BULK_CHUNK_ROWS) {
+                       diesel::insert_into(schema::molecules::table).values(chunk)
+                                                                    .on_conflict((schema::molecules::tenant_id,
+                                                                                  schema::molecules::inchikey))
+                                                                    .do_nothing()
+                                                                    .execute(conn)?;
+                     }
+                     Ok(())
+                   }))?;
+    Ok(molecules.iter().map(|m| Ok(m.inchikey().to_string())).collect())
+  }
+
   fn get_molecule(&self, inchikey: &str) -> Result<Option<Molecule>, DomainError> {
     let mut conn = self.conn()?;
-    let opt = molecules_dsl::molecules.filter(molecules_dsl::inchikey.eq(inchikey))
-                                      .first::<MoleculeRow>(&mut conn)
+    let opt = molecules_dsl::molecules.filter(molecules_dsl::tenant_id.eq(&self.tenant))
+                                      .filter(molecules_dsl::inchikey.eq(inchikey))
+                                      .first::<MoleculeRow>(&mut *conn)
                                       .optional()
                                       .map_err(|e| DomainError::ExternalError(format!("db: {}", e)))?;
     if let Some(r) = opt {
@@ -365,7 +530,8 @@ impl DomainRepository for DieselDomainRepository {
 
   fn list_molecules(&self) -> Result<Vec<Molecule>, DomainError> {
     let mut conn = self.conn()?;
-    let rows = molecules_dsl::molecules.load::<MoleculeRow>(&mut conn)
+    let rows = molecules_dsl::molecules.filter(molecules_dsl::tenant_id.eq(&self.tenant))
+                                       .load::<MoleculeRow>(&mut *conn)
                                        .map_err(|e| DomainError::ExternalError(format!("db: {}", e)))?;
     let mut out = Vec::with_capacity(rows.len());
     for r in rows {
@@ -385,10 +551,12 @@ impl DomainRepository for DieselDomainRepository {
     let mut conn = self.conn()?;
     // Load all families
     let family_rows =
-      families_dsl::families.load::<FamilyRow>(&mut conn).map_err(|e| DomainError::ExternalError(format!("db: {}", e)))?;
+      families_dsl::families.filter(families_dsl::tenant_id.eq(&self.tenant))
+                            .load::<FamilyRow>(&mut *conn).map_err(|e| DomainError::ExternalError(format!("db: {}", e)))?;
 
     // Load all family members
-    let member_rows = fm_dsl::family_members.load::<FamilyMemberRow>(&mut conn)
+    let member_rows = fm_dsl::family_members.filter(fm_dsl::tenant_id.eq(&self.tenant))
+                                            .load::<FamilyMemberRow>(&mut *conn)
                                             .map_err(|e| DomainError::ExternalError(format!("db: {}", e)))?;
 
     // Group members by family_id
@@ -406,8 +574,9 @@ impl DomainRepository for DieselDomainRepository {
 
     // Load all relevant molecules in one query
     let molecule_rows: Vec<MoleculeRow> = if !all_inchikeys.is_empty() {
-      molecules_dsl::molecules.filter(molecules_dsl::inchikey.eq_any(&all_inchikeys))
-                              .load(&mut conn)
+      molecules_dsl::molecules.filter(molecules_dsl::tenant_id.eq(&self.tenant))
+                              .filter(molecules_dsl::inchikey.eq_any(&all_inchikeys))
+                              .load(&mut *conn)
                               .map_err(|e| DomainError::ExternalError(format!("db: {}", e)))?
     } else {
       Vec::new()
@@ -457,83 +626,85 @@ impl DomainRepository for DieselDomainRepository {
     let row = FamilyPropertyRow { id: prop.id.to_string(),
                                   family_id: prop.family_id.to_string(),
                                   property_type: prop.property_type,
-                                  value: prop.value.to_string(),
+                                  value: JsonText(prop.value.to_string()),
                                   quality: prop.quality,
                                   preferred: prop.preferred,
                                   value_hash: prop.value_hash,
-                                  metadata: prop.metadata.to_string() };
-    map_db_err(diesel::insert_into(schema::family_properties::table).values(&row).execute(&mut conn))?;
+                                  metadata: JsonText(prop.metadata.to_string()),
+                                  tenant_id: self.tenant.clone() };
+    map_db_err(diesel::insert_into(schema::family_properties::table).values(&row).execute(&mut *conn))?;
     Uuid::parse_str(&row.id).map_err(|e| DomainError::ExternalError(format!("invalid uuid: {}", e)))
   }
 
   fn get_family_properties(&self, family_id: &Uuid) -> Result<Vec<chem_domain::OwnedFamilyProperty>, DomainError> {
     let mut conn = self.conn()?;
     let f_id = family_id.to_string();
-    let rows = fp_dsl::family_properties.filter(fp_dsl::family_id.eq(&f_id))
-                                        .load::<FamilyPropertyRow>(&mut conn)
+    let rows = fp_dsl::family_properties.filter(fp_dsl::tenant_id.eq(&self.tenant))
+                                        .filter(fp_dsl::family_id.eq(&f_id))
+                                        .load::<FamilyPropertyRow>(&mut *conn)
                                         .map_err(|e| DomainError::ExternalError(format!("db: {}", e)))?;
-    let mut out = Vec::with_capacity(rows.len());
-    for r in rows {
-      out.push(chem_domain::OwnedFamilyProperty { id: Uuid::parse_str(&r.id).map_err(|e| {
-                                                        DomainError::ExternalError(format!("invalid uuid: {}", e))
-                                                      })?,
-                                                  family_id: Uuid::parse_str(&r.family_id).map_err(|e| {
-                                                               DomainError::ExternalError(format!("invalid uuid: {}", e))
-                                                             })?,
-                                                  property_type: r.property_type,
-                                                  value: serde_json::from_str(&r.value).unwrap_or(serde_json::json!({})),
-                                                  quality: r.quality,
-                                                  preferred: r.preferred,
-                                                  value_hash: r.value_hash,
-                                                  metadata:
-                                                    serde_json::from_str(&r.metadata).unwrap_or(serde_json::json!({})) });
-    }
-    Ok(out)
+    rows.into_iter().map(family_property_from_row).collect()
   }
 
   fn save_molecular_property(&self, prop: chem_domain::OwnedMolecularProperty) -> Result<Uuid, DomainError> {
     let mut conn = self.conn()?;
-    let row = MolecularPropertyRow { id: prop.id.to_string(),
-                                     molecule_inchikey: prop.molecule_inchikey,
-                                     property_type: prop.property_type,
-                                     value: prop.value.to_string(),
-                                     quality: prop.quality,
-                                     preferred: prop.preferred,
-                                     value_hash: prop.value_hash,
-                                     metadata: prop.metadata.to_string() };
-    map_db_err(diesel::insert_into(schema::molecular_properties::table).values(&row).execute(&mut conn))?;
+    let row = self.molecular_property_row(prop);
+    map_db_err(diesel::insert_into(schema::molecular_properties::table).values(&row).execute(&mut *conn))?;
     Uuid::parse_str(&row.id).map_err(|e| DomainError::ExternalError(format!("invalid uuid: {}", e)))
   }
 
+  /// En una transacción: consulta qué ids ya existen y guarda el resto con
+  /// inserciones de varias filas.
+  fn save_molecular_properties_bulk(&self,
+                                    props: Vec<OwnedMolecularProperty>)
+                                    -> Result<Vec<Result<Uuid, DomainError>>, DomainError> {
+    use molecular_properties_dsl as mp_dsl;
+    let ids: Vec<String> = props.iter().map(|p| p.id.to_string()).collect();
+    let mut conn = self.conn()?;
+    map_db_err(conn.transaction::<_, DieselError, _>(|conn| {
+                     // `id` es clave primaria de toda la tabla, no por tenant
+                     let mut taken = HashSet::new();
+                     for chunk in ids.chunks(BULK_CHUNK_ROWS) {
+                       taken.extend(mp_dsl::molecular_properties.filter(mp_dsl::id.eq_any(chunk))
+                                                                .select(mp_dsl::id)
+                                                                .load::<String>(conn)?);
+                     }
+                     let mut rows = Vec::with_capacity(props.len());
+                     let results = props.into_iter()
+                                        .map(|p| {
+                                          let id = p.id;
+                                          if !taken.insert(id.to_string()) {
+                                            return Err(DomainError::ValidationError(format!("La propiedad molecular {} \
+                                                                                             ya existe",
+                                                                                            id)));
+                                          }
+                                          rows.push(self.molecular_property_row(p));
+                                          Ok(id)
+                                        })
+                                        .collect();
+                     for chunk in rows.chunks(BULK_CHUNK_ROWS) {
+                       diesel::insert_into(schema::molecular_properties::table).values(chunk).execute(conn)?;
+                     }
+                     Ok(results)
+                   }))
+  }
+
   fn get_molecular_properties(&self, inchikey: &str) -> Result<Vec<chem_domain::OwnedMolecularProperty>, DomainError> {
     let mut conn = self.conn()?;
     let rows =
-      molecular_properties_dsl::molecular_properties.filter(molecular_properties_dsl::molecule_inchikey.eq(inchikey))
-                                                    .load::<MolecularPropertyRow>(&mut conn)
+      molecular_properties_dsl::molecular_properties.filter(molecular_properties_dsl::tenant_id.eq(&self.tenant))
+                                                    .filter(molecular_properties_dsl::molecule_inchikey.eq(inchikey))
+                                                    .load::<MolecularPropertyRow>(&mut *conn)
                                                     .map_err(|e| DomainError::ExternalError(format!("db: {}", e)))?;
-    let mut out = Vec::with_capacity(rows.len());
-    for r in rows {
-      out.push(chem_domain::OwnedMolecularProperty { id: Uuid::parse_str(&r.id).map_err(|e| {
-                                                           DomainError::ExternalError(format!("invalid uuid: {}", e))
-                                                         })?,
-                                                     molecule_inchikey: r.molecule_inchikey,
-                                                     property_type: r.property_type,
-                                                     value:
-                                                       serde_json::from_str(&r.value).unwrap_or(serde_json::json!({})),
-                                                     quality: r.quality,
-                                                     preferred: r.preferred,
-                                                     value_hash: r.value_hash,
-                                                     metadata:
-                                                       serde_json::from_str(&r.metadata).unwrap_or(serde_json::json!({})) });
-    }
-    Ok(out)
+    rows.into_iter().map(molecular_property_from_row).collect()
   }
 
   fn delete_molecule(&self, inchikey: &str) -> Result<(), DomainError> {
     let mut conn = self.conn()?;
     let tx_result = conn.transaction::<(), diesel::result::Error, _>(|conn| {
                           // Check if referenced
-                          let exists = fm_dsl::family_members.filter(fm_dsl::molecule_inchikey.eq(inchikey))
+                          let exists = fm_dsl::family_members.filter(fm_dsl::tenant_id.eq(&self.tenant))
+                                                             .filter(fm_dsl::molecule_inchikey.eq(inchikey))
                                                              .select(fm_dsl::id)
                                                              .first::<String>(conn)
                                                              .optional()?;
@@ -541,7 +712,8 @@ impl DomainRepository for DieselDomainRepository {
                             // Return a Diesel error to abort the transaction
                             return Err(diesel::result::Error::RollbackTransaction);
                           }
-                          diesel::delete(molecules_dsl::molecules.filter(molecules_dsl::inchikey.eq(inchikey)))
+                          diesel::delete(molecules_dsl::molecules.filter(molecules_dsl::tenant_id.eq(&self.tenant))
+                                                                  .filter(molecules_dsl::inchikey.eq(inchikey)))
                 .execute(conn)?;
                           Ok(())
                         });
@@ -560,21 +732,24 @@ impl DomainRepository for DieselDomainRepository {
     let id_s = id.to_string();
     conn.transaction::<(), diesel::result::Error, _>(|conn| {
           map_db_err(
-                diesel::delete(fp_dsl::family_properties.filter(fp_dsl::family_id.eq(&id_s)))
+                diesel::delete(fp_dsl::family_properties.filter(fp_dsl::tenant_id.eq(&self.tenant))
+                                                         .filter(fp_dsl::family_id.eq(&id_s)))
                     .execute(conn)
                     .map(|_| ()),
             )
             .map_err(|_| diesel::result::Error::RollbackTransaction)?;
 
           map_db_err(
-                diesel::delete(fm_dsl::family_members.filter(fm_dsl::family_id.eq(&id_s)))
+                diesel::delete(fm_dsl::family_members.filter(fm_dsl::tenant_id.eq(&self.tenant))
+                                                      .filter(fm_dsl::family_id.eq(&id_s)))
                     .execute(conn)
                     .map(|_| ()),
             )
             .map_err(|_| diesel::result::Error::RollbackTransaction)?;
 
           map_db_err(
-                diesel::delete(families_dsl::families.filter(families_dsl::id.eq(&id_s)))
+                diesel::delete(families_dsl::families.filter(families_dsl::tenant_id.eq(&self.tenant))
+                                                      .filter(families_dsl::id.eq(&id_s)))
                     .execute(conn)
                     .map(|_| ()),
             )
@@ -593,40 +768,32 @@ impl DomainRepository for DieselDomainRepository {
           let new_fam = fam.add_molecule(molecule.clone()).map_err(|_| diesel::result::Error::RollbackTransaction)?;
 
           // Insert molecule if not exists
-          let mr = MoleculeRow { inchikey: molecule.inchikey().to_string(),
-                                 smiles: molecule.smiles().to_string(),
-                                 inchi: molecule.inchi().to_string(),
-                                 metadata: molecule.metadata().to_string(),
-                                 structure: molecule.metadata().get("structure").and_then(|v| serde_json::to_string(v).ok()) };
+          let mr = self.molecule_row(&molecule);
           #[cfg(feature = "pg")]
           {
             map_db_err(
                     diesel::insert_into(schema::molecules::table)
                         .values(&mr)
-                        .on_conflict(schema::molecules::inchikey)
+                        .on_conflict((schema::molecules::tenant_id, schema::molecules::inchikey))
                         .do_nothing()
                         .execute(conn),
                 ).map_err(|_| diesel::result::Error::RollbackTransaction)?;
           }
           #[cfg(not(feature = "pg"))]
           {
-            map_db_err(diesel::sql_query("INSERT OR IGNORE INTO molecules (inchikey, smiles, inchi, metadata, structure) VALUES (?, ?, ?, ?, ?)")
+            map_db_err(diesel::sql_query("INSERT OR IGNORE INTO molecules (inchikey, smiles, inchi, metadata, structure, tenant_id) VALUES (?, ?, ?, ?, ?, ?)")
                                                     .bind::<diesel::sql_types::Text, _>(mr.inchikey)
                                                     .bind::<diesel::sql_types::Text, _>(mr.smiles)
                                                     .bind::<diesel::sql_types::Text, _>(mr.inchi)
                                                     .bind::<diesel::sql_types::Text, _>(mr.metadata)
                                                     .bind::<diesel::sql_types::Nullable<diesel::sql_types::Text>, _>(mr.structure)
+                                                    .bind::<diesel::sql_types::Text, _>(mr.tenant_id)
                                                     .execute(conn)).map_err(|_| diesel::result::Error::RollbackTransaction)?;
           }
 
           // Persist new family (new ID, no upsert needed, just insert)
           let new_id_s = new_fam.id().to_string();
-          let family_row = FamilyRow { id: new_id_s.clone(),
-                                       name: new_fam.name().map(|s| s.to_string()),
-                                       description: new_fam.description().map(|s| s.to_string()),
-                                       family_hash: new_fam.family_hash().to_string(),
-                                       provenance: new_fam.provenance().to_string(),
-                                       frozen: new_fam.is_frozen() };
+          let family_row = self.family_row(&new_fam);
           diesel::insert_into(schema::families::table).values(&family_row)
                                                       .execute(conn)
                                                       .map_err(|_| diesel::result::Error::RollbackTransaction)?;
@@ -634,7 +801,8 @@ impl DomainRepository for DieselDomainRepository {
           for m in new_fam.molecules() {
             let fm = FamilyMemberRow { id: Uuid::new_v4().to_string(),
                                        family_id: new_id_s.clone(),
-                                       molecule_inchikey: m.inchikey().to_string() };
+                                       molecule_inchikey: m.inchikey().to_string(),
+                                       tenant_id: self.tenant.clone() };
             diesel::insert_into(schema::family_members::table).values(&fm)
                                                               .execute(conn)
                                                               .map_err(|_| diesel::result::Error::RollbackTransaction)?;
@@ -654,12 +822,7 @@ impl DomainRepository for DieselDomainRepository {
 
           // Persist new family (new ID)
           let new_id_s = new_fam.id().to_string();
-          let family_row = FamilyRow { id: new_id_s.clone(),
-                                       name: new_fam.name().map(|s| s.to_string()),
-                                       description: new_fam.description().map(|s| s.to_string()),
-                                       family_hash: new_fam.family_hash().to_string(),
-                                       provenance: new_fam.provenance().to_string(),
-                                       frozen: new_fam.is_frozen() };
+          let family_row = self.family_row(&new_fam);
           diesel::insert_into(schema::families::table).values(&family_row)
                                                       .execute(conn)
                                                       .map_err(|_| diesel::result::Error::RollbackTransaction)?;
@@ -667,7 +830,8 @@ impl DomainRepository for DieselDomainRepository {
           for m in new_fam.molecules() {
             let fm = FamilyMemberRow { id: Uuid::new_v4().to_string(),
                                        family_id: new_id_s.clone(),
-                                       molecule_inchikey: m.inchikey().to_string() };
+                                       molecule_inchikey: m.inchikey().to_string(),
+                                       tenant_id: self.tenant.clone() };
             diesel::insert_into(schema::family_members::table).values(&fm)
                                                               .execute(conn)
                                                               .map_err(|_| diesel::result::Error::RollbackTransaction)?;
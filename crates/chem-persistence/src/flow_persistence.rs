@@ -4,28 +4,102 @@ use diesel::prelude::*;
 use diesel::r2d2::{ConnectionManager, Pool, PooledConnection};
 use diesel::result::Error as DieselError;
 use diesel_migrations::{embed_migrations, EmbeddedMigrations, MigrationHarness};
-use flow::domain::{FlowData, FlowMeta, PersistResult, SnapshotMeta, WorkItem};
+use flow::artifact_store::{artifact_key, artifact_refs, is_artifact_key, verify_artifact};
+use flow::domain::{FlowData, FlowDataMeta, FlowMeta, PersistResult, SnapshotMeta, WorkItem, DEFAULT_WORK_LEASE_SECS};
 use flow::errors::{FlowError, Result as FlowResult};
-use flow::repository::{ArtifactStore, FlowRepository, SnapshotStore};
+use flow::lineage::{data_segments, DataSegment};
+use flow::query::{FlowDataQuery, FlowPage, FlowQuery, FlowSort};
+use flow::repository::{ArtifactStore, FlowLockGuard, FlowRepository, SnapshotStore};
+use flow::retention::{
+  orphan_blob_keys, purge_cutoff, snapshots_to_prune, PurgeReport, SnapshotGcReport, SnapshotRetention,
+};
+use flow::snapshot_store::{decode_inline_snapshot, load_snapshot_blob, StoredBlob};
+use flow::subscription::{ChangeFeed, FlowSubscription};
 use serde_json::Value as JsonValue;
+use std::collections::HashSet;
 use std::fs;
-use std::path::Path;
+use std::path::PathBuf;
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 use uuid::Uuid;
 // Reusar el módulo `schema` definido en `lib.rs`.
+use crate::audit_log::DbAuditLog;
+use crate::json_query::{diesel_filter, JsonText};
 use crate::schema;
 use crate::schema::flow_data::dsl as data_dsl;
 use crate::schema::flows::dsl as flows_dsl;
+use crate::schema::work_queue::dsl as work_dsl;
 use crate::schema::*;
+use crate::snapshot_store::{snapshot_retention_from_env, snapshot_store_from_env, DbSnapshotStore};
+use crate::unit_of_work::{DbConnGuard, SharedConn};
 pub const MIGRATIONS: EmbeddedMigrations = embed_migrations!("./migrations");
+/// Migraciones solo de Postgres (columnas JSONB), aplicadas después de
+/// `MIGRATIONS`; sus versiones no coinciden con las comunes.
+#[cfg(any(feature = "async-pg", all(feature = "pg", not(test))))]
+pub const PG_MIGRATIONS: EmbeddedMigrations = embed_migrations!("./migrations_pg");
 #[cfg(all(feature = "pg", not(test)))]
-type DbPool = Pool<ConnectionManager<PgConnection>>;
+pub(crate) type DbPool = Pool<ConnectionManager<PgConnection>>;
 #[cfg(any(test, not(feature = "pg")))]
-type DbPool = Pool<ConnectionManager<SqliteConnection>>;
+pub(crate) type DbPool = Pool<ConnectionManager<SqliteConnection>>;
+#[cfg(all(feature = "pg", not(test)))]
+pub(crate) type DbConn = PgConnection;
+#[cfg(any(test, not(feature = "pg")))]
+pub(crate) type DbConn = SqliteConnection;
 pub struct DieselFlowRepository {
   pool: Arc<DbPool>,
-  snapshot_dir: String,
-  artifact_dir: String,
+  /// Tenant de todas las filas que lee y escribe (columna `tenant_id`); ver
+  /// `new_for_tenant`.
+  tenant: String,
+  /// Store de los blobs de snapshot (`SNAPSHOT_STORE`, ver
+  /// `snapshot_store_from_env`); `snapshots.state_ptr` guarda su key.
+  snapshot_store: Arc<dyn SnapshotStore>,
+  /// Política de `compact_snapshots`/`gc_snapshots` (`SNAPSHOT_KEEP_*`, ver
+  /// `snapshot_retention_from_env`).
+  retention: SnapshotRetention,
+  /// Directorio de los blobs de artifacts (`ARTIFACT_DIR`), un fichero por
+  /// key; la tabla `artifacts` lleva sus contadores de referencias.
+  artifact_dir: PathBuf,
+  /// Duración en milisegundos del lease de la cola de trabajo
+  /// (`WORK_LEASE_SECS`, por defecto `DEFAULT_WORK_LEASE_SECS`).
+  lease_ms: i64,
+  /// Expiración en milisegundos de los locks de `flow_locks` (solo SQLite;
+  /// `FLOW_LOCK_TTL_SECS`, por defecto `DEFAULT_FLOW_LOCK_TTL_SECS`).
+  lock_ttl_ms: i64,
+  /// Suscriptores de `subscribe`. En SQLite se publica tras cada commit de
+  /// `persist_data` (solo en este proceso); en Postgres lo alimenta el hilo
+  /// que escucha `NOTIFY flow_data`.
+  feed: Arc<ChangeFeed>,
+  /// Si el hilo `LISTEN` ya está en marcha (solo Postgres; se arranca con la
+  /// primera suscripción). Lo comparten las copias de `in_unit_of_work`, que
+  /// publican en el mismo `feed`.
+  #[cfg(all(feature = "pg", not(test)))]
+  listener_started: Arc<std::sync::Mutex<bool>>,
+  /// Conexión con la transacción de una `DieselUnitOfWork`; si está, todas
+  /// las operaciones la usan en lugar del pool.
+  shared: Option<Arc<SharedConn>>,
+}
+/// Expiración por defecto de un lock en `flow_locks`. Evita que un proceso
+/// caído deje el flow bloqueado indefinidamente.
+pub const DEFAULT_FLOW_LOCK_TTL_SECS: i64 = 300;
+/// Tenant de los repositorios construidos sin indicar uno (y de los datos
+/// anteriores a la columna `tenant_id`).
+pub const DEFAULT_TENANT: &str = "";
+/// Tenant indicado por `TENANT_ID` (o `DEFAULT_TENANT`), para los
+/// constructores `*_from_env`.
+pub(crate) fn tenant_from_env() -> String {
+  std::env::var("TENANT_ID").unwrap_or_else(|_| DEFAULT_TENANT.to_string())
+}
+/// Intervalo entre reintentos mientras se espera un lock.
+const LOCK_POLL_INTERVAL: Duration = Duration::from_millis(50);
+#[derive(Debug, Queryable, Insertable)]
+#[diesel(table_name = work_queue)]
+struct WorkQueueRow {
+  flow_id: String,
+  worker_id: Option<String>,
+  lease_expires_at_ms: Option<i64>,
+  attempts: i64,
+  enqueued_at_ms: i64,
+  tenant_id: String,
 }
 #[derive(Debug, Queryable, Insertable)]
 #[diesel(table_name = flows)]
@@ -39,19 +113,41 @@ struct FlowRow {
   current_version: i64,
   parent_flow_id: Option<String>,
   parent_cursor: Option<i64>,
-  metadata: String,
+  metadata: JsonText,
+  /// Momento (segundos) de `delete_branch`; `None` si el flow está vivo.
+  deleted_at_ts: Option<i64>,
+  tenant_id: String,
 }
-#[derive(Debug, Queryable, Insertable)]
+fn flow_meta_from_row(row: FlowRow) -> FlowMeta {
+  FlowMeta { id: Uuid::parse_str(&row.id).unwrap(),
+             name: row.name,
+             status: row.status,
+             created_by: row.created_by,
+             created_at: Utc.timestamp_opt(row.created_at_ts, 0).single().unwrap_or(Utc::now()),
+             current_cursor: row.current_cursor,
+             current_version: row.current_version,
+             parent_flow_id: row.parent_flow_id.and_then(|s| Uuid::parse_str(&s).ok()),
+             parent_cursor: row.parent_cursor,
+             metadata: serde_json::from_str(&row.metadata).unwrap_or(serde_json::json!({})),
+             deleted_at: row.deleted_at_ts.and_then(|t| Utc.timestamp_opt(t, 0).single()) }
+}
+#[derive(Debug, Clone, Queryable, Insertable)]
 #[diesel(table_name = flow_data)]
 struct FlowDataRow {
   id: String,
   flow_id: String,
   cursor: i64,
   key: String,
-  payload: String,
-  metadata: String,
+  payload: JsonText,
+  metadata: JsonText,
   command_id: Option<String>,
   created_at_ts: i64,
+  /// Versión del flow tras persistir este registro; permite devolver el
+  /// `PersistResult` original cuando se repite un `command_id`.
+  persisted_version: Option<i64>,
+  /// Momento (segundos) en que `delete_from_step` truncó el registro.
+  deleted_at_ts: Option<i64>,
+  tenant_id: String,
 }
 #[derive(Debug, Queryable, Insertable)]
 #[diesel(table_name = snapshots)]
@@ -62,17 +158,32 @@ struct SnapshotRow {
   state_ptr: String,
   metadata: String,
   created_at_ts: i64,
+  /// Momento (segundos) en que `delete_from_step` truncó el snapshot.
+  deleted_at_ts: Option<i64>,
+  tenant_id: String,
 }
 #[cfg(any(test, not(feature = "pg")))]
 impl DieselFlowRepository {
   pub fn new(database_url: &str) -> Self {
+    Self::new_for_tenant(database_url, DEFAULT_TENANT)
+  }
+  /// Como `new`, con todas las lecturas y escrituras limitadas a `tenant`.
+  pub fn new_for_tenant(database_url: &str, tenant: &str) -> Self {
     let manager = ConnectionManager::<SqliteConnection>::new(database_url);
-    let pool = Pool::builder().max_size(1).build(manager).expect("no se pudo crear el pool de conexiones");
-    let snapshot_dir = std::env::var("SNAPSHOT_DIR").unwrap_or_else(|_| "./snapshots".to_string());
-    let artifact_dir = std::env::var("ARTIFACT_DIR").unwrap_or_else(|_| "./artifacts".to_string());
-    fs::create_dir_all(&snapshot_dir).ok();
+    let pool = Arc::new(Pool::builder().max_size(1).build(manager).expect("no se pudo crear el pool de conexiones"));
+    let snapshot_store =
+      snapshot_store_from_env(Some(Arc::clone(&pool)), tenant).expect("no se pudo crear el snapshot store");
+    let artifact_dir = PathBuf::from(std::env::var("ARTIFACT_DIR").unwrap_or_else(|_| "./artifacts".to_string()));
     fs::create_dir_all(&artifact_dir).ok();
-    let repo = DieselFlowRepository { pool: Arc::new(pool), snapshot_dir, artifact_dir };
+    let repo = DieselFlowRepository { pool,
+                                      tenant: tenant.to_string(),
+                                      snapshot_store,
+                                      retention: snapshot_retention_from_env(),
+                                      artifact_dir,
+                                      lease_ms: lease_ms_from_env(),
+                                      lock_ttl_ms: lock_ttl_ms_from_env(),
+                                      feed: Arc::new(ChangeFeed::new()),
+                                      shared: None };
     if let Ok(mut c) = repo.conn_raw() {
       let _ = diesel::sql_query("PRAGMA journal_mode = WAL;").execute(&mut c);
       let _ = diesel::sql_query("PRAGMA busy_timeout = 5000;").execute(&mut c);
@@ -84,9 +195,6 @@ impl DieselFlowRepository {
     }
     repo
   }
-  pub fn conn(&self) -> FlowResult<PooledConnection<ConnectionManager<SqliteConnection>>> {
-    self.conn_raw().map_err(|e| FlowError::Storage(format!("pool: {}", e)))
-  }
   fn conn_raw(&self) -> std::result::Result<PooledConnection<ConnectionManager<SqliteConnection>>, r2d2::Error> {
     let mut conn = self.pool.get()?;
     let _ = diesel::sql_query("PRAGMA journal_mode = WAL;").execute(&mut conn);
@@ -96,36 +204,39 @@ impl DieselFlowRepository {
 }
 #[cfg(all(feature = "pg", not(test)))]
 impl DieselFlowRepository {
-  pub fn conn(&self) -> FlowResult<PooledConnection<ConnectionManager<PgConnection>>> {
-    self.conn_raw().map_err(|e| FlowError::Storage(format!("pool: {}", e)))
-  }
   fn conn_raw(&self) -> std::result::Result<PooledConnection<ConnectionManager<PgConnection>>, r2d2::Error> {
     self.pool.get()
   }
 }
-#[cfg(all(feature = "pg", not(test)))]
+/// Repositorio de `DATABASE_URL` para el tenant de `TENANT_ID` (ver
+/// `new_from_env_for_tenant`).
 pub fn new_from_env() -> FlowResult<DieselFlowRepository> {
+  dotenvy::dotenv().ok();
+  new_from_env_for_tenant(&tenant_from_env())
+}
+#[cfg(all(feature = "pg", not(test)))]
+pub fn new_from_env_for_tenant(tenant: &str) -> FlowResult<DieselFlowRepository> {
   dotenvy::dotenv().ok();
   let url = std::env::var("DATABASE_URL").map_err(|_| FlowError::Other("DATABASE_URL not set".into()))?;
   if !(url.starts_with("postgres") || url.starts_with("postgresql://") || url.contains("@")) {
     return Err(FlowError::Other("chem-persistence: DATABASE_URL does not look like Postgres URL".into()));
   }
-  DieselFlowRepository::new_pg(&url)
+  DieselFlowRepository::new_pg_for_tenant(&url, tenant)
 }
 #[cfg(test)]
-pub fn new_from_env() -> FlowResult<DieselFlowRepository> {
+pub fn new_from_env_for_tenant(tenant: &str) -> FlowResult<DieselFlowRepository> {
   dotenvy::dotenv().ok();
   let url = std::env::var("DATABASE_URL").unwrap_or_else(|_| "file:memdb1?mode=memory&cache=shared".into());
-  let repo = DieselFlowRepository::new(&url);
+  let repo = DieselFlowRepository::new_for_tenant(&url, tenant);
   Ok(repo)
 }
 #[cfg(all(not(feature = "pg"), not(test)))]
-pub fn new_from_env() -> FlowResult<DieselFlowRepository> {
+pub fn new_from_env_for_tenant(tenant: &str) -> FlowResult<DieselFlowRepository> {
   dotenvy::dotenv().ok();
   let url = std::env::var("DATABASE_URL").map_err(|_| FlowError::Other("DATABASE_URL not set".into()))?;
   let url_l = url.to_lowercase();
   if url_l.starts_with("file:") || url_l.contains("mode=memory") || url_l.contains("sqlite") {
-    let repo = DieselFlowRepository::new(&url);
+    let repo = DieselFlowRepository::new_for_tenant(&url, tenant);
     return Ok(repo);
   }
   Err(FlowError::Other("chem-persistence was compiled without 'pg' feature; enable the 'pg' feature to use Postgres in \
@@ -135,17 +246,30 @@ pub fn new_from_env() -> FlowResult<DieselFlowRepository> {
 #[cfg(all(feature = "pg", not(test)))]
 impl DieselFlowRepository {
   pub fn new_pg(database_url: &str) -> FlowResult<DieselFlowRepository> {
+    Self::new_pg_for_tenant(database_url, DEFAULT_TENANT)
+  }
+  /// Como `new_pg`, con todas las lecturas y escrituras limitadas a `tenant`.
+  pub fn new_pg_for_tenant(database_url: &str, tenant: &str) -> FlowResult<DieselFlowRepository> {
     let manager = ConnectionManager::<PgConnection>::new(database_url);
     let pool = Pool::builder().build(manager)
                               .map_err(|e| FlowError::Storage(format!("no se pudo crear el pool de conexiones: {}", e)))?;
-    let snapshot_dir = std::env::var("SNAPSHOT_DIR").unwrap_or_else(|_| "./snapshots".to_string());
-    let artifact_dir = std::env::var("ARTIFACT_DIR").unwrap_or_else(|_| "./artifacts".to_string());
-    fs::create_dir_all(&snapshot_dir).ok();
+    let pool = Arc::new(pool);
+    let snapshot_store = snapshot_store_from_env(Some(Arc::clone(&pool)), tenant)?;
+    let artifact_dir = PathBuf::from(std::env::var("ARTIFACT_DIR").unwrap_or_else(|_| "./artifacts".to_string()));
     fs::create_dir_all(&artifact_dir).ok();
-    let repo = DieselFlowRepository { pool: Arc::new(pool), snapshot_dir, artifact_dir };
+    let repo = DieselFlowRepository { pool,
+                                      tenant: tenant.to_string(),
+                                      snapshot_store,
+                                      retention: snapshot_retention_from_env(),
+                                      artifact_dir,
+                                      lease_ms: lease_ms_from_env(),
+                                      lock_ttl_ms: lock_ttl_ms_from_env(),
+                                      feed: Arc::new(ChangeFeed::new()),
+                                      listener_started: Arc::new(std::sync::Mutex::new(false)),
+                                      shared: None };
     if let Ok(mut c) = repo.conn_raw() {
-      match c.run_pending_migrations(MIGRATIONS) {
-        Ok(applied) => eprintln!("chem-persistence (pg): aplicadas {} migraciones embebidas", applied.len()),
+      match run_pg_migrations(&mut c) {
+        Ok(applied) => eprintln!("chem-persistence (pg): aplicadas {} migraciones embebidas", applied),
         Err(e) => eprintln!("chem-persistence (pg): fallo al ejecutar migraciones embebidas: {}", e),
       }
     }
@@ -154,48 +278,758 @@ impl DieselFlowRepository {
   pub fn new_pg_from_env() -> FlowResult<DieselFlowRepository> {
     dotenvy::dotenv().ok();
     let url = std::env::var("DATABASE_URL").map_err(|_| FlowError::Other("DATABASE_URL not set".into()))?;
-    DieselFlowRepository::new_pg(&url)
+    DieselFlowRepository::new_pg_for_tenant(&url, &tenant_from_env())
   }
 }
+/// Aplica `MIGRATIONS` y después `PG_MIGRATIONS`; devuelve cuántas se
+/// aplicaron.
+#[cfg(any(feature = "async-pg", all(feature = "pg", not(test))))]
+pub(crate) fn run_pg_migrations(c: &mut PgConnection) -> diesel::migration::Result<usize> {
+  let common = c.run_pending_migrations(MIGRATIONS)?.len();
+  Ok(common + c.run_pending_migrations(PG_MIGRATIONS)?.len())
+}
 fn map_db_err<T>(res: std::result::Result<T, DieselError>) -> FlowResult<T> {
   res.map_err(|e| FlowError::Storage(format!("db: {}", e)))
 }
+/// `payload` de las filas de `query_data_meta`, que no lo leen.
+#[cfg(all(feature = "pg", not(test)))]
+const NULL_PAYLOAD_SQL: &str = "'null'::jsonb";
+#[cfg(any(test, not(feature = "pg")))]
+const NULL_PAYLOAD_SQL: &str = "'null'";
+/// `WORK_LEASE_SECS` en milisegundos.
+pub(crate) fn lease_ms_from_env() -> i64 {
+  std::env::var("WORK_LEASE_SECS").ok().and_then(|v| v.parse::<i64>().ok()).unwrap_or(DEFAULT_WORK_LEASE_SECS) * 1000
+}
+/// `FLOW_LOCK_TTL_SECS` en milisegundos.
+fn lock_ttl_ms_from_env() -> i64 {
+  std::env::var("FLOW_LOCK_TTL_SECS").ok().and_then(|v| v.parse::<i64>().ok()).unwrap_or(DEFAULT_FLOW_LOCK_TTL_SECS) * 1000
+}
+impl DieselFlowRepository {
+  /// Tenant al que está limitado este repositorio.
+  pub fn tenant(&self) -> &str {
+    &self.tenant
+  }
+  /// Conexión de la unidad de trabajo en curso o, si no hay, una del pool.
+  pub fn conn(&self) -> FlowResult<DbConnGuard<'_>> {
+    match &self.shared {
+      Some(shared) => shared.lock().map_err(FlowError::Storage),
+      None => self.pooled_conn().map(DbConnGuard::Pooled),
+    }
+  }
+  pub(crate) fn pooled_conn(&self) -> FlowResult<PooledConnection<ConnectionManager<DbConn>>> {
+    self.conn_raw().map_err(|e| FlowError::Storage(format!("pool: {}", e)))
+  }
+  /// Copia del repositorio que trabaja sobre la conexión de `shared`.
+  pub(crate) fn in_unit_of_work(&self, shared: Arc<SharedConn>) -> Self {
+    DieselFlowRepository { pool: Arc::clone(&self.pool),
+                           tenant: self.tenant.clone(),
+                           snapshot_store: Arc::clone(&self.snapshot_store),
+                           retention: self.retention.clone(),
+                           artifact_dir: self.artifact_dir.clone(),
+                           lease_ms: self.lease_ms,
+                           lock_ttl_ms: self.lock_ttl_ms,
+                           feed: Arc::clone(&self.feed),
+                           #[cfg(all(feature = "pg", not(test)))]
+                           listener_started: Arc::clone(&self.listener_started),
+                           shared: Some(shared) }
+  }
+  /// Error si el repositorio es de una unidad de trabajo: la cola y los
+  /// locks coordinan con otros procesos, así que no pueden depender de una
+  /// transacción que aún puede deshacerse (ni bloquear su conexión).
+  fn outside_unit_of_work(&self, op: &str) -> FlowResult<()> {
+    if self.shared.is_some() {
+      return Err(FlowError::Other(format!("{} no se puede usar dentro de una unidad de trabajo", op)));
+    }
+    Ok(())
+  }
+  /// `query_data`/`query_data_meta`: tramos del linaje y filas de cada
+  /// uno en la misma transacción, con el `flow_id` de la rama pedida.
+  fn query_data_rows(&self, flow_id: &Uuid, query: &FlowDataQuery, with_payload: bool) -> FlowResult<Vec<FlowData>> {
+    query.validate()?;
+    let mut conn = self.conn()?;
+    let rows = map_db_err(conn.transaction::<_, DieselError, _>(|conn| {
+                                let segments = segments_of(conn, &self.tenant, &flow_id.to_string())?;
+                                queried_rows(conn, &self.tenant, &segments, query, with_payload)
+                              }))?;
+    Ok(query.order_and_limit(rows.into_iter().map(|r| FlowData { flow_id: *flow_id, ..flow_data_from_row(r) })))
+  }
+  /// Ajusta la duración del lease de la cola de trabajo (con precisión de
+  /// milisegundos).
+  pub fn with_lease_duration(mut self, lease: Duration) -> Self {
+    self.lease_ms = lease.as_millis() as i64;
+    self
+  }
+  /// Ajusta la expiración de los locks de `flow_locks` (solo SQLite; con
+  /// precisión de milisegundos).
+  pub fn with_lock_ttl(mut self, ttl: Duration) -> Self {
+    self.lock_ttl_ms = ttl.as_millis() as i64;
+    self
+  }
+  /// Usa `store` para los blobs de snapshot.
+  pub fn with_snapshot_store(mut self, store: Arc<dyn SnapshotStore>) -> Self {
+    self.snapshot_store = store;
+    self
+  }
+  /// Usa `policy` al compactar snapshots.
+  pub fn with_snapshot_retention(mut self, policy: SnapshotRetention) -> Self {
+    self.retention = policy;
+    self
+  }
+  /// Usa `dir` (creándolo si no existe) para los blobs de artifacts.
+  pub fn with_artifact_dir(mut self, dir: impl Into<PathBuf>) -> Self {
+    self.artifact_dir = dir.into();
+    fs::create_dir_all(&self.artifact_dir).ok();
+    self
+  }
+  /// Store de blobs de snapshot `db` (tabla `snapshot_blobs`) sobre el pool
+  /// y el tenant de este repositorio.
+  pub fn db_snapshot_store(&self) -> DbSnapshotStore {
+    DbSnapshotStore::from_pool(Arc::clone(&self.pool), self.tenant.clone())
+  }
+  /// Registro de auditoría en la tabla `audit_log` sobre el pool y el tenant
+  /// de este repositorio, para `flow::audit::AuditedFlowRepository`.
+  pub fn db_audit_log(&self) -> DbAuditLog {
+    DbAuditLog::from_pool(Arc::clone(&self.pool), self.tenant.clone())
+  }
+  /// Migra los snapshots guardados con el formato anterior (estado JSON en
+  /// base64 directamente en `state_ptr`) al store configurado, dejando en
+  /// `state_ptr` solo la key. Es idempotente; devuelve cuántos migró.
+  pub fn migrate_inline_snapshots(&self) -> FlowResult<usize> {
+    use schema::snapshots::dsl as snap_dsl;
+    let rows = {
+      let mut conn = self.conn()?;
+      map_db_err(snap_dsl::snapshots.filter(snap_dsl::tenant_id.eq(&self.tenant))
+                                    .select((snap_dsl::id, snap_dsl::state_ptr))
+                                    .load::<(String, String)>(&mut *conn))?
+    };
+    let mut migrated = 0;
+    for (sid, ptr) in rows {
+      if self.snapshot_store.load(&ptr).is_ok() {
+        continue;
+      }
+      let Some(state) = decode_inline_snapshot(&ptr) else {
+        continue;
+      };
+      let key = self.snapshot_store.save(&state)?;
+      let mut conn = self.conn()?;
+      map_db_err(diesel::update(snap_dsl::snapshots.filter(snap_dsl::id.eq(&sid)).filter(snap_dsl::tenant_id.eq(&self.tenant)))
+                   .set(snap_dsl::state_ptr.eq(&key))
+                   .execute(&mut *conn))?;
+      migrated += 1;
+    }
+    Ok(migrated)
+  }
+  /// Compacta los snapshots de `flow_id`; devuelve `(snapshots, blobs)`
+  /// borrados. Las filas se borran en una transacción y los blobs después,
+  /// sin conexión tomada.
+  fn compact_flow_snapshots(&self, flow_id: &Uuid) -> FlowResult<(usize, usize)> {
+    use schema::snapshots::dsl as snap_dsl;
+    let fid = flow_id.to_string();
+    let mut conn = self.conn()?;
+    let compacted =
+      map_db_err(conn.transaction::<_, DieselError, _>(|conn| {
+                       let exists = flows_dsl::flows.filter(flows_dsl::id.eq(&fid))
+                                                    .filter(flows_dsl::tenant_id.eq(&self.tenant))
+                                                    .filter(flows_dsl::deleted_at_ts.is_null())
+                                                    .count()
+                                                    .get_result::<i64>(conn)?;
+                       if exists == 0 {
+                         return Ok(None);
+                       }
+                       let branch_points: Vec<i64> =
+                         flows_dsl::flows.filter(flows_dsl::parent_flow_id.eq(Some(fid.clone())))
+                                         .filter(flows_dsl::tenant_id.eq(&self.tenant))
+                                         .select(flows_dsl::parent_cursor)
+                                         .load::<Option<i64>>(conn)?
+                                         .into_iter()
+                                         .flatten()
+                                         .collect();
+                       let own: Vec<SnapshotMeta> = snap_dsl::snapshots.filter(snap_dsl::flow_id.eq(&fid))
+                                                                       .filter(snap_dsl::tenant_id.eq(&self.tenant))
+                                                                       .filter(snap_dsl::deleted_at_ts.is_null())
+                                                                       .load::<SnapshotRow>(conn)?
+                                                                       .into_iter()
+                                                                       .map(snapshot_meta_from_row)
+                                                                       .collect();
+                       let pruned = snapshots_to_prune(&own, &branch_points, &self.retention);
+                       let ids: Vec<String> = pruned.iter().map(|s| s.id.to_string()).collect();
+                       diesel::delete(snap_dsl::snapshots.filter(snap_dsl::id.eq_any(&ids))
+                                                         .filter(snap_dsl::tenant_id.eq(&self.tenant))).execute(conn)?;
+                       // las ramas copian los snapshots del padre con la misma key
+                       let ptrs: Vec<String> = pruned.iter().map(|s| s.state_ptr.clone()).collect();
+                       let still: HashSet<String> = snap_dsl::snapshots.filter(snap_dsl::state_ptr.eq_any(&ptrs))
+                                                                       .filter(snap_dsl::tenant_id.eq(&self.tenant))
+                                                                       .select(snap_dsl::state_ptr)
+                                                                       .load::<String>(conn)?
+                                                                       .into_iter()
+                                                                       .collect();
+                       let keys: HashSet<String> =
+                         ptrs.into_iter().filter(|k| !still.contains(k) && decode_inline_snapshot(k).is_none()).collect();
+                       Ok(Some((pruned.len(), keys)))
+                     }))?;
+    drop(conn);
+    let (removed, keys) = compacted.ok_or_else(|| FlowError::NotFound(format!("flow {}", flow_id)))?;
+    for key in &keys {
+      self.snapshot_store.delete(key)?;
+    }
+    Ok((removed, keys.len()))
+  }
+}
+#[cfg(all(feature = "pg", not(test)))]
+diesel::define_sql_function! { fn pg_try_advisory_lock(key: diesel::sql_types::BigInt) -> diesel::sql_types::Bool; }
+#[cfg(all(feature = "pg", not(test)))]
+diesel::define_sql_function! { fn pg_advisory_unlock(key: diesel::sql_types::BigInt) -> diesel::sql_types::Bool; }
+/// Clave del advisory lock de Postgres derivada del `Uuid` del flow
+/// (compartida con el backend async).
+#[cfg(any(all(feature = "pg", not(test)), feature = "async-pg"))]
+pub(crate) fn advisory_key(flow_id: &Uuid) -> i64 {
+  let (hi, lo) = flow_id.as_u64_pair();
+  (hi ^ lo) as i64
+}
+#[cfg(all(feature = "pg", not(test)))]
+impl DieselFlowRepository {
+  /// Toma un advisory lock de sesión (`pg_try_advisory_lock`) sobre una
+  /// conexión dedicada del pool, reintentando hasta `timeout`. La conexión
+  /// queda retenida por el guard y se devuelve al pool tras
+  /// `pg_advisory_unlock`. Devuelve `None` si se agota el timeout.
+  fn acquire_flow_lock(&self, flow_id: &Uuid, timeout: Duration) -> FlowResult<Option<FlowLockGuard>> {
+    let key = advisory_key(flow_id);
+    let deadline = Instant::now() + timeout;
+    let mut conn = self.pooled_conn()?;
+    loop {
+      if map_db_err(diesel::select(pg_try_advisory_lock(key)).get_result::<bool>(&mut *conn))? {
+        return Ok(Some(FlowLockGuard::new(*flow_id, move || {
+                         if let Err(e) = diesel::select(pg_advisory_unlock(key)).get_result::<bool>(&mut *conn) {
+                           eprintln!("chem-persistence (pg): fallo al liberar advisory lock {}: {}", key, e);
+                         }
+                       })));
+      }
+      let now = Instant::now();
+      if now >= deadline {
+        return Ok(None);
+      }
+      std::thread::sleep(LOCK_POLL_INTERVAL.min(deadline - now));
+    }
+  }
+}
+#[cfg(any(test, not(feature = "pg")))]
+impl DieselFlowRepository {
+  /// Toma el lock insertando una fila en `flow_locks` (descartando antes una
+  /// fila expirada), reintentando hasta `timeout`. El guard borra la fila al
+  /// liberarse y la renueva (un tercio del TTL, para `keep_alive`) moviendo
+  /// su expiración; si la fila ya no es de este dueño la renovación devuelve
+  /// `Conflict`. Devuelve `None` si se agota el timeout.
+  fn acquire_flow_lock(&self, flow_id: &Uuid, timeout: Duration) -> FlowResult<Option<FlowLockGuard>> {
+    use schema::flow_locks::dsl as lock_dsl;
+    let fid = flow_id.to_string();
+    let tenant = self.tenant.clone();
+    let owner_id = Uuid::new_v4().to_string();
+    let deadline = Instant::now() + timeout;
+    loop {
+      let mut conn = self.conn()?;
+      let now_ms = Utc::now().timestamp_millis();
+      let acquired = conn.immediate_transaction::<bool, DieselError, _>(|conn| {
+                           diesel::delete(lock_dsl::flow_locks.filter(lock_dsl::flow_id.eq(&fid)
+                                                                                       .and(lock_dsl::tenant_id.eq(&tenant))
+                                                                                       .and(lock_dsl::expires_at_ms.le(now_ms))))
+                             .execute(conn)?;
+                           let n = diesel::insert_into(lock_dsl::flow_locks)
+                             .values((lock_dsl::flow_id.eq(&fid),
+                                      lock_dsl::owner.eq(&owner_id),
+                                      lock_dsl::expires_at_ms.eq(now_ms + self.lock_ttl_ms),
+                                      lock_dsl::tenant_id.eq(&tenant)))
+                             .on_conflict_do_nothing()
+                             .execute(conn)?;
+                           Ok(n == 1)
+                         })
+                         .map_err(|e| FlowError::Storage(format!("db txn: {}", e)))?;
+      drop(conn);
+      if acquired {
+        let pool = Arc::clone(&self.pool);
+        let renew = self.lock_renewal(flow_id, &owner_id);
+        return Ok(Some(FlowLockGuard::new(*flow_id, move || {
+                         let res = pool.get().map_err(|e| e.to_string()).and_then(|mut c| {
+                                     diesel::delete(lock_dsl::flow_locks.filter(lock_dsl::flow_id.eq(&fid)
+                                                                                                 .and(lock_dsl::tenant_id.eq(&tenant))
+                                                                                                 .and(lock_dsl::owner.eq(&owner_id))))
+                                       .execute(&mut c)
+                                       .map_err(|e| e.to_string())
+                                   });
+                         if let Err(e) = res {
+                           eprintln!("chem-persistence (sqlite): fallo al liberar lock de {}: {}", fid, e);
+                         }
+                       })
+                       .with_renew(LOCK_POLL_INTERVAL.max(Duration::from_millis(self.lock_ttl_ms as u64 / 3)), renew)));
+      }
+      let now = Instant::now();
+      if now >= deadline {
+        return Ok(None);
+      }
+      std::thread::sleep(LOCK_POLL_INTERVAL.min(deadline - now));
+    }
+  }
+}
+#[cfg(any(test, not(feature = "pg")))]
+impl DieselFlowRepository {
+  /// Acción de renovación del lock de `owner_id` sobre el flow: extiende su
+  /// expiración un TTL desde ahora.
+  fn lock_renewal(&self, flow_id: &Uuid, owner_id: &str) -> impl Fn() -> FlowResult<()> + Send + Sync + 'static {
+    use schema::flow_locks::dsl as lock_dsl;
+    let pool = Arc::clone(&self.pool);
+    let (fid, tenant, owner, ttl_ms) = (flow_id.to_string(), self.tenant.clone(), owner_id.to_string(), self.lock_ttl_ms);
+    move || {
+      let mut conn = pool.get().map_err(|e| FlowError::Storage(format!("pool: {}", e)))?;
+      let mine = lock_dsl::flow_locks.filter(lock_dsl::flow_id.eq(&fid)
+                                                              .and(lock_dsl::tenant_id.eq(&tenant))
+                                                              .and(lock_dsl::owner.eq(&owner)));
+      let expires_at_ms = Utc::now().timestamp_millis() + ttl_ms;
+      match map_db_err(diesel::update(mine).set(lock_dsl::expires_at_ms.eq(expires_at_ms)).execute(&mut conn))? {
+        0 => Err(FlowError::Conflict(format!("lock del flow {} perdido", fid))),
+        _ => Ok(()),
+      }
+    }
+  }
+}
+fn snapshot_meta_from_row(r: SnapshotRow) -> SnapshotMeta {
+  SnapshotMeta { id: Uuid::parse_str(&r.id).unwrap(),
+                 flow_id: Uuid::parse_str(&r.flow_id).unwrap(),
+                 cursor: r.cursor,
+                 state_ptr: r.state_ptr,
+                 metadata: serde_json::from_str(&r.metadata).unwrap_or(serde_json::json!({})),
+                 created_at: Utc.timestamp_opt(r.created_at_ts, 0).single().unwrap_or(Utc::now()) }
+}
+/// Registra en `artifact_refs` (a nombre de `tenant`) los artifacts que
+/// declara `data`.
+fn insert_artifact_refs(conn: &mut DbConn, tenant: &str, data: &FlowData) -> std::result::Result<(), DieselError> {
+  use schema::artifact_refs::dsl as refs_dsl;
+  let fid = data.flow_id.to_string();
+  for key in artifact_refs(data) {
+    diesel::insert_into(refs_dsl::artifact_refs).values((refs_dsl::flow_id.eq(&fid),
+                                                         refs_dsl::cursor.eq(data.cursor),
+                                                         refs_dsl::artifact_key.eq(&key),
+                                                         refs_dsl::tenant_id.eq(tenant)))
+                                                .on_conflict_do_nothing()
+                                                .execute(conn)?;
+  }
+  Ok(())
+}
+/// Tramos de `flow_data` que componen el historial de `fid` (vacío si el
+/// flow no existe, está borrado o es de otro tenant); ver
+/// `flow::lineage::data_segments`.
+fn segments_of(conn: &mut DbConn, tenant: &str, fid: &str) -> std::result::Result<Vec<DataSegment>, DieselError> {
+  let Some(row) = flows_dsl::flows.filter(flows_dsl::id.eq(fid))
+                                  .filter(flows_dsl::tenant_id.eq(tenant))
+                                  .filter(flows_dsl::deleted_at_ts.is_null())
+                                  .first::<FlowRow>(conn)
+                                  .optional()?
+  else {
+    return Ok(Vec::new());
+  };
+  let flow = flow_meta_from_row(row);
+  let chain = chain_of(conn, tenant, &flow)?;
+  Ok(data_segments(&flow, &chain))
+}
+/// Antepasados de `flow` (padre primero), incluidos los borrados: una rama
+/// viva sigue leyendo a través de un padre borrado hasta que se purga. La
+/// cadena se corta en el primer antepasado que no sea de `tenant`.
+fn chain_of(conn: &mut DbConn, tenant: &str, flow: &FlowMeta) -> std::result::Result<Vec<FlowMeta>, DieselError> {
+  let mut chain = Vec::new();
+  let mut seen = HashSet::from([flow.id]);
+  let mut next = flow.parent_flow_id;
+  while let Some(parent_id) = next {
+    if !seen.insert(parent_id) {
+      return Err(DieselError::QueryBuilderError(format!("ciclo en el linaje de {}", flow.id).into()));
+    }
+    let Some(row) = flows_dsl::flows.filter(flows_dsl::id.eq(parent_id.to_string()))
+                                    .filter(flows_dsl::tenant_id.eq(tenant))
+                                    .first::<FlowRow>(conn)
+                                    .optional()?
+    else {
+      break;
+    };
+    let parent = flow_meta_from_row(row);
+    next = parent.parent_flow_id;
+    chain.push(parent);
+  }
+  Ok(chain)
+}
+/// Si el historial de `flow` pasa por pasos de un antepasado que siguen
+/// truncados en `flow_truncations`.
+fn reads_truncated(conn: &mut DbConn, tenant: &str, flow: &FlowMeta) -> std::result::Result<bool, DieselError> {
+  use schema::flow_truncations::dsl as trunc_dsl;
+  let chain = chain_of(conn, tenant, flow)?;
+  for seg in data_segments(flow, &chain).iter().skip(1) {
+    let Some(up_to) = seg.up_to else {
+      continue;
+    };
+    let hits: i64 = trunc_dsl::flow_truncations.filter(trunc_dsl::tenant_id.eq(tenant))
+                                               .filter(trunc_dsl::flow_id.eq(seg.flow_id.to_string()))
+                                               .filter(trunc_dsl::from_cursor.le(up_to))
+                                               .count()
+                                               .get_result(conn)?;
+    if hits > 0 {
+      return Ok(true);
+    }
+  }
+  Ok(false)
+}
+/// Ids guardados como lista JSON en `flow_truncations`.
+pub(crate) fn truncated_ids(ids: &str) -> Vec<String> {
+  serde_json::from_str(ids).unwrap_or_default()
+}
+/// Filas no truncadas de `segments` con `after < cursor <= up_to` y `cursor
+/// > from_cursor`, ordenadas por cursor. Conservan su `flow_id` físico.
+fn stitched_rows(conn: &mut DbConn,
+                 tenant: &str,
+                 segments: &[DataSegment],
+                 from_cursor: i64)
+                 -> std::result::Result<Vec<FlowDataRow>, DieselError> {
+  let mut rows = Vec::new();
+  for seg in segments {
+    rows.extend(data_dsl::flow_data.filter(data_dsl::flow_id.eq(seg.flow_id.to_string()))
+                                   .filter(data_dsl::tenant_id.eq(tenant))
+                                   .filter(data_dsl::cursor.gt(from_cursor.max(seg.after.unwrap_or(i64::MIN))))
+                                   .filter(data_dsl::cursor.le(seg.up_to.unwrap_or(i64::MAX)))
+                                   .filter(data_dsl::deleted_at_ts.is_null())
+                                   .load::<FlowDataRow>(conn)?);
+  }
+  rows.sort_by_key(|r| r.cursor);
+  Ok(rows)
+}
+/// Filas de `segments` que cumplen `query`: cada tramo se filtra, ordena y
+/// limita en la base y `FlowDataQuery::order_and_limit` mezcla los
+/// resultados. Sin
+/// `with_payload` la columna `payload` no se lee (llega como `null`).
+fn queried_rows(conn: &mut DbConn,
+                tenant: &str,
+                segments: &[DataSegment],
+                query: &FlowDataQuery,
+                with_payload: bool)
+                -> std::result::Result<Vec<FlowDataRow>, DieselError> {
+  use diesel::dsl::sql;
+  use crate::schema::sql_types::JsonDoc;
+  use diesel::sql_types::{Bool, Integer, Text};
+  let mut rows = Vec::new();
+  for seg in segments {
+    let after = query.after.unwrap_or(0).max(seg.after.unwrap_or(i64::MIN));
+    let up_to = query.up_to.unwrap_or(i64::MAX).min(seg.up_to.unwrap_or(i64::MAX));
+    let mut q = data_dsl::flow_data.filter(data_dsl::flow_id.eq(seg.flow_id.to_string()))
+                                   .filter(data_dsl::tenant_id.eq(tenant.to_string()))
+                                   .filter(data_dsl::cursor.gt(after))
+                                   .filter(data_dsl::cursor.le(up_to))
+                                   .filter(data_dsl::deleted_at_ts.is_null())
+                                   .into_boxed();
+    if let Some(k) = &query.key {
+      q = q.filter(data_dsl::key.eq(k.clone()));
+    }
+    if let Some(p) = &query.key_prefix {
+      q = q.filter(sql::<Bool>("substr(\"key\", 1, ").bind::<Integer, _>(p.chars().count() as i32)
+                                                       .sql(") = ")
+                                                       .bind::<Text, _>(p.clone()));
+    }
+    for pred in &query.json {
+      q = q.filter(diesel_filter(pred));
+    }
+    q = if query.latest_first { q.order(data_dsl::cursor.desc()) } else { q.order(data_dsl::cursor.asc()) };
+    if let Some(limit) = query.limit {
+      q = q.limit(limit as i64);
+    }
+    if with_payload {
+      rows.extend(q.load::<FlowDataRow>(conn)?);
+    } else {
+      rows.extend(q.select((data_dsl::id,
+                            data_dsl::flow_id,
+                            data_dsl::cursor,
+                            data_dsl::key,
+                            sql::<JsonDoc>(NULL_PAYLOAD_SQL),
+                            data_dsl::metadata,
+                            data_dsl::command_id,
+                            data_dsl::created_at_ts,
+                            data_dsl::persisted_version,
+                            data_dsl::deleted_at_ts,
+                            data_dsl::tenant_id))
+                   .load::<FlowDataRow>(conn)?);
+    }
+  }
+  Ok(rows)
+}
+/// Copia en la rama `child_id` las filas de `rows` con `cursor <= up_to`
+/// que no tenga ya como propias (vivas), con sus `artifact_refs`. Lo usa
+/// `purge_deleted` para que la rama no pierda lo que heredaba de un flow
+/// purgado.
+fn copy_inherited(conn: &mut DbConn,
+                  tenant: &str,
+                  child_id: &str,
+                  rows: &[FlowDataRow],
+                  up_to: i64)
+                  -> std::result::Result<(), DieselError> {
+  let own: HashSet<i64> = data_dsl::flow_data.filter(data_dsl::flow_id.eq(child_id))
+                                             .filter(data_dsl::tenant_id.eq(tenant))
+                                             .filter(data_dsl::deleted_at_ts.is_null())
+                                             .select(data_dsl::cursor)
+                                             .load::<i64>(conn)?
+                                             .into_iter()
+                                             .collect();
+  for row in rows.iter().filter(|r| r.cursor <= up_to && !own.contains(&r.cursor)) {
+    let copy = FlowDataRow { id: Uuid::new_v4().to_string(),
+                             flow_id: child_id.to_string(),
+                             tenant_id: tenant.to_string(),
+                             ..row.clone() };
+    diesel::insert_into(data_dsl::flow_data).values(&copy).execute(conn)?;
+    insert_artifact_refs(conn, tenant, &flow_data_from_row(copy))?;
+  }
+  Ok(())
+}
+fn flow_data_from_row(r: FlowDataRow) -> FlowData {
+  let created = Utc.timestamp_opt(r.created_at_ts, 0).single().unwrap_or(Utc::now());
+  FlowData { id: Uuid::parse_str(&r.id).unwrap(),
+             flow_id: Uuid::parse_str(&r.flow_id).unwrap(),
+             cursor: r.cursor,
+             key: r.key,
+             payload: serde_json::from_str(&r.payload).unwrap_or(serde_json::json!({})),
+             metadata: serde_json::from_str(&r.metadata).unwrap_or(serde_json::json!({})),
+             command_id: r.command_id.and_then(|s| Uuid::parse_str(&s).ok()),
+             created_at: created }
+}
+/// Canal de `LISTEN/NOTIFY` por el que Postgres anuncia cada `flow_data`
+/// insertado (compartido con el backend async). El payload es
+/// `<flow_id>:<cursor>`; el registro se lee al recibirlo porque `NOTIFY`
+/// limita el payload a 8000 bytes.
+#[cfg(any(all(feature = "pg", not(test)), feature = "async-pg"))]
+pub(crate) const FLOW_DATA_CHANNEL: &str = "flow_data";
+#[cfg(any(all(feature = "pg", not(test)), feature = "async-pg"))]
+pub(crate) fn notify_payload(flow_id: &Uuid, cursor: i64) -> String {
+  format!("{}:{}", flow_id, cursor)
+}
+#[cfg(any(all(feature = "pg", not(test)), feature = "async-pg"))]
+pub(crate) fn parse_notify_payload(payload: &str) -> Option<(Uuid, i64)> {
+  let (fid, cursor) = payload.split_once(':')?;
+  Some((Uuid::parse_str(fid).ok()?, cursor.parse().ok()?))
+}
+/// Intervalo con el que el hilo `LISTEN` revisa las notificaciones
+/// recibidas (`notifications_iter` no bloquea).
+#[cfg(all(feature = "pg", not(test)))]
+const NOTIFY_POLL_INTERVAL: Duration = Duration::from_millis(100);
+/// Emite el `NOTIFY` dentro de la transacción de `persist_data`; Postgres
+/// solo lo entrega si la transacción confirma.
+#[cfg(all(feature = "pg", not(test)))]
+fn notify_appended(conn: &mut DbConn, data: &FlowData) -> std::result::Result<(), DieselError> {
+  diesel::sql_query("SELECT pg_notify($1, $2)").bind::<diesel::sql_types::Text, _>(FLOW_DATA_CHANNEL)
+                                               .bind::<diesel::sql_types::Text, _>(notify_payload(&data.flow_id,
+                                                                                                  data.cursor))
+                                               .execute(conn)?;
+  Ok(())
+}
+#[cfg(any(test, not(feature = "pg")))]
+fn notify_appended(_conn: &mut DbConn, _data: &FlowData) -> std::result::Result<(), DieselError> {
+  Ok(())
+}
+#[cfg(all(feature = "pg", not(test)))]
+impl DieselFlowRepository {
+  /// En Postgres los suscriptores se alimentan desde `NOTIFY` (también los
+  /// registros escritos por este proceso).
+  fn publish_local(&self, _data: &FlowData) {}
+  /// Arranca (una vez) el hilo que escucha `flow_data`. El `LISTEN` se
+  /// ejecuta antes de volver para que ninguna notificación posterior a
+  /// `subscribe` se pierda.
+  fn ensure_listener(&self) -> FlowResult<()> {
+    let mut started = self.listener_started.lock().unwrap_or_else(|e| e.into_inner());
+    if *started {
+      return Ok(());
+    }
+    let conn = listen_conn(&self.pool)?;
+    let pool = Arc::clone(&self.pool);
+    let feed = Arc::downgrade(&self.feed);
+    let tenant = self.tenant.clone();
+    std::thread::Builder::new().name("flow-data-listener".into())
+                               .spawn(move || listen_loop(pool, conn, feed, tenant))
+                               .map_err(|e| FlowError::Other(format!("listener: {}", e)))?;
+    *started = true;
+    Ok(())
+  }
+}
+#[cfg(all(feature = "pg", not(test)))]
+fn listen_conn(pool: &DbPool) -> FlowResult<PooledConnection<ConnectionManager<PgConnection>>> {
+  let mut conn = pool.get().map_err(|e| FlowError::Storage(format!("pool: {}", e)))?;
+  map_db_err(diesel::sql_query(format!("LISTEN {}", FLOW_DATA_CHANNEL)).execute(&mut conn))?;
+  Ok(conn)
+}
+/// Bucle del hilo `LISTEN`: por cada notificación con suscriptores lee el
+/// registro (solo si es de `tenant`) y lo publica en el feed. Termina cuando el repositorio (dueño
+/// del feed) se destruye; si la conexión falla, vuelve a escuchar con otra.
+#[cfg(all(feature = "pg", not(test)))]
+fn listen_loop(pool: Arc<DbPool>,
+               mut conn: PooledConnection<ConnectionManager<PgConnection>>,
+               feed: std::sync::Weak<ChangeFeed>,
+               tenant: String) {
+  loop {
+    let Some(feed) = feed.upgrade() else {
+      return;
+    };
+    let pending: std::result::Result<Vec<_>, DieselError> = conn.notifications_iter().collect();
+    match pending {
+      Ok(notifications) => {
+        for n in notifications {
+          let Some((flow_id, cursor)) = parse_notify_payload(&n.payload) else {
+            continue;
+          };
+          if !feed.has_subscribers(&flow_id) {
+            continue;
+          }
+          let row = data_dsl::flow_data.filter(data_dsl::flow_id.eq(flow_id.to_string()).and(data_dsl::cursor.eq(cursor)))
+                                       .filter(data_dsl::tenant_id.eq(&tenant))
+                                       .filter(data_dsl::deleted_at_ts.is_null())
+                                       .first::<FlowDataRow>(&mut conn)
+                                       .optional();
+          match row {
+            Ok(Some(r)) => feed.publish(&flow_data_from_row(r)),
+            Ok(None) => {}
+            Err(e) => eprintln!("chem-persistence (pg): fallo al leer flow_data notificado: {}", e),
+          }
+        }
+      }
+      Err(e) => {
+        eprintln!("chem-persistence (pg): conexión LISTEN perdida: {}", e);
+        match listen_conn(&pool) {
+          Ok(c) => conn = c,
+          Err(e) => eprintln!("chem-persistence (pg): no se pudo reabrir LISTEN: {}", e),
+        }
+      }
+    }
+    drop(feed);
+    std::thread::sleep(NOTIFY_POLL_INTERVAL);
+  }
+}
+#[cfg(any(test, not(feature = "pg")))]
+impl DieselFlowRepository {
+  /// SQLite no tiene `NOTIFY`: se publica tras el commit y solo llega a los
+  /// suscriptores de este proceso.
+  /// Dentro de una unidad de trabajo se espera a su commit.
+  fn publish_local(&self, data: &FlowData) {
+    match &self.shared {
+      Some(shared) => shared.publish_on_commit(&self.feed, data),
+      None => self.feed.publish(data),
+    }
+  }
+  fn ensure_listener(&self) -> FlowResult<()> {
+    Ok(())
+  }
+}
+/// `true` si `fid` existe en `tenant` y no está borrado.
+fn flow_is_live(conn: &mut DbConn, tenant: &str, fid: &str) -> FlowResult<bool> {
+  let c: i64 = map_db_err(flows_dsl::flows.filter(flows_dsl::id.eq(fid))
+                                          .filter(flows_dsl::tenant_id.eq(tenant))
+                                          .filter(flows_dsl::deleted_at_ts.is_null())
+                                          .count()
+                                          .get_result(conn))?;
+  Ok(c > 0)
+}
+/// Busca un registro previo (no truncado) con el mismo `command_id` en el
+/// flow y construye el `PersistResult` que se devolvió al aplicarlo.
+/// Registros anteriores a la columna `persisted_version` devuelven la versión
+/// actual del flow.
+fn replayed_result(conn: &mut DbConn,
+                   tenant: &str,
+                   fid: &str,
+                   cmd: &str,
+                   current_version: i64)
+                   -> std::result::Result<Option<PersistResult>, DieselError> {
+  let prev = data_dsl::flow_data.filter(data_dsl::flow_id.eq(fid).and(data_dsl::command_id.eq(cmd)))
+                                .filter(data_dsl::tenant_id.eq(tenant))
+                                .filter(data_dsl::deleted_at_ts.is_null())
+                                .select(data_dsl::persisted_version)
+                                .first::<Option<i64>>(conn)
+                                .optional()?;
+  Ok(prev.map(|v| PersistResult::Ok { new_version: v.unwrap_or(current_version) }))
+}
+/// Selecciona el siguiente item reclamable de la cola. En Postgres se usa
+/// `FOR UPDATE SKIP LOCKED` para que workers concurrentes no se bloqueen ni
+/// reciban la misma fila.
+#[cfg(all(feature = "pg", not(test)))]
+fn next_claimable(conn: &mut DbConn, tenant: &str, now_ms: i64) -> std::result::Result<Option<WorkQueueRow>, DieselError> {
+  work_dsl::work_queue.filter(work_dsl::tenant_id.eq(tenant))
+                      .filter(work_dsl::worker_id.is_null().or(work_dsl::lease_expires_at_ms.le(now_ms)))
+                      .order((work_dsl::enqueued_at_ms.asc(), work_dsl::flow_id.asc()))
+                      .for_update()
+                      .skip_locked()
+                      .first::<WorkQueueRow>(conn)
+                      .optional()
+}
+/// SQLite no soporta `SKIP LOCKED`; el claim se hace dentro de una
+/// transacción `IMMEDIATE` (ver `claim_transaction`), que serializa a los
+/// escritores.
+#[cfg(any(test, not(feature = "pg")))]
+fn next_claimable(conn: &mut DbConn, tenant: &str, now_ms: i64) -> std::result::Result<Option<WorkQueueRow>, DieselError> {
+  work_dsl::work_queue.filter(work_dsl::tenant_id.eq(tenant))
+                      .filter(work_dsl::worker_id.is_null().or(work_dsl::lease_expires_at_ms.le(now_ms)))
+                      .order((work_dsl::enqueued_at_ms.asc(), work_dsl::flow_id.asc()))
+                      .first::<WorkQueueRow>(conn)
+                      .optional()
+}
+#[cfg(all(feature = "pg", not(test)))]
+fn claim_transaction<T, F>(conn: &mut DbConn, f: F) -> std::result::Result<T, DieselError>
+  where F: FnOnce(&mut DbConn) -> std::result::Result<T, DieselError>
+{
+  conn.transaction(f)
+}
+#[cfg(any(test, not(feature = "pg")))]
+fn claim_transaction<T, F>(conn: &mut DbConn, f: F) -> std::result::Result<T, DieselError>
+  where F: FnOnce(&mut DbConn) -> std::result::Result<T, DieselError>
+{
+  conn.immediate_transaction(f)
+}
+/// Transacción que serializa `put` y `gc_artifacts` sobre una misma key. En
+/// Postgres toma un advisory lock de transacción derivado de la key (los
+/// primeros 16 dígitos de su SHA-256).
+#[cfg(all(feature = "pg", not(test)))]
+fn artifact_transaction<T, F>(conn: &mut DbConn, key: &str, _nested: bool, f: F) -> std::result::Result<T, DieselError>
+  where F: FnOnce(&mut DbConn) -> std::result::Result<T, DieselError>
+{
+  let lock_key = key.get(..16).and_then(|h| u64::from_str_radix(h, 16).ok()).unwrap_or(0) as i64;
+  conn.transaction(|conn| {
+        diesel::sql_query("SELECT pg_advisory_xact_lock($1)").bind::<diesel::sql_types::BigInt, _>(lock_key)
+                                                             .execute(conn)?;
+        f(conn)
+      })
+}
+/// En SQLite basta una transacción `IMMEDIATE`, que serializa a los
+/// escritores; dentro de una unidad de trabajo (`nested`) es un savepoint de
+/// su transacción, que retiene el lock de escritura hasta confirmarse.
+#[cfg(any(test, not(feature = "pg")))]
+fn artifact_transaction<T, F>(conn: &mut DbConn, _key: &str, nested: bool, f: F) -> std::result::Result<T, DieselError>
+  where F: FnOnce(&mut DbConn) -> std::result::Result<T, DieselError>
+{
+  if nested {
+    conn.transaction(f)
+  } else {
+    conn.immediate_transaction(f)
+  }
+}
 impl FlowRepository for DieselFlowRepository {
   fn get_flow_meta(&self, flow_id: &Uuid) -> FlowResult<FlowMeta> {
     use schema::flows::dsl::*;
     let mut conn = self.conn()?;
     let fid = flow_id.to_string();
-    let row = map_db_err(flows.filter(id.eq(&fid)).first::<FlowRow>(&mut conn))?;
-    Ok(FlowMeta { id: Uuid::parse_str(&row.id).unwrap(),
-                  name: row.name,
-                  status: row.status,
-                  created_by: row.created_by,
-                  created_at: Utc.timestamp_opt(row.created_at_ts, 0).single().unwrap_or(Utc::now()),
-                  current_cursor: row.current_cursor,
-                  current_version: row.current_version,
-                  parent_flow_id: row.parent_flow_id.and_then(|s| Uuid::parse_str(&s).ok()),
-                  parent_cursor: row.parent_cursor,
-                  metadata: serde_json::from_str(&row.metadata).unwrap_or(serde_json::json!({})) })
+    let row = map_db_err(flows.filter(id.eq(&fid))
+                              .filter(tenant_id.eq(&self.tenant))
+                              .filter(deleted_at_ts.is_null())
+                              .first::<FlowRow>(&mut *conn)
+                              .optional())?
+                .ok_or_else(|| FlowError::NotFound(format!("flow {}", flow_id)))?;
+    Ok(flow_meta_from_row(row))
   }
   fn dump_tables_for_debug(&self) -> FlowResult<(Vec<FlowMeta>, Vec<FlowData>)> {
     let mut conn = self.conn()?;
-    let frows = map_db_err(flows_dsl::flows.load::<FlowRow>(&mut conn))?;
-    let mut flows_out = Vec::new();
-    for r in frows {
-      flows_out.push(FlowMeta { id: Uuid::parse_str(&r.id).unwrap(),
-                                name: r.name,
-                                status: r.status,
-                                created_by: r.created_by,
-                                created_at: Utc.timestamp_opt(r.created_at_ts, 0).single().unwrap_or(Utc::now()),
-                                current_cursor: r.current_cursor,
-                                current_version: r.current_version,
-                                parent_flow_id: r.parent_flow_id.and_then(|s| Uuid::parse_str(&s).ok()),
-                                parent_cursor: r.parent_cursor,
-                                metadata: serde_json::from_str(&r.metadata).unwrap_or(serde_json::json!({})) });
-    }
-    let drows = map_db_err(data_dsl::flow_data.load::<FlowDataRow>(&mut conn))?;
+    let frows = map_db_err(flows_dsl::flows.filter(flows_dsl::tenant_id.eq(&self.tenant))
+                                           .filter(flows_dsl::deleted_at_ts.is_null())
+                                           .load::<FlowRow>(&mut *conn))?;
+    let live: HashSet<String> = frows.iter().map(|r| r.id.clone()).collect();
+    let flows_out: Vec<FlowMeta> = frows.into_iter().map(flow_meta_from_row).collect();
+    let drows = map_db_err(data_dsl::flow_data.filter(data_dsl::tenant_id.eq(&self.tenant))
+                                              .filter(data_dsl::deleted_at_ts.is_null())
+                                              .load::<FlowDataRow>(&mut *conn))?;
     let mut data_out = Vec::new();
-    for r in drows {
+    for r in drows.into_iter().filter(|r| live.contains(&r.flow_id)) {
       data_out.push(FlowData { id: Uuid::parse_str(&r.id).unwrap(),
                                flow_id: Uuid::parse_str(&r.flow_id).unwrap(),
                                cursor: r.cursor,
@@ -208,6 +1042,14 @@ impl FlowRepository for DieselFlowRepository {
     Ok((flows_out, data_out))
   }
   fn create_flow(&self, name_in: Option<String>, status_in: Option<String>, metadata_in: JsonValue) -> FlowResult<Uuid> {
+    self.create_flow_as(name_in, status_in, metadata_in, None)
+  }
+  fn create_flow_as(&self,
+                    name_in: Option<String>,
+                    status_in: Option<String>,
+                    metadata_in: JsonValue,
+                    created_by: Option<String>)
+                    -> FlowResult<Uuid> {
     let mut conn = self.conn()?;
     let new_id = Uuid::new_v4();
     let now_ts = Utc::now().timestamp();
@@ -215,103 +1057,166 @@ impl FlowRepository for DieselFlowRepository {
     let new = FlowRow { id: new_id.to_string(),
                         name: name_in,
                         status: status_in,
-                        created_by: None,
+                        created_by,
                         created_at_ts: now_ts,
                         current_cursor: 0,
                         current_version: 0,
                         parent_flow_id: None,
                         parent_cursor: None,
-                        metadata: meta_s };
-    map_db_err(diesel::insert_into(flows_dsl::flows).values(&new).execute(&mut conn))?;
+                        metadata: JsonText(meta_s),
+                        deleted_at_ts: None,
+                        tenant_id: self.tenant.clone() };
+    map_db_err(diesel::insert_into(flows_dsl::flows).values(&new).execute(&mut *conn))?;
     Ok(new_id)
   }
   fn persist_data(&self, data: &FlowData, expected_version: i64) -> FlowResult<PersistResult> {
     use diesel::prelude::*;
     let mut conn = self.conn()?;
     let fid = data.flow_id.to_string();
-    let tx_res: std::result::Result<PersistResult, diesel::result::Error> =
-      conn.transaction::<PersistResult, diesel::result::Error, _>(|conn| {
-            let row_version: i64 =
-              flows_dsl::flows.filter(flows_dsl::id.eq(&fid)).select(flows_dsl::current_version).first(conn)?;
+    let cmd = data.command_id.map(|u| u.to_string());
+    let mut appended = false;
+    let tx_res: std::result::Result<FlowResult<PersistResult>, diesel::result::Error> =
+      conn.transaction::<FlowResult<PersistResult>, diesel::result::Error, _>(|conn| {
+            let Some((row_version, row_cursor)) =
+              flows_dsl::flows.filter(flows_dsl::id.eq(&fid))
+                              .filter(flows_dsl::tenant_id.eq(&self.tenant))
+                              .filter(flows_dsl::deleted_at_ts.is_null())
+                              .select((flows_dsl::current_version, flows_dsl::current_cursor))
+                              .first::<(i64, i64)>(conn)
+                              .optional()?
+            else {
+              return Ok(Err(FlowError::NotFound(format!("flow {}", fid))));
+            };
+            // Reintento de un comando ya aplicado: se devuelve el resultado
+            // original sin comprobar versión (el caller reintenta con la
+            // versión que tenía antes del primer intento).
+            if let Some(cmd) = &cmd {
+              if let Some(prev) = replayed_result(conn, &self.tenant, &fid, cmd, row_version)? {
+                return Ok(Ok(prev));
+              }
+            }
             if row_version != expected_version {
-              return Ok(PersistResult::Conflict);
+              return Ok(Ok(PersistResult::Conflict));
+            }
+            if data.cursor <= row_cursor {
+              return Ok(Err(FlowError::Conflict(format!("cursor {} not greater than current {}",
+                                                        data.cursor, row_cursor))));
             }
             let row = FlowDataRow { id: data.id.to_string(),
                                     flow_id: data.flow_id.to_string(),
                                     cursor: data.cursor,
                                     key: data.key.clone(),
-                                    payload: data.payload.to_string(),
-                                    metadata: data.metadata.to_string(),
+                                    payload: JsonText(data.payload.to_string()),
+                                    metadata: JsonText(data.metadata.to_string()),
                                     command_id: data.command_id.map(|u| u.to_string()),
-                                    created_at_ts: data.created_at.timestamp() };
+                                    created_at_ts: data.created_at.timestamp(),
+                                    persisted_version: Some(row_version + 1),
+                                    deleted_at_ts: None,
+                                    tenant_id: self.tenant.clone() };
             diesel::insert_into(data_dsl::flow_data).values(&row).execute(conn)?;
-            diesel::update(flows_dsl::flows.filter(flows_dsl::id.eq(&fid))).set((flows_dsl::current_version.eq(row_version
+            insert_artifact_refs(conn, &self.tenant, data)?;
+            diesel::update(flows_dsl::flows.filter(flows_dsl::id.eq(&fid)).filter(flows_dsl::tenant_id.eq(&self.tenant))).set((flows_dsl::current_version.eq(row_version
                                                                                                                + 1),
                                                                                  flows_dsl::current_cursor.eq(data.cursor)))
                                                                            .execute(conn)?;
-            Ok(PersistResult::Ok { new_version: row_version + 1 })
+            notify_appended(conn, data)?;
+            appended = true;
+            Ok(Ok(PersistResult::Ok { new_version: row_version + 1 }))
           });
     match tx_res {
-      Ok(v) => Ok(v),
+      Ok(v) => {
+        if appended {
+          self.publish_local(data);
+        }
+        v
+      }
+      // Carrera entre dos reintentos del mismo comando: el índice único
+      // rechaza el segundo insert y se devuelve el resultado del primero.
+      Err(DieselError::DatabaseError(diesel::result::DatabaseErrorKind::UniqueViolation, _)) if cmd.is_some() => {
+        let cmd = cmd.as_deref().unwrap_or_default();
+        let current = map_db_err(flows_dsl::flows.filter(flows_dsl::id.eq(&fid))
+                                                 .filter(flows_dsl::tenant_id.eq(&self.tenant))
+                                                 .select(flows_dsl::current_version)
+                                                 .first::<i64>(&mut *conn))?;
+        let replay = map_db_err(replayed_result(&mut conn, &self.tenant, &fid, cmd, current))?;
+        replay.ok_or_else(|| FlowError::Storage("db: command_id duplicado sin registro previo".into()))
+      }
       Err(e) => Err(FlowError::Storage(format!("db txn: {}", e))),
     }
   }
+  /// En una rama compone los registros propios con los heredados de sus
+  /// antepasados (`segments_of`); todos se devuelven con el `flow_id` pedido.
   fn read_data(&self, flow_id: &Uuid, from_cursor: i64) -> FlowResult<Vec<FlowData>> {
     let mut conn = self.conn()?;
-    let fid = flow_id.to_string();
-    let rows = map_db_err(data_dsl::flow_data.filter(data_dsl::flow_id.eq(&fid).and(data_dsl::cursor.gt(from_cursor)))
-                                             .order(data_dsl::cursor.asc())
-                                             .load::<FlowDataRow>(&mut conn))?;
-    let mut out = Vec::new();
-    for r in rows {
-      let created = Utc.timestamp_opt(r.created_at_ts, 0).single().unwrap_or(Utc::now());
-      let item = FlowData { id: Uuid::parse_str(&r.id).unwrap(),
-                            flow_id: Uuid::parse_str(&r.flow_id).unwrap(),
-                            cursor: r.cursor,
-                            key: r.key,
-                            payload: serde_json::from_str(&r.payload).unwrap_or(serde_json::json!({})),
-                            metadata: serde_json::from_str(&r.metadata).unwrap_or(serde_json::json!({})),
-                            command_id: r.command_id.and_then(|s| Uuid::parse_str(&s).ok()),
-                            created_at: created };
-      out.push(item);
+    let rows = map_db_err(conn.transaction::<_, DieselError, _>(|conn| {
+                                let segments = segments_of(conn, &self.tenant, &flow_id.to_string())?;
+                                stitched_rows(conn, &self.tenant, &segments, from_cursor)
+                              }))?;
+    Ok(rows.into_iter().map(|r| FlowData { flow_id: *flow_id, ..flow_data_from_row(r) }).collect())
+  }
+  fn query_data(&self, flow_id: &Uuid, query: &FlowDataQuery) -> FlowResult<Vec<FlowData>> {
+    self.query_data_rows(flow_id, query, true)
+  }
+  fn query_data_meta(&self, flow_id: &Uuid, query: &FlowDataQuery) -> FlowResult<Vec<FlowDataMeta>> {
+    Ok(self.query_data_rows(flow_id, query, false)?.iter().map(FlowDataMeta::from).collect())
+  }
+  fn latest_by_key(&self, flow_id: &Uuid, key: &str) -> FlowResult<Option<FlowData>> {
+    let query = FlowDataQuery::new().key(key).latest_first().limit(1);
+    Ok(self.query_data_rows(flow_id, &query, true)?.into_iter().next())
+  }
+  /// El backlog se lee después de registrar al suscriptor, así que un
+  /// registro concurrente puede llegar por ambos lados; `FlowSubscription`
+  /// descarta el duplicado.
+  fn subscribe(&self, flow_id: &Uuid, from_cursor: i64) -> FlowResult<FlowSubscription> {
+    if !self.branch_exists(flow_id)? {
+      return Err(FlowError::NotFound(format!("flow {}", flow_id)));
     }
-    Ok(out)
+    self.ensure_listener()?;
+    let live = self.feed.register(*flow_id);
+    let backlog = self.read_data(flow_id, from_cursor)?;
+    Ok(FlowSubscription::new(*flow_id, from_cursor, backlog, live))
   }
   fn load_latest_snapshot(&self, flow_id_in: &Uuid) -> FlowResult<Option<SnapshotMeta>> {
     use schema::snapshots::dsl::*;
     let mut conn = self.conn()?;
     let fid_s = flow_id_in.to_string();
+    if !flow_is_live(&mut conn, &self.tenant, &fid_s)? {
+      return Ok(None);
+    }
     let row_opt = snapshots.filter(flow_id.eq(&fid_s))
+                           .filter(tenant_id.eq(&self.tenant))
+                           .filter(deleted_at_ts.is_null())
                            .order((cursor.desc(), created_at_ts.desc()))
-                           .first::<SnapshotRow>(&mut conn)
+                           .first::<SnapshotRow>(&mut *conn)
                            .optional()
                            .map_err(|e| FlowError::Storage(format!("db: {}", e)))?;
-    if let Some(r) = row_opt {
-      let meta = SnapshotMeta { id: Uuid::parse_str(&r.id).unwrap(),
-                                flow_id: Uuid::parse_str(&r.flow_id).unwrap(),
-                                cursor: r.cursor,
-                                state_ptr: r.state_ptr.clone(),
-                                metadata: serde_json::from_str(&r.metadata).unwrap_or(serde_json::json!({})),
-                                created_at: Utc.timestamp_opt(r.created_at_ts, 0).single().unwrap_or(Utc::now()) };
-      Ok(Some(meta))
-    } else {
-      Ok(None)
+    Ok(row_opt.map(snapshot_meta_from_row))
+  }
+  fn load_snapshot_at(&self, flow_id_in: &Uuid, cursor_in: i64) -> FlowResult<Option<SnapshotMeta>> {
+    use schema::snapshots::dsl::*;
+    let mut conn = self.conn()?;
+    let fid_s = flow_id_in.to_string();
+    if !flow_is_live(&mut conn, &self.tenant, &fid_s)? {
+      return Ok(None);
     }
+    let row_opt = map_db_err(snapshots.filter(flow_id.eq(&fid_s))
+                                      .filter(tenant_id.eq(&self.tenant))
+                                      .filter(cursor.le(cursor_in))
+                                      .filter(deleted_at_ts.is_null())
+                                      .order((cursor.desc(), created_at_ts.desc()))
+                                      .first::<SnapshotRow>(&mut *conn)
+                                      .optional())?;
+    Ok(row_opt.map(snapshot_meta_from_row))
   }
   fn load_snapshot(&self, snapshot_id: &Uuid) -> FlowResult<(Vec<u8>, SnapshotMeta)> {
     use schema::snapshots::dsl::*;
     let mut conn = self.conn()?;
     let sid = snapshot_id.to_string();
-    let r =
-      snapshots.filter(id.eq(&sid)).first::<SnapshotRow>(&mut conn).map_err(|e| FlowError::Storage(format!("db: {}", e)))?;
-    let bytes = self.load(&r.state_ptr)?;
-    let meta = SnapshotMeta { id: Uuid::parse_str(&r.id).unwrap(),
-                              flow_id: Uuid::parse_str(&r.flow_id).unwrap(),
-                              cursor: r.cursor,
-                              state_ptr: r.state_ptr.clone(),
-                              metadata: serde_json::from_str(&r.metadata).unwrap_or(serde_json::json!({})),
-                              created_at: Utc.timestamp_opt(r.created_at_ts, 0).single().unwrap_or(Utc::now()) };
-    Ok((bytes, meta))
+    let r = map_db_err(snapshots.filter(id.eq(&sid)).filter(tenant_id.eq(&self.tenant)).first::<SnapshotRow>(&mut *conn).optional())?
+              .ok_or_else(|| FlowError::NotFound(format!("snapshot {}", snapshot_id)))?;
+    drop(conn);
+    let bytes = load_snapshot_blob(self.snapshot_store.as_ref(), &r.state_ptr)?;
+    Ok((bytes, snapshot_meta_from_row(r)))
   }
   fn save_snapshot(&self,
                    flow_id_in: &Uuid,
@@ -328,28 +1233,71 @@ impl FlowRepository for DieselFlowRepository {
                              cursor: cursor_in,
                              state_ptr: state_ptr_in.to_string(),
                              metadata: metadata_in.to_string(),
-                             created_at_ts: now_ts };
-    diesel::insert_into(snapshots).values(&snap).execute(&mut conn).map_err(|e| FlowError::Storage(format!("db: {}", e)))?;
+                             created_at_ts: now_ts,
+                             deleted_at_ts: None,
+                             tenant_id: self.tenant.clone() };
+    diesel::insert_into(snapshots).values(&snap).execute(&mut *conn).map_err(|e| FlowError::Storage(format!("db: {}", e)))?;
     Ok(new_id)
   }
+  fn save_snapshot_state(&self, flow_id: &Uuid, cursor: i64, state: &[u8], metadata: serde_json::Value) -> FlowResult<Uuid> {
+    let key = self.snapshot_store.save(state)?;
+    self.save_snapshot(flow_id, cursor, &key, metadata)
+  }
+  fn compact_snapshots(&self, flow_id: &Uuid) -> FlowResult<usize> {
+    Ok(self.compact_flow_snapshots(flow_id)?.0)
+  }
+  /// Compacta cada flow y después borra los blobs huérfanos del store. Los
+  /// blobs se listan antes de leer las referencias: uno guardado entre ambas
+  /// lecturas no aparece en el listado. Un store `fs` o `s3` lo comparten
+  /// todos los tenants, así que las referencias se leen de todos (solo para
+  /// no borrar blobs ajenos; no se devuelve nada de otro tenant).
+  fn gc_snapshots(&self) -> FlowResult<SnapshotGcReport> {
+    use schema::snapshots::dsl as snap_dsl;
+    let mut report = SnapshotGcReport::default();
+    for flow_id in self.list_flow_ids()? {
+      match self.compact_flow_snapshots(&flow_id) {
+        Ok((snaps, blobs)) => {
+          report.snapshots_removed += snaps;
+          report.blobs_removed += blobs;
+        }
+        // borrado entre el listado y la compactación
+        Err(FlowError::NotFound(_)) => {}
+        Err(e) => return Err(e),
+      }
+    }
+    let blobs = self.snapshot_store.list()?;
+    let referenced: HashSet<String> = {
+      let mut conn = self.conn()?;
+      map_db_err(snap_dsl::snapshots.select(snap_dsl::state_ptr).distinct().load::<String>(&mut *conn))?.into_iter().collect()
+    };
+    for key in orphan_blob_keys(&blobs, &referenced, &self.retention, Utc::now()) {
+      self.snapshot_store.delete(&key)?;
+      report.blobs_removed += 1;
+    }
+    Ok(report)
+  }
   fn create_branch(&self, parent_flow_id: &Uuid, parent_cursor: i64, metadata_in: JsonValue) -> FlowResult<Uuid> {
+    if !self.branch_exists(parent_flow_id)? {
+      return Err(FlowError::NotFound(format!("flow {}", parent_flow_id)));
+    }
     let mut conn = self.conn()?;
     conn.transaction::<Uuid, diesel::result::Error, _>(|conn| {
           let new_id = Uuid::new_v4();
           let meta_s = metadata_in.to_string();
           let now_ts = Utc::now().timestamp();
           let parent_id_s = parent_flow_id.to_string();
-          let rows = data_dsl::flow_data.filter(data_dsl::flow_id.eq(&parent_id_s).and(data_dsl::cursor.le(parent_cursor)))
-                                        .load::<FlowDataRow>(conn)?;
           use schema::snapshots::dsl as snaps_dsl;
           let snaps = snaps_dsl::snapshots.filter(snaps_dsl::flow_id.eq(&parent_id_s)
                                                                     .and(snaps_dsl::cursor.le(parent_cursor)))
+                                          .filter(snaps_dsl::tenant_id.eq(&self.tenant))
+                                          .filter(snaps_dsl::deleted_at_ts.is_null())
                                           .load::<SnapshotRow>(conn)?;
-          let _copied_count = rows.len() as i64;
           //el nombre y status de la nueva rama son los mismos que los del padre el
           // nombre_branch para eso consultamos al padre y verificamos su nombre
           // y status y que exista
-          let parent_flow = flows_dsl::flows.filter(flows_dsl::id.eq(&parent_id_s)).first::<FlowRow>(conn)?;
+          let parent_flow = flows_dsl::flows.filter(flows_dsl::id.eq(&parent_id_s))
+                                            .filter(flows_dsl::tenant_id.eq(&self.tenant))
+                                            .first::<FlowRow>(conn)?;
           let status_in = parent_flow.status;
           let name_in = parent_flow.name.map(|n| format!("{}_branch", n)).or(Some("branch".into()));
           let new = FlowRow { id: new_id.to_string(),
@@ -361,26 +1309,21 @@ impl FlowRepository for DieselFlowRepository {
                               current_version: 0,
                               parent_flow_id: Some(parent_flow_id.to_string()),
                               parent_cursor: Some(parent_cursor),
-                              metadata: meta_s };
+                              metadata: JsonText(meta_s),
+                              deleted_at_ts: None,
+                              tenant_id: self.tenant.clone() };
           diesel::insert_into(flows_dsl::flows).values(&new).execute(conn)?;
-          for r in rows {
-            let copy = FlowDataRow { id: Uuid::new_v4().to_string(),
-                                     flow_id: new_id.to_string(),
-                                     cursor: r.cursor,
-                                     key: r.key.clone(),
-                                     payload: r.payload.clone(),
-                                     metadata: r.metadata.clone(),
-                                     command_id: r.command_id.clone(),
-                                     created_at_ts: r.created_at_ts };
-            diesel::insert_into(data_dsl::flow_data).values(&copy).execute(conn)?;
-          }
+          // los `flow_data` no se copian: `read_data` los lee del padre hasta
+          // `parent_cursor`, y sus `artifact_refs` siguen a nombre del padre
           for s in snaps {
             let s_copy = SnapshotRow { id: Uuid::new_v4().to_string(),
                                        flow_id: new_id.to_string(),
                                        cursor: s.cursor,
                                        state_ptr: s.state_ptr.clone(),
                                        metadata: s.metadata.clone(),
-                                       created_at_ts: s.created_at_ts };
+                                       created_at_ts: s.created_at_ts,
+                                       deleted_at_ts: None,
+                                       tenant_id: self.tenant.clone() };
             diesel::insert_into(snaps_dsl::snapshots).values(&s_copy).execute(conn)?;
           }
           Ok(new_id)
@@ -388,18 +1331,33 @@ impl FlowRepository for DieselFlowRepository {
         .map_err(|e| FlowError::Storage(format!("db txn: {}", e)))
   }
   fn branch_exists(&self, flow_id: &Uuid) -> FlowResult<bool> {
+    let mut conn = self.conn()?;
+    flow_is_live(&mut conn, &self.tenant, &flow_id.to_string())
+  }
+  fn list_children(&self, flow_id: &Uuid) -> FlowResult<Vec<FlowMeta>> {
     let mut conn = self.conn()?;
     let fid = flow_id.to_string();
-    let c: i64 = map_db_err(flows_dsl::flows.filter(flows_dsl::id.eq(&fid)).count().get_result(&mut conn))?;
-    Ok(c > 0)
+    if !flow_is_live(&mut conn, &self.tenant, &fid)? {
+      return Err(FlowError::NotFound(format!("flow {}", flow_id)));
+    }
+    let rows = map_db_err(flows_dsl::flows.filter(flows_dsl::parent_flow_id.eq(Some(fid)))
+                                          .filter(flows_dsl::tenant_id.eq(&self.tenant))
+                                          .filter(flows_dsl::deleted_at_ts.is_null())
+                                          .order((flows_dsl::parent_cursor.asc(),
+                                                  flows_dsl::created_at_ts.asc(),
+                                                  flows_dsl::id.asc()))
+                                          .load::<FlowRow>(&mut *conn))?;
+    Ok(rows.into_iter().map(flow_meta_from_row).collect())
   }
   fn get_flow_status(&self, flow_id: &Uuid) -> FlowResult<Option<String>> {
     use schema::flows::dsl::*;
     let mut conn = self.conn()?;
     let fid = flow_id.to_string();
     let row_opt = flows.filter(id.eq(&fid))
+                       .filter(tenant_id.eq(&self.tenant))
+                       .filter(deleted_at_ts.is_null())
                        .select(status)
-                       .first::<Option<String>>(&mut conn)
+                       .first::<Option<String>>(&mut *conn)
                        .optional()
                        .map_err(|e| FlowError::Storage(format!("db: {}", e)))?;
     Ok(row_opt.flatten())
@@ -409,8 +1367,10 @@ impl FlowRepository for DieselFlowRepository {
     let mut conn = self.conn()?;
     let fid = flow_id.to_string();
     let row = flows.filter(id.eq(&fid))
+                   .filter(tenant_id.eq(&self.tenant))
+                   .filter(deleted_at_ts.is_null())
                    .select(metadata)
-                   .first::<String>(&mut conn)
+                   .first::<JsonText>(&mut *conn)
                    .optional()
                    .map_err(|e| FlowError::Storage(format!("db: {}", e)))?;
     if let Some(meta_s) = row {
@@ -427,8 +1387,10 @@ impl FlowRepository for DieselFlowRepository {
     let fid = flow_id.to_string();
     // Read current metadata
     let current = flows.filter(id.eq(&fid))
+                       .filter(tenant_id.eq(&self.tenant))
+                       .filter(deleted_at_ts.is_null())
                        .select(metadata)
-                       .first::<String>(&mut conn)
+                       .first::<JsonText>(&mut *conn)
                        .optional()
                        .map_err(|e| FlowError::Storage(format!("db: {}", e)))?;
     if let Some(mut meta_s) = current {
@@ -439,8 +1401,9 @@ impl FlowRepository for DieselFlowRepository {
       if let Some(obj) = meta_json.as_object_mut() {
         obj.insert(key.to_string(), value);
       }
-      meta_s = meta_json.to_string();
-      map_db_err(diesel::update(flows.filter(id.eq(&fid))).set(metadata.eq(meta_s)).execute(&mut conn))?;
+      meta_s = JsonText(meta_json.to_string());
+      map_db_err(diesel::update(flows.filter(id.eq(&fid)).filter(tenant_id.eq(&self.tenant))).set(metadata.eq(meta_s))
+                                                                                         .execute(&mut *conn))?;
       Ok(())
     } else {
       Err(FlowError::NotFound(format!("flow {}", flow_id)))
@@ -452,8 +1415,10 @@ impl FlowRepository for DieselFlowRepository {
     let mut conn = self.conn()?;
     let fid = flow_id.to_string();
     let current = flows.filter(id.eq(&fid))
+                       .filter(tenant_id.eq(&self.tenant))
+                       .filter(deleted_at_ts.is_null())
                        .select(metadata)
-                       .first::<String>(&mut conn)
+                       .first::<JsonText>(&mut *conn)
                        .optional()
                        .map_err(|e| FlowError::Storage(format!("db: {}", e)))?;
     if let Some(mut meta_s) = current {
@@ -461,8 +1426,9 @@ impl FlowRepository for DieselFlowRepository {
       if let Some(obj) = meta_json.as_object_mut() {
         obj.remove(key);
       }
-      meta_s = meta_json.to_string();
-      map_db_err(diesel::update(flows.filter(id.eq(&fid))).set(metadata.eq(meta_s)).execute(&mut conn))?;
+      meta_s = JsonText(meta_json.to_string());
+      map_db_err(diesel::update(flows.filter(id.eq(&fid)).filter(tenant_id.eq(&self.tenant))).set(metadata.eq(meta_s))
+                                                                                         .execute(&mut *conn))?;
       Ok(())
     } else {
       Err(FlowError::NotFound(format!("flow {}", flow_id)))
@@ -473,18 +1439,16 @@ impl FlowRepository for DieselFlowRepository {
     use schema::flows::dsl::*;
     let mut conn = self.conn()?;
     let fid = flow_id.to_string();
-    map_db_err(diesel::update(flows.filter(id.eq(&fid))).set(status.eq(new_status.clone())).execute(&mut conn))?;
-    let row = map_db_err(flows.filter(id.eq(&fid)).first::<FlowRow>(&mut conn))?;
-    Ok(FlowMeta { id: Uuid::parse_str(&row.id).unwrap(),
-                  name: row.name,
-                  status: row.status,
-                  created_by: row.created_by,
-                  created_at: Utc.timestamp_opt(row.created_at_ts, 0).single().unwrap_or(Utc::now()),
-                  current_cursor: row.current_cursor,
-                  current_version: row.current_version,
-                  parent_flow_id: row.parent_flow_id.and_then(|s| Uuid::parse_str(&s).ok()),
-                  parent_cursor: row.parent_cursor,
-                  metadata: serde_json::from_str(&row.metadata).unwrap_or(serde_json::json!({})) })
+    let updated = map_db_err(diesel::update(flows.filter(id.eq(&fid))
+                                                 .filter(tenant_id.eq(&self.tenant))
+                                                 .filter(deleted_at_ts.is_null()))
+                               .set(status.eq(new_status.clone()))
+                               .execute(&mut *conn))?;
+    if updated == 0 {
+      return Err(FlowError::NotFound(format!("flow {}", flow_id)));
+    }
+    let row = map_db_err(flows.filter(id.eq(&fid)).filter(tenant_id.eq(&self.tenant)).first::<FlowRow>(&mut *conn))?;
+    Ok(flow_meta_from_row(row))
   }
   fn count_steps(&self, flow_id: &Uuid) -> FlowResult<i64> {
     use schema::flows::dsl as flows_dsl_local;
@@ -492,15 +1456,26 @@ impl FlowRepository for DieselFlowRepository {
     let fid = flow_id.to_string();
     // Obtener el current_cursor del flujo (si no existe devolvemos -1)
     let parent_row = flows_dsl_local::flows.filter(flows_dsl_local::id.eq(&fid))
-                                           .first::<FlowRow>(&mut conn)
+                                           .filter(flows_dsl_local::tenant_id.eq(&self.tenant))
+                                           .filter(flows_dsl_local::deleted_at_ts.is_null())
+                                           .first::<FlowRow>(&mut *conn)
                                            .optional()
                                            .map_err(|e| FlowError::Storage(format!("db: {}", e)))?;
     if let Some(row) = parent_row {
+      // pasos propios y heredados hasta current_cursor
       let current_cursor = row.current_cursor;
-      let c: i64 = map_db_err(data_dsl::flow_data.filter(data_dsl::flow_id.eq(&fid)
-                                                                          .and(data_dsl::cursor.le(current_cursor)))
-                                                 .count()
-                                                 .get_result(&mut conn))?;
+      let mut c = 0;
+      for seg in map_db_err(segments_of(&mut conn, &self.tenant, &fid))? {
+        let n: i64 =
+          map_db_err(data_dsl::flow_data.filter(data_dsl::flow_id.eq(seg.flow_id.to_string()))
+                                        .filter(data_dsl::tenant_id.eq(&self.tenant))
+                                        .filter(data_dsl::cursor.gt(seg.after.unwrap_or(i64::MIN)))
+                                        .filter(data_dsl::cursor.le(seg.up_to.unwrap_or(i64::MAX).min(current_cursor)))
+                                        .filter(data_dsl::deleted_at_ts.is_null())
+                                        .count()
+                                        .get_result(&mut *conn))?;
+        c += n;
+      }
       Ok(c)
     } else {
       Ok(-1)
@@ -509,7 +1484,8 @@ impl FlowRepository for DieselFlowRepository {
   fn list_flow_ids(&self) -> FlowResult<Vec<Uuid>> {
     use schema::flows::dsl::*;
     let mut conn = self.conn()?;
-    let rows = map_db_err(flows.select(id).load::<String>(&mut conn))?;
+    let rows =
+      map_db_err(flows.filter(tenant_id.eq(&self.tenant)).filter(deleted_at_ts.is_null()).select(id).load::<String>(&mut *conn))?;
     let mut out = Vec::new();
     for s in rows {
       if let Ok(u) = Uuid::parse_str(&s) {
@@ -518,101 +1494,709 @@ impl FlowRepository for DieselFlowRepository {
     }
     Ok(out)
   }
+  /// Un único `SELECT` sobre `flows`: los filtros, el keyset y el orden se
+  /// traducen a SQL y se leen `page_size + 1` filas para saber si hay otra
+  /// página.
+  fn query_flows(&self, query: &FlowQuery) -> FlowResult<FlowPage> {
+    use diesel::dsl::sql;
+    use diesel::sql_types::{Bool, Integer, Text};
+    query.validate()?;
+    let mut q = flows_dsl::flows.filter(flows_dsl::tenant_id.eq(self.tenant.clone()))
+                                .filter(flows_dsl::deleted_at_ts.is_null())
+                                .into_boxed();
+    if let Some(s) = &query.status {
+      q = q.filter(flows_dsl::status.eq(s.clone()));
+    }
+    if let Some(c) = &query.created_by {
+      q = q.filter(flows_dsl::created_by.eq(c.clone()));
+    }
+    if let Some(p) = query.parent_flow_id {
+      q = q.filter(flows_dsl::parent_flow_id.eq(p.to_string()));
+    }
+    if let Some(since) = query.created_since {
+      q = q.filter(flows_dsl::created_at_ts.ge(since.timestamp()));
+    }
+    if let Some(until) = query.created_until {
+      q = q.filter(flows_dsl::created_at_ts.lt(until.timestamp()));
+    }
+    for pred in query.json_predicates() {
+      q = q.filter(diesel_filter(&pred));
+    }
+    if let Some(p) = &query.name_prefix {
+      q = q.filter(sql::<Bool>("substr(name, 1, ").bind::<Integer, _>(p.chars().count() as i32)
+                                                   .sql(") = ")
+                                                   .bind::<Text, _>(p.clone()));
+    }
+    let op = if query.sort.descending() { "<" } else { ">" };
+    if let Some(c) = &query.after {
+      let cid = c.id.to_string();
+      if query.sort.by_name() {
+        let name = c.name.clone().unwrap_or_default();
+        q = q.filter(sql::<Bool>(&format!("(COALESCE(name, '') {} ", op)).bind::<Text, _>(name.clone())
+                                                                          .sql(" OR (COALESCE(name, '') = ")
+                                                                          .bind::<Text, _>(name)
+                                                                          .sql(&format!(" AND id {} ", op))
+                                                                          .bind::<Text, _>(cid)
+                                                                          .sql("))"));
+      } else {
+        let ts = c.created_at.timestamp();
+        q = if query.sort.descending() {
+          q.filter(flows_dsl::created_at_ts.lt(ts).or(flows_dsl::created_at_ts.eq(ts).and(flows_dsl::id.lt(cid))))
+        } else {
+          q.filter(flows_dsl::created_at_ts.gt(ts).or(flows_dsl::created_at_ts.eq(ts).and(flows_dsl::id.gt(cid))))
+        };
+      }
+    }
+    q = match query.sort {
+      FlowSort::CreatedAsc => q.order((flows_dsl::created_at_ts.asc(), flows_dsl::id.asc())),
+      FlowSort::CreatedDesc => q.order((flows_dsl::created_at_ts.desc(), flows_dsl::id.desc())),
+      FlowSort::NameAsc => q.order((sql::<Text>("COALESCE(name, '')").asc(), flows_dsl::id.asc())),
+      FlowSort::NameDesc => q.order((sql::<Text>("COALESCE(name, '')").desc(), flows_dsl::id.desc())),
+    };
+    let page_size = query.page_size();
+    let mut conn = self.conn()?;
+    let rows = map_db_err(q.limit(page_size as i64 + 1).load::<FlowRow>(&mut *conn))?;
+    Ok(FlowPage::from_rows(rows.into_iter().map(flow_meta_from_row).collect(), page_size))
+  }
+  /// Solo marca el flow con `deleted_at_ts` y quita su entrada de la cola;
+  /// sus filas de `flow_data`, `snapshots` y `artifact_refs` se conservan
+  /// hasta `purge_deleted` y sus hijas las siguen leyendo.
   fn delete_branch(&self, flow_id: &Uuid) -> FlowResult<()> {
+    if !self.branch_exists(flow_id)? {
+      return Err(FlowError::NotFound(format!("flow {}", flow_id)));
+    }
     let mut conn = self.conn()?;
     let fid = flow_id.to_string();
+    let now_ts = Utc::now().timestamp();
     conn.transaction::<(), diesel::result::Error, _>(|conn| {
-            diesel::delete(data_dsl::flow_data.filter(data_dsl::flow_id.eq(&fid))).execute(conn)?;
-            diesel::delete(schema::snapshots::dsl::snapshots.filter(schema::snapshots::dsl::flow_id.eq(&fid))).execute(conn)?;
-            diesel::update(flows_dsl::flows.filter(flows_dsl::parent_flow_id.eq(Some(fid.clone()))))
-                .set((flows_dsl::parent_flow_id.eq::<Option<String>>(None), flows_dsl::parent_cursor.eq::<Option<i64>>(None)))
-                .execute(conn)?;
-            diesel::delete(flows_dsl::flows.filter(flows_dsl::id.eq(&fid))).execute(conn)?;
-            Ok(())
-        }).map_err(|e| FlowError::Storage(format!("db txn: {}", e)))
-  }
-  fn delete_from_step(&self, _flow_id: &Uuid, _from_cursor: i64) -> FlowResult<()> {
-    // Behavior:
-    // - keep steps with cursor < from_cursor
-    // - delete steps with cursor >= from_cursor for the given flow
-    // - delete snapshots with cursor >= from_cursor for the given flow
-    // - find child branches whose parent_cursor >= from_cursor and delete them
-    //   recursively
-    // - update current_cursor to max remaining cursor or 0
-    let mut conn = self.conn()?;
-    let fid = _flow_id.to_string();
-    // Ensure flow exists
-    let exists = map_db_err(flows_dsl::flows.filter(flows_dsl::id.eq(&fid))
-                                            .select(flows_dsl::id)
-                                            .first::<String>(&mut conn)
-                                            .optional())?;
-    if exists.is_none() {
-      return Err(FlowError::NotFound(format!("flow {}", _flow_id)));
-    }
-    conn.transaction::<(), diesel::result::Error, _>(|conn| {
-      // Delete flow_data with cursor >= from_cursor
-      diesel::delete(data_dsl::flow_data.filter(data_dsl::flow_id.eq(&fid).and(data_dsl::cursor.ge(_from_cursor)))).execute(conn)?;
-      // Delete snapshots with cursor >= from_cursor
-      diesel::delete(schema::snapshots::dsl::snapshots.filter(schema::snapshots::dsl::flow_id.eq(&fid).and(schema::snapshots::dsl::cursor.ge(_from_cursor)))).execute(conn)?;
-      // Update current_cursor to max remaining cursor or 0
-      let new_cursor_opt: Option<i64> = data_dsl::flow_data.filter(data_dsl::flow_id.eq(&fid))
-        .select(diesel::dsl::max(data_dsl::cursor))
-        .first(conn)?;
-      let new_cursor = new_cursor_opt.unwrap_or(0);
-      diesel::update(flows_dsl::flows.filter(flows_dsl::id.eq(&fid)))
+          diesel::delete(work_dsl::work_queue.filter(work_dsl::flow_id.eq(&fid)).filter(work_dsl::tenant_id.eq(&self.tenant)))
+            .execute(conn)?;
+          diesel::update(flows_dsl::flows.filter(flows_dsl::id.eq(&fid)).filter(flows_dsl::tenant_id.eq(&self.tenant)))
+            .set(flows_dsl::deleted_at_ts.eq(Some(now_ts)))
+            .execute(conn)?;
+          Ok(())
+        })
+        .map_err(|e| FlowError::Storage(format!("db txn: {}", e)))
+  }
+  /// Los pasos y snapshots truncados y las ramas afectadas se marcan con
+  /// `deleted_at_ts` (sus `artifact_refs` se conservan hasta
+  /// `purge_deleted`), y el truncado queda en `flow_truncations` para
+  /// `undelete_steps`.
+  fn delete_from_step(&self, flow_id: &Uuid, from_cursor: i64) -> FlowResult<()> {
+    use schema::flow_truncations::dsl as trunc_dsl;
+    use schema::snapshots::dsl as snap_dsl;
+    let mut conn = self.conn()?;
+    let fid = flow_id.to_string();
+    let now = Utc::now();
+    let now_ts = now.timestamp();
+    let tenant = self.tenant.as_str();
+    map_db_err(conn.transaction::<FlowResult<()>, DieselError, _>(|conn| {
+      let Some(flow) = flows_dsl::flows.filter(flows_dsl::id.eq(&fid))
+                                       .filter(flows_dsl::tenant_id.eq(tenant))
+                                       .filter(flows_dsl::deleted_at_ts.is_null())
+                                       .first::<FlowRow>(conn)
+                                       .optional()?
+      else {
+        return Ok(Err(FlowError::NotFound(format!("flow {}", flow_id))));
+      };
+      // descendientes que leen desde from_cursor: cada tramo heredado llega
+      // hasta el menor parent_cursor del camino
+      let mut affected = Vec::new();
+      let mut seen = HashSet::from([fid.clone()]);
+      let mut frontier = vec![fid.clone()];
+      while let Some(parent) = frontier.pop() {
+        let children = flows_dsl::flows.filter(flows_dsl::parent_flow_id.eq(Some(parent))
+                                                                        .and(flows_dsl::parent_cursor.ge(from_cursor)))
+                                       .filter(flows_dsl::tenant_id.eq(tenant))
+                                       .load::<FlowRow>(conn)?;
+        for child in children.into_iter().filter(|c| seen.insert(c.id.clone())) {
+          if child.deleted_at_ts.is_none() {
+            affected.push(child.id.clone());
+          }
+          frontier.push(child.id);
+        }
+      }
+      let data_ids: Vec<String> = data_dsl::flow_data.filter(data_dsl::flow_id.eq(&fid))
+                                                     .filter(data_dsl::tenant_id.eq(tenant))
+                                                     .filter(data_dsl::cursor.ge(from_cursor))
+                                                     .filter(data_dsl::deleted_at_ts.is_null())
+                                                     .select(data_dsl::id)
+                                                     .load(conn)?;
+      let snapshot_ids: Vec<String> = snap_dsl::snapshots.filter(snap_dsl::flow_id.eq(&fid))
+                                                         .filter(snap_dsl::tenant_id.eq(tenant))
+                                                         .filter(snap_dsl::cursor.ge(from_cursor))
+                                                         .filter(snap_dsl::deleted_at_ts.is_null())
+                                                         .select(snap_dsl::id)
+                                                         .load(conn)?;
+      diesel::update(data_dsl::flow_data.filter(data_dsl::tenant_id.eq(tenant)).filter(data_dsl::id.eq_any(&data_ids)))
+        .set(data_dsl::deleted_at_ts.eq(Some(now_ts)))
+        .execute(conn)?;
+      diesel::update(snap_dsl::snapshots.filter(snap_dsl::tenant_id.eq(tenant)).filter(snap_dsl::id.eq_any(&snapshot_ids)))
+        .set(snap_dsl::deleted_at_ts.eq(Some(now_ts)))
+        .execute(conn)?;
+      diesel::update(flows_dsl::flows.filter(flows_dsl::tenant_id.eq(tenant)).filter(flows_dsl::id.eq_any(&affected)))
+        .set(flows_dsl::deleted_at_ts.eq(Some(now_ts)))
+        .execute(conn)?;
+      diesel::delete(work_dsl::work_queue.filter(work_dsl::tenant_id.eq(tenant)).filter(work_dsl::flow_id.eq_any(&affected)))
+        .execute(conn)?;
+      // en una rama, el tramo heredado no puede pasar de from_cursor - 1
+      let parent_cursor = flow.parent_cursor.filter(|pc| flow.parent_flow_id.is_some() && *pc >= from_cursor);
+      if parent_cursor.is_some() {
+        diesel::update(flows_dsl::flows.filter(flows_dsl::id.eq(&fid)).filter(flows_dsl::tenant_id.eq(tenant)))
+          .set(flows_dsl::parent_cursor.eq(from_cursor - 1))
+          .execute(conn)?;
+      }
+      let segments = segments_of(conn, tenant, &fid)?;
+      let new_cursor = stitched_rows(conn, tenant, &segments, 0)?.last().map(|r| r.cursor).unwrap_or(0);
+      diesel::update(flows_dsl::flows.filter(flows_dsl::id.eq(&fid)).filter(flows_dsl::tenant_id.eq(tenant)))
         .set(flows_dsl::current_cursor.eq(new_cursor))
         .execute(conn)?;
-      Ok(())
-    }).map_err(|e| FlowError::Storage(format!("db txn: {}", e)))?;
-    // Find child flows whose parent_cursor >= from_cursor and collect their ids
-    let child_rows: Vec<FlowRow> =
-      map_db_err(flows_dsl::flows.filter(flows_dsl::parent_flow_id.eq(Some(fid.clone()))
-                                                                  .and(flows_dsl::parent_cursor.ge(_from_cursor)))
-                                 .load::<FlowRow>(&mut conn))?;
-    // For each child, call delete_branch to remove subtree
-    for child in child_rows {
-      if let Ok(child_uuid) = Uuid::parse_str(&child.id) {
-        // delete_branch is transactional on its own
-        self.delete_branch(&child_uuid)?;
+      let ids_json = |ids: &[String]| serde_json::json!(ids).to_string();
+      let record = (trunc_dsl::id.eq(Uuid::new_v4().to_string()),
+                    trunc_dsl::tenant_id.eq(tenant),
+                    trunc_dsl::flow_id.eq(&fid),
+                    trunc_dsl::from_cursor.eq(from_cursor),
+                    trunc_dsl::flow_version.eq(flow.current_version),
+                    trunc_dsl::parent_cursor.eq(parent_cursor),
+                    trunc_dsl::at_us.eq(now.timestamp_micros()),
+                    trunc_dsl::deleted_at_ts.eq(now_ts),
+                    trunc_dsl::data_ids.eq(ids_json(&data_ids)),
+                    trunc_dsl::snapshot_ids.eq(ids_json(&snapshot_ids)),
+                    trunc_dsl::flow_ids.eq(ids_json(&affected)));
+      diesel::insert_into(trunc_dsl::flow_truncations).values(record).execute(conn)?;
+      Ok(Ok(()))
+    }))?
+  }
+  /// Restaura el truncado más reciente de `flow_truncations` y borra su fila,
+  /// en una transacción.
+  fn undelete_steps(&self, flow_id: &Uuid) -> FlowResult<()> {
+    use schema::flow_truncations::dsl as trunc_dsl;
+    use schema::snapshots::dsl as snap_dsl;
+    let mut conn = self.conn()?;
+    let fid = flow_id.to_string();
+    let tenant = self.tenant.as_str();
+    map_db_err(conn.transaction::<FlowResult<()>, DieselError, _>(|conn| {
+      let Some(version) = flows_dsl::flows.filter(flows_dsl::id.eq(&fid))
+                                          .filter(flows_dsl::tenant_id.eq(tenant))
+                                          .filter(flows_dsl::deleted_at_ts.is_null())
+                                          .select(flows_dsl::current_version)
+                                          .first::<i64>(conn)
+                                          .optional()?
+      else {
+        return Ok(Err(FlowError::NotFound(format!("flow {}", flow_id))));
+      };
+      let Some((id, flow_version, parent_cursor, data_ids, snapshot_ids, flow_ids)) =
+        trunc_dsl::flow_truncations.filter(trunc_dsl::tenant_id.eq(tenant))
+                                   .filter(trunc_dsl::flow_id.eq(&fid))
+                                   .order(trunc_dsl::at_us.desc())
+                                   .select((trunc_dsl::id,
+                                            trunc_dsl::flow_version,
+                                            trunc_dsl::parent_cursor,
+                                            trunc_dsl::data_ids,
+                                            trunc_dsl::snapshot_ids,
+                                            trunc_dsl::flow_ids))
+                                   .first::<(String, i64, Option<i64>, String, String, String)>(conn)
+                                   .optional()?
+      else {
+        return Ok(Err(FlowError::NotFound(format!("pasos truncados del flow {}", flow_id))));
+      };
+      if flow_version != version {
+        return Ok(Err(FlowError::Conflict(format!("el flow {} persistió pasos después de truncar", flow_id))));
       }
+      diesel::delete(trunc_dsl::flow_truncations.filter(trunc_dsl::id.eq(&id))).execute(conn)?;
+      diesel::update(data_dsl::flow_data.filter(data_dsl::tenant_id.eq(tenant))
+                                        .filter(data_dsl::id.eq_any(truncated_ids(&data_ids))))
+        .set(data_dsl::deleted_at_ts.eq::<Option<i64>>(None))
+        .execute(conn)?;
+      diesel::update(snap_dsl::snapshots.filter(snap_dsl::tenant_id.eq(tenant))
+                                        .filter(snap_dsl::id.eq_any(truncated_ids(&snapshot_ids))))
+        .set(snap_dsl::deleted_at_ts.eq::<Option<i64>>(None))
+        .execute(conn)?;
+      if let Some(pc) = parent_cursor {
+        diesel::update(flows_dsl::flows.filter(flows_dsl::id.eq(&fid)).filter(flows_dsl::tenant_id.eq(tenant)))
+          .set(flows_dsl::parent_cursor.eq(pc))
+          .execute(conn)?;
+      }
+      // vuelven las ramas borradas con el truncado que no lean otros pasos
+      // truncados
+      for child in truncated_ids(&flow_ids) {
+        let Some(row) = flows_dsl::flows.filter(flows_dsl::id.eq(&child))
+                                        .filter(flows_dsl::tenant_id.eq(tenant))
+                                        .filter(flows_dsl::deleted_at_ts.is_not_null())
+                                        .first::<FlowRow>(conn)
+                                        .optional()?
+        else {
+          continue;
+        };
+        if !reads_truncated(conn, tenant, &flow_meta_from_row(row))? {
+          diesel::update(flows_dsl::flows.filter(flows_dsl::id.eq(&child)).filter(flows_dsl::tenant_id.eq(tenant)))
+            .set(flows_dsl::deleted_at_ts.eq::<Option<i64>>(None))
+            .execute(conn)?;
+        }
+      }
+      let segments = segments_of(conn, tenant, &fid)?;
+      let new_cursor = stitched_rows(conn, tenant, &segments, 0)?.last().map(|r| r.cursor).unwrap_or(0);
+      diesel::update(flows_dsl::flows.filter(flows_dsl::id.eq(&fid)).filter(flows_dsl::tenant_id.eq(tenant)))
+        .set(flows_dsl::current_cursor.eq(new_cursor))
+        .execute(conn)?;
+      Ok(Ok(()))
+    }))?
+  }
+  fn undelete(&self, flow_id: &Uuid) -> FlowResult<()> {
+    let mut conn = self.conn()?;
+    let fid = flow_id.to_string();
+    let Some(row) = map_db_err(flows_dsl::flows.filter(flows_dsl::id.eq(&fid))
+                                               .filter(flows_dsl::tenant_id.eq(&self.tenant))
+                                               .first::<FlowRow>(&mut *conn)
+                                               .optional())?
+    else {
+      return Err(FlowError::NotFound(format!("flow {}", flow_id)));
+    };
+    if row.deleted_at_ts.is_some() {
+      if map_db_err(reads_truncated(&mut conn, &self.tenant, &flow_meta_from_row(row)))? {
+        return Err(FlowError::Conflict(format!("el historial del flow {} pasa por pasos truncados", flow_id)));
+      }
+      map_db_err(diesel::update(flows_dsl::flows.filter(flows_dsl::id.eq(&fid)).filter(flows_dsl::tenant_id.eq(&self.tenant)))
+                   .set(flows_dsl::deleted_at_ts.eq::<Option<i64>>(None))
+                   .execute(&mut *conn))?;
+    }
+    Ok(())
+  }
+  fn list_deleted_flows(&self) -> FlowResult<Vec<FlowMeta>> {
+    let mut conn = self.conn()?;
+    let rows = map_db_err(flows_dsl::flows.filter(flows_dsl::tenant_id.eq(&self.tenant))
+                                          .filter(flows_dsl::deleted_at_ts.is_not_null())
+                                          .order((flows_dsl::deleted_at_ts.asc(), flows_dsl::id.asc()))
+                                          .load::<FlowRow>(&mut *conn))?;
+    Ok(rows.into_iter().map(flow_meta_from_row).collect())
+  }
+  /// Todo en una transacción. Las `artifact_refs` de los cursores con pasos
+  /// purgados se recalculan con los registros que siguen guardados en ese
+  /// cursor (el paso nuevo que lo ocupó tras truncar).
+  fn purge_deleted(&self, retention: Duration) -> FlowResult<PurgeReport> {
+    use schema::artifact_refs::dsl as refs_dsl;
+    use schema::flow_truncations::dsl as trunc_dsl;
+    use schema::snapshots::dsl as snap_dsl;
+    let cutoff = purge_cutoff(retention, Utc::now()).timestamp();
+    let mut conn = self.conn()?;
+    map_db_err(conn.transaction::<PurgeReport, DieselError, _>(|conn| {
+                     let tenant = self.tenant.as_str();
+                     // el padre de una rama con un truncado pendiente que
+                     // recortó su tramo heredado se conserva
+                     let truncated_branches: Vec<String> =
+                       trunc_dsl::flow_truncations.filter(trunc_dsl::tenant_id.eq(tenant))
+                                                  .filter(trunc_dsl::deleted_at_ts.gt(cutoff))
+                                                  .filter(trunc_dsl::parent_cursor.is_not_null())
+                                                  .select(trunc_dsl::flow_id)
+                                                  .load(conn)?;
+                     let pinned: HashSet<String> = flows_dsl::flows.filter(flows_dsl::tenant_id.eq(tenant))
+                                                                   .filter(flows_dsl::id.eq_any(&truncated_branches))
+                                                                   .select(flows_dsl::parent_flow_id)
+                                                                   .load::<Option<String>>(conn)?
+                                                                   .into_iter()
+                                                                   .flatten()
+                                                                   .collect();
+                     let flow_ids: Vec<String> = flows_dsl::flows.filter(flows_dsl::tenant_id.eq(tenant))
+                                                                 .filter(flows_dsl::deleted_at_ts.le(cutoff))
+                                                                 .select(flows_dsl::id)
+                                                                 .load::<String>(conn)?
+                                                                 .into_iter()
+                                                                 .filter(|id| !pinned.contains(id))
+                                                                 .collect();
+                     // las hijas que quedan reciben copias de lo que heredaban
+                     // a través de un flow purgado y quedan huérfanas
+                     let survivors: Vec<FlowMeta> =
+                       flows_dsl::flows.filter(flows_dsl::tenant_id.eq(tenant))
+                                       .filter(flows_dsl::parent_flow_id.eq_any(flow_ids.iter().map(|id| Some(id.clone()))))
+                                       .load::<FlowRow>(conn)?
+                                       .into_iter()
+                                       .filter(|c| !flow_ids.contains(&c.id))
+                                       .map(flow_meta_from_row)
+                                       .collect();
+                     let mut copies = Vec::with_capacity(survivors.len());
+                     for child in &survivors {
+                       let segments = data_segments(child, &chain_of(conn, tenant, child)?);
+                       copies.push(stitched_rows(conn, tenant, &segments[1..], 0)?);
+                     }
+                     for (child, rows) in survivors.iter().zip(&copies) {
+                       let child_id = child.id.to_string();
+                       copy_inherited(conn, tenant, &child_id, rows, child.parent_cursor.unwrap_or(0))?;
+                       diesel::update(flows_dsl::flows.filter(flows_dsl::id.eq(&child_id))
+                                                      .filter(flows_dsl::tenant_id.eq(tenant)))
+                         .set((flows_dsl::parent_flow_id.eq::<Option<String>>(None),
+                               flows_dsl::parent_cursor.eq::<Option<i64>>(None)))
+                         .execute(conn)?;
+                     }
+                     diesel::delete(data_dsl::flow_data.filter(data_dsl::tenant_id.eq(tenant))
+                                                       .filter(data_dsl::flow_id.eq_any(&flow_ids))).execute(conn)?;
+                     diesel::delete(snap_dsl::snapshots.filter(snap_dsl::tenant_id.eq(tenant))
+                                                       .filter(snap_dsl::flow_id.eq_any(&flow_ids))).execute(conn)?;
+                     diesel::delete(refs_dsl::artifact_refs.filter(refs_dsl::tenant_id.eq(tenant))
+                                                           .filter(refs_dsl::flow_id.eq_any(&flow_ids))).execute(conn)?;
+                     diesel::delete(work_dsl::work_queue.filter(work_dsl::tenant_id.eq(tenant))
+                                                        .filter(work_dsl::flow_id.eq_any(&flow_ids))).execute(conn)?;
+                     let expired = trunc_dsl::flow_id.eq_any(&flow_ids).or(trunc_dsl::deleted_at_ts.le(cutoff));
+                     diesel::delete(trunc_dsl::flow_truncations.filter(trunc_dsl::tenant_id.eq(tenant)).filter(expired))
+                       .execute(conn)?;
+                     diesel::delete(flows_dsl::flows.filter(flows_dsl::tenant_id.eq(tenant))
+                                                    .filter(flows_dsl::id.eq_any(&flow_ids))).execute(conn)?;
+                     let truncated: Vec<(String, i64)> = data_dsl::flow_data.filter(data_dsl::tenant_id.eq(tenant))
+                                                                             .filter(data_dsl::deleted_at_ts.le(cutoff))
+                                                                             .select((data_dsl::flow_id, data_dsl::cursor))
+                                                                             .distinct()
+                                                                             .load(conn)?;
+                     let records = diesel::delete(data_dsl::flow_data.filter(data_dsl::tenant_id.eq(tenant))
+                                                                     .filter(data_dsl::deleted_at_ts.le(cutoff))).execute(conn)?;
+                     for (fid, cursor) in truncated {
+                       diesel::delete(refs_dsl::artifact_refs.filter(refs_dsl::tenant_id.eq(tenant))
+                                                             .filter(refs_dsl::flow_id.eq(&fid).and(refs_dsl::cursor.eq(cursor))))
+                         .execute(conn)?;
+                       for row in data_dsl::flow_data.filter(data_dsl::tenant_id.eq(tenant))
+                                                     .filter(data_dsl::flow_id.eq(&fid).and(data_dsl::cursor.eq(cursor)))
+                                                     .load::<FlowDataRow>(conn)?
+                       {
+                         insert_artifact_refs(conn, tenant, &flow_data_from_row(row))?;
+                       }
+                     }
+                     let snapshots = diesel::delete(snap_dsl::snapshots.filter(snap_dsl::tenant_id.eq(tenant))
+                                                                       .filter(snap_dsl::deleted_at_ts.le(cutoff))).execute(conn)?;
+                     Ok(PurgeReport { flows: flow_ids.len(), records, snapshots })
+                   }))
+  }
+  /// Las referencias a artifacts del registro se recalculan con la nueva
+  /// `metadata`, a nombre del flow que guarda la fila (un registro leído
+  /// desde una rama puede ser de un antepasado).
+  fn rewrite_data(&self, rows: &[FlowData]) -> FlowResult<usize> {
+    use schema::artifact_refs::dsl as refs_dsl;
+    let mut conn = self.conn()?;
+    let mut missing = None;
+    let res = conn.transaction::<usize, DieselError, _>(|conn| {
+                    for row in rows {
+                      let target = data_dsl::flow_data.filter(data_dsl::id.eq(row.id.to_string()))
+                                                      .filter(data_dsl::tenant_id.eq(&self.tenant))
+                                                      .filter(data_dsl::deleted_at_ts.is_null())
+                                                      .select((data_dsl::flow_id, data_dsl::cursor))
+                                                      .first::<(String, i64)>(conn)
+                                                      .optional()?;
+                      let Some((fid, cursor)) = target else {
+                        missing = Some(row.id);
+                        return Err(DieselError::RollbackTransaction);
+                      };
+                      diesel::update(data_dsl::flow_data.filter(data_dsl::id.eq(row.id.to_string()))
+                                                        .filter(data_dsl::tenant_id.eq(&self.tenant)))
+                        .set((data_dsl::payload.eq(JsonText(row.payload.to_string())),
+                              data_dsl::metadata.eq(JsonText(row.metadata.to_string()))))
+                        .execute(conn)?;
+                      diesel::delete(refs_dsl::artifact_refs.filter(refs_dsl::tenant_id.eq(&self.tenant))
+                                                            .filter(refs_dsl::flow_id.eq(&fid).and(refs_dsl::cursor.eq(cursor))))
+                        .execute(conn)?;
+                      let flow_id = Uuid::parse_str(&fid).unwrap_or(row.flow_id);
+                      insert_artifact_refs(conn, &self.tenant, &FlowData { flow_id, cursor, ..row.clone() })?;
+                    }
+                    Ok(rows.len())
+                  });
+    match (res, missing) {
+      (Err(DieselError::RollbackTransaction), Some(id)) => Err(FlowError::NotFound(format!("flow_data {}", id))),
+      (res, _) => res.map_err(|e| FlowError::Storage(format!("db txn: {}", e))),
+    }
+  }
+  fn lock_for_update(&self, flow_id: &Uuid, expected_version: i64, timeout: Duration) -> FlowResult<Option<FlowLockGuard>> {
+    self.outside_unit_of_work("lock_for_update")?;
+    let fid = flow_id.to_string();
+    let version_of = |repo: &Self| -> FlowResult<Option<i64>> {
+      let mut conn = repo.conn()?;
+      map_db_err(flows_dsl::flows.filter(flows_dsl::id.eq(&fid))
+                                 .filter(flows_dsl::tenant_id.eq(&repo.tenant))
+                                 .filter(flows_dsl::deleted_at_ts.is_null())
+                                 .select(flows_dsl::current_version)
+                                 .first::<i64>(&mut *conn)
+                                 .optional())
+    };
+    if version_of(self)?.is_none() {
+      return Err(FlowError::NotFound(format!("flow {}", flow_id)));
+    }
+    let Some(guard) = self.acquire_flow_lock(flow_id, timeout)? else {
+      return Err(FlowError::Conflict(format!("timeout esperando lock del flow {}", flow_id)));
+    };
+    // Con el lock tomado, verificar la versión; si no coincide el guard se
+    // suelta aquí y libera el lock.
+    Ok(if version_of(self)? == Some(expected_version) { Some(guard) } else { None })
+  }
+  fn enqueue_work(&self, flow_id: &Uuid) -> FlowResult<()> {
+    self.outside_unit_of_work("enqueue_work")?;
+    let mut conn = self.conn()?;
+    let fid = flow_id.to_string();
+    if !flow_is_live(&mut conn, &self.tenant, &fid)? {
+      return Err(FlowError::NotFound(format!("flow {}", flow_id)));
     }
+    let row = WorkQueueRow { flow_id: fid,
+                             worker_id: None,
+                             lease_expires_at_ms: None,
+                             attempts: 0,
+                             enqueued_at_ms: Utc::now().timestamp_millis(),
+                             tenant_id: self.tenant.clone() };
+    map_db_err(diesel::insert_into(work_dsl::work_queue).values(&row)
+                                                        .on_conflict(work_dsl::flow_id)
+                                                        .do_nothing()
+                                                        .execute(&mut *conn))?;
     Ok(())
   }
-  fn lock_for_update(&self, _flow_id: &Uuid, _expected_version: i64) -> FlowResult<bool> {
-    Ok(true)
+  fn claim_work(&self, worker_id_in: &str) -> FlowResult<Option<WorkItem>> {
+    self.outside_unit_of_work("claim_work")?;
+    let mut conn = self.conn()?;
+    let now_ms = Utc::now().timestamp_millis();
+    let lease_until = now_ms + self.lease_ms;
+    let claimed = claim_transaction(&mut conn, |conn| {
+                    let Some(row) = next_claimable(conn, &self.tenant, now_ms)? else {
+                      return Ok(None);
+                    };
+                    diesel::update(work_dsl::work_queue.filter(work_dsl::flow_id.eq(&row.flow_id))
+                                                       .filter(work_dsl::tenant_id.eq(&self.tenant)))
+                      .set((work_dsl::worker_id.eq(Some(worker_id_in)),
+                            work_dsl::lease_expires_at_ms.eq(Some(lease_until)),
+                            work_dsl::attempts.eq(row.attempts + 1)))
+                      .execute(conn)?;
+                    Ok(Some((row.flow_id, row.attempts + 1)))
+                  }).map_err(|e| FlowError::Storage(format!("db txn: {}", e)))?;
+    let Some((fid, attempts)) = claimed else {
+      return Ok(None);
+    };
+    let flow_uuid = Uuid::parse_str(&fid).map_err(|e| FlowError::Storage(format!("work_queue flow_id: {}", e)))?;
+    let last_cursor = map_db_err(flows_dsl::flows.filter(flows_dsl::id.eq(&fid))
+                                                 .filter(flows_dsl::tenant_id.eq(&self.tenant))
+                                                 .select(flows_dsl::current_cursor)
+                                                 .first::<i64>(&mut *conn)
+                                                 .optional())?.unwrap_or(0);
+    drop(conn);
+    let snapshot_ptr = self.load_latest_snapshot(&flow_uuid)?.map(|s| s.state_ptr);
+    Ok(Some(WorkItem { flow_id: flow_uuid,
+                       last_cursor,
+                       snapshot_ptr,
+                       worker_id: worker_id_in.to_string(),
+                       lease_expires_at: Utc.timestamp_millis_opt(lease_until).single().unwrap_or(Utc::now()),
+                       attempts }))
   }
-  fn claim_work(&self, _worker_id: &str) -> FlowResult<Option<WorkItem>> {
-    Ok(None)
+  fn heartbeat_work(&self, flow_id: &Uuid, worker_id_in: &str) -> FlowResult<bool> {
+    self.outside_unit_of_work("heartbeat_work")?;
+    let mut conn = self.conn()?;
+    let fid = flow_id.to_string();
+    let lease_until = Utc::now().timestamp_millis() + self.lease_ms;
+    let n = map_db_err(diesel::update(work_dsl::work_queue.filter(work_dsl::flow_id.eq(&fid)
+                                                                          .and(work_dsl::tenant_id.eq(&self.tenant))
+                                                                          .and(work_dsl::worker_id.eq(worker_id_in))))
+                         .set(work_dsl::lease_expires_at_ms.eq(Some(lease_until)))
+                         .execute(&mut *conn))?;
+    Ok(n > 0)
+  }
+  fn complete_work(&self, flow_id: &Uuid, worker_id_in: &str) -> FlowResult<bool> {
+    self.outside_unit_of_work("complete_work")?;
+    let mut conn = self.conn()?;
+    let fid = flow_id.to_string();
+    let n = map_db_err(diesel::delete(work_dsl::work_queue.filter(work_dsl::flow_id.eq(&fid)
+                                                                          .and(work_dsl::tenant_id.eq(&self.tenant))
+                                                                          .and(work_dsl::worker_id.eq(worker_id_in))))
+                         .execute(&mut *conn))?;
+    Ok(n > 0)
   }
 }
+/// Delegan en el `snapshot_store` configurado.
 impl SnapshotStore for DieselFlowRepository {
   fn save(&self, state: &[u8]) -> FlowResult<String> {
-    let key = format!("{}.bin", Uuid::new_v4());
-    let path = Path::new(&self.snapshot_dir).join(&key);
-    fs::write(&path, state).map_err(|e| FlowError::Storage(e.to_string()))?;
-    Ok(key)
+    self.snapshot_store.save(state)
   }
   fn load(&self, key: &str) -> FlowResult<Vec<u8>> {
-    let path = Path::new(&self.snapshot_dir).join(key);
-    fs::read(&path).map_err(|e| FlowError::Storage(e.to_string()))
+    self.snapshot_store.load(key)
+  }
+  fn delete(&self, key: &str) -> FlowResult<()> {
+    self.snapshot_store.delete(key)
+  }
+  fn list(&self) -> FlowResult<Vec<StoredBlob>> {
+    self.snapshot_store.list()
   }
 }
+impl DieselFlowRepository {
+  /// Ruta del blob de `key`; rechaza lo que no sea una key de artifact.
+  fn artifact_path(&self, key: &str) -> FlowResult<PathBuf> {
+    if !is_artifact_key(key) {
+      return Err(FlowError::NotFound(format!("artifact {}", key)));
+    }
+    Ok(self.artifact_dir.join(key))
+  }
+  /// Escribe el blob si aún no existe (fichero temporal + `rename`, para no
+  /// dejar nunca un blob a medias con el nombre definitivo).
+  fn write_artifact_blob(&self, key: &str, blob: &[u8]) -> FlowResult<()> {
+    let path = self.artifact_path(key)?;
+    if path.exists() {
+      return Ok(());
+    }
+    let tmp = self.artifact_dir.join(format!(".{}.{}.tmp", key, Uuid::new_v4()));
+    fs::write(&tmp, blob).and_then(|_| fs::rename(&tmp, &path)).map_err(|e| {
+                                                                 fs::remove_file(&tmp).ok();
+                                                                 FlowError::Storage(format!("{}: {}", path.display(), e))
+                                                               })
+  }
+  /// Ejecuta `f` en la sección crítica de `key` (`artifact_transaction`):
+  /// la fila de `artifacts` y el fichero cambian juntos, así que `gc_artifacts`
+  /// no puede borrar un fichero que un `put` concurrente acaba de registrar.
+  /// Un error de `f` en `io_err` deshace la transacción y se devuelve tal cual.
+  fn in_artifact_transaction<T, F>(&self, key: &str, f: F) -> FlowResult<T>
+    where F: FnOnce(&mut DbConn, &mut Option<FlowError>) -> std::result::Result<T, DieselError>
+  {
+    let mut io_err = None;
+    let res = {
+      let mut conn = self.conn()?;
+      artifact_transaction(&mut conn, key, self.shared.is_some(), |conn| f(conn, &mut io_err))
+    };
+    match (res, io_err) {
+      (Err(_), Some(e)) => Err(e),
+      (res, _) => res.map_err(|e| FlowError::Storage(format!("db txn: {}", e))),
+    }
+  }
+  /// Ajusta `ref_count` de `key` (sin bajar de cero);
+  /// `Err(FlowError::NotFound)` si no está registrado. El `UPDATE` es el que
+  /// decide: una fila que `gc_artifacts` borra a la vez no cuenta como
+  /// retenida.
+  fn bump_artifact_refs(&self, key: &str, delta: i64) -> FlowResult<()> {
+    use schema::artifacts::dsl as art_dsl;
+    let mut conn = self.conn()?;
+    let target = art_dsl::artifacts.filter(art_dsl::tenant_id.eq(&self.tenant))
+                                   .filter(art_dsl::key.eq(key).and((art_dsl::ref_count + delta).ge(0)));
+    let updated =
+      map_db_err(diesel::update(target).set(art_dsl::ref_count.eq(art_dsl::ref_count + delta)).execute(&mut *conn))?;
+    if updated > 0 {
+      return Ok(());
+    }
+    // sin fila actualizada: o no está registrado o ya estaba a cero
+    let exists = map_db_err(art_dsl::artifacts.filter(art_dsl::tenant_id.eq(&self.tenant))
+                                              .filter(art_dsl::key.eq(key))
+                                              .count()
+                                              .get_result::<i64>(&mut *conn))?;
+    if exists == 0 {
+      return Err(FlowError::NotFound(format!("artifact {}", key)));
+    }
+    Ok(())
+  }
+}
+/// Blobs en `artifact_dir` con nombre `<sha256>`; la tabla `artifacts` guarda
+/// el contador de retenciones y `artifact_refs` las referencias desde
+/// `flow_data`. El directorio es común a todos los tenants (un mismo
+/// contenido se guarda una vez), pero cada tenant solo ve las keys que tiene
+/// registradas en `artifacts`.
 impl ArtifactStore for DieselFlowRepository {
   fn put(&self, blob: &[u8]) -> FlowResult<String> {
-    let key = format!("{}.bin", Uuid::new_v4());
-    let path = Path::new(&self.artifact_dir).join(&key);
-    fs::write(&path, blob).map_err(|e| FlowError::Storage(e.to_string()))?;
+    use schema::artifacts::dsl as art_dsl;
+    let key = artifact_key(blob);
+    let now_ts = Utc::now().timestamp();
+    // la fila primero y el fichero después, dentro de la misma sección
+    // crítica: si el fichero ya existe, nadie lo borra antes del commit
+    self.in_artifact_transaction(&key, |conn, io_err| {
+          diesel::insert_into(art_dsl::artifacts).values((art_dsl::key.eq(&key),
+                                                          art_dsl::size.eq(blob.len() as i64),
+                                                          art_dsl::ref_count.eq(0),
+                                                          art_dsl::last_put_ts.eq(now_ts),
+                                                          art_dsl::tenant_id.eq(&self.tenant)))
+                                                 .on_conflict((art_dsl::tenant_id, art_dsl::key))
+                                                 .do_update()
+                                                 .set(art_dsl::last_put_ts.eq(now_ts))
+                                                 .execute(conn)?;
+          self.write_artifact_blob(&key, blob).map_err(|e| {
+                                                *io_err = Some(e);
+                                                DieselError::RollbackTransaction
+                                              })
+        })?;
     Ok(key)
   }
   fn get(&self, key: &str) -> FlowResult<Vec<u8>> {
-    let path = Path::new(&self.artifact_dir).join(key);
-    fs::read(&path).map_err(|e| FlowError::Storage(e.to_string()))
+    use schema::artifacts::dsl as art_dsl;
+    let path = self.artifact_path(key)?;
+    let registered = {
+      let mut conn = self.conn()?;
+      map_db_err(art_dsl::artifacts.filter(art_dsl::tenant_id.eq(&self.tenant))
+                                   .filter(art_dsl::key.eq(key))
+                                   .count()
+                                   .get_result::<i64>(&mut *conn))?
+    };
+    if registered == 0 {
+      return Err(FlowError::NotFound(format!("artifact {}", key)));
+    }
+    let blob = match fs::read(path) {
+      Ok(blob) => blob,
+      Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Err(FlowError::NotFound(format!("artifact {}", key))),
+      Err(e) => return Err(FlowError::Storage(e.to_string())),
+    };
+    verify_artifact(key, &blob)?;
+    Ok(blob)
   }
   fn copy_if_needed(&self, src_key: &str) -> FlowResult<String> {
-    let new_key = format!("{}.bin", Uuid::new_v4());
-    let src_path = Path::new(&self.artifact_dir).join(src_key);
-    let dest_path = Path::new(&self.artifact_dir).join(&new_key);
-    fs::copy(&src_path, &dest_path).map_err(|e| FlowError::Storage(e.to_string()))?;
-    Ok(new_key)
+    self.bump_artifact_refs(src_key, 1)?;
+    Ok(src_key.to_string())
+  }
+  fn release(&self, key: &str) -> FlowResult<()> {
+    self.bump_artifact_refs(key, -1)
+  }
+  /// Las filas se borran una a una con la condición repetida, de modo que un
+  /// `put` o `copy_if_needed` concurrente las mantiene; el fichero se borra
+  /// solo si la fila se borró y ningún otro tenant tiene fila para esa key.
+  /// También borra los ficheros sin fila en ningún tenant (p. ej. de un `put`
+  /// interrumpido) con más antigüedad que `grace`. Cada borrado comprueba las
+  /// filas y desenlaza el fichero en la sección crítica de su key, la misma
+  /// que usa `put`.
+  fn gc_artifacts(&self, grace: Duration) -> FlowResult<usize> {
+    use schema::artifact_refs::dsl as refs_dsl;
+    use schema::artifacts::dsl as art_dsl;
+    let cutoff_ts = Utc::now().timestamp().saturating_sub(grace.as_secs() as i64);
+    let unreferenced = || {
+      art_dsl::tenant_id.eq(self.tenant.clone())
+                        .and(art_dsl::ref_count.le(0))
+                        .and(art_dsl::last_put_ts.le(cutoff_ts))
+                        .and(diesel::dsl::not(diesel::dsl::exists(refs_dsl::artifact_refs.filter(
+          refs_dsl::tenant_id.eq(art_dsl::tenant_id).and(refs_dsl::artifact_key.eq(art_dsl::key)),
+        ))))
+    };
+    // `known` abarca todos los tenants; solo descarta de antemano los
+    // ficheros registrados, cada borrado vuelve a mirar las filas
+    let (known, candidates) = {
+      let mut conn = self.conn()?;
+      let known: HashSet<String> =
+        map_db_err(art_dsl::artifacts.select(art_dsl::key).distinct().load::<String>(&mut *conn))?.into_iter().collect();
+      let candidates = map_db_err(art_dsl::artifacts.filter(unreferenced()).select(art_dsl::key).load::<String>(&mut *conn))?;
+      (known, candidates)
+    };
+    let mut removed = 0;
+    for key in candidates {
+      let deleted = self.in_artifact_transaction(&key, |conn, _| {
+                          let rows = art_dsl::artifacts.filter(art_dsl::key.eq(&key));
+                          let deleted = diesel::delete(rows.filter(unreferenced())).execute(conn)?;
+                          let shared = art_dsl::artifacts.filter(art_dsl::key.eq(&key)).count().get_result::<i64>(conn)? > 0;
+                          if deleted > 0 && !shared {
+                            fs::remove_file(self.artifact_dir.join(&key)).ok();
+                          }
+                          Ok(deleted)
+                        })?;
+      if deleted > 0 {
+        removed += 1;
+      }
+    }
+    let cutoff = std::time::SystemTime::now().checked_sub(grace).unwrap_or(std::time::UNIX_EPOCH);
+    let io_err = |e: std::io::Error| FlowError::Storage(format!("{}: {}", self.artifact_dir.display(), e));
+    for entry in fs::read_dir(&self.artifact_dir).map_err(io_err)? {
+      let entry = entry.map_err(io_err)?;
+      let name = entry.file_name().to_string_lossy().to_string();
+      if !is_artifact_key(&name) || known.contains(&name) {
+        continue;
+      }
+      let modified = entry.metadata().and_then(|m| m.modified()).map_err(io_err)?;
+      if modified > cutoff {
+        continue;
+      }
+      let orphan = self.in_artifact_transaction(&name, |conn, _| {
+                         let registered = art_dsl::artifacts.filter(art_dsl::key.eq(&name)).count().get_result::<i64>(conn)?;
+                         Ok(registered == 0 && fs::remove_file(entry.path()).is_ok())
+                       })?;
+      if orphan {
+        removed += 1;
+      }
+    }
+    Ok(removed)
   }
 }
@@ -0,0 +1,130 @@
+//! Columnas JSON (`schema::sql_types::JsonDoc`) y traducción de
+//! `flow::json_predicate::JsonPredicate` a SQL.
+//!
+//! `JsonText` es el texto JSON que Diesel lee y escribe en esas columnas:
+//! en Postgres viaja en el formato binario de `jsonb` (versión 1 + texto) y
+//! en SQLite como texto. `json_sql` produce la condición de un predicado
+//! para cada dialecto; el backend Diesel la usa con `diesel_filter` y el
+//! async con sus placeholders `$n`.
+use crate::schema::sql_types::JsonDoc;
+use diesel::deserialize::{self, FromSql, FromSqlRow};
+use diesel::expression::AsExpression;
+use diesel::pg::{Pg, PgValue};
+use diesel::serialize::{self, IsNull, Output, ToSql};
+use diesel::sql_types::{Bool, Text};
+use diesel::sqlite::{Sqlite, SqliteValue};
+use diesel::BoxableExpression;
+use flow::json_predicate::{JsonOp, JsonPredicate};
+use std::io::Write;
+use std::ops::Deref;
+/// Versión del formato binario de `jsonb` que envía y espera Postgres.
+const JSONB_VERSION: u8 = 1;
+#[cfg(all(feature = "pg", not(test)))]
+pub(crate) type DbBackend = Pg;
+#[cfg(any(test, not(feature = "pg")))]
+pub(crate) type DbBackend = Sqlite;
+/// Dialecto de los filtros del backend Diesel compilado.
+const DIESEL_PG: bool = cfg!(all(feature = "pg", not(test)));
+/// Texto JSON de una columna `JsonDoc`.
+#[derive(Debug, Clone, PartialEq, AsExpression, FromSqlRow)]
+#[diesel(sql_type = JsonDoc)]
+pub(crate) struct JsonText(pub String);
+impl Deref for JsonText {
+  type Target = str;
+  fn deref(&self) -> &str {
+    &self.0
+  }
+}
+impl ToSql<JsonDoc, Pg> for JsonText {
+  fn to_sql<'b>(&'b self, out: &mut Output<'b, '_, Pg>) -> serialize::Result {
+    out.write_all(&[JSONB_VERSION])?;
+    out.write_all(self.0.as_bytes())?;
+    Ok(IsNull::No)
+  }
+}
+impl FromSql<JsonDoc, Pg> for JsonText {
+  fn from_sql(value: PgValue<'_>) -> deserialize::Result<Self> {
+    match value.as_bytes().split_first() {
+      Some((&JSONB_VERSION, text)) => Ok(JsonText(String::from_utf8(text.to_vec())?)),
+      _ => Err("jsonb con versión de formato desconocida".into()),
+    }
+  }
+}
+impl ToSql<JsonDoc, Sqlite> for JsonText {
+  fn to_sql<'b>(&'b self, out: &mut Output<'b, '_, Sqlite>) -> serialize::Result {
+    out.set_value(self.0.as_str());
+    Ok(IsNull::No)
+  }
+}
+impl FromSql<JsonDoc, Sqlite> for JsonText {
+  fn from_sql(value: SqliteValue<'_, '_, '_>) -> deserialize::Result<Self> {
+    <String as FromSql<Text, Sqlite>>::from_sql(value).map(JsonText)
+  }
+}
+/// Condición SQL de un predicado: `sql[0] $1 sql[1] $2 ... sql[n]`, con los
+/// `n` parámetros de texto de `params`.
+pub(crate) struct JsonSql {
+  pub sql: Vec<String>,
+  pub params: Vec<String>,
+}
+/// Traduce `pred` sobre la columna `pred.column()` (ya validada por el
+/// llamador, que solo admite nombres fijos). La ruta y el valor van como
+/// parámetros.
+///
+/// - Postgres: `col @> '{"a":{"b":v}}' AND col #> '{a,b}' = v`; la contención
+///   usa el índice GIN y la igualdad descarta los arrays que solo contienen
+///   `v`.
+/// - SQLite: `json_type` y `json_extract` en la ruta iguales a los de `v` (`IS`
+///   para que `null` sea igual a `null`).
+///
+/// `!=` es la negación de `==`, de modo que también se cumple cuando la
+/// ruta no existe.
+pub(crate) fn json_sql(pred: &JsonPredicate, pg: bool) -> JsonSql {
+  let col = pred.column();
+  let value = pred.value().to_string();
+  let (mut sql, params) = if pg {
+    (vec![format!("({} @> ", col),
+          format!("::text::jsonb AND {} #> ", col),
+          "::text::text[] = ".to_string(),
+          "::text::jsonb)".to_string()],
+     vec![pred.containment().to_string(), pred.pg_path(), value])
+  } else {
+    let path = pred.sqlite_path();
+    (vec![format!("(json_type({}, ", col),
+          ") = json_type(".to_string(),
+          format!(", '$') AND json_extract({}, ", col),
+          ") IS json_extract(".to_string(),
+          ", '$'))".to_string()],
+     vec![path.clone(), value.clone(), path, value])
+  };
+  if pred.op() == JsonOp::Ne {
+    sql[0].insert_str(0, "NOT COALESCE(");
+    if let Some(last) = sql.last_mut() {
+      last.push_str(", FALSE)");
+    }
+  }
+  JsonSql { sql, params }
+}
+/// `json_sql` como filtro de una consulta Diesel en caja. Cada dialecto
+/// tiene un número fijo de parámetros.
+pub(crate) fn diesel_filter<QS>(pred: &JsonPredicate) -> Box<dyn BoxableExpression<QS, DbBackend, SqlType = Bool>> {
+  use diesel::dsl::sql;
+  let JsonSql { sql: s, params: p } = json_sql(pred, DIESEL_PG);
+  match (s.as_slice(), p.as_slice()) {
+    ([a, b, c, d], [p, q, r]) => Box::new(sql::<Bool>(a).bind::<Text, _>(p.clone())
+                                                        .sql(b)
+                                                        .bind::<Text, _>(q.clone())
+                                                        .sql(c)
+                                                        .bind::<Text, _>(r.clone())
+                                                        .sql(d)),
+    ([a, b, c, d, e], [p, q, r, t]) => Box::new(sql::<Bool>(a).bind::<Text, _>(p.clone())
+                                                              .sql(b)
+                                                              .bind::<Text, _>(q.clone())
+                                                              .sql(c)
+                                                              .bind::<Text, _>(r.clone())
+                                                              .sql(d)
+                                                              .bind::<Text, _>(t.clone())
+                                                              .sql(e)),
+    _ => unreachable!("json_sql devuelve 3 parámetros en Postgres y 4 en SQLite"),
+  }
+}
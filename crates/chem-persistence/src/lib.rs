@@ -1,11 +1,41 @@
 //! Implementación mínima de persistencia para el trait `FlowRepository`.
 //! Este archivo expone el módulo `schema` y reexporta el repositorio Diesel
 //! que implementa los traits de persistencia del dominio. La implementación
-//! detallada está en `domain_persistence.rs`.
+//! detallada está en `domain_persistence.rs`. Con la feature `async-pg` se
+//! expone además `AsyncPgFlowRepository`, un backend Postgres nativo async.
+//! Los blobs de snapshot se guardan en un `SnapshotStore` intercambiable
+//! (`snapshot_store.rs`; `S3SnapshotStore` con la feature `s3`). La
+//! auditoría (`DbAuditLog`, `AuditedDomainRepository`) está en `audit_log.rs`
+//! y la autorización por principal del dominio en `principal_domain.rs`.
+//! `unit_of_work.rs` comparte una transacción entre los repositorios de
+//! flows y de dominio.
+//! Cada repositorio trabaja sobre las filas de un tenant (`new_for_tenant`;
+//! `DEFAULT_TENANT` para los constructores sin tenant).
+#[cfg(feature = "async-pg")]
+mod async_flow_persistence;
+mod audit_log;
 mod domain_persistence;
 mod flow_persistence;
+mod json_query;
+mod principal_domain;
 pub mod schema;
+mod snapshot_store;
+mod unit_of_work;
 #[cfg(not(feature = "pg"))]
 pub use domain_persistence::new_sqlite_for_test;
-pub use domain_persistence::{new_domain_repo_from_env, new_from_env as new_domain_from_env, DieselDomainRepository};
-pub use flow_persistence::{new_from_env as new_flow_from_env, DieselFlowRepository};
+#[cfg(feature = "async-pg")]
+pub use async_flow_persistence::AsyncPgFlowRepository;
+pub use audit_log::{AuditedDomainRepository, DbAuditLog};
+pub use domain_persistence::{
+  new_domain_repo_from_env, new_from_env as new_domain_from_env, new_from_env_for_tenant as new_domain_from_env_for_tenant,
+  DieselDomainRepository,
+};
+pub use flow_persistence::{
+  new_from_env as new_flow_from_env, new_from_env_for_tenant as new_flow_from_env_for_tenant, DieselFlowRepository,
+  DEFAULT_TENANT,
+};
+pub use principal_domain::PrincipalDomainRepository;
+pub use snapshot_store::DbSnapshotStore;
+#[cfg(feature = "s3")]
+pub use snapshot_store::{S3Config, S3SnapshotStore};
+pub use unit_of_work::{DbConnGuard, DieselUnitOfWork, InMemoryUnitOfWork, UnitOfWork, UnitOfWorkScope};
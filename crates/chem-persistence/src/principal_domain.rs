@@ -0,0 +1,106 @@
+//! `PrincipalDomainRepository`: ejecuta las llamadas de un `DomainRepository`
+//! en nombre de un `flow::Principal`, con el mismo `Authorizer` que
+//! `flow::PrincipalFlowRepository` (vive aquí porque `chem-domain` no depende
+//! de `flow`).
+use chem_domain::{DomainError, DomainRepository, Molecule, MoleculeFamily, OwnedFamilyProperty, OwnedMolecularProperty};
+use flow::errors::FlowError;
+use flow::principal::{Authorizer, Principal};
+use std::sync::Arc;
+use uuid::Uuid;
+/// `DomainRepository` que consulta `authorizer` antes de cada llamada a
+/// `inner`, con el nombre del método como operación (sin `flow_id`). Un
+/// rechazo se devuelve como `DomainError::Forbidden`.
+///
+/// Los guardados en bloque se autorizan como su versión de un elemento
+/// (`save_molecule`, `save_molecular_property`), para que las reglas
+/// existentes también los cubran.
+pub struct PrincipalDomainRepository {
+  inner: Arc<dyn DomainRepository>,
+  principal: Principal,
+  authorizer: Arc<dyn Authorizer>,
+}
+impl PrincipalDomainRepository {
+  pub fn new(inner: Arc<dyn DomainRepository>, principal: Principal, authorizer: Arc<dyn Authorizer>) -> Self {
+    Self { inner, principal, authorizer }
+  }
+  pub fn principal(&self) -> &Principal {
+    &self.principal
+  }
+  fn check(&self, operation: &str) -> Result<(), DomainError> {
+    self.authorizer.authorize(&self.principal, operation, None).map_err(|e| match e {
+                                                                 FlowError::Forbidden(msg) => DomainError::Forbidden(msg),
+                                                                 other => DomainError::ExternalError(other.to_string()),
+                                                               })
+  }
+}
+impl DomainRepository for PrincipalDomainRepository {
+  fn rewrap(&self, repo: Arc<dyn DomainRepository>) -> Arc<dyn DomainRepository> {
+    Arc::new(PrincipalDomainRepository::new(self.inner.rewrap(repo), self.principal.clone(), self.authorizer.clone()))
+  }
+  fn save_family(&self, family: MoleculeFamily) -> Result<Uuid, DomainError> {
+    self.check("save_family")?;
+    self.inner.save_family(family)
+  }
+  fn get_family(&self, id: &Uuid) -> Result<Option<MoleculeFamily>, DomainError> {
+    self.check("get_family")?;
+    self.inner.get_family(id)
+  }
+  fn save_molecule(&self, molecule: Molecule) -> Result<String, DomainError> {
+    self.check("save_molecule")?;
+    self.inner.save_molecule(molecule)
+  }
+  fn save_molecules_bulk(&self, molecules: Vec<Molecule>) -> Result<Vec<Result<String, DomainError>>, DomainError> {
+    self.check("save_molecule")?;
+    self.inner.save_molecules_bulk(molecules)
+  }
+  fn get_molecule(&self, inchikey: &str) -> Result<Option<Molecule>, DomainError> {
+    self.check("get_molecule")?;
+    self.inner.get_molecule(inchikey)
+  }
+  fn list_families(&self) -> Result<Vec<MoleculeFamily>, DomainError> {
+    self.check("list_families")?;
+    self.inner.list_families()
+  }
+  fn save_family_property(&self, prop: OwnedFamilyProperty) -> Result<Uuid, DomainError> {
+    self.check("save_family_property")?;
+    self.inner.save_family_property(prop)
+  }
+  fn get_family_properties(&self, family_id: &Uuid) -> Result<Vec<OwnedFamilyProperty>, DomainError> {
+    self.check("get_family_properties")?;
+    self.inner.get_family_properties(family_id)
+  }
+  fn save_molecular_property(&self, prop: OwnedMolecularProperty) -> Result<Uuid, DomainError> {
+    self.check("save_molecular_property")?;
+    self.inner.save_molecular_property(prop)
+  }
+  fn save_molecular_properties_bulk(&self,
+                                    props: Vec<OwnedMolecularProperty>)
+                                    -> Result<Vec<Result<Uuid, DomainError>>, DomainError> {
+    self.check("save_molecular_property")?;
+    self.inner.save_molecular_properties_bulk(props)
+  }
+  fn get_molecular_properties(&self, inchikey: &str) -> Result<Vec<OwnedMolecularProperty>, DomainError> {
+    self.check("get_molecular_properties")?;
+    self.inner.get_molecular_properties(inchikey)
+  }
+  fn list_molecules(&self) -> Result<Vec<Molecule>, DomainError> {
+    self.check("list_molecules")?;
+    self.inner.list_molecules()
+  }
+  fn delete_molecule(&self, inchikey: &str) -> Result<(), DomainError> {
+    self.check("delete_molecule")?;
+    self.inner.delete_molecule(inchikey)
+  }
+  fn delete_family(&self, id: &Uuid) -> Result<(), DomainError> {
+    self.check("delete_family")?;
+    self.inner.delete_family(id)
+  }
+  fn add_molecule_to_family(&self, family_id: &Uuid, molecule: Molecule) -> Result<Uuid, DomainError> {
+    self.check("add_molecule_to_family")?;
+    self.inner.add_molecule_to_family(family_id, molecule)
+  }
+  fn remove_molecule_from_family(&self, family_id: &Uuid, inchikey: &str) -> Result<Uuid, DomainError> {
+    self.check("remove_molecule_from_family")?;
+    self.inner.remove_molecule_from_family(family_id, inchikey)
+  }
+}
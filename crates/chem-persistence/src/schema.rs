@@ -1,7 +1,19 @@
 // Simplified Diesel schema for SQLite used in tests.
-// Tablas: flows, flow_data, snapshots
+// Tablas: flows, flow_data, snapshots, work_queue, flow_locks, snapshot_blobs,
+// artifacts, artifact_refs. Todas llevan `tenant_id` (migración
+// 00000000000011_add_tenant_id). Las columnas JSON consultables son
+// `JsonDoc`: JSONB en Postgres (`migrations_pg`) y texto en SQLite.
 use diesel::allow_tables_to_appear_in_same_query;
+pub mod sql_types {
+  /// Documento JSON: `jsonb` en Postgres, texto JSON en SQLite.
+  #[derive(diesel::sql_types::SqlType, diesel::query_builder::QueryId)]
+  #[diesel(postgres_type(oid = 3802, array_oid = 3807))]
+  #[diesel(sqlite_type(name = "Text"))]
+  pub struct JsonDoc;
+}
 diesel::table! {
+    use diesel::sql_types::*;
+    use super::sql_types::JsonDoc;
     flows (id) {
         id -> Text,
         name -> Nullable<Text>,
@@ -12,19 +24,26 @@ diesel::table! {
         current_version -> BigInt,
         parent_flow_id -> Nullable<Text>,
         parent_cursor -> Nullable<BigInt>,
-        metadata -> Text,
+        metadata -> JsonDoc,
+        deleted_at_ts -> Nullable<BigInt>,
+        tenant_id -> Text,
     }
 }
 diesel::table! {
+    use diesel::sql_types::*;
+    use super::sql_types::JsonDoc;
     flow_data (id) {
         id -> Text,
         flow_id -> Text,
         cursor -> BigInt,
         key -> Text,
-        payload -> Text,
-        metadata -> Text,
+        payload -> JsonDoc,
+        metadata -> JsonDoc,
         command_id -> Nullable<Text>,
         created_at_ts -> BigInt,
+        persisted_version -> Nullable<BigInt>,
+        deleted_at_ts -> Nullable<BigInt>,
+        tenant_id -> Text,
     }
 }
 diesel::table! {
@@ -35,50 +54,141 @@ diesel::table! {
         state_ptr -> Text,
         metadata -> Text,
         created_at_ts -> BigInt,
+        deleted_at_ts -> Nullable<BigInt>,
+        tenant_id -> Text,
+    }
+}
+diesel::table! {
+    work_queue (flow_id) {
+        flow_id -> Text,
+        worker_id -> Nullable<Text>,
+        lease_expires_at_ms -> Nullable<BigInt>,
+        attempts -> BigInt,
+        enqueued_at_ms -> BigInt,
+        tenant_id -> Text,
     }
 }
-allow_tables_to_appear_in_same_query!(flows, flow_data, snapshots);
 diesel::table! {
-    molecules (inchikey) {
+    flow_locks (flow_id) {
+        flow_id -> Text,
+        owner -> Text,
+        expires_at_ms -> BigInt,
+        tenant_id -> Text,
+    }
+}
+diesel::table! {
+    snapshot_blobs (key) {
+        key -> Text,
+        data -> Binary,
+        created_at_ts -> BigInt,
+        tenant_id -> Text,
+    }
+}
+diesel::table! {
+    artifacts (tenant_id, key) {
+        key -> Text,
+        size -> BigInt,
+        ref_count -> BigInt,
+        last_put_ts -> BigInt,
+        tenant_id -> Text,
+    }
+}
+diesel::table! {
+    artifact_refs (flow_id, cursor, artifact_key) {
+        flow_id -> Text,
+        cursor -> BigInt,
+        artifact_key -> Text,
+        tenant_id -> Text,
+    }
+}
+diesel::table! {
+    audit_log (id) {
+        id -> Text,
+        at_us -> BigInt,
+        actor -> Nullable<Text>,
+        operation -> Text,
+        flow_id -> Nullable<Text>,
+        target -> Nullable<Text>,
+        before_value -> Nullable<Text>,
+        after_value -> Nullable<Text>,
+        tenant_id -> Text,
+    }
+}
+diesel::table! {
+    flow_truncations (id) {
+        id -> Text,
+        tenant_id -> Text,
+        flow_id -> Text,
+        from_cursor -> BigInt,
+        flow_version -> BigInt,
+        parent_cursor -> Nullable<BigInt>,
+        at_us -> BigInt,
+        deleted_at_ts -> BigInt,
+        data_ids -> Text,
+        snapshot_ids -> Text,
+        flow_ids -> Text,
+    }
+}
+allow_tables_to_appear_in_same_query!(
+    flows,
+    flow_data,
+    snapshots,
+    work_queue,
+    flow_locks,
+    snapshot_blobs,
+    artifacts,
+    artifact_refs,
+    flow_truncations
+);
+diesel::table! {
+    molecules (tenant_id, inchikey) {
         inchikey -> Text,
         smiles -> Text,
         inchi -> Text,
         metadata -> Text,
         structure -> Nullable<Text>,
+        tenant_id -> Text,
     }
 }
 diesel::table! {
-    families (id) {
+    families (tenant_id, id) {
         id -> Text,
         name -> Nullable<Text>,
         description -> Nullable<Text>,
         family_hash -> Text,
         provenance -> Text,
         frozen -> Bool,
+        tenant_id -> Text,
     }
 }
 diesel::table! {
+    use diesel::sql_types::*;
+    use super::sql_types::JsonDoc;
     family_properties (id) {
         id -> Text,
         family_id -> Text,
         property_type -> Text,
-        value -> Text,
+        value -> JsonDoc,
         quality -> Nullable<Text>,
         preferred -> Bool,
         value_hash -> Text,
-        metadata -> Text,
+        metadata -> JsonDoc,
+        tenant_id -> Text,
     }
 }
 diesel::table! {
+    use diesel::sql_types::*;
+    use super::sql_types::JsonDoc;
     molecular_properties (id) {
         id -> Text,
         molecule_inchikey -> Text,
         property_type -> Text,
-        value -> Text,
+        value -> JsonDoc,
         quality -> Nullable<Text>,
         preferred -> Bool,
         value_hash -> Text,
-        metadata -> Text,
+        metadata -> JsonDoc,
+        tenant_id -> Text,
     }
 }
 diesel::table! {
@@ -86,6 +196,7 @@ diesel::table! {
         id -> Text,
         family_id -> Text,
         molecule_inchikey -> Text,
+        tenant_id -> Text,
     }
 }
 allow_tables_to_appear_in_same_query!(molecules, families, family_properties, molecular_properties, family_members);
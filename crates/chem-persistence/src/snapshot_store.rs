@@ -0,0 +1,389 @@
+//! Stores de blobs de snapshot para los backends SQL.
+//!
+//! - `DbSnapshotStore`: tabla `snapshot_blobs` en la misma base que los flows.
+//! - `S3SnapshotStore` (feature `s3`): objetos en un bucket S3-compatible
+//!   (MinIO en local) con firma SigV4 sobre HTTP plano.
+//! - `snapshot_store_from_env`: elige el store según `SNAPSHOT_STORE` (`fs` por
+//!   defecto, `db` o `s3`).
+//! - `snapshot_retention_from_env`: política de retención (`SNAPSHOT_KEEP_*`).
+//!
+//! Los stores en memoria y de ficheros viven en `flow::snapshot_store`.
+use crate::flow_persistence::DbPool;
+use crate::schema::snapshot_blobs::dsl as blobs_dsl;
+use chrono::{TimeZone, Utc};
+use diesel::prelude::*;
+use flow::errors::{FlowError, Result as FlowResult};
+use flow::repository::SnapshotStore;
+use flow::retention::SnapshotRetention;
+use flow::snapshot_store::{new_snapshot_key, FsSnapshotStore, StoredBlob};
+use std::sync::Arc;
+use std::time::Duration;
+/// Store en la tabla `snapshot_blobs`, limitado a las filas de un tenant.
+#[derive(Clone)]
+pub struct DbSnapshotStore {
+  pool: Arc<DbPool>,
+  tenant: String,
+}
+impl DbSnapshotStore {
+  pub(crate) fn from_pool(pool: Arc<DbPool>, tenant: String) -> Self {
+    Self { pool, tenant }
+  }
+  fn conn(&self) -> FlowResult<r2d2::PooledConnection<diesel::r2d2::ConnectionManager<crate::flow_persistence::DbConn>>> {
+    self.pool.get().map_err(|e| FlowError::Storage(format!("pool: {}", e)))
+  }
+}
+impl SnapshotStore for DbSnapshotStore {
+  fn save(&self, state: &[u8]) -> FlowResult<String> {
+    let key = new_snapshot_key();
+    let mut conn = self.conn()?;
+    diesel::insert_into(blobs_dsl::snapshot_blobs).values((blobs_dsl::key.eq(&key),
+                                                           blobs_dsl::data.eq(state),
+                                                           blobs_dsl::created_at_ts.eq(Utc::now().timestamp()),
+                                                           blobs_dsl::tenant_id.eq(&self.tenant)))
+                                                  .execute(&mut conn)
+                                                  .map_err(|e| FlowError::Storage(format!("db: {}", e)))?;
+    Ok(key)
+  }
+  fn load(&self, key: &str) -> FlowResult<Vec<u8>> {
+    let mut conn = self.conn()?;
+    blobs_dsl::snapshot_blobs.filter(blobs_dsl::key.eq(key))
+                             .filter(blobs_dsl::tenant_id.eq(&self.tenant))
+                             .select(blobs_dsl::data)
+                             .first::<Vec<u8>>(&mut conn)
+                             .optional()
+                             .map_err(|e| FlowError::Storage(format!("db: {}", e)))?
+                             .ok_or_else(|| FlowError::NotFound(format!("snapshot blob {}", key)))
+  }
+  fn delete(&self, key: &str) -> FlowResult<()> {
+    let mut conn = self.conn()?;
+    diesel::delete(blobs_dsl::snapshot_blobs.filter(blobs_dsl::key.eq(key)).filter(blobs_dsl::tenant_id.eq(&self.tenant)))
+      .execute(&mut conn)
+      .map_err(|e| FlowError::Storage(format!("db: {}", e)))?;
+    Ok(())
+  }
+  fn list(&self) -> FlowResult<Vec<StoredBlob>> {
+    let mut conn = self.conn()?;
+    let rows = blobs_dsl::snapshot_blobs.filter(blobs_dsl::tenant_id.eq(&self.tenant))
+                                        .select((blobs_dsl::key, blobs_dsl::created_at_ts))
+                                        .load::<(String, i64)>(&mut conn)
+                                        .map_err(|e| FlowError::Storage(format!("db: {}", e)))?;
+    Ok(rows.into_iter()
+           .map(|(key, ts)| StoredBlob { key, created_at: Utc.timestamp_opt(ts, 0).single().unwrap_or(Utc::now()) })
+           .collect())
+  }
+}
+/// Construye el store indicado por `SNAPSHOT_STORE`:
+/// - `fs` (por defecto): ficheros en `SNAPSHOT_DIR` (`./snapshots`).
+/// - `db`: tabla `snapshot_blobs` usando `pool`, con las filas de `tenant`.
+/// - `s3`: `S3SnapshotStore::from_env` (requiere la feature `s3`).
+///
+/// Los stores `fs` y `s3` los comparten todos los tenants.
+pub(crate) fn snapshot_store_from_env(pool: Option<Arc<DbPool>>, tenant: &str) -> FlowResult<Arc<dyn SnapshotStore>> {
+  let kind = std::env::var("SNAPSHOT_STORE").unwrap_or_else(|_| "fs".to_string());
+  match kind.as_str() {
+    "fs" => {
+      let dir = std::env::var("SNAPSHOT_DIR").unwrap_or_else(|_| "./snapshots".to_string());
+      Ok(Arc::new(FsSnapshotStore::new(dir)?))
+    }
+    "db" => match pool {
+      Some(pool) => Ok(Arc::new(DbSnapshotStore::from_pool(pool, tenant.to_string()))),
+      None => Err(FlowError::Other("SNAPSHOT_STORE=db no disponible en este backend; usar with_snapshot_store".into())),
+    },
+    #[cfg(feature = "s3")]
+    "s3" => Ok(Arc::new(s3::S3SnapshotStore::from_env()?)),
+    other => Err(FlowError::Other(format!("SNAPSHOT_STORE desconocido: {}", other))),
+  }
+}
+/// Política de retención de snapshots a partir del entorno, sobre
+/// `SnapshotRetention::default()`:
+/// - `SNAPSHOT_KEEP_LAST`: snapshots más recientes a conservar por flow.
+/// - `SNAPSHOT_KEEP_EVERY`: conserva uno cada K cursores (`0` lo desactiva).
+/// - `SNAPSHOT_ORPHAN_GRACE_SECS`: antigüedad mínima de un blob huérfano para
+///   borrarlo en `gc_snapshots`.
+pub(crate) fn snapshot_retention_from_env() -> SnapshotRetention {
+  let var = |name: &str| std::env::var(name).ok().and_then(|v| v.parse::<u64>().ok());
+  let mut policy = SnapshotRetention::default();
+  if let Some(n) = var("SNAPSHOT_KEEP_LAST") {
+    policy.keep_last = n as usize;
+  }
+  if let Some(k) = var("SNAPSHOT_KEEP_EVERY") {
+    policy.keep_every = (k > 0).then_some(k as i64);
+  }
+  if let Some(secs) = var("SNAPSHOT_ORPHAN_GRACE_SECS") {
+    policy.orphan_grace = Duration::from_secs(secs);
+  }
+  policy
+}
+#[cfg(feature = "s3")]
+pub use s3::{S3Config, S3SnapshotStore};
+#[cfg(feature = "s3")]
+mod s3 {
+  use flow::errors::{FlowError, Result as FlowResult};
+  use flow::repository::SnapshotStore;
+  use flow::snapshot_store::{new_snapshot_key, StoredBlob};
+  use hmac::{Hmac, KeyInit, Mac};
+  use sha2::{Digest, Sha256};
+  use std::io::{Read, Write};
+  use std::net::TcpStream;
+  use std::time::Duration;
+  /// Timeout de lectura/escritura de cada petición.
+  const IO_TIMEOUT: Duration = Duration::from_secs(30);
+  /// Conexión a un bucket S3-compatible con URLs path-style
+  /// (`http://host:port/<bucket>/<key>`).
+  #[derive(Debug, Clone)]
+  pub struct S3Config {
+    /// Endpoint `http://host[:port]` (sin TLS; pensado para MinIO local o un
+    /// proxy).
+    pub endpoint: String,
+    pub bucket: String,
+    pub region: String,
+    pub access_key: String,
+    pub secret_key: String,
+    /// Prefijo de las keys (p. ej. `snapshots/`).
+    pub prefix: String,
+  }
+  impl S3Config {
+    /// Lee `S3_ENDPOINT`, `S3_BUCKET`, `S3_ACCESS_KEY`, `S3_SECRET_KEY`,
+    /// `S3_REGION` (`us-east-1`) y `S3_PREFIX` (`snapshots/`).
+    pub fn from_env() -> FlowResult<Self> {
+      let var = |name: &str| std::env::var(name).map_err(|_| FlowError::Other(format!("{} not set", name)));
+      Ok(Self { endpoint: var("S3_ENDPOINT")?,
+                bucket: var("S3_BUCKET")?,
+                region: std::env::var("S3_REGION").unwrap_or_else(|_| "us-east-1".to_string()),
+                access_key: var("S3_ACCESS_KEY")?,
+                secret_key: var("S3_SECRET_KEY")?,
+                prefix: std::env::var("S3_PREFIX").unwrap_or_else(|_| "snapshots/".to_string()) })
+    }
+  }
+  /// Store en un bucket S3-compatible. Cada blob es un objeto
+  /// `<prefix><uuid>.bin`; `state_ptr` guarda la key completa.
+  #[derive(Debug, Clone)]
+  pub struct S3SnapshotStore {
+    config: S3Config,
+    /// `host[:port]` extraído del endpoint (cabecera `Host` y conexión).
+    host: String,
+  }
+  struct Response {
+    status: u16,
+    body: Vec<u8>,
+  }
+  impl S3SnapshotStore {
+    pub fn new(config: S3Config) -> FlowResult<Self> {
+      let host = config.endpoint
+                       .strip_prefix("http://")
+                       .ok_or_else(|| FlowError::Other(format!("S3_ENDPOINT debe ser http://: {}", config.endpoint)))?
+                       .trim_end_matches('/')
+                       .to_string();
+      Ok(Self { config, host })
+    }
+    pub fn from_env() -> FlowResult<Self> {
+      Self::new(S3Config::from_env()?)
+    }
+    fn check_key(key: &str) -> FlowResult<()> {
+      let valid = !key.is_empty()
+                  && !key.contains("..")
+                  && key.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.' | '/'));
+      if valid {
+        Ok(())
+      } else {
+        Err(FlowError::Storage(format!("snapshot key inválida: {}", key)))
+      }
+    }
+    /// Ejecuta una petición firmada (SigV4, payload firmado) sobre `key` (o
+    /// sobre el bucket si está vacía) y devuelve el status y el cuerpo.
+    fn request(&self, method: &str, key: &str, query: &[(&str, &str)], body: &[u8]) -> FlowResult<Response> {
+      let path =
+        if key.is_empty() { format!("/{}", self.config.bucket) } else { format!("/{}/{}", self.config.bucket, key) };
+      let mut params: Vec<(String, String)> = query.iter().map(|(k, v)| (uri_encode(k), uri_encode(v))).collect();
+      params.sort();
+      let query = params.iter().map(|(k, v)| format!("{}={}", k, v)).collect::<Vec<_>>().join("&");
+      let now = chrono::Utc::now();
+      let amz_date = now.format("%Y%m%dT%H%M%SZ").to_string();
+      let date = now.format("%Y%m%d").to_string();
+      let payload_hash = hex(&Sha256::digest(body));
+      let signed_headers = "host;x-amz-content-sha256;x-amz-date";
+      let canonical = format!("{}\n{}\n{}\nhost:{}\nx-amz-content-sha256:{}\nx-amz-date:{}\n\n{}\n{}",
+                              method, path, query, self.host, payload_hash, amz_date, signed_headers, payload_hash);
+      let scope = format!("{}/{}/s3/aws4_request", date, self.config.region);
+      let to_sign = format!("AWS4-HMAC-SHA256\n{}\n{}\n{}",
+                            amz_date,
+                            scope,
+                            hex(&Sha256::digest(canonical.as_bytes())));
+      let mut signing_key = hmac_sha256(format!("AWS4{}", self.config.secret_key).as_bytes(), date.as_bytes());
+      for part in [self.config.region.as_str(), "s3", "aws4_request"] {
+        signing_key = hmac_sha256(&signing_key, part.as_bytes());
+      }
+      let signature = hex(&hmac_sha256(&signing_key, to_sign.as_bytes()));
+      let target = if query.is_empty() { path } else { format!("{}?{}", path, query) };
+      let head = format!("{} {} HTTP/1.1\r\nHost: {}\r\nx-amz-date: {}\r\nx-amz-content-sha256: {}\r\nAuthorization: \
+                          AWS4-HMAC-SHA256 Credential={}/{}, SignedHeaders={}, Signature={}\r\nContent-Length: \
+                          {}\r\nConnection: close\r\n\r\n",
+                         method,
+                         target,
+                         self.host,
+                         amz_date,
+                         payload_hash,
+                         self.config.access_key,
+                         scope,
+                         signed_headers,
+                         signature,
+                         body.len());
+      let io_err = |e: std::io::Error| FlowError::Storage(format!("s3 {}: {}", self.host, e));
+      let mut stream = TcpStream::connect(&self.host).map_err(io_err)?;
+      stream.set_read_timeout(Some(IO_TIMEOUT)).map_err(io_err)?;
+      stream.set_write_timeout(Some(IO_TIMEOUT)).map_err(io_err)?;
+      stream.write_all(head.as_bytes()).map_err(io_err)?;
+      stream.write_all(body).map_err(io_err)?;
+      let mut raw = Vec::new();
+      stream.read_to_end(&mut raw).map_err(io_err)?;
+      parse_response(&raw).ok_or_else(|| FlowError::Storage(format!("s3 {}: respuesta HTTP inválida", self.host)))
+    }
+  }
+  impl SnapshotStore for S3SnapshotStore {
+    fn save(&self, state: &[u8]) -> FlowResult<String> {
+      let key = format!("{}{}", self.config.prefix, new_snapshot_key());
+      Self::check_key(&key)?;
+      let res = self.request("PUT", &key, &[], state)?;
+      if !(200..300).contains(&res.status) {
+        return Err(FlowError::Storage(format!("s3 PUT {}: HTTP {} {}",
+                                              key,
+                                              res.status,
+                                              String::from_utf8_lossy(&res.body))));
+      }
+      Ok(key)
+    }
+    fn load(&self, key: &str) -> FlowResult<Vec<u8>> {
+      Self::check_key(key)?;
+      let res = self.request("GET", key, &[], &[])?;
+      match res.status {
+        200..=299 => Ok(res.body),
+        404 => Err(FlowError::NotFound(format!("snapshot blob {}", key))),
+        status => Err(FlowError::Storage(format!("s3 GET {}: HTTP {} {}", key, status, String::from_utf8_lossy(&res.body)))),
+      }
+    }
+    fn delete(&self, key: &str) -> FlowResult<()> {
+      Self::check_key(key)?;
+      let res = self.request("DELETE", key, &[], &[])?;
+      match res.status {
+        200..=299 | 404 => Ok(()),
+        status => {
+          Err(FlowError::Storage(format!("s3 DELETE {}: HTTP {} {}", key, status, String::from_utf8_lossy(&res.body))))
+        }
+      }
+    }
+    /// `ListObjectsV2` bajo `prefix`, siguiendo los `continuation-token`.
+    fn list(&self) -> FlowResult<Vec<StoredBlob>> {
+      let mut blobs = Vec::new();
+      let mut token: Option<String> = None;
+      loop {
+        let mut query = vec![("list-type", "2"), ("prefix", self.config.prefix.as_str())];
+        if let Some(t) = &token {
+          query.push(("continuation-token", t.as_str()));
+        }
+        let res = self.request("GET", "", &query, &[])?;
+        let body = String::from_utf8_lossy(&res.body);
+        if !(200..300).contains(&res.status) {
+          return Err(FlowError::Storage(format!("s3 LIST {}: HTTP {} {}", self.config.bucket, res.status, body)));
+        }
+        for contents in xml_elements(&body, "Contents") {
+          let (Some(key), Some(modified)) = (xml_first(contents, "Key"), xml_first(contents, "LastModified")) else {
+            continue;
+          };
+          let created_at =
+            chrono::DateTime::parse_from_rfc3339(modified).map_err(|e| {
+                                                            FlowError::Storage(format!("s3 LIST: LastModified inválido \
+                                                                                        {}: {}",
+                                                                                       modified, e))
+                                                          })?
+                                                          .with_timezone(&chrono::Utc);
+          blobs.push(StoredBlob { key: xml_unescape(key), created_at });
+        }
+        let truncated = xml_first(&body, "IsTruncated") == Some("true");
+        token = match xml_first(&body, "NextContinuationToken") {
+          Some(next) if truncated => Some(xml_unescape(next)),
+          _ => break,
+        };
+      }
+      Ok(blobs)
+    }
+  }
+  /// Codificación de URI de SigV4: todo salvo `A-Za-z0-9-_.~` como `%XX`.
+  fn uri_encode(value: &str) -> String {
+    value.bytes()
+         .map(|b| match b {
+           b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => (b as char).to_string(),
+           _ => format!("%{:02X}", b),
+         })
+         .collect()
+  }
+  /// Contenido de cada `<tag>...</tag>` de `xml` (sin anidamiento del mismo
+  /// tag, suficiente para las respuestas de S3).
+  fn xml_elements<'a>(xml: &'a str, tag: &str) -> Vec<&'a str> {
+    let (open, close) = (format!("<{}>", tag), format!("</{}>", tag));
+    let mut out = Vec::new();
+    let mut rest = xml;
+    while let Some(start) = rest.find(&open) {
+      let after = &rest[start + open.len()..];
+      let Some(end) = after.find(&close) else { break };
+      out.push(&after[..end]);
+      rest = &after[end + close.len()..];
+    }
+    out
+  }
+  fn xml_first<'a>(xml: &'a str, tag: &str) -> Option<&'a str> {
+    xml_elements(xml, tag).into_iter().next()
+  }
+  fn xml_unescape(text: &str) -> String {
+    text.replace("&lt;", "<").replace("&gt;", ">").replace("&quot;", "\"").replace("&apos;", "'").replace("&amp;", "&")
+  }
+  fn hmac_sha256(key: &[u8], data: &[u8]) -> Vec<u8> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC acepta claves de cualquier longitud");
+    mac.update(data);
+    mac.finalize().into_bytes().to_vec()
+  }
+  fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+  }
+  /// Separa status, cabeceras y cuerpo (con `Content-Length`, chunked o
+  /// hasta el cierre de la conexión).
+  fn parse_response(raw: &[u8]) -> Option<Response> {
+    let split = raw.windows(4).position(|w| w == b"\r\n\r\n")?;
+    let head = std::str::from_utf8(&raw[..split]).ok()?;
+    let rest = &raw[split + 4..];
+    let mut lines = head.split("\r\n");
+    let status = lines.next()?.split_whitespace().nth(1)?.parse().ok()?;
+    let mut length = None;
+    let mut chunked = false;
+    for line in lines {
+      let (name, value) = line.split_once(':')?;
+      match name.trim().to_ascii_lowercase().as_str() {
+        "content-length" => length = value.trim().parse::<usize>().ok(),
+        "transfer-encoding" => chunked = value.trim().eq_ignore_ascii_case("chunked"),
+        _ => {}
+      }
+    }
+    let body = if chunked {
+      decode_chunked(rest)?
+    } else {
+      match length {
+        Some(n) => rest.get(..n)?.to_vec(),
+        None => rest.to_vec(),
+      }
+    };
+    Some(Response { status, body })
+  }
+  fn decode_chunked(mut data: &[u8]) -> Option<Vec<u8>> {
+    let mut out = Vec::new();
+    loop {
+      let eol = data.windows(2).position(|w| w == b"\r\n")?;
+      let size_line = std::str::from_utf8(&data[..eol]).ok()?;
+      let size = usize::from_str_radix(size_line.split(';').next()?.trim(), 16).ok()?;
+      data = &data[eol + 2..];
+      if size == 0 {
+        return Some(out);
+      }
+      out.extend_from_slice(data.get(..size)?);
+      data = data.get(size + 2..)?;
+    }
+  }
+}
@@ -0,0 +1,264 @@
+//! Unidad de trabajo: una transacción compartida por un `FlowRepository` y
+//! un `DomainRepository`.
+//!
+//! `UnitOfWork::begin` abre un `UnitOfWorkScope` cuyos repositorios escriben
+//! dentro de la misma transacción; nada es visible fuera hasta `commit`, y
+//! `rollback` (o soltar el alcance) lo descarta todo. El engine lo usa para
+//! que las escrituras de dominio de un paso solo queden si `persist_data`
+//! acepta su `FlowData` (`ChemicalFlowEngine::execute_and_persist_current_step_in`).
+//!
+//! - `DieselUnitOfWork`: toma una conexión del pool de flows, abre la
+//!   transacción y la comparte con copias de ambos repositorios Diesel. Las
+//!   transacciones internas de cada método pasan a ser savepoints. La cola
+//!   de trabajo y `lock_for_update` devuelven error dentro del alcance.
+//! - `InMemoryUnitOfWork`: para tests; el alcance escribe en copias de los
+//!   repositorios en memoria que `commit` fusiona con los originales.
+use crate::domain_persistence::DieselDomainRepository;
+use crate::flow_persistence::{DbConn, DieselFlowRepository};
+use chem_domain::{DomainRepository, InMemoryDomainRepository, InMemoryDomainState};
+use diesel::connection::{Connection, TransactionManager};
+use diesel::r2d2::{ConnectionManager, PooledConnection};
+use flow::domain::FlowData;
+use flow::errors::{FlowError, Result as FlowResult};
+use flow::repository::FlowRepository;
+use flow::stubs::{InMemoryFlowRepository, InMemoryFlowState};
+use flow::subscription::ChangeFeed;
+use std::ops::{Deref, DerefMut};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+/// Transacción abierta sobre repositorios de flows y de dominio.
+pub trait UnitOfWorkScope: Send {
+  /// Repositorio de flows que escribe en la transacción.
+  fn flow_repo(&self) -> Arc<dyn FlowRepository>;
+  /// Repositorio de dominio que escribe en la transacción.
+  fn domain_repo(&self) -> Arc<dyn DomainRepository>;
+  /// Confirma lo escrito por ambos repositorios. Después no hay que volver
+  /// a usar los repositorios del alcance (los Diesel devuelven error).
+  fn commit(self: Box<Self>) -> FlowResult<()>;
+  /// Descarta lo escrito. Soltar el alcance sin `commit` hace lo mismo.
+  fn rollback(self: Box<Self>) -> FlowResult<()>;
+}
+/// Abre alcances transaccionales (`UnitOfWorkScope`).
+pub trait UnitOfWork: Send + Sync {
+  fn begin(&self) -> FlowResult<Box<dyn UnitOfWorkScope>>;
+}
+type PooledDbConn = PooledConnection<ConnectionManager<DbConn>>;
+/// Conexión de un `DieselScope`, con su transacción abierta (`None` cuando
+/// ya terminó), y los `FlowData` que se publicarán en el feed al confirmar.
+pub(crate) struct SharedConn {
+  conn: Mutex<Option<PooledDbConn>>,
+  pending_publish: Mutex<Vec<(Arc<ChangeFeed>, FlowData)>>,
+}
+impl SharedConn {
+  pub(crate) fn lock(&self) -> Result<DbConnGuard<'_>, String> {
+    let guard = self.conn.lock().map_err(|_| "conexión de la unidad de trabajo envenenada".to_string())?;
+    if guard.is_none() {
+      return Err("la unidad de trabajo ya terminó".to_string());
+    }
+    Ok(DbConnGuard::Shared(guard))
+  }
+  /// Publica `data` en `feed` solo si la transacción se confirma (SQLite,
+  /// que publica desde el proceso).
+  #[cfg(any(test, not(feature = "pg")))]
+  pub(crate) fn publish_on_commit(&self, feed: &Arc<ChangeFeed>, data: &FlowData) {
+    if let Ok(mut pending) = self.pending_publish.lock() {
+      pending.push((Arc::clone(feed), data.clone()));
+    }
+  }
+  /// Confirma (`commit`) o deshace la transacción y devuelve la conexión al
+  /// pool.
+  fn finish(&self, commit: bool) -> FlowResult<()> {
+    let mut guard = self.conn.lock().map_err(|_| FlowError::Storage("conexión de la unidad de trabajo envenenada".into()))?;
+    let Some(mut conn) = guard.take() else {
+      return Ok(());
+    };
+    let conn: &mut DbConn = &mut conn;
+    let res = if commit {
+      <DbConn as Connection>::TransactionManager::commit_transaction(conn)
+    } else {
+      <DbConn as Connection>::TransactionManager::rollback_transaction(conn)
+    };
+    res.map_err(|e| FlowError::Storage(format!("db: {}", e)))?;
+    let pending = std::mem::take(&mut *self.pending_publish.lock().unwrap_or_else(|e| e.into_inner()));
+    if commit {
+      for (feed, data) in pending {
+        feed.publish(&data);
+      }
+    }
+    Ok(())
+  }
+}
+/// Conexión que usan los repositorios Diesel: la del pool o la compartida
+/// de una unidad de trabajo.
+pub enum DbConnGuard<'a> {
+  Pooled(PooledDbConn),
+  Shared(MutexGuard<'a, Option<PooledDbConn>>),
+}
+impl Deref for DbConnGuard<'_> {
+  type Target = DbConn;
+  fn deref(&self) -> &DbConn {
+    match self {
+      DbConnGuard::Pooled(c) => c,
+      // `SharedConn::lock` no devuelve guards de una conexión ya terminada
+      DbConnGuard::Shared(c) => c.as_ref().expect("unidad de trabajo terminada"),
+    }
+  }
+}
+impl DerefMut for DbConnGuard<'_> {
+  fn deref_mut(&mut self) -> &mut DbConn {
+    match self {
+      DbConnGuard::Pooled(c) => c,
+      DbConnGuard::Shared(c) => c.as_mut().expect("unidad de trabajo terminada"),
+    }
+  }
+}
+/// `UnitOfWork` sobre un `DieselFlowRepository` y un `DieselDomainRepository`
+/// de la misma base (cada uno conserva su tenant).
+///
+/// Dentro del alcance no deben usarse los locks de flow ni la cola de
+/// trabajo (abren su propia transacción), ni los repositorios originales
+/// sobre SQLite, cuyo pool de flows es de una conexión. En SQLite los
+/// `FlowData` se publican a los suscriptores al confirmar; en Postgres el
+/// `NOTIFY` ya va en la transacción.
+pub struct DieselUnitOfWork {
+  flow: Arc<DieselFlowRepository>,
+  domain: Arc<DieselDomainRepository>,
+}
+impl DieselUnitOfWork {
+  pub fn new(flow: Arc<DieselFlowRepository>, domain: Arc<DieselDomainRepository>) -> Self {
+    Self { flow, domain }
+  }
+}
+impl UnitOfWork for DieselUnitOfWork {
+  fn begin(&self) -> FlowResult<Box<dyn UnitOfWorkScope>> {
+    let mut conn = self.flow.pooled_conn()?;
+    <DbConn as Connection>::TransactionManager::begin_transaction(&mut *conn).map_err(|e| {
+                                                                                FlowError::Storage(format!("db: {}", e))
+                                                                              })?;
+    let shared =
+      Arc::new(SharedConn { conn: Mutex::new(Some(conn)), pending_publish: Mutex::new(Vec::new()) });
+    Ok(Box::new(DieselScope { flow: Arc::new(self.flow.in_unit_of_work(Arc::clone(&shared))),
+                              domain: Arc::new(self.domain.in_unit_of_work(Arc::clone(&shared))),
+                              shared }))
+  }
+}
+struct DieselScope {
+  flow: Arc<DieselFlowRepository>,
+  domain: Arc<DieselDomainRepository>,
+  shared: Arc<SharedConn>,
+}
+impl UnitOfWorkScope for DieselScope {
+  fn flow_repo(&self) -> Arc<dyn FlowRepository> {
+    self.flow.clone()
+  }
+  fn domain_repo(&self) -> Arc<dyn DomainRepository> {
+    self.domain.clone()
+  }
+  fn commit(self: Box<Self>) -> FlowResult<()> {
+    self.shared.finish(true)
+  }
+  fn rollback(self: Box<Self>) -> FlowResult<()> {
+    self.shared.finish(false)
+  }
+}
+impl Drop for DieselScope {
+  fn drop(&mut self) {
+    let _ = self.shared.finish(false);
+  }
+}
+/// `UnitOfWork` sobre los repositorios en memoria. Los alcances se abren de
+/// uno en uno (`begin` espera a que termine el anterior) y escriben en copias
+/// (`InMemoryFlowRepository::detached`, `InMemoryDomainRepository::from_state`).
+/// `commit` fusiona lo que cambió en las copias; si otro escribió entretanto
+/// en los originales la misma entrada (el mismo flow, molécula, familia…)
+/// devuelve `Conflict` y no aplica nada. El rollback solo descarta las
+/// copias, así que nunca deshace lo escrito fuera del alcance.
+pub struct InMemoryUnitOfWork {
+  flow: Arc<InMemoryFlowRepository>,
+  domain: Arc<InMemoryDomainRepository>,
+  /// `true` mientras hay un alcance abierto.
+  busy: Arc<(Mutex<bool>, Condvar)>,
+}
+impl InMemoryUnitOfWork {
+  pub fn new(flow: Arc<InMemoryFlowRepository>, domain: Arc<InMemoryDomainRepository>) -> Self {
+    Self { flow, domain, busy: Arc::new((Mutex::new(false), Condvar::new())) }
+  }
+}
+impl UnitOfWork for InMemoryUnitOfWork {
+  fn begin(&self) -> FlowResult<Box<dyn UnitOfWorkScope>> {
+    let (busy, freed) = &*self.busy;
+    let mut guard = busy.lock().map_err(|e| FlowError::Storage(format!("mutex poisoned: {:?}", e)))?;
+    while *guard {
+      guard = freed.wait(guard).map_err(|e| FlowError::Storage(format!("mutex poisoned: {:?}", e)))?;
+    }
+    *guard = true;
+    drop(guard);
+    let mut scope = InMemoryScope { flow: Arc::clone(&self.flow),
+                                    domain: Arc::clone(&self.domain),
+                                    busy: Arc::clone(&self.busy),
+                                    work: None };
+    // si falla el checkpoint, soltar `scope` libera `busy`
+    let domain_base = self.domain.checkpoint().map_err(|e| FlowError::Storage(e.to_string()))?;
+    let flow_base = self.flow.checkpoint()?;
+    scope.work = Some(ScopeCopies { flow: Arc::new(self.flow.detached(flow_base.clone())),
+                                    domain: Arc::new(InMemoryDomainRepository::from_state(domain_base.clone())),
+                                    flow_base,
+                                    domain_base });
+    Ok(Box::new(scope))
+  }
+}
+/// Copias en las que escribe un `InMemoryScope` y el contenido de los
+/// originales al abrirlo.
+struct ScopeCopies {
+  flow: Arc<InMemoryFlowRepository>,
+  domain: Arc<InMemoryDomainRepository>,
+  flow_base: InMemoryFlowState,
+  domain_base: InMemoryDomainState,
+}
+struct InMemoryScope {
+  flow: Arc<InMemoryFlowRepository>,
+  domain: Arc<InMemoryDomainRepository>,
+  busy: Arc<(Mutex<bool>, Condvar)>,
+  /// Siempre presente tras `begin`.
+  work: Option<ScopeCopies>,
+}
+impl InMemoryScope {
+  fn copies(&self) -> &ScopeCopies {
+    self.work.as_ref().expect("alcance sin copias")
+  }
+}
+impl UnitOfWorkScope for InMemoryScope {
+  fn flow_repo(&self) -> Arc<dyn FlowRepository> {
+    self.copies().flow.clone()
+  }
+  fn domain_repo(&self) -> Arc<dyn DomainRepository> {
+    self.copies().domain.clone()
+  }
+  fn commit(mut self: Box<Self>) -> FlowResult<()> {
+    let Some(work) = self.work.take() else {
+      return Ok(());
+    };
+    let flow_state = work.flow.checkpoint()?;
+    let domain_state = work.domain.checkpoint().map_err(|e| FlowError::Storage(e.to_string()))?;
+    // el dominio se fusiona con el repositorio de flows ya bloqueado y
+    // comprobado: o se aplican ambos o ninguno
+    self.flow.merge(&work.flow_base, flow_state, || {
+               match self.domain.merge(&work.domain_base, domain_state) {
+                 Ok(true) => Ok(()),
+                 Ok(false) => Err(FlowError::Conflict("el dominio cambió fuera de la unidad de trabajo".into())),
+                 Err(e) => Err(FlowError::Storage(e.to_string())),
+               }
+             })
+  }
+  fn rollback(self: Box<Self>) -> FlowResult<()> {
+    Ok(())
+  }
+}
+impl Drop for InMemoryScope {
+  fn drop(&mut self) {
+    let (busy, freed) = &*self.busy;
+    if let Ok(mut guard) = busy.lock() {
+      *guard = false;
+    }
+    freed.notify_one();
+  }
+}
@@ -0,0 +1,81 @@
+// `ArtifactStore` direccionado por contenido: repositorio en memoria y
+// `DieselFlowRepository` sobre SQLite (sin `pg`) o Postgres (`DATABASE_URL`),
+// con `flow::testkit::check_artifact_store`. Los repositorios Diesel usan un
+// `ARTIFACT_DIR` temporal propio, donde también se comprueba que un blob
+// alterado en disco se detecta al leerlo y que `gc_artifacts` no deja filas
+// sin fichero cuando corre a la vez que `put`.
+use chem_persistence::DieselFlowRepository;
+use flow::errors::FlowError;
+use flow::repository::ArtifactStore;
+use flow::stubs::InMemoryFlowRepository;
+use flow::testkit::check_artifact_store;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+/// Directorio temporal de artifacts propio de cada test.
+fn temp_artifact_dir() -> PathBuf {
+  std::env::temp_dir().join(format!("artifacts_{}", uuid::Uuid::new_v4()))
+}
+/// Sobrescribe el fichero de un blob y comprueba que `get` lo rechaza.
+fn assert_detects_corruption(repo: &DieselFlowRepository, dir: &std::path::Path) {
+  let blob = format!("íntegro {}", uuid::Uuid::new_v4()).into_bytes();
+  let key = repo.put(&blob).expect("put");
+  assert_eq!(std::fs::read(dir.join(&key)).expect("fichero del blob"), blob);
+  std::fs::write(dir.join(&key), b"alterado").expect("corromper");
+  assert!(matches!(repo.get(&key), Err(FlowError::Integrity(_))));
+  assert!(matches!(repo.get("../fuera"), Err(FlowError::NotFound(_))));
+}
+/// `put` repetido del mismo blob mientras otro hilo ejecuta
+/// `gc_artifacts(0)`: si la key quedó registrada (`copy_if_needed` la
+/// encuentra), su fichero tiene que existir.
+fn assert_put_survives_gc(repo: Arc<DieselFlowRepository>) {
+  let blob = format!("compartido {}", uuid::Uuid::new_v4()).into_bytes();
+  let done = Arc::new(AtomicBool::new(false));
+  let gc = {
+    let (repo, done) = (Arc::clone(&repo), Arc::clone(&done));
+    std::thread::spawn(move || {
+      while !done.load(Ordering::Relaxed) {
+        repo.gc_artifacts(Duration::ZERO).expect("gc");
+      }
+    })
+  };
+  for _ in 0..200 {
+    let key = repo.put(&blob).expect("put");
+    if repo.copy_if_needed(&key).is_ok() {
+      assert_eq!(repo.get(&key).expect("fichero del blob registrado"), blob);
+      repo.release(&key).expect("release");
+    }
+  }
+  done.store(true, Ordering::Relaxed);
+  gc.join().expect("hilo de gc");
+}
+#[test]
+fn in_memory_artifact_store() {
+  check_artifact_store(Arc::new(InMemoryFlowRepository::new()));
+}
+#[cfg(not(feature = "pg"))]
+#[test]
+fn diesel_sqlite_artifact_store() {
+  let memdb = || format!("file:memdb_artifacts_{}?mode=memory&cache=shared", uuid::Uuid::new_v4());
+  check_artifact_store(Arc::new(DieselFlowRepository::new(&memdb()).with_artifact_dir(temp_artifact_dir())));
+  let dir = temp_artifact_dir();
+  assert_detects_corruption(&DieselFlowRepository::new(&memdb()).with_artifact_dir(&dir), &dir);
+  assert_put_survives_gc(Arc::new(DieselFlowRepository::new(&memdb()).with_artifact_dir(&dir)));
+  std::fs::remove_dir_all(dir).ok();
+}
+#[cfg(feature = "pg")]
+#[test]
+fn diesel_pg_artifact_store() {
+  let Ok(url) = std::env::var("DATABASE_URL") else {
+    eprintln!("skipping pg artifact store test: DATABASE_URL not set");
+    return;
+  };
+  let dir = temp_artifact_dir();
+  let repo = DieselFlowRepository::new_pg(&url).expect("create pg repo").with_artifact_dir(&dir);
+  let repo = Arc::new(repo);
+  check_artifact_store(Arc::clone(&repo));
+  assert_detects_corruption(&repo, &dir);
+  assert_put_survives_gc(repo);
+  std::fs::remove_dir_all(dir).ok();
+}
@@ -0,0 +1,95 @@
+// Variante async del repositorio: adaptadores `AsyncFlowAdapter` /
+// `BlockingFlowAdapter` (y sus equivalentes de dominio) sobre los stubs en
+// memoria y, con la feature `async-pg` y `DATABASE_URL` definida, el backend
+// nativo `AsyncPgFlowRepository` pasando por la batería de `flow::testkit`
+// (incluida la retención de snapshots).
+use flow::domain::{FlowData, PersistResult};
+use flow::repository::FlowRepository;
+use flow::stubs::InMemoryFlowRepository;
+use flow::testkit::{check_snapshot_retention, retention_for_checks, run_conformance_suite};
+use flow::{AsyncFlowAdapter, AsyncFlowRepository, BlockingFlowAdapter};
+use serde_json::json;
+use std::sync::Arc;
+use std::time::Duration;
+use uuid::Uuid;
+#[tokio::test(flavor = "multi_thread")]
+async fn async_adapter_drives_sync_repository() {
+  let repo = AsyncFlowAdapter::new(Arc::new(InMemoryFlowRepository::new()));
+  let flow_id = repo.create_flow(Some("async".into()), None, json!({})).await.expect("create");
+  let data = FlowData { id: Uuid::new_v4(),
+                        flow_id,
+                        cursor: 1,
+                        key: "step_state:async".into(),
+                        payload: json!({"n": 1}),
+                        metadata: json!({}),
+                        command_id: Some(Uuid::new_v4()),
+                        created_at: chrono::Utc::now() };
+  assert_eq!(repo.persist_data(&data, 0).await.expect("persist"),
+             PersistResult::Ok { new_version: 1 });
+  assert_eq!(repo.persist_data(&data, 0).await.expect("replay"),
+             PersistResult::Ok { new_version: 1 });
+  assert_eq!(repo.read_data(&flow_id, 0).await.expect("read").len(), 1);
+  let guard = repo.lock_for_update(&flow_id, 1, Duration::from_millis(100)).await.expect("lock").expect("guard");
+  assert!(repo.lock_for_update(&flow_id, 1, Duration::from_millis(50)).await.is_err());
+  drop(guard);
+  assert!(repo.lock_for_update(&flow_id, 1, Duration::from_millis(50)).await.expect("lock libre").is_some());
+}
+#[tokio::test(flavor = "multi_thread")]
+async fn domain_adapters_round_trip() {
+  use chem_domain::{
+    AsyncDomainAdapter, AsyncDomainRepository, BlockingDomainAdapter, DomainRepository, InMemoryDomainRepository,
+  };
+  let async_repo: Arc<dyn AsyncDomainRepository> =
+    Arc::new(AsyncDomainAdapter::new(Arc::new(InMemoryDomainRepository::new())));
+  assert!(async_repo.list_families().await.expect("list").is_empty());
+  let blocking = BlockingDomainAdapter::from_current(async_repo);
+  let found = tokio::task::spawn_blocking(move || blocking.get_molecule("NOPE-INCHIKEY")).await.expect("join");
+  assert!(found.expect("get_molecule").is_none());
+}
+#[test]
+fn adapters_round_trip_conforms() {
+  let rt = tokio::runtime::Runtime::new().expect("runtime");
+  let handle = rt.handle().clone();
+  run_conformance_suite(|| {
+    let inner: Arc<dyn AsyncFlowRepository> = Arc::new(AsyncFlowAdapter::new(Arc::new(InMemoryFlowRepository::new())));
+    Arc::new(BlockingFlowAdapter::new(inner, handle.clone())) as Arc<dyn FlowRepository>
+  });
+}
+#[test]
+fn adapters_snapshot_retention() {
+  let rt = tokio::runtime::Runtime::new().expect("runtime");
+  let inner = Arc::new(InMemoryFlowRepository::new().with_snapshot_retention(retention_for_checks()));
+  let repo: Arc<dyn AsyncFlowRepository> = Arc::new(AsyncFlowAdapter::new(inner));
+  check_snapshot_retention(Arc::new(BlockingFlowAdapter::new(repo, rt.handle().clone())));
+}
+#[cfg(feature = "async-pg")]
+#[test]
+fn async_pg_snapshot_retention() {
+  use chem_persistence::AsyncPgFlowRepository;
+  let Ok(url) = std::env::var("DATABASE_URL") else {
+    eprintln!("skipping async pg snapshot retention test: DATABASE_URL not set");
+    return;
+  };
+  let rt = tokio::runtime::Runtime::new().expect("runtime");
+  let dir = std::env::temp_dir().join(format!("snapstore_{}", Uuid::new_v4()));
+  let store = Arc::new(flow::FsSnapshotStore::new(dir).expect("fs store"));
+  let pg = rt.block_on(AsyncPgFlowRepository::connect(&url))
+             .expect("connect")
+             .with_snapshot_store(store)
+             .with_snapshot_retention(retention_for_checks());
+  let pg: Arc<dyn AsyncFlowRepository> = Arc::new(pg);
+  check_snapshot_retention(Arc::new(BlockingFlowAdapter::new(pg, rt.handle().clone())));
+}
+#[cfg(feature = "async-pg")]
+#[test]
+fn async_pg_repository_conforms() {
+  use chem_persistence::AsyncPgFlowRepository;
+  let Ok(url) = std::env::var("DATABASE_URL") else {
+    eprintln!("skipping async pg conformance test: DATABASE_URL not set");
+    return;
+  };
+  let rt = tokio::runtime::Runtime::new().expect("runtime");
+  let pg: Arc<dyn AsyncFlowRepository> = Arc::new(rt.block_on(AsyncPgFlowRepository::connect(&url)).expect("connect"));
+  let repo: Arc<dyn FlowRepository> = Arc::new(BlockingFlowAdapter::new(pg, rt.handle().clone()));
+  run_conformance_suite(|| repo.clone());
+}
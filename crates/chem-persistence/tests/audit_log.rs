@@ -0,0 +1,132 @@
+// Auditoría de los repositorios (`flow::audit`, `AuditedDomainRepository`):
+// las llamadas que modifican flows o dominio dejan una entrada con actor,
+// operación, valores antes/después e instante, consultable por actor, flow y
+// rango de tiempo. Se ejecuta en memoria, sobre SQLite (sin `pg`) y sobre
+// Postgres (con `pg` y `DATABASE_URL`).
+use chem_domain::{DomainRepository, InMemoryDomainRepository, Molecule};
+use chem_persistence::AuditedDomainRepository;
+use chrono::Utc;
+use flow::audit::{AuditQuery, AuditedFlowRepository, InMemoryAuditLog};
+use flow::domain::FlowData;
+use flow::repository::{AuditLog, FlowRepository};
+use flow::stubs::InMemoryFlowRepository;
+use flow::testkit::run_conformance_suite;
+use serde_json::json;
+use std::sync::Arc;
+use std::time::Duration;
+use uuid::Uuid;
+fn operations(log: &dyn AuditLog, query: &AuditQuery) -> Vec<String> {
+  log.query(query).expect("query").into_iter().map(|e| e.operation).collect()
+}
+fn assert_audits(flows: Arc<dyn FlowRepository>, domain: Arc<dyn DomainRepository>, log: Arc<dyn AuditLog>) {
+  let alice = format!("alice-{}", Uuid::new_v4());
+  let bob = format!("bob-{}", Uuid::new_v4());
+  let repo = AuditedFlowRepository::new(flows.clone(), log.clone()).with_actor(alice.clone());
+  let flow_id = repo.create_flow(Some("cadma".into()), Some("queued".into()), json!({})).expect("create");
+  repo.set_flow_status(&flow_id, Some("running".into())).expect("status");
+  repo.set_meta(&flow_id, "method", json!("ADMETSA")).expect("set_meta");
+  repo.set_meta(&flow_id, "method", json!("Custom")).expect("set_meta");
+  let data = FlowData { id: Uuid::new_v4(),
+                        flow_id,
+                        cursor: 1,
+                        key: "step_state:FamilyReferenceStep".into(),
+                        payload: json!({}),
+                        metadata: json!({}),
+                        command_id: None,
+                        created_at: Utc::now() };
+  repo.persist_data(&data, 0).expect("persist");
+  // las lecturas y los conflictos no dejan entrada
+  repo.read_data(&flow_id, 0).expect("read");
+  repo.get_meta(&flow_id, "method").expect("get_meta");
+  repo.persist_data(&FlowData { id: Uuid::new_v4(), cursor: 2, ..data.clone() }, 0).expect("conflict");
+  std::thread::sleep(Duration::from_millis(5));
+  let mid = Utc::now();
+  std::thread::sleep(Duration::from_millis(5));
+  repo.del_meta(&flow_id, "method").expect("del_meta");
+  let branch = repo.create_branch(&flow_id, 1, json!({})).expect("branch");
+  repo.delete_branch(&branch).expect("delete_branch");
+  let by_alice = log.query(&AuditQuery::new().actor(alice.clone())).expect("query");
+  assert!(by_alice.iter().all(|e| e.actor.as_deref() == Some(alice.as_str())));
+  assert_eq!(by_alice.iter().map(|e| e.operation.as_str()).collect::<Vec<_>>(),
+             vec!["create_flow",
+                  "set_flow_status",
+                  "set_meta",
+                  "set_meta",
+                  "persist_data",
+                  "del_meta",
+                  "create_branch",
+                  "delete_branch"]);
+  assert!(by_alice.windows(2).all(|w| w[0].at <= w[1].at), "orden cronológico");
+  let status = &by_alice[1];
+  assert_eq!((status.flow_id, status.before.clone(), status.after.clone()),
+             (Some(flow_id), Some(json!("queued")), Some(json!("running"))));
+  let second_set = &by_alice[3];
+  assert_eq!(second_set.target.as_deref(), Some("method"));
+  assert_eq!((second_set.before.clone(), second_set.after.clone()),
+             (Some(json!("ADMETSA")), Some(json!("Custom"))));
+  assert_eq!(by_alice[4].target.as_deref(), Some("1"));
+  assert_eq!(by_alice[5].before, Some(json!("Custom")));
+  let deleted = &by_alice[7];
+  assert_eq!(deleted.flow_id, Some(branch));
+  assert_eq!(deleted.before.as_ref().and_then(|m| m.get("parent_cursor").cloned()),
+             Some(json!(1)));
+  // filtros por flow, rango de tiempo y límite
+  assert_eq!(operations(log.as_ref(), &AuditQuery::new().actor(alice.clone()).flow(flow_id)).len(),
+             6);
+  assert_eq!(operations(log.as_ref(), &AuditQuery::new().actor(alice.clone()).since(mid)),
+             vec!["del_meta", "create_branch", "delete_branch"]);
+  assert_eq!(operations(log.as_ref(), &AuditQuery::new().actor(alice.clone()).until(mid).limit(2)),
+             vec!["create_flow", "set_flow_status"]);
+  // otro actor sobre el mismo registro, y el repositorio de dominio
+  let as_bob = AuditedFlowRepository::new(flows, log.clone()).with_actor(bob.clone());
+  as_bob.set_meta(&flow_id, "reviewed", json!(true)).expect("set_meta bob");
+  let domain = AuditedDomainRepository::new(domain, log.clone()).with_actor(bob.clone());
+  let molecule = Molecule::from_parts("ABCDEFGHIJKLMN-OPQRSTUVWX-1",
+                                      "CCO",
+                                      "InChI=1S/C2H6O/c1-2-3/h3H,2H2,1H3",
+                                      json!({})).expect("molecule");
+  domain.save_molecule(molecule.clone()).expect("save_molecule");
+  domain.delete_molecule(molecule.inchikey()).expect("delete_molecule");
+  let by_bob = log.query(&AuditQuery::new().actor(bob)).expect("query bob");
+  assert_eq!(by_bob.iter().map(|e| e.operation.as_str()).collect::<Vec<_>>(),
+             vec!["set_meta", "save_molecule", "delete_molecule"]);
+  assert_eq!(by_bob[1].target.as_deref(), Some("molecule:ABCDEFGHIJKLMN-OPQRSTUVWX-1"));
+  assert_eq!(by_bob[1].flow_id, None);
+  assert_eq!(by_bob[2].before.as_ref().and_then(|m| m.get("smiles").cloned()),
+             Some(json!("CCO")));
+  assert_eq!(operations(log.as_ref(), &AuditQuery::new().flow(flow_id)).len(), 7);
+}
+#[test]
+fn in_memory_repositories_are_audited() {
+  assert_audits(Arc::new(InMemoryFlowRepository::new()),
+                Arc::new(InMemoryDomainRepository::new()),
+                Arc::new(InMemoryAuditLog::new()));
+}
+#[test]
+fn audited_flow_repository_conforms() {
+  run_conformance_suite(|| {
+    Arc::new(AuditedFlowRepository::new(Arc::new(InMemoryFlowRepository::new()), Arc::new(InMemoryAuditLog::new())))
+    as Arc<dyn FlowRepository>
+  });
+}
+#[cfg(not(feature = "pg"))]
+#[test]
+fn diesel_sqlite_repositories_are_audited() {
+  use chem_persistence::{DieselDomainRepository, DieselFlowRepository};
+  let url = format!("file:memdb_audit_{}?mode=memory&cache=shared", Uuid::new_v4());
+  let flows = DieselFlowRepository::new(&url);
+  let log = Arc::new(flows.db_audit_log());
+  assert_audits(Arc::new(flows), Arc::new(DieselDomainRepository::new(&url)), log);
+}
+#[cfg(feature = "pg")]
+#[test]
+fn diesel_pg_repositories_are_audited() {
+  use chem_persistence::{DieselDomainRepository, DieselFlowRepository};
+  let Ok(url) = std::env::var("DATABASE_URL") else {
+    eprintln!("skipping pg audit test: DATABASE_URL not set");
+    return;
+  };
+  let flows = DieselFlowRepository::new_pg(&url).expect("create pg repo");
+  let log = Arc::new(flows.db_audit_log());
+  assert_audits(Arc::new(flows), Arc::new(DieselDomainRepository::new(&url)), log);
+}
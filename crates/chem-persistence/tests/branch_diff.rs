@@ -0,0 +1,106 @@
+// Diff de ramas (`flow::diff`): dos ramas de un flow tipo CADMA que
+// comparten la selección de familia y divergen en el método ADMETSA. Se
+// ejecuta sobre el repositorio en memoria y sobre SQLite (sin `pg`).
+use chrono::Utc;
+use flow::diff::{diff_branches, json_diff, JsonChange, RecordDiff};
+use flow::domain::FlowData;
+use flow::lineage::CommonAncestor;
+use flow::repository::FlowRepository;
+use flow::stubs::InMemoryFlowRepository;
+use serde_json::{json, Value as JsonValue};
+use uuid::Uuid;
+fn append(repo: &dyn FlowRepository, flow_id: Uuid, key: &str, payload: JsonValue, metadata: JsonValue) {
+  let meta = repo.get_flow_meta(&flow_id).expect("meta");
+  let data = FlowData { id: Uuid::new_v4(),
+                        flow_id,
+                        cursor: meta.current_cursor + 1,
+                        key: key.into(),
+                        payload,
+                        metadata,
+                        command_id: None,
+                        created_at: Utc::now() };
+  repo.persist_data(&data, meta.current_version).expect("persist");
+}
+#[test]
+fn json_diff_reports_paths() {
+  let left = json!({"method": "ADMETSA", "props": [1, 2, 3], "a/b": {"x": 1}, "same": true});
+  let right = json!({"method": "Custom", "props": [1, 5], "a/b": {"x": 1, "y": null}, "same": true, "new": 1});
+  assert_eq!(json_diff(&left, &right),
+             vec![JsonChange::Added { path: "/a~1b/y".into(), value: JsonValue::Null },
+                  JsonChange::Changed { path: "/method".into(), from: json!("ADMETSA"), to: json!("Custom") },
+                  JsonChange::Added { path: "/new".into(), value: json!(1) },
+                  JsonChange::Changed { path: "/props/1".into(), from: json!(2), to: json!(5) },
+                  JsonChange::Removed { path: "/props/2".into(), value: json!(3) }]);
+  assert!(json_diff(&left, &left).is_empty());
+  assert_eq!(json_diff(&json!(1), &json!("1")),
+             vec![JsonChange::Changed { path: "".into(), from: json!(1), to: json!("1") }]);
+}
+fn assert_diffs_branches(repo: &dyn FlowRepository) {
+  let family = Uuid::new_v4();
+  let root = repo.create_flow(Some("cadma".into()), None, json!({})).expect("create");
+  append(repo,
+         root,
+         "step_state:FamilyReferenceStep",
+         json!({"family_uuid": family}),
+         json!({}));
+  append(repo,
+         root,
+         "step_state:ADMETSAPropertiesStep",
+         json!({"method": "ADMETSA", "n": 3}),
+         json!({}));
+  append(repo, root, "step_state:MoleculeInitialStep", json!({"count": 10}), json!({}));
+  let other = repo.create_branch(&root, 1, json!({})).expect("branch");
+  append(repo,
+         other,
+         "step_state:ADMETSAPropertiesStep",
+         json!({"method": "Custom", "n": 3}),
+         json!({"status": "ok"}));
+  append(repo, other, "step_state:MoleculeInitialStep", json!({"count": 10}), json!({}));
+  append(repo, other, "step_state:Extra", json!({}), json!({}));
+  let diff = diff_branches(repo, &root, &other).expect("diff");
+  assert_eq!(diff.common_ancestor, Some(CommonAncestor { flow_id: root, cursor: 1 }));
+  assert_eq!(diff.unchanged, 1, "el paso 3 es igual en ambas ramas");
+  let summary: Vec<(i64, &str)> = diff.records.iter().map(|r| (r.cursor(), r.key())).collect();
+  assert_eq!(summary, vec![(2, "step_state:ADMETSAPropertiesStep"), (4, "step_state:Extra")]);
+  match &diff.records[0] {
+    RecordDiff::Changed { payload, metadata, .. } => {
+      assert_eq!(payload,
+                 &vec![JsonChange::Changed { path: "/method".into(), from: json!("ADMETSA"), to: json!("Custom") }]);
+      assert_eq!(metadata,
+                 &vec![JsonChange::Added { path: "/status".into(), value: json!("ok") }]);
+    }
+    other => panic!("se esperaba Changed, obtenido {:?}", other),
+  }
+  assert!(matches!(diff.records[1], RecordDiff::Added { .. }));
+  let reverse = diff_branches(repo, &other, &root).expect("diff inverso");
+  assert!(matches!(reverse.records[1], RecordDiff::Removed { .. }));
+  let rendered = diff.render();
+  assert!(rendered.contains("~ 2 step_state:ADMETSAPropertiesStep"), "{}", rendered);
+  assert!(rendered.contains("payload/method: \"ADMETSA\" → \"Custom\""), "{}", rendered);
+  assert!(rendered.contains("+ 4 step_state:Extra"), "{}", rendered);
+  let as_json = serde_json::to_value(&diff).expect("json");
+  assert_eq!(as_json["records"][0]["kind"], "changed");
+  assert_eq!(as_json["records"][0]["payload"][0]["op"], "changed");
+  assert!(diff_branches(repo, &root, &root).expect("misma rama").is_empty());
+  // sin antepasado común se comparan todos los registros
+  let unrelated = repo.create_flow(None, None, json!({})).expect("create");
+  append(repo,
+         unrelated,
+         "step_state:FamilyReferenceStep",
+         json!({"family_uuid": family}),
+         json!({}));
+  let diff = diff_branches(repo, &root, &unrelated).expect("diff sin antepasado");
+  assert_eq!(diff.common_ancestor, None);
+  assert_eq!((diff.unchanged, diff.records.len()), (1, 2));
+}
+#[test]
+fn in_memory_branches_diff() {
+  assert_diffs_branches(&InMemoryFlowRepository::new());
+}
+#[cfg(not(feature = "pg"))]
+#[test]
+fn diesel_sqlite_branches_diff() {
+  use chem_persistence::DieselFlowRepository;
+  let url = format!("file:memdb_diff_{}?mode=memory&cache=shared", Uuid::new_v4());
+  assert_diffs_branches(&DieselFlowRepository::new(&url));
+}
@@ -0,0 +1,72 @@
+// `save_molecules_bulk` y `save_molecular_properties_bulk` del repositorio
+// Diesel: una transacción con inserciones de varias filas (en tramos) y un
+// resultado por elemento. Corre sobre SQLite y, con `pg` y `DATABASE_URL`,
+// sobre Postgres.
+use chem_domain::{DomainError, DomainRepository, Molecule, OwnedMolecularProperty};
+use chem_persistence::DieselDomainRepository;
+use serde_json::json;
+use uuid::Uuid;
+fn molecule(inchikey: &str, smiles: &str) -> Molecule {
+  Molecule::from_parts(inchikey,
+                       smiles,
+                       "InChI=1S/C2H6O/c1-2-3/h3H,2H2,1H3",
+                       json!({ "source": "bulk" })).expect("molecule")
+}
+fn property(inchikey: &str, n: usize) -> OwnedMolecularProperty {
+  OwnedMolecularProperty { id: Uuid::new_v4(),
+                           molecule_inchikey: inchikey.into(),
+                           property_type: format!("prop_{}", n),
+                           value: json!(n),
+                           quality: n.is_multiple_of(2).then(|| "calculated".to_string()),
+                           preferred: true,
+                           value_hash: format!("h{}", n),
+                           metadata: json!({ "n": n }) }
+}
+fn assert_bulk_inserts(repo: &DieselDomainRepository) {
+  let (m1, m2) = (molecule("ABCDEFGHIJKLMN-OPQRSTUVWX-5", "CCO"), molecule("ZYXWVUTSRQPONM-MLKJIHGFED-5", "CCN"));
+  repo.save_molecule(m1.clone()).expect("save m1");
+  let keys = repo.save_molecules_bulk(vec![m1.clone(), m2.clone(), m2.clone()]).expect("save_molecules_bulk");
+  assert_eq!(keys.into_iter().collect::<Result<Vec<_>, _>>().expect("keys"),
+             vec![m1.inchikey().to_string(), m2.inchikey().to_string(), m2.inchikey().to_string()],
+             "las existentes y las repetidas se ignoran como en save_molecule");
+  assert_eq!(repo.list_molecules().expect("list").len(), 2);
+  assert_eq!(repo.get_molecule(m2.inchikey()).expect("get").expect("m2").metadata()["source"],
+             json!("bulk"));
+
+  // más filas que un tramo, una propiedad ya guardada y una repetida
+  let existing = property(m1.inchikey(), 0);
+  repo.save_molecular_property(existing.clone()).expect("save existing");
+  let mut props: Vec<OwnedMolecularProperty> = (1..=250).map(|n| property(m1.inchikey(), n)).collect();
+  props.insert(10, existing.clone());
+  props.push(props[0].clone());
+  let results = repo.save_molecular_properties_bulk(props.clone()).expect("save_molecular_properties_bulk");
+  assert_eq!(results.len(), props.len());
+  for (i, (res, prop)) in results.iter().zip(&props).enumerate() {
+    match i {
+      10 | 251 => assert!(matches!(res, Err(DomainError::ValidationError(_))), "posición {}: {:?}", i, res),
+      _ => assert_eq!(res.as_ref().ok(), Some(&prop.id), "posición {}", i),
+    }
+  }
+  let stored = repo.get_molecular_properties(m1.inchikey()).expect("get properties");
+  assert_eq!(stored.len(), 251);
+  let p7 = stored.iter().find(|p| p.id == props[7].id).expect("prop 7");
+  assert_eq!((&p7.value, &p7.quality, &p7.metadata),
+             (&props[7].value, &props[7].quality, &props[7].metadata));
+  assert!(repo.save_molecular_properties_bulk(Vec::new()).expect("lote vacío").is_empty());
+}
+#[cfg(not(feature = "pg"))]
+#[test]
+fn diesel_sqlite_bulk_inserts() {
+  let url = format!("file:memdb_bulk_{}?mode=memory&cache=shared", Uuid::new_v4());
+  assert_bulk_inserts(&DieselDomainRepository::new_for_tenant(&url, "lab"));
+}
+#[cfg(feature = "pg")]
+#[test]
+fn diesel_pg_bulk_inserts() {
+  let Ok(url) = std::env::var("DATABASE_URL") else {
+    eprintln!("skipping pg bulk insert test: DATABASE_URL not set");
+    return;
+  };
+  // tenant propio: la base de test es compartida
+  assert_bulk_inserts(&DieselDomainRepository::new_for_tenant(&url, &format!("lab-{}", Uuid::new_v4())));
+}
@@ -0,0 +1,32 @@
+// Conformidad de los repositorios con el contrato de `FlowRepository`
+// usando la batería de `flow::testkit`: stub en memoria, SQLite (sin `pg`) y
+// Postgres (con `pg` y `DATABASE_URL` definida en el entorno; usar una base
+// dedicada a tests).
+use flow::repository::FlowRepository;
+use flow::stubs::InMemoryFlowRepository;
+use flow::testkit::run_conformance_suite;
+use std::sync::Arc;
+#[test]
+fn in_memory_repository_conforms() {
+  run_conformance_suite(|| Arc::new(InMemoryFlowRepository::new()) as Arc<dyn FlowRepository>);
+}
+#[cfg(not(feature = "pg"))]
+#[test]
+fn diesel_sqlite_repository_conforms() {
+  use chem_persistence::DieselFlowRepository;
+  run_conformance_suite(|| {
+    let url = format!("file:memdb_conf_{}?mode=memory&cache=shared", uuid::Uuid::new_v4());
+    Arc::new(DieselFlowRepository::new(&url)) as Arc<dyn FlowRepository>
+  });
+}
+#[cfg(feature = "pg")]
+#[test]
+fn diesel_pg_repository_conforms() {
+  use chem_persistence::DieselFlowRepository;
+  let Ok(url) = std::env::var("DATABASE_URL") else {
+    eprintln!("skipping pg conformance test: DATABASE_URL not set");
+    return;
+  };
+  let repo: Arc<dyn FlowRepository> = Arc::new(DieselFlowRepository::new_pg(&url).expect("create pg repo"));
+  run_conformance_suite(|| repo.clone());
+}
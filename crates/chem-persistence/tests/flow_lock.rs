@@ -0,0 +1,61 @@
+// Expiración y renovación de los locks de `flow_locks` en el backend SQLite,
+// también con TTLs de menos de un segundo. El contrato general de
+// `lock_for_update` se cubre en `conformance.rs`.
+#![cfg(not(feature = "pg"))]
+use chem_persistence::DieselFlowRepository;
+use flow::errors::FlowError;
+use flow::repository::FlowRepository;
+use serde_json::json;
+use std::time::Duration;
+const SHORT: Duration = Duration::from_millis(200);
+#[test]
+fn diesel_sqlite_expired_lock_is_taken_over() {
+  let url = format!("file:memdb_lock_ttl_{}?mode=memory&cache=shared", uuid::Uuid::new_v4());
+  let repo = DieselFlowRepository::new(&url).with_lock_ttl(Duration::ZERO);
+  let flow_id = repo.create_flow(None, None, json!({})).expect("create");
+  let stale = repo.lock_for_update(&flow_id, 0, SHORT).expect("lock").expect("guard");
+  // El lock anterior ya expiró (ttl 0): otro dueño puede tomarlo.
+  let fresh = repo.lock_for_update(&flow_id, 0, SHORT).expect("takeover").expect("guard");
+  // Liberar el guard viejo no borra el lock del nuevo dueño.
+  drop(stale);
+  drop(fresh);
+}
+#[test]
+fn diesel_sqlite_sub_second_lock_ttl() {
+  let url = format!("file:memdb_lock_ttl_ms_{}?mode=memory&cache=shared", uuid::Uuid::new_v4());
+  let repo = DieselFlowRepository::new(&url).with_lock_ttl(Duration::from_millis(500));
+  let flow_id = repo.create_flow(None, None, json!({})).expect("create");
+  let held = repo.lock_for_update(&flow_id, 0, SHORT).expect("lock").expect("guard");
+  // El TTL no se trunca a cero: el lock sigue vigente justo después.
+  assert!(matches!(repo.lock_for_update(&flow_id, 0, Duration::from_millis(50)), Err(FlowError::Conflict(_))));
+  // Vencido el TTL, otro dueño lo toma.
+  let fresh = repo.lock_for_update(&flow_id, 0, Duration::from_secs(2)).expect("takeover").expect("guard");
+  drop(held);
+  drop(fresh);
+}
+#[test]
+fn diesel_sqlite_renewed_lock_outlives_ttl() {
+  let url = format!("file:memdb_lock_renew_{}?mode=memory&cache=shared", uuid::Uuid::new_v4());
+  let repo = DieselFlowRepository::new(&url).with_lock_ttl(Duration::from_millis(300));
+  let flow_id = repo.create_flow(None, None, json!({})).expect("create");
+  let held = repo.lock_for_update(&flow_id, 0, SHORT).expect("lock").expect("guard");
+  for _ in 0..4 {
+    std::thread::sleep(Duration::from_millis(150));
+    held.renew().expect("renew");
+  }
+  assert!(matches!(repo.lock_for_update(&flow_id, 0, Duration::from_millis(50)), Err(FlowError::Conflict(_))));
+  // `keep_alive` renueva solo mientras dura el trabajo.
+  held.keep_alive(|| std::thread::sleep(Duration::from_millis(800)));
+  assert!(matches!(repo.lock_for_update(&flow_id, 0, Duration::from_millis(50)), Err(FlowError::Conflict(_))));
+  drop(held);
+}
+#[test]
+fn diesel_sqlite_renew_reports_lost_lock() {
+  let url = format!("file:memdb_lock_lost_{}?mode=memory&cache=shared", uuid::Uuid::new_v4());
+  let repo = DieselFlowRepository::new(&url).with_lock_ttl(Duration::ZERO);
+  let flow_id = repo.create_flow(None, None, json!({})).expect("create");
+  let stale = repo.lock_for_update(&flow_id, 0, SHORT).expect("lock").expect("guard");
+  let fresh = repo.lock_for_update(&flow_id, 0, SHORT).expect("takeover").expect("guard");
+  assert!(matches!(stale.renew(), Err(FlowError::Conflict(_))));
+  fresh.renew().expect("renew del dueño actual");
+}
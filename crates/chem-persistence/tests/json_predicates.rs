@@ -0,0 +1,80 @@
+// Predicados JSON sobre las propiedades (`find_molecular_properties`,
+// `find_family_properties`): se resuelven en la base, sobre JSONB en
+// Postgres (con `pg` y `DATABASE_URL`) y con `json_extract` en SQLite. Los
+// de flows y registros están en `flow::testkit::check_json_predicates`.
+use chem_domain::{DomainError, DomainRepository, OwnedFamilyProperty, OwnedMolecularProperty};
+use chem_persistence::DieselDomainRepository;
+use flow::json_predicate::JsonPredicate;
+use serde_json::{json, Value as JsonValue};
+use uuid::Uuid;
+fn pred(expr: &str) -> JsonPredicate {
+  expr.parse().expect("predicado JSON")
+}
+fn molecular(inchikey: &str, property_type: &str, value: JsonValue, metadata: JsonValue) -> OwnedMolecularProperty {
+  OwnedMolecularProperty { id: Uuid::new_v4(),
+                           molecule_inchikey: inchikey.into(),
+                           property_type: property_type.into(),
+                           value,
+                           quality: None,
+                           preferred: true,
+                           value_hash: "h".into(),
+                           metadata }
+}
+fn assert_property_predicates(repo: &DieselDomainRepository) {
+  let key = "ABCDEFGHIJKLMN-OPQRSTUVWX-4";
+  let xtb = repo.save_molecular_property(molecular(key, "logp", json!(1.5), json!({ "method": "xtb", "run": { "n": 1 } })))
+                .expect("save xtb");
+  let dft = repo.save_molecular_property(molecular(key, "logp", json!(2.0), json!({ "method": "dft" }))).expect("save dft");
+  let flag = repo.save_molecular_property(molecular(key, "toxic", json!(true), json!({}))).expect("save flag");
+  let ids = |preds: &[JsonPredicate]| -> Vec<Uuid> {
+    let mut ids: Vec<Uuid> =
+      repo.find_molecular_properties(preds).expect("find_molecular_properties").iter().map(|p| p.id).collect();
+    ids.sort();
+    ids
+  };
+  let sorted = |mut v: Vec<Uuid>| {
+    v.sort();
+    v
+  };
+  assert_eq!(ids(&[pred("metadata.method == \"xtb\"")]), vec![xtb]);
+  assert_eq!(ids(&[pred("metadata.run.n == 1")]), vec![xtb]);
+  assert_eq!(ids(&[pred("value.x == 1")]), Vec::<Uuid>::new(), "un escalar no tiene claves");
+  assert_eq!(ids(&[pred("metadata.method != \"xtb\"")]), sorted(vec![dft, flag]));
+  assert_eq!(ids(&[]), sorted(vec![xtb, dft, flag]));
+  let found = repo.find_molecular_properties(&[pred("metadata.method == \"dft\"")]).expect("find");
+  assert_eq!((found[0].value.clone(), found[0].property_type.as_str()), (json!(2.0), "logp"));
+  assert!(matches!(repo.find_molecular_properties(&[pred("payload.method == \"xtb\"")]),
+                   Err(DomainError::ValidationError(_))));
+  let family_id = Uuid::new_v4();
+  let prop = OwnedFamilyProperty { id: Uuid::new_v4(),
+                                   family_id,
+                                   property_type: "logp_mean".into(),
+                                   value: json!({ "mean": 1.75, "unit": "log" }),
+                                   quality: None,
+                                   preferred: true,
+                                   value_hash: "h".into(),
+                                   metadata: json!({ "method": "xtb" }) };
+  repo.save_family_property(prop.clone()).expect("save family property");
+  let found = repo.find_family_properties(&[pred("value.unit == \"log\""), pred("metadata.method == \"xtb\"")])
+                  .expect("find_family_properties");
+  assert_eq!(found.iter().map(|p| (p.id, p.family_id)).collect::<Vec<_>>(),
+             vec![(prop.id, family_id)]);
+  assert_eq!(found[0].value, prop.value);
+  assert!(repo.find_family_properties(&[pred("value.unit == \"kcal\"")]).expect("find").is_empty());
+}
+#[cfg(not(feature = "pg"))]
+#[test]
+fn diesel_sqlite_finds_properties_by_json_predicate() {
+  let url = format!("file:memdb_json_{}?mode=memory&cache=shared", Uuid::new_v4());
+  assert_property_predicates(&DieselDomainRepository::new_for_tenant(&url, "lab"));
+}
+#[cfg(feature = "pg")]
+#[test]
+fn diesel_pg_finds_properties_by_json_predicate() {
+  let Ok(url) = std::env::var("DATABASE_URL") else {
+    eprintln!("skipping pg json predicate test: DATABASE_URL not set");
+    return;
+  };
+  // tenant propio: la base de test es compartida
+  assert_property_predicates(&DieselDomainRepository::new_for_tenant(&url, &format!("lab-{}", Uuid::new_v4())));
+}
@@ -0,0 +1,222 @@
+// Decodificación de `FlowData` por key + versión de esquema con
+// `flow::PayloadRegistry`, sobre registros leídos del repositorio en memoria
+// y de SQLite (sin `pg`). Dos tipos de payload con los mismos campos no se
+// confunden y los desajustes se devuelven como `PayloadError`. También cubre
+// los upcasters: lectura de registros antiguos y su reescritura en lote con
+// `upcast_stored_payloads`.
+use flow::domain::FlowData;
+use flow::errors::{FlowError, PayloadError};
+use flow::payload::{upcast_stored_payloads, with_schema_version, FlowDataKey, PayloadRegistry, SchemaVersioned};
+use flow::repository::FlowRepository;
+use flow::stubs::InMemoryFlowRepository;
+use serde::Deserialize;
+use serde_json::{json, Value as JsonValue};
+use uuid::Uuid;
+#[derive(Debug, Deserialize, PartialEq)]
+struct FamilyRef {
+  family_uuid: Uuid,
+}
+#[derive(Debug, Deserialize, PartialEq)]
+struct FamilyCopy {
+  family_uuid: Uuid,
+}
+fn registry() -> PayloadRegistry {
+  let mut registry = PayloadRegistry::new();
+  registry.register::<FamilyRef>(FlowDataKey::step_state("Reference"), 1).expect("register");
+  registry.register::<FamilyCopy>(FlowDataKey::step_state("Copy"), 2).expect("register");
+  registry
+}
+fn append(repo: &dyn FlowRepository,
+          flow_id: Uuid,
+          cursor: i64,
+          key: &FlowDataKey,
+          payload: JsonValue,
+          metadata: JsonValue) {
+  let data = FlowData { id: Uuid::new_v4(),
+                        flow_id,
+                        cursor,
+                        key: key.to_string(),
+                        payload,
+                        metadata,
+                        command_id: None,
+                        created_at: chrono::Utc::now() };
+  repo.persist_data(&data, cursor - 1).expect("persist");
+}
+fn assert_decodes_by_key(repo: &dyn FlowRepository) {
+  let registry = registry();
+  let (reference, copy) = (Uuid::new_v4(), Uuid::new_v4());
+  let flow_id = repo.create_flow(None, None, json!({})).expect("create");
+  // registro anterior al registro de esquemas: sin `schema_version` es v1
+  append(repo,
+         flow_id,
+         1,
+         &FlowDataKey::step_state("Reference"),
+         json!({"family_uuid": reference}),
+         json!({}));
+  append(repo,
+         flow_id,
+         2,
+         &FlowDataKey::step_state("Copy"),
+         json!({"family_uuid": copy}),
+         with_schema_version(json!({"status": "ok"}), 2));
+  let data = repo.read_data(&flow_id, 0).expect("read");
+  assert_eq!(data[0].typed_key().step_name(), Some("Reference"));
+  assert_eq!(data[1].schema_version(), 2);
+  assert_eq!(data[1].metadata["status"], "ok");
+  // el registro más reciente encaja con ambos tipos, pero su key es de
+  // `FamilyCopy`
+  assert_eq!(registry.latest::<FamilyRef>(&data).expect("latest"),
+             Some(FamilyRef { family_uuid: reference }));
+  assert_eq!(registry.latest::<FamilyCopy>(&data).expect("latest"),
+             Some(FamilyCopy { family_uuid: copy }));
+  assert!(matches!(registry.decode::<FamilyRef>(&data[1]), Err(PayloadError::TypeMismatch { .. })));
+  assert_eq!(registry.latest::<String>(&data),
+             Err(PayloadError::UnregisteredType("alloc::string::String")));
+  append(repo,
+         flow_id,
+         3,
+         &FlowDataKey::step_state("Copy"),
+         json!({"family_uuid": copy}),
+         json!({}));
+  append(repo, flow_id, 4, &FlowDataKey::parse("input"), json!({}), json!({}));
+  let data = repo.read_data(&flow_id, 2).expect("read");
+  assert_eq!(registry.latest::<FamilyCopy>(&data),
+             Err(PayloadError::MissingUpcaster { key: "step_state:Copy".into(), from: 1 }));
+  assert_eq!(registry.decode::<FamilyRef>(&data[1]),
+             Err(PayloadError::UnknownKey("input".into())));
+  append(repo,
+         flow_id,
+         5,
+         &FlowDataKey::step_state("Reference"),
+         json!({"family": "x"}),
+         json!({}));
+  let data = repo.read_data(&flow_id, 4).expect("read");
+  assert!(matches!(registry.latest::<FamilyRef>(&data),
+                   Err(PayloadError::Decode { version: 1, .. })));
+}
+/// Payload en su versión 3: `family` (v1) pasó a `family_uuid` (v2) y se
+/// añadió `count` (v3).
+#[derive(Debug, Deserialize, PartialEq)]
+struct Counted {
+  family_uuid: Uuid,
+  count: u32,
+}
+impl SchemaVersioned for Counted {
+  const SCHEMA_VERSION: u32 = 3;
+  fn upcasters() -> Vec<(u32, flow::payload::Upcaster)> {
+    vec![(1,
+          |mut v| {
+            let family = v.get_mut("family").map(JsonValue::take).ok_or("falta family")?;
+            v["family_uuid"] = family;
+            v.as_object_mut().map(|o| o.remove("family"));
+            Ok(v)
+          }),
+         (2,
+          |mut v| {
+            v["count"] = json!(0);
+            Ok(v)
+          })]
+  }
+}
+fn versioned_registry() -> PayloadRegistry {
+  let mut registry = PayloadRegistry::new();
+  registry.register_versioned::<Counted>(FlowDataKey::step_state("Counted")).expect("register");
+  registry
+}
+fn assert_upcasts_and_rewrites(repo: &dyn FlowRepository) {
+  let registry = versioned_registry();
+  let key = FlowDataKey::step_state("Counted");
+  let family = Uuid::new_v4();
+  let flow_id = repo.create_flow(None, None, json!({})).expect("create");
+  append(repo, flow_id, 1, &key, json!({"family": family}), json!({"status": "ok"}));
+  append(repo,
+         flow_id,
+         2,
+         &key,
+         json!({"family_uuid": family}),
+         with_schema_version(json!({}), 2));
+  append(repo,
+         flow_id,
+         3,
+         &key,
+         json!({"family_uuid": family, "count": 7}),
+         with_schema_version(json!({}), 3));
+  let data = repo.read_data(&flow_id, 0).expect("read");
+  assert_eq!(registry.decode::<Counted>(&data[0]).expect("v1"),
+             Counted { family_uuid: family, count: 0 });
+  assert_eq!(registry.decode::<Counted>(&data[1]).expect("v2"),
+             Counted { family_uuid: family, count: 0 });
+  assert!(registry.upcast_data(&data[2]).expect("vigente").is_none());
+  let report = upcast_stored_payloads(repo, &registry).expect("upcast");
+  assert!(report.flows_scanned >= 1);
+  let data = repo.read_data(&flow_id, 0).expect("read");
+  assert!(data.iter().all(|d| d.schema_version() == 3));
+  assert_eq!(data[0].payload, json!({"family_uuid": family, "count": 0}));
+  assert_eq!(data[0].metadata["status"], "ok", "se conserva el resto de la metadata");
+  assert_eq!(data.iter().map(|d| d.cursor).collect::<Vec<_>>(), vec![1, 2, 3]);
+  assert_eq!(upcast_stored_payloads(repo, &registry).expect("upcast again").rows_rewritten,
+             0,
+             "la migración es idempotente");
+  // un registro que el upcaster no sabe transformar
+  let broken = repo.create_flow(None, None, json!({})).expect("create");
+  append(repo, broken, 1, &key, json!({"otro": 1}), json!({}));
+  let data = repo.read_data(&broken, 0).expect("read");
+  assert!(matches!(registry.decode::<Counted>(&data[0]), Err(PayloadError::Upcast { from: 1, .. })));
+  assert!(matches!(upcast_stored_payloads(repo, &registry),
+                   Err(FlowError::Payload(PayloadError::Upcast { .. }))));
+  assert_eq!(repo.read_data(&broken, 0).expect("read")[0].payload, json!({"otro": 1}));
+  repo.delete_branch(&broken).expect("delete");
+}
+#[test]
+fn registry_rejects_a_second_type_for_a_key() {
+  let mut registry = registry();
+  assert!(matches!(registry.register::<FamilyCopy>(FlowDataKey::step_state("Reference"), 1),
+                   Err(PayloadError::TypeMismatch { .. })));
+  registry.register::<FamilyRef>(FlowDataKey::step_state("Reference"), 3).expect("nueva versión");
+  assert_eq!(registry.version_of(&FlowDataKey::step_state("Reference")), Some(3));
+  assert_eq!(FlowDataKey::parse("step_state:Reference"), FlowDataKey::step_state("Reference"));
+}
+#[test]
+fn registry_upcasts_through_each_version() {
+  let registry = versioned_registry();
+  let key = FlowDataKey::step_state("Counted");
+  assert_eq!(registry.upcast(&key, json!({"family": 1}), 1, 3),
+             Ok(json!({"family_uuid": 1, "count": 0})));
+  assert_eq!(registry.upcast(&key, json!({}), 3, 2),
+             Err(PayloadError::VersionMismatch { key: key.to_string(), found: 3, expected: 2 }));
+  assert_eq!(registry.upcast(&key, json!({}), 0, 3),
+             Err(PayloadError::MissingUpcaster { key: key.to_string(), from: 0 }));
+}
+#[test]
+fn in_memory_payloads_decode_by_key_and_version() {
+  assert_decodes_by_key(&InMemoryFlowRepository::new());
+}
+#[test]
+fn in_memory_stored_payloads_are_upcast() {
+  assert_upcasts_and_rewrites(&InMemoryFlowRepository::new());
+}
+#[cfg(not(feature = "pg"))]
+#[test]
+fn diesel_sqlite_payloads_decode_by_key_and_version() {
+  use chem_persistence::DieselFlowRepository;
+  let url = format!("file:memdb_payloads_{}?mode=memory&cache=shared", Uuid::new_v4());
+  assert_decodes_by_key(&DieselFlowRepository::new(&url));
+}
+#[cfg(not(feature = "pg"))]
+#[test]
+fn diesel_sqlite_stored_payloads_are_upcast() {
+  use chem_persistence::DieselFlowRepository;
+  let url = format!("file:memdb_upcast_{}?mode=memory&cache=shared", Uuid::new_v4());
+  assert_upcasts_and_rewrites(&DieselFlowRepository::new(&url));
+}
+#[cfg(feature = "pg")]
+#[test]
+fn diesel_pg_stored_payloads_are_upcast() {
+  use chem_persistence::DieselFlowRepository;
+  let Ok(url) = std::env::var("DATABASE_URL") else {
+    eprintln!("skipping pg payload upcast test: DATABASE_URL not set");
+    return;
+  };
+  let repo = DieselFlowRepository::new_pg(&url).expect("create pg repo");
+  assert_upcasts_and_rewrites(&repo);
+}
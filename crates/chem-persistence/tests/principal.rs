@@ -0,0 +1,118 @@
+// Principal y autorización (`flow::principal`, `PrincipalDomainRepository`):
+// los flows se crean con `created_by`, cada `FlowData` lleva el principal en
+// su metadata y el `Authorizer` rechaza operaciones por rol antes de llegar
+// al repositorio. Se ejecuta en memoria, sobre SQLite (sin `pg`) y sobre
+// Postgres (con `pg` y `DATABASE_URL`).
+use chem_domain::{DomainError, DomainRepository, InMemoryDomainRepository, Molecule};
+use chem_persistence::PrincipalDomainRepository;
+use chrono::Utc;
+use flow::domain::{FlowData, PersistResult};
+use flow::errors::FlowError;
+use flow::principal::{principal_of, AllowAll, Authorizer, Principal, PrincipalFlowRepository, RoleAuthorizer};
+use flow::repository::FlowRepository;
+use flow::stubs::InMemoryFlowRepository;
+use flow::testkit::run_conformance_suite;
+use serde_json::json;
+use std::sync::Arc;
+use uuid::Uuid;
+fn step(flow_id: Uuid, cursor: i64) -> FlowData {
+  FlowData { id: Uuid::new_v4(),
+             flow_id,
+             cursor,
+             key: "step_state:FamilyReferenceStep".into(),
+             payload: json!({}),
+             metadata: json!({ "status": "ok" }),
+             command_id: None,
+             created_at: Utc::now() }
+}
+fn roles() -> Arc<dyn Authorizer> {
+  Arc::new(RoleAuthorizer::new().require("persist_data", ["chemist"])
+                                .require("delete_branch", ["admin"])
+                                .require("save_molecule", ["chemist"]))
+}
+fn assert_principals(flows: Arc<dyn FlowRepository>, domain: Arc<dyn DomainRepository>) {
+  let chemist = Principal::new("alice").with_role("chemist").with_tenant("lab-a");
+  let viewer = Principal::new("bob").with_role("viewer");
+  let as_chemist = PrincipalFlowRepository::new(flows.clone(), chemist.clone(), roles());
+  let as_viewer = PrincipalFlowRepository::new(flows.clone(), viewer, roles());
+  // created_by y metadata de cada registro
+  let flow_id = as_chemist.create_flow(Some("cadma".into()), None, json!({})).expect("create");
+  assert_eq!(flows.get_flow_meta(&flow_id).expect("meta").created_by.as_deref(),
+             Some("alice"));
+  let other = as_chemist.create_flow_as(None, None, json!({}), Some("scheduler".into())).expect("create_as");
+  assert_eq!(flows.get_flow_meta(&other).expect("meta").created_by.as_deref(),
+             Some("scheduler"));
+  assert!(matches!(as_chemist.persist_data(&step(flow_id, 1), 0),
+                   Ok(PersistResult::Ok { new_version: 1 })));
+  let stored = flows.read_data(&flow_id, 0).expect("read");
+  assert_eq!(stored[0].metadata["status"], json!("ok"));
+  assert_eq!(principal_of(&stored[0].metadata), Some(chemist.clone()));
+  // un rol sin permiso no llega al repositorio
+  match as_viewer.persist_data(&step(flow_id, 2), 1) {
+    Err(FlowError::Forbidden(msg)) => assert!(msg.contains("persist_data"), "{}", msg),
+    other => panic!("se esperaba Forbidden, obtenido {:?}", other),
+  }
+  assert_eq!(flows.get_flow_meta(&flow_id).expect("meta").current_version, 1);
+  assert_eq!(as_viewer.read_data(&flow_id, 0).expect("read viewer").len(), 1);
+  assert!(matches!(as_chemist.delete_branch(&other), Err(FlowError::Forbidden(_))));
+  assert!(flows.branch_exists(&other).expect("exists"));
+  // un closure como authorizer: solo principals del tenant lab-a
+  let same_tenant: Arc<dyn Authorizer> =
+    Arc::new(|p: &Principal, op: &str, _flow: Option<&Uuid>| match p.tenant.as_deref() {
+      Some("lab-a") => Ok(()),
+      _ => Err(FlowError::Forbidden(format!("{} fuera del tenant lab-a", op))),
+    });
+  let outsider = PrincipalFlowRepository::new(flows.clone(), Principal::new("eve"), same_tenant.clone());
+  assert!(matches!(outsider.get_flow_meta(&flow_id), Err(FlowError::Forbidden(_))));
+  let insider = PrincipalFlowRepository::new(flows, chemist.clone(), same_tenant);
+  assert_eq!(insider.count_steps(&flow_id).expect("count"), 1);
+  // dominio
+  let molecule = Molecule::from_parts("ABCDEFGHIJKLMN-OPQRSTUVWX-2",
+                                      "CCN",
+                                      "InChI=1S/C2H7N/c1-2-3/h2-3H2,1H3",
+                                      json!({})).expect("molecule");
+  let viewer_domain = PrincipalDomainRepository::new(domain.clone(), Principal::new("bob").with_role("viewer"), roles());
+  assert!(matches!(viewer_domain.save_molecule(molecule.clone()), Err(DomainError::Forbidden(_))));
+  // la regla de save_molecule también cubre el guardado en bloque
+  match viewer_domain.save_molecules_bulk(vec![molecule.clone()]) {
+    Err(DomainError::Forbidden(msg)) => assert!(msg.contains("save_molecule"), "{}", msg),
+    other => panic!("se esperaba Forbidden, obtenido {:?}", other),
+  }
+  assert!(domain.get_molecule(molecule.inchikey()).expect("get").is_none());
+  let chemist_domain = PrincipalDomainRepository::new(domain.clone(), chemist, roles());
+  chemist_domain.save_molecule(molecule.clone()).expect("save_molecule");
+  assert!(viewer_domain.get_molecule(molecule.inchikey()).expect("get viewer").is_some());
+  domain.delete_molecule(molecule.inchikey()).expect("cleanup");
+}
+#[test]
+fn in_memory_repositories_check_principals() {
+  assert_principals(Arc::new(InMemoryFlowRepository::new()),
+                    Arc::new(InMemoryDomainRepository::new()));
+}
+#[test]
+fn principal_flow_repository_conforms() {
+  run_conformance_suite(|| {
+    Arc::new(PrincipalFlowRepository::new(Arc::new(InMemoryFlowRepository::new()),
+                                          Principal::new("conformance"),
+                                          Arc::new(AllowAll))) as Arc<dyn FlowRepository>
+  });
+}
+#[cfg(not(feature = "pg"))]
+#[test]
+fn diesel_sqlite_repositories_check_principals() {
+  use chem_persistence::{DieselDomainRepository, DieselFlowRepository};
+  let url = format!("file:memdb_principal_{}?mode=memory&cache=shared", Uuid::new_v4());
+  assert_principals(Arc::new(DieselFlowRepository::new(&url)),
+                    Arc::new(DieselDomainRepository::new(&url)));
+}
+#[cfg(feature = "pg")]
+#[test]
+fn diesel_pg_repositories_check_principals() {
+  use chem_persistence::{DieselDomainRepository, DieselFlowRepository};
+  let Ok(url) = std::env::var("DATABASE_URL") else {
+    eprintln!("skipping pg principal test: DATABASE_URL not set");
+    return;
+  };
+  assert_principals(Arc::new(DieselFlowRepository::new_pg(&url).expect("create pg repo")),
+                    Arc::new(DieselDomainRepository::new(&url)));
+}
@@ -0,0 +1,244 @@
+// Stores de blobs de snapshot: ficheros y memoria (`flow::snapshot_store`),
+// tabla `snapshot_blobs` sobre SQLite (sin `pg`) o Postgres (`DATABASE_URL`),
+// S3 (feature `s3`) contra un servidor falso en proceso y, con
+// `S3_ENDPOINT`, contra un MinIO real. También cubre la migración de los
+// snapshots inline antiguos (base64 en `state_ptr`) y la retención
+// (`compact_snapshots`/`gc_snapshots`) con
+// `flow::testkit::check_snapshot_retention`.
+use base64::Engine;
+use flow::errors::FlowError;
+use flow::repository::{FlowRepository, SnapshotStore};
+use flow::stubs::InMemoryFlowRepository;
+use flow::testkit::{check_snapshot_retention, retention_for_checks};
+use flow::{FsSnapshotStore, InMemorySnapshotStore};
+use serde_json::json;
+use std::sync::Arc;
+fn assert_round_trip(store: &dyn SnapshotStore) {
+  let key = store.save(b"{\"state\": 1}").expect("save");
+  assert_eq!(store.load(&key).expect("load"), b"{\"state\": 1}");
+  assert_ne!(store.save(b"{\"state\": 1}").expect("save 2"),
+             key,
+             "cada save genera una key nueva");
+  assert!(matches!(store.load("nope.bin"), Err(FlowError::NotFound(_))));
+  assert!(store.list().expect("list").iter().any(|b| b.key == key));
+  store.delete(&key).expect("delete");
+  assert!(matches!(store.load(&key), Err(FlowError::NotFound(_))));
+  assert!(!store.list().expect("list").iter().any(|b| b.key == key));
+  store.delete(&key).expect("borrar una key inexistente no es error");
+}
+/// Directorio temporal para un `FsSnapshotStore` propio de cada test.
+fn temp_fs_store() -> Arc<FsSnapshotStore> {
+  let dir = std::env::temp_dir().join(format!("snapstore_{}", uuid::Uuid::new_v4()));
+  Arc::new(FsSnapshotStore::new(dir).expect("fs store"))
+}
+#[test]
+fn memory_and_fs_stores_round_trip() {
+  assert_round_trip(&InMemorySnapshotStore::new());
+  let dir = std::env::temp_dir().join(format!("snapstore_{}", uuid::Uuid::new_v4()));
+  let fs_store = FsSnapshotStore::new(&dir).expect("fs store");
+  assert_round_trip(&fs_store);
+  assert!(fs_store.load("../etc/passwd").is_err());
+  std::fs::remove_dir_all(dir).ok();
+}
+#[test]
+fn in_memory_snapshot_retention() {
+  check_snapshot_retention(Arc::new(InMemoryFlowRepository::new().with_snapshot_retention(retention_for_checks())));
+  let repo =
+    InMemoryFlowRepository::new().with_snapshot_store(temp_fs_store()).with_snapshot_retention(retention_for_checks());
+  check_snapshot_retention(Arc::new(repo));
+}
+/// Guarda un snapshot con el formato anterior, lo migra al store del
+/// repositorio y comprueba que se sigue leyendo igual.
+fn assert_migrates_inline(repo: &chem_persistence::DieselFlowRepository) {
+  let flow_id = repo.create_flow(None, None, json!({})).expect("create");
+  let state = json!({"current_step": 3});
+  let inline = base64::engine::general_purpose::STANDARD.encode(state.to_string());
+  let sid = repo.save_snapshot(&flow_id, 3, &inline, json!({})).expect("inline");
+  assert!(repo.migrate_inline_snapshots().expect("migrate") >= 1);
+  let (bytes, meta) = repo.load_snapshot(&sid).expect("load");
+  assert_ne!(meta.state_ptr, inline);
+  assert_eq!(SnapshotStore::load(repo, &meta.state_ptr).expect("blob en el store"), bytes);
+  assert_eq!(serde_json::from_slice::<serde_json::Value>(&bytes).expect("json"), state);
+  assert_eq!(repo.migrate_inline_snapshots().expect("migrate again"),
+             0,
+             "la migración es idempotente");
+}
+#[cfg(not(feature = "pg"))]
+#[test]
+fn diesel_sqlite_db_store_and_migration() {
+  use chem_persistence::DieselFlowRepository;
+  let url = format!("file:memdb_snapstore_{}?mode=memory&cache=shared", uuid::Uuid::new_v4());
+  let repo = DieselFlowRepository::new(&url);
+  let store = repo.db_snapshot_store();
+  assert_round_trip(&store);
+  let repo = repo.with_snapshot_store(Arc::new(store));
+  let flow_id = repo.create_flow(None, None, json!({})).expect("create");
+  let sid = repo.save_snapshot_state(&flow_id, 1, b"{\"a\": 1}", json!({})).expect("save state");
+  assert_eq!(repo.load_snapshot(&sid).expect("load").0, b"{\"a\": 1}");
+  assert_migrates_inline(&repo);
+}
+#[cfg(not(feature = "pg"))]
+#[test]
+fn diesel_sqlite_snapshot_retention() {
+  use chem_persistence::DieselFlowRepository;
+  let memdb = || format!("file:memdb_retention_{}?mode=memory&cache=shared", uuid::Uuid::new_v4());
+  let repo =
+    DieselFlowRepository::new(&memdb()).with_snapshot_store(temp_fs_store()).with_snapshot_retention(retention_for_checks());
+  check_snapshot_retention(Arc::new(repo));
+  let repo = DieselFlowRepository::new(&memdb());
+  let store = Arc::new(repo.db_snapshot_store());
+  check_snapshot_retention(Arc::new(repo.with_snapshot_store(store).with_snapshot_retention(retention_for_checks())));
+}
+#[cfg(feature = "pg")]
+#[test]
+fn diesel_pg_db_store_and_migration() {
+  use chem_persistence::DieselFlowRepository;
+  let Ok(url) = std::env::var("DATABASE_URL") else {
+    eprintln!("skipping pg snapshot store test: DATABASE_URL not set");
+    return;
+  };
+  let repo = DieselFlowRepository::new_pg(&url).expect("create pg repo");
+  let store = repo.db_snapshot_store();
+  assert_round_trip(&store);
+  let repo = repo.with_snapshot_store(Arc::new(store));
+  assert_migrates_inline(&repo);
+}
+#[cfg(feature = "pg")]
+#[test]
+fn diesel_pg_snapshot_retention() {
+  use chem_persistence::DieselFlowRepository;
+  let Ok(url) = std::env::var("DATABASE_URL") else {
+    eprintln!("skipping pg snapshot retention test: DATABASE_URL not set");
+    return;
+  };
+  let repo = DieselFlowRepository::new_pg(&url).expect("create pg repo")
+                                               .with_snapshot_store(temp_fs_store())
+                                               .with_snapshot_retention(retention_for_checks());
+  check_snapshot_retention(Arc::new(repo));
+}
+#[cfg(feature = "s3")]
+mod s3 {
+  use super::assert_round_trip;
+  use chem_persistence::{S3Config, S3SnapshotStore};
+  use flow::repository::SnapshotStore;
+  use std::collections::HashMap;
+  use std::io::{Read, Write};
+  use std::net::TcpListener;
+  use std::sync::{Arc, Mutex};
+  fn percent_decode(value: &str) -> String {
+    let bytes = value.as_bytes();
+    let mut out = Vec::new();
+    let mut i = 0;
+    while i < bytes.len() {
+      if bytes[i] == b'%' && i + 2 < bytes.len() {
+        out.push(u8::from_str_radix(&value[i + 1..i + 3], 16).expect("hex"));
+        i += 3;
+      } else {
+        out.push(bytes[i]);
+        i += 1;
+      }
+    }
+    String::from_utf8(out).expect("utf8")
+  }
+  /// Página de `ListObjectsV2` con un solo objeto por página, para ejercitar
+  /// los `continuation-token`.
+  fn list_page(objects: &HashMap<String, Vec<u8>>, bucket_path: &str, query: &str) -> Vec<u8> {
+    let params: HashMap<String, String> =
+      query.split('&').filter_map(|kv| kv.split_once('=')).map(|(k, v)| (k.to_string(), percent_decode(v))).collect();
+    let prefix = format!("{}/{}", bucket_path, params.get("prefix").cloned().unwrap_or_default());
+    let mut keys: Vec<String> = objects.keys()
+                                       .filter(|p| p.starts_with(&prefix))
+                                       .map(|p| p[bucket_path.len() + 1..].to_string())
+                                       .filter(|k| params.get("continuation-token").is_none_or(|t| k > t))
+                                       .collect();
+    keys.sort();
+    let mut xml = String::from("<ListBucketResult>");
+    if let Some(key) = keys.first() {
+      xml.push_str(&format!("<Contents><Key>{}</Key><LastModified>2000-01-01T00:00:00.000Z</LastModified></Contents>",
+                            key));
+      if keys.len() > 1 {
+        xml.push_str(&format!("<IsTruncated>true</IsTruncated><NextContinuationToken>{}</NextContinuationToken>",
+                              key));
+      }
+    }
+    xml.push_str("</ListBucketResult>");
+    xml.into_bytes()
+  }
+  /// Servidor S3 mínimo: guarda los PUT y responde GET, DELETE y
+  /// `ListObjectsV2` de peticiones firmadas con SigV4; 404 si el objeto no
+  /// existe.
+  fn fake_s3() -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").expect("bind");
+    let addr = listener.local_addr().expect("addr");
+    let objects: Arc<Mutex<HashMap<String, Vec<u8>>>> = Arc::default();
+    std::thread::spawn(move || {
+      for stream in listener.incoming() {
+        let Ok(mut stream) = stream else { continue };
+        let mut raw = Vec::new();
+        let mut buf = [0u8; 4096];
+        let (head, mut body) = loop {
+          let n = stream.read(&mut buf).expect("read");
+          raw.extend_from_slice(&buf[..n]);
+          if let Some(pos) = raw.windows(4).position(|w| w == b"\r\n\r\n") {
+            break (String::from_utf8_lossy(&raw[..pos]).to_string(), raw[pos + 4..].to_vec());
+          }
+        };
+        let len = head.lines()
+                      .find_map(|l| l.to_ascii_lowercase().strip_prefix("content-length:").map(|v| v.trim().to_string()))
+                      .and_then(|v| v.parse::<usize>().ok())
+                      .unwrap_or(0);
+        while body.len() < len {
+          let n = stream.read(&mut buf).expect("read body");
+          body.extend_from_slice(&buf[..n]);
+        }
+        let mut parts = head.split_whitespace();
+        let method = parts.next().unwrap_or_default();
+        let target = parts.next().unwrap_or_default();
+        let (path, query) = target.split_once('?').unwrap_or((target, ""));
+        let path = path.to_string();
+        let signed = head.contains("Authorization: AWS4-HMAC-SHA256 Credential=minio/");
+        let (status, payload) = match (signed, method) {
+          (false, _) => ("403 Forbidden", Vec::new()),
+          (true, "PUT") => {
+            objects.lock().unwrap().insert(path, body);
+            ("200 OK", Vec::new())
+          }
+          (true, "DELETE") => {
+            objects.lock().unwrap().remove(&path);
+            ("204 No Content", Vec::new())
+          }
+          (true, "GET") if !query.is_empty() => ("200 OK", list_page(&objects.lock().unwrap(), &path, query)),
+          (true, "GET") => match objects.lock().unwrap().get(&path) {
+            Some(obj) => ("200 OK", obj.clone()),
+            None => ("404 Not Found", b"NoSuchKey".to_vec()),
+          },
+          _ => ("405 Method Not Allowed", Vec::new()),
+        };
+        let head = format!("HTTP/1.1 {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                           status,
+                           payload.len());
+        let _ = stream.write_all(head.as_bytes()).and_then(|_| stream.write_all(&payload));
+      }
+    });
+    format!("http://{}", addr)
+  }
+  #[test]
+  fn s3_store_round_trip_against_fake_server() {
+    let store = S3SnapshotStore::new(S3Config { endpoint: fake_s3(),
+                                                bucket: "snapshots".into(),
+                                                region: "us-east-1".into(),
+                                                access_key: "minio".into(),
+                                                secret_key: "minio123".into(),
+                                                prefix: "snapshots/".into() }).expect("store");
+    assert_round_trip(&store);
+    assert!(store.load("../otro-bucket/x").is_err());
+  }
+  #[test]
+  fn s3_store_round_trip_against_minio() {
+    if std::env::var("S3_ENDPOINT").is_err() {
+      eprintln!("skipping MinIO snapshot store test: S3_ENDPOINT not set");
+      return;
+    }
+    assert_round_trip(&S3SnapshotStore::from_env().expect("store"));
+  }
+}
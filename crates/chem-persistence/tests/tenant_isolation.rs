@@ -0,0 +1,168 @@
+// Aislamiento por tenant (columna `tenant_id`): dos repositorios sobre la
+// misma base, cada uno para su tenant, no ven los flows, ramas, cola de
+// trabajo, auditoría, moléculas, familias ni propiedades del otro. Se
+// ejecuta sobre SQLite (sin `pg`) y sobre Postgres (con `pg` y
+// `DATABASE_URL`; con `async-pg` también el backend async).
+use chem_domain::{DomainError, DomainRepository, Molecule, MoleculeFamily, OwnedFamilyProperty};
+use chrono::Utc;
+use flow::audit::{AuditQuery, AuditedFlowRepository};
+use flow::domain::{FlowData, PersistResult};
+use flow::errors::FlowError;
+use flow::repository::{AuditLog, FlowRepository};
+use serde_json::json;
+use std::sync::Arc;
+use uuid::Uuid;
+fn step(flow_id: Uuid, cursor: i64) -> FlowData {
+  FlowData { id: Uuid::new_v4(),
+             flow_id,
+             cursor,
+             key: "step_state:FamilyReferenceStep".into(),
+             payload: json!({ "cursor": cursor }),
+             metadata: json!({}),
+             command_id: None,
+             created_at: Utc::now() }
+}
+fn assert_flows_isolated(a: Arc<dyn FlowRepository>, b: Arc<dyn FlowRepository>) {
+  let flow_id = a.create_flow(Some("cadma".into()), None, json!({})).expect("create");
+  assert!(matches!(a.persist_data(&step(flow_id, 1), 0), Ok(PersistResult::Ok { new_version: 1 })));
+  assert!(matches!(a.persist_data(&step(flow_id, 2), 1), Ok(PersistResult::Ok { new_version: 2 })));
+  let branch = a.create_branch(&flow_id, 1, json!({})).expect("branch");
+  assert!(a.list_flow_ids().expect("ids a").contains(&flow_id));
+  let ids_b = b.list_flow_ids().expect("ids b");
+  assert!(!ids_b.contains(&flow_id) && !ids_b.contains(&branch));
+  assert!(matches!(b.get_flow_meta(&flow_id), Err(FlowError::NotFound(_))));
+  assert!(!b.branch_exists(&branch).expect("exists"));
+  assert!(b.read_data(&flow_id, 0).expect("read b").is_empty());
+  assert!(b.read_data(&branch, 0).expect("read branch b").is_empty());
+  assert_eq!(b.count_steps(&flow_id).expect("count b"), -1);
+  assert!(matches!(b.persist_data(&step(flow_id, 3), 2), Err(FlowError::NotFound(_))));
+  assert!(b.create_branch(&flow_id, 1, json!({})).is_err());
+  assert!(matches!(b.set_meta(&flow_id, "k", json!(1)), Err(FlowError::NotFound(_))));
+  assert!(b.delete_branch(&flow_id).is_err());
+  assert!(b.dump_tables_for_debug().expect("dump b").1.iter().all(|d| d.flow_id != flow_id));
+  // nada de lo anterior tocó el flow de a
+  assert_eq!(a.read_data(&branch, 0).expect("read branch a").len(), 1);
+  assert_eq!(a.count_steps(&flow_id).expect("count a"), 2);
+  assert_eq!(a.list_children(&flow_id).expect("children").len(), 1);
+  // cola de trabajo
+  a.enqueue_work(&flow_id).expect("enqueue");
+  assert!(matches!(b.enqueue_work(&flow_id), Err(FlowError::NotFound(_))));
+  assert!(b.claim_work("worker-b").expect("claim b").is_none());
+  let item = a.claim_work("worker-a").expect("claim a").expect("item");
+  assert_eq!(item.flow_id, flow_id);
+  assert!(!b.complete_work(&flow_id, "worker-a").expect("complete b"));
+  assert!(a.complete_work(&flow_id, "worker-a").expect("complete a"));
+}
+fn assert_audit_isolated(a: Arc<dyn FlowRepository>, log_a: Arc<dyn AuditLog>, log_b: Arc<dyn AuditLog>) {
+  let actor = format!("alice-{}", Uuid::new_v4());
+  let audited = AuditedFlowRepository::new(a, log_a.clone()).with_actor(actor.clone());
+  audited.create_flow(None, None, json!({})).expect("create");
+  assert_eq!(log_a.query(&AuditQuery::new().actor(actor.clone())).expect("query a").len(), 1);
+  assert!(log_b.query(&AuditQuery::new().actor(actor)).expect("query b").is_empty());
+}
+fn assert_domain_isolated(a: Arc<dyn DomainRepository>, b: Arc<dyn DomainRepository>) {
+  // la misma InChIKey con otro contenido en cada tenant
+  let in_a = Molecule::from_parts("ABCDEFGHIJKLMN-OPQRSTUVWX-3",
+                                  "CCO",
+                                  "InChI=1S/C2H6O/c1-2-3/h3H,2H2,1H3",
+                                  json!({})).expect("molecule a");
+  let in_b = Molecule::from_parts("ABCDEFGHIJKLMN-OPQRSTUVWX-3",
+                                  "CCN",
+                                  "InChI=1S/C2H7N/c1-2-3/h3H,2H2,1H3",
+                                  json!({})).expect("molecule b");
+  let only_a = Molecule::from_parts("ZYXWVUTSRQPONM-MLKJIHGFED-3",
+                                    "CCC",
+                                    "InChI=1S/C3H8/c1-3-2/h3H2,1-2H3",
+                                    json!({})).expect("molecule");
+  a.save_molecule(in_a.clone()).expect("save a");
+  a.save_molecule(only_a.clone()).expect("save only a");
+  assert!(b.get_molecule(in_a.inchikey()).expect("get b").is_none());
+  b.save_molecule(in_b.clone()).expect("save b");
+  assert_eq!(a.get_molecule(in_a.inchikey()).expect("get a").expect("a").smiles(), "CCO");
+  assert_eq!(b.get_molecule(in_b.inchikey()).expect("get b").expect("b").smiles(), "CCN");
+  assert!(b.get_molecule(only_a.inchikey()).expect("get only a").is_none());
+  let listed_b: Vec<String> = b.list_molecules().expect("list b").iter().map(|m| m.smiles().to_string()).collect();
+  assert_eq!(listed_b, vec!["CCN".to_string()]);
+  // familias: el mismo id en b no pisa la de a
+  let family = MoleculeFamily::new(vec![in_a.clone(), only_a.clone()], json!({})).expect("family").with_name("lab-a");
+  let family_id = a.save_family(family.clone()).expect("save family");
+  assert!(b.get_family(&family_id).expect("get family b").is_none());
+  assert!(b.list_families().expect("families b").is_empty());
+  assert!(matches!(b.add_molecule_to_family(&family_id, in_b.clone()),
+                   Err(DomainError::ExternalError(_))));
+  let in_b_family =
+    MoleculeFamily::new(vec![in_b.clone()], json!({})).expect("family b").with_name("lab-b").with_id(family_id);
+  b.save_family(in_b_family).expect("save family b");
+  let stored_a = a.get_family(&family_id).expect("get family a").expect("family a");
+  assert_eq!(stored_a.name(), Some("lab-a"));
+  assert_eq!(stored_a.molecules().len(), 2);
+  assert_eq!(stored_a.molecules()[0].smiles(), "CCO");
+  let stored_b = b.get_family(&family_id).expect("get family b").expect("family b");
+  assert_eq!(stored_b.molecules().len(), 1);
+  assert_eq!(stored_b.molecules()[0].smiles(), "CCN");
+  // la pertenencia de only_a en a no bloquea nada en b
+  b.save_molecule(only_a.clone()).expect("save only_a b");
+  b.delete_molecule(only_a.inchikey()).expect("delete b");
+  assert!(matches!(a.delete_molecule(only_a.inchikey()), Err(DomainError::ValidationError(_))));
+  // propiedades de familia
+  let prop = OwnedFamilyProperty { id: Uuid::new_v4(),
+                                   family_id,
+                                   property_type: "logp_mean".into(),
+                                   value: json!(1.5),
+                                   quality: None,
+                                   preferred: true,
+                                   value_hash: "h".into(),
+                                   metadata: json!({}) };
+  a.save_family_property(prop).expect("save property");
+  assert_eq!(a.get_family_properties(&family_id).expect("props a").len(), 1);
+  assert!(b.get_family_properties(&family_id).expect("props b").is_empty());
+  b.delete_family(&family_id).expect("delete family b");
+  assert_eq!(a.get_family_properties(&family_id).expect("props a").len(), 1);
+  assert!(a.get_family(&family_id).expect("get family a").is_some());
+}
+#[cfg(not(feature = "pg"))]
+#[test]
+fn diesel_sqlite_repositories_are_isolated_by_tenant() {
+  use chem_persistence::{DieselDomainRepository, DieselFlowRepository};
+  let url = format!("file:memdb_tenant_{}?mode=memory&cache=shared", Uuid::new_v4());
+  let a = Arc::new(DieselFlowRepository::new_for_tenant(&url, "lab-a"));
+  let b = Arc::new(DieselFlowRepository::new_for_tenant(&url, "lab-b"));
+  assert_eq!(a.tenant(), "lab-a");
+  assert_flows_isolated(a.clone(), b.clone());
+  assert_audit_isolated(a.clone(), Arc::new(a.db_audit_log()), Arc::new(b.db_audit_log()));
+  assert_domain_isolated(Arc::new(DieselDomainRepository::new_for_tenant(&url, "lab-a")),
+                         Arc::new(DieselDomainRepository::new_for_tenant(&url, "lab-b")));
+}
+#[cfg(feature = "pg")]
+#[test]
+fn diesel_pg_repositories_are_isolated_by_tenant() {
+  use chem_persistence::{DieselDomainRepository, DieselFlowRepository};
+  let Ok(url) = std::env::var("DATABASE_URL") else {
+    eprintln!("skipping pg tenant test: DATABASE_URL not set");
+    return;
+  };
+  let (lab_a, lab_b) = (format!("lab-a-{}", Uuid::new_v4()), format!("lab-b-{}", Uuid::new_v4()));
+  let a = Arc::new(DieselFlowRepository::new_pg_for_tenant(&url, &lab_a).expect("create pg repo"));
+  let b = Arc::new(DieselFlowRepository::new_pg_for_tenant(&url, &lab_b).expect("create pg repo"));
+  assert_flows_isolated(a.clone(), b.clone());
+  assert_audit_isolated(a.clone(), Arc::new(a.db_audit_log()), Arc::new(b.db_audit_log()));
+  assert_domain_isolated(Arc::new(DieselDomainRepository::new_for_tenant(&url, &lab_a)),
+                         Arc::new(DieselDomainRepository::new_for_tenant(&url, &lab_b)));
+}
+#[cfg(feature = "async-pg")]
+#[test]
+fn async_pg_repository_is_isolated_by_tenant() {
+  use chem_persistence::AsyncPgFlowRepository;
+  use flow::BlockingFlowAdapter;
+  let Ok(url) = std::env::var("DATABASE_URL") else {
+    eprintln!("skipping async pg tenant test: DATABASE_URL not set");
+    return;
+  };
+  let rt = tokio::runtime::Runtime::new().expect("runtime");
+  let connect = |tenant: String| -> Arc<dyn FlowRepository> {
+    let pg = rt.block_on(AsyncPgFlowRepository::connect_for_tenant(&url, &tenant)).expect("connect");
+    Arc::new(BlockingFlowAdapter::new(Arc::new(pg), rt.handle().clone()))
+  };
+  assert_flows_isolated(connect(format!("lab-a-{}", Uuid::new_v4())),
+                        connect(format!("lab-b-{}", Uuid::new_v4())));
+}
@@ -0,0 +1,139 @@
+// Unidad de trabajo (`UnitOfWork`): lo que un alcance escribe en el dominio
+// y en el flow se confirma junto o no se confirma. Se ejecuta en memoria,
+// sobre SQLite (sin `pg`) y sobre Postgres (con `pg` y `DATABASE_URL`).
+use chem_domain::{DomainRepository, InMemoryDomainRepository, Molecule};
+use chem_persistence::{InMemoryUnitOfWork, UnitOfWork};
+use chrono::Utc;
+use flow::domain::{FlowData, PersistResult};
+use flow::errors::FlowError;
+use flow::repository::FlowRepository;
+use flow::stubs::InMemoryFlowRepository;
+use serde_json::json;
+use std::sync::Arc;
+use uuid::Uuid;
+fn molecule(inchikey: &str) -> Molecule {
+  Molecule::from_parts(inchikey, "CCO", "InChI=1S/C2H6O/c1-2-3/h3H,2H2,1H3", json!({})).expect("molecule")
+}
+fn data(flow_id: Uuid, cursor: i64) -> FlowData {
+  FlowData { id: Uuid::new_v4(),
+             flow_id,
+             cursor,
+             key: format!("step_state:paso{}", cursor),
+             payload: json!({ "cursor": cursor }),
+             metadata: json!({}),
+             command_id: None,
+             created_at: Utc::now() }
+}
+/// `flows` y `domain` son los repositorios sobre los que trabaja `uow`; solo
+/// se usan con el alcance ya cerrado.
+fn assert_unit_of_work(uow: &dyn UnitOfWork, flows: &dyn FlowRepository, domain: &dyn DomainRepository) {
+  let flow_id = flows.create_flow(Some("uow".into()), None, json!({})).expect("create_flow");
+  let (m1, m2, m3) = (molecule("AAAAAAAAAAAAAA-AAAAAAAAAA-6"),
+                      molecule("BBBBBBBBBBBBBB-BBBBBBBBBB-6"),
+                      molecule("CCCCCCCCCCCCCC-CCCCCCCCCC-6"));
+
+  // persist_data acepta el registro: se confirman ambas escrituras
+  let scope = uow.begin().expect("begin");
+  scope.domain_repo().save_molecule(m1.clone()).expect("save m1");
+  let res = scope.flow_repo().persist_data(&data(flow_id, 1), 0).expect("persist");
+  assert!(matches!(res, PersistResult::Ok { new_version: 1 }), "{:?}", res);
+  scope.commit().expect("commit");
+  assert!(domain.get_molecule(m1.inchikey()).expect("get m1").is_some());
+  assert_eq!(flows.read_data(&flow_id, 0).expect("read_data").len(), 1);
+
+  // versión obsoleta: Conflict y rollback, la molécula no queda
+  let scope = uow.begin().expect("begin");
+  scope.domain_repo().save_molecule(m2.clone()).expect("save m2");
+  let res = scope.flow_repo().persist_data(&data(flow_id, 2), 0).expect("persist");
+  assert!(matches!(res, PersistResult::Conflict), "{:?}", res);
+  scope.rollback().expect("rollback");
+  assert!(domain.get_molecule(m2.inchikey()).expect("get m2").is_none());
+
+  // soltar el alcance sin commit también deshace
+  let scope = uow.begin().expect("begin");
+  scope.domain_repo().save_molecule(m3.clone()).expect("save m3");
+  scope.flow_repo().persist_data(&data(flow_id, 2), 1).expect("persist");
+  drop(scope);
+  assert!(domain.get_molecule(m3.inchikey()).expect("get m3").is_none());
+  assert_eq!(flows.read_data(&flow_id, 0).expect("read_data").len(), 1);
+  assert_eq!(flows.get_flow_meta(&flow_id).expect("meta").current_version, 1);
+}
+#[test]
+fn in_memory_unit_of_work_commits_or_rolls_back() {
+  let (flows, domain) = (Arc::new(InMemoryFlowRepository::new()), Arc::new(InMemoryDomainRepository::new()));
+  let uow = InMemoryUnitOfWork::new(flows.clone(), domain.clone());
+  assert_unit_of_work(&uow, flows.as_ref(), domain.as_ref());
+
+  // lo escrito fuera del alcance sobrevive al rollback
+  let (outside, inside) = (molecule("DDDDDDDDDDDDDD-DDDDDDDDDD-6"), molecule("EEEEEEEEEEEEEE-EEEEEEEEEE-6"));
+  let other_flow = flows.create_flow(Some("fuera".into()), None, json!({})).expect("create_flow");
+  let scope = uow.begin().expect("begin");
+  scope.domain_repo().save_molecule(inside.clone()).expect("save inside");
+  domain.save_molecule(outside.clone()).expect("save outside");
+  flows.persist_data(&data(other_flow, 1), 0).expect("persist outside");
+  scope.rollback().expect("rollback");
+  assert!(domain.get_molecule(outside.inchikey()).expect("get").is_some());
+  assert!(domain.get_molecule(inside.inchikey()).expect("get").is_none());
+  assert_eq!(flows.read_data(&other_flow, 0).expect("read_data").len(), 1);
+
+  // y al commit, si no toca lo mismo que el alcance
+  let scope = uow.begin().expect("begin");
+  scope.domain_repo().save_molecule(inside.clone()).expect("save inside");
+  scope.flow_repo().persist_data(&data(other_flow, 2), 1).expect("persist inside");
+  let late = flows.create_flow(Some("tarde".into()), None, json!({})).expect("create_flow");
+  scope.commit().expect("commit");
+  assert!(domain.get_molecule(inside.inchikey()).expect("get").is_some());
+  assert!(flows.get_flow_meta(&late).is_ok());
+  assert_eq!(flows.get_flow_meta(&other_flow).expect("meta").current_version, 2);
+
+  // el mismo flow escrito dentro y fuera: Conflict y no se aplica nada
+  let (m4, m5) = (molecule("FFFFFFFFFFFFFF-FFFFFFFFFF-6"), molecule("GGGGGGGGGGGGGG-GGGGGGGGGG-6"));
+  let scope = uow.begin().expect("begin");
+  scope.domain_repo().save_molecule(m4.clone()).expect("save m4");
+  scope.flow_repo().persist_data(&data(other_flow, 3), 2).expect("persist inside");
+  flows.persist_data(&data(other_flow, 3), 2).expect("persist outside");
+  domain.save_molecule(m5.clone()).expect("save m5");
+  assert!(matches!(scope.commit(), Err(FlowError::Conflict(_))));
+  assert!(domain.get_molecule(m4.inchikey()).expect("get m4").is_none());
+  assert!(domain.get_molecule(m5.inchikey()).expect("get m5").is_some());
+  assert_eq!(flows.read_data(&other_flow, 0).expect("read_data").len(), 3);
+}
+#[cfg(not(feature = "pg"))]
+#[test]
+fn diesel_sqlite_unit_of_work_commits_or_rolls_back() {
+  use chem_persistence::{DieselDomainRepository, DieselFlowRepository, DieselUnitOfWork};
+  let url = format!("file:memdb_uow_{}?mode=memory&cache=shared", Uuid::new_v4());
+  let (flows, domain) = (Arc::new(DieselFlowRepository::new(&url)), Arc::new(DieselDomainRepository::new(&url)));
+  let uow = DieselUnitOfWork::new(flows.clone(), domain.clone());
+  assert_unit_of_work(&uow, flows.as_ref(), domain.as_ref());
+  // la cola y los locks no se usan dentro de la transacción
+  let flow_id = flows.create_flow(Some("queue".into()), None, json!({})).expect("create_flow");
+  let scope = uow.begin().expect("begin");
+  let scoped_flows = scope.flow_repo();
+  assert!(scoped_flows.enqueue_work(&flow_id).is_err());
+  assert!(scoped_flows.claim_work("w1").is_err());
+  assert!(scoped_flows.heartbeat_work(&flow_id, "w1").is_err());
+  assert!(scoped_flows.complete_work(&flow_id, "w1").is_err());
+  assert!(scoped_flows.lock_for_update(&flow_id, 0, std::time::Duration::from_millis(10)).is_err());
+  scope.rollback().expect("rollback");
+  // los repositorios de un alcance terminado no vuelven a la conexión
+  let scope = uow.begin().expect("begin");
+  let scoped = scope.domain_repo();
+  scope.commit().expect("commit");
+  assert!(scoped.list_molecules().is_err());
+}
+#[cfg(feature = "pg")]
+#[test]
+fn diesel_pg_unit_of_work_commits_or_rolls_back() {
+  use chem_persistence::{DieselDomainRepository, DieselFlowRepository, DieselUnitOfWork};
+  let Ok(url) = std::env::var("DATABASE_URL") else {
+    eprintln!("skipping pg unit of work test: DATABASE_URL not set");
+    return;
+  };
+  // tenant propio: la base de test es compartida
+  let tenant = format!("lab-{}", Uuid::new_v4());
+  let flows = Arc::new(DieselFlowRepository::new_pg_for_tenant(&url, &tenant).expect("create pg repo"));
+  let domain = Arc::new(DieselDomainRepository::new_for_tenant(&url, &tenant));
+  let uow = DieselUnitOfWork::new(flows.clone(), domain.clone());
+  assert_unit_of_work(&uow, flows.as_ref(), domain.as_ref());
+}
@@ -0,0 +1,58 @@
+// Expiración de leases de la cola de trabajo (`check_expired_work_lease` de
+// `flow::testkit`) con repositorios configurados con lease de duración cero,
+// y leases de menos de un segundo, que no deben truncarse a cero.
+// El resto del contrato de la cola se cubre en `conformance.rs`.
+use flow::repository::FlowRepository;
+use flow::stubs::InMemoryFlowRepository;
+use flow::testkit::check_expired_work_lease;
+use serde_json::json;
+use std::sync::Arc;
+use std::time::Duration;
+const SUB_SECOND: Duration = Duration::from_millis(300);
+/// Con un lease de `SUB_SECOND` el item sigue reclamado justo después del
+/// claim y vuelve a estar disponible cuando el lease vence.
+fn check_sub_second_lease(repo: &dyn FlowRepository) {
+  let flow_id = repo.create_flow(None, None, json!({})).expect("create");
+  repo.enqueue_work(&flow_id).expect("enqueue");
+  let first = repo.claim_work("w1").expect("claim").expect("item");
+  assert!(first.lease_expires_at > chrono::Utc::now());
+  assert!(repo.claim_work("w2").expect("claim").is_none(), "lease vigente");
+  std::thread::sleep(SUB_SECOND + Duration::from_millis(200));
+  let second = repo.claim_work("w2").expect("claim").expect("lease vencido");
+  assert_eq!((second.flow_id, second.attempts), (flow_id, 2));
+}
+#[test]
+fn in_memory_expired_lease_is_reclaimed() {
+  check_expired_work_lease(Arc::new(InMemoryFlowRepository::new().with_lease_duration(Duration::ZERO)));
+}
+#[cfg(not(feature = "pg"))]
+#[test]
+fn diesel_sqlite_expired_lease_is_reclaimed() {
+  use chem_persistence::DieselFlowRepository;
+  let url = format!("file:memdb_wq_{}?mode=memory&cache=shared", uuid::Uuid::new_v4());
+  check_expired_work_lease(Arc::new(DieselFlowRepository::new(&url).with_lease_duration(Duration::ZERO)));
+}
+#[test]
+fn in_memory_sub_second_lease() {
+  check_sub_second_lease(&InMemoryFlowRepository::new().with_lease_duration(SUB_SECOND));
+}
+#[cfg(not(feature = "pg"))]
+#[test]
+fn diesel_sqlite_sub_second_lease() {
+  use chem_persistence::DieselFlowRepository;
+  let url = format!("file:memdb_wq_ms_{}?mode=memory&cache=shared", uuid::Uuid::new_v4());
+  check_sub_second_lease(&DieselFlowRepository::new(&url).with_lease_duration(SUB_SECOND));
+}
+#[cfg(feature = "pg")]
+#[test]
+fn diesel_pg_sub_second_lease() {
+  use chem_persistence::DieselFlowRepository;
+  let Ok(url) = std::env::var("DATABASE_URL") else {
+    eprintln!("skipping pg work queue test: DATABASE_URL not set");
+    return;
+  };
+  // tenant propio: otros tests de la base compartida también reclaman items
+  let tenant = format!("wq-{}", uuid::Uuid::new_v4());
+  let repo = DieselFlowRepository::new_pg_for_tenant(&url, &tenant).expect("create pg repo");
+  check_sub_second_lease(&repo.with_lease_duration(SUB_SECOND));
+}
@@ -12,6 +12,7 @@ use chem_workflow::flows::cadma_flow::steps::{
   molecule_initial_step3::{GenerationMethod, Step3Input},
 };
 use chem_workflow::{factory::ChemicalWorkflowFactory, flows::cadma_flow::CadmaFlow, ChemicalFlowEngine};
+use flow::principal::{AllowAll, Principal};
 use flow::repository::FlowRepository;
 use serde_json::json;
 use std::error::Error;
@@ -71,9 +72,13 @@ fn select_flow_from_repo(repo: &dyn FlowRepository) -> Result<Option<Uuid>, Box<
 fn create_flow_interactive() -> Result<CadmaFlow, Box<dyn Error>> {
   let name = prompt("Nombre del flow (enter = cadma-demo): ")?;
   let flow_name = if name.trim().is_empty() { "cadma-demo".to_string() } else { name };
-  // ChemicalWorkflowFactory::create<T> crea y persiste el flow en la repo
-  let engine_box = ChemicalWorkflowFactory::create::<CadmaFlow>(flow_name)?;
-  println!("Flow creado: {}", engine_box.id());
+  // ChemicalWorkflowFactory::create_as<T> crea y persiste el flow en la repo
+  // en nombre del usuario del sistema (queda en `created_by` y en la
+  // metadata de cada paso)
+  let user = std::env::var("USER").unwrap_or_else(|_| "demo".to_string());
+  let principal = Principal::new(user).with_role("chemist");
+  let engine_box = ChemicalWorkflowFactory::create_as::<CadmaFlow>(flow_name, principal, Arc::new(AllowAll))?;
+  println!("Flow creado: {} (por {})", engine_box.id(), engine_box.principal().map(|p| p.user_id.as_str()).unwrap_or("-"));
   // Unbox para devolver la instancia concreta
   Ok(*engine_box)
 }
@@ -433,6 +438,18 @@ fn dump_flow_data(engine: &CadmaFlow) -> Result<(), Box<dyn Error>> {
   Ok(())
 }
 
+/// Reconstruir el estado del engine en un cursor pasado (snapshot + replay)
+fn show_state_at(engine: &CadmaFlow) -> Result<(), Box<dyn Error>> {
+  let cursor: i64 = prompt("Cursor a inspeccionar: ")?.trim().parse()?;
+  let at = engine.state_at(cursor)?;
+  match &at.snapshot {
+    Some(s) => println!("Desde snapshot {} (cursor {}) + {} registros", s.id, s.cursor, at.replayed),
+    None => println!("Sin snapshot: {} registros desde el inicio", at.replayed),
+  }
+  println!("{}", serde_json::to_string_pretty(&at.state)?);
+  Ok(())
+}
+
 fn list_families() -> Result<(), Box<dyn Error>> {
   let repo = new_domain_from_env()?;
   let fams = repo.list_families()?;
@@ -481,6 +498,7 @@ fn main() -> Result<(), Box<dyn Error>> {
     println!("9) Listar familias (dominio)");
     println!("a) Crear molécula (persistir en dominio)");
     println!("b) Crear familia desde moléculas existentes en dominio");
+    println!("c) Ver estado del flow en un cursor pasado");
     println!("0) Guardar snapshot");
     println!("q) Salir");
 
@@ -612,6 +630,15 @@ fn main() -> Result<(), Box<dyn Error>> {
           Err(e) => println!("Error guardando familia: {}", e),
         }
       }
+      "c" => {
+        if let Some(engine) = &maybe_engine {
+          if let Err(e) = show_state_at(engine) {
+            println!("Error reconstruyendo estado: {}", e);
+          }
+        } else {
+          println!("Carga o crea un flow primero.");
+        }
+      }
       "0" => {
         if let Some(engine) = &maybe_engine {
           save_snapshot(engine);
@@ -1,16 +1,25 @@
 // chemical_flow.rs
 use crate::step::{StepContext, StepInfo};
 use crate::{workflow_type::WorkflowType, WorkflowError};
-use base64::Engine;
 use chem_domain::DomainRepository;
+use chem_persistence::UnitOfWork;
 use chrono::Utc;
-use flow::domain::{FlowData, PersistResult};
+use flow::domain::{FlowData, PersistResult, SnapshotMeta};
+use flow::errors::FlowError;
+use flow::payload::{schema_version_of, with_schema_version, FlowDataKey, PayloadRegistry, Upcaster, ENGINE_STATE_KEY};
+use flow::principal::Principal;
 use flow::repository::FlowRepository;
+use flow::time_travel::{state_at, StateAt, StateReducer};
+use serde::{de::DeserializeOwned, Serialize};
 use serde_json::Value as JsonValue;
-use std::{error::Error, sync::Arc};
+use std::{error::Error, marker::PhantomData, sync::Arc, time::Duration};
 use uuid::Uuid;
 
 // ========== DEFINICIONES DE TIPOS ==========
+/// Espera máxima por el lock del flow al ejecutar un paso
+/// (`execute_and_persist_current_step`).
+pub const STEP_LOCK_TIMEOUT: Duration = Duration::from_secs(30);
+
 /// Estado público común a todos los engines
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum FlowStatus {
@@ -21,8 +30,55 @@ pub enum FlowStatus {
   Unknown,
 }
 
+/// Estado de un engine que se puede reconstruir reproduciendo sus
+/// `FlowData` (el reducer que usa `ChemicalFlowEngine::state_at`).
+pub trait ReplayableState: Serialize + DeserializeOwned + Default {
+  /// Aplica un registro del flow, ya migrado a la versión de esquema actual
+  /// de su key.
+  fn apply_data(&mut self, data: &FlowData) -> Result<(), WorkflowError>;
+}
+
+/// `StateReducer` de un engine: migra el estado de los snapshots con
+/// `state_upcasters` y cada registro con el `PayloadRegistry` del engine
+/// antes de pasárselo a `ReplayableState::apply_data`.
+struct EngineReducer<S> {
+  registry: PayloadRegistry,
+  state_version: u32,
+  _state: PhantomData<S>,
+}
+
+impl<S: ReplayableState> StateReducer for EngineReducer<S> {
+  type State = S;
+
+  fn initial_state(&self) -> flow::errors::Result<S> {
+    Ok(S::default())
+  }
+
+  fn decode_snapshot(&self, bytes: &[u8], snapshot: &SnapshotMeta) -> flow::errors::Result<S> {
+    let invalid = |e: serde_json::Error| FlowError::Other(format!("snapshot {}: {}", snapshot.id, e));
+    let value: JsonValue = serde_json::from_slice(bytes).map_err(invalid)?;
+    let value = self.registry.upcast(&FlowDataKey::Other(ENGINE_STATE_KEY.into()),
+                                      value,
+                                      schema_version_of(&snapshot.metadata),
+                                      self.state_version)?;
+    serde_json::from_value(value).map_err(invalid)
+  }
+
+  fn apply(&self, state: &mut S, data: &FlowData) -> flow::errors::Result<()> {
+    let upcast = self.registry.upcast_data(data)?;
+    state.apply_data(upcast.as_ref().unwrap_or(data)).map_err(|e| match e {
+                                                       WorkflowError::Flow(e) => e,
+                                                       WorkflowError::Payload(e) => FlowError::Payload(e),
+                                                       other => FlowError::Other(other.to_string()),
+                                                     })
+  }
+}
+
 pub trait ChemicalFlowEngine: Send + Sync {
   // === MÉTODOS REQUERIDOS (IMPLEMENTACIÓN ESPECÍFICA) ===
+  /// Estado tipado del engine (el que guardan sus snapshots)
+  type State: ReplayableState;
+
   /// Identificador único del engine
   fn id(&self) -> Uuid;
 
@@ -32,6 +88,17 @@ pub trait ChemicalFlowEngine: Send + Sync {
   /// Crea un snapshot del estado actual
   fn snapshot(&self) -> Result<JsonValue, Box<dyn Error>>;
 
+  /// Versión de esquema del estado que guarda `snapshot`
+  fn state_schema_version(&self) -> u32 {
+    flow::payload::DEFAULT_SCHEMA_VERSION
+  }
+
+  /// Upcasters del estado desde versiones anteriores (`(n, f)` pasa de `n` a
+  /// `n + 1`)
+  fn state_upcasters(&self) -> Vec<(u32, Upcaster)> {
+    Vec::new()
+  }
+
   /// Tipo de workflow específico del engine
   fn engine_workflow_type() -> WorkflowType
     where Self: Sized;
@@ -46,6 +113,15 @@ pub trait ChemicalFlowEngine: Send + Sync {
   /// Obtiene la referencia al repositorio de dominio
   fn domain_repo(&self) -> &Arc<dyn DomainRepository>;
 
+  /// Principal en cuyo nombre se ejecutan los pasos (lo recibe cada
+  /// `StepContext`)
+  fn principal(&self) -> Option<&Principal>;
+
+  /// Fija el principal del engine. No envuelve los repositorios: para que
+  /// autoricen y firmen las operaciones hay que construir el engine con
+  /// `step::scoped_repos` (como `ChemicalWorkflowFactory::create_as`).
+  fn set_principal(&mut self, principal: Option<Principal>);
+
   /// Obtiene el paso actual como trait object dinámico
   fn get_current_step(&self) -> Result<Box<dyn crate::step::WorkflowStepDyn>, WorkflowError>;
 
@@ -65,10 +141,59 @@ pub trait ChemicalFlowEngine: Send + Sync {
   /// ejecuta con un `StepContext` nuevo.
   fn execute_step_by_index_unchecked(&mut self, idx: u32, input: &JsonValue) -> Result<StepInfo, WorkflowError> {
     let step = self.get_step_by_index(idx)?;
-    let ctx = StepContext::new(self.id(), self.flow_repo().clone(), self.domain_repo().clone());
+    let ctx = self.step_context()?;
     step.execute(&ctx, input)
   }
 
+  /// Registro de payloads de los pasos del engine (`step_state:{paso}` →
+  /// `Payload` del paso, su versión y sus upcasters) y de los upcasters del
+  /// estado (`ENGINE_STATE_KEY`).
+  fn payload_registry(&self) -> Result<PayloadRegistry, WorkflowError> {
+    let mut registry = PayloadRegistry::new();
+    let mut idx = 0;
+    while let Ok(step) = self.get_step_by_index(idx) {
+      step.register_payload(&mut registry)?;
+      idx += 1;
+    }
+    for (from_version, upcaster) in self.state_upcasters() {
+      registry.register_upcaster(FlowDataKey::Other(ENGINE_STATE_KEY.into()), from_version, upcaster);
+    }
+    Ok(registry)
+  }
+
+  /// Reescribe los `FlowData` de este flow guardados con versiones de
+  /// esquema anteriores (ver `flow::payload::upcast_stored_payloads` para
+  /// todos los flows). Devuelve cuántos registros migró.
+  fn upcast_stored_payloads(&self) -> Result<usize, WorkflowError> {
+    let registry = self.payload_registry()?;
+    let rows = self.flow_repo()
+                   .read_data(&self.id(), 0)?
+                   .iter()
+                   .filter_map(|d| registry.upcast_data(d).transpose())
+                   .collect::<Result<Vec<FlowData>, _>>()?;
+    if rows.is_empty() {
+      return Ok(0);
+    }
+    Ok(self.flow_repo().rewrite_data(&rows)?)
+  }
+
+  /// Estado del engine tal y como era tras el registro `cursor` de su flow
+  /// (`0` = antes del primer paso): parte del snapshot más cercano anterior
+  /// o igual y reproduce los registros posteriores con
+  /// `ReplayableState::apply_data`. No modifica el engine ni guarda nada.
+  fn state_at(&self, cursor: i64) -> Result<StateAt<Self::State>, WorkflowError> {
+    let reducer =
+      EngineReducer { registry: self.payload_registry()?, state_version: self.state_schema_version(), _state: PhantomData };
+    Ok(state_at(self.flow_repo().as_ref(), &self.id(), cursor, &reducer)?)
+  }
+
+  /// Contexto para ejecutar un paso de este engine
+  fn step_context(&self) -> Result<StepContext, WorkflowError> {
+    let mut ctx = StepContext::new(self.id(), self.flow_repo().clone(), self.domain_repo().clone());
+    ctx.principal = self.principal().cloned();
+    Ok(ctx.with_payloads(Arc::new(self.payload_registry()?)))
+  }
+
   /// Crea una nueva instancia del engine
   fn new(id: Uuid, flow_repo: Arc<dyn FlowRepository>, domain_repo: Arc<dyn DomainRepository>) -> Self
     where Self: Sized
@@ -96,6 +221,7 @@ pub trait ChemicalFlowEngine: Send + Sync {
                      .create_branch(&self.id(), parent_cursor, metadata)
                      .map_err(|e| WorkflowError::Persistence(format!("create_branch error: {}", e)))?;
     let mut new = Self::construct_with_repos(new_id, self.flow_repo().clone(), self.domain_repo().clone());
+    new.set_principal(self.principal().cloned());
     new.rehydrate_from_storage()?;
     Ok(new)
   }
@@ -139,7 +265,7 @@ pub trait ChemicalFlowEngine: Send + Sync {
     let step = self.get_current_step()?;
     let step_name = step.name().to_string();
     self.validate_step_execution(&step_name)?;
-    let ctx = StepContext::new(self.id(), self.flow_repo().clone(), self.domain_repo().clone());
+    let ctx = self.step_context()?;
     step.execute(&ctx, input)
   }
 
@@ -157,12 +283,17 @@ pub trait ChemicalFlowEngine: Send + Sync {
                          command_id: Option<Uuid>)
                          -> Result<PersistResult, WorkflowError> {
     let (cursor, version) = self.calculate_cursor_and_version(expected_version)?;
+    let key = FlowDataKey::step_state(step_name);
+    let metadata = match self.payload_registry()?.version_of(&key) {
+      Some(schema_version) => with_schema_version(info.metadata, schema_version),
+      None => info.metadata,
+    };
     let data = FlowData { id: Uuid::new_v4(),
                           flow_id: self.id(),
                           cursor,
-                          key: format!("step_state:{}", step_name),
+                          key: key.to_string(),
                           payload: info.payload,
-                          metadata: info.metadata,
+                          metadata,
                           command_id,
                           created_at: Utc::now() };
     let result = self.flow_repo().persist_data(&data, version)?;
@@ -172,6 +303,73 @@ pub trait ChemicalFlowEngine: Send + Sync {
     Ok(result)
   }
 
+  /// Ejecuta el paso actual y persiste su resultado con el flow bloqueado
+  /// (`lock_for_update`): otro engine sobre el mismo flow espera hasta
+  /// `STEP_LOCK_TIMEOUT` en lugar de repetir el trabajo. Si el flow ya no
+  /// está en `expected_version` devuelve `PersistResult::Conflict` sin
+  /// ejecutar el paso. Mientras el paso corre el lock se renueva
+  /// (`FlowLockGuard::keep_alive`) y antes de persistir se comprueba que
+  /// sigue siendo nuestro.
+  fn execute_and_persist_current_step(&mut self,
+                                      input: &JsonValue,
+                                      expected_version: i64,
+                                      command_id: Option<Uuid>)
+                                      -> Result<PersistResult, WorkflowError> {
+    let step = self.get_current_step()?;
+    let step_name = step.name().to_string();
+    self.validate_step_execution(&step_name)?;
+    let Some(guard) = self.flow_repo().lock_for_update(&self.id(), expected_version, STEP_LOCK_TIMEOUT)? else {
+      return Ok(PersistResult::Conflict);
+    };
+    let ctx = self.step_context()?;
+    let info = guard.keep_alive(|| step.execute(&ctx, input))?;
+    guard.renew()?;
+    self.persist_step_result(&step_name, info, expected_version, command_id)
+  }
+
+  /// Como `execute_and_persist_current_step`, pero en una unidad de
+  /// trabajo: lo que el paso escriba en el dominio solo se confirma si
+  /// `persist_data` devuelve `Ok`; con `Conflict` o un error se deshace.
+  /// Los repositorios del alcance se envuelven con los mismos decoradores
+  /// (principal, autorización, auditoría) que los del engine (`rewrap`).
+  ///
+  /// El lock se toma fuera del alcance y se renueva justo antes de abrirlo:
+  /// en SQLite el alcance ocupa la única conexión del pool, así que durante
+  /// el paso no se renueva; si expira y otro engine avanza el flow,
+  /// `persist_data` devuelve `Conflict` y el alcance se deshace.
+  fn execute_and_persist_current_step_in(&mut self,
+                                         uow: &dyn UnitOfWork,
+                                         input: &JsonValue,
+                                         expected_version: i64,
+                                         command_id: Option<Uuid>)
+                                         -> Result<PersistResult, WorkflowError> {
+    let step = self.get_current_step()?;
+    let step_name = step.name().to_string();
+    self.validate_step_execution(&step_name)?;
+    // declarado antes que `scope`: se libera después de cerrar la transacción
+    let Some(guard) = self.flow_repo().lock_for_update(&self.id(), expected_version, STEP_LOCK_TIMEOUT)? else {
+      return Ok(PersistResult::Conflict);
+    };
+    guard.renew()?;
+    let scope = uow.begin()?;
+    let flow_repo = self.flow_repo().rewrap(scope.flow_repo());
+    let domain_repo = self.domain_repo().rewrap(scope.domain_repo());
+    let mut ctx = StepContext::new(self.id(), flow_repo, domain_repo);
+    ctx.principal = self.principal().cloned();
+    let ctx = ctx.with_payloads(Arc::new(self.payload_registry()?));
+    // si algo falla antes del commit, soltar `scope` deshace la transacción
+    let info = step.execute(&ctx, input)?;
+    let result = ctx.save_typed_result(&step_name, info, expected_version, command_id)?;
+    if let PersistResult::Ok { .. } = result {
+      scope.commit()?;
+      let cursor = self.flow_repo().get_flow_meta(&self.id())?.current_cursor;
+      self.update_engine_state_after_persist(cursor)?;
+    } else {
+      scope.rollback()?;
+    }
+    Ok(result)
+  }
+
   // --- Operaciones de avance y validación ---
   /// Avanza al siguiente paso actualizando los metadatos
   fn advance_step(&mut self) -> Result<(), WorkflowError> {
@@ -181,10 +379,8 @@ pub trait ChemicalFlowEngine: Send + Sync {
   // --- Operaciones de repositorio delegadas ---
   /// Lee el payload del último paso ejecutado
   fn get_last_step_payload(&self, step_name: &str) -> Result<Option<JsonValue>, WorkflowError> {
-    let key = format!("step_state:{}", step_name);
-    let data = self.flow_repo().read_data(&self.id(), 0)?;
-    let payload = data.into_iter().rev().find(|fd| fd.key.eq_ignore_ascii_case(&key)).map(|fd| fd.payload);
-    Ok(payload)
+    let key = FlowDataKey::step_state(step_name).to_string();
+    Ok(self.flow_repo().latest_by_key(&self.id(), &key)?.map(|fd| fd.payload))
   }
 
   /// Obtiene metadatos específicos
@@ -289,20 +485,24 @@ pub trait ChemicalFlowEngine: Send + Sync {
     // en la metadata `flow_metadata.current_step`.
     let next_step = (cursor as u32).saturating_add(1);
     self.set_metadata("flow_metadata", serde_json::json!({ "current_step": next_step }))?;
-    // Intentar guardar snapshot (operación best-effort)
-    let _ = self.save_snapshot();
+    // Intentar guardar snapshot y aplicar la política de retención del
+    // repositorio (operaciones best-effort)
+    if self.save_snapshot().is_ok() {
+      let _ = self.flow_repo().compact_snapshots(&self.id());
+    }
     Ok(())
   }
 
-  /// Guarda snapshot del estado actual
+  /// Guarda snapshot del estado actual en el último cursor del flow (el que
+  /// `state_at` toma como punto de partida). El JSON va al `SnapshotStore`
+  /// del repositorio y el snapshot solo registra su key; su metadata lleva
+  /// `schema_version` con `state_schema_version`.
   fn save_snapshot(&self) -> Result<(), WorkflowError> {
     let snapshot = self.snapshot().map_err(|e| WorkflowError::Persistence(format!("snapshot error: {}", e)))?;
     let state_bytes = serde_json::to_vec(&snapshot)?;
-    let state_b64 = base64::engine::general_purpose::STANDARD.encode(&state_bytes);
-    self.flow_repo().save_snapshot(&self.id(),
-                                    self.current_step() as i64,
-                                    &state_b64,
-                                    self.get_metadata("flow_metadata")?)?;
+    let cursor = self.flow_repo().get_flow_meta(&self.id())?.current_cursor;
+    let metadata = with_schema_version(self.get_metadata("flow_metadata")?, self.state_schema_version());
+    self.flow_repo().save_snapshot_state(&self.id(), cursor, &state_bytes, metadata)?;
     Ok(())
   }
 
@@ -313,18 +513,20 @@ pub trait ChemicalFlowEngine: Send + Sync {
     Ok(())
   }
 
-  /// Rehidrata desde snapshot si está disponible
+  /// Rehidrata desde snapshot si está disponible. Un estado de una versión
+  /// anterior se migra con `state_upcasters`; si no se puede leer o migrar
+  /// se devuelve el error en lugar de ignorar el snapshot.
   fn rehydrate_from_snapshot(&mut self) -> Result<(), WorkflowError> {
     if let Some(snapshot_meta) = self.flow_repo().load_latest_snapshot(&self.id())? {
-      if let Ok((bytes, _meta)) = self.flow_repo().load_snapshot(&snapshot_meta.id) {
-        if let Ok(state_b64) = String::from_utf8(bytes) {
-          if let Ok(decoded) = base64::engine::general_purpose::STANDARD.decode(state_b64.as_bytes()) {
-            let snapshot: JsonValue = serde_json::from_slice(&decoded)?;
-            self.apply_snapshot(&snapshot)
-                .map_err(|e| WorkflowError::Persistence(format!("apply_snapshot error: {}", e)))?;
-          }
-        }
-      }
+      // `load_snapshot` también resuelve los snapshots inline del formato
+      // anterior (base64 en `state_ptr`).
+      let (bytes, meta) = self.flow_repo().load_snapshot(&snapshot_meta.id)?;
+      let snapshot: JsonValue = serde_json::from_slice(&bytes)?;
+      let snapshot = self.payload_registry()?.upcast(&FlowDataKey::Other(ENGINE_STATE_KEY.into()),
+                                                      snapshot,
+                                                      schema_version_of(&meta.metadata),
+                                                      self.state_schema_version())?;
+      self.apply_snapshot(&snapshot).map_err(|e| WorkflowError::Persistence(format!("apply_snapshot error: {}", e)))?;
     }
     Ok(())
   }
@@ -404,6 +606,8 @@ pub trait ChemicalFlowEngine: Send + Sync {
 macro_rules! impl_chemical_flow {
     ($flow_ty:ty, $state_ty:ty, $workflow_type:expr, { $($idx:expr => $step:ty),* $(,)? }) => {
         impl $crate::engine::ChemicalFlowEngine for $flow_ty {
+            type State = $state_ty;
+
             fn id(&self) -> ::uuid::Uuid {
                 self.id
             }
@@ -421,12 +625,20 @@ macro_rules! impl_chemical_flow {
                 $workflow_type
             }
 
+            fn state_schema_version(&self) -> u32 {
+                <$state_ty as ::flow::payload::SchemaVersioned>::SCHEMA_VERSION
+            }
+
+            fn state_upcasters(&self) -> Vec<(u32, ::flow::payload::Upcaster)> {
+                <$state_ty as ::flow::payload::SchemaVersioned>::upcasters()
+            }
+
             fn construct_with_repos(
                 id: ::uuid::Uuid,
                 flow_repo: ::std::sync::Arc<dyn ::flow::repository::FlowRepository>,
                 domain_repo: ::std::sync::Arc<dyn ::chem_domain::DomainRepository>
             ) -> Self {
-                Self { id, state: Default::default(), flow_repo, domain_repo }
+                Self { id, state: Default::default(), flow_repo, domain_repo, principal: None }
             }
 
             fn flow_repo(&self) -> &::std::sync::Arc<dyn ::flow::repository::FlowRepository> {
@@ -437,6 +649,14 @@ macro_rules! impl_chemical_flow {
                 &self.domain_repo
             }
 
+            fn principal(&self) -> Option<&::flow::principal::Principal> {
+                self.principal.as_ref()
+            }
+
+            fn set_principal(&mut self, principal: Option<::flow::principal::Principal>) {
+                self.principal = principal;
+            }
+
             fn get_current_step(&self) -> Result<Box<dyn $crate::step::WorkflowStepDyn>, $crate::WorkflowError> {
         // Delega en get_step_by_index para evitar duplicar la tabla de pasos
         self.get_step_by_index(self.state.current_step)
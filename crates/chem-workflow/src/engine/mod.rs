@@ -1,2 +1,2 @@
 pub mod chemical_flow;
-pub use chemical_flow::ChemicalFlowEngine;
+pub use chemical_flow::{ChemicalFlowEngine, ReplayableState};
@@ -15,6 +15,10 @@ pub enum WorkflowError {
   /// Errores de persistencia de alto nivel (mensajes simples).
   #[error("Error de persistencia: {0}")]
   Persistence(String),
+  /// Payload de `FlowData` que no corresponde al esquema registrado para su
+  /// key (tipo o version).
+  #[error("Error de payload: {0}")]
+  Payload(#[from] flow::errors::PayloadError),
   /// Errores de serializacion/deserializacion JSON.
   #[error("Error de serializacion: {0}")]
   Serialization(#[from] serde_json::Error),
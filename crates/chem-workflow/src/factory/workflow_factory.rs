@@ -5,10 +5,16 @@ use std::sync::Arc;
 use uuid::Uuid;
 // Usar los repositorios en memoria del workspace para la fábrica para que
 // los motores creados sean utilizables de inmediato en ejemplos y pruebas.
+use crate::step::scoped_repos;
 use crate::workflow_type::WorkflowType;
+use chem_domain::DomainRepository;
+use flow::principal::{Authorizer, Principal};
+use flow::query::{FlowQuery, WORKFLOW_TYPE_META_KEY};
 use flow::repository::FlowRepository;
 use std::collections::HashMap;
 // la rehidratación se delega al motor concreto vía `rehydrate`
+/// Repositorios de flows y de dominio de un engine.
+type Repos = (Arc<dyn FlowRepository>, Arc<dyn DomainRepository>);
 /// Fábrica para crear o cargar instancias de motores de flujo.
 ///
 /// Provee métodos de creación rápidos que usan repositorios (por defecto
@@ -19,20 +25,27 @@ pub struct ChemicalWorkflowFactory;
 impl ChemicalWorkflowFactory {
   /// Lista todos los flows y sus tipos de workflow.
   /// Retorna un `HashMap` desde el UUID (string) del flow hasta su
-  /// `WorkflowType`.
+  /// `WorkflowType`. Recorre los flows página a página con `query_flows`,
+  /// que ya trae la metadata de cada uno.
   pub fn get_chem_flows() -> Result<HashMap<String, WorkflowType>, WorkflowError> {
     let repo = chem_persistence::new_flow_from_env()?;
-    let repo_arc: Arc<dyn FlowRepository> = Arc::new(repo);
-    let ids = repo_arc.list_flow_ids()?;
     let mut out = HashMap::new();
-    for id in ids {
-      let wt = match repo_arc.get_meta(&id, "workflow_type") {
-        Ok(v) => v.as_str().and_then(|s| s.parse::<WorkflowType>().ok()).unwrap_or(WorkflowType::Unknown),
-        Err(_) => WorkflowType::Unknown,
-      };
-      out.insert(id.to_string(), wt);
+    let mut query = FlowQuery::new();
+    loop {
+      let page = repo.query_flows(&query)?;
+      for meta in page.flows {
+        let wt = meta.metadata
+                     .get(WORKFLOW_TYPE_META_KEY)
+                     .and_then(|v| v.as_str())
+                     .and_then(|s| s.parse::<WorkflowType>().ok())
+                     .unwrap_or(WorkflowType::Unknown);
+        out.insert(meta.id.to_string(), wt);
+      }
+      match page.next {
+        Some(cursor) => query = query.after(cursor),
+        None => return Ok(out),
+      }
     }
-    Ok(out)
   }
   /// Constructor genérico que crea un nuevo flow y construye el engine
   /// concreto `E` asociado.
@@ -44,34 +57,85 @@ impl ChemicalWorkflowFactory {
   pub fn create<E>(create_name: String) -> Result<Box<E>, WorkflowError>
     where E: ChemicalFlowEngine + 'static
   {
-    let workflow_type = E::engine_workflow_type();
-    let repo = chem_persistence::new_flow_from_env()?;
-    let repo_arc: Arc<dyn FlowRepository> = Arc::new(repo);
-    let id = repo_arc.create_flow(Some(create_name), Some("created".into()), json!({}))?;
-    repo_arc.set_meta(&id, "workflow_type", json!(workflow_type.to_string()))?;
-    let domain_repo = chem_persistence::new_domain_from_env()?;
-    let domain_arc: Arc<dyn chem_domain::DomainRepository> = Arc::new(domain_repo);
-    let engine = E::construct_with_repos(id, repo_arc, domain_arc);
-    Ok(Box::new(engine))
+    let (repo_arc, domain_arc) = Self::env_repos()?;
+    Self::create_with_repos(create_name, repo_arc, domain_arc)
+  }
+  /// Como `create`, pero el engine ejecuta todo en nombre de `principal`:
+  /// el flow se crea con `created_by`, `authorizer` decide cada operación y
+  /// los pasos reciben el principal en su `StepContext`. Si el principal
+  /// tiene `tenant`, los repositorios se limitan a ese tenant.
+  pub fn create_as<E>(create_name: String,
+                      principal: Principal,
+                      authorizer: Arc<dyn Authorizer>)
+                      -> Result<Box<E>, WorkflowError>
+    where E: ChemicalFlowEngine + 'static
+  {
+    let (repo_arc, domain_arc) = Self::env_repos_for(principal.tenant.as_deref())?;
+    let (repo_arc, domain_arc) = scoped_repos(repo_arc, domain_arc, &principal, authorizer);
+    let mut engine = Self::create_with_repos::<E>(create_name, repo_arc, domain_arc)?;
+    engine.set_principal(Some(principal));
+    Ok(engine)
   }
   /// Carga una instancia apuntando a un `flow_id` existente.
   ///
   /// Intenta rehidratar el motor con el snapshot existente si está
   /// disponible; la rehidratación concreta la realiza la implementación
   /// del engine en `E::rehydrate`.
-  pub fn load<E>(_flow_id: &Uuid) -> Result<Box<E>, WorkflowError>
+  pub fn load<E>(flow_id: &Uuid) -> Result<Box<E>, WorkflowError>
     where E: ChemicalFlowEngine + 'static
   {
     // inicializar repositorios respaldados por persistencia (obligatorio)
+    let (repo_arc, domain_arc) = Self::env_repos()?;
+    Self::load_with_repos(flow_id, repo_arc, domain_arc)
+  }
+  /// Como `load`, en nombre de `principal` (ver `create_as`); la propia
+  /// rehidratación ya pasa por `authorizer`.
+  pub fn load_as<E>(flow_id: &Uuid, principal: Principal, authorizer: Arc<dyn Authorizer>) -> Result<Box<E>, WorkflowError>
+    where E: ChemicalFlowEngine + 'static
+  {
+    let (repo_arc, domain_arc) = Self::env_repos_for(principal.tenant.as_deref())?;
+    let (repo_arc, domain_arc) = scoped_repos(repo_arc, domain_arc, &principal, authorizer);
+    let mut engine = Self::load_with_repos::<E>(flow_id, repo_arc, domain_arc)?;
+    engine.set_principal(Some(principal));
+    Ok(engine)
+  }
+  fn env_repos() -> Result<Repos, WorkflowError> {
     let repo = chem_persistence::new_flow_from_env()?;
-    let repo_arc: Arc<dyn FlowRepository> = Arc::new(repo);
     let domain_repo = chem_persistence::new_domain_from_env()?;
-    let domain_arc: Arc<dyn chem_domain::DomainRepository> = Arc::new(domain_repo);
-    let engine = E::rehydrate(*_flow_id, repo_arc.clone(), domain_arc)?;
+    Ok((Arc::new(repo), Arc::new(domain_repo)))
+  }
+  /// `env_repos` limitado a `tenant` (o al de `TENANT_ID` si es `None`).
+  fn env_repos_for(tenant: Option<&str>) -> Result<Repos, WorkflowError> {
+    let Some(tenant) = tenant else {
+      return Self::env_repos();
+    };
+    let repo = chem_persistence::new_flow_from_env_for_tenant(tenant)?;
+    let domain_repo = chem_persistence::new_domain_from_env_for_tenant(tenant)?;
+    Ok((Arc::new(repo), Arc::new(domain_repo)))
+  }
+  fn create_with_repos<E>(create_name: String,
+                          repo_arc: Arc<dyn FlowRepository>,
+                          domain_arc: Arc<dyn DomainRepository>)
+                          -> Result<Box<E>, WorkflowError>
+    where E: ChemicalFlowEngine + 'static
+  {
+    let workflow_type = E::engine_workflow_type();
+    let id = repo_arc.create_flow(Some(create_name), Some("created".into()), json!({}))?;
+    repo_arc.set_meta(&id, WORKFLOW_TYPE_META_KEY, json!(workflow_type.to_string()))?;
+    let engine = E::construct_with_repos(id, repo_arc, domain_arc);
+    Ok(Box::new(engine))
+  }
+  fn load_with_repos<E>(flow_id: &Uuid,
+                        repo_arc: Arc<dyn FlowRepository>,
+                        domain_arc: Arc<dyn DomainRepository>)
+                        -> Result<Box<E>, WorkflowError>
+    where E: ChemicalFlowEngine + 'static
+  {
+    let engine = E::rehydrate(*flow_id, repo_arc.clone(), domain_arc)?;
     if let Ok(meta_val) = engine.get_metadata("flow_metadata") {
       let has_cs = meta_val.get("current_step").is_some();
       if !has_cs {
-        if let Ok(flow_meta) = repo_arc.get_flow_meta(_flow_id) {
+        if let Ok(flow_meta) = repo_arc.get_flow_meta(flow_id) {
           let cs = flow_meta.current_cursor as u32;
           let _ = engine.set_metadata("flow_metadata", json!({ "current_step": cs }));
         }
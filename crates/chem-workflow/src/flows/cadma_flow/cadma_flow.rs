@@ -5,10 +5,15 @@
 // parte de la lógica común al trait `ChemicalFlowEngine` mediante la
 // macro `impl_chemical_flow!`.
 use crate::{
+  engine::ReplayableState,
   flows::cadma_flow::steps::{ADMETSAPropertiesStep2, FamilyReferenceStep1, MoleculeInitialStep3},
   workflow_type::WorkflowType,
+  WorkflowError,
 };
 use chem_domain::DomainRepository;
+use flow::domain::FlowData;
+use flow::payload::SchemaVersioned;
+use flow::principal::Principal;
 use flow::repository::FlowRepository;
 use serde::{Deserialize, Serialize};
 use serde_json::Value as JsonValue;
@@ -23,6 +28,9 @@ pub struct CadmaState {
   pub status: String,
 }
 
+/// Versión 1 del estado guardado en snapshots (sin upcasters todavía).
+impl SchemaVersioned for CadmaState {}
+
 impl Default for CadmaState {
   fn default() -> Self {
     CadmaState { current_step: 0,
@@ -32,12 +40,35 @@ impl Default for CadmaState {
   }
 }
 
+/// Cada paso persistido deja el flow en el paso siguiente (como
+/// `update_engine_state_after_persist`) y añade a `domain_refs` las familias
+/// que referencia: `family_uuid`/`family_id` del payload y los ids de
+/// `metadata.domain_refs` (el resto de entradas son inchikeys).
+impl ReplayableState for CadmaState {
+  fn apply_data(&mut self, data: &FlowData) -> Result<(), WorkflowError> {
+    self.current_step = (data.cursor as u32).saturating_add(1);
+    self.status = "running".to_string();
+    if let JsonValue::Object(meta) = &mut self.metadata {
+      meta.insert("current_step".into(), JsonValue::from(self.current_step));
+    }
+    let from_payload = ["family_uuid", "family_id"].into_iter().filter_map(|k| data.payload.get(k));
+    let from_metadata = data.metadata.get("domain_refs").and_then(|v| v.as_array()).into_iter().flatten();
+    for id in from_payload.chain(from_metadata).filter_map(|v| v.as_str()).filter_map(|s| Uuid::parse_str(s).ok()) {
+      if !self.domain_refs.contains(&id) {
+        self.domain_refs.push(id);
+      }
+    }
+    Ok(())
+  }
+}
+
 #[derive(Clone)]
 pub struct CadmaFlow {
   pub id: Uuid,
   pub state: CadmaState,
   pub flow_repo: Arc<dyn FlowRepository>,
   pub domain_repo: Arc<dyn DomainRepository>,
+  pub principal: Option<Principal>,
 }
 
 crate::impl_chemical_flow!(
@@ -3,12 +3,14 @@
 //! creada/seleccionada en Step1.
 //! - Soporta "method_property_map" y "preferred_methods".
 //! - Los valores manuales se pueden suministrar por SMILES.
-//! - Guarda cada propiedad en domain_repo como OwnedMolecularProperty.
+//! - Guarda todas las propiedades en domain_repo como OwnedMolecularProperty
+//!   con una sola llamada a `save_molecular_properties_bulk`.
 
 use crate::errors::WorkflowError;
 use crate::flows::cadma_flow::steps::family_reference_step1::Step1Payload;
 use crate::step::StepContext;
 use chem_domain::{Molecule, OwnedMolecularProperty};
+use flow::payload::SchemaVersioned;
 use serde::{Deserialize, Serialize};
 use std::collections::{HashMap, HashSet};
 use uuid::Uuid;
@@ -143,6 +145,8 @@ pub struct Step2Payload {
   pub selected_properties: SelectedProperties,
 }
 
+impl SchemaVersioned for Step2Payload {}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Step2Metadata {
   pub status: String,
@@ -347,18 +351,21 @@ impl ADMETSAPropertiesStep2 {
     let mut saved_ids: Vec<String> = Vec::with_capacity(mol_count * REQUIRED_PROPERTIES.len());
     let mut domain_refs: Vec<String> = vec![family_id.to_string()];
 
-    for mol in molecules {
-      let props = self.compute_properties_for_molecule(mol, &family_id, &input)?;
-      // convertir a GeneratedPropertyEntry y persistir cada OwnedMolecularProperty en
-      // domain repo
+    let computed = molecules.into_iter()
+                            .map(|mol| Ok((mol, self.compute_properties_for_molecule(mol, &family_id, &input)?)))
+                            .collect::<Result<Vec<_>, WorkflowError>>()?;
+
+    // Persistir todas las propiedades en una transacción; los resultados
+    // vienen en el mismo orden en que se aplanan
+    let to_save = computed.iter().flat_map(|(_, props)| props.iter().cloned()).collect();
+    for saved in ctx.domain_repo.save_molecular_properties_bulk(to_save)? {
+      saved_ids.push(saved?.to_string());
+    }
+
+    for (mol, props) in computed {
+      // convertir a GeneratedPropertyEntry para el retorno
       let mut generated_entries: Vec<GeneratedPropertyEntry> = Vec::with_capacity(props.len());
       for p in props.into_iter() {
-        // persistir (clonar p porque save_molecular_property consume
-        // OwnedMolecularProperty)
-        ctx.domain_repo.save_molecular_property(p.clone())?;
-        saved_ids.push(p.id.to_string());
-
-        // construir entry para el retorno
         let v = p.value.as_f64().unwrap_or(0.0);
         let method = p.metadata.get("method").and_then(|m| m.as_str()).unwrap_or("unknown").to_string();
         generated_entries.push(GeneratedPropertyEntry { id: p.id,
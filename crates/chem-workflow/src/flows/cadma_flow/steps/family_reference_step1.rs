@@ -9,6 +9,7 @@ use crate::errors::WorkflowError;
 use crate::step::{StepContext, StepInfo};
 use chem_domain::{Molecule, MoleculeFamily};
 use chrono::Utc;
+use flow::payload::SchemaVersioned;
 use serde::{Deserialize, Serialize};
 use serde_json::json;
 use std::collections::HashSet;
@@ -35,6 +36,8 @@ pub struct Step1Payload {
   pub molecules_count: usize,
 }
 
+impl SchemaVersioned for Step1Payload {}
+
 /// Metadatos legibles para auditoría / UI
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Step1Metadata {
@@ -124,6 +127,7 @@ impl FamilyReferenceStep1 {
                             -> Result<(Uuid, usize, Vec<String>), WorkflowError> {
     let provenance = json!({
         "created_by": "family_reference_step1",
+        "principal": ctx.principal,
         "name": name.clone().unwrap_or_default(),
         "timestamp": Utc::now().to_rfc3339()
     });
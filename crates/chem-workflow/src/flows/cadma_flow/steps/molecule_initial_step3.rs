@@ -8,6 +8,7 @@
 use crate::errors::WorkflowError;
 use crate::step::StepContext;
 use chem_domain::Molecule;
+use flow::payload::SchemaVersioned;
 use serde::{Deserialize, Serialize};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -28,6 +29,8 @@ pub struct Step3Payload {
   pub step_result: String,
 }
 
+impl SchemaVersioned for Step3Payload {}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Step3Metadata {
   pub status: String,
@@ -54,15 +57,14 @@ impl MoleculeInitialStep3 {
       }
     };
 
-    let mut generated_inchikeys = Vec::new();
-    let mut domain_refs = Vec::new();
-
-    for smiles in &smiles_list {
-      let molecule = Molecule::from_smiles(smiles).map_err(WorkflowError::Domain)?;
-      let inchikey = ctx.domain_repo.save_molecule(molecule.clone())?;
-      generated_inchikeys.push(inchikey.clone());
-      domain_refs.push(inchikey);
-    }
+    let molecules = smiles_list.iter()
+                               .map(|smiles| Molecule::from_smiles(smiles).map_err(WorkflowError::Domain))
+                               .collect::<Result<Vec<_>, _>>()?;
+    let generated_inchikeys = ctx.domain_repo
+                                 .save_molecules_bulk(molecules)?
+                                 .into_iter()
+                                 .collect::<Result<Vec<_>, _>>()?;
+    let domain_refs = generated_inchikeys.clone();
 
     let method_str = match &input.method {
       GenerationMethod::Manual { .. } => "Manual".to_string(),
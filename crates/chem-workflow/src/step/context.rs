@@ -3,11 +3,17 @@
 // Provee `StepContext`, un helper ligero que facilita a los pasos
 // acceder a la persistencia (FlowRepository) y al DomainRepository.
 // Incluye utilidades para leer el último payload tipado y para
-// persistir resultados tipados de pasos.
+// persistir resultados tipados de pasos. Los payloads se decodifican con el
+// `PayloadRegistry` del engine (key + versión de esquema). Con un
+// `Principal` (`with_principal`) los repositorios del contexto autorizan cada
+// operación y firman lo que escriben.
 use crate::errors::WorkflowError;
 use crate::step::StepInfo;
 use chem_domain::DomainRepository;
+use chem_persistence::PrincipalDomainRepository;
 use flow::domain::PersistResult;
+use flow::payload::{with_schema_version, FlowDataKey, PayloadRegistry};
+use flow::principal::{Authorizer, Principal, PrincipalFlowRepository};
 use flow::repository::FlowRepository;
 use serde::de::DeserializeOwned;
 use std::sync::Arc;
@@ -16,23 +22,60 @@ pub struct StepContext {
   pub flow_id: Uuid,
   pub flow_repo: Arc<dyn FlowRepository>,
   pub domain_repo: Arc<dyn DomainRepository>,
+  /// Tipos de payload por key (los pasos del engine que crea el contexto).
+  pub payloads: Arc<PayloadRegistry>,
+  /// Usuario o servicio que ejecuta el paso (`None`: sin identificar).
+  pub principal: Option<Principal>,
+}
+/// Envuelve los repositorios para que ejecuten cada llamada en nombre de
+/// `principal`: `authorizer` puede rechazarla, los flows nuevos llevan
+/// `created_by` y los `FlowData` el principal en su metadata.
+pub fn scoped_repos(flow_repo: Arc<dyn FlowRepository>,
+                    domain_repo: Arc<dyn DomainRepository>,
+                    principal: &Principal,
+                    authorizer: Arc<dyn Authorizer>)
+                    -> (Arc<dyn FlowRepository>, Arc<dyn DomainRepository>) {
+  (Arc::new(PrincipalFlowRepository::new(flow_repo, principal.clone(), authorizer.clone())),
+   Arc::new(PrincipalDomainRepository::new(domain_repo, principal.clone(), authorizer)))
 }
 impl StepContext {
-  /// Crea un nuevo contexto para el flow indicado.
+  /// Crea un nuevo contexto para el flow indicado, con un registro de
+  /// payloads vacío (ver `with_payloads`).
   pub fn new(flow_id: Uuid, flow_repo: Arc<dyn FlowRepository>, domain_repo: Arc<dyn DomainRepository>) -> Self {
-    Self { flow_id, flow_repo, domain_repo }
+    Self { flow_id, flow_repo, domain_repo, payloads: Arc::new(PayloadRegistry::new()), principal: None }
+  }
+  /// Ejecuta los pasos en nombre de `principal` (ver `scoped_repos`).
+  pub fn with_principal(mut self, principal: Principal, authorizer: Arc<dyn Authorizer>) -> Self {
+    (self.flow_repo, self.domain_repo) = scoped_repos(self.flow_repo, self.domain_repo, &principal, authorizer);
+    self.principal = Some(principal);
+    self
+  }
+  /// Usa `payloads` para decodificar y versionar los resultados de pasos.
+  pub fn with_payloads(mut self, payloads: Arc<PayloadRegistry>) -> Self {
+    self.payloads = payloads;
+    self
   }
-  /// Obtiene el último output tipado del flujo
+  /// Obtiene el último output del flujo cuya key está registrada con el
+  /// tipo `T`. Falla con `WorkflowError::Payload` si `T` no está registrado
+  /// o si el registro no corresponde a su esquema. Solo lee el último
+  /// registro de cada key de `T` (`latest_by_key`), no el historial.
   pub fn get_typed_output_by_type<T>(&self) -> Result<Option<T>, WorkflowError>
-    where T: DeserializeOwned
+    where T: DeserializeOwned + 'static
+  {
+    let mut latest = Vec::new();
+    for key in self.payloads.keys_for::<T>() {
+      latest.extend(self.flow_repo.latest_by_key(&self.flow_id, &key.to_string())?);
+    }
+    Ok(self.payloads.latest::<T>(&latest)?)
+  }
+  /// Obtiene el último output guardado con `key`, decodificado como `T`.
+  pub fn get_typed_output_by_key<T>(&self, key: &FlowDataKey) -> Result<Option<T>, WorkflowError>
+    where T: DeserializeOwned + 'static
   {
-    let data = self.flow_repo.read_data(&self.flow_id, 0)?;
-    for fd in data.iter().rev() {
-      if let Ok(v) = serde_json::from_value::<T>(fd.payload.clone()) {
-        return Ok(Some(v));
-      }
+    match self.flow_repo.latest_by_key(&self.flow_id, &key.to_string())? {
+      Some(fd) => Ok(Some(self.payloads.decode(&fd)?)),
+      None => Ok(None),
     }
-    Ok(None)
   }
   /// Persiste un resultado tipado de paso
   pub fn save_typed_result(&self,
@@ -43,7 +86,11 @@ impl StepContext {
                            -> Result<PersistResult, WorkflowError> {
     use chrono::Utc;
     use flow::domain::FlowData;
-    let key = format!("step_state:{}", step_name);
+    let key = FlowDataKey::step_state(step_name);
+    let metadata = match self.payloads.version_of(&key) {
+      Some(version) => with_schema_version(info.metadata, version),
+      None => info.metadata,
+    };
     // Determinar cursor y versión
     let (cursor_candidate, ev) = self.flow_repo
                                      .get_flow_meta(&self.flow_id)
@@ -56,9 +103,9 @@ impl StepContext {
     let data = FlowData { id: Uuid::new_v4(),
                           flow_id: self.flow_id,
                           cursor: cursor_candidate,
-                          key,
+                          key: key.to_string(),
                           payload: info.payload,
-                          metadata: info.metadata,
+                          metadata,
                           command_id,
                           created_at: Utc::now() };
     self.flow_repo.persist_data(&data, ev).map_err(Into::into)
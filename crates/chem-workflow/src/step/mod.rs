@@ -1,4 +1,4 @@
 pub mod context;
 pub mod trait_step;
-pub use context::StepContext;
+pub use context::{scoped_repos, StepContext};
 pub use trait_step::{StepInfo, StepResult, WorkflowStep, WorkflowStepDyn};
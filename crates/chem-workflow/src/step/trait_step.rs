@@ -1,6 +1,8 @@
 use crate::errors::WorkflowError;
 use crate::step::StepContext;
 use chem_domain::DomainRepository;
+use flow::errors::PayloadError;
+use flow::payload::{FlowDataKey, PayloadRegistry, SchemaVersioned};
 use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
 use serde_json::Value as JsonValue;
@@ -18,7 +20,9 @@ pub type StepResult = Result<StepInfo, WorkflowError>;
 
 /// Trait principal para pasos de workflow con tipos fuertemente tipados
 pub trait WorkflowStep: Send + Sync {
-  type Payload: Serialize + DeserializeOwned + Send + Sync + 'static;
+  /// Payload persistido en `FlowData`; su versión de esquema y sus upcasters
+  /// vienen de `SchemaVersioned`.
+  type Payload: Serialize + DeserializeOwned + SchemaVersioned + Send + Sync + 'static;
   type Metadata: Serialize + DeserializeOwned + Send + Sync + 'static;
   type Input: DeserializeOwned + Send + Sync + 'static;
 
@@ -55,6 +59,9 @@ pub trait WorkflowStepDyn: Send + Sync {
   /// engine crea/obtiene el paso. Por defecto es no-op; implementaciones
   /// concretas pueden almacenar el repo si lo necesitan.
   fn init(&mut self, _domain_repo: Arc<dyn DomainRepository>);
+  /// Registra el payload del paso (tipo, versión y upcasters) bajo
+  /// `step_state:{name}`.
+  fn register_payload(&self, registry: &mut PayloadRegistry) -> Result<(), PayloadError>;
 }
 
 // Implementación automática del trait dinámico para todos los WorkflowStep
@@ -73,6 +80,10 @@ impl<T> WorkflowStepDyn for T where T: WorkflowStep
     // implement `init` on the `WorkflowStep` trait and receive the repo.
     WorkflowStep::init(self, domain_repo)
   }
+
+  fn register_payload(&self, registry: &mut PayloadRegistry) -> Result<(), PayloadError> {
+    registry.register_versioned::<T::Payload>(FlowDataKey::step_state(WorkflowStep::name(self)))
+  }
 }
 
 // Macro helper para reducir boilerplate al definir pasos simples
@@ -0,0 +1,44 @@
+// `execute_and_persist_current_step` toma el lock del flow: un segundo
+// dueño hace esperar al paso en lugar de chocar al persistir, y una versión
+// obsoleta devuelve Conflict sin ejecutar el paso.
+use chem_domain::{DomainRepository, InMemoryDomainRepository, Molecule};
+use chem_workflow::flows::CadmaFlow;
+use chem_workflow::ChemicalFlowEngine;
+use flow::domain::PersistResult;
+use flow::repository::FlowRepository;
+use flow::stubs::InMemoryFlowRepository;
+use serde_json::json;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+fn step1_input(inchikey: &str, family: &str) -> serde_json::Value {
+  let m = Molecule::from_parts(inchikey, "CCO", "InChI=1S/C2H6O/c1-2-3/h3H,2H2,1H3", json!({})).expect("molecule");
+  json!({ "families": null, "molecules": [m], "new_family_name": family, "new_family_description": null })
+}
+#[test]
+fn step_waits_for_flow_lock() {
+  let (flows, domain) = (Arc::new(InMemoryFlowRepository::new()), Arc::new(InMemoryDomainRepository::new()));
+  let flow_id = flows.create_flow(Some("cadma".into()), None, json!({})).expect("create_flow");
+  let held = flows.lock_for_update(&flow_id, 0, Duration::from_millis(100)).expect("lock").expect("guard");
+  let release = std::thread::spawn(move || {
+    std::thread::sleep(Duration::from_millis(300));
+    drop(held);
+  });
+  let started = Instant::now();
+  let mut engine = CadmaFlow::new(flow_id, flows.clone(), domain.clone());
+  let res = engine.execute_and_persist_current_step(&step1_input("JJJJJJJJJJJJJJ-JJJJJJJJJJ-7", "bloqueada"), 0, None)
+                  .expect("execute");
+  assert_eq!(res, PersistResult::Ok { new_version: 1 });
+  assert!(started.elapsed() >= Duration::from_millis(250), "el paso esperó al otro dueño");
+  release.join().expect("join");
+}
+#[test]
+fn stale_version_skips_step() {
+  let (flows, domain) = (Arc::new(InMemoryFlowRepository::new()), Arc::new(InMemoryDomainRepository::new()));
+  let flow_id = flows.create_flow(Some("cadma".into()), None, json!({})).expect("create_flow");
+  let mut engine = CadmaFlow::new(flow_id, flows.clone(), domain.clone());
+  let res = engine.execute_and_persist_current_step(&step1_input("KKKKKKKKKKKKKK-KKKKKKKKKK-8", "obsoleta"), 3, None)
+                  .expect("execute");
+  assert_eq!(res, PersistResult::Conflict);
+  assert!(domain.list_families().expect("list_families").is_empty());
+  assert_eq!(flows.get_flow_meta(&flow_id).expect("meta").current_version, 0);
+}
@@ -0,0 +1,87 @@
+// `execute_and_persist_current_step_in` cuando el resultado del paso se
+// rechaza: con una versión obsoleta el lock devuelve Conflict antes de
+// ejecutar el paso; si `persist_data` falla (aquí, prohibido al principal),
+// la unidad de trabajo deshace la familia y las moléculas que creó el paso 1
+// de CADMA. Se ejecuta en memoria, sobre SQLite (sin `pg`) y sobre Postgres
+// (con `pg` y `DATABASE_URL`).
+use chem_domain::{DomainRepository, InMemoryDomainRepository, Molecule};
+use chem_persistence::{InMemoryUnitOfWork, UnitOfWork};
+use chem_workflow::flows::CadmaFlow;
+use chem_workflow::{ChemicalFlowEngine, WorkflowError};
+use chrono::Utc;
+use flow::domain::{FlowData, PersistResult};
+use flow::errors::FlowError;
+use flow::principal::{Principal, PrincipalFlowRepository, RoleAuthorizer};
+use flow::repository::FlowRepository;
+use flow::stubs::InMemoryFlowRepository;
+use serde_json::json;
+use std::sync::Arc;
+use uuid::Uuid;
+fn molecule(inchikey: &str) -> Molecule {
+  Molecule::from_parts(inchikey, "CCO", "InChI=1S/C2H6O/c1-2-3/h3H,2H2,1H3", json!({})).expect("molecule")
+}
+fn assert_rejected_step_rolls_back(uow: &dyn UnitOfWork,
+                                   flows: Arc<dyn FlowRepository>,
+                                   domain: Arc<dyn DomainRepository>) {
+  let flow_id = flows.create_flow(Some("cadma".into()), None, json!({})).expect("create_flow");
+  // otro escritor avanza el flow: la versión 0 queda obsoleta
+  let note = FlowData { id: Uuid::new_v4(),
+                        flow_id,
+                        cursor: 1,
+                        key: "nota".into(),
+                        payload: json!({}),
+                        metadata: json!({}),
+                        command_id: None,
+                        created_at: Utc::now() };
+  assert!(matches!(flows.persist_data(&note, 0).expect("persist"), PersistResult::Ok { new_version: 1 }));
+  let (m1, m2) = (molecule("HHHHHHHHHHHHHH-HHHHHHHHHH-6"), molecule("IIIIIIIIIIIIII-IIIIIIIIII-6"));
+  let input = json!({ "families": null,
+                      "molecules": [m1, m2],
+                      "new_family_name": "rechazada",
+                      "new_family_description": null });
+  let mut engine = CadmaFlow::new(flow_id, flows.clone(), domain.clone());
+  let res = engine.execute_and_persist_current_step_in(uow, &input, 0, None).expect("execute");
+  assert_eq!(res, PersistResult::Conflict);
+  // con la versión vigente el paso corre, pero el principal no puede persistir
+  let authorizer = RoleAuthorizer::new().require("persist_data", ["escritor"]);
+  let readonly: Arc<dyn FlowRepository> =
+    Arc::new(PrincipalFlowRepository::new(flows.clone(), Principal::new("lector"), Arc::new(authorizer)));
+  let mut engine = CadmaFlow::new(flow_id, readonly, domain.clone());
+  let res = engine.execute_and_persist_current_step_in(uow, &input, 1, None);
+  assert!(matches!(res, Err(WorkflowError::Flow(FlowError::Forbidden(_)))), "{:?}", res);
+  assert!(domain.list_families().expect("list_families").is_empty());
+  assert!(domain.get_molecule(m1.inchikey()).expect("get m1").is_none());
+  assert!(domain.get_molecule(m2.inchikey()).expect("get m2").is_none());
+  let meta = flows.get_flow_meta(&flow_id).expect("meta");
+  assert_eq!((meta.current_cursor, meta.current_version), (1, 1));
+}
+#[test]
+fn in_memory_rejected_step_leaves_no_domain_writes() {
+  let (flows, domain) = (Arc::new(InMemoryFlowRepository::new()), Arc::new(InMemoryDomainRepository::new()));
+  let uow = InMemoryUnitOfWork::new(flows.clone(), domain.clone());
+  assert_rejected_step_rolls_back(&uow, flows, domain);
+}
+#[cfg(not(feature = "pg"))]
+#[test]
+fn diesel_sqlite_rejected_step_leaves_no_domain_writes() {
+  use chem_persistence::{DieselDomainRepository, DieselFlowRepository, DieselUnitOfWork};
+  let url = format!("file:memdb_wf_uow_{}?mode=memory&cache=shared", Uuid::new_v4());
+  let (flows, domain) = (Arc::new(DieselFlowRepository::new(&url)), Arc::new(DieselDomainRepository::new(&url)));
+  let uow = DieselUnitOfWork::new(flows.clone(), domain.clone());
+  assert_rejected_step_rolls_back(&uow, flows, domain);
+}
+#[cfg(feature = "pg")]
+#[test]
+fn diesel_pg_rejected_step_leaves_no_domain_writes() {
+  use chem_persistence::{DieselDomainRepository, DieselFlowRepository, DieselUnitOfWork};
+  let Ok(url) = std::env::var("DATABASE_URL") else {
+    eprintln!("skipping pg workflow unit of work test: DATABASE_URL not set");
+    return;
+  };
+  // tenant propio: la base de test es compartida
+  let tenant = format!("lab-{}", Uuid::new_v4());
+  let flows = Arc::new(DieselFlowRepository::new_pg_for_tenant(&url, &tenant).expect("create pg repo"));
+  let domain = Arc::new(DieselDomainRepository::new_for_tenant(&url, &tenant));
+  let uow = DieselUnitOfWork::new(flows.clone(), domain.clone());
+  assert_rejected_step_rolls_back(&uow, flows, domain);
+}
@@ -0,0 +1,48 @@
+// Archivo: artifact_store.rs
+// Propósito: direccionamiento por contenido de los `ArtifactStore`.
+//
+// - `artifact_key`: la key de un artifact es el SHA-256 (hex) de su contenido
+//   (el mismo hash que usa `MolecularProperty` para `value_hash`), de modo que
+//   dos `put` del mismo blob comparten almacenamiento.
+// - `verify_artifact`: comprueba un blob leído contra su key.
+// - `artifact_refs`: keys que un `FlowData` declara en `metadata.artifacts`;
+//   los backends las registran al persistir para que `gc_artifacts` no borre
+//   blobs en uso.
+use crate::domain::FlowData;
+use crate::errors::{FlowError, Result};
+use sha2::{Digest, Sha256};
+/// Campo de `FlowData::metadata` con la lista de keys de artifacts que usa
+/// el registro, p. ej. `{"artifacts": ["<sha256>", ...]}`.
+pub const ARTIFACTS_METADATA_KEY: &str = "artifacts";
+/// Key de un blob: SHA-256 de su contenido en hex (minúsculas).
+pub fn artifact_key(blob: &[u8]) -> String {
+  format!("{:x}", Sha256::digest(blob))
+}
+/// Indica si `key` tiene el formato de `artifact_key` (64 dígitos hex en
+/// minúsculas). Los backends lo usan también para validar rutas.
+pub fn is_artifact_key(key: &str) -> bool {
+  key.len() == 64 && key.bytes().all(|b| b.is_ascii_digit() || (b'a'..=b'f').contains(&b))
+}
+/// Comprueba que `blob` corresponde a `key`; `Err(FlowError::Integrity)` si
+/// el contenido se ha alterado.
+pub fn verify_artifact(key: &str, blob: &[u8]) -> Result<()> {
+  let actual = artifact_key(blob);
+  if actual == key {
+    Ok(())
+  } else {
+    Err(FlowError::Integrity(format!("artifact {}: el contenido tiene hash {}", key, actual)))
+  }
+}
+/// Keys de artifacts referenciadas por `data` (en `metadata.artifacts`), sin
+/// repetidos. Las entradas que no son keys válidas se ignoran.
+pub fn artifact_refs(data: &FlowData) -> Vec<String> {
+  let mut keys: Vec<String> =
+    data.metadata
+        .get(ARTIFACTS_METADATA_KEY)
+        .and_then(|v| v.as_array())
+        .map(|items| items.iter().filter_map(|v| v.as_str()).filter(|k| is_artifact_key(k)).map(String::from).collect())
+        .unwrap_or_default();
+  keys.sort();
+  keys.dedup();
+  keys
+}
@@ -0,0 +1,424 @@
+// Archivo: async_repository.rs
+// Propósito: variante asíncrona del contrato de persistencia
+// (`AsyncFlowRepository`) y adaptadores en ambos sentidos (feature `async`):
+//
+// - `AsyncFlowAdapter`: envuelve cualquier `FlowRepository` síncrono y ejecuta
+//   cada llamada en el pool bloqueante de tokio (`spawn_blocking`).
+// - `BlockingFlowAdapter`: expone un `AsyncFlowRepository` (p. ej. un backend
+//   nativo async) como `FlowRepository` síncrono, para los componentes que
+//   consumen el trait síncrono (`ChemicalFlowEngine`, steps, testkit).
+//
+// La semántica de cada método es la documentada en `FlowRepository`.
+use crate::domain::{FlowData, FlowDataMeta, FlowMeta, PersistResult, SnapshotMeta, WorkItem};
+use crate::errors::{FlowError, Result};
+use crate::query::{FlowDataQuery, FlowPage, FlowQuery};
+use crate::repository::{FlowLockGuard, FlowRepository};
+use crate::retention::{PurgeReport, SnapshotGcReport};
+use crate::subscription::FlowSubscription;
+use async_trait::async_trait;
+use serde_json::Value as JsonValue;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::runtime::Handle;
+use uuid::Uuid;
+/// Versión asíncrona de `FlowRepository`, con los mismos métodos y el mismo
+/// contrato (errores, versiones, idempotencia por `command_id`).
+#[async_trait]
+pub trait AsyncFlowRepository: Send + Sync {
+  async fn get_flow_meta(&self, flow_id: &Uuid) -> Result<FlowMeta>;
+  async fn create_flow(&self, name: Option<String>, status: Option<String>, metadata: JsonValue) -> Result<Uuid>;
+  async fn create_flow_as(&self,
+                          name: Option<String>,
+                          status: Option<String>,
+                          metadata: JsonValue,
+                          created_by: Option<String>)
+                          -> Result<Uuid>;
+  async fn persist_data(&self, data: &FlowData, expected_version: i64) -> Result<PersistResult>;
+  async fn read_data(&self, flow_id: &Uuid, from_cursor: i64) -> Result<Vec<FlowData>>;
+  async fn query_data(&self, flow_id: &Uuid, query: &FlowDataQuery) -> Result<Vec<FlowData>>;
+  async fn query_data_meta(&self, flow_id: &Uuid, query: &FlowDataQuery) -> Result<Vec<FlowDataMeta>>;
+  async fn latest_by_key(&self, flow_id: &Uuid, key: &str) -> Result<Option<FlowData>>;
+  /// Igual que `FlowRepository::subscribe`. La `FlowSubscription` devuelta
+  /// es bloqueante: desde tareas async conviene consumirla con `try_next` o
+  /// dentro de `spawn_blocking`.
+  async fn subscribe(&self, flow_id: &Uuid, from_cursor: i64) -> Result<FlowSubscription>;
+  async fn load_latest_snapshot(&self, flow_id: &Uuid) -> Result<Option<SnapshotMeta>>;
+  async fn load_snapshot_at(&self, flow_id: &Uuid, cursor: i64) -> Result<Option<SnapshotMeta>>;
+  async fn load_snapshot(&self, snapshot_id: &Uuid) -> Result<(Vec<u8>, SnapshotMeta)>;
+  async fn save_snapshot(&self, flow_id: &Uuid, cursor: i64, state_ptr: &str, metadata: JsonValue) -> Result<Uuid>;
+  async fn save_snapshot_state(&self, flow_id: &Uuid, cursor: i64, state: &[u8], metadata: JsonValue) -> Result<Uuid>;
+  async fn compact_snapshots(&self, flow_id: &Uuid) -> Result<usize>;
+  async fn gc_snapshots(&self) -> Result<SnapshotGcReport>;
+  async fn create_branch(&self, parent_flow_id: &Uuid, parent_cursor: i64, metadata: JsonValue) -> Result<Uuid>;
+  async fn branch_exists(&self, flow_id: &Uuid) -> Result<bool>;
+  async fn list_children(&self, flow_id: &Uuid) -> Result<Vec<FlowMeta>>;
+  async fn count_steps(&self, flow_id: &Uuid) -> Result<i64>;
+  async fn delete_branch(&self, flow_id: &Uuid) -> Result<()>;
+  async fn delete_from_step(&self, flow_id: &Uuid, from_cursor: i64) -> Result<()>;
+  async fn undelete_steps(&self, flow_id: &Uuid) -> Result<()>;
+  async fn undelete(&self, flow_id: &Uuid) -> Result<()>;
+  async fn list_deleted_flows(&self) -> Result<Vec<FlowMeta>>;
+  async fn purge_deleted(&self, retention: Duration) -> Result<PurgeReport>;
+  async fn rewrite_data(&self, rows: &[FlowData]) -> Result<usize>;
+  /// Igual que `FlowRepository::lock_for_update`, pero la espera no bloquea
+  /// el hilo del runtime.
+  async fn lock_for_update(&self, flow_id: &Uuid, expected_version: i64, timeout: Duration)
+                           -> Result<Option<FlowLockGuard>>;
+  async fn enqueue_work(&self, flow_id: &Uuid) -> Result<()>;
+  async fn claim_work(&self, worker_id: &str) -> Result<Option<WorkItem>>;
+  async fn heartbeat_work(&self, flow_id: &Uuid, worker_id: &str) -> Result<bool>;
+  async fn complete_work(&self, flow_id: &Uuid, worker_id: &str) -> Result<bool>;
+  async fn get_flow_status(&self, flow_id: &Uuid) -> Result<Option<String>>;
+  async fn set_flow_status(&self, flow_id: &Uuid, new_status: Option<String>) -> Result<FlowMeta>;
+  async fn get_meta(&self, flow_id: &Uuid, key: &str) -> Result<JsonValue>;
+  async fn set_meta(&self, flow_id: &Uuid, key: &str, value: JsonValue) -> Result<()>;
+  async fn del_meta(&self, flow_id: &Uuid, key: &str) -> Result<()>;
+  async fn list_flow_ids(&self) -> Result<Vec<Uuid>>;
+  async fn query_flows(&self, query: &FlowQuery) -> Result<FlowPage>;
+  async fn dump_tables_for_debug(&self) -> Result<(Vec<FlowMeta>, Vec<FlowData>)>;
+}
+/// Adapta un `FlowRepository` síncrono (Diesel, in-memory, ...) a
+/// `AsyncFlowRepository` ejecutando cada llamada con
+/// `tokio::task::spawn_blocking`, de modo que las conexiones bloqueantes no
+/// ocupan los hilos del runtime. Debe usarse dentro de un runtime tokio.
+#[derive(Clone)]
+pub struct AsyncFlowAdapter {
+  inner: Arc<dyn FlowRepository>,
+}
+impl AsyncFlowAdapter {
+  pub fn new(inner: Arc<dyn FlowRepository>) -> Self {
+    Self { inner }
+  }
+  /// Repositorio síncrono envuelto.
+  pub fn inner(&self) -> &Arc<dyn FlowRepository> {
+    &self.inner
+  }
+  async fn run<T, F>(&self, f: F) -> Result<T>
+    where T: Send + 'static,
+          F: FnOnce(&dyn FlowRepository) -> Result<T> + Send + 'static
+  {
+    let inner = Arc::clone(&self.inner);
+    tokio::task::spawn_blocking(move || f(inner.as_ref())).await
+                                                          .map_err(|e| FlowError::Other(format!("spawn_blocking: {}", e)))?
+  }
+}
+#[async_trait]
+impl AsyncFlowRepository for AsyncFlowAdapter {
+  async fn get_flow_meta(&self, flow_id: &Uuid) -> Result<FlowMeta> {
+    let flow_id = *flow_id;
+    self.run(move |r| r.get_flow_meta(&flow_id)).await
+  }
+  async fn create_flow(&self, name: Option<String>, status: Option<String>, metadata: JsonValue) -> Result<Uuid> {
+    self.run(move |r| r.create_flow(name, status, metadata)).await
+  }
+  async fn create_flow_as(&self,
+                          name: Option<String>,
+                          status: Option<String>,
+                          metadata: JsonValue,
+                          created_by: Option<String>)
+                          -> Result<Uuid> {
+    self.run(move |r| r.create_flow_as(name, status, metadata, created_by)).await
+  }
+  async fn persist_data(&self, data: &FlowData, expected_version: i64) -> Result<PersistResult> {
+    let data = data.clone();
+    self.run(move |r| r.persist_data(&data, expected_version)).await
+  }
+  async fn read_data(&self, flow_id: &Uuid, from_cursor: i64) -> Result<Vec<FlowData>> {
+    let flow_id = *flow_id;
+    self.run(move |r| r.read_data(&flow_id, from_cursor)).await
+  }
+  async fn query_data(&self, flow_id: &Uuid, query: &FlowDataQuery) -> Result<Vec<FlowData>> {
+    let (flow_id, query) = (*flow_id, query.clone());
+    self.run(move |r| r.query_data(&flow_id, &query)).await
+  }
+  async fn query_data_meta(&self, flow_id: &Uuid, query: &FlowDataQuery) -> Result<Vec<FlowDataMeta>> {
+    let (flow_id, query) = (*flow_id, query.clone());
+    self.run(move |r| r.query_data_meta(&flow_id, &query)).await
+  }
+  async fn latest_by_key(&self, flow_id: &Uuid, key: &str) -> Result<Option<FlowData>> {
+    let (flow_id, key) = (*flow_id, key.to_string());
+    self.run(move |r| r.latest_by_key(&flow_id, &key)).await
+  }
+  async fn subscribe(&self, flow_id: &Uuid, from_cursor: i64) -> Result<FlowSubscription> {
+    let flow_id = *flow_id;
+    self.run(move |r| r.subscribe(&flow_id, from_cursor)).await
+  }
+  async fn load_latest_snapshot(&self, flow_id: &Uuid) -> Result<Option<SnapshotMeta>> {
+    let flow_id = *flow_id;
+    self.run(move |r| r.load_latest_snapshot(&flow_id)).await
+  }
+  async fn load_snapshot_at(&self, flow_id: &Uuid, cursor: i64) -> Result<Option<SnapshotMeta>> {
+    let flow_id = *flow_id;
+    self.run(move |r| r.load_snapshot_at(&flow_id, cursor)).await
+  }
+  async fn load_snapshot(&self, snapshot_id: &Uuid) -> Result<(Vec<u8>, SnapshotMeta)> {
+    let snapshot_id = *snapshot_id;
+    self.run(move |r| r.load_snapshot(&snapshot_id)).await
+  }
+  async fn save_snapshot(&self, flow_id: &Uuid, cursor: i64, state_ptr: &str, metadata: JsonValue) -> Result<Uuid> {
+    let (flow_id, state_ptr) = (*flow_id, state_ptr.to_string());
+    self.run(move |r| r.save_snapshot(&flow_id, cursor, &state_ptr, metadata)).await
+  }
+  async fn save_snapshot_state(&self, flow_id: &Uuid, cursor: i64, state: &[u8], metadata: JsonValue) -> Result<Uuid> {
+    let (flow_id, state) = (*flow_id, state.to_vec());
+    self.run(move |r| r.save_snapshot_state(&flow_id, cursor, &state, metadata)).await
+  }
+  async fn compact_snapshots(&self, flow_id: &Uuid) -> Result<usize> {
+    let flow_id = *flow_id;
+    self.run(move |r| r.compact_snapshots(&flow_id)).await
+  }
+  async fn gc_snapshots(&self) -> Result<SnapshotGcReport> {
+    self.run(|r| r.gc_snapshots()).await
+  }
+  async fn create_branch(&self, parent_flow_id: &Uuid, parent_cursor: i64, metadata: JsonValue) -> Result<Uuid> {
+    let parent_flow_id = *parent_flow_id;
+    self.run(move |r| r.create_branch(&parent_flow_id, parent_cursor, metadata)).await
+  }
+  async fn branch_exists(&self, flow_id: &Uuid) -> Result<bool> {
+    let flow_id = *flow_id;
+    self.run(move |r| r.branch_exists(&flow_id)).await
+  }
+  async fn list_children(&self, flow_id: &Uuid) -> Result<Vec<FlowMeta>> {
+    let flow_id = *flow_id;
+    self.run(move |r| r.list_children(&flow_id)).await
+  }
+  async fn count_steps(&self, flow_id: &Uuid) -> Result<i64> {
+    let flow_id = *flow_id;
+    self.run(move |r| r.count_steps(&flow_id)).await
+  }
+  async fn delete_branch(&self, flow_id: &Uuid) -> Result<()> {
+    let flow_id = *flow_id;
+    self.run(move |r| r.delete_branch(&flow_id)).await
+  }
+  async fn delete_from_step(&self, flow_id: &Uuid, from_cursor: i64) -> Result<()> {
+    let flow_id = *flow_id;
+    self.run(move |r| r.delete_from_step(&flow_id, from_cursor)).await
+  }
+  async fn undelete_steps(&self, flow_id: &Uuid) -> Result<()> {
+    let flow_id = *flow_id;
+    self.run(move |r| r.undelete_steps(&flow_id)).await
+  }
+  async fn undelete(&self, flow_id: &Uuid) -> Result<()> {
+    let flow_id = *flow_id;
+    self.run(move |r| r.undelete(&flow_id)).await
+  }
+  async fn list_deleted_flows(&self) -> Result<Vec<FlowMeta>> {
+    self.run(|r| r.list_deleted_flows()).await
+  }
+  async fn purge_deleted(&self, retention: Duration) -> Result<PurgeReport> {
+    self.run(move |r| r.purge_deleted(retention)).await
+  }
+  async fn rewrite_data(&self, rows: &[FlowData]) -> Result<usize> {
+    let rows = rows.to_vec();
+    self.run(move |r| r.rewrite_data(&rows)).await
+  }
+  async fn lock_for_update(&self,
+                           flow_id: &Uuid,
+                           expected_version: i64,
+                           timeout: Duration)
+                           -> Result<Option<FlowLockGuard>> {
+    let flow_id = *flow_id;
+    self.run(move |r| r.lock_for_update(&flow_id, expected_version, timeout)).await
+  }
+  async fn enqueue_work(&self, flow_id: &Uuid) -> Result<()> {
+    let flow_id = *flow_id;
+    self.run(move |r| r.enqueue_work(&flow_id)).await
+  }
+  async fn claim_work(&self, worker_id: &str) -> Result<Option<WorkItem>> {
+    let worker_id = worker_id.to_string();
+    self.run(move |r| r.claim_work(&worker_id)).await
+  }
+  async fn heartbeat_work(&self, flow_id: &Uuid, worker_id: &str) -> Result<bool> {
+    let (flow_id, worker_id) = (*flow_id, worker_id.to_string());
+    self.run(move |r| r.heartbeat_work(&flow_id, &worker_id)).await
+  }
+  async fn complete_work(&self, flow_id: &Uuid, worker_id: &str) -> Result<bool> {
+    let (flow_id, worker_id) = (*flow_id, worker_id.to_string());
+    self.run(move |r| r.complete_work(&flow_id, &worker_id)).await
+  }
+  async fn get_flow_status(&self, flow_id: &Uuid) -> Result<Option<String>> {
+    let flow_id = *flow_id;
+    self.run(move |r| r.get_flow_status(&flow_id)).await
+  }
+  async fn set_flow_status(&self, flow_id: &Uuid, new_status: Option<String>) -> Result<FlowMeta> {
+    let flow_id = *flow_id;
+    self.run(move |r| r.set_flow_status(&flow_id, new_status)).await
+  }
+  async fn get_meta(&self, flow_id: &Uuid, key: &str) -> Result<JsonValue> {
+    let (flow_id, key) = (*flow_id, key.to_string());
+    self.run(move |r| r.get_meta(&flow_id, &key)).await
+  }
+  async fn set_meta(&self, flow_id: &Uuid, key: &str, value: JsonValue) -> Result<()> {
+    let (flow_id, key) = (*flow_id, key.to_string());
+    self.run(move |r| r.set_meta(&flow_id, &key, value)).await
+  }
+  async fn del_meta(&self, flow_id: &Uuid, key: &str) -> Result<()> {
+    let (flow_id, key) = (*flow_id, key.to_string());
+    self.run(move |r| r.del_meta(&flow_id, &key)).await
+  }
+  async fn list_flow_ids(&self) -> Result<Vec<Uuid>> {
+    self.run(|r| r.list_flow_ids()).await
+  }
+  async fn query_flows(&self, query: &FlowQuery) -> Result<FlowPage> {
+    let query = query.clone();
+    self.run(move |r| r.query_flows(&query)).await
+  }
+  async fn dump_tables_for_debug(&self) -> Result<(Vec<FlowMeta>, Vec<FlowData>)> {
+    self.run(|r| r.dump_tables_for_debug()).await
+  }
+}
+/// Expone un `AsyncFlowRepository` como `FlowRepository` síncrono
+/// resolviendo cada llamada con `Handle::block_on`.
+///
+/// Las llamadas bloquean el hilo actual, por lo que deben hacerse fuera de los
+/// hilos del runtime: desde hilos propios o dentro de
+/// `tokio::task::spawn_blocking` (llamarlo desde una tarea async provoca un
+/// `panic!` de tokio).
+#[derive(Clone)]
+pub struct BlockingFlowAdapter {
+  inner: Arc<dyn AsyncFlowRepository>,
+  handle: Handle,
+}
+impl BlockingFlowAdapter {
+  /// Envuelve `inner` usando el runtime de `handle` para ejecutar las
+  /// llamadas.
+  pub fn new(inner: Arc<dyn AsyncFlowRepository>, handle: Handle) -> Self {
+    Self { inner, handle }
+  }
+  /// Igual que `new` tomando el runtime actual. Debe llamarse dentro de un
+  /// contexto tokio (`Handle::current`).
+  pub fn from_current(inner: Arc<dyn AsyncFlowRepository>) -> Self {
+    Self::new(inner, Handle::current())
+  }
+  /// Repositorio asíncrono envuelto.
+  pub fn inner(&self) -> &Arc<dyn AsyncFlowRepository> {
+    &self.inner
+  }
+}
+impl FlowRepository for BlockingFlowAdapter {
+  fn get_flow_meta(&self, flow_id: &Uuid) -> Result<FlowMeta> {
+    self.handle.block_on(self.inner.get_flow_meta(flow_id))
+  }
+  fn create_flow(&self, name: Option<String>, status: Option<String>, metadata: JsonValue) -> Result<Uuid> {
+    self.handle.block_on(self.inner.create_flow(name, status, metadata))
+  }
+  fn create_flow_as(&self,
+                    name: Option<String>,
+                    status: Option<String>,
+                    metadata: JsonValue,
+                    created_by: Option<String>)
+                    -> Result<Uuid> {
+    self.handle.block_on(self.inner.create_flow_as(name, status, metadata, created_by))
+  }
+  fn persist_data(&self, data: &FlowData, expected_version: i64) -> Result<PersistResult> {
+    self.handle.block_on(self.inner.persist_data(data, expected_version))
+  }
+  fn read_data(&self, flow_id: &Uuid, from_cursor: i64) -> Result<Vec<FlowData>> {
+    self.handle.block_on(self.inner.read_data(flow_id, from_cursor))
+  }
+  fn query_data(&self, flow_id: &Uuid, query: &FlowDataQuery) -> Result<Vec<FlowData>> {
+    self.handle.block_on(self.inner.query_data(flow_id, query))
+  }
+  fn query_data_meta(&self, flow_id: &Uuid, query: &FlowDataQuery) -> Result<Vec<FlowDataMeta>> {
+    self.handle.block_on(self.inner.query_data_meta(flow_id, query))
+  }
+  fn latest_by_key(&self, flow_id: &Uuid, key: &str) -> Result<Option<FlowData>> {
+    self.handle.block_on(self.inner.latest_by_key(flow_id, key))
+  }
+  fn subscribe(&self, flow_id: &Uuid, from_cursor: i64) -> Result<FlowSubscription> {
+    self.handle.block_on(self.inner.subscribe(flow_id, from_cursor))
+  }
+  fn load_latest_snapshot(&self, flow_id: &Uuid) -> Result<Option<SnapshotMeta>> {
+    self.handle.block_on(self.inner.load_latest_snapshot(flow_id))
+  }
+  fn load_snapshot_at(&self, flow_id: &Uuid, cursor: i64) -> Result<Option<SnapshotMeta>> {
+    self.handle.block_on(self.inner.load_snapshot_at(flow_id, cursor))
+  }
+  fn load_snapshot(&self, snapshot_id: &Uuid) -> Result<(Vec<u8>, SnapshotMeta)> {
+    self.handle.block_on(self.inner.load_snapshot(snapshot_id))
+  }
+  fn save_snapshot(&self, flow_id: &Uuid, cursor: i64, state_ptr: &str, metadata: JsonValue) -> Result<Uuid> {
+    self.handle.block_on(self.inner.save_snapshot(flow_id, cursor, state_ptr, metadata))
+  }
+  fn save_snapshot_state(&self, flow_id: &Uuid, cursor: i64, state: &[u8], metadata: JsonValue) -> Result<Uuid> {
+    self.handle.block_on(self.inner.save_snapshot_state(flow_id, cursor, state, metadata))
+  }
+  fn compact_snapshots(&self, flow_id: &Uuid) -> Result<usize> {
+    self.handle.block_on(self.inner.compact_snapshots(flow_id))
+  }
+  fn gc_snapshots(&self) -> Result<SnapshotGcReport> {
+    self.handle.block_on(self.inner.gc_snapshots())
+  }
+  fn create_branch(&self, parent_flow_id: &Uuid, parent_cursor: i64, metadata: JsonValue) -> Result<Uuid> {
+    self.handle.block_on(self.inner.create_branch(parent_flow_id, parent_cursor, metadata))
+  }
+  fn branch_exists(&self, flow_id: &Uuid) -> Result<bool> {
+    self.handle.block_on(self.inner.branch_exists(flow_id))
+  }
+  fn list_children(&self, flow_id: &Uuid) -> Result<Vec<FlowMeta>> {
+    self.handle.block_on(self.inner.list_children(flow_id))
+  }
+  fn count_steps(&self, flow_id: &Uuid) -> Result<i64> {
+    self.handle.block_on(self.inner.count_steps(flow_id))
+  }
+  fn delete_branch(&self, flow_id: &Uuid) -> Result<()> {
+    self.handle.block_on(self.inner.delete_branch(flow_id))
+  }
+  fn delete_from_step(&self, flow_id: &Uuid, from_cursor: i64) -> Result<()> {
+    self.handle.block_on(self.inner.delete_from_step(flow_id, from_cursor))
+  }
+  fn undelete_steps(&self, flow_id: &Uuid) -> Result<()> {
+    self.handle.block_on(self.inner.undelete_steps(flow_id))
+  }
+  fn undelete(&self, flow_id: &Uuid) -> Result<()> {
+    self.handle.block_on(self.inner.undelete(flow_id))
+  }
+  fn list_deleted_flows(&self) -> Result<Vec<FlowMeta>> {
+    self.handle.block_on(self.inner.list_deleted_flows())
+  }
+  fn purge_deleted(&self, retention: Duration) -> Result<PurgeReport> {
+    self.handle.block_on(self.inner.purge_deleted(retention))
+  }
+  fn rewrite_data(&self, rows: &[FlowData]) -> Result<usize> {
+    self.handle.block_on(self.inner.rewrite_data(rows))
+  }
+  fn lock_for_update(&self, flow_id: &Uuid, expected_version: i64, timeout: Duration) -> Result<Option<FlowLockGuard>> {
+    self.handle.block_on(self.inner.lock_for_update(flow_id, expected_version, timeout))
+  }
+  fn enqueue_work(&self, flow_id: &Uuid) -> Result<()> {
+    self.handle.block_on(self.inner.enqueue_work(flow_id))
+  }
+  fn claim_work(&self, worker_id: &str) -> Result<Option<WorkItem>> {
+    self.handle.block_on(self.inner.claim_work(worker_id))
+  }
+  fn heartbeat_work(&self, flow_id: &Uuid, worker_id: &str) -> Result<bool> {
+    self.handle.block_on(self.inner.heartbeat_work(flow_id, worker_id))
+  }
+  fn complete_work(&self, flow_id: &Uuid, worker_id: &str) -> Result<bool> {
+    self.handle.block_on(self.inner.complete_work(flow_id, worker_id))
+  }
+  fn get_flow_status(&self, flow_id: &Uuid) -> Result<Option<String>> {
+    self.handle.block_on(self.inner.get_flow_status(flow_id))
+  }
+  fn set_flow_status(&self, flow_id: &Uuid, new_status: Option<String>) -> Result<FlowMeta> {
+    self.handle.block_on(self.inner.set_flow_status(flow_id, new_status))
+  }
+  fn get_meta(&self, flow_id: &Uuid, key: &str) -> Result<JsonValue> {
+    self.handle.block_on(self.inner.get_meta(flow_id, key))
+  }
+  fn set_meta(&self, flow_id: &Uuid, key: &str, value: JsonValue) -> Result<()> {
+    self.handle.block_on(self.inner.set_meta(flow_id, key, value))
+  }
+  fn del_meta(&self, flow_id: &Uuid, key: &str) -> Result<()> {
+    self.handle.block_on(self.inner.del_meta(flow_id, key))
+  }
+  fn list_flow_ids(&self) -> Result<Vec<Uuid>> {
+    self.handle.block_on(self.inner.list_flow_ids())
+  }
+  fn query_flows(&self, query: &FlowQuery) -> Result<FlowPage> {
+    self.handle.block_on(self.inner.query_flows(query))
+  }
+  fn dump_tables_for_debug(&self) -> Result<(Vec<FlowMeta>, Vec<FlowData>)> {
+    self.handle.block_on(self.inner.dump_tables_for_debug())
+  }
+}
@@ -0,0 +1,366 @@
+// Archivo: audit.rs
+// Propósito: registro de auditoría de las llamadas que modifican un
+// repositorio (quién, qué operación, sobre qué flow, valor antes/después y
+// cuándo).
+//
+// - `AuditQuery`: filtro por actor, flow y rango de tiempo para
+//   `AuditLog::query`.
+// - `InMemoryAuditLog`: implementación en memoria para tests y desarrollo.
+// - `AuditedFlowRepository`: decorador de cualquier `FlowRepository` que delega
+//   cada llamada y, si modifica algo y termina bien, añade una `AuditEntry` al
+//   `AuditLog` configurado.
+//
+// El decorador equivalente para `DomainRepository` y el `AuditLog` sobre la
+// tabla `audit_log` están en `chem-persistence`.
+use crate::domain::{AuditEntry, FlowData, FlowDataMeta, FlowMeta, PersistResult, SnapshotMeta, WorkItem};
+use crate::errors::{FlowError, Result};
+use crate::query::{FlowDataQuery, FlowPage, FlowQuery};
+use crate::repository::{AuditLog, FlowLockGuard, FlowRepository};
+use crate::retention::{PurgeReport, SnapshotGcReport};
+use crate::subscription::FlowSubscription;
+use chrono::{DateTime, Utc};
+use serde_json::{json, Value as JsonValue};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use uuid::Uuid;
+/// Filtro de `AuditLog::query`. Los campos `None` no filtran; el rango de
+/// tiempo es `[since, until)`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct AuditQuery {
+  pub actor: Option<String>,
+  pub flow_id: Option<Uuid>,
+  pub since: Option<DateTime<Utc>>,
+  pub until: Option<DateTime<Utc>>,
+  /// Número máximo de entradas (las más antiguas primero).
+  pub limit: Option<usize>,
+}
+impl AuditQuery {
+  pub fn new() -> Self {
+    Self::default()
+  }
+  pub fn actor(mut self, actor: impl Into<String>) -> Self {
+    self.actor = Some(actor.into());
+    self
+  }
+  pub fn flow(mut self, flow_id: Uuid) -> Self {
+    self.flow_id = Some(flow_id);
+    self
+  }
+  pub fn since(mut self, since: DateTime<Utc>) -> Self {
+    self.since = Some(since);
+    self
+  }
+  pub fn until(mut self, until: DateTime<Utc>) -> Self {
+    self.until = Some(until);
+    self
+  }
+  pub fn limit(mut self, limit: usize) -> Self {
+    self.limit = Some(limit);
+    self
+  }
+  /// `true` si `entry` cumple el filtro (sin tener en cuenta `limit`).
+  pub fn matches(&self, entry: &AuditEntry) -> bool {
+    self.actor.as_ref().is_none_or(|a| entry.actor.as_ref() == Some(a))
+    && self.flow_id.is_none_or(|f| entry.flow_id == Some(f))
+    && self.since.is_none_or(|s| entry.at >= s)
+    && self.until.is_none_or(|u| entry.at < u)
+  }
+}
+/// `AuditLog` en memoria, en orden de inserción.
+#[derive(Default)]
+pub struct InMemoryAuditLog {
+  entries: Mutex<Vec<AuditEntry>>,
+}
+impl InMemoryAuditLog {
+  pub fn new() -> Self {
+    Self::default()
+  }
+}
+impl AuditLog for InMemoryAuditLog {
+  fn append(&self, entry: &AuditEntry) -> Result<()> {
+    self.entries.lock().map_err(|e| FlowError::Storage(format!("audit log: {}", e)))?.push(entry.clone());
+    Ok(())
+  }
+  fn query(&self, query: &AuditQuery) -> Result<Vec<AuditEntry>> {
+    let entries = self.entries.lock().map_err(|e| FlowError::Storage(format!("audit log: {}", e)))?;
+    let mut found: Vec<AuditEntry> = entries.iter().filter(|e| query.matches(e)).cloned().collect();
+    // orden estable: a igual instante se conserva el de inserción
+    found.sort_by_key(|e| e.at);
+    found.truncate(query.limit.unwrap_or(usize::MAX));
+    Ok(found)
+  }
+}
+/// Entrada nueva con el instante actual.
+pub fn audit_entry(actor: Option<&str>,
+                   operation: &str,
+                   flow_id: Option<Uuid>,
+                   target: Option<String>,
+                   before: Option<JsonValue>,
+                   after: Option<JsonValue>)
+                   -> AuditEntry {
+  AuditEntry { id: Uuid::new_v4(),
+               at: Utc::now(),
+               actor: actor.map(String::from),
+               operation: operation.to_string(),
+               flow_id,
+               target,
+               before,
+               after }
+}
+/// `FlowRepository` que audita las llamadas de `inner` en `log`.
+///
+/// Solo se registran las operaciones que modifican algo y terminan bien
+/// (un `persist_data` con `PersistResult::Conflict` no deja entrada). Los
+/// valores `before` se leen de `inner` justo antes de la llamada, sin lock,
+/// así que con escritores concurrentes son orientativos. Si `log.append`
+/// falla, la operación ya está hecha y se devuelve el error del registro.
+pub struct AuditedFlowRepository {
+  inner: Arc<dyn FlowRepository>,
+  log: Arc<dyn AuditLog>,
+  actor: Option<String>,
+}
+impl AuditedFlowRepository {
+  pub fn new(inner: Arc<dyn FlowRepository>, log: Arc<dyn AuditLog>) -> Self {
+    Self { inner, log, actor: None }
+  }
+  /// Actor que se anota en las entradas (usuario o servicio).
+  pub fn with_actor(mut self, actor: impl Into<String>) -> Self {
+    self.actor = Some(actor.into());
+    self
+  }
+  pub fn audit_log(&self) -> &Arc<dyn AuditLog> {
+    &self.log
+  }
+  fn record(&self,
+            operation: &str,
+            flow_id: Option<Uuid>,
+            target: Option<String>,
+            before: Option<JsonValue>,
+            after: Option<JsonValue>)
+            -> Result<()> {
+    self.log.append(&audit_entry(self.actor.as_deref(), operation, flow_id, target, before, after))
+  }
+}
+fn meta_json(meta: &FlowMeta) -> Option<JsonValue> {
+  serde_json::to_value(meta).ok()
+}
+impl FlowRepository for AuditedFlowRepository {
+  fn rewrap(&self, repo: Arc<dyn FlowRepository>) -> Arc<dyn FlowRepository> {
+    Arc::new(AuditedFlowRepository { inner: self.inner.rewrap(repo), log: self.log.clone(), actor: self.actor.clone() })
+  }
+  fn get_flow_meta(&self, flow_id: &Uuid) -> Result<FlowMeta> {
+    self.inner.get_flow_meta(flow_id)
+  }
+  fn create_flow(&self, name: Option<String>, status: Option<String>, metadata: JsonValue) -> Result<Uuid> {
+    let after = json!({ "name": name, "status": status, "metadata": metadata });
+    let id = self.inner.create_flow(name, status, metadata)?;
+    self.record("create_flow", Some(id), None, None, Some(after))?;
+    Ok(id)
+  }
+  fn create_flow_as(&self,
+                    name: Option<String>,
+                    status: Option<String>,
+                    metadata: JsonValue,
+                    created_by: Option<String>)
+                    -> Result<Uuid> {
+    let after = json!({ "name": name, "status": status, "metadata": metadata, "created_by": created_by });
+    let id = self.inner.create_flow_as(name, status, metadata, created_by)?;
+    self.record("create_flow", Some(id), None, None, Some(after))?;
+    Ok(id)
+  }
+  fn persist_data(&self, data: &FlowData, expected_version: i64) -> Result<PersistResult> {
+    let res = self.inner.persist_data(data, expected_version)?;
+    if let PersistResult::Ok { new_version } = res {
+      self.record("persist_data",
+                  Some(data.flow_id),
+                  Some(data.cursor.to_string()),
+                  None,
+                  Some(json!({ "id": data.id,
+                               "key": data.key,
+                               "command_id": data.command_id,
+                               "new_version": new_version })))?;
+    }
+    Ok(res)
+  }
+  fn read_data(&self, flow_id: &Uuid, from_cursor: i64) -> Result<Vec<FlowData>> {
+    self.inner.read_data(flow_id, from_cursor)
+  }
+  fn query_data(&self, flow_id: &Uuid, query: &FlowDataQuery) -> Result<Vec<FlowData>> {
+    self.inner.query_data(flow_id, query)
+  }
+  fn query_data_meta(&self, flow_id: &Uuid, query: &FlowDataQuery) -> Result<Vec<FlowDataMeta>> {
+    self.inner.query_data_meta(flow_id, query)
+  }
+  fn latest_by_key(&self, flow_id: &Uuid, key: &str) -> Result<Option<FlowData>> {
+    self.inner.latest_by_key(flow_id, key)
+  }
+  fn subscribe(&self, flow_id: &Uuid, from_cursor: i64) -> Result<FlowSubscription> {
+    self.inner.subscribe(flow_id, from_cursor)
+  }
+  fn load_latest_snapshot(&self, flow_id: &Uuid) -> Result<Option<SnapshotMeta>> {
+    self.inner.load_latest_snapshot(flow_id)
+  }
+  fn load_snapshot_at(&self, flow_id: &Uuid, cursor: i64) -> Result<Option<SnapshotMeta>> {
+    self.inner.load_snapshot_at(flow_id, cursor)
+  }
+  fn load_snapshot(&self, snapshot_id: &Uuid) -> Result<(Vec<u8>, SnapshotMeta)> {
+    self.inner.load_snapshot(snapshot_id)
+  }
+  fn save_snapshot(&self, flow_id: &Uuid, cursor: i64, state_ptr: &str, metadata: JsonValue) -> Result<Uuid> {
+    let after = json!({ "cursor": cursor, "state_ptr": state_ptr, "metadata": metadata });
+    let id = self.inner.save_snapshot(flow_id, cursor, state_ptr, metadata)?;
+    self.record("save_snapshot", Some(*flow_id), Some(id.to_string()), None, Some(after))?;
+    Ok(id)
+  }
+  fn save_snapshot_state(&self, flow_id: &Uuid, cursor: i64, state: &[u8], metadata: JsonValue) -> Result<Uuid> {
+    let after = json!({ "cursor": cursor, "bytes": state.len(), "metadata": metadata });
+    let id = self.inner.save_snapshot_state(flow_id, cursor, state, metadata)?;
+    self.record("save_snapshot_state", Some(*flow_id), Some(id.to_string()), None, Some(after))?;
+    Ok(id)
+  }
+  fn compact_snapshots(&self, flow_id: &Uuid) -> Result<usize> {
+    let removed = self.inner.compact_snapshots(flow_id)?;
+    self.record("compact_snapshots",
+                Some(*flow_id),
+                None,
+                None,
+                Some(json!({ "snapshots_removed": removed })))?;
+    Ok(removed)
+  }
+  fn gc_snapshots(&self) -> Result<SnapshotGcReport> {
+    let report = self.inner.gc_snapshots()?;
+    self.record("gc_snapshots",
+                None,
+                None,
+                None,
+                Some(json!({ "snapshots_removed": report.snapshots_removed, "blobs_removed": report.blobs_removed })))?;
+    Ok(report)
+  }
+  fn create_branch(&self, parent_flow_id: &Uuid, parent_cursor: i64, metadata: JsonValue) -> Result<Uuid> {
+    let after = json!({ "parent_flow_id": parent_flow_id, "parent_cursor": parent_cursor, "metadata": metadata });
+    let id = self.inner.create_branch(parent_flow_id, parent_cursor, metadata)?;
+    self.record("create_branch", Some(id), None, None, Some(after))?;
+    Ok(id)
+  }
+  fn branch_exists(&self, flow_id: &Uuid) -> Result<bool> {
+    self.inner.branch_exists(flow_id)
+  }
+  fn list_children(&self, flow_id: &Uuid) -> Result<Vec<FlowMeta>> {
+    self.inner.list_children(flow_id)
+  }
+  fn count_steps(&self, flow_id: &Uuid) -> Result<i64> {
+    self.inner.count_steps(flow_id)
+  }
+  fn delete_branch(&self, flow_id: &Uuid) -> Result<()> {
+    let before = self.inner.get_flow_meta(flow_id).ok();
+    self.inner.delete_branch(flow_id)?;
+    self.record("delete_branch", Some(*flow_id), None, before.as_ref().and_then(meta_json), None)
+  }
+  fn delete_from_step(&self, flow_id: &Uuid, from_cursor: i64) -> Result<()> {
+    let before = self.inner.get_flow_meta(flow_id).ok().map(|m| json!({ "current_cursor": m.current_cursor }));
+    self.inner.delete_from_step(flow_id, from_cursor)?;
+    let after = self.inner.get_flow_meta(flow_id).ok().map(|m| json!({ "current_cursor": m.current_cursor }));
+    self.record("delete_from_step", Some(*flow_id), Some(from_cursor.to_string()), before, after)
+  }
+  fn undelete_steps(&self, flow_id: &Uuid) -> Result<()> {
+    let before = self.inner.get_flow_meta(flow_id).ok().map(|m| json!({ "current_cursor": m.current_cursor }));
+    self.inner.undelete_steps(flow_id)?;
+    let after = self.inner.get_flow_meta(flow_id).ok().map(|m| json!({ "current_cursor": m.current_cursor }));
+    self.record("undelete_steps", Some(*flow_id), None, before, after)
+  }
+  fn undelete(&self, flow_id: &Uuid) -> Result<()> {
+    self.inner.undelete(flow_id)?;
+    let after = self.inner.get_flow_meta(flow_id).ok();
+    self.record("undelete", Some(*flow_id), None, None, after.as_ref().and_then(meta_json))
+  }
+  fn list_deleted_flows(&self) -> Result<Vec<FlowMeta>> {
+    self.inner.list_deleted_flows()
+  }
+  fn purge_deleted(&self, retention: Duration) -> Result<PurgeReport> {
+    let report = self.inner.purge_deleted(retention)?;
+    self.record("purge_deleted",
+                None,
+                None,
+                None,
+                Some(json!({ "retention_secs": retention.as_secs(),
+                             "flows": report.flows,
+                             "records": report.records,
+                             "snapshots": report.snapshots })))?;
+    Ok(report)
+  }
+  /// Una entrada por registro reescrito, con su payload y metadata nuevos.
+  fn rewrite_data(&self, rows: &[FlowData]) -> Result<usize> {
+    let n = self.inner.rewrite_data(rows)?;
+    for row in rows {
+      self.record("rewrite_data",
+                  Some(row.flow_id),
+                  Some(row.cursor.to_string()),
+                  None,
+                  Some(json!({ "id": row.id, "payload": row.payload, "metadata": row.metadata })))?;
+    }
+    Ok(n)
+  }
+  fn lock_for_update(&self, flow_id: &Uuid, expected_version: i64, timeout: Duration) -> Result<Option<FlowLockGuard>> {
+    self.inner.lock_for_update(flow_id, expected_version, timeout)
+  }
+  fn enqueue_work(&self, flow_id: &Uuid) -> Result<()> {
+    self.inner.enqueue_work(flow_id)?;
+    self.record("enqueue_work", Some(*flow_id), None, None, None)
+  }
+  fn claim_work(&self, worker_id: &str) -> Result<Option<WorkItem>> {
+    let item = self.inner.claim_work(worker_id)?;
+    if let Some(item) = &item {
+      self.record("claim_work",
+                  Some(item.flow_id),
+                  Some(worker_id.to_string()),
+                  None,
+                  Some(json!({ "attempts": item.attempts, "lease_expires_at": item.lease_expires_at })))?;
+    }
+    Ok(item)
+  }
+  fn heartbeat_work(&self, flow_id: &Uuid, worker_id: &str) -> Result<bool> {
+    let renewed = self.inner.heartbeat_work(flow_id, worker_id)?;
+    if renewed {
+      self.record("heartbeat_work", Some(*flow_id), Some(worker_id.to_string()), None, None)?;
+    }
+    Ok(renewed)
+  }
+  fn complete_work(&self, flow_id: &Uuid, worker_id: &str) -> Result<bool> {
+    let completed = self.inner.complete_work(flow_id, worker_id)?;
+    if completed {
+      self.record("complete_work", Some(*flow_id), Some(worker_id.to_string()), None, None)?;
+    }
+    Ok(completed)
+  }
+  fn get_flow_status(&self, flow_id: &Uuid) -> Result<Option<String>> {
+    self.inner.get_flow_status(flow_id)
+  }
+  fn set_flow_status(&self, flow_id: &Uuid, new_status: Option<String>) -> Result<FlowMeta> {
+    let before = self.inner.get_flow_status(flow_id).ok().map(|s| json!(s));
+    let meta = self.inner.set_flow_status(flow_id, new_status)?;
+    self.record("set_flow_status", Some(*flow_id), None, before, Some(json!(meta.status)))?;
+    Ok(meta)
+  }
+  fn get_meta(&self, flow_id: &Uuid, key: &str) -> Result<JsonValue> {
+    self.inner.get_meta(flow_id, key)
+  }
+  fn set_meta(&self, flow_id: &Uuid, key: &str, value: JsonValue) -> Result<()> {
+    let before = self.inner.get_meta(flow_id, key).ok();
+    self.inner.set_meta(flow_id, key, value.clone())?;
+    self.record("set_meta", Some(*flow_id), Some(key.to_string()), before, Some(value))
+  }
+  fn del_meta(&self, flow_id: &Uuid, key: &str) -> Result<()> {
+    let before = self.inner.get_meta(flow_id, key).ok();
+    self.inner.del_meta(flow_id, key)?;
+    self.record("del_meta", Some(*flow_id), Some(key.to_string()), before, None)
+  }
+  fn list_flow_ids(&self) -> Result<Vec<Uuid>> {
+    self.inner.list_flow_ids()
+  }
+  fn query_flows(&self, query: &FlowQuery) -> Result<FlowPage> {
+    self.inner.query_flows(query)
+  }
+  fn dump_tables_for_debug(&self) -> Result<(Vec<FlowMeta>, Vec<FlowData>)> {
+    self.inner.dump_tables_for_debug()
+  }
+}
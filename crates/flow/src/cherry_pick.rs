@@ -0,0 +1,216 @@
+// Archivo: cherry_pick.rs
+// Propósito: copiar registros seleccionados de una rama a otra (la inversa
+// de `create_branch`), p. ej. devolver al flow padre un resultado corregido
+// del paso 2 calculado en una rama.
+//
+// - `cherry_pick`: añade copias de los `FlowData` elegidos al final de la rama
+//   destino, bajo `lock_for_update` y con `expected_version`, numerando de
+//   nuevo sus cursores.
+// - Conflictos: si el destino ya tiene un registro con la misma key, o otro
+//   de los registros elegidos la repite, se aplica `KeyConflictPolicy`
+//   (abortar, saltar o añadir igualmente).
+// - Las copias se escriben una a una; si una falla, `CherryPickError` lleva
+//   junto al error el informe de las ya confirmadas.
+// - Procedencia: cada copia lleva en `metadata.cherry_picked_from` el flow,
+//   cursor e id del registro original; volver a copiarlo se detecta y se salta,
+//   de modo que la operación es idempotente.
+use crate::domain::{FlowData, PersistResult};
+use crate::errors::FlowError;
+use crate::repository::FlowRepository;
+use chrono::Utc;
+use serde_json::{json, Value as JsonValue};
+use std::collections::BTreeSet;
+use std::time::Duration;
+use thiserror::Error;
+use uuid::Uuid;
+/// Key de `FlowData::metadata` con la procedencia de un registro copiado.
+pub const CHERRY_PICK_METADATA_KEY: &str = "cherry_picked_from";
+/// Espera máxima por defecto del lock del flow destino.
+pub const DEFAULT_CHERRY_PICK_LOCK_TIMEOUT: Duration = Duration::from_secs(5);
+/// Qué hacer cuando el destino ya tiene un registro con la key de uno de los
+/// registros a copiar.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum KeyConflictPolicy {
+  /// Devuelve `FlowError::Conflict` sin copiar nada.
+  #[default]
+  Abort,
+  /// Copia el resto y anota el registro en `CherryPickReport::skipped`.
+  Skip,
+  /// Copia el registro igualmente; al ser el de mayor cursor pasa a ser el
+  /// último de esa key.
+  Append,
+}
+/// Parámetros de `cherry_pick`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CherryPickOptions {
+  /// Versión esperada del flow destino (locking optimista).
+  pub expected_version: i64,
+  pub on_conflict: KeyConflictPolicy,
+  pub lock_timeout: Duration,
+}
+impl CherryPickOptions {
+  pub fn new(expected_version: i64) -> Self {
+    Self { expected_version, on_conflict: KeyConflictPolicy::default(), lock_timeout: DEFAULT_CHERRY_PICK_LOCK_TIMEOUT }
+  }
+  pub fn on_conflict(mut self, policy: KeyConflictPolicy) -> Self {
+    self.on_conflict = policy;
+    self
+  }
+}
+/// Registro copiado por `cherry_pick`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PickedRecord {
+  pub source_cursor: i64,
+  pub target_cursor: i64,
+  pub key: String,
+  /// Id del nuevo registro en el flow destino.
+  pub record_id: Uuid,
+}
+/// Motivo por el que `cherry_pick` no copió un registro.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SkipReason {
+  /// El destino ya tiene una copia de ese registro (misma procedencia).
+  AlreadyApplied,
+  /// El destino ya tiene un registro con esa key (`KeyConflictPolicy::Skip`).
+  KeyConflict,
+}
+/// Registro que `cherry_pick` no copió.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SkippedRecord {
+  pub source_cursor: i64,
+  pub key: String,
+  pub reason: SkipReason,
+}
+/// Resultado de `cherry_pick`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CherryPickReport {
+  pub picked: Vec<PickedRecord>,
+  pub skipped: Vec<SkippedRecord>,
+  /// Versión del flow destino tras la operación (`expected_version` si no
+  /// se copió nada).
+  pub new_version: i64,
+}
+/// Error de `cherry_pick`. Cada copia es un `persist_data` propio y el lock
+/// del destino solo excluye a quien también lo toma, así que una escritura
+/// puede fallar (p. ej. `Conflict` por otro escritor) después de confirmar
+/// las anteriores: `report` lista las que ya están en el destino.
+#[derive(Debug, Error)]
+#[error("{error} ({} registros ya copiados)", report.picked.len())]
+pub struct CherryPickError {
+  pub error: FlowError,
+  pub report: CherryPickReport,
+}
+impl From<FlowError> for CherryPickError {
+  fn from(error: FlowError) -> Self {
+    Self { error, report: CherryPickReport::default() }
+  }
+}
+impl From<CherryPickError> for FlowError {
+  fn from(e: CherryPickError) -> Self {
+    e.error
+  }
+}
+/// Procedencia guardada en `metadata.cherry_picked_from` de una copia.
+fn provenance(data: &FlowData) -> JsonValue {
+  json!({ "flow_id": data.flow_id, "cursor": data.cursor, "record_id": data.id })
+}
+/// Procedencia de un registro del destino, si es una copia.
+fn provenance_of(data: &FlowData) -> Option<(Uuid, i64)> {
+  let origin = data.metadata.get(CHERRY_PICK_METADATA_KEY)?;
+  let flow_id = origin.get("flow_id")?.as_str().and_then(|s| Uuid::parse_str(s).ok())?;
+  Some((flow_id, origin.get("cursor")?.as_i64()?))
+}
+/// Copia los registros de `source` con los cursores dados al final de
+/// `target`, en orden de cursor y con cursores consecutivos a partir del
+/// `current_cursor` del destino.
+///
+/// - Toma `lock_for_update(target, expected_version)`: si la versión no
+///   coincide o no obtiene el lock devuelve `FlowError::Conflict`.
+/// - `Err(FlowError::NotFound)` si alguna rama o alguno de los cursores no
+///   existe.
+/// - Los conflictos de key (con el destino o entre los propios registros
+///   elegidos, donde gana el de menor cursor) se resuelven con
+///   `options.on_conflict` antes de escribir nada; con `Abort` no se copia
+///   ningún registro.
+/// - Si falla la escritura de una copia, el error lleva el informe de las
+///   copias anteriores, que no se deshacen.
+///
+/// Las copias son registros nuevos (id nuevo, sin `command_id`) que
+/// conservan key, payload y metadata del original, más
+/// `metadata.cherry_picked_from`.
+pub fn cherry_pick(repo: &dyn FlowRepository,
+                   source: &Uuid,
+                   target: &Uuid,
+                   cursors: &[i64],
+                   options: &CherryPickOptions)
+                   -> std::result::Result<CherryPickReport, CherryPickError> {
+  if source == target {
+    return Err(FlowError::Other(format!("cherry-pick de {} sobre sí mismo", source)).into());
+  }
+  let wanted: BTreeSet<i64> = cursors.iter().copied().collect();
+  let records: Vec<FlowData> = repo.read_data(source, 0)?.into_iter().filter(|d| wanted.contains(&d.cursor)).collect();
+  if let Some(missing) = wanted.iter().find(|c| !records.iter().any(|d| d.cursor == **c)) {
+    return Err(FlowError::NotFound(format!("flow_data {} cursor {}", source, missing)).into());
+  }
+  let Some(guard) = repo.lock_for_update(target, options.expected_version, options.lock_timeout)? else {
+    let stale = format!("el flow {} ya no está en la versión {}", target, options.expected_version);
+    return Err(FlowError::Conflict(stale).into());
+  };
+  let existing = repo.read_data(target, 0)?;
+  let mut report = CherryPickReport { new_version: options.expected_version, ..Default::default() };
+  let mut to_copy: Vec<FlowData> = Vec::new();
+  for data in records {
+    let reason = if existing.iter().any(|e| provenance_of(e) == Some((data.flow_id, data.cursor))) {
+      Some(SkipReason::AlreadyApplied)
+    } else if existing.iter().chain(&to_copy).any(|e| e.key == data.key) {
+      match options.on_conflict {
+        KeyConflictPolicy::Abort => {
+          return Err(FlowError::Conflict(format!("el flow {} ya tiene o recibe un registro {} (cursor {} de {})",
+                                                 target, data.key, data.cursor, source)).into());
+        }
+        KeyConflictPolicy::Skip => Some(SkipReason::KeyConflict),
+        KeyConflictPolicy::Append => None,
+      }
+    } else {
+      None
+    };
+    match reason {
+      Some(reason) => report.skipped.push(SkippedRecord { source_cursor: data.cursor, key: data.key, reason }),
+      None => to_copy.push(data),
+    }
+  }
+  let mut cursor = repo.get_flow_meta(target)?.current_cursor;
+  for data in to_copy {
+    cursor += 1;
+    let mut metadata = match data.metadata.clone() {
+      JsonValue::Object(map) => map,
+      _ => serde_json::Map::new(),
+    };
+    metadata.insert(CHERRY_PICK_METADATA_KEY.to_string(), provenance(&data));
+    let copy = FlowData { id: Uuid::new_v4(),
+                          flow_id: *target,
+                          cursor,
+                          key: data.key.clone(),
+                          payload: data.payload.clone(),
+                          metadata: JsonValue::Object(metadata),
+                          command_id: None,
+                          created_at: Utc::now() };
+    let error = match repo.persist_data(&copy, report.new_version) {
+      Ok(PersistResult::Ok { new_version }) => {
+        report.new_version = new_version;
+        None
+      }
+      Ok(PersistResult::Conflict) => {
+        Some(FlowError::Conflict(format!("el flow {} cambió durante el cherry-pick (cursor {})", target, cursor)))
+      }
+      Err(e) => Some(e),
+    };
+    if let Some(error) = error {
+      return Err(CherryPickError { error, report });
+    }
+    report.picked
+          .push(PickedRecord { source_cursor: data.cursor, target_cursor: cursor, key: data.key, record_id: copy.id });
+  }
+  drop(guard);
+  Ok(report)
+}
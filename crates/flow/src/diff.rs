@@ -0,0 +1,183 @@
+// Archivo: diff.rs
+// Propósito: comparar dos ramas de un flow registro a registro.
+//
+// - `diff_branches`: alinea los `FlowData` de ambas ramas por `(cursor, key)` a
+//   partir del último cursor que comparten (`lineage::common_ancestor`) y
+//   clasifica cada registro como añadido, eliminado o cambiado.
+// - `json_diff`: diff estructural de dos valores JSON como lista de cambios por
+//   ruta (JSON Pointer, RFC 6901); se usa para payload y metadata.
+//
+// Todos los tipos son serializables para poder volcar el diff como JSON
+// (p. ej. desde el CLI de ejemplo).
+use crate::domain::FlowData;
+use crate::errors::Result;
+use crate::lineage::{common_ancestor, CommonAncestor};
+use crate::repository::FlowRepository;
+use serde::Serialize;
+use serde_json::Value as JsonValue;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write as _;
+use uuid::Uuid;
+/// Cambio en una ruta de un documento JSON.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum JsonChange {
+  /// La ruta solo existe en el documento de la derecha.
+  Added { path: String, value: JsonValue },
+  /// La ruta solo existe en el documento de la izquierda.
+  Removed { path: String, value: JsonValue },
+  /// La ruta existe en ambos con valores distintos.
+  Changed { path: String, from: JsonValue, to: JsonValue },
+}
+impl JsonChange {
+  /// Ruta del cambio como JSON Pointer (`""` es la raíz).
+  pub fn path(&self) -> &str {
+    match self {
+      JsonChange::Added { path, .. } | JsonChange::Removed { path, .. } | JsonChange::Changed { path, .. } => path,
+    }
+  }
+}
+/// Diferencias estructurales de `left` a `right`. Objetos y arrays se
+/// recorren recursivamente (los arrays por posición); cualquier otra
+/// diferencia, incluido un cambio de tipo, es un `Changed` en esa ruta.
+/// Vacío si son iguales.
+pub fn json_diff(left: &JsonValue, right: &JsonValue) -> Vec<JsonChange> {
+  let mut changes = Vec::new();
+  diff_into(left, right, String::new(), &mut changes);
+  changes
+}
+fn diff_into(left: &JsonValue, right: &JsonValue, path: String, out: &mut Vec<JsonChange>) {
+  match (left, right) {
+    (JsonValue::Object(l), JsonValue::Object(r)) => {
+      let keys: BTreeSet<&String> = l.keys().chain(r.keys()).collect();
+      for key in keys {
+        let child = format!("{}/{}", path, key.replace('~', "~0").replace('/', "~1"));
+        match (l.get(key), r.get(key)) {
+          (Some(a), Some(b)) => diff_into(a, b, child, out),
+          (Some(a), None) => out.push(JsonChange::Removed { path: child, value: a.clone() }),
+          (None, Some(b)) => out.push(JsonChange::Added { path: child, value: b.clone() }),
+          (None, None) => {}
+        }
+      }
+    }
+    (JsonValue::Array(l), JsonValue::Array(r)) => {
+      for i in 0..l.len().max(r.len()) {
+        let child = format!("{}/{}", path, i);
+        match (l.get(i), r.get(i)) {
+          (Some(a), Some(b)) => diff_into(a, b, child, out),
+          (Some(a), None) => out.push(JsonChange::Removed { path: child, value: a.clone() }),
+          (None, Some(b)) => out.push(JsonChange::Added { path: child, value: b.clone() }),
+          (None, None) => {}
+        }
+      }
+    }
+    _ if left != right => out.push(JsonChange::Changed { path, from: left.clone(), to: right.clone() }),
+    _ => {}
+  }
+}
+/// Diferencia de un registro `(cursor, key)` entre dos ramas.
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum RecordDiff {
+  /// Solo existe en la rama de la derecha.
+  Added { record: FlowData },
+  /// Solo existe en la rama de la izquierda.
+  Removed { record: FlowData },
+  /// Existe en ambas con payload o metadata distintos.
+  Changed { left: Box<FlowData>, right: Box<FlowData>, payload: Vec<JsonChange>, metadata: Vec<JsonChange> },
+}
+impl RecordDiff {
+  pub fn cursor(&self) -> i64 {
+    match self {
+      RecordDiff::Added { record } | RecordDiff::Removed { record } => record.cursor,
+      RecordDiff::Changed { left, .. } => left.cursor,
+    }
+  }
+  pub fn key(&self) -> &str {
+    match self {
+      RecordDiff::Added { record } | RecordDiff::Removed { record } => &record.key,
+      RecordDiff::Changed { left, .. } => &left.key,
+    }
+  }
+}
+/// Resultado de `diff_branches`.
+#[derive(Debug, Clone, Serialize)]
+pub struct BranchDiff {
+  pub left: Uuid,
+  pub right: Uuid,
+  /// Antepasado común; `None` si las ramas no comparten historial, en cuyo
+  /// caso se comparan todos sus registros.
+  pub common_ancestor: Option<CommonAncestor>,
+  /// Registros posteriores a la bifurcación que difieren, por cursor y key.
+  pub records: Vec<RecordDiff>,
+  /// Registros posteriores a la bifurcación idénticos en ambas ramas.
+  pub unchanged: usize,
+}
+impl BranchDiff {
+  /// `true` si ambas ramas tienen los mismos registros tras la bifurcación.
+  pub fn is_empty(&self) -> bool {
+    self.records.is_empty()
+  }
+  /// Resumen en texto: una línea por registro (`+` añadido, `-` eliminado,
+  /// `~` cambiado) seguida de los cambios de payload y metadata.
+  pub fn render(&self) -> String {
+    let mut out = String::new();
+    let _ = match &self.common_ancestor {
+      Some(a) => writeln!(out, "{} → {} (desde {} cursor {})", self.left, self.right, a.flow_id, a.cursor),
+      None => writeln!(out, "{} → {} (sin antepasado común)", self.left, self.right),
+    };
+    for record in &self.records {
+      let _ = match record {
+        RecordDiff::Added { record } => writeln!(out, "+ {} {} {}", record.cursor, record.key, record.payload),
+        RecordDiff::Removed { record } => writeln!(out, "- {} {} {}", record.cursor, record.key, record.payload),
+        RecordDiff::Changed { left, payload, metadata, .. } => {
+          let _ = writeln!(out, "~ {} {}", left.cursor, left.key);
+          for (section, change) in payload.iter().map(|c| ("payload", c)).chain(metadata.iter().map(|c| ("metadata", c))) {
+            let _ = match change {
+              JsonChange::Added { path, value } => writeln!(out, "    {}{}: + {}", section, path, value),
+              JsonChange::Removed { path, value } => writeln!(out, "    {}{}: - {}", section, path, value),
+              JsonChange::Changed { path, from, to } => writeln!(out, "    {}{}: {} → {}", section, path, from, to),
+            };
+          }
+          Ok(())
+        }
+      };
+    }
+    let _ = writeln!(out, "{} diferencias, {} registros iguales", self.records.len(), self.unchanged);
+    out
+  }
+}
+/// Compara las ramas `left` y `right`: lee de cada una los registros con
+/// `cursor` posterior al último compartido y los alinea por `(cursor,
+/// key)`. Un registro solo en `right` es `Added`, solo en `left` es
+/// `Removed`, y en ambas con payload o metadata distintos es `Changed`
+/// (con su `json_diff`). `Err(FlowError::NotFound)` si alguna no existe.
+pub fn diff_branches(repo: &dyn FlowRepository, left: &Uuid, right: &Uuid) -> Result<BranchDiff> {
+  let ancestor = common_ancestor(repo, left, right)?;
+  let from_cursor = ancestor.map(|a| a.cursor).unwrap_or(0);
+  let index = |flow_id: &Uuid| -> Result<BTreeMap<(i64, String), FlowData>> {
+    Ok(repo.read_data(flow_id, from_cursor)?.into_iter().map(|d| ((d.cursor, d.key.clone()), d)).collect())
+  };
+  let mut lhs = index(left)?;
+  let mut rhs = index(right)?;
+  let keys: BTreeSet<(i64, String)> = lhs.keys().chain(rhs.keys()).cloned().collect();
+  let mut records = Vec::new();
+  let mut unchanged = 0;
+  for key in keys {
+    match (lhs.remove(&key), rhs.remove(&key)) {
+      (Some(l), Some(r)) => {
+        let payload = json_diff(&l.payload, &r.payload);
+        let metadata = json_diff(&l.metadata, &r.metadata);
+        if payload.is_empty() && metadata.is_empty() {
+          unchanged += 1;
+        } else {
+          records.push(RecordDiff::Changed { left: Box::new(l), right: Box::new(r), payload, metadata });
+        }
+      }
+      (Some(l), None) => records.push(RecordDiff::Removed { record: l }),
+      (None, Some(r)) => records.push(RecordDiff::Added { record: r }),
+      (None, None) => {}
+    }
+  }
+  Ok(BranchDiff { left: *left, right: *right, common_ancestor: ancestor, records, unchanged })
+}
@@ -2,7 +2,8 @@
 // Propósito: definir los tipos de dominio principales del crate `flow`.
 //
 // Aquí se definen `FlowData`, `FlowMeta`, `SnapshotMeta`, resultados de
-// persistencia y estructuras auxiliares. Comentarios y nombres en español.
+// persistencia, entradas de auditoría y estructuras auxiliares. Comentarios y
+// nombres en español.
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
@@ -14,7 +15,7 @@ use uuid::Uuid;
 ///
 /// Importante: este crate no ejecuta la lógica de negocio del step — solo
 /// proporciona la estructura para persistir y recuperar los registros.
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct FlowData {
   /// Identificador único del registro de datos.
   pub id: Uuid,
@@ -34,8 +35,32 @@ pub struct FlowData {
   /// Marca temporal de creación.
   pub created_at: DateTime<Utc>,
 }
+/// Proyección de un `FlowData` sin `payload`
+/// (`FlowRepository::query_data_meta`): basta para localizar registros por
+/// key, cursor o metadata sin leer cuerpos que pueden ser grandes.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FlowDataMeta {
+  pub id: Uuid,
+  pub flow_id: Uuid,
+  pub cursor: i64,
+  pub key: String,
+  pub metadata: serde_json::Value,
+  pub command_id: Option<Uuid>,
+  pub created_at: DateTime<Utc>,
+}
+impl From<&FlowData> for FlowDataMeta {
+  fn from(d: &FlowData) -> Self {
+    Self { id: d.id,
+           flow_id: d.flow_id,
+           cursor: d.cursor,
+           key: d.key.clone(),
+           metadata: d.metadata.clone(),
+           command_id: d.command_id,
+           created_at: d.created_at }
+  }
+}
 /// Metadata de snapshot: metadata en Postgres y `state_ptr` apunta a blob
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct SnapshotMeta {
   pub id: Uuid,
   pub flow_id: Uuid,
@@ -45,7 +70,7 @@ pub struct SnapshotMeta {
   pub created_at: DateTime<Utc>,
 }
 /// Metadatos ligeros del agregado `flow` guardados en Postgres.
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct FlowMeta {
   pub id: Uuid,
   pub name: Option<String>,
@@ -59,9 +84,14 @@ pub struct FlowMeta {
   pub parent_flow_id: Option<Uuid>,
   pub parent_cursor: Option<i64>,
   pub metadata: serde_json::Value,
+  /// Momento del borrado lógico (`FlowRepository::delete_branch`); `None`
+  /// mientras el flow está vivo. Solo aparece en
+  /// `FlowRepository::list_deleted_flows`.
+  #[serde(default)]
+  pub deleted_at: Option<DateTime<Utc>>,
 }
 /// Resultado de operaciones de persistencia que requieren control de versiones.
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum PersistResult {
   /// OK con nueva versión.
   Ok {
@@ -69,8 +99,12 @@ pub enum PersistResult {
   },
   Conflict,
 }
+/// Duración por defecto (en segundos) del lease que recibe un worker al
+/// reclamar un `WorkItem`.
+pub const DEFAULT_WORK_LEASE_SECS: i64 = 30;
 /// Item de trabajo que un worker puede reclamar. Contiene referencias para
-/// rehidratación (último cursor y pointer a snapshot si existe).
+/// rehidratación (último cursor y pointer a snapshot si existe) y el estado
+/// del lease concedido al worker.
 #[derive(Debug, Clone)]
 pub struct WorkItem {
   /// Identificador del flow a procesar.
@@ -79,4 +113,30 @@ pub struct WorkItem {
   pub last_cursor: i64,
   /// Snapshot pointer si existe.
   pub snapshot_ptr: Option<String>,
+  /// Worker que posee el lease actual.
+  pub worker_id: String,
+  /// Instante en que expira el lease; después de él otro worker puede
+  /// reclamar el item.
+  pub lease_expires_at: DateTime<Utc>,
+  /// Número de veces que el item ha sido reclamado (incluye este claim).
+  pub attempts: i64,
+}
+/// Entrada del registro de auditoría (`AuditLog`): una llamada que modificó
+/// un repositorio, con el valor afectado antes y después.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AuditEntry {
+  pub id: Uuid,
+  pub at: DateTime<Utc>,
+  /// Usuario o servicio que hizo la llamada; `None` si no se conoce.
+  pub actor: Option<String>,
+  /// Método del repositorio, p. ej. `set_meta` o `save_molecule`.
+  pub operation: String,
+  /// Flow afectado; `None` en las operaciones de dominio y en las globales
+  /// (`gc_snapshots`, `purge_deleted`).
+  pub flow_id: Option<Uuid>,
+  /// Objeto concreto de la operación: key de metadata, cursor, id de
+  /// snapshot, inchikey, id de familia...
+  pub target: Option<String>,
+  pub before: Option<serde_json::Value>,
+  pub after: Option<serde_json::Value>,
 }
@@ -8,6 +8,9 @@ use thiserror::Error;
 /// - `NotFound`: entidad no encontrada.
 /// - `Conflict`: conflicto de concurrencia o versión.
 /// - `Storage`: error al acceder al almacenamiento externo.
+/// - `Integrity`: el contenido leído no coincide con su hash.
+/// - `Payload`: payload incompatible con su esquema registrado.
+/// - `Forbidden`: el principal no tiene permiso para la operación.
 /// - `Other`: cualquier otro error.
 #[derive(Error, Debug)]
 pub enum FlowError {
@@ -20,9 +23,46 @@ pub enum FlowError {
   /// Error genérico de almacenamiento (BD, S3, etc.).
   #[error("Error de almacenamiento: {0}")]
   Storage(String),
+  /// Blob corrupto: su contenido no coincide con la key (hash) con la que se
+  /// guardó (ver `flow::artifact_store`).
+  #[error("Integridad: {0}")]
+  Integrity(String),
+  /// Payload que no se puede decodificar o migrar (ver `flow::payload`).
+  #[error("Payload: {0}")]
+  Payload(#[from] PayloadError),
+  /// Operación rechazada por el `Authorizer` (ver `flow::principal`).
+  #[error("Operación no permitida: {0}")]
+  Forbidden(String),
   /// Otro tipo de error.
   #[error("Otro: {0}")]
   Other(String),
 }
 /// Alias de resultado usado por las APIs del crate.
 pub type Result<T> = std::result::Result<T, FlowError>;
+/// Errores al decodificar el payload de un `FlowData` con un
+/// `PayloadRegistry` (ver `flow::payload`).
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum PayloadError {
+  /// La key no tiene ningún tipo registrado.
+  #[error("Key sin esquema registrado: {0}")]
+  UnknownKey(String),
+  /// El tipo pedido no está registrado para ninguna key.
+  #[error("Tipo de payload sin registrar: {0}")]
+  UnregisteredType(&'static str),
+  /// La key está registrada con otro tipo de payload.
+  #[error("La key {key} corresponde a {registered}, no a {requested}")]
+  TypeMismatch { key: String, registered: &'static str, requested: &'static str },
+  /// El registro se escribió con una versión de esquema posterior a la
+  /// registrada (no se puede migrar hacia atrás).
+  #[error("La key {key} tiene esquema v{found}; se esperaba v{expected}")]
+  VersionMismatch { key: String, found: u32, expected: u32 },
+  /// Falta el upcaster que migra `key` desde la versión `from`.
+  #[error("Sin upcaster para {key} desde v{from}")]
+  MissingUpcaster { key: String, from: u32 },
+  /// El upcaster de `key` desde la versión `from` falló.
+  #[error("Upcaster de {key} v{from} falló: {message}")]
+  Upcast { key: String, from: u32, message: String },
+  /// El payload no se ajusta al tipo registrado para su key y versión.
+  #[error("Payload inválido para {key} v{version}: {message}")]
+  Decode { key: String, version: u32, message: String },
+}
@@ -0,0 +1,144 @@
+// Archivo: json_predicate.rs
+// Propósito: predicados sobre columnas JSON (`metadata`, `payload`, `value`)
+// que los repositorios traducen a SQL.
+//
+// Un `JsonPredicate` compara el valor en una ruta de una columna JSON con un
+// valor JSON: `metadata.workflow_type == "cadma"` o
+// `payload.status != "failed"`. El primer segmento de la ruta es la columna
+// y el resto las claves de objetos anidados. Postgres lo resuelve sobre
+// JSONB (con índice GIN para `==`) y SQLite con `json_extract`;
+// `JsonPredicate::matches` es la semántica de referencia en memoria.
+use crate::errors::{FlowError, Result};
+use serde_json::{Map, Value as JsonValue};
+use std::fmt;
+use std::str::FromStr;
+/// Comparación de un `JsonPredicate`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JsonOp {
+  /// El valor en la ruta existe y es igual.
+  Eq,
+  /// El valor en la ruta no existe o es distinto.
+  Ne,
+}
+/// `<columna>.<clave>[.<clave>...] (==|!=) <valor JSON>`.
+///
+/// Los campos son privados: solo se construye con `eq`, `ne` o `parse`, que
+/// validan los segmentos de la ruta.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct JsonPredicate {
+  column: String,
+  /// Claves desde la raíz de la columna (al menos una).
+  path: Vec<String>,
+  op: JsonOp,
+  value: JsonValue,
+}
+impl JsonPredicate {
+  /// `path == value`, con `path` de la forma `columna.clave...`.
+  pub fn eq(path: &str, value: JsonValue) -> Result<Self> {
+    Self::new(path, JsonOp::Eq, value)
+  }
+  /// `path != value`.
+  pub fn ne(path: &str, value: JsonValue) -> Result<Self> {
+    Self::new(path, JsonOp::Ne, value)
+  }
+  fn new(path: &str, op: JsonOp, value: JsonValue) -> Result<Self> {
+    let mut segments = path.trim().split('.').map(str::to_string);
+    let column = segments.next().unwrap_or_default();
+    let path: Vec<String> = segments.collect();
+    if path.is_empty() {
+      return Err(FlowError::Other(format!("ruta JSON sin clave: '{}'", path_str(&column, &path))));
+    }
+    if let Some(bad) = std::iter::once(&column).chain(&path).find(|s| !is_identifier(s)) {
+      return Err(FlowError::Other(format!("segmento de ruta JSON no válido: '{}'", bad)));
+    }
+    Ok(Self { column, path, op, value })
+  }
+  /// Interpreta `metadata.workflow_type == "cadma"`; el valor se lee como
+  /// JSON (`"texto"`, `3`, `true`, `null`, `{...}`). El operador es el
+  /// primero que aparece: la ruta no puede contener `=` ni `!`, así que los
+  /// que haya después forman parte del valor.
+  pub fn parse(expr: &str) -> Result<Self> {
+    let op_at = [("==", JsonOp::Eq), ("!=", JsonOp::Ne)].into_iter()
+                                                         .filter_map(|(tok, op)| expr.find(tok).map(|i| (i, op)))
+                                                         .min_by_key(|(i, _)| *i);
+    let Some((i, op)) = op_at else {
+      return Err(FlowError::Other(format!("predicado JSON sin '==' ni '!=': '{}'", expr)));
+    };
+    let (path, value) = (&expr[..i], &expr[i + 2..]);
+    let value = serde_json::from_str(value.trim()).map_err(|e| {
+                                                    FlowError::Other(format!("valor JSON no válido en '{}': {}", expr, e))
+                                                  })?;
+    Self::new(path, op, value)
+  }
+  /// Columna JSON sobre la que se evalúa (primer segmento de la ruta).
+  pub fn column(&self) -> &str {
+    &self.column
+  }
+  /// Claves desde la raíz de la columna.
+  pub fn path(&self) -> &[String] {
+    &self.path
+  }
+  pub fn op(&self) -> JsonOp {
+    self.op
+  }
+  pub fn value(&self) -> &JsonValue {
+    &self.value
+  }
+  /// `true` si `root` (el contenido de la columna) cumple el predicado.
+  pub fn matches(&self, root: &JsonValue) -> bool {
+    let found = self.path.iter().try_fold(root, |v, key| v.get(key)) == Some(&self.value);
+    match self.op {
+      JsonOp::Eq => found,
+      JsonOp::Ne => !found,
+    }
+  }
+  /// `Err` si la columna no es una de `columns`.
+  pub fn check_column(&self, columns: &[&str]) -> Result<()> {
+    if columns.contains(&self.column.as_str()) {
+      Ok(())
+    } else {
+      Err(FlowError::Other(format!("columna JSON '{}' no consultable (se admite {})",
+                                   self.column,
+                                   columns.join(", "))))
+    }
+  }
+  /// Documento mínimo que contiene el valor en la ruta (`{"a": {"b": v}}`),
+  /// para el operador de contención `@>` de Postgres.
+  pub fn containment(&self) -> JsonValue {
+    self.path.iter().rev().fold(self.value.clone(), |inner, key| {
+                            JsonValue::Object(Map::from_iter([(key.clone(), inner)]))
+                          })
+  }
+  /// Ruta como literal de array de texto de Postgres (`{"a","b"}`), para
+  /// `#>` con la ruta como parámetro.
+  pub fn pg_path(&self) -> String {
+    format!("{{{}}}", self.path.iter().map(|key| format!("\"{}\"", key)).collect::<Vec<_>>().join(","))
+  }
+  /// Ruta en la sintaxis de `json_extract` de SQLite (`$."a"."b"`).
+  pub fn sqlite_path(&self) -> String {
+    self.path.iter().fold("$".to_string(), |acc, key| format!("{}.\"{}\"", acc, key))
+  }
+}
+impl FromStr for JsonPredicate {
+  type Err = FlowError;
+  fn from_str(s: &str) -> Result<Self> {
+    Self::parse(s)
+  }
+}
+impl fmt::Display for JsonPredicate {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    let op = match self.op {
+      JsonOp::Eq => "==",
+      JsonOp::Ne => "!=",
+    };
+    write!(f, "{} {} {}", path_str(&self.column, &self.path), op, self.value)
+  }
+}
+fn path_str(column: &str, path: &[String]) -> String {
+  std::iter::once(column.to_string()).chain(path.iter().cloned()).collect::<Vec<_>>().join(".")
+}
+/// La columna se escribe en el SQL y las claves forman rutas de `#>` y
+/// `json_extract`, así que solo se admiten letras, dígitos, `_` y `-`.
+fn is_identifier(s: &str) -> bool {
+  !s.is_empty() && s.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+}
@@ -2,7 +2,11 @@
 //!
 //! Este crate define los tipos de dominio (por ejemplo `FlowData`, `FlowMeta`),
 //! el contrato de persistencia `FlowRepository` y una implementación en memoria
-//! útil para pruebas (`InMemoryFlowRepository`). También expone un motor
+//! útil para pruebas (`InMemoryFlowRepository`). Con la feature `testkit`
+//! expone además una batería de conformidad (`flow::testkit`) para validar
+//! cualquier implementación de `FlowRepository`, y con la feature `async` la
+//! variante asíncrona `AsyncFlowRepository` junto a sus adaptadores
+//! (`AsyncFlowAdapter`, `BlockingFlowAdapter`). También expone un motor
 //! auxiliar `FlowEngine` con helpers ergonómicos para crear flujos, añadir
 //! pasos, crear ramas y gestionar snapshots.
 //!
@@ -29,13 +33,26 @@
 //!   `payload` de ese `FlowData` contiene el DTO serializado (por ejemplo
 //!   `Step2Payload`) y `metadata` contiene status/params y referencias a
 //!   objetos de dominio. Esta convención permite que otros pasos o herramientas
-//!   busquen rápidamente el último resultado de un paso concreto.
+//!   busquen rápidamente el último resultado de un paso concreto. La key se
+//!   interpreta con `FlowDataKey` y un `PayloadRegistry` asocia cada key a su
+//!   tipo de payload y versión de esquema (`metadata.schema_version`), de modo
+//!   que los registros se decodifican de forma determinista.
 //!
 //! - Snapshots: para acelerar la reconstrucción del estado completo del motor,
 //!   se pueden guardar snapshots (blob) que contienen una representación
-//!   serializada del estado (por ejemplo `CadmaState`). El repositorio expone
-//!   `load_latest_snapshot` y `load_snapshot` para recuperar el snapshot más
-//!   reciente y su contenido.
+//!   serializada del estado (por ejemplo `CadmaState`). El blob se guarda en
+//!   un `SnapshotStore` (memoria, ficheros, tabla de blobs, S3) mediante
+//!   `save_snapshot_state`, y el snapshot solo registra su key en
+//!   `state_ptr`. El repositorio expone `load_latest_snapshot` y
+//!   `load_snapshot` para recuperar el snapshot más reciente y su contenido.
+//!
+//! - Ramas: `create_branch` bifurca un flow en un cursor sin copiar sus
+//!   registros (copy-on-write: `read_data` los lee de los antepasados);
+//!   `FlowRepository::list_children` y el módulo `lineage` (antepasados, árbol
+//!   de ramas con sus cursores de bifurcación, antepasado común) permiten
+//!   recorrer el grafo de linaje. `diff::diff_branches` compara dos ramas
+//!   registro a registro desde su antepasado común, y
+//!   `cherry_pick::cherry_pick` copia registros concretos de una rama a otra.
 //!
 //! - Rehidratación (pasos prácticos): un proceso de rehidratación habitual
 //!   consta de:
@@ -47,11 +64,67 @@
 //!   3) Leer los `FlowData` relevantes con `FlowRepository::read_data(flow_id,
 //!      from_cursor)` y aplicar (replay) esos eventos sobre el estado
 //!      reconstruido si el snapshot no estaba completo hasta el cursor deseado.
+//!
+//!   `time_travel::state_at` automatiza estos pasos para cualquier cursor
+//!   pasado: parte del snapshot más cercano anterior o igual
+//!   (`load_snapshot_at`) y reproduce los registros posteriores con el
+//!   `StateReducer` del engine.
+//!
+//! - Auditoría: `audit::AuditedFlowRepository` envuelve cualquier
+//!   `FlowRepository` y anota en un `AuditLog` cada llamada que modifica algo
+//!   (actor, operación, flow, valor antes/después e instante);
+//!   `AuditLog::query` filtra las entradas con un `AuditQuery`.
+//!
+//! - Principal: `principal::PrincipalFlowRepository` ejecuta las llamadas en
+//!   nombre de un `Principal` (usuario, roles, tenant): un `Authorizer` puede
+//!   rechazar cada operación (`FlowError::Forbidden`), los flows se crean con
+//!   `created_by` y cada `FlowData` lleva el principal en su metadata.
+//!
+//! - Consultas: `FlowRepository::query_flows` lista flows filtrados (status,
+//!   `workflow_type`, prefijo de nombre, autor, fechas, padre) y ordenados,
+//!   en páginas con cursor (`query::FlowQuery`, `FlowPage::next`).
+//!   `query_data` lee solo parte del historial de un flow (rango de cursores,
+//!   key o prefijo de key, `FlowDataQuery`), `query_data_meta` lo mismo sin
+//!   payloads y `latest_by_key` el último registro de una key. Ambas
+//!   consultas aceptan además predicados sobre columnas JSON
+//!   (`json_predicate::JsonPredicate`, p. ej. `metadata.workflow_type ==
+//!   "cadma"`), que los backends SQL resuelven en la base.
+pub mod artifact_store;
+#[cfg(feature = "async")]
+pub mod async_repository;
+pub mod audit;
+pub mod cherry_pick;
+pub mod diff;
 pub mod domain;
 pub mod engine;
 pub mod errors;
+pub mod json_predicate;
+pub mod lineage;
+pub mod payload;
+pub mod principal;
+pub mod query;
 pub mod repository;
+pub mod retention;
+pub mod snapshot_store;
 pub mod stubs;
+pub mod subscription;
+#[cfg(feature = "testkit")]
+pub mod testkit;
+pub mod time_travel;
+#[cfg(feature = "async")]
+pub use async_repository::*;
+pub use audit::{AuditQuery, AuditedFlowRepository, InMemoryAuditLog};
+pub use cherry_pick::{cherry_pick, CherryPickError, CherryPickOptions, CherryPickReport, KeyConflictPolicy};
+pub use diff::{diff_branches, BranchDiff};
 pub use errors::*;
+pub use json_predicate::{JsonOp, JsonPredicate};
+pub use lineage::{CommonAncestor, LineageNode};
+pub use payload::{FlowDataKey, PayloadRegistry};
+pub use principal::{AllowAll, Authorizer, Principal, PrincipalFlowRepository, RoleAuthorizer};
+pub use query::{FlowCursor, FlowDataQuery, FlowPage, FlowQuery, FlowSort};
 pub use repository::*;
+pub use retention::{SnapshotGcReport, SnapshotRetention};
+pub use snapshot_store::{FsSnapshotStore, InMemorySnapshotStore, StoredBlob};
 pub use stubs::*;
+pub use subscription::{ChangeFeed, FlowSubscription};
+pub use time_travel::{state_at, StateAt, StateReducer};
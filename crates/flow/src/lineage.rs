@@ -0,0 +1,202 @@
+// Archivo: lineage.rs
+// Propósito: grafo de linaje de las ramas de un flow, construido sobre
+// `FlowMeta::parent_flow_id`/`parent_cursor` y `FlowRepository::list_children`.
+//
+// - `ancestors` / `root_of`: cadena de padres de un flow hasta la raíz.
+// - `lineage_tree` / `LineageNode`: subárbol de ramas con sus cursores de
+//   bifurcación, y su representación en texto (`LineageNode::render`).
+// - `common_ancestor`: flow más cercano del que descienden dos ramas y último
+//   cursor que comparten, p. ej. para comparar dos ejecuciones de CADMA que
+//   partieron de la misma selección de familia.
+// - `data_segments`: tramos de `flow_data` de cada antepasado que ve una rama
+//   copy-on-write; lo usan los backends para componer `read_data`.
+//
+// Funciona con cualquier backend; cada paso es una llamada al repositorio,
+// así que está pensado para árboles de tamaño moderado.
+use crate::domain::FlowMeta;
+use crate::errors::{FlowError, Result};
+use crate::repository::FlowRepository;
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+use std::fmt::{self, Write as _};
+use uuid::Uuid;
+/// Nodo del árbol de ramas: un flow y sus ramas hijas.
+#[derive(Debug, Clone)]
+pub struct LineageNode {
+  pub flow: FlowMeta,
+  /// Hijas directas, en el orden de `FlowRepository::list_children`.
+  pub children: Vec<LineageNode>,
+}
+impl LineageNode {
+  /// Busca `flow_id` en el subárbol.
+  pub fn find(&self, flow_id: &Uuid) -> Option<&LineageNode> {
+    if self.flow.id == *flow_id {
+      return Some(self);
+    }
+    self.children.iter().find_map(|c| c.find(flow_id))
+  }
+  /// Ids del subárbol en preorden (este flow primero).
+  pub fn flow_ids(&self) -> Vec<Uuid> {
+    let mut ids = vec![self.flow.id];
+    ids.extend(self.children.iter().flat_map(LineageNode::flow_ids));
+    ids
+  }
+  /// Árbol en texto, una rama por línea con su cursor actual y el cursor
+  /// del padre desde el que se bifurcó:
+  ///
+  /// ```text
+  /// 6f1c… "cadma" (cursor 3)
+  /// ├── 0b2e… (cursor 5, desde 2)
+  /// │   └── 9a40… (cursor 4, desde 4)
+  /// └── c7d3… (cursor 3, desde 3)
+  /// ```
+  pub fn render(&self) -> String {
+    let mut out = String::new();
+    self.render_into(&mut out, "", None);
+    out
+  }
+  fn render_into(&self, out: &mut String, prefix: &str, branch: Option<bool>) {
+    let (connector, child_prefix) = match branch {
+      None => ("", String::new()),
+      Some(true) => ("└── ", format!("{}    ", prefix)),
+      Some(false) => ("├── ", format!("{}│   ", prefix)),
+    };
+    let _ = writeln!(out, "{}{}{}", prefix, connector, NodeLabel(&self.flow));
+    for (i, child) in self.children.iter().enumerate() {
+      child.render_into(out, &child_prefix, Some(i + 1 == self.children.len()));
+    }
+  }
+}
+impl fmt::Display for LineageNode {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.write_str(&self.render())
+  }
+}
+/// Etiqueta de un flow en `LineageNode::render`.
+struct NodeLabel<'a>(&'a FlowMeta);
+impl fmt::Display for NodeLabel<'_> {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    let meta = self.0;
+    write!(f, "{}", meta.id)?;
+    if let Some(name) = &meta.name {
+      write!(f, " {:?}", name)?;
+    }
+    match meta.parent_cursor {
+      Some(from) if meta.parent_flow_id.is_some() => write!(f, " (cursor {}, desde {})", meta.current_cursor, from),
+      _ => write!(f, " (cursor {})", meta.current_cursor),
+    }
+  }
+}
+/// Antepasado común de dos ramas (ver `common_ancestor`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct CommonAncestor {
+  /// Flow más cercano del que descienden (o que es) ambas ramas.
+  pub flow_id: Uuid,
+  /// Último cursor de `flow_id` que comparten ambas ramas; sus registros
+  /// con `cursor <= cursor` proceden del mismo historial.
+  pub cursor: i64,
+}
+/// Tramo de `flow_data` físico que ve una rama: los registros guardados con
+/// `flow_id` y `after < cursor <= up_to` (`None` = sin límite).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DataSegment {
+  pub flow_id: Uuid,
+  pub after: Option<i64>,
+  pub up_to: Option<i64>,
+}
+impl DataSegment {
+  pub fn contains(&self, cursor: i64) -> bool {
+    self.after.is_none_or(|a| cursor > a) && self.up_to.is_none_or(|u| cursor <= u)
+  }
+}
+/// Tramos que componen el historial de `flow`, dada su cadena de antepasados
+/// (padre primero, como devuelve `ancestors`), empezando por el propio flow.
+///
+/// Una rama solo guarda los registros posteriores a su `parent_cursor`; los
+/// anteriores se leen del padre, que a su vez ve los de su padre hasta su
+/// propio `parent_cursor`, y así hasta la raíz. Cada antepasado aporta sus
+/// registros hasta el menor `parent_cursor` del camino que baja hasta `flow`.
+/// Los registros que una rama guardara en su propio tramo heredado (copias
+/// físicas de versiones anteriores) quedan fuera.
+pub fn data_segments(flow: &FlowMeta, ancestors: &[FlowMeta]) -> Vec<DataSegment> {
+  let fork = |meta: &FlowMeta| meta.parent_flow_id.and(meta.parent_cursor);
+  let mut segments = vec![DataSegment { flow_id: flow.id, after: fork(flow), up_to: None }];
+  let mut up_to: Option<i64> = None;
+  let mut child = flow;
+  for parent in ancestors {
+    if let Some(pc) = child.parent_cursor {
+      up_to = Some(up_to.map_or(pc, |u| u.min(pc)));
+    }
+    segments.push(DataSegment { flow_id: parent.id, after: fork(parent), up_to });
+    child = parent;
+  }
+  segments
+}
+/// Cadena de antepasados vivos de `flow_id`: su padre primero y la raíz al
+/// final (vacía si `flow_id` es raíz). La cadena se corta en el primer
+/// antepasado borrado con `delete_branch`, aunque `flow_id` siga leyendo sus
+/// registros. `Err(FlowError::NotFound)` si el flow no existe; un ciclo en
+/// `parent_flow_id` devuelve `FlowError::Other`.
+pub fn ancestors(repo: &dyn FlowRepository, flow_id: &Uuid) -> Result<Vec<FlowMeta>> {
+  let mut chain = Vec::new();
+  let mut seen = HashSet::from([*flow_id]);
+  let mut current = repo.get_flow_meta(flow_id)?;
+  while let Some(parent_id) = current.parent_flow_id {
+    if !seen.insert(parent_id) {
+      return Err(FlowError::Other(format!("ciclo en el linaje de {}", flow_id)));
+    }
+    current = match repo.get_flow_meta(&parent_id) {
+      Ok(parent) => parent,
+      Err(FlowError::NotFound(_)) => break,
+      Err(e) => return Err(e),
+    };
+    chain.push(current.clone());
+  }
+  Ok(chain)
+}
+/// Raíz del árbol de ramas al que pertenece `flow_id` (él mismo si no tiene
+/// padre).
+pub fn root_of(repo: &dyn FlowRepository, flow_id: &Uuid) -> Result<FlowMeta> {
+  match ancestors(repo, flow_id)?.pop() {
+    Some(root) => Ok(root),
+    None => repo.get_flow_meta(flow_id),
+  }
+}
+/// Subárbol de ramas que cuelga de `flow_id` (incluido). Para el árbol
+/// completo de una rama cualquiera: `lineage_tree(repo, &root_of(repo,
+/// id)?.id)`.
+pub fn lineage_tree(repo: &dyn FlowRepository, flow_id: &Uuid) -> Result<LineageNode> {
+  let mut seen = HashSet::new();
+  build_tree(repo, repo.get_flow_meta(flow_id)?, &mut seen)
+}
+fn build_tree(repo: &dyn FlowRepository, flow: FlowMeta, seen: &mut HashSet<Uuid>) -> Result<LineageNode> {
+  if !seen.insert(flow.id) {
+    return Err(FlowError::Other(format!("ciclo en el linaje de {}", flow.id)));
+  }
+  let children =
+    repo.list_children(&flow.id)?.into_iter().map(|child| build_tree(repo, child, seen)).collect::<Result<Vec<_>>>()?;
+  Ok(LineageNode { flow, children })
+}
+/// Antepasado común más cercano de `a` y `b` (uno de ellos si desciende del
+/// otro), o `None` si no están en el mismo árbol de ramas vivas (p. ej. si
+/// el camino de una de ellas pasa por una rama borrada).
+pub fn common_ancestor(repo: &dyn FlowRepository, a: &Uuid, b: &Uuid) -> Result<Option<CommonAncestor>> {
+  let from_b: HashMap<Uuid, i64> = fork_points(repo, b)?.into_iter().collect();
+  for (flow_id, cursor_a) in fork_points(repo, a)? {
+    if let Some(cursor_b) = from_b.get(&flow_id) {
+      return Ok(Some(CommonAncestor { flow_id, cursor: cursor_a.min(*cursor_b) }));
+    }
+  }
+  Ok(None)
+}
+/// `flow_id` y sus antepasados, cada uno con el último de sus cursores que
+/// ve `flow_id`: el `current_cursor` propio y, para cada antepasado, el
+/// límite de su tramo en `data_segments`.
+fn fork_points(repo: &dyn FlowRepository, flow_id: &Uuid) -> Result<Vec<(Uuid, i64)>> {
+  let flow = repo.get_flow_meta(flow_id)?;
+  let chain = ancestors(repo, flow_id)?;
+  Ok(data_segments(&flow, &chain).into_iter()
+                                 .zip(std::iter::once(&flow).chain(&chain))
+                                 .map(|(segment, meta)| (segment.flow_id, segment.up_to.unwrap_or(meta.current_cursor)))
+                                 .collect())
+}
@@ -0,0 +1,265 @@
+// Archivo: payload.rs
+// Propósito: keys tipadas de `FlowData` y registro de esquemas de payload.
+//
+// - `FlowDataKey`: interpreta `FlowData::key` (p. ej. `step_state:{step}`) sin
+//   cambiar su representación en almacenamiento.
+// - `PayloadRegistry`: asocia cada key a un tipo de payload y a la versión de
+//   esquema vigente, de modo que un `FlowData` se decodifica por key+versión y
+//   no probando tipos hasta que uno encaje. Los desajustes se devuelven como
+//   `PayloadError`.
+// - Upcasters: funciones que pasan el JSON de la versión `n` a la `n + 1`;
+//   `decode` aplica la cadena a los registros antiguos y
+//   `upcast_stored_payloads` reescribe los ya guardados.
+//
+// La versión de esquema de un registro va en `metadata.schema_version`; los
+// registros anteriores al registro de esquemas no la tienen y cuentan como
+// versión 1.
+use crate::domain::FlowData;
+use crate::errors::{PayloadError, Result as FlowResult};
+use crate::repository::FlowRepository;
+use serde::de::DeserializeOwned;
+use serde_json::Value as JsonValue;
+use std::any::{type_name, TypeId};
+use std::collections::HashMap;
+use std::fmt;
+/// Campo de `FlowData::metadata` con la versión de esquema del payload.
+pub const SCHEMA_VERSION_METADATA_KEY: &str = "schema_version";
+/// Versión de los registros que no declaran `schema_version`.
+pub const DEFAULT_SCHEMA_VERSION: u32 = 1;
+/// Key bajo la que los engines registran los upcasters de su estado
+/// serializado en snapshots.
+pub const ENGINE_STATE_KEY: &str = "engine_state";
+/// Transforma el JSON de una versión de esquema a la siguiente.
+pub type Upcaster = fn(JsonValue) -> Result<JsonValue, String>;
+/// Tipo con esquema versionado: la versión vigente y los upcasters desde
+/// las anteriores (`(n, f)` pasa de la versión `n` a la `n + 1`).
+pub trait SchemaVersioned {
+  const SCHEMA_VERSION: u32 = DEFAULT_SCHEMA_VERSION;
+  fn upcasters() -> Vec<(u32, Upcaster)> {
+    Vec::new()
+  }
+}
+/// Prefijo de las keys con el resultado de un paso.
+const STEP_STATE_PREFIX: &str = "step_state:";
+/// Key tipada de un `FlowData`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum FlowDataKey {
+  /// Resultado de un paso: `step_state:{step_name}`.
+  StepState(String),
+  /// Cualquier otra key, tal cual.
+  Other(String),
+}
+impl FlowDataKey {
+  /// Key del resultado del paso `step_name`.
+  pub fn step_state(step_name: impl Into<String>) -> Self {
+    FlowDataKey::StepState(step_name.into())
+  }
+  /// Interpreta una key almacenada.
+  pub fn parse(key: &str) -> Self {
+    match key.strip_prefix(STEP_STATE_PREFIX) {
+      Some(step) => FlowDataKey::StepState(step.to_string()),
+      None => FlowDataKey::Other(key.to_string()),
+    }
+  }
+  /// Nombre del paso si es una key `step_state:`.
+  pub fn step_name(&self) -> Option<&str> {
+    match self {
+      FlowDataKey::StepState(step) => Some(step),
+      FlowDataKey::Other(_) => None,
+    }
+  }
+}
+impl fmt::Display for FlowDataKey {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      FlowDataKey::StepState(step) => write!(f, "{}{}", STEP_STATE_PREFIX, step),
+      FlowDataKey::Other(key) => f.write_str(key),
+    }
+  }
+}
+impl From<&str> for FlowDataKey {
+  fn from(key: &str) -> Self {
+    FlowDataKey::parse(key)
+  }
+}
+impl FlowData {
+  /// Key de este registro interpretada como `FlowDataKey`.
+  pub fn typed_key(&self) -> FlowDataKey {
+    FlowDataKey::parse(&self.key)
+  }
+  /// Versión de esquema del payload (`metadata.schema_version`, o
+  /// `DEFAULT_SCHEMA_VERSION` si no la declara).
+  pub fn schema_version(&self) -> u32 {
+    schema_version_of(&self.metadata)
+  }
+}
+/// Versión de esquema declarada en `metadata` (de un `FlowData` o de un
+/// snapshot), o `DEFAULT_SCHEMA_VERSION` si no la declara.
+pub fn schema_version_of(metadata: &JsonValue) -> u32 {
+  metadata.get(SCHEMA_VERSION_METADATA_KEY).and_then(|v| v.as_u64()).map(|v| v as u32).unwrap_or(DEFAULT_SCHEMA_VERSION)
+}
+/// Devuelve `metadata` con `schema_version` fijado a `version`. Si
+/// `metadata` no es un objeto (p. ej. `null`) se sustituye por uno.
+pub fn with_schema_version(metadata: JsonValue, version: u32) -> JsonValue {
+  let mut map = match metadata {
+    JsonValue::Object(map) => map,
+    _ => serde_json::Map::new(),
+  };
+  map.insert(SCHEMA_VERSION_METADATA_KEY.to_string(), JsonValue::from(version));
+  JsonValue::Object(map)
+}
+/// Esquema registrado para una key.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PayloadSchema {
+  type_id: TypeId,
+  /// Nombre del tipo de payload (para mensajes de error).
+  pub type_name: &'static str,
+  /// Versión de esquema vigente.
+  pub version: u32,
+}
+/// Resultado de `upcast_stored_payloads`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PayloadMigrationReport {
+  /// Flows recorridos.
+  pub flows_scanned: usize,
+  /// Registros de `flow_data` reescritos a la versión vigente.
+  pub rows_rewritten: usize,
+}
+/// Registro `FlowDataKey` → tipo de payload + versión de esquema, con los
+/// upcasters de cada key.
+#[derive(Debug, Clone, Default)]
+pub struct PayloadRegistry {
+  schemas: HashMap<FlowDataKey, PayloadSchema>,
+  upcasters: HashMap<(FlowDataKey, u32), Upcaster>,
+}
+impl PayloadRegistry {
+  pub fn new() -> Self {
+    Self::default()
+  }
+  /// Registra `T` como payload de `key` en la versión `version`. Volver a
+  /// registrar el mismo tipo actualiza la versión; registrar otro tipo para
+  /// una key ya usada devuelve `PayloadError::TypeMismatch`.
+  pub fn register<T: DeserializeOwned + 'static>(&mut self, key: FlowDataKey, version: u32) -> Result<(), PayloadError> {
+    if let Some(existing) = self.schemas.get(&key) {
+      if existing.type_id != TypeId::of::<T>() {
+        return Err(PayloadError::TypeMismatch { key: key.to_string(),
+                                                registered: existing.type_name,
+                                                requested: type_name::<T>() });
+      }
+    }
+    self.schemas.insert(key,
+                        PayloadSchema { type_id: TypeId::of::<T>(), type_name: type_name::<T>(), version });
+    Ok(())
+  }
+  /// Registra `T` como payload de `key` con su versión y sus upcasters
+  /// (`SchemaVersioned`).
+  pub fn register_versioned<T>(&mut self, key: FlowDataKey) -> Result<(), PayloadError>
+    where T: SchemaVersioned + DeserializeOwned + 'static
+  {
+    self.register::<T>(key.clone(), T::SCHEMA_VERSION)?;
+    for (from_version, upcaster) in T::upcasters() {
+      self.register_upcaster(key.clone(), from_version, upcaster);
+    }
+    Ok(())
+  }
+  /// Registra la función que pasa el JSON de `key` de la versión
+  /// `from_version` a la siguiente.
+  pub fn register_upcaster(&mut self, key: FlowDataKey, from_version: u32, upcaster: Upcaster) {
+    self.upcasters.insert((key, from_version), upcaster);
+  }
+  /// Lleva `value` (JSON de `key` en la versión `from`) a la versión `to`
+  /// aplicando los upcasters en orden. Una versión posterior a `to` da
+  /// `PayloadError::VersionMismatch`, y un eslabón sin upcaster
+  /// `PayloadError::MissingUpcaster`.
+  pub fn upcast(&self, key: &FlowDataKey, mut value: JsonValue, from: u32, to: u32) -> Result<JsonValue, PayloadError> {
+    if from > to {
+      return Err(PayloadError::VersionMismatch { key: key.to_string(), found: from, expected: to });
+    }
+    for version in from..to {
+      let upcaster = self.upcasters
+                         .get(&(key.clone(), version))
+                         .ok_or_else(|| PayloadError::MissingUpcaster { key: key.to_string(), from: version })?;
+      value = upcaster(value).map_err(|message| PayloadError::Upcast { key: key.to_string(), from: version, message })?;
+    }
+    Ok(value)
+  }
+  /// Si `data` tiene una key registrada y una versión anterior a la vigente,
+  /// devuelve una copia con el payload migrado y `schema_version`
+  /// actualizado; `Ok(None)` si no hay nada que migrar.
+  pub fn upcast_data(&self, data: &FlowData) -> Result<Option<FlowData>, PayloadError> {
+    let key = data.typed_key();
+    let Some(schema) = self.schemas.get(&key) else {
+      return Ok(None);
+    };
+    let found = data.schema_version();
+    if found == schema.version {
+      return Ok(None);
+    }
+    let payload = self.upcast(&key, data.payload.clone(), found, schema.version)?;
+    Ok(Some(FlowData { payload,
+                       metadata: with_schema_version(data.metadata.clone(), schema.version),
+                       ..data.clone() }))
+  }
+  /// Esquema registrado para `key`.
+  pub fn schema(&self, key: &FlowDataKey) -> Option<&PayloadSchema> {
+    self.schemas.get(key)
+  }
+  /// Versión vigente de `key`, si está registrada.
+  pub fn version_of(&self, key: &FlowDataKey) -> Option<u32> {
+    self.schemas.get(key).map(|s| s.version)
+  }
+  /// Keys registradas con el tipo `T`.
+  pub fn keys_for<T: 'static>(&self) -> Vec<&FlowDataKey> {
+    let mut keys: Vec<&FlowDataKey> =
+      self.schemas.iter().filter(|(_, s)| s.type_id == TypeId::of::<T>()).map(|(k, _)| k).collect();
+    keys.sort();
+    keys
+  }
+  /// Decodifica el payload de `data` como `T`, comprobando que su key está
+  /// registrada con ese tipo. Los registros de versiones anteriores se
+  /// migran antes con `upcast`.
+  pub fn decode<T: DeserializeOwned + 'static>(&self, data: &FlowData) -> Result<T, PayloadError> {
+    let key = data.typed_key();
+    let schema = self.schemas.get(&key).ok_or_else(|| PayloadError::UnknownKey(data.key.clone()))?;
+    if schema.type_id != TypeId::of::<T>() {
+      return Err(PayloadError::TypeMismatch { key: data.key.clone(),
+                                              registered: schema.type_name,
+                                              requested: type_name::<T>() });
+    }
+    let payload = self.upcast(&key, data.payload.clone(), data.schema_version(), schema.version)?;
+    serde_json::from_value(payload).map_err(|e| PayloadError::Decode { key: data.key.clone(),
+                                                                       version: schema.version,
+                                                                       message: e.to_string() })
+  }
+  /// Decodifica el registro más reciente (mayor cursor) de `data` cuya key
+  /// está registrada con el tipo `T`. `Ok(None)` si no hay ninguno y
+  /// `PayloadError::UnregisteredType` si `T` no está registrado.
+  pub fn latest<T: DeserializeOwned + 'static>(&self, data: &[FlowData]) -> Result<Option<T>, PayloadError> {
+    let keys = self.keys_for::<T>();
+    if keys.is_empty() {
+      return Err(PayloadError::UnregisteredType(type_name::<T>()));
+    }
+    data.iter().filter(|d| keys.contains(&&d.typed_key())).max_by_key(|d| d.cursor).map(|d| self.decode(d)).transpose()
+  }
+}
+/// Reescribe en el repositorio los registros de `flow_data` cuya key está en
+/// `registry` con una versión anterior a la vigente, aplicando sus
+/// upcasters (`FlowRepository::rewrite_data`, un lote por flow). Es
+/// idempotente; un registro que no se pueda migrar aborta con
+/// `FlowError::Payload` sin tocar el resto de su flow.
+pub fn upcast_stored_payloads(repo: &dyn FlowRepository,
+                              registry: &PayloadRegistry)
+                              -> FlowResult<PayloadMigrationReport> {
+  let mut report = PayloadMigrationReport::default();
+  for flow_id in repo.list_flow_ids()? {
+    let rewritten = repo.read_data(&flow_id, 0)?
+                        .iter()
+                        .filter_map(|d| registry.upcast_data(d).transpose())
+                        .collect::<Result<Vec<FlowData>, PayloadError>>()?;
+    if !rewritten.is_empty() {
+      report.rows_rewritten += repo.rewrite_data(&rewritten)?;
+    }
+    report.flows_scanned += 1;
+  }
+  Ok(report)
+}
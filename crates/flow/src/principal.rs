@@ -0,0 +1,317 @@
+// Archivo: principal.rs
+// Propósito: identidad de quien ejecuta las operaciones (usuario o servicio,
+// sus roles y su tenant) y el punto de autorización por operación.
+//
+// - `Principal`: usuario, roles y tenant.
+// - `Authorizer`: decide si un principal puede ejecutar una operación
+//   (`AllowAll`, `RoleAuthorizer` o cualquier closure con la misma firma).
+// - `PrincipalFlowRepository`: decorador de cualquier `FlowRepository` que
+//   consulta el `Authorizer` antes de cada llamada, crea los flows con
+//   `created_by` y añade el principal a la metadata de cada `FlowData`.
+//
+// El decorador equivalente para `DomainRepository` está en `chem-persistence`
+// y `chem-workflow` lo lleva en el `StepContext` de cada paso.
+use crate::domain::{FlowData, FlowDataMeta, FlowMeta, PersistResult, SnapshotMeta, WorkItem};
+use crate::errors::{FlowError, Result};
+use crate::query::{FlowDataQuery, FlowPage, FlowQuery};
+use crate::repository::{FlowLockGuard, FlowRepository};
+use crate::retention::{PurgeReport, SnapshotGcReport};
+use crate::subscription::FlowSubscription;
+use serde::{Deserialize, Serialize};
+use serde_json::Value as JsonValue;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+use uuid::Uuid;
+/// Key de `FlowData::metadata` con el principal que escribió el registro.
+pub const PRINCIPAL_METADATA_KEY: &str = "principal";
+/// Usuario o servicio que ejecuta una operación.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Principal {
+  pub user_id: String,
+  #[serde(default)]
+  pub roles: Vec<String>,
+  #[serde(default)]
+  pub tenant: Option<String>,
+}
+impl Principal {
+  pub fn new(user_id: impl Into<String>) -> Self {
+    Self { user_id: user_id.into(), roles: Vec::new(), tenant: None }
+  }
+  pub fn with_role(mut self, role: impl Into<String>) -> Self {
+    self.roles.push(role.into());
+    self
+  }
+  pub fn with_tenant(mut self, tenant: impl Into<String>) -> Self {
+    self.tenant = Some(tenant.into());
+    self
+  }
+  pub fn has_role(&self, role: &str) -> bool {
+    self.roles.iter().any(|r| r == role)
+  }
+}
+/// Principal guardado en `metadata` (ver `with_principal`), si lo hay.
+pub fn principal_of(metadata: &JsonValue) -> Option<Principal> {
+  metadata.get(PRINCIPAL_METADATA_KEY).and_then(|v| serde_json::from_value(v.clone()).ok())
+}
+/// Devuelve `metadata` con el principal en `PRINCIPAL_METADATA_KEY`. Si
+/// `metadata` no es un objeto (p. ej. `null`) se sustituye por uno.
+pub fn with_principal(metadata: JsonValue, principal: &Principal) -> JsonValue {
+  let mut map = match metadata {
+    JsonValue::Object(map) => map,
+    _ => serde_json::Map::new(),
+  };
+  map.insert(PRINCIPAL_METADATA_KEY.to_string(),
+             serde_json::to_value(principal).unwrap_or(JsonValue::Null));
+  JsonValue::Object(map)
+}
+/// Decide si `principal` puede ejecutar `operation` (el nombre del método
+/// del repositorio, p. ej. `"persist_data"` o `"save_molecule"`) sobre
+/// `flow_id`, cuando la operación es sobre un flow concreto. Para rechazar
+/// devuelve `FlowError::Forbidden`.
+pub trait Authorizer: Send + Sync {
+  fn authorize(&self, principal: &Principal, operation: &str, flow_id: Option<&Uuid>) -> Result<()>;
+}
+impl<F> Authorizer for F where F: Fn(&Principal, &str, Option<&Uuid>) -> Result<()> + Send + Sync
+{
+  fn authorize(&self, principal: &Principal, operation: &str, flow_id: Option<&Uuid>) -> Result<()> {
+    self(principal, operation, flow_id)
+  }
+}
+/// `Authorizer` que permite todo.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct AllowAll;
+impl Authorizer for AllowAll {
+  fn authorize(&self, _principal: &Principal, _operation: &str, _flow_id: Option<&Uuid>) -> Result<()> {
+    Ok(())
+  }
+}
+/// `Authorizer` por roles: cada operación configurada con `require` exige
+/// que el principal tenga al menos uno de sus roles; las demás se permiten.
+#[derive(Debug, Clone, Default)]
+pub struct RoleAuthorizer {
+  required: HashMap<String, Vec<String>>,
+}
+impl RoleAuthorizer {
+  pub fn new() -> Self {
+    Self::default()
+  }
+  /// Exige uno de `roles` para `operation` (se acumulan entre llamadas).
+  pub fn require<I, S>(mut self, operation: impl Into<String>, roles: I) -> Self
+    where I: IntoIterator<Item = S>,
+          S: Into<String>
+  {
+    self.required.entry(operation.into()).or_default().extend(roles.into_iter().map(Into::into));
+    self
+  }
+}
+impl Authorizer for RoleAuthorizer {
+  fn authorize(&self, principal: &Principal, operation: &str, _flow_id: Option<&Uuid>) -> Result<()> {
+    match self.required.get(operation) {
+      Some(roles) if !roles.iter().any(|r| principal.has_role(r)) => {
+        Err(FlowError::Forbidden(format!("{} requiere uno de los roles {:?} ({} tiene {:?})",
+                                         operation, roles, principal.user_id, principal.roles)))
+      }
+      _ => Ok(()),
+    }
+  }
+}
+/// `FlowRepository` que ejecuta las llamadas de `inner` en nombre de
+/// `principal`.
+///
+/// Cada llamada (lecturas incluidas) pasa antes por `authorizer` con el
+/// nombre del método; si la rechaza no se llega a `inner`. `create_flow`
+/// guarda `created_by` con `principal.user_id` y `persist_data` añade el
+/// principal a la metadata del registro (`principal_of` lo recupera).
+pub struct PrincipalFlowRepository {
+  inner: Arc<dyn FlowRepository>,
+  principal: Principal,
+  authorizer: Arc<dyn Authorizer>,
+}
+impl PrincipalFlowRepository {
+  pub fn new(inner: Arc<dyn FlowRepository>, principal: Principal, authorizer: Arc<dyn Authorizer>) -> Self {
+    Self { inner, principal, authorizer }
+  }
+  pub fn principal(&self) -> &Principal {
+    &self.principal
+  }
+  fn check(&self, operation: &str, flow_id: Option<&Uuid>) -> Result<()> {
+    self.authorizer.authorize(&self.principal, operation, flow_id)
+  }
+}
+impl FlowRepository for PrincipalFlowRepository {
+  fn rewrap(&self, repo: Arc<dyn FlowRepository>) -> Arc<dyn FlowRepository> {
+    Arc::new(PrincipalFlowRepository::new(self.inner.rewrap(repo), self.principal.clone(), self.authorizer.clone()))
+  }
+  fn get_flow_meta(&self, flow_id: &Uuid) -> Result<FlowMeta> {
+    self.check("get_flow_meta", Some(flow_id))?;
+    self.inner.get_flow_meta(flow_id)
+  }
+  fn create_flow(&self, name: Option<String>, status: Option<String>, metadata: JsonValue) -> Result<Uuid> {
+    self.create_flow_as(name, status, metadata, Some(self.principal.user_id.clone()))
+  }
+  /// Un `created_by` explícito prevalece sobre el del principal.
+  fn create_flow_as(&self,
+                    name: Option<String>,
+                    status: Option<String>,
+                    metadata: JsonValue,
+                    created_by: Option<String>)
+                    -> Result<Uuid> {
+    self.check("create_flow", None)?;
+    let created_by = created_by.or_else(|| Some(self.principal.user_id.clone()));
+    self.inner.create_flow_as(name, status, metadata, created_by)
+  }
+  fn persist_data(&self, data: &FlowData, expected_version: i64) -> Result<PersistResult> {
+    self.check("persist_data", Some(&data.flow_id))?;
+    let data = FlowData { metadata: with_principal(data.metadata.clone(), &self.principal), ..data.clone() };
+    self.inner.persist_data(&data, expected_version)
+  }
+  fn read_data(&self, flow_id: &Uuid, from_cursor: i64) -> Result<Vec<FlowData>> {
+    self.check("read_data", Some(flow_id))?;
+    self.inner.read_data(flow_id, from_cursor)
+  }
+  fn query_data(&self, flow_id: &Uuid, query: &FlowDataQuery) -> Result<Vec<FlowData>> {
+    self.check("query_data", Some(flow_id))?;
+    self.inner.query_data(flow_id, query)
+  }
+  fn query_data_meta(&self, flow_id: &Uuid, query: &FlowDataQuery) -> Result<Vec<FlowDataMeta>> {
+    self.check("query_data_meta", Some(flow_id))?;
+    self.inner.query_data_meta(flow_id, query)
+  }
+  fn latest_by_key(&self, flow_id: &Uuid, key: &str) -> Result<Option<FlowData>> {
+    self.check("latest_by_key", Some(flow_id))?;
+    self.inner.latest_by_key(flow_id, key)
+  }
+  fn subscribe(&self, flow_id: &Uuid, from_cursor: i64) -> Result<FlowSubscription> {
+    self.check("subscribe", Some(flow_id))?;
+    self.inner.subscribe(flow_id, from_cursor)
+  }
+  fn load_latest_snapshot(&self, flow_id: &Uuid) -> Result<Option<SnapshotMeta>> {
+    self.check("load_latest_snapshot", Some(flow_id))?;
+    self.inner.load_latest_snapshot(flow_id)
+  }
+  fn load_snapshot_at(&self, flow_id: &Uuid, cursor: i64) -> Result<Option<SnapshotMeta>> {
+    self.check("load_snapshot_at", Some(flow_id))?;
+    self.inner.load_snapshot_at(flow_id, cursor)
+  }
+  fn load_snapshot(&self, snapshot_id: &Uuid) -> Result<(Vec<u8>, SnapshotMeta)> {
+    self.check("load_snapshot", None)?;
+    self.inner.load_snapshot(snapshot_id)
+  }
+  fn save_snapshot(&self, flow_id: &Uuid, cursor: i64, state_ptr: &str, metadata: JsonValue) -> Result<Uuid> {
+    self.check("save_snapshot", Some(flow_id))?;
+    self.inner.save_snapshot(flow_id, cursor, state_ptr, metadata)
+  }
+  fn save_snapshot_state(&self, flow_id: &Uuid, cursor: i64, state: &[u8], metadata: JsonValue) -> Result<Uuid> {
+    self.check("save_snapshot_state", Some(flow_id))?;
+    self.inner.save_snapshot_state(flow_id, cursor, state, metadata)
+  }
+  fn compact_snapshots(&self, flow_id: &Uuid) -> Result<usize> {
+    self.check("compact_snapshots", Some(flow_id))?;
+    self.inner.compact_snapshots(flow_id)
+  }
+  fn gc_snapshots(&self) -> Result<SnapshotGcReport> {
+    self.check("gc_snapshots", None)?;
+    self.inner.gc_snapshots()
+  }
+  fn create_branch(&self, parent_flow_id: &Uuid, parent_cursor: i64, metadata: JsonValue) -> Result<Uuid> {
+    self.check("create_branch", Some(parent_flow_id))?;
+    self.inner.create_branch(parent_flow_id, parent_cursor, metadata)
+  }
+  fn branch_exists(&self, flow_id: &Uuid) -> Result<bool> {
+    self.check("branch_exists", Some(flow_id))?;
+    self.inner.branch_exists(flow_id)
+  }
+  fn list_children(&self, flow_id: &Uuid) -> Result<Vec<FlowMeta>> {
+    self.check("list_children", Some(flow_id))?;
+    self.inner.list_children(flow_id)
+  }
+  fn count_steps(&self, flow_id: &Uuid) -> Result<i64> {
+    self.check("count_steps", Some(flow_id))?;
+    self.inner.count_steps(flow_id)
+  }
+  fn delete_branch(&self, flow_id: &Uuid) -> Result<()> {
+    self.check("delete_branch", Some(flow_id))?;
+    self.inner.delete_branch(flow_id)
+  }
+  fn delete_from_step(&self, flow_id: &Uuid, from_cursor: i64) -> Result<()> {
+    self.check("delete_from_step", Some(flow_id))?;
+    self.inner.delete_from_step(flow_id, from_cursor)
+  }
+  fn undelete_steps(&self, flow_id: &Uuid) -> Result<()> {
+    self.check("undelete_steps", Some(flow_id))?;
+    self.inner.undelete_steps(flow_id)
+  }
+  fn undelete(&self, flow_id: &Uuid) -> Result<()> {
+    self.check("undelete", Some(flow_id))?;
+    self.inner.undelete(flow_id)
+  }
+  fn list_deleted_flows(&self) -> Result<Vec<FlowMeta>> {
+    self.check("list_deleted_flows", None)?;
+    self.inner.list_deleted_flows()
+  }
+  fn purge_deleted(&self, retention: Duration) -> Result<PurgeReport> {
+    self.check("purge_deleted", None)?;
+    self.inner.purge_deleted(retention)
+  }
+  /// Los registros conservan su metadata (y con ella el principal que los
+  /// escribió).
+  fn rewrite_data(&self, rows: &[FlowData]) -> Result<usize> {
+    for row in rows {
+      self.check("rewrite_data", Some(&row.flow_id))?;
+    }
+    self.inner.rewrite_data(rows)
+  }
+  fn lock_for_update(&self, flow_id: &Uuid, expected_version: i64, timeout: Duration) -> Result<Option<FlowLockGuard>> {
+    self.check("lock_for_update", Some(flow_id))?;
+    self.inner.lock_for_update(flow_id, expected_version, timeout)
+  }
+  fn enqueue_work(&self, flow_id: &Uuid) -> Result<()> {
+    self.check("enqueue_work", Some(flow_id))?;
+    self.inner.enqueue_work(flow_id)
+  }
+  fn claim_work(&self, worker_id: &str) -> Result<Option<WorkItem>> {
+    self.check("claim_work", None)?;
+    self.inner.claim_work(worker_id)
+  }
+  fn heartbeat_work(&self, flow_id: &Uuid, worker_id: &str) -> Result<bool> {
+    self.check("heartbeat_work", Some(flow_id))?;
+    self.inner.heartbeat_work(flow_id, worker_id)
+  }
+  fn complete_work(&self, flow_id: &Uuid, worker_id: &str) -> Result<bool> {
+    self.check("complete_work", Some(flow_id))?;
+    self.inner.complete_work(flow_id, worker_id)
+  }
+  fn get_flow_status(&self, flow_id: &Uuid) -> Result<Option<String>> {
+    self.check("get_flow_status", Some(flow_id))?;
+    self.inner.get_flow_status(flow_id)
+  }
+  fn set_flow_status(&self, flow_id: &Uuid, new_status: Option<String>) -> Result<FlowMeta> {
+    self.check("set_flow_status", Some(flow_id))?;
+    self.inner.set_flow_status(flow_id, new_status)
+  }
+  fn get_meta(&self, flow_id: &Uuid, key: &str) -> Result<JsonValue> {
+    self.check("get_meta", Some(flow_id))?;
+    self.inner.get_meta(flow_id, key)
+  }
+  fn set_meta(&self, flow_id: &Uuid, key: &str, value: JsonValue) -> Result<()> {
+    self.check("set_meta", Some(flow_id))?;
+    self.inner.set_meta(flow_id, key, value)
+  }
+  fn del_meta(&self, flow_id: &Uuid, key: &str) -> Result<()> {
+    self.check("del_meta", Some(flow_id))?;
+    self.inner.del_meta(flow_id, key)
+  }
+  fn list_flow_ids(&self) -> Result<Vec<Uuid>> {
+    self.check("list_flow_ids", None)?;
+    self.inner.list_flow_ids()
+  }
+  fn query_flows(&self, query: &FlowQuery) -> Result<FlowPage> {
+    self.check("query_flows", None)?;
+    self.inner.query_flows(query)
+  }
+  fn dump_tables_for_debug(&self) -> Result<(Vec<FlowMeta>, Vec<FlowData>)> {
+    self.check("dump_tables_for_debug", None)?;
+    self.inner.dump_tables_for_debug()
+  }
+}
@@ -0,0 +1,329 @@
+// Archivo: query.rs
+// Propósito: consultas paginadas sobre los flows de un repositorio
+// (`FlowRepository::query_flows`), sin enumerar ids ni leer metadatos flow a
+// flow.
+//
+// - `FlowQuery`: filtros (status, `workflow_type` de la metadata, prefijo de
+//   nombre, `created_by`, rango de `created_at`, flow padre), orden
+//   (`FlowSort`) y tamaño de página.
+// - `FlowCursor` / `FlowPage`: paginación por keyset; el cursor es la clave de
+//   orden del último flow de la página, así que las páginas siguientes no
+//   dependen de un offset y no repiten ni saltan flows aunque se creen otros
+//   entre llamadas.
+//
+// Los backends SQL traducen la consulta a un único `SELECT`; `FlowQuery::page`
+// es la implementación de referencia sobre flows en memoria.
+//
+// `FlowDataQuery` hace lo mismo con los registros de un flow
+// (`FlowRepository::query_data`): rango de cursores, key exacta o prefijo,
+// sentido y límite, para no leer el historial completo con `read_data`.
+//
+// Ambas admiten predicados `JsonPredicate` (`metadata` de los flows;
+// `payload` o `metadata` de los registros); `validate` rechaza las columnas
+// que la consulta no admite antes de tocar la base.
+use crate::domain::{FlowData, FlowMeta};
+use crate::errors::Result;
+use crate::json_predicate::JsonPredicate;
+use chrono::{DateTime, Utc};
+use serde_json::json;
+use std::cmp::Ordering;
+use uuid::Uuid;
+/// Tamaño de página de `FlowQuery::new`.
+pub const DEFAULT_FLOW_PAGE_SIZE: usize = 100;
+/// Clave de metadata que filtra `FlowQuery::workflow_type`.
+pub const WORKFLOW_TYPE_META_KEY: &str = "workflow_type";
+/// Columnas JSON de `flows` que admite `FlowQuery::json`.
+pub const FLOW_JSON_COLUMNS: &[&str] = &["metadata"];
+/// Columnas JSON de `flow_data` que admite `FlowDataQuery::json`.
+pub const FLOW_DATA_JSON_COLUMNS: &[&str] = &["payload", "metadata"];
+/// Orden de `query_flows`. Los empates se resuelven siempre por `id` en el
+/// mismo sentido, de modo que el orden es total.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum FlowSort {
+  /// Por `created_at` (con la precisión que guarde el backend).
+  #[default]
+  CreatedAsc,
+  CreatedDesc,
+  /// Por `name`, con los flows sin nombre como `""`.
+  NameAsc,
+  NameDesc,
+}
+impl FlowSort {
+  pub fn descending(self) -> bool {
+    matches!(self, FlowSort::CreatedDesc | FlowSort::NameDesc)
+  }
+  pub fn by_name(self) -> bool {
+    matches!(self, FlowSort::NameAsc | FlowSort::NameDesc)
+  }
+}
+/// Posición de un flow en el orden de `query_flows`; se pasa en
+/// `FlowQuery::after` para pedir la página siguiente.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FlowCursor {
+  pub created_at: DateTime<Utc>,
+  pub name: Option<String>,
+  pub id: Uuid,
+}
+impl FlowCursor {
+  /// Cursor que apunta justo después de `meta`.
+  pub fn of(meta: &FlowMeta) -> Self {
+    Self { created_at: meta.created_at, name: meta.name.clone(), id: meta.id }
+  }
+  fn cmp_with(&self, meta: &FlowMeta, sort: FlowSort) -> Ordering {
+    let key = if sort.by_name() {
+      meta.name.as_deref().unwrap_or("").cmp(self.name.as_deref().unwrap_or(""))
+    } else {
+      meta.created_at.cmp(&self.created_at)
+    };
+    key.then(meta.id.cmp(&self.id))
+  }
+}
+/// Filtro, orden y página de `FlowRepository::query_flows`. Los filtros
+/// `None` no filtran; el rango de `created_at` es `[created_since,
+/// created_until)`. Solo se devuelven flows vivos (ni borrados ni de otro
+/// tenant).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FlowQuery {
+  pub status: Option<String>,
+  /// Valor de `metadata.workflow_type` (lo escribe
+  /// `ChemicalWorkflowFactory`).
+  pub workflow_type: Option<String>,
+  /// Prefijo de `name`, sensible a mayúsculas.
+  pub name_prefix: Option<String>,
+  pub created_by: Option<String>,
+  pub created_since: Option<DateTime<Utc>>,
+  pub created_until: Option<DateTime<Utc>>,
+  /// Solo las ramas hijas directas de este flow.
+  pub parent_flow_id: Option<Uuid>,
+  /// Predicados sobre `metadata` que deben cumplirse todos.
+  pub json: Vec<JsonPredicate>,
+  pub sort: FlowSort,
+  /// Número máximo de flows por página (al menos 1).
+  pub limit: usize,
+  /// Devuelve los flows posteriores a este cursor en el orden de `sort`
+  /// (`FlowPage::next` de la página anterior).
+  pub after: Option<FlowCursor>,
+}
+impl Default for FlowQuery {
+  fn default() -> Self {
+    Self { status: None,
+           workflow_type: None,
+           name_prefix: None,
+           created_by: None,
+           created_since: None,
+           created_until: None,
+           parent_flow_id: None,
+           json: Vec::new(),
+           sort: FlowSort::default(),
+           limit: DEFAULT_FLOW_PAGE_SIZE,
+           after: None }
+  }
+}
+impl FlowQuery {
+  pub fn new() -> Self {
+    Self::default()
+  }
+  pub fn status(mut self, status: impl Into<String>) -> Self {
+    self.status = Some(status.into());
+    self
+  }
+  pub fn workflow_type(mut self, workflow_type: impl Into<String>) -> Self {
+    self.workflow_type = Some(workflow_type.into());
+    self
+  }
+  pub fn name_prefix(mut self, prefix: impl Into<String>) -> Self {
+    self.name_prefix = Some(prefix.into());
+    self
+  }
+  pub fn created_by(mut self, created_by: impl Into<String>) -> Self {
+    self.created_by = Some(created_by.into());
+    self
+  }
+  pub fn created_since(mut self, since: DateTime<Utc>) -> Self {
+    self.created_since = Some(since);
+    self
+  }
+  pub fn created_until(mut self, until: DateTime<Utc>) -> Self {
+    self.created_until = Some(until);
+    self
+  }
+  pub fn parent(mut self, parent_flow_id: Uuid) -> Self {
+    self.parent_flow_id = Some(parent_flow_id);
+    self
+  }
+  /// Añade un predicado sobre `metadata`.
+  pub fn json(mut self, predicate: JsonPredicate) -> Self {
+    self.json.push(predicate);
+    self
+  }
+  pub fn sort(mut self, sort: FlowSort) -> Self {
+    self.sort = sort;
+    self
+  }
+  pub fn limit(mut self, limit: usize) -> Self {
+    self.limit = limit;
+    self
+  }
+  pub fn after(mut self, cursor: FlowCursor) -> Self {
+    self.after = Some(cursor);
+    self
+  }
+  /// `limit` efectivo (`0` se trata como `1`).
+  pub fn page_size(&self) -> usize {
+    self.limit.max(1)
+  }
+  /// `Err` si algún predicado de `json` no es sobre `metadata`.
+  pub fn validate(&self) -> Result<()> {
+    self.json.iter().try_for_each(|p| p.check_column(FLOW_JSON_COLUMNS))
+  }
+  /// Predicados JSON de la consulta: los de `json` más
+  /// `metadata.workflow_type == <workflow_type>`.
+  pub fn json_predicates(&self) -> Vec<JsonPredicate> {
+    let path = format!("metadata.{}", WORKFLOW_TYPE_META_KEY);
+    let workflow_type = self.workflow_type.as_ref().map(|w| {
+                                                     JsonPredicate::eq(&path, json!(w)).expect("clave de workflow_type válida")
+                                                   });
+    self.json.iter().cloned().chain(workflow_type).collect()
+  }
+  /// `true` si `meta` cumple los filtros (sin tener en cuenta `after` ni
+  /// `limit`).
+  pub fn matches(&self, meta: &FlowMeta) -> bool {
+    self.status.as_ref().is_none_or(|s| meta.status.as_ref() == Some(s))
+    && self.json_predicates().iter().all(|p| p.matches(&meta.metadata))
+    && self.name_prefix.as_ref().is_none_or(|p| meta.name.as_ref().is_some_and(|n| n.starts_with(p.as_str())))
+    && self.created_by.as_ref().is_none_or(|c| meta.created_by.as_ref() == Some(c))
+    && self.created_since.is_none_or(|s| meta.created_at >= s)
+    && self.created_until.is_none_or(|u| meta.created_at < u)
+    && self.parent_flow_id.is_none_or(|p| meta.parent_flow_id == Some(p))
+  }
+  /// `true` si `meta` va después de `after` en el orden de `sort` (siempre
+  /// `true` sin `after`).
+  pub fn is_after_cursor(&self, meta: &FlowMeta) -> bool {
+    self.after.as_ref().is_none_or(|c| {
+                         let ord = c.cmp_with(meta, self.sort);
+                         if self.sort.descending() {
+                           ord == Ordering::Less
+                         } else {
+                           ord == Ordering::Greater
+                         }
+                       })
+  }
+  /// Aplica la consulta completa a `flows`: filtra, ordena y corta la
+  /// página.
+  pub fn page(&self, flows: impl IntoIterator<Item = FlowMeta>) -> FlowPage {
+    let mut out: Vec<FlowMeta> = flows.into_iter().filter(|m| self.matches(m) && self.is_after_cursor(m)).collect();
+    out.sort_by(|a, b| {
+         let ord = FlowCursor::of(b).cmp_with(a, self.sort);
+         if self.sort.descending() {
+           ord.reverse()
+         } else {
+           ord
+         }
+       });
+    FlowPage::from_rows(out, self.page_size())
+  }
+}
+/// Página de `query_flows`.
+#[derive(Debug, Clone, Default)]
+pub struct FlowPage {
+  pub flows: Vec<FlowMeta>,
+  /// Cursor para pedir la página siguiente; `None` si no hay más flows.
+  pub next: Option<FlowCursor>,
+}
+impl FlowPage {
+  /// Construye la página a partir de filas ya filtradas y ordenadas, de las
+  /// que los backends leen `page_size + 1` para saber si hay más.
+  pub fn from_rows(mut rows: Vec<FlowMeta>, page_size: usize) -> Self {
+    let more = rows.len() > page_size;
+    rows.truncate(page_size);
+    let next = if more { rows.last().map(FlowCursor::of) } else { None };
+    Self { flows: rows, next }
+  }
+}
+/// Filtro de `FlowRepository::query_data`/`query_data_meta` sobre el
+/// historial de un flow (el mismo que devuelve `read_data`, incluidos los
+/// registros heredados de sus antepasados). El rango de cursores es
+/// `(after, up_to]`; sin límite se devuelven todos los que cumplan.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FlowDataQuery {
+  pub after: Option<i64>,
+  pub up_to: Option<i64>,
+  /// Key exacta.
+  pub key: Option<String>,
+  /// Prefijo de key, sensible a mayúsculas (p. ej. `step_state:`).
+  pub key_prefix: Option<String>,
+  /// Predicados sobre `payload` o `metadata` que deben cumplirse todos.
+  pub json: Vec<JsonPredicate>,
+  /// Orden por cursor descendente (los más recientes primero).
+  pub latest_first: bool,
+  pub limit: Option<usize>,
+}
+impl FlowDataQuery {
+  pub fn new() -> Self {
+    Self::default()
+  }
+  pub fn after(mut self, cursor: i64) -> Self {
+    self.after = Some(cursor);
+    self
+  }
+  pub fn up_to(mut self, cursor: i64) -> Self {
+    self.up_to = Some(cursor);
+    self
+  }
+  pub fn key(mut self, key: impl Into<String>) -> Self {
+    self.key = Some(key.into());
+    self
+  }
+  pub fn key_prefix(mut self, prefix: impl Into<String>) -> Self {
+    self.key_prefix = Some(prefix.into());
+    self
+  }
+  /// Añade un predicado sobre `payload` o `metadata`.
+  pub fn json(mut self, predicate: JsonPredicate) -> Self {
+    self.json.push(predicate);
+    self
+  }
+  pub fn latest_first(mut self) -> Self {
+    self.latest_first = true;
+    self
+  }
+  pub fn limit(mut self, limit: usize) -> Self {
+    self.limit = Some(limit);
+    self
+  }
+  /// `Err` si algún predicado de `json` no es sobre `payload` ni
+  /// `metadata`.
+  pub fn validate(&self) -> Result<()> {
+    self.json.iter().try_for_each(|p| p.check_column(FLOW_DATA_JSON_COLUMNS))
+  }
+  /// `true` si un registro con `cursor` y `key` cumple los filtros (sin los
+  /// predicados JSON).
+  pub fn matches(&self, cursor: i64, key: &str) -> bool {
+    self.after.is_none_or(|a| cursor > a)
+    && self.up_to.is_none_or(|u| cursor <= u)
+    && self.key.as_ref().is_none_or(|k| key == k)
+    && self.key_prefix.as_ref().is_none_or(|p| key.starts_with(p.as_str()))
+  }
+  /// `true` si `data` cumple los predicados JSON.
+  pub fn matches_json(&self, data: &FlowData) -> bool {
+    self.json.iter().all(|p| p.matches(if p.column() == "payload" { &data.payload } else { &data.metadata }))
+  }
+  /// Aplica la consulta completa a `rows`: filtra, ordena por cursor en el
+  /// sentido pedido y corta en `limit`.
+  pub fn apply(&self, rows: impl IntoIterator<Item = FlowData>) -> Vec<FlowData> {
+    self.order_and_limit(rows.into_iter().filter(|d| self.matches(d.cursor, &d.key) && self.matches_json(d)))
+  }
+  /// Solo el orden y el límite de `apply`, para mezclar filas que la base
+  /// ya filtró (las de `query_data_meta` llegan sin payload).
+  pub fn order_and_limit(&self, rows: impl IntoIterator<Item = FlowData>) -> Vec<FlowData> {
+    let mut out: Vec<FlowData> = rows.into_iter().collect();
+    out.sort_by_key(|d| d.cursor);
+    if self.latest_first {
+      out.reverse();
+    }
+    if let Some(limit) = self.limit {
+      out.truncate(limit);
+    }
+    out
+  }
+}
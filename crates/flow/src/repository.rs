@@ -1,17 +1,114 @@
 //ahora serian los demas repository
 // Archivo: repository.rs
 // Propósito: definir el trait `FlowRepository` y los traits auxiliares
-// (`SnapshotStore`, `ArtifactStore`). Describe el contrato que deben
-// implementar las persistencias (Postgres, in-memory, etc.).
-use crate::domain::{FlowData, FlowMeta, PersistResult, SnapshotMeta, WorkItem};
-use crate::errors::Result;
+// (`SnapshotStore`, `ArtifactStore`, `AuditLog`). Describe el contrato que
+// deben implementar las persistencias (Postgres, in-memory, etc.).
+use crate::audit::AuditQuery;
+use crate::domain::{AuditEntry, FlowData, FlowDataMeta, FlowMeta, PersistResult, SnapshotMeta, WorkItem};
+use crate::errors::{FlowError, Result};
+use crate::query::{FlowDataQuery, FlowPage, FlowQuery};
+use crate::retention::{PurgeReport, SnapshotGcReport};
+use crate::snapshot_store::StoredBlob;
+use crate::subscription::FlowSubscription;
 use serde_json::Value as JsonValue;
+use std::fmt;
+use std::sync::mpsc::RecvTimeoutError;
+use std::sync::Arc;
+use std::time::Duration;
 use uuid::Uuid;
+/// Guard RAII de un lock exclusivo sobre un flow (ver
+/// `FlowRepository::lock_for_update`). El lock se libera al destruir el
+/// guard o al llamar a `release`.
+///
+/// En los backends cuyo lock expira (SQLite, `FLOW_LOCK_TTL_SECS`) el guard
+/// lleva además una acción de renovación: `renew` la ejecuta una vez y
+/// `keep_alive` la repite en segundo plano mientras corre un trabajo largo.
+pub struct FlowLockGuard {
+  flow_id: Uuid,
+  release: Option<Box<dyn FnOnce() + Send>>,
+  renew: Option<LockRenewal>,
+}
+/// Acción de renovación de un lock que expira y cada cuánto repetirla.
+struct LockRenewal {
+  every: Duration,
+  action: Box<dyn Fn() -> Result<()> + Send + Sync>,
+}
+impl FlowLockGuard {
+  /// Construye el guard con la acción que libera el lock en el backend.
+  /// Pensado para las implementaciones de `FlowRepository`.
+  pub fn new(flow_id: Uuid, release: impl FnOnce() + Send + 'static) -> Self {
+    Self { flow_id, release: Some(Box::new(release)), renew: None }
+  }
+  /// Añade la acción que extiende la expiración del lock, a repetir cada
+  /// `every` en `keep_alive`. Debe devolver `Err(FlowError::Conflict)` si el
+  /// lock ya no pertenece a este guard.
+  pub fn with_renew(mut self, every: Duration, renew: impl Fn() -> Result<()> + Send + Sync + 'static) -> Self {
+    self.renew = Some(LockRenewal { every, action: Box::new(renew) });
+    self
+  }
+  /// Flow protegido por este guard.
+  pub fn flow_id(&self) -> Uuid {
+    self.flow_id
+  }
+  /// Extiende la expiración del lock. Sin acción de renovación (locks que
+  /// no expiran) no hace nada. `Err(FlowError::Conflict)` indica que el
+  /// lock expiró y otro dueño lo tomó.
+  pub fn renew(&self) -> Result<()> {
+    match &self.renew {
+      Some(renewal) => (renewal.action)(),
+      None => Ok(()),
+    }
+  }
+  /// Ejecuta `f` renovando el lock en otro hilo cada intervalo de
+  /// renovación, hasta que `f` termine o el lock se pierda. Los fallos de
+  /// renovación no interrumpen `f`: quien necesite saber si el lock sigue
+  /// siendo suyo llama a `renew` después.
+  pub fn keep_alive<T>(&self, f: impl FnOnce() -> T) -> T {
+    let Some(renewal) = &self.renew else {
+      return f();
+    };
+    std::thread::scope(|s| {
+      let (stop, stopped) = std::sync::mpsc::channel::<()>();
+      s.spawn(move || {
+         while let Err(RecvTimeoutError::Timeout) = stopped.recv_timeout(renewal.every) {
+           if let Err(FlowError::Conflict(_)) = (renewal.action)() {
+             break;
+           }
+         }
+       });
+      let out = f();
+      drop(stop);
+      out
+    })
+  }
+  /// Libera el lock explícitamente (equivalente a `drop`).
+  pub fn release(mut self) {
+    if let Some(f) = self.release.take() {
+      f();
+    }
+  }
+}
+impl Drop for FlowLockGuard {
+  fn drop(&mut self) {
+    if let Some(f) = self.release.take() {
+      f();
+    }
+  }
+}
+impl fmt::Debug for FlowLockGuard {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.debug_struct("FlowLockGuard").field("flow_id", &self.flow_id).finish()
+  }
+}
 /// Contrato mínimo del repositorio de flujos en el modelo basado en FlowData.
 ///
 /// El repositorio persiste registros de datos del flujo (`FlowData`) en tiempo
 /// real: cada registro contiene la información necesaria para reconstruir el
 /// estado en un cursor dado y se guarda inmediatamente.
+///
+/// Los borrados (`delete_branch`, `delete_from_step`) son lógicos: lo borrado
+/// deja de verse en el resto de operaciones (un flow borrado se comporta como
+/// inexistente) pero se conserva hasta `purge_deleted`.
 pub trait FlowRepository: Send + Sync {
   /// Obtiene metadatos ligeros del `flow`.
   ///
@@ -23,51 +120,197 @@ pub trait FlowRepository: Send + Sync {
   /// cursor). Se pasa sólo la información ergonomica: `name`, `status`
   /// y `metadata`.
   fn create_flow(&self, name: Option<String>, status: Option<String>, metadata: JsonValue) -> Result<Uuid>;
+  /// Igual que `create_flow`, guardando `created_by` (usuario o servicio que
+  /// crea el flow; ver `flow::principal`).
+  fn create_flow_as(&self,
+                    name: Option<String>,
+                    status: Option<String>,
+                    metadata: JsonValue,
+                    created_by: Option<String>)
+                    -> Result<Uuid>;
   /// Persiste un registro de datos para el flujo. `expected_version` permite
   /// controlar concurrencia (optimistic). Devuelve `PersistResult`.
   ///
   /// Comportamiento esperado:
+  /// - Si `command_id` está presente y ya existe un registro con ese
+  ///   `command_id` en el flow -> no se duplica y se devuelve el
+  ///   `PersistResult` original (mismo `new_version`), sin importar
+  ///   `expected_version`. Así un reintento tras un fallo de red es seguro.
   /// - Si `expected_version` no coincide con la versión actual ->
   ///   `PersistResult::Conflict`.
-  /// - Si `command_id` está presente y ya existe un registro con ese
-  ///   `command_id` -> comportamiento idempotente (no duplicar).
+  /// - El `cursor` debe ser mayor que `current_cursor`; si no, devuelve
+  ///   `Err(FlowError::Conflict)`.
+  /// - Si el flow no existe -> `Err(FlowError::NotFound)`.
   fn persist_data(&self, data: &FlowData, expected_version: i64) -> Result<PersistResult>;
   /// Lee registros de datos a partir de un cursor (exclusive), ordenados.
+  /// En una rama incluye los heredados de sus antepasados (ver
+  /// `create_branch`), todos con `flow_id` igual al pedido; su `id` es el del
+  /// registro guardado en el antepasado.
   fn read_data(&self, flow_id: &Uuid, from_cursor: i64) -> Result<Vec<FlowData>>;
+  /// Como `read_data`, pero solo los registros que cumplen `query` (rango
+  /// de cursores, key, prefijo de key, predicados JSON sobre `payload` o
+  /// `metadata`), en su orden y hasta su límite. Los
+  /// filtros se aplican en el almacenamiento; la semántica de referencia es
+  /// `FlowDataQuery::apply` sobre `read_data(flow_id, 0)`.
+  fn query_data(&self, flow_id: &Uuid, query: &FlowDataQuery) -> Result<Vec<FlowData>>;
+  /// `query_data` sin leer los payloads.
+  fn query_data_meta(&self, flow_id: &Uuid, query: &FlowDataQuery) -> Result<Vec<FlowDataMeta>>;
+  /// Registro de mayor cursor con exactamente `key` en el historial del
+  /// flow (heredados incluidos); `None` si no hay ninguno o el flow no
+  /// existe.
+  fn latest_by_key(&self, flow_id: &Uuid, key: &str) -> Result<Option<FlowData>>;
+  /// Suscribe a los registros del flow con `cursor > from_cursor`: primero
+  /// los ya persistidos y después cada nuevo `FlowData` confirmado, en orden
+  /// de cursor y sin duplicados (ver `FlowSubscription`). Evita hacer polling
+  /// sobre `read_data`. Si el flow no existe -> `Err(FlowError::NotFound)`.
+  fn subscribe(&self, flow_id: &Uuid, from_cursor: i64) -> Result<FlowSubscription>;
   /// Devuelve metadata del último snapshot para este flow, si existe.
   fn load_latest_snapshot(&self, flow_id: &Uuid) -> Result<Option<SnapshotMeta>>;
+  /// Metadata del snapshot de mayor `cursor` que no supere `cursor` (el
+  /// punto de partida para reconstruir el estado en ese cursor, ver
+  /// `time_travel::state_at`). `Ok(None)` si no hay ninguno.
+  fn load_snapshot_at(&self, flow_id: &Uuid, cursor: i64) -> Result<Option<SnapshotMeta>>;
   /// Carga snapshot por id: devuelve bytes serializados + metadata.
+  /// `Err(FlowError::NotFound)` si el snapshot no existe.
   fn load_snapshot(&self, snapshot_id: &Uuid) -> Result<(Vec<u8>, SnapshotMeta)>; // bytes, meta
-  /// Guarda snapshot: escribe blob en object store y metadata en Postgres.
+  /// Registra un snapshot cuyo blob ya está en el `SnapshotStore` del
+  /// repositorio: `state_ptr` es su key.
   fn save_snapshot(&self, flow_id: &Uuid, cursor: i64, state_ptr: &str, metadata: serde_json::Value) -> Result<Uuid>;
+  /// Guarda `state` en el `SnapshotStore` del repositorio y registra el
+  /// snapshot con la key resultante como `state_ptr`. `load_snapshot`
+  /// devuelve de nuevo estos bytes.
+  fn save_snapshot_state(&self, flow_id: &Uuid, cursor: i64, state: &[u8], metadata: serde_json::Value) -> Result<Uuid>;
+  /// Aplica la política de retención del repositorio (`SnapshotRetention`)
+  /// a los snapshots de `flow_id`: borra los que no conserva y los blobs de
+  /// su `SnapshotStore` que ya no referencia ningún snapshot (las ramas
+  /// comparten blobs con su padre). Devuelve cuántos snapshots se borraron.
+  /// `Err(FlowError::NotFound)` si el flow no existe.
+  fn compact_snapshots(&self, flow_id: &Uuid) -> Result<usize>;
+  /// Recolección masiva: compacta todos los flows y borra del
+  /// `SnapshotStore` los blobs que ningún snapshot referencia y que superan
+  /// `SnapshotRetention::orphan_grace` (p. ej. de ramas borradas o de
+  /// guardados interrumpidos).
+  fn gc_snapshots(&self) -> Result<SnapshotGcReport>;
   /// Crea una rama (branch) a partir de `parent_flow_id` y `parent_cursor`.
-  /// Firma ergonomica: el caller pasa `name`, `status`, `parent_cursor`
-  /// y `metadata`. El repositorio genera el nuevo `flow_id`, copia los
-  /// `FlowData` del padre hasta `parent_cursor` y persiste la nueva fila
-  /// en `flows`. Devuelve el `Uuid` de la nueva rama.
+  /// El repositorio genera el nuevo `flow_id`, copia las filas de snapshot
+  /// del padre con `cursor <= parent_cursor` (los blobs se comparten) y
+  /// persiste la nueva fila en `flows` con la `metadata` dada,
+  /// `current_cursor = parent_cursor` y `current_version = 0`. Devuelve el
+  /// `Uuid` de la nueva rama, o `Err(FlowError::NotFound)` si el padre no
+  /// existe.
+  ///
+  /// Las ramas son copy-on-write: los `FlowData` del padre no se copian.
+  /// La rama solo guarda los registros que se le añadan y `read_data`
+  /// compone el historial con los del padre hasta `parent_cursor` (y, por
+  /// la misma regla, los de sus antepasados; ver
+  /// `lineage::data_segments`). La idempotencia por `command_id` es por
+  /// flow, así que los comandos heredados no se deduplican en la rama.
   ///
   /// Debe hacerse de forma atómica por el repositorio concreto.
   fn create_branch(&self, parent_flow_id: &Uuid, parent_cursor: i64, metadata: JsonValue) -> Result<Uuid>;
   /// Verifica si existe una rama/flow con el id dado.
   fn branch_exists(&self, flow_id: &Uuid) -> Result<bool>;
-  /// Cuenta cuántos pasos (`FlowData`) tiene un flow. Debe devolver
-  /// -1 si el flow no existe, 0 si existe pero no tiene pasos.
+  /// Ramas hijas directas de `flow_id` (las que tienen `parent_flow_id ==
+  /// flow_id`), ordenadas por `parent_cursor`, `created_at` e id.
+  /// `Err(FlowError::NotFound)` si el flow no existe. El resto del grafo de
+  /// linaje se construye sobre esto en `flow::lineage`.
+  fn list_children(&self, flow_id: &Uuid) -> Result<Vec<FlowMeta>>;
+  /// Cuenta cuántos pasos (`FlowData`) tiene un flow, incluidos los
+  /// heredados. Debe devolver -1 si el flow no existe, 0 si existe pero no
+  /// tiene pasos.
   fn count_steps(&self, flow_id: &Uuid) -> Result<i64>;
-  /// Elimina una rama y todas sus subramas (recursivo). Borra metadata,
-  /// steps y snapshots asociados.
+  /// Borra una rama de forma lógica: marca el flow con `deleted_at` y desde
+  /// ese momento se comporta como inexistente; sus registros y snapshots se
+  /// conservan para `undelete` hasta `purge_deleted`. Las ramas hijas no se
+  /// borran ni cambian: conservan `parent_flow_id`/`parent_cursor` y siguen
+  /// leyendo a través de la rama borrada, así que `read_data` les devuelve
+  /// el mismo historial y `undelete` la recupera con el linaje intacto.
+  /// `Err(FlowError::NotFound)` si el flow no existe o ya estaba borrado.
   fn delete_branch(&self, flow_id: &Uuid) -> Result<()>;
-  /// Elimina todos los pasos y subramas a partir de un cursor dado
-  /// (inclusive) en el flow `flow_id`.
+  /// Trunca el flow `flow_id` desde `from_cursor`: marca como borrados los
+  /// pasos y snapshots con `cursor >= from_cursor` (dejan de leerse y de
+  /// contar para la idempotencia por `command_id`) y deja `current_cursor`
+  /// en el mayor cursor restante (o 0). En una rama con `parent_cursor >=
+  /// from_cursor` el tramo heredado se recorta bajando `parent_cursor` a
+  /// `from_cursor - 1`; los registros del padre no se tocan. Las ramas
+  /// descendientes cuyo historial incluye el tramo truncado (las del camino
+  /// con `parent_cursor >= from_cursor`) se borran como con
+  /// `delete_branch`. `current_version` no cambia. `undelete_steps` deshace
+  /// el truncado completo mientras no se purgue.
   fn delete_from_step(&self, flow_id: &Uuid, from_cursor: i64) -> Result<()>;
-  /// Lock ligero para actualizaciones (puede mapear a check de versión).
-  fn lock_for_update(&self, flow_id: &Uuid, expected_version: i64) -> Result<bool>;
-  /// Claim de trabajo para workers. Marca job como in-flight o devuelve
-  /// `None`.
+  /// Deshace el último `delete_from_step` de `flow_id` aún no purgado: los
+  /// pasos y snapshots truncados vuelven a leerse (y a deduplicar por
+  /// `command_id`), `parent_cursor` y `current_cursor` recuperan su valor y
+  /// las ramas borradas con el truncado vuelven a estar vivas (salvo las que
+  /// aún lean pasos truncados de otro flow). `Err(FlowError::Conflict)` si
+  /// el flow ha persistido pasos desde entonces (cambió su
+  /// `current_version`); `Err(FlowError::NotFound)` si el flow no existe o
+  /// no tiene truncados pendientes.
+  fn undelete_steps(&self, flow_id: &Uuid) -> Result<()>;
+  /// Deshace un `delete_branch`: el flow vuelve a estar visible con sus
+  /// registros, snapshots y linaje. No hace nada si el flow está vivo.
+  /// `Err(FlowError::Conflict)` si su historial pasa por pasos truncados de
+  /// un antepasado (se recuperan antes con `undelete_steps`);
+  /// `Err(FlowError::NotFound)` si no existe o ya se purgó.
+  fn undelete(&self, flow_id: &Uuid) -> Result<()>;
+  /// Flows borrados con `delete_branch` y aún no purgados, con `deleted_at`,
+  /// del borrado más antiguo al más reciente.
+  fn list_deleted_flows(&self) -> Result<Vec<FlowMeta>>;
+  /// Elimina definitivamente lo borrado hace al menos `retention`: los
+  /// flows de `delete_branch` (con sus registros, snapshots y entradas de la
+  /// cola) y los pasos y snapshots truncados por `delete_from_step`, que ya
+  /// no se pueden recuperar. Antes de purgar un flow, las hijas que le
+  /// sobreviven reciben copias de los registros que heredaban a través de
+  /// él y quedan huérfanas (`parent_flow_id`/`parent_cursor` a `None`) con
+  /// el mismo historial; un flow no se purga mientras una hija tenga
+  /// pendiente un truncado que recortó su tramo heredado. Los blobs de
+  /// snapshots y artifacts que queden sin referencias los recogen después
+  /// `gc_snapshots` y `gc_artifacts`.
+  fn purge_deleted(&self, retention: Duration) -> Result<PurgeReport>;
+  /// Reescribe en el sitio `payload` y `metadata` de registros existentes
+  /// (identificados por `id`, aunque se hayan leído desde una rama que los
+  /// hereda) en una sola transacción, sin cambiar cursor,
+  /// versión del flow ni `command_id`. Pensado para migraciones de esquema
+  /// (`flow::payload::upcast_stored_payloads`); no se notifica a
+  /// `subscribe`. `Err(FlowError::NotFound)` si algún `id` no existe, sin
+  /// aplicar ninguno. Devuelve cuántos registros reescribió.
+  fn rewrite_data(&self, rows: &[FlowData]) -> Result<usize>;
+  /// Adquiere un lock exclusivo sobre el flow, esperando como máximo
+  /// `timeout`. Una vez adquirido comprueba que `current_version` coincida
+  /// con `expected_version`.
+  ///
+  /// - `Ok(Some(guard))`: lock adquirido y versión vigente; el lock se libera
+  ///   al soltar el guard.
+  /// - `Ok(None)`: la versión no coincide (el lock ya fue liberado).
+  /// - `Err(FlowError::Conflict)`: no se pudo adquirir dentro de `timeout`.
+  /// - `Err(FlowError::NotFound)`: el flow no existe.
+  ///
+  /// El lock no es reentrante: volver a pedirlo mientras se mantiene el guard
+  /// espera hasta agotar el timeout.
+  fn lock_for_update(&self, flow_id: &Uuid, expected_version: i64, timeout: Duration) -> Result<Option<FlowLockGuard>>;
+  /// Encola el flow para que algún worker lo procese. Es idempotente: si el
+  /// flow ya está en la cola no se modifica su lease ni su posición.
+  /// Devuelve `Err(FlowError::NotFound)` si el flow no existe.
+  fn enqueue_work(&self, flow_id: &Uuid) -> Result<()>;
+  /// Claim de trabajo para workers. Toma el item más antiguo de la cola que
+  /// no tenga lease vigente (nunca reclamado o con lease expirado), lo marca
+  /// como in-flight para `worker_id` con un lease nuevo y lo devuelve. Si no
+  /// hay trabajo disponible devuelve `None`.
+  ///
+  /// Dos workers concurrentes nunca reciben el mismo item mientras el lease
+  /// esté vigente; si un worker muere sin completar, el item vuelve a estar
+  /// disponible cuando expira su lease.
   fn claim_work(&self, worker_id: &str) -> Result<Option<WorkItem>>;
+  /// Renueva el lease de un item reclamado. Devuelve `false` si `worker_id`
+  /// ya no posee el item (fue reclamado por otro worker o completado).
+  fn heartbeat_work(&self, flow_id: &Uuid, worker_id: &str) -> Result<bool>;
+  /// Marca el item como terminado y lo retira de la cola. Devuelve `false` si
+  /// `worker_id` ya no posee el item.
+  fn complete_work(&self, flow_id: &Uuid, worker_id: &str) -> Result<bool>;
   /// Obtiene el estado (status) actual del flow.
   fn get_flow_status(&self, flow_id: &Uuid) -> Result<Option<String>>;
   /// Actualiza el estado (status) del flow. Devuelve el nuevo FlowMeta si se
-  /// actualizó correctamente.
+  /// actualizó correctamente o `Err(FlowError::NotFound)` si no existe.
   fn set_flow_status(&self, flow_id: &Uuid, new_status: Option<String>) -> Result<FlowMeta>;
   /// Read a single metadata value by key from the `metadata` JSON object
   /// stored on the Flow. If the key is not present, returns
@@ -79,32 +322,83 @@ pub trait FlowRepository: Send + Sync {
   /// Delete a metadata key from the flow's metadata object. No-op if key
   /// not present.
   fn del_meta(&self, flow_id: &Uuid, key: &str) -> Result<()>;
-  /// Devuelve los ids (UUID) de todos los flujos persistidos.
+  /// Devuelve los ids (UUID) de todos los flujos persistidos (sin los
+  /// borrados).
   ///
   /// Útil para inspección y para desacoplar lógica que quiera enumerar
   /// flujos sin conocer la implementación interna del repositorio.
   fn list_flow_ids(&self) -> Result<Vec<Uuid>>;
+  /// Flows vivos que cumplen los filtros de `query`, en el orden de
+  /// `query.sort` y a partir de `query.after`, con como mucho
+  /// `query.page_size()` flows por página. Recorrer las páginas con
+  /// `FlowPage::next` devuelve cada flow una sola vez. Debe resolverse con
+  /// una sola consulta al almacenamiento; la semántica de referencia es
+  /// `FlowQuery::page`. Un predicado JSON sobre una columna no admitida
+  /// (`FlowQuery::validate`) es un error.
+  fn query_flows(&self, query: &FlowQuery) -> Result<FlowPage>;
   /// Helper de depuración: devuelve una copia de las tablas `flows` y
   /// `flow_data` en tipos de dominio. Pensado sólo para uso CLI/tests.
   ///
   /// No es obligatorio para implementaciones productivas, pero es
   /// conveniente para ejemplos y debugging local. Implementaciones
-  /// deben devolver pares (Vec<FlowMeta>, Vec<FlowData>).
+  /// deben devolver pares (Vec<FlowMeta>, Vec<FlowData>), sin flows ni
+  /// registros borrados.
   fn dump_tables_for_debug(&self) -> Result<(Vec<FlowMeta>, Vec<FlowData>)>;
+  /// Envuelve `repo` (otro repositorio sobre el mismo almacén, como el de
+  /// un alcance de unidad de trabajo) con los mismos decoradores que este:
+  /// principal, auditoría… Las implementaciones base lo devuelven tal cual.
+  fn rewrap(&self, repo: Arc<dyn FlowRepository>) -> Arc<dyn FlowRepository> {
+    repo
+  }
 }
 // Store traits para separar implementaciones de bajo nivel.
+/// Almacén de blobs de snapshot. Los repositorios guardan en `state_ptr`
+/// solo la key devuelta por `save` (ver `flow::snapshot_store` y los stores
+/// de `chem-persistence`).
 pub trait SnapshotStore: Send + Sync {
   /// Guarda bytes serializados y devuelve una key (p.ej. s3 key).
   fn save(&self, state: &[u8]) -> Result<String>;
-  /// Carga bytes desde la key.
+  /// Carga bytes desde la key. `Err(FlowError::NotFound)` si no existe.
   fn load(&self, key: &str) -> Result<Vec<u8>>;
+  /// Borra el blob de la key. Borrar una key inexistente no es error.
+  fn delete(&self, key: &str) -> Result<()>;
+  /// Lista los blobs guardados, para detectar los huérfanos.
+  fn list(&self) -> Result<Vec<StoredBlob>>;
 }
+/// Almacén de artifacts direccionado por contenido: la key es el SHA-256 del
+/// blob (ver `flow::artifact_store`), por lo que el contenido de una key es
+/// inmutable y los blobs repetidos se guardan una sola vez.
+///
+/// Un blob se conserva mientras tenga retenciones (`copy_if_needed`) o algún
+/// `FlowData` lo declare en `metadata.artifacts`; `gc_artifacts` borra el
+/// resto.
 pub trait ArtifactStore: Send + Sync {
-  /// Almacena blob y devuelve key.
+  /// Almacena el blob y devuelve su key. Si ya existe no se duplica (solo se
+  /// renueva la fecha del último `put`).
   fn put(&self, blob: &[u8]) -> Result<String>;
-  /// Recupera blob por key.
-  fn get(&self, key: &str) -> Result<Vec<u8>>;
-  /// Copia el blob si se necesita aislamiento (copy-on-write), devuelve nueva
+  /// Recupera el blob verificando su hash. `Err(FlowError::NotFound)` si no
+  /// existe y `Err(FlowError::Integrity)` si su contenido no coincide con la
   /// key.
+  fn get(&self, key: &str) -> Result<Vec<u8>>;
+  /// Retiene el blob para un nuevo dueño (incrementa su contador de
+  /// referencias) y devuelve la misma key: al ser inmutable no hace falta
+  /// copiarlo. `Err(FlowError::NotFound)` si no existe.
   fn copy_if_needed(&self, src_key: &str) -> Result<String>;
+  /// Libera una retención tomada con `copy_if_needed` (sin bajar de cero).
+  /// `Err(FlowError::NotFound)` si el blob no existe.
+  fn release(&self, key: &str) -> Result<()>;
+  /// Borra los blobs sin retenciones ni referencias desde `FlowData` cuyo
+  /// último `put` es anterior a `grace` (margen para los blobs recién
+  /// guardados que aún no se han referenciado). Devuelve cuántos borró.
+  fn gc_artifacts(&self, grace: Duration) -> Result<usize>;
+}
+/// Registro de auditoría de solo inserción: lo alimenta
+/// `flow::audit::AuditedFlowRepository` (y el equivalente de dominio) con
+/// cada llamada que modifica un repositorio.
+pub trait AuditLog: Send + Sync {
+  /// Añade una entrada. Las entradas nunca se modifican ni se borran.
+  fn append(&self, entry: &AuditEntry) -> Result<()>;
+  /// Entradas que cumplen `query`, en orden cronológico (y de inserción
+  /// dentro del mismo instante).
+  fn query(&self, query: &AuditQuery) -> Result<Vec<AuditEntry>>;
 }
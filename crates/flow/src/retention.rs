@@ -0,0 +1,108 @@
+// Archivo: retention.rs
+// Propósito: política de retención de snapshots usada por
+// `FlowRepository::compact_snapshots` y `FlowRepository::gc_snapshots`.
+//
+// - `SnapshotRetention`: qué snapshots de un flow se conservan (los últimos N,
+//   uno cada K cursores y los que sirven de base a ramas hijas) y cuánto se
+//   respeta un blob sin referencias antes de borrarlo.
+// - `snapshots_to_prune` / `orphan_blob_keys`: decisiones puras, comunes a
+//   todos los backends; cada repositorio solo aplica los borrados.
+// - `PurgeReport` / `purge_cutoff`: ventana de retención de lo borrado de forma
+//   lógica, que `FlowRepository::purge_deleted` elimina definitivamente.
+use crate::domain::SnapshotMeta;
+use crate::snapshot_store::StoredBlob;
+use chrono::{DateTime, Utc};
+use std::collections::HashSet;
+use std::time::Duration;
+use uuid::Uuid;
+/// Política de retención de snapshots de un repositorio.
+///
+/// Un snapshot se conserva si cumple cualquiera de las reglas; el resto se
+/// borra al compactar. El snapshot más reciente de un flow se conserva
+/// siempre (`keep_last` vale como mínimo 1).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SnapshotRetention {
+  /// Conserva los `keep_last` snapshots más recientes (por cursor).
+  pub keep_last: usize,
+  /// Conserva el snapshot más reciente de cada tramo de `K` cursores
+  /// (`cursor / K`), para poder rehidratar puntos antiguos sin replay largo.
+  pub keep_every: Option<i64>,
+  /// Conserva, para cada rama hija, el último snapshot con `cursor <=
+  /// parent_cursor` (el punto desde el que se rehidrataría la bifurcación).
+  pub keep_branch_points: bool,
+  /// Antigüedad mínima de un blob sin referencias para que `gc_snapshots`
+  /// lo borre; protege los blobs recién guardados cuyo snapshot aún no se
+  /// ha registrado.
+  pub orphan_grace: Duration,
+}
+impl Default for SnapshotRetention {
+  fn default() -> Self {
+    Self { keep_last: 10, keep_every: None, keep_branch_points: true, orphan_grace: Duration::from_secs(3600) }
+  }
+}
+/// Resultado de `FlowRepository::gc_snapshots`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SnapshotGcReport {
+  /// Filas de `snapshots` borradas al compactar los flows.
+  pub snapshots_removed: usize,
+  /// Blobs borrados del `SnapshotStore` (de snapshots compactados o
+  /// huérfanos).
+  pub blobs_removed: usize,
+}
+/// Snapshots de un flow que la política permite borrar.
+///
+/// `snapshots` son los del flow y `branch_points` los `parent_cursor` de
+/// sus ramas hijas. El orden del resultado no es significativo.
+pub fn snapshots_to_prune(snapshots: &[SnapshotMeta],
+                          branch_points: &[i64],
+                          policy: &SnapshotRetention)
+                          -> Vec<SnapshotMeta> {
+  let mut ordered: Vec<&SnapshotMeta> = snapshots.iter().collect();
+  ordered.sort_by(|a, b| b.cursor.cmp(&a.cursor).then(b.created_at.cmp(&a.created_at)));
+  let mut keep: HashSet<Uuid> = ordered.iter().take(policy.keep_last.max(1)).map(|s| s.id).collect();
+  if let Some(every) = policy.keep_every.filter(|k| *k > 0) {
+    let mut buckets = HashSet::new();
+    for s in &ordered {
+      if buckets.insert(s.cursor.div_euclid(every)) {
+        keep.insert(s.id);
+      }
+    }
+  }
+  if policy.keep_branch_points {
+    for point in branch_points {
+      if let Some(s) = ordered.iter().find(|s| s.cursor <= *point) {
+        keep.insert(s.id);
+      }
+    }
+  }
+  ordered.into_iter().filter(|s| !keep.contains(&s.id)).cloned().collect()
+}
+/// Keys de `blobs` que ningún snapshot referencia (`referenced`) y cuya
+/// antigüedad en `now` supera `orphan_grace`.
+pub fn orphan_blob_keys(blobs: &[StoredBlob],
+                        referenced: &HashSet<String>,
+                        policy: &SnapshotRetention,
+                        now: DateTime<Utc>)
+                        -> Vec<String> {
+  let grace = chrono::Duration::from_std(policy.orphan_grace).unwrap_or(chrono::Duration::MAX);
+  blobs.iter()
+       .filter(|b| !referenced.contains(&b.key) && now.signed_duration_since(b.created_at) >= grace)
+       .map(|b| b.key.clone())
+       .collect()
+}
+/// Resultado de `FlowRepository::purge_deleted`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PurgeReport {
+  /// Flows borrados con `delete_branch` eliminados junto con sus registros y
+  /// snapshots.
+  pub flows: usize,
+  /// Registros truncados por `delete_from_step` eliminados.
+  pub records: usize,
+  /// Snapshots truncados por `delete_from_step` eliminados.
+  pub snapshots: usize,
+}
+/// Instante límite de `purge_deleted`: se purga lo borrado en `cutoff` o
+/// antes. Una `retention` que no cabe en el calendario no purga nada.
+pub fn purge_cutoff(retention: Duration, now: DateTime<Utc>) -> DateTime<Utc> {
+  chrono::Duration::from_std(retention).ok().and_then(|r| now.checked_sub_signed(r)).unwrap_or(DateTime::<Utc>::MIN_UTC)
+}
@@ -0,0 +1,137 @@
+// Archivo: snapshot_store.rs
+// Propósito: implementaciones de `SnapshotStore` sin dependencias externas.
+//
+// - `InMemorySnapshotStore`: blobs en un `HashMap` (tests, stubs).
+// - `FsSnapshotStore`: un fichero `<key>` por blob dentro de un directorio.
+// - `StoredBlob`: entrada de `SnapshotStore::list`, usada por el GC de blobs
+//   huérfanos (`FlowRepository::gc_snapshots`).
+// - `decode_inline_snapshot` / `load_snapshot_blob`: reconocen el formato
+//   anterior, en el que el estado (JSON en base64) se guardaba directamente en
+//   `state_ptr`, para poder leerlo y migrarlo a un store.
+//
+// Los backends SQL añaden stores propios (tabla de blobs, S3/MinIO) en
+// `chem-persistence`.
+use crate::errors::{FlowError, Result};
+use crate::repository::SnapshotStore;
+use base64::Engine;
+use chrono::{DateTime, Utc};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use uuid::Uuid;
+/// Blob presente en un `SnapshotStore` (ver `SnapshotStore::list`).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StoredBlob {
+  pub key: String,
+  /// Momento en que se guardó (o última modificación, según el store).
+  pub created_at: DateTime<Utc>,
+}
+/// Genera una key nueva para un blob de snapshot.
+pub fn new_snapshot_key() -> String {
+  format!("{}.bin", Uuid::new_v4())
+}
+/// Decodifica un `state_ptr` en el formato inline anterior (JSON codificado
+/// en base64). Devuelve `None` si no lo es, p. ej. si es una key de store.
+pub fn decode_inline_snapshot(state_ptr: &str) -> Option<Vec<u8>> {
+  let bytes = base64::engine::general_purpose::STANDARD.decode(state_ptr.as_bytes()).ok()?;
+  serde_json::from_slice::<serde_json::Value>(&bytes).ok()?;
+  Some(bytes)
+}
+/// Lee el blob de `state_ptr` del store; si no está y `state_ptr` es un
+/// snapshot inline del formato anterior, devuelve su contenido decodificado.
+pub fn load_snapshot_blob(store: &dyn SnapshotStore, state_ptr: &str) -> Result<Vec<u8>> {
+  store.load(state_ptr).or_else(|e| decode_inline_snapshot(state_ptr).ok_or(e))
+}
+/// Blobs en memoria por key, con su fecha de guardado.
+type BlobMap = HashMap<String, (Vec<u8>, DateTime<Utc>)>;
+/// Store en memoria; no duradero.
+#[derive(Debug, Default)]
+pub struct InMemorySnapshotStore {
+  blobs: Mutex<BlobMap>,
+}
+impl InMemorySnapshotStore {
+  pub fn new() -> Self {
+    Self::default()
+  }
+}
+impl SnapshotStore for InMemorySnapshotStore {
+  fn save(&self, state: &[u8]) -> Result<String> {
+    let key = new_snapshot_key();
+    self.blobs.lock().unwrap_or_else(|e| e.into_inner()).insert(key.clone(), (state.to_vec(), Utc::now()));
+    Ok(key)
+  }
+  fn load(&self, key: &str) -> Result<Vec<u8>> {
+    self.blobs
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .get(key)
+        .map(|(bytes, _)| bytes.clone())
+        .ok_or_else(|| FlowError::NotFound(format!("snapshot blob {}", key)))
+  }
+  fn delete(&self, key: &str) -> Result<()> {
+    self.blobs.lock().unwrap_or_else(|e| e.into_inner()).remove(key);
+    Ok(())
+  }
+  fn list(&self) -> Result<Vec<StoredBlob>> {
+    let blobs = self.blobs.lock().unwrap_or_else(|e| e.into_inner());
+    Ok(blobs.iter().map(|(key, (_, created_at))| StoredBlob { key: key.clone(), created_at: *created_at }).collect())
+  }
+}
+/// Store en el sistema de ficheros: cada blob es el fichero `dir/<key>`.
+///
+/// `list` solo considera los ficheros `*.bin` (las keys que genera `save`),
+/// pero el directorio debería ser exclusivo del store.
+#[derive(Debug, Clone)]
+pub struct FsSnapshotStore {
+  dir: PathBuf,
+}
+impl FsSnapshotStore {
+  /// Usa `dir` (creándolo si no existe).
+  pub fn new(dir: impl Into<PathBuf>) -> Result<Self> {
+    let dir = dir.into();
+    std::fs::create_dir_all(&dir).map_err(|e| FlowError::Storage(format!("{}: {}", dir.display(), e)))?;
+    Ok(Self { dir })
+  }
+  /// Ruta del blob; rechaza keys que saldrían del directorio.
+  fn path(&self, key: &str) -> Result<PathBuf> {
+    if key.is_empty() || key.contains(['/', '\\']) || key.starts_with('.') {
+      return Err(FlowError::Storage(format!("snapshot key inválida: {}", key)));
+    }
+    Ok(self.dir.join(key))
+  }
+}
+impl SnapshotStore for FsSnapshotStore {
+  fn save(&self, state: &[u8]) -> Result<String> {
+    let key = new_snapshot_key();
+    std::fs::write(self.path(&key)?, state).map_err(|e| FlowError::Storage(e.to_string()))?;
+    Ok(key)
+  }
+  fn load(&self, key: &str) -> Result<Vec<u8>> {
+    match std::fs::read(self.path(key)?) {
+      Ok(bytes) => Ok(bytes),
+      Err(e) if e.kind() == std::io::ErrorKind::NotFound => Err(FlowError::NotFound(format!("snapshot blob {}", key))),
+      Err(e) => Err(FlowError::Storage(e.to_string())),
+    }
+  }
+  fn delete(&self, key: &str) -> Result<()> {
+    match std::fs::remove_file(self.path(key)?) {
+      Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(FlowError::Storage(e.to_string())),
+      _ => Ok(()),
+    }
+  }
+  fn list(&self) -> Result<Vec<StoredBlob>> {
+    let io_err = |e: std::io::Error| FlowError::Storage(format!("{}: {}", self.dir.display(), e));
+    let mut blobs = Vec::new();
+    for entry in std::fs::read_dir(&self.dir).map_err(io_err)? {
+      let entry = entry.map_err(io_err)?;
+      let key = entry.file_name().to_string_lossy().to_string();
+      let meta = entry.metadata().map_err(io_err)?;
+      if !meta.is_file() || key.starts_with('.') || !key.ends_with(".bin") {
+        continue;
+      }
+      let created_at = meta.modified().map(DateTime::<Utc>::from).unwrap_or_else(|_| Utc::now());
+      blobs.push(StoredBlob { key, created_at });
+    }
+    Ok(blobs)
+  }
+}
@@ -11,12 +11,22 @@
 // Estas implementaciones son intencionalmente sencillas y no garantizan
 // durabilidad, aislamiento concurrente real ni escalabilidad; están pensadas
 // para demos, tests unitarios y como referencia.
-use crate::domain::{FlowData, FlowMeta, PersistResult, SnapshotMeta, WorkItem};
+use crate::artifact_store::{artifact_key, artifact_refs, verify_artifact};
+use crate::domain::{FlowData, FlowDataMeta, FlowMeta, PersistResult, SnapshotMeta, WorkItem, DEFAULT_WORK_LEASE_SECS};
 use crate::errors::{FlowError, Result};
-use crate::repository::{ArtifactStore, FlowRepository, SnapshotStore};
-use chrono::Utc;
-use std::collections::{HashMap, VecDeque};
-use std::sync::{Mutex, MutexGuard};
+use crate::lineage::{data_segments, DataSegment};
+use crate::query::{FlowDataQuery, FlowPage, FlowQuery};
+use crate::repository::{ArtifactStore, FlowLockGuard, FlowRepository, SnapshotStore};
+use crate::retention::{
+  orphan_blob_keys, purge_cutoff, snapshots_to_prune, PurgeReport, SnapshotGcReport, SnapshotRetention,
+};
+use crate::snapshot_store::{decode_inline_snapshot, load_snapshot_blob, InMemorySnapshotStore, StoredBlob};
+use crate::subscription::{ChangeFeed, FlowSubscription};
+use chrono::{DateTime, Duration, Utc};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::hash::Hash;
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::time::Instant;
 use uuid::Uuid;
 /// Pool simple en memoria para encolar y reclamar `WorkItem`.
 ///
@@ -85,19 +95,195 @@ impl Default for GateService {
     Self::new()
   }
 }
+/// Entrada de la cola de trabajo del repositorio en memoria.
+#[derive(Clone, PartialEq)]
+struct QueuedWork {
+  flow_id: Uuid,
+  worker_id: Option<String>,
+  lease_expires_at: Option<DateTime<Utc>>,
+  attempts: i64,
+}
+/// Artifact guardado por el repositorio en memoria.
+#[derive(Clone, PartialEq)]
+struct StoredArtifact {
+  blob: Vec<u8>,
+  /// Retenciones de `copy_if_needed` pendientes de `release`.
+  ref_count: i64,
+  last_put: DateTime<Utc>,
+}
+/// Truncado de `delete_from_step` pendiente de `undelete_steps` o
+/// `purge_deleted`, con lo que quitó del repositorio.
+#[derive(Clone, PartialEq)]
+struct Truncation {
+  flow_id: Uuid,
+  at: DateTime<Utc>,
+  from_cursor: i64,
+  /// `current_version` del flow al truncar; si cambia, el truncado ya no se
+  /// puede deshacer.
+  version: i64,
+  /// `parent_cursor` anterior del flow, si el truncado lo bajó.
+  parent_cursor: Option<i64>,
+  steps: Vec<FlowData>,
+  snapshots: Vec<SnapshotMeta>,
+  /// Versión resultante de cada `command_id` truncado.
+  commands: Vec<(Uuid, i64)>,
+  /// Ramas vivas borradas con el truncado.
+  flows: Vec<Uuid>,
+}
+/// Antepasados de `flow` (padre primero), incluidos los borrados: una rama
+/// viva sigue leyendo a través de un padre borrado hasta que se purga.
+fn chain_of(flows: &HashMap<Uuid, FlowMeta>, deleted: &HashMap<Uuid, FlowMeta>, flow: &FlowMeta) -> Result<Vec<FlowMeta>> {
+  let mut chain = Vec::new();
+  let mut seen = HashSet::from([flow.id]);
+  let mut next = flow.parent_flow_id;
+  while let Some(parent) = next.and_then(|p| flows.get(&p).or_else(|| deleted.get(&p))) {
+    if !seen.insert(parent.id) {
+      return Err(FlowError::Other(format!("ciclo en el linaje de {}", flow.id)));
+    }
+    next = parent.parent_flow_id;
+    chain.push(parent.clone());
+  }
+  Ok(chain)
+}
+/// Tramos de `steps` que componen el historial de `flow_id` (vacío si el
+/// flow no existe o está borrado).
+fn segments_of(flows: &HashMap<Uuid, FlowMeta>,
+               deleted: &HashMap<Uuid, FlowMeta>,
+               flow_id: &Uuid)
+               -> Result<Vec<DataSegment>> {
+  let Some(flow) = flows.get(flow_id) else {
+    return Ok(Vec::new());
+  };
+  Ok(data_segments(flow, &chain_of(flows, deleted, flow)?))
+}
+/// Si el historial de `flow` pasa por pasos de un antepasado que siguen
+/// truncados en `truncations`.
+fn reads_truncated(flows: &HashMap<Uuid, FlowMeta>,
+                   deleted: &HashMap<Uuid, FlowMeta>,
+                   truncations: &[Truncation],
+                   flow: &FlowMeta)
+                   -> Result<bool> {
+  let segments = data_segments(flow, &chain_of(flows, deleted, flow)?);
+  Ok(segments.iter().skip(1).any(|seg| {
+                               truncations.iter()
+                                          .any(|t| t.flow_id == seg.flow_id && seg.up_to.is_some_and(|u| t.from_cursor <= u))
+                             }))
+}
+/// Registros de `segments` con `cursor > from_cursor`, ordenados por cursor
+/// y con el `flow_id` de la rama que los lee.
+fn stitch(steps: &HashMap<Uuid, Vec<FlowData>>,
+          flow_id: &Uuid,
+          segments: &[DataSegment],
+          from_cursor: i64)
+          -> Vec<FlowData> {
+  let mut out: Vec<FlowData> =
+    segments.iter()
+            .flat_map(|seg| {
+              steps.get(&seg.flow_id).into_iter().flatten().filter(move |d| d.cursor > from_cursor && seg.contains(d.cursor))
+            })
+            .map(|d| FlowData { flow_id: *flow_id, ..d.clone() })
+            .collect();
+  out.sort_by_key(|d| d.cursor);
+  out
+}
 // Repositorio mínimo en memoria para ejemplos y wiring (no durable)
 pub struct InMemoryFlowRepository {
   /// Metadatos de flows indexados por `flow_id`.
   flows: Mutex<HashMap<Uuid, FlowMeta>>,
+  /// Flows borrados con `delete_branch` (con `deleted_at`), pendientes de
+  /// `undelete` o `purge_deleted`. Sus registros y snapshots siguen en
+  /// `steps` y `snapshots`; solo los leen sus ramas descendientes vivas.
+  deleted_flows: Mutex<HashMap<Uuid, FlowMeta>>,
   /// Registros de `FlowData` por flow (ordenados por inserción/`cursor`).
   steps: Mutex<HashMap<Uuid, Vec<FlowData>>>,
   /// Snapshots metadata indexados por snapshot id.
   snapshots: Mutex<HashMap<Uuid, SnapshotMeta>>,
+  /// Truncados de `delete_from_step` en orden, con sus registros y
+  /// snapshots.
+  truncations: Mutex<Vec<Truncation>>,
+  /// Versión resultante de cada comando aplicado, por `(flow_id,
+  /// command_id)`; permite devolver el `PersistResult` original en
+  /// reintentos.
+  command_versions: Mutex<HashMap<(Uuid, Uuid), i64>>,
+  /// Cola de trabajo en orden de encolado.
+  work: Mutex<Vec<QueuedWork>>,
+  /// Duración del lease concedido en `claim_work`/`heartbeat_work`.
+  lease_duration: Duration,
+  /// Flows con lock exclusivo tomado; el `Condvar` despierta a quienes
+  /// esperan cuando un guard se libera.
+  locks: Arc<(Mutex<HashSet<Uuid>>, Condvar)>,
+  /// Difusión de los `FlowData` persistidos hacia `subscribe`.
+  feed: ChangeFeed,
+  /// Store de los blobs de snapshot (`InMemorySnapshotStore` por defecto).
+  snapshot_store: Arc<dyn SnapshotStore>,
+  /// Política aplicada por `compact_snapshots`/`gc_snapshots`.
+  retention: SnapshotRetention,
+  /// Artifacts por key (SHA-256). Las referencias desde `FlowData` se
+  /// obtienen recorriendo `steps` en `gc_artifacts`.
+  artifacts: Mutex<HashMap<String, StoredArtifact>>,
 }
 impl InMemoryFlowRepository {
   /// Crea una nueva instancia del repositorio en memoria.
   pub fn new() -> Self {
-    Self { flows: Mutex::new(HashMap::new()), steps: Mutex::new(HashMap::new()), snapshots: Mutex::new(HashMap::new()) }
+    Self { flows: Mutex::new(HashMap::new()),
+           deleted_flows: Mutex::new(HashMap::new()),
+           steps: Mutex::new(HashMap::new()),
+           snapshots: Mutex::new(HashMap::new()),
+           truncations: Mutex::new(Vec::new()),
+           command_versions: Mutex::new(HashMap::new()),
+           work: Mutex::new(Vec::new()),
+           lease_duration: Duration::seconds(DEFAULT_WORK_LEASE_SECS),
+           locks: Arc::new((Mutex::new(HashSet::new()), Condvar::new())),
+           feed: ChangeFeed::new(),
+           snapshot_store: Arc::new(InMemorySnapshotStore::new()),
+           retention: SnapshotRetention::default(),
+           artifacts: Mutex::new(HashMap::new()) }
+  }
+  /// Usa `store` para los blobs de snapshot.
+  pub fn with_snapshot_store(mut self, store: Arc<dyn SnapshotStore>) -> Self {
+    self.snapshot_store = store;
+    self
+  }
+  /// Usa `policy` al compactar snapshots.
+  pub fn with_snapshot_retention(mut self, policy: SnapshotRetention) -> Self {
+    self.retention = policy;
+    self
+  }
+  /// Compacta los snapshots de `flow_id`; devuelve `(snapshots, blobs)`
+  /// borrados.
+  fn compact_flow_snapshots(&self, flow_id: &Uuid) -> Result<(usize, usize)> {
+    let branch_points: Vec<i64> = {
+      let flows = self.lock(&self.flows)?;
+      if !flows.contains_key(flow_id) {
+        return Err(FlowError::NotFound(format!("flow {}", flow_id)));
+      }
+      flows.values().filter(|f| f.parent_flow_id == Some(*flow_id)).filter_map(|f| f.parent_cursor).collect()
+    };
+    let (removed, keys) = {
+      let mut snaps = self.lock(&self.snapshots)?;
+      let own: Vec<SnapshotMeta> = snaps.values().filter(|s| s.flow_id == *flow_id).cloned().collect();
+      let pruned = snapshots_to_prune(&own, &branch_points, &self.retention);
+      for s in &pruned {
+        snaps.remove(&s.id);
+      }
+      // las ramas copian los snapshots del padre con la misma key
+      let referenced: HashSet<&str> = snaps.values().map(|s| s.state_ptr.as_str()).collect();
+      let keys: HashSet<String> = pruned.iter()
+                                        .map(|s| s.state_ptr.clone())
+                                        .filter(|k| !referenced.contains(k.as_str()) && decode_inline_snapshot(k).is_none())
+                                        .collect();
+      (pruned.len(), keys)
+    };
+    for key in &keys {
+      self.snapshot_store.delete(key)?;
+    }
+    Ok((removed, keys.len()))
+  }
+  /// Ajusta la duración del lease de la cola de trabajo (útil en tests para
+  /// forzar expiraciones rápidas).
+  pub fn with_lease_duration(mut self, lease: std::time::Duration) -> Self {
+    self.lease_duration = Duration::from_std(lease).unwrap_or(Duration::seconds(DEFAULT_WORK_LEASE_SECS));
+    self
   }
   /// Helper para mapear `Mutex::lock()` en un `Result` con
   /// `FlowError::Storage`.
@@ -107,6 +293,146 @@ impl InMemoryFlowRepository {
   fn lock<'a, T>(&'a self, m: &'a Mutex<T>) -> std::result::Result<MutexGuard<'a, T>, FlowError> {
     m.lock().map_err(|e| FlowError::Storage(format!("mutex poisoned: {:?}", e)))
   }
+  /// `segments_of` con los flows borrados, que una rama viva aún lee.
+  fn segments(&self, flows: &HashMap<Uuid, FlowMeta>, flow_id: &Uuid) -> Result<Vec<DataSegment>> {
+    segments_of(flows, &*self.lock(&self.deleted_flows)?, flow_id)
+  }
+}
+/// Copia del contenido de un `InMemoryFlowRepository` tomada con
+/// `checkpoint`. No incluye los blobs del `SnapshotStore` ni deshace lo ya
+/// publicado a los suscriptores.
+#[derive(Clone)]
+pub struct InMemoryFlowState {
+  flows: HashMap<Uuid, FlowMeta>,
+  deleted_flows: HashMap<Uuid, FlowMeta>,
+  steps: HashMap<Uuid, Vec<FlowData>>,
+  snapshots: HashMap<Uuid, SnapshotMeta>,
+  truncations: Vec<Truncation>,
+  command_versions: HashMap<(Uuid, Uuid), i64>,
+  work: Vec<QueuedWork>,
+  artifacts: HashMap<String, StoredArtifact>,
+}
+/// Entradas de `mine` que difieren de `base` (`None` si se borró), o
+/// `Conflict` si alguna también cambió en `current` con otro valor.
+fn changes<K: Eq + Hash + Clone, V: PartialEq + Clone>(current: &HashMap<K, V>,
+                                                      base: &HashMap<K, V>,
+                                                      mine: &HashMap<K, V>)
+                                                      -> Result<Vec<(K, Option<V>)>> {
+  let mut out = Vec::new();
+  for key in base.keys().chain(mine.keys().filter(|k| !base.contains_key(*k))) {
+    let (before, after) = (base.get(key), mine.get(key));
+    if before == after {
+      continue;
+    }
+    let now = current.get(key);
+    if now != before && now != after {
+      return Err(FlowError::Conflict("el repositorio cambió fuera de la unidad de trabajo".into()));
+    }
+    out.push((key.clone(), after.cloned()));
+  }
+  Ok(out)
+}
+fn apply_changes<K: Eq + Hash, V>(map: &mut HashMap<K, V>, changes: Vec<(K, Option<V>)>) {
+  for (key, value) in changes {
+    match value {
+      Some(v) => map.insert(key, v),
+      None => map.remove(&key),
+    };
+  }
+}
+/// `mine` si difiere de `base` y `current` no cambió (`None` si no hay nada
+/// que aplicar); para las listas, que se comparan enteras.
+fn replacement<T: PartialEq>(current: &T, base: &T, mine: T) -> Result<Option<T>> {
+  if mine == *base || mine == *current {
+    Ok(None)
+  } else if current == base {
+    Ok(Some(mine))
+  } else {
+    Err(FlowError::Conflict("el repositorio cambió fuera de la unidad de trabajo".into()))
+  }
+}
+impl InMemoryFlowRepository {
+  /// Copia los flows, registros, snapshots, cola y artifacts (la unidad de
+  /// trabajo en memoria de `chem-persistence` la usa con `detached` y
+  /// `merge`).
+  pub fn checkpoint(&self) -> Result<InMemoryFlowState> {
+    Ok(InMemoryFlowState { flows: self.lock(&self.flows)?.clone(),
+                           deleted_flows: self.lock(&self.deleted_flows)?.clone(),
+                           steps: self.lock(&self.steps)?.clone(),
+                           snapshots: self.lock(&self.snapshots)?.clone(),
+                           truncations: self.lock(&self.truncations)?.clone(),
+                           command_versions: self.lock(&self.command_versions)?.clone(),
+                           work: self.lock(&self.work)?.clone(),
+                           artifacts: self.lock(&self.artifacts)?.clone() })
+  }
+  /// Repositorio independiente con el contenido de `state` y la misma
+  /// configuración (store de snapshots, retención, lease y locks de flow).
+  /// Sus escrituras no llegan a `self` ni a sus suscriptores hasta `merge`.
+  pub fn detached(&self, state: InMemoryFlowState) -> Self {
+    Self { flows: Mutex::new(state.flows),
+           deleted_flows: Mutex::new(state.deleted_flows),
+           steps: Mutex::new(state.steps),
+           snapshots: Mutex::new(state.snapshots),
+           truncations: Mutex::new(state.truncations),
+           command_versions: Mutex::new(state.command_versions),
+           work: Mutex::new(state.work),
+           lease_duration: self.lease_duration,
+           locks: Arc::clone(&self.locks),
+           feed: ChangeFeed::new(),
+           snapshot_store: Arc::clone(&self.snapshot_store),
+           retention: self.retention.clone(),
+           artifacts: Mutex::new(state.artifacts) }
+  }
+  /// Aplica lo que cambió de `base` a `mine` (por flow, snapshot, comando o
+  /// artifact; la cola y los truncados se comparan enteros) y publica los
+  /// registros nuevos. Si algo de eso cambió también en `self` desde `base`
+  /// devuelve `Conflict` sin aplicar nada. `also` se ejecuta con el
+  /// repositorio bloqueado, tras comprobar los conflictos; si falla tampoco
+  /// se aplica nada.
+  pub fn merge(&self, base: &InMemoryFlowState, mine: InMemoryFlowState, also: impl FnOnce() -> Result<()>) -> Result<()> {
+    // mismo orden que el resto de métodos: todos empiezan por `flows`
+    let mut flows = self.lock(&self.flows)?;
+    let mut deleted = self.lock(&self.deleted_flows)?;
+    let mut steps = self.lock(&self.steps)?;
+    let mut snaps = self.lock(&self.snapshots)?;
+    let mut truncations = self.lock(&self.truncations)?;
+    let mut command_versions = self.lock(&self.command_versions)?;
+    let mut work = self.lock(&self.work)?;
+    let mut artifacts = self.lock(&self.artifacts)?;
+    let flow_changes = changes(&flows, &base.flows, &mine.flows)?;
+    let deleted_changes = changes(&deleted, &base.deleted_flows, &mine.deleted_flows)?;
+    let step_changes = changes(&steps, &base.steps, &mine.steps)?;
+    let snapshot_changes = changes(&snaps, &base.snapshots, &mine.snapshots)?;
+    let command_changes = changes(&command_versions, &base.command_versions, &mine.command_versions)?;
+    let artifact_changes = changes(&artifacts, &base.artifacts, &mine.artifacts)?;
+    let new_truncations = replacement(&*truncations, &base.truncations, mine.truncations)?;
+    let new_work = replacement(&*work, &base.work, mine.work)?;
+    also()?;
+    let published: Vec<FlowData> =
+      step_changes.iter()
+                  .flat_map(|(flow_id, rows)| {
+                    let before: HashSet<Uuid> = base.steps.get(flow_id).into_iter().flatten().map(|d| d.id).collect();
+                    rows.iter().flatten().filter(move |d| !before.contains(&d.id)).cloned()
+                  })
+                  .collect();
+    apply_changes(&mut flows, flow_changes);
+    apply_changes(&mut deleted, deleted_changes);
+    apply_changes(&mut steps, step_changes);
+    apply_changes(&mut snaps, snapshot_changes);
+    apply_changes(&mut command_versions, command_changes);
+    apply_changes(&mut artifacts, artifact_changes);
+    if let Some(t) = new_truncations {
+      *truncations = t;
+    }
+    if let Some(w) = new_work {
+      *work = w;
+    }
+    drop((flows, deleted, steps, snaps, truncations, command_versions, work, artifacts));
+    for data in &published {
+      self.feed.publish(data);
+    }
+    Ok(())
+  }
 }
 impl Default for InMemoryFlowRepository {
   fn default() -> Self {
@@ -124,21 +450,30 @@ impl FlowRepository for InMemoryFlowRepository {
   /// Crea un nuevo flow en memoria. Inserta la metadata y devuelve el id.
   ///
   /// `name`, `status` y `metadata` son valores ergonomicos; los campos
-  /// `created_by`, `created_at`, `current_cursor` y `current_version` se
-  /// generan automáticamente.
+  /// `created_at`, `current_cursor` y `current_version` se generan
+  /// automáticamente; `created_by` queda vacío (ver `create_flow_as`).
   fn create_flow(&self, name: Option<String>, status: Option<String>, metadata: serde_json::Value) -> Result<Uuid> {
+    self.create_flow_as(name, status, metadata, None)
+  }
+  fn create_flow_as(&self,
+                    name: Option<String>,
+                    status: Option<String>,
+                    metadata: serde_json::Value,
+                    created_by: Option<String>)
+                    -> Result<Uuid> {
     // Generar id y metadatos básicos
     let id = Uuid::new_v4();
     let meta = FlowMeta { id,
                           name,
                           status,
-                          created_by: None,
+                          created_by,
                           created_at: Utc::now(),
                           current_cursor: 0,
                           current_version: 0,
                           parent_flow_id: None,
                           parent_cursor: None,
-                          metadata };
+                          metadata,
+                          deleted_at: None };
     self.lock(&self.flows)?.insert(id, meta.clone());
     Ok(id)
   }
@@ -147,10 +482,22 @@ impl FlowRepository for InMemoryFlowRepository {
   /// Si existen varios snapshots asociados al flow, se devuelve el de
   /// mayor `cursor`.
   fn load_latest_snapshot(&self, flow_id: &Uuid) -> Result<Option<SnapshotMeta>> {
+    if !self.lock(&self.flows)?.contains_key(flow_id) {
+      return Ok(None);
+    }
     // Elegimos el snapshot de mayor cursor para el flow (si existe).
     let snaps = self.lock(&self.snapshots)?;
     Ok(snaps.values().filter(|s| &s.flow_id == flow_id).max_by_key(|s| s.cursor).cloned())
   }
+  /// Igual que `load_latest_snapshot` pero ignorando los snapshots con
+  /// `cursor` posterior al pedido.
+  fn load_snapshot_at(&self, flow_id: &Uuid, cursor: i64) -> Result<Option<SnapshotMeta>> {
+    if !self.lock(&self.flows)?.contains_key(flow_id) {
+      return Ok(None);
+    }
+    let snaps = self.lock(&self.snapshots)?;
+    Ok(snaps.values().filter(|s| &s.flow_id == flow_id && s.cursor <= cursor).max_by_key(|s| s.cursor).cloned())
+  }
   /// Carga un snapshot por id. Retorna los bytes (simulados) y la metadata.
   ///
   /// En este stub los bytes retornados son vacíos (no se persigue
@@ -158,44 +505,62 @@ impl FlowRepository for InMemoryFlowRepository {
   fn load_snapshot(&self, snapshot_id: &Uuid) -> Result<(Vec<u8>, SnapshotMeta)> {
     let snaps = self.lock(&self.snapshots)?;
     let meta = snaps.get(snapshot_id).cloned().ok_or(FlowError::NotFound("snapshot".into()))?;
-    Ok((vec![], meta))
+    drop(snaps);
+    let bytes = load_snapshot_blob(self.snapshot_store.as_ref(), &meta.state_ptr)?;
+    Ok((bytes, meta))
   }
   /// Lee los `FlowData` para un `flow_id` a partir de `from_cursor`
-  /// (exclusive), ordenados por cursor.
+  /// (exclusive), ordenados por cursor. En una rama incluye los registros
+  /// heredados de sus antepasados.
   ///
   /// Retorna un vector vacío si no hay pasos para el `flow_id`.
   fn read_data(&self, flow_id: &Uuid, from_cursor: i64) -> Result<Vec<FlowData>> {
+    let flows = self.lock(&self.flows)?;
+    let segments = self.segments(&flows, flow_id)?;
+    let steps = self.lock(&self.steps)?;
+    Ok(stitch(&steps, flow_id, &segments, from_cursor))
+  }
+  fn query_data(&self, flow_id: &Uuid, query: &FlowDataQuery) -> Result<Vec<FlowData>> {
+    query.validate()?;
+    let flows = self.lock(&self.flows)?;
+    let segments = self.segments(&flows, flow_id)?;
     let steps = self.lock(&self.steps)?;
-    Ok(steps.get(flow_id).cloned().unwrap_or_default().into_iter().filter(|d| d.cursor > from_cursor).collect())
+    Ok(query.apply(stitch(&steps, flow_id, &segments, query.after.unwrap_or(0))))
+  }
+  fn query_data_meta(&self, flow_id: &Uuid, query: &FlowDataQuery) -> Result<Vec<FlowDataMeta>> {
+    Ok(self.query_data(flow_id, query)?.iter().map(FlowDataMeta::from).collect())
+  }
+  fn latest_by_key(&self, flow_id: &Uuid, key: &str) -> Result<Option<FlowData>> {
+    let query = FlowDataQuery::new().key(key).latest_first().limit(1);
+    Ok(self.query_data(flow_id, &query)?.into_iter().next())
   }
   /// Persiste un `FlowData` aplicando control optimista por
   /// `expected_version` y deduplicación por `command_id` cuando está
   /// presente.
   ///
   /// Reglas principales:
+  /// - Si `command_id` está presente y ya se aplicó en este flow, se considera
+  ///   un reintento: no se inserta de nuevo y se devuelve el `PersistResult`
+  ///   original (antes de comprobar la versión).
   /// - Si `expected_version` no coincide con `current_version` del flow,
   ///   devuelve `PersistResult::Conflict`.
-  /// - Si `command_id` está presente y existe un registro con el mismo
-  ///   `command_id`, se considera idempotente y no se inserta de nuevo.
   /// - El `cursor` del nuevo dato debe ser estrictamente mayor que el
   ///   `current_cursor` del flow.
   fn persist_data(&self, data: &FlowData, expected_version: i64) -> Result<PersistResult> {
     let mut flows = self.lock(&self.flows)?;
     let mut steps = self.lock(&self.steps)?;
+    let mut command_versions = self.lock(&self.command_versions)?;
     let flow_meta = flows.get_mut(&data.flow_id).ok_or(FlowError::NotFound("flow".into()))?;
+    // Idempotency: a replayed command returns its original result
+    if let Some(cmd_id) = data.command_id {
+      if let Some(v) = command_versions.get(&(data.flow_id, cmd_id)) {
+        return Ok(PersistResult::Ok { new_version: *v });
+      }
+    }
     // Optimistic concurrency: check expected_version
     if flow_meta.current_version != expected_version {
       return Ok(PersistResult::Conflict);
     }
-    // Idempotency: if command_id present, ensure we don't duplicate
-    if let Some(cmd_id) = data.command_id {
-      if let Some(existing) = steps.get(&data.flow_id) {
-        if existing.iter().any(|d| d.command_id == Some(cmd_id)) {
-          // Return current version (no change)
-          return Ok(PersistResult::Ok { new_version: flow_meta.current_version });
-        }
-      }
-    }
     // Basic validations: ensure cursor monotonicity
     if data.cursor <= flow_meta.current_cursor {
       return Err(FlowError::Conflict(format!("cursor {} not greater than current {}",
@@ -206,8 +571,23 @@ impl FlowRepository for InMemoryFlowRepository {
     list.push(data.clone());
     flow_meta.current_version = flow_meta.current_version.saturating_add(1);
     flow_meta.current_cursor = data.cursor;
+    if let Some(cmd_id) = data.command_id {
+      command_versions.insert((data.flow_id, cmd_id), flow_meta.current_version);
+    }
+    self.feed.publish(data);
     Ok(PersistResult::Ok { new_version: flow_meta.current_version })
   }
+  /// Registra el suscriptor en el `ChangeFeed` y después lee los registros
+  /// existentes; los que lleguen por ambos caminos se descartan en
+  /// `FlowSubscription`.
+  fn subscribe(&self, flow_id: &Uuid, from_cursor: i64) -> Result<FlowSubscription> {
+    if !self.lock(&self.flows)?.contains_key(flow_id) {
+      return Err(FlowError::NotFound(format!("flow {}", flow_id)));
+    }
+    let live = self.feed.register(*flow_id);
+    let backlog = self.read_data(flow_id, from_cursor)?;
+    Ok(FlowSubscription::new(*flow_id, from_cursor, backlog, live))
+  }
   fn get_meta(&self, flow_id: &Uuid, key: &str) -> Result<serde_json::Value> {
     let flows = self.lock(&self.flows)?;
     if let Some(meta) = flows.get(flow_id) {
@@ -244,8 +624,8 @@ impl FlowRepository for InMemoryFlowRepository {
       Err(FlowError::NotFound(format!("flow {}", flow_id)))
     }
   }
-  /// Guarda metadata de snapshot en memoria. El `state_ptr` es solamente
-  /// un string/clave simbólica en esta implementación.
+  /// Guarda metadata de snapshot en memoria. El `state_ptr` es la key del
+  /// blob en `snapshot_store`.
   ///
   /// Retorna el `Uuid` generado para el snapshot.
   fn save_snapshot(&self, flow_id: &Uuid, seq: i64, state_ptr: &str, metadata: serde_json::Value) -> Result<uuid::Uuid> {
@@ -259,92 +639,153 @@ impl FlowRepository for InMemoryFlowRepository {
     self.lock(&self.snapshots)?.insert(id, meta);
     Ok(id)
   }
-  /// Crea una nueva rama en memoria: genera `new_id`, copia todos los
-  /// `FlowData` del padre con `cursor <= parent_cursor` y añade un
-  /// registro `BranchCreated` al final. Devuelve `new_id`.
+  fn save_snapshot_state(&self, flow_id: &Uuid, cursor: i64, state: &[u8], metadata: serde_json::Value) -> Result<Uuid> {
+    let key = self.snapshot_store.save(state)?;
+    self.save_snapshot(flow_id, cursor, &key, metadata)
+  }
+  fn compact_snapshots(&self, flow_id: &Uuid) -> Result<usize> {
+    Ok(self.compact_flow_snapshots(flow_id)?.0)
+  }
+  /// Compacta cada flow y después borra los blobs huérfanos del store.
+  fn gc_snapshots(&self) -> Result<SnapshotGcReport> {
+    let mut report = SnapshotGcReport::default();
+    for flow_id in self.list_flow_ids()? {
+      match self.compact_flow_snapshots(&flow_id) {
+        Ok((snaps, blobs)) => {
+          report.snapshots_removed += snaps;
+          report.blobs_removed += blobs;
+        }
+        // borrado entre el listado y la compactación
+        Err(FlowError::NotFound(_)) => {}
+        Err(e) => return Err(e),
+      }
+    }
+    let blobs = self.snapshot_store.list()?;
+    let mut referenced: HashSet<String> = self.lock(&self.snapshots)?.values().map(|s| s.state_ptr.clone()).collect();
+    referenced.extend(self.lock(&self.truncations)?.iter().flat_map(|t| &t.snapshots).map(|s| s.state_ptr.clone()));
+    for key in orphan_blob_keys(&blobs, &referenced, &self.retention, Utc::now()) {
+      self.snapshot_store.delete(&key)?;
+      report.blobs_removed += 1;
+    }
+    Ok(report)
+  }
+  /// Crea una nueva rama en memoria: genera `new_id`, copia los snapshots
+  /// del padre con `cursor <= parent_cursor` y devuelve `new_id`.
   ///
   /// Comportamiento:
-  /// - Si el padre no existe, se crea una metadata nueva basada en los
-  ///   parámetros provistos.
-  /// - Los pasos copiados reciben nuevos `id` y `flow_id` para mantener la
-  ///   independencia de la rama hija.
+  /// - Si el padre no existe devuelve `NotFound`.
+  /// - La rama hereda el status del padre, se llama `<padre>_branch` y usa la
+  ///   `metadata` provista (igual que la implementación SQL).
+  /// - No se copian pasos: `read_data` los lee del padre hasta `parent_cursor`.
+  ///   Sí se copian las filas de snapshot (el blob se comparte).
   fn create_branch(&self, parent_flow_id: &Uuid, parent_cursor: i64, metadata: serde_json::Value) -> Result<Uuid> {
     let new_id = Uuid::new_v4();
-    // clonar metadata del padre si existe
-    let parent_meta = {
-      let flows = self.lock(&self.flows)?;
-      flows.get(parent_flow_id).cloned()
-    };
-    let meta = if let Some(mut pm) = parent_meta {
-      pm.id = new_id;
-      pm.parent_flow_id = Some(*parent_flow_id);
-      pm.parent_cursor = Some(parent_cursor);
-      pm.current_cursor = parent_cursor;
-      pm.current_version = 0;
-      if metadata != serde_json::json!({}) {
-        pm.metadata = metadata.clone();
-      }
-      pm
-    } else {
-      FlowMeta { id: new_id,
-                 name: Some(format!("branch-of-{}", parent_flow_id)),
-                 status: Some("queued".into()),
-                 created_by: None,
-                 created_at: Utc::now(),
-                 current_cursor: parent_cursor,
-                 current_version: 0,
-                 parent_flow_id: Some(*parent_flow_id),
-                 parent_cursor: Some(parent_cursor),
-                 metadata }
-    };
+    let parent = self.get_flow_meta(parent_flow_id)?;
+    let meta = FlowMeta { id: new_id,
+                          name: parent.name.map(|n| format!("{}_branch", n)).or(Some("branch".into())),
+                          status: parent.status,
+                          created_by: None,
+                          created_at: Utc::now(),
+                          current_cursor: parent_cursor,
+                          current_version: 0,
+                          parent_flow_id: Some(*parent_flow_id),
+                          parent_cursor: Some(parent_cursor),
+                          metadata,
+                          deleted_at: None };
     // insertar metadata de la nueva rama
-    self.lock(&self.flows)?.insert(new_id, meta.clone());
-    // copiar pasos del padre hasta `parent_cursor` (inclusive)
-    let mut steps = self.lock(&self.steps)?;
-    if let Some(parent_steps) = steps.get(parent_flow_id).cloned() {
-      let copied: Vec<FlowData> = parent_steps.into_iter()
-                                              .filter(|d| d.cursor <= parent_cursor)
-                                              .map(|mut d| {
-                                                d.id = Uuid::new_v4();
-                                                d.flow_id = new_id;
-                                                d
-                                              })
-                                              .collect();
-      let entry = steps.entry(new_id).or_default();
-      entry.extend(copied);
-    } else {
-      // No hay pasos previos; esto es válido (branch vacío)
-      println!("[stub] no se encontraron pasos del padre para {}", parent_flow_id);
-    }
-    // Crear un registro `BranchCreated` como siguiente cursor para la
-    // nueva rama (indica creación y referencia al padre).
-    let st = FlowData { id: Uuid::new_v4(),
-                        flow_id: new_id,
-                        cursor: parent_cursor + 1,
-                        key: "BranchCreated".into(),
-                        payload: serde_json::json!({"parent": parent_flow_id}),
-                        metadata: serde_json::json!({}),
-                        command_id: None,
-                        created_at: Utc::now() };
-    // Append the BranchCreated record for the new branch.
-    steps.entry(new_id).or_default().push(st);
+    self.lock(&self.flows)?.insert(new_id, meta);
+    // copiar snapshots del padre hasta `parent_cursor`
+    let mut snaps = self.lock(&self.snapshots)?;
+    let copied_snaps: Vec<SnapshotMeta> =
+      snaps.values().filter(|s| s.flow_id == *parent_flow_id && s.cursor <= parent_cursor).cloned().collect();
+    for mut s in copied_snaps {
+      s.id = Uuid::new_v4();
+      s.flow_id = new_id;
+      snaps.insert(s.id, s);
+    }
     Ok(new_id)
   }
-  /// Lock ligero: en memoria se simula comprobando versión; no hay
-  /// bloqueo distribuido real.
-  fn lock_for_update(&self, _flow_id: &Uuid, _expected_version: i64) -> Result<bool> {
-    // In-memory lock: check that the flow exists and version matches expected.
-    let flows = self.lock(&self.flows)?;
-    if let Some(meta) = flows.get(_flow_id) {
-      Ok(meta.current_version == _expected_version)
-    } else {
-      Err(FlowError::NotFound(format!("flow {}", _flow_id)))
+  /// Lock exclusivo en memoria: un set de flows bloqueados protegido por
+  /// mutex + condvar. Sólo es exclusivo dentro del proceso.
+  fn lock_for_update(&self,
+                     flow_id: &Uuid,
+                     expected_version: i64,
+                     timeout: std::time::Duration)
+                     -> Result<Option<FlowLockGuard>> {
+    if !self.lock(&self.flows)?.contains_key(flow_id) {
+      return Err(FlowError::NotFound(format!("flow {}", flow_id)));
     }
+    let deadline = Instant::now() + timeout;
+    let (held, cvar) = &*self.locks;
+    let mut set = self.lock(held)?;
+    while set.contains(flow_id) {
+      let now = Instant::now();
+      if now >= deadline {
+        return Err(FlowError::Conflict(format!("timeout esperando lock del flow {}", flow_id)));
+      }
+      set = cvar.wait_timeout(set, deadline - now).map_err(|e| FlowError::Storage(format!("mutex poisoned: {:?}", e)))?.0;
+    }
+    set.insert(*flow_id);
+    drop(set);
+    let locks = Arc::clone(&self.locks);
+    let id = *flow_id;
+    let guard = FlowLockGuard::new(id, move || {
+      let (held, cvar) = &*locks;
+      held.lock().unwrap_or_else(|e| e.into_inner()).remove(&id);
+      cvar.notify_all();
+    });
+    // Comprobar la versión ya con el lock tomado; si no coincide el guard se
+    // suelta aquí y libera el lock.
+    let current = self.lock(&self.flows)?.get(flow_id).map(|m| m.current_version);
+    Ok(if current == Some(expected_version) { Some(guard) } else { None })
   }
-  /// Claim de trabajo: en memoria no hay implementación de cola
-  /// persistente, por lo que siempre devuelve `None`.
-  fn claim_work(&self, _worker_id: &str) -> Result<Option<WorkItem>> {
-    Ok(None)
+  /// Encola el flow si no estaba ya en la cola.
+  fn enqueue_work(&self, flow_id: &Uuid) -> Result<()> {
+    if !self.lock(&self.flows)?.contains_key(flow_id) {
+      return Err(FlowError::NotFound(format!("flow {}", flow_id)));
+    }
+    let mut work = self.lock(&self.work)?;
+    if !work.iter().any(|w| w.flow_id == *flow_id) {
+      work.push(QueuedWork { flow_id: *flow_id, worker_id: None, lease_expires_at: None, attempts: 0 });
+    }
+    Ok(())
+  }
+  /// Claim de trabajo: toma el primer item (orden de encolado) sin lease
+  /// vigente. El mutex de la cola serializa los claims concurrentes.
+  fn claim_work(&self, worker_id: &str) -> Result<Option<WorkItem>> {
+    let now = Utc::now();
+    let (flow_id, lease_expires_at, attempts) = {
+      let mut work = self.lock(&self.work)?;
+      let Some(entry) = work.iter_mut().find(|w| w.lease_expires_at.is_none_or(|exp| exp <= now)) else {
+        return Ok(None);
+      };
+      let expires = now + self.lease_duration;
+      entry.worker_id = Some(worker_id.to_string());
+      entry.lease_expires_at = Some(expires);
+      entry.attempts += 1;
+      (entry.flow_id, expires, entry.attempts)
+    };
+    let last_cursor = self.lock(&self.flows)?.get(&flow_id).map(|m| m.current_cursor).unwrap_or(0);
+    let snapshot_ptr = self.load_latest_snapshot(&flow_id)?.map(|s| s.state_ptr);
+    Ok(Some(WorkItem { flow_id, last_cursor, snapshot_ptr, worker_id: worker_id.to_string(), lease_expires_at, attempts }))
+  }
+  /// Renueva el lease si `worker_id` sigue siendo el dueño del item.
+  fn heartbeat_work(&self, flow_id: &Uuid, worker_id: &str) -> Result<bool> {
+    let mut work = self.lock(&self.work)?;
+    match work.iter_mut().find(|w| w.flow_id == *flow_id && w.worker_id.as_deref() == Some(worker_id)) {
+      Some(entry) => {
+        entry.lease_expires_at = Some(Utc::now() + self.lease_duration);
+        Ok(true)
+      }
+      None => Ok(false),
+    }
+  }
+  /// Retira el item de la cola si `worker_id` es su dueño.
+  fn complete_work(&self, flow_id: &Uuid, worker_id: &str) -> Result<bool> {
+    let mut work = self.lock(&self.work)?;
+    let before = work.len();
+    work.retain(|w| !(w.flow_id == *flow_id && w.worker_id.as_deref() == Some(worker_id)));
+    Ok(work.len() != before)
   }
   /// Devuelve una copia de las tablas en memoria para debugging.
   fn dump_tables_for_debug(&self) -> Result<(Vec<FlowMeta>, Vec<FlowData>)> {
@@ -354,7 +795,10 @@ impl FlowRepository for InMemoryFlowRepository {
     flows_out.sort_by_key(|f| f.created_at);
     let steps = self.lock(&self.steps)?;
     let mut data_out: Vec<FlowData> = Vec::new();
-    for v in steps.values() {
+    for (flow_id, v) in steps.iter() {
+      if !flows.contains_key(flow_id) {
+        continue;
+      }
       for d in v.iter() {
         data_out.push(d.clone());
       }
@@ -366,6 +810,16 @@ impl FlowRepository for InMemoryFlowRepository {
     let flows = self.lock(&self.flows)?;
     Ok(flows.contains_key(flow_id))
   }
+  /// Hijas directas de `flow_id`, ordenadas como en la implementación SQL.
+  fn list_children(&self, flow_id: &Uuid) -> Result<Vec<FlowMeta>> {
+    let flows = self.lock(&self.flows)?;
+    if !flows.contains_key(flow_id) {
+      return Err(FlowError::NotFound(format!("flow {}", flow_id)));
+    }
+    let mut children: Vec<FlowMeta> = flows.values().filter(|m| m.parent_flow_id == Some(*flow_id)).cloned().collect();
+    children.sort_by_key(|m| (m.parent_cursor, m.created_at, m.id));
+    Ok(children)
+  }
   /// Devuelve el status actual del flow (si existe).
   fn get_flow_status(&self, flow_id: &Uuid) -> Result<Option<String>> {
     let flows = self.lock(&self.flows)?;
@@ -380,125 +834,319 @@ impl FlowRepository for InMemoryFlowRepository {
   }
   /// Cuenta cuántos pasos tiene un flow. -1 si no existe.
   ///
-  /// Sólo cuenta pasos con `cursor <= current_cursor` del flujo para
-  /// evitar contabilizar registros auxiliares creados como helper.
+  /// Sólo cuenta pasos con `cursor <= current_cursor` del flujo, incluidos
+  /// los heredados (mismo criterio que la implementación SQL).
   fn count_steps(&self, flow_id: &Uuid) -> Result<i64> {
     let flows = self.lock(&self.flows)?;
-    if !flows.contains_key(flow_id) {
+    let Some(current_cursor) = flows.get(flow_id).map(|m| m.current_cursor) else {
       return Ok(-1);
-    }
-    // Only count steps up to the flow's current_cursor. This ensures
-    // that helper records (e.g. BranchCreated with cursor = parent_cursor+1)
-    // don't get counted as visible steps for the flow.
-    let current_cursor = flows.get(flow_id).map(|m| m.current_cursor).unwrap_or(0);
+    };
+    let segments = self.segments(&flows, flow_id)?;
     let steps = self.lock(&self.steps)?;
-    let cnt = steps.get(flow_id).map(|v| v.iter().filter(|d| d.cursor <= current_cursor).count() as i64).unwrap_or(0);
-    Ok(cnt)
+    Ok(stitch(&steps, flow_id, &segments, 0).iter().filter(|d| d.cursor <= current_cursor).count() as i64)
   }
-  /// Lista todos los UUIDs de los flujos en memoria.
+  /// Lista todos los UUIDs de los flujos en memoria (sin los borrados).
   fn list_flow_ids(&self) -> Result<Vec<Uuid>> {
     let flows = self.lock(&self.flows)?;
     Ok(flows.keys().cloned().collect())
   }
-  /// Elimina una rama pero NO borra sus hijos.
+  fn query_flows(&self, query: &FlowQuery) -> Result<FlowPage> {
+    query.validate()?;
+    let flows = self.lock(&self.flows)?;
+    Ok(query.page(flows.values().cloned()))
+  }
+  /// Borra una rama de forma lógica pero NO borra sus hijos.
   ///
-  /// Nuevo comportamiento: cuando se elimina una rama/flow, los hijos que
-  /// tenían `parent_flow_id` apuntando a ésta pasan a quedar huérfanos;
-  /// es decir, se actualiza su `parent_flow_id` y `parent_cursor` a `None`
-  /// y se dejan como ramas principales. Sólo se eliminan la metadata,
-  /// los pasos y snapshots del flow solicitado.
+  /// El flow pasa de `flows` a `deleted_flows` con `deleted_at`; sus pasos,
+  /// snapshots y comandos aplicados se conservan para `undelete`. Los hijos
+  /// no cambian: siguen leyendo a través de esta rama (ver `chain_of`) hasta
+  /// que `purge_deleted` les copia lo que heredaban.
   fn delete_branch(&self, flow_id: &Uuid) -> Result<()> {
-    // verify exists
-    {
-      let flows = self.lock(&self.flows)?;
-      if !flows.contains_key(flow_id) {
-        return Err(FlowError::NotFound(format!("flow {}", flow_id)));
+    let mut flows = self.lock(&self.flows)?;
+    let mut meta = flows.remove(flow_id).ok_or(FlowError::NotFound(format!("flow {}", flow_id)))?;
+    meta.deleted_at = Some(Utc::now());
+    self.lock(&self.deleted_flows)?.insert(*flow_id, meta);
+    self.lock(&self.work)?.retain(|w| w.flow_id != *flow_id);
+    Ok(())
+  }
+  /// Trunca el flow a partir de un cursor dado (inclusive).
+  ///
+  /// Comportamiento:
+  /// - Se mantienen los pasos con `cursor < from_cursor`; el resto pasa a un
+  ///   `Truncation` y sus `command_id` dejan de deduplicar.
+  /// - Los snapshots con `cursor >= from_cursor` pasan al mismo `Truncation`.
+  /// - En una rama, si `from_cursor <= parent_cursor`, el tramo heredado se
+  ///   recorta bajando `parent_cursor` a `from_cursor - 1`.
+  /// - `current_cursor` pasa a ser el mayor cursor restante (o 0).
+  /// - Las ramas descendientes vivas cuyo camino hasta este flow tiene todos
+  ///   los `parent_cursor` >= `from_cursor` pasan a `deleted_flows`.
+  fn delete_from_step(&self, flow_id: &Uuid, from_cursor: i64) -> Result<()> {
+    let now = Utc::now();
+    let mut flows = self.lock(&self.flows)?;
+    let mut deleted = self.lock(&self.deleted_flows)?;
+    let Some(meta) = flows.get(flow_id).cloned() else {
+      return Err(FlowError::NotFound(format!("flow {}", flow_id)));
+    };
+    // descendientes que leen desde from_cursor: cada tramo heredado llega
+    // hasta el menor parent_cursor del camino
+    let mut affected = Vec::new();
+    let mut seen = HashSet::from([*flow_id]);
+    let mut frontier = vec![*flow_id];
+    while let Some(parent) = frontier.pop() {
+      for child in flows.values().chain(deleted.values()).filter(|m| {
+                                                           m.parent_flow_id == Some(parent)
+                                                           && m.parent_cursor.is_some_and(|pc| pc >= from_cursor)
+                                                         })
+      {
+        if seen.insert(child.id) {
+          frontier.push(child.id);
+          if child.deleted_at.is_none() {
+            affected.push(child.id);
+          }
+        }
       }
     }
-    // remove the flow's metadata, steps and snapshots
+    let mut steps = self.lock(&self.steps)?;
+    let (kept, truncated): (Vec<FlowData>, Vec<FlowData>) =
+      steps.remove(flow_id).unwrap_or_default().into_iter().partition(|d| d.cursor < from_cursor);
+    steps.insert(*flow_id, kept);
+    let commands = {
+      let mut command_versions = self.lock(&self.command_versions)?;
+      truncated.iter()
+               .filter_map(|d| d.command_id)
+               .filter_map(|cmd| command_versions.remove(&(*flow_id, cmd)).map(|v| (cmd, v)))
+               .collect()
+    };
+    let snapshots = {
+      let mut snaps = self.lock(&self.snapshots)?;
+      let ids: Vec<Uuid> =
+        snaps.values().filter(|s| s.flow_id == *flow_id && s.cursor >= from_cursor).map(|s| s.id).collect();
+      ids.iter().filter_map(|id| snaps.remove(id)).collect()
+    };
+    let parent_cursor = meta.parent_cursor.filter(|pc| meta.parent_flow_id.is_some() && *pc >= from_cursor);
+    if let (Some(_), Some(meta)) = (parent_cursor, flows.get_mut(flow_id)) {
+      meta.parent_cursor = Some(from_cursor - 1);
+    }
+    for id in &affected {
+      if let Some(mut child) = flows.remove(id) {
+        child.deleted_at = Some(now);
+        deleted.insert(*id, child);
+      }
+    }
+    let segments = segments_of(&flows, &deleted, flow_id)?;
+    let new_cursor = stitch(&steps, flow_id, &segments, 0).last().map(|d| d.cursor).unwrap_or(0);
+    if let Some(meta) = flows.get_mut(flow_id) {
+      meta.current_cursor = new_cursor;
+    }
+    self.lock(&self.work)?.retain(|w| !affected.contains(&w.flow_id));
+    self.lock(&self.truncations)?.push(Truncation { flow_id: *flow_id,
+                                                    at: now,
+                                                    from_cursor,
+                                                    version: meta.current_version,
+                                                    parent_cursor,
+                                                    steps: truncated,
+                                                    snapshots,
+                                                    commands,
+                                                    flows: affected });
+    Ok(())
+  }
+  fn undelete_steps(&self, flow_id: &Uuid) -> Result<()> {
     let mut flows = self.lock(&self.flows)?;
+    let mut deleted = self.lock(&self.deleted_flows)?;
+    let Some(version) = flows.get(flow_id).map(|m| m.current_version) else {
+      return Err(FlowError::NotFound(format!("flow {}", flow_id)));
+    };
     let mut steps = self.lock(&self.steps)?;
-    let mut snaps = self.lock(&self.snapshots)?;
-    flows.remove(flow_id);
-    steps.remove(flow_id);
-    let keys: Vec<Uuid> = snaps.iter().filter(|(_, s)| s.flow_id == *flow_id).map(|(k, _)| *k).collect();
-    for k in keys {
-      snaps.remove(&k);
-    }
-    // Hijos huérfanos: encontrar flujos cuyo `parent_flow_id` == flow_id y
-    // actualizar sus campos para que no apunten al padre borrado.
-    for (_, meta) in flows.iter_mut() {
-      if let Some(parent) = meta.parent_flow_id {
-        if parent == *flow_id {
-          meta.parent_flow_id = None;
-          meta.parent_cursor = None;
+    let mut truncations = self.lock(&self.truncations)?;
+    let pos = truncations.iter()
+                         .rposition(|t| t.flow_id == *flow_id)
+                         .ok_or_else(|| FlowError::NotFound(format!("pasos truncados del flow {}", flow_id)))?;
+    if truncations[pos].version != version {
+      return Err(FlowError::Conflict(format!("el flow {} persistió pasos después de truncar", flow_id)));
+    }
+    let truncation = truncations.remove(pos);
+    let list = steps.entry(*flow_id).or_default();
+    list.extend(truncation.steps);
+    list.sort_by_key(|d| d.cursor);
+    self.lock(&self.snapshots)?.extend(truncation.snapshots.into_iter().map(|s| (s.id, s)));
+    self.lock(&self.command_versions)?.extend(truncation.commands.into_iter().map(|(cmd, v)| ((*flow_id, cmd), v)));
+    if let (Some(pc), Some(meta)) = (truncation.parent_cursor, flows.get_mut(flow_id)) {
+      meta.parent_cursor = Some(pc);
+    }
+    for id in &truncation.flows {
+      let Some(child) = deleted.get(id) else {
+        continue;
+      };
+      if !reads_truncated(&flows, &deleted, &truncations, child)? {
+        if let Some(mut child) = deleted.remove(id) {
+          child.deleted_at = None;
+          flows.insert(*id, child);
         }
       }
     }
+    let segments = segments_of(&flows, &deleted, flow_id)?;
+    let new_cursor = stitch(&steps, flow_id, &segments, 0).last().map(|d| d.cursor).unwrap_or(0);
+    if let Some(meta) = flows.get_mut(flow_id) {
+      meta.current_cursor = new_cursor;
+    }
     Ok(())
   }
-  /// Elimina todos los pasos y subramas a partir de un cursor dado
-  /// (inclusive) en el flow `flow_id`.
-  ///
-  /// Comportamiento:
-  /// - Se mantienen los pasos con `cursor < from_cursor`.
-  /// - Se eliminan recursivamente las ramas hijas cuyo `parent_cursor` sea >=
-  ///   `from_cursor`.
-  fn delete_from_step(&self, flow_id: &Uuid, from_cursor: i64) -> Result<()> {
-    // check exists
-    let flows = self.lock(&self.flows)?;
-    let _meta = flows.get(flow_id).cloned().ok_or(FlowError::NotFound(format!("flow {}", flow_id)))?;
-    drop(flows);
-    // delete steps with cursor >= from_cursor
+  fn undelete(&self, flow_id: &Uuid) -> Result<()> {
+    let mut flows = self.lock(&self.flows)?;
+    let mut deleted = self.lock(&self.deleted_flows)?;
+    if flows.contains_key(flow_id) {
+      return Ok(());
+    }
+    let meta = deleted.get(flow_id).ok_or(FlowError::NotFound(format!("flow {}", flow_id)))?;
+    if reads_truncated(&flows, &deleted, &self.lock(&self.truncations)?, meta)? {
+      return Err(FlowError::Conflict(format!("el historial del flow {} pasa por pasos truncados", flow_id)));
+    }
+    if let Some(mut meta) = deleted.remove(flow_id) {
+      meta.deleted_at = None;
+      flows.insert(*flow_id, meta);
+    }
+    Ok(())
+  }
+  fn list_deleted_flows(&self) -> Result<Vec<FlowMeta>> {
+    let mut out: Vec<FlowMeta> = self.lock(&self.deleted_flows)?.values().cloned().collect();
+    out.sort_by_key(|m| (m.deleted_at, m.id));
+    Ok(out)
+  }
+  /// Los pasos truncados de un flow purgado se eliminan con él y no cuentan
+  /// en `PurgeReport::records`.
+  fn purge_deleted(&self, retention: std::time::Duration) -> Result<PurgeReport> {
+    let cutoff = purge_cutoff(retention, Utc::now());
+    let mut flows = self.lock(&self.flows)?;
+    let mut deleted = self.lock(&self.deleted_flows)?;
     let mut steps = self.lock(&self.steps)?;
-    if let Some(vec) = steps.get_mut(flow_id) {
-      vec.retain(|d| d.cursor < from_cursor);
+    let mut truncations = self.lock(&self.truncations)?;
+    // el padre de una rama con un truncado pendiente que recortó su tramo
+    // heredado se conserva: undelete_steps volvería a leerlo
+    let pinned: HashSet<Uuid> = truncations.iter()
+                                           .filter(|t| t.at > cutoff && t.parent_cursor.is_some())
+                                           .filter_map(|t| flows.get(&t.flow_id).or_else(|| deleted.get(&t.flow_id)))
+                                           .filter_map(|m| m.parent_flow_id)
+                                           .collect();
+    let purged: HashSet<Uuid> = deleted.values()
+                                       .filter(|m| m.deleted_at.is_some_and(|at| at <= cutoff) && !pinned.contains(&m.id))
+                                       .map(|m| m.id)
+                                       .collect();
+    // las hijas que quedan reciben copias de lo que heredaban a través de
+    // un flow purgado y quedan huérfanas
+    let mut copies = Vec::new();
+    for child in flows.values().chain(deleted.values()).filter(|m| {
+                                                         !purged.contains(&m.id)
+                                                         && m.parent_flow_id.is_some_and(|p| purged.contains(&p))
+                                                       })
+    {
+      let segments = data_segments(child, &chain_of(&flows, &deleted, child)?);
+      let fork = child.parent_cursor.unwrap_or(0);
+      let inherited: Vec<FlowData> =
+        stitch(&steps, &child.id, &segments[1..], 0).into_iter()
+                                                    .filter(|d| d.cursor <= fork)
+                                                    .map(|d| FlowData { id: Uuid::new_v4(), ..d })
+                                                    .collect();
+      copies.push((child.id, inherited));
     }
-    drop(steps);
-    // eliminar subramas cuyo `parent_cursor` >= from_cursor recursivamente
-    let mut to_delete: Vec<Uuid> = Vec::new();
-    let flows = self.lock(&self.flows)?;
-    for (id, fm) in flows.iter() {
-      if let Some(p) = fm.parent_flow_id {
-        if p == *flow_id {
-          if let Some(pc) = fm.parent_cursor {
-            if pc >= from_cursor {
-              to_delete.push(*id);
-            }
-          }
-        }
+    for (child_id, inherited) in copies {
+      let list = steps.entry(child_id).or_default();
+      list.extend(inherited);
+      list.sort_by_key(|d| d.cursor);
+      if let Some(child) = flows.get_mut(&child_id).or_else(|| deleted.get_mut(&child_id)) {
+        child.parent_flow_id = None;
+        child.parent_cursor = None;
       }
     }
+    deleted.retain(|id, _| !purged.contains(id));
+    steps.retain(|id, _| !purged.contains(id));
+    truncations.retain(|t| !purged.contains(&t.flow_id));
+    let (expired, pending): (Vec<Truncation>, Vec<Truncation>) = truncations.drain(..).partition(|t| t.at <= cutoff);
+    *truncations = pending;
+    drop(truncations);
+    drop(steps);
+    drop(deleted);
     drop(flows);
-    for id in to_delete.iter() {
-      // reuse delete_branch to remove subtrees
-      self.delete_branch(id)?;
+    self.lock(&self.snapshots)?.retain(|_, s| !purged.contains(&s.flow_id));
+    self.lock(&self.command_versions)?.retain(|(fid, _), _| !purged.contains(fid));
+    self.lock(&self.work)?.retain(|w| !purged.contains(&w.flow_id));
+    Ok(PurgeReport { flows: purged.len(),
+                     records: expired.iter().map(|t| t.steps.len()).sum(),
+                     snapshots: expired.iter().map(|t| t.snapshots.len()).sum() })
+  }
+  /// Busca cada registro por id en todos los flows: un registro leído desde
+  /// una rama puede estar guardado en un antepasado.
+  fn rewrite_data(&self, rows: &[FlowData]) -> Result<usize> {
+    let mut steps = self.lock(&self.steps)?;
+    let mut targets = Vec::with_capacity(rows.len());
+    for row in rows {
+      let target = steps.iter()
+                        .find_map(|(fid, v)| v.iter().position(|d| d.id == row.id).map(|pos| (*fid, pos)))
+                        .ok_or_else(|| FlowError::NotFound(format!("flow_data {}", row.id)))?;
+      targets.push((row, target));
     }
-    Ok(())
+    for (row, (fid, pos)) in &targets {
+      if let Some(stored) = steps.get_mut(fid).and_then(|v| v.get_mut(*pos)) {
+        stored.payload = row.payload.clone();
+        stored.metadata = row.metadata.clone();
+      }
+    }
+    Ok(targets.len())
   }
 }
 impl SnapshotStore for InMemoryFlowRepository {
-  /// Guarda bytes serializados y devuelve una key representativa.
-  fn save(&self, _state: &[u8]) -> Result<String> {
-    Ok("inmem".into())
+  /// Delegan en el `snapshot_store` configurado.
+  fn save(&self, state: &[u8]) -> Result<String> {
+    self.snapshot_store.save(state)
   }
-  /// Carga bytes desde la key (no persistido en este stub).
-  fn load(&self, _key: &str) -> Result<Vec<u8>> {
-    Ok(vec![])
+  fn load(&self, key: &str) -> Result<Vec<u8>> {
+    self.snapshot_store.load(key)
+  }
+  fn delete(&self, key: &str) -> Result<()> {
+    self.snapshot_store.delete(key)
+  }
+  fn list(&self) -> Result<Vec<StoredBlob>> {
+    self.snapshot_store.list()
   }
 }
 impl ArtifactStore for InMemoryFlowRepository {
-  /// Almacena un blob y devuelve una key simbólica.
-  fn put(&self, _blob: &[u8]) -> Result<String> {
-    Ok("inmem-artifact".into())
+  fn put(&self, blob: &[u8]) -> Result<String> {
+    let key = artifact_key(blob);
+    let mut artifacts = self.lock(&self.artifacts)?;
+    artifacts.entry(key.clone()).and_modify(|a| a.last_put = Utc::now()).or_insert_with(|| StoredArtifact { blob:
+                                                                                                              blob.to_vec(),
+                                                                                                            ref_count: 0,
+                                                                                                            last_put:
+                                                                                                              Utc::now() });
+    Ok(key)
   }
-  /// Recupera blob por key (no persistido en este stub).
-  fn get(&self, _key: &str) -> Result<Vec<u8>> {
-    Ok(vec![])
+  fn get(&self, key: &str) -> Result<Vec<u8>> {
+    let blob = self.lock(&self.artifacts)?
+                   .get(key)
+                   .map(|a| a.blob.clone())
+                   .ok_or_else(|| FlowError::NotFound(format!("artifact {}", key)))?;
+    verify_artifact(key, &blob)?;
+    Ok(blob)
   }
-  /// Copia el blob si se necesita aislamiento. En este stub retorna la misma
-  /// key.
   fn copy_if_needed(&self, src_key: &str) -> Result<String> {
+    let mut artifacts = self.lock(&self.artifacts)?;
+    let artifact = artifacts.get_mut(src_key).ok_or_else(|| FlowError::NotFound(format!("artifact {}", src_key)))?;
+    artifact.ref_count += 1;
     Ok(src_key.to_string())
   }
+  fn release(&self, key: &str) -> Result<()> {
+    let mut artifacts = self.lock(&self.artifacts)?;
+    let artifact = artifacts.get_mut(key).ok_or_else(|| FlowError::NotFound(format!("artifact {}", key)))?;
+    artifact.ref_count = (artifact.ref_count - 1).max(0);
+    Ok(())
+  }
+  fn gc_artifacts(&self, grace: std::time::Duration) -> Result<usize> {
+    let mut referenced: HashSet<String> = self.lock(&self.steps)?.values().flatten().flat_map(artifact_refs).collect();
+    referenced.extend(self.lock(&self.truncations)?.iter().flat_map(|t| &t.steps).flat_map(artifact_refs));
+    let cutoff =
+      Duration::from_std(grace).ok().and_then(|g| Utc::now().checked_sub_signed(g)).unwrap_or(DateTime::<Utc>::MIN_UTC);
+    let mut artifacts = self.lock(&self.artifacts)?;
+    let before = artifacts.len();
+    artifacts.retain(|key, a| a.ref_count > 0 || a.last_put > cutoff || referenced.contains(key));
+    Ok(before - artifacts.len())
+  }
 }
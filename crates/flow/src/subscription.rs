@@ -0,0 +1,128 @@
+// Archivo: subscription.rs
+// Propósito: feed de cambios de `flow_data` (`FlowRepository::subscribe`).
+//
+// - `ChangeFeed`: difusión (broadcast) en proceso de los `FlowData` persistidos
+//   hacia los suscriptores de cada flow. Los backends publican tras confirmar
+//   la escritura (o al recibir un `NOTIFY` de Postgres).
+// - `FlowSubscription`: iterador bloqueante que entrega primero los registros
+//   existentes con `cursor > from_cursor` y después los nuevos, en orden de
+//   cursor y sin duplicados.
+use crate::domain::FlowData;
+use std::collections::VecDeque;
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender, TryRecvError};
+use std::sync::Mutex;
+use std::time::Duration;
+use uuid::Uuid;
+/// Difusión en proceso de los `FlowData` persistidos, por flow.
+///
+/// Cada suscriptor recibe su propio canal; los canales cuyo receptor se ha
+/// destruido se descartan en la siguiente publicación.
+#[derive(Debug, Default)]
+pub struct ChangeFeed {
+  subscribers: Mutex<Vec<(Uuid, Sender<FlowData>)>>,
+}
+impl ChangeFeed {
+  pub fn new() -> Self {
+    Self::default()
+  }
+  /// Registra un suscriptor para `flow_id` y devuelve su receptor.
+  pub fn register(&self, flow_id: Uuid) -> Receiver<FlowData> {
+    let (tx, rx) = mpsc::channel();
+    self.subscribers.lock().unwrap_or_else(|e| e.into_inner()).push((flow_id, tx));
+    rx
+  }
+  /// Entrega `data` a los suscriptores de su flow.
+  pub fn publish(&self, data: &FlowData) {
+    let mut subs = self.subscribers.lock().unwrap_or_else(|e| e.into_inner());
+    subs.retain(|(flow_id, tx)| *flow_id != data.flow_id || tx.send(data.clone()).is_ok());
+  }
+  /// Indica si `flow_id` tiene algún suscriptor registrado (vivo o
+  /// pendiente de descartar). Permite a los backends evitar lecturas cuando
+  /// nadie escucha.
+  pub fn has_subscribers(&self, flow_id: &Uuid) -> bool {
+    self.subscribers.lock().unwrap_or_else(|e| e.into_inner()).iter().any(|(id, _)| id == flow_id)
+  }
+}
+/// Suscripción a los registros de un flow devuelta por
+/// `FlowRepository::subscribe`.
+///
+/// Como `Iterator` bloquea hasta el siguiente registro y termina cuando el
+/// backend cierra el feed (p. ej. el repositorio se destruye); `recv_timeout`
+/// y `try_next` permiten esperar con límite o sin bloquear.
+#[derive(Debug)]
+pub struct FlowSubscription {
+  flow_id: Uuid,
+  last_cursor: i64,
+  backlog: VecDeque<FlowData>,
+  live: Receiver<FlowData>,
+}
+impl FlowSubscription {
+  /// Construye la suscripción a partir de los registros ya existentes
+  /// (`backlog`, leídos después de registrar `live`) y del receptor en vivo.
+  /// Los registros con `cursor <= from_cursor` o repetidos se descartan.
+  pub fn new(flow_id: Uuid, from_cursor: i64, backlog: Vec<FlowData>, live: Receiver<FlowData>) -> Self {
+    Self { flow_id, last_cursor: from_cursor, backlog: backlog.into(), live }
+  }
+  /// Flow suscrito.
+  pub fn flow_id(&self) -> Uuid {
+    self.flow_id
+  }
+  /// Cursor del último registro entregado (o `from_cursor`).
+  pub fn last_cursor(&self) -> i64 {
+    self.last_cursor
+  }
+  /// Siguiente registro disponible sin bloquear.
+  pub fn try_next(&mut self) -> Option<FlowData> {
+    loop {
+      let data = match self.backlog.pop_front() {
+        Some(d) => d,
+        None => match self.live.try_recv() {
+          Ok(d) => d,
+          Err(TryRecvError::Empty | TryRecvError::Disconnected) => return None,
+        },
+      };
+      if let Some(d) = self.accept(data) {
+        return Some(d);
+      }
+    }
+  }
+  /// Espera el siguiente registro como mucho `timeout`.
+  pub fn recv_timeout(&mut self, timeout: Duration) -> Option<FlowData> {
+    let deadline = std::time::Instant::now() + timeout;
+    loop {
+      if let Some(d) = self.try_next() {
+        return Some(d);
+      }
+      let remaining = deadline.saturating_duration_since(std::time::Instant::now());
+      match self.live.recv_timeout(remaining) {
+        Ok(data) => {
+          if let Some(d) = self.accept(data) {
+            return Some(d);
+          }
+        }
+        Err(RecvTimeoutError::Timeout | RecvTimeoutError::Disconnected) => return None,
+      }
+    }
+  }
+  fn accept(&mut self, data: FlowData) -> Option<FlowData> {
+    if data.flow_id != self.flow_id || data.cursor <= self.last_cursor {
+      return None;
+    }
+    self.last_cursor = data.cursor;
+    Some(data)
+  }
+}
+impl Iterator for FlowSubscription {
+  type Item = FlowData;
+  fn next(&mut self) -> Option<FlowData> {
+    loop {
+      if let Some(d) = self.try_next() {
+        return Some(d);
+      }
+      let data = self.live.recv().ok()?;
+      if let Some(d) = self.accept(data) {
+        return Some(d);
+      }
+    }
+  }
+}
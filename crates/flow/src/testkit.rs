@@ -0,0 +1,1031 @@
+// Archivo: testkit.rs
+// Propósito: batería de conformidad reutilizable para cualquier
+// implementación de `FlowRepository` (feature `testkit`).
+//
+// Cada `check_*` recibe un repositorio recién creado y verifica una parte
+// del contrato documentado en `flow::repository`, fallando con `panic!` (como
+// un test normal) cuando la implementación se desvía. `run_conformance_suite`
+// ejecuta todos los checks usando una factoría, de modo que un backend propio
+// puede probar su compatibilidad con una sola línea:
+//
+// ```ignore
+// #[test]
+// fn my_backend_conforms() {
+//   flow::testkit::run_conformance_suite(|| Arc::new(MyRepo::new()));
+// }
+// ```
+//
+// Los checks crean sus propios flows con ids aleatorios, por lo que también
+// pueden ejecutarse sobre una base compartida (p. ej. un Postgres de test).
+use crate::artifact_store::artifact_key;
+use crate::cherry_pick::{
+  cherry_pick, CherryPickError, CherryPickOptions, KeyConflictPolicy, SkipReason, CHERRY_PICK_METADATA_KEY,
+};
+use crate::domain::{FlowData, FlowDataMeta, FlowMeta, PersistResult, SnapshotMeta};
+use crate::errors::FlowError;
+use crate::json_predicate::JsonPredicate;
+use crate::lineage::{ancestors, common_ancestor, lineage_tree, root_of, CommonAncestor};
+use crate::query::{FlowDataQuery, FlowQuery, FlowSort, WORKFLOW_TYPE_META_KEY};
+use crate::repository::{ArtifactStore, FlowRepository};
+use crate::retention::SnapshotRetention;
+use crate::time_travel::{state_at, StateReducer};
+use base64::Engine;
+use chrono::Utc;
+use serde_json::json;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use uuid::Uuid;
+/// Timeout corto usado en los checks de `lock_for_update`.
+const LOCK_TIMEOUT: Duration = Duration::from_millis(200);
+/// Espera máxima de un registro en vivo en `check_subscribe` (los backends
+/// con `LISTEN/NOTIFY` lo entregan de forma asíncrona).
+const FEED_TIMEOUT: Duration = Duration::from_secs(5);
+/// `orphan_grace` de `retention_for_checks`.
+const ORPHAN_GRACE: Duration = Duration::from_secs(2);
+/// Retención usada con `purge_deleted`. Con fechas en segundos, lo borrado
+/// hace menos de 1 s nunca se purga, así que un check no purga lo que otro
+/// check concurrente acaba de borrar sobre la misma base.
+const PURGE_RETENTION: Duration = Duration::from_secs(2);
+/// Ejecuta todos los checks de conformidad, cada uno sobre un repositorio
+/// nuevo obtenido de `factory`.
+pub fn run_conformance_suite<F>(factory: F)
+  where F: Fn() -> Arc<dyn FlowRepository>
+{
+  check_create_and_meta(factory());
+  check_persist_and_versioning(factory());
+  check_command_idempotency(factory());
+  check_metadata_and_status(factory());
+  check_snapshots(factory());
+  check_snapshot_state(factory());
+  check_state_at(factory());
+  check_branching(factory());
+  check_delete_branch(factory());
+  check_copy_on_write_branches(factory());
+  check_lineage(factory());
+  check_cherry_pick(factory());
+  check_delete_from_step(factory());
+  check_soft_delete(factory());
+  check_query_flows(factory());
+  check_query_data(factory());
+  check_json_predicates(factory());
+  check_rewrite_data(factory());
+  check_lock_for_update(factory());
+  check_work_queue(factory());
+  check_subscribe(factory());
+}
+/// Construye un `FlowData` de prueba con key `step_state:testkit`.
+pub fn sample_data(flow_id: Uuid, cursor: i64, command_id: Option<Uuid>) -> FlowData {
+  FlowData { id: Uuid::new_v4(),
+             flow_id,
+             cursor,
+             key: "step_state:testkit".into(),
+             payload: json!({ "cursor": cursor }),
+             metadata: json!({}),
+             command_id,
+             created_at: Utc::now() }
+}
+/// Crea un flow con `n` pasos (cursores `1..=n`) y devuelve su id.
+fn flow_with_steps(repo: &dyn FlowRepository, n: i64) -> Uuid {
+  let flow_id = repo.create_flow(Some("testkit".into()), Some("running".into()), json!({})).expect("create_flow");
+  for c in 1..=n {
+    let res = repo.persist_data(&sample_data(flow_id, c, None), c - 1).expect("persist_data");
+    assert_eq!(res, PersistResult::Ok { new_version: c });
+  }
+  flow_id
+}
+fn cursors(repo: &dyn FlowRepository, flow_id: &Uuid) -> Vec<i64> {
+  repo.read_data(flow_id, 0).expect("read_data").iter().map(|d| d.cursor).collect()
+}
+fn assert_not_found<T: std::fmt::Debug>(res: crate::errors::Result<T>, what: &str) {
+  match res {
+    Err(FlowError::NotFound(_)) => {}
+    other => panic!("{}: se esperaba NotFound, obtenido {:?}", what, other),
+  }
+}
+/// `create_flow` inicializa cursor/versión a 0 y el flow es visible por
+/// `get_flow_meta`, `branch_exists`, `list_flow_ids` y `count_steps`;
+/// `create_flow_as` guarda además `created_by`.
+pub fn check_create_and_meta(repo: Arc<dyn FlowRepository>) {
+  let flow_id = repo.create_flow(Some("nombre".into()), Some("queued".into()), json!({"k": "v"})).expect("create_flow");
+  let meta = repo.get_flow_meta(&flow_id).expect("get_flow_meta");
+  assert_eq!(meta.id, flow_id);
+  assert_eq!(meta.name.as_deref(), Some("nombre"));
+  assert_eq!(meta.status.as_deref(), Some("queued"));
+  assert_eq!(meta.current_cursor, 0);
+  assert_eq!(meta.current_version, 0);
+  assert_eq!(meta.parent_flow_id, None);
+  assert_eq!(meta.metadata, json!({"k": "v"}));
+  assert!(repo.branch_exists(&flow_id).expect("branch_exists"));
+  assert!(repo.list_flow_ids().expect("list_flow_ids").contains(&flow_id));
+  assert_eq!(repo.count_steps(&flow_id).expect("count_steps"), 0);
+  assert_eq!(repo.get_flow_status(&flow_id).expect("get_flow_status").as_deref(),
+             Some("queued"));
+  let missing = Uuid::new_v4();
+  assert_not_found(repo.get_flow_meta(&missing), "get_flow_meta de flow inexistente");
+  assert!(!repo.branch_exists(&missing).expect("branch_exists inexistente"));
+  assert_eq!(repo.count_steps(&missing).expect("count_steps inexistente"), -1);
+  let owned = repo.create_flow_as(None, None, json!({}), Some("alice".into())).expect("create_flow_as");
+  assert_eq!(repo.get_flow_meta(&owned).expect("meta").created_by.as_deref(), Some("alice"));
+}
+/// `persist_data` incrementa la versión, avanza el cursor, rechaza
+/// versiones obsoletas (`Conflict`) y cursores no crecientes.
+pub fn check_persist_and_versioning(repo: Arc<dyn FlowRepository>) {
+  let flow_id = flow_with_steps(repo.as_ref(), 3);
+  assert_eq!(cursors(repo.as_ref(), &flow_id), vec![1, 2, 3]);
+  let tail: Vec<i64> = repo.read_data(&flow_id, 1).expect("read_data").iter().map(|d| d.cursor).collect();
+  assert_eq!(tail, vec![2, 3], "read_data debe ser exclusivo en from_cursor");
+  let meta = repo.get_flow_meta(&flow_id).expect("meta");
+  assert_eq!((meta.current_cursor, meta.current_version), (3, 3));
+  assert_eq!(repo.count_steps(&flow_id).expect("count_steps"), 3);
+  let stale = repo.persist_data(&sample_data(flow_id, 4, None), 1).expect("persist stale");
+  assert_eq!(stale, PersistResult::Conflict);
+  match repo.persist_data(&sample_data(flow_id, 3, None), 3) {
+    Err(FlowError::Conflict(_)) => {}
+    other => panic!("cursor repetido: se esperaba Err(Conflict), obtenido {:?}", other),
+  }
+  assert_eq!(cursors(repo.as_ref(), &flow_id),
+             vec![1, 2, 3],
+             "los rechazos no deben persistir nada");
+  assert_not_found(repo.persist_data(&sample_data(Uuid::new_v4(), 1, None), 0),
+                   "persist_data sin flow");
+}
+/// Un `command_id` repetido no se duplica y devuelve el `PersistResult`
+/// original, incluso con reintentos concurrentes o con versión obsoleta.
+pub fn check_command_idempotency(repo: Arc<dyn FlowRepository>) {
+  let flow_id = repo.create_flow(Some("idem".into()), None, json!({})).expect("create");
+  let cmd_a = Uuid::new_v4();
+  let first = repo.persist_data(&sample_data(flow_id, 1, Some(cmd_a)), 0).expect("persist a");
+  assert_eq!(first, PersistResult::Ok { new_version: 1 });
+  let replay = repo.persist_data(&sample_data(flow_id, 1, Some(cmd_a)), 0).expect("replay a");
+  assert_eq!(replay, first);
+  let second = repo.persist_data(&sample_data(flow_id, 2, Some(Uuid::new_v4())), 1).expect("persist b");
+  assert_eq!(second, PersistResult::Ok { new_version: 2 });
+  let late = repo.persist_data(&sample_data(flow_id, 3, Some(cmd_a)), 0).expect("late replay a");
+  assert_eq!(late, first, "un reintento tardío devuelve el resultado original");
+  let stale = repo.persist_data(&sample_data(flow_id, 3, Some(Uuid::new_v4())), 0).expect("stale");
+  assert_eq!(stale, PersistResult::Conflict);
+  let cmd_c = Uuid::new_v4();
+  let handles: Vec<_> = (0..4).map(|_| {
+                                let repo = repo.clone();
+                                std::thread::spawn(move || repo.persist_data(&sample_data(flow_id, 3, Some(cmd_c)), 2))
+                              })
+                              .collect();
+  for h in handles {
+    assert_eq!(h.join().expect("join").expect("persist c"),
+               PersistResult::Ok { new_version: 3 });
+  }
+  let rows = repo.read_data(&flow_id, 0).expect("read");
+  assert_eq!(rows.len(), 3);
+  assert_eq!(rows.iter().filter(|d| d.command_id == Some(cmd_a)).count(), 1);
+  assert_eq!(repo.get_flow_meta(&flow_id).expect("meta").current_version, 3);
+  let other = repo.create_flow(None, None, json!({})).expect("create other");
+  assert_eq!(repo.persist_data(&sample_data(other, 1, Some(cmd_a)), 0).expect("other flow"),
+             PersistResult::Ok { new_version: 1 },
+             "el command_id es único por flow, no global");
+}
+/// `get_meta`/`set_meta`/`del_meta` y `set_flow_status`.
+pub fn check_metadata_and_status(repo: Arc<dyn FlowRepository>) {
+  let flow_id = repo.create_flow(None, None, json!({"a": 1})).expect("create");
+  assert_eq!(repo.get_meta(&flow_id, "a").expect("get a"), json!(1));
+  assert_eq!(repo.get_meta(&flow_id, "nope").expect("get nope"), serde_json::Value::Null);
+  repo.set_meta(&flow_id, "b", json!({"x": true})).expect("set b");
+  assert_eq!(repo.get_meta(&flow_id, "b").expect("get b"), json!({"x": true}));
+  repo.del_meta(&flow_id, "a").expect("del a");
+  assert_eq!(repo.get_meta(&flow_id, "a").expect("get a"), serde_json::Value::Null);
+  assert_eq!(repo.get_flow_meta(&flow_id).expect("meta").metadata, json!({"b": {"x": true}}));
+  let meta = repo.set_flow_status(&flow_id, Some("done".into())).expect("set status");
+  assert_eq!(meta.status.as_deref(), Some("done"));
+  assert_eq!(repo.get_flow_status(&flow_id).expect("status").as_deref(), Some("done"));
+  let missing = Uuid::new_v4();
+  assert_not_found(repo.get_meta(&missing, "a"), "get_meta sin flow");
+  assert_not_found(repo.set_meta(&missing, "a", json!(1)), "set_meta sin flow");
+  assert_not_found(repo.del_meta(&missing, "a"), "del_meta sin flow");
+  assert_not_found(repo.set_flow_status(&missing, None), "set_flow_status sin flow");
+}
+/// `save_snapshot`/`load_latest_snapshot` eligen el snapshot de mayor
+/// cursor y `load_snapshot_at` el de mayor cursor que no supere el pedido;
+/// `load_snapshot` de un id desconocido es `NotFound`.
+pub fn check_snapshots(repo: Arc<dyn FlowRepository>) {
+  let flow_id = flow_with_steps(repo.as_ref(), 3);
+  assert!(repo.load_latest_snapshot(&flow_id).expect("latest vacío").is_none());
+  let s3 = repo.save_snapshot(&flow_id, 3, "ptr-3", json!({"n": 3})).expect("save 3");
+  repo.save_snapshot(&flow_id, 1, "ptr-1", json!({"n": 1})).expect("save 1");
+  let latest = repo.load_latest_snapshot(&flow_id).expect("latest").expect("hay snapshot");
+  assert_eq!(latest.id, s3);
+  assert_eq!(latest.flow_id, flow_id);
+  assert_eq!(latest.cursor, 3);
+  assert_eq!(latest.state_ptr, "ptr-3");
+  assert_eq!(latest.metadata, json!({"n": 3}));
+  let at = |c: i64| repo.load_snapshot_at(&flow_id, c).expect("load_snapshot_at").map(|s| s.cursor);
+  assert_eq!((at(0), at(1), at(2), at(3), at(9)), (None, Some(1), Some(1), Some(3), Some(3)));
+  assert!(repo.load_snapshot_at(&Uuid::new_v4(), 3).expect("flow inexistente").is_none());
+  assert_not_found(repo.load_snapshot(&Uuid::new_v4()), "load_snapshot inexistente");
+}
+/// `save_snapshot_state` guarda el blob en el store del repositorio y deja
+/// solo su key en `state_ptr`; `load_snapshot` devuelve los bytes. Los
+/// snapshots inline del formato anterior (JSON en base64) siguen leyéndose.
+pub fn check_snapshot_state(repo: Arc<dyn FlowRepository>) {
+  let flow_id = flow_with_steps(repo.as_ref(), 2);
+  let state = serde_json::to_vec(&json!({"step": 2, "items": [1, 2, 3]})).expect("json");
+  let sid = repo.save_snapshot_state(&flow_id, 2, &state, json!({"n": 2})).expect("save_snapshot_state");
+  let (bytes, meta) = repo.load_snapshot(&sid).expect("load_snapshot");
+  assert_eq!(bytes, state);
+  assert_eq!((meta.flow_id, meta.cursor), (flow_id, 2));
+  assert!(!meta.state_ptr.is_empty());
+  assert!(crate::snapshot_store::decode_inline_snapshot(&meta.state_ptr).is_none(),
+          "state_ptr debe ser una key, no el estado inline");
+  let legacy = json!({"legacy": true});
+  let inline = base64::engine::general_purpose::STANDARD.encode(legacy.to_string());
+  let old = repo.save_snapshot(&flow_id, 1, &inline, json!({})).expect("snapshot inline");
+  let (bytes, _) = repo.load_snapshot(&old).expect("load inline");
+  assert_eq!(serde_json::from_slice::<serde_json::Value>(&bytes).expect("json"), legacy);
+}
+/// Reducer de `check_state_at`: el estado es la lista de `payload.cursor`
+/// aplicados; los snapshots guardan esa lista como JSON.
+struct CursorLog;
+impl StateReducer for CursorLog {
+  type State = Vec<i64>;
+  fn initial_state(&self) -> crate::errors::Result<Vec<i64>> {
+    Ok(Vec::new())
+  }
+  fn decode_snapshot(&self, bytes: &[u8], _snapshot: &SnapshotMeta) -> crate::errors::Result<Vec<i64>> {
+    serde_json::from_slice(bytes).map_err(|e| FlowError::Other(format!("snapshot: {}", e)))
+  }
+  fn apply(&self, state: &mut Vec<i64>, data: &FlowData) -> crate::errors::Result<()> {
+    state.push(data.payload["cursor"].as_i64().unwrap_or(-1));
+    Ok(())
+  }
+}
+/// `state_at` parte del snapshot más cercano anterior o igual al cursor y
+/// reproduce solo los registros posteriores; sin snapshot parte del estado
+/// inicial. En una rama usa los snapshots y registros heredados.
+pub fn check_state_at(repo: Arc<dyn FlowRepository>) {
+  let flow_id = flow_with_steps(repo.as_ref(), 4);
+  // el contenido del snapshot difiere del replay para saber de dónde sale
+  let snap = serde_json::to_vec(&vec![10, 20]).expect("json");
+  repo.save_snapshot_state(&flow_id, 2, &snap, json!({})).expect("snapshot 2");
+  let at = |flow: &Uuid, c: i64| state_at(repo.as_ref(), flow, c, &CursorLog).expect("state_at");
+  let s0 = at(&flow_id, 0);
+  assert_eq!((s0.state, s0.replayed, s0.snapshot.is_none()), (vec![], 0, true));
+  let s1 = at(&flow_id, 1);
+  assert_eq!((s1.state, s1.replayed, s1.snapshot.is_none()), (vec![1], 1, true));
+  let s2 = at(&flow_id, 2);
+  assert_eq!((s2.state, s2.replayed), (vec![10, 20], 0));
+  let s4 = at(&flow_id, 4);
+  assert_eq!((s4.state, s4.replayed), (vec![10, 20, 3, 4], 2));
+  assert_eq!(s4.snapshot.map(|s| s.cursor), Some(2));
+  assert_eq!((s4.flow_id, s4.cursor), (flow_id, 4));
+  assert_not_found(state_at(repo.as_ref(), &flow_id, 5, &CursorLog),
+                   "state_at más allá del cursor actual");
+  assert_not_found(state_at(repo.as_ref(), &flow_id, -1, &CursorLog),
+                   "state_at con cursor negativo");
+  assert_not_found(state_at(repo.as_ref(), &Uuid::new_v4(), 0, &CursorLog), "state_at sin flow");
+  // la rama hereda el snapshot 2 y los registros 1..=3 del padre
+  let branch = repo.create_branch(&flow_id, 3, json!({})).expect("create_branch");
+  let mut step = sample_data(branch, 4, None);
+  step.payload = json!({"cursor": 40});
+  repo.persist_data(&step, 0).expect("persist rama");
+  let b4 = at(&branch, 4);
+  assert_eq!((b4.state, b4.replayed), (vec![10, 20, 3, 40], 2));
+}
+/// `create_branch` ve los datos y snapshots del padre hasta `parent_cursor`,
+/// arranca en versión 0 y evoluciona independiente del padre.
+pub fn check_branching(repo: Arc<dyn FlowRepository>) {
+  let parent = flow_with_steps(repo.as_ref(), 3);
+  repo.save_snapshot(&parent, 2, "ptr-2", json!({})).expect("snap 2");
+  repo.save_snapshot(&parent, 3, "ptr-3", json!({})).expect("snap 3");
+  let branch = repo.create_branch(&parent, 2, json!({"rama": true})).expect("create_branch");
+  let meta = repo.get_flow_meta(&branch).expect("branch meta");
+  assert_eq!(meta.parent_flow_id, Some(parent));
+  assert_eq!(meta.parent_cursor, Some(2));
+  assert_eq!((meta.current_cursor, meta.current_version), (2, 0));
+  assert_eq!(meta.metadata, json!({"rama": true}));
+  let copied = repo.read_data(&branch, 0).expect("read branch");
+  assert_eq!(copied.iter().map(|d| d.cursor).collect::<Vec<_>>(), vec![1, 2]);
+  assert!(copied.iter().all(|d| d.flow_id == branch && d.key == "step_state:testkit"));
+  assert_eq!(copied[1].payload, json!({"cursor": 2}));
+  assert_eq!(repo.count_steps(&branch).expect("count branch"), 2);
+  let snap = repo.load_latest_snapshot(&branch).expect("branch snap").expect("snapshot copiado");
+  assert_eq!((snap.cursor, snap.state_ptr.as_str()), (2, "ptr-2"));
+  let res = repo.persist_data(&sample_data(branch, 3, None), 0).expect("persist en rama");
+  assert_eq!(res, PersistResult::Ok { new_version: 1 });
+  assert_eq!(cursors(repo.as_ref(), &parent), vec![1, 2, 3], "el padre no cambia");
+  assert_eq!(repo.get_flow_meta(&parent).expect("parent meta").current_version, 3);
+  assert_not_found(repo.create_branch(&Uuid::new_v4(), 0, json!({})), "create_branch sin padre");
+}
+/// `delete_branch` borra la rama sin tocar a sus hijas, que la siguen
+/// leyendo, y `undelete` la devuelve con el mismo linaje.
+pub fn check_delete_branch(repo: Arc<dyn FlowRepository>) {
+  let root = flow_with_steps(repo.as_ref(), 2);
+  let child = repo.create_branch(&root, 2, json!({})).expect("child");
+  repo.persist_data(&sample_data(child, 3, None), 0).expect("persist child");
+  repo.save_snapshot(&child, 3, "ptr-child", json!({})).expect("snap child");
+  let grandchild = repo.create_branch(&child, 3, json!({})).expect("grandchild");
+  let ids = |flow_id: &Uuid| repo.read_data(flow_id, 0).expect("read_data").iter().map(|d| d.id).collect::<Vec<_>>();
+  let history = ids(&grandchild);
+  repo.delete_branch(&child).expect("delete_branch");
+  assert!(!repo.branch_exists(&child).expect("exists child"));
+  assert_not_found(repo.get_flow_meta(&child), "meta de rama borrada");
+  assert!(repo.read_data(&child, 0).expect("read child").is_empty());
+  assert!(repo.load_latest_snapshot(&child).expect("snap child").is_none());
+  let meta = repo.get_flow_meta(&grandchild).expect("grandchild sigue existiendo");
+  assert_eq!((meta.parent_flow_id, meta.parent_cursor), (Some(child), Some(3)));
+  assert_eq!(ids(&grandchild), history, "lee los mismos registros a través de la rama borrada");
+  assert_eq!(repo.count_steps(&grandchild).expect("count grandchild"), 3);
+  assert_eq!(cursors(repo.as_ref(), &root), vec![1, 2]);
+  assert_not_found(repo.delete_branch(&child), "delete_branch repetido");
+  repo.undelete(&child).expect("undelete");
+  assert_eq!(cursors(repo.as_ref(), &child), vec![1, 2, 3]);
+  assert_eq!(repo.load_latest_snapshot(&child).expect("snap child").map(|s| s.cursor), Some(3));
+  let chain: Vec<Uuid> = ancestors(repo.as_ref(), &grandchild).expect("ancestors").iter().map(|m| m.id).collect();
+  assert_eq!(chain, vec![child, root], "el linaje no cambia");
+  assert_eq!(repo.get_flow_meta(&grandchild).expect("grandchild").parent_flow_id, Some(child));
+  assert_eq!(ids(&grandchild), history);
+}
+/// Las ramas no guardan copias de los registros heredados: los leen de sus
+/// antepasados, `rewrite_data` los modifica donde están guardados, y
+/// `delete_branch`/`delete_from_step` conservan el historial de las hijas.
+pub fn check_copy_on_write_branches(repo: Arc<dyn FlowRepository>) {
+  let root = flow_with_steps(repo.as_ref(), 3);
+  let child = repo.create_branch(&root, 2, json!({})).expect("child");
+  repo.persist_data(&sample_data(child, 3, None), 0).expect("persist child 3");
+  repo.persist_data(&sample_data(child, 4, None), 1).expect("persist child 4");
+  let grandchild = repo.create_branch(&child, 3, json!({})).expect("grandchild");
+  let (_, rows) = repo.dump_tables_for_debug().expect("dump");
+  let stored = |flow_id: Uuid| {
+    let mut c: Vec<i64> = rows.iter().filter(|d| d.flow_id == flow_id).map(|d| d.cursor).collect();
+    c.sort();
+    c
+  };
+  assert_eq!(stored(child), vec![3, 4], "la rama solo guarda sus registros nuevos");
+  assert!(stored(grandchild).is_empty());
+  let root_rows = repo.read_data(&root, 0).expect("read root");
+  let data = repo.read_data(&grandchild, 0).expect("read grandchild");
+  assert_eq!(data.iter().map(|d| d.cursor).collect::<Vec<_>>(), vec![1, 2, 3]);
+  assert!(data.iter().all(|d| d.flow_id == grandchild));
+  assert_eq!((data[0].id, data[1].id),
+             (root_rows[0].id, root_rows[1].id),
+             "1-2 vienen de la raíz");
+  assert_eq!(cursors(repo.as_ref(), &child), vec![1, 2, 3, 4]);
+  assert_eq!(repo.read_data(&grandchild, 1).expect("read desde 1").len(), 2);
+  assert_eq!(repo.count_steps(&grandchild).expect("count"), 3);
+  let mut inherited = data[0].clone();
+  inherited.payload = json!({"reescrito": true});
+  assert_eq!(repo.rewrite_data(&[inherited]).expect("rewrite heredado"), 1);
+  assert_eq!(repo.read_data(&root, 0).expect("read root")[0].payload,
+             json!({"reescrito": true}),
+             "se reescribe el registro de la raíz");
+  // borrar la rama intermedia no copia nada: el nieto la sigue leyendo
+  repo.delete_branch(&child).expect("delete child");
+  let (_, rows) = repo.dump_tables_for_debug().expect("dump");
+  assert!(!rows.iter().any(|d| d.flow_id == grandchild));
+  let data = repo.read_data(&grandchild, 0).expect("read nieto");
+  assert_eq!(data.iter().map(|d| d.cursor).collect::<Vec<_>>(), vec![1, 2, 3]);
+  assert_eq!((&data[0].payload, &data[2].payload),
+             (&json!({"reescrito": true}), &json!({"cursor": 3})));
+  assert_eq!(repo.count_steps(&grandchild).expect("count nieto"), 3);
+  // truncar dentro del tramo heredado lo recorta sin tocar al padre
+  let branch = repo.create_branch(&root, 3, json!({})).expect("branch");
+  repo.delete_from_step(&branch, 2).expect("delete_from_step");
+  let meta = repo.get_flow_meta(&branch).expect("branch meta");
+  assert_eq!((meta.parent_cursor, meta.current_cursor), (Some(1), 1));
+  assert_eq!(cursors(repo.as_ref(), &branch), vec![1]);
+  assert_eq!(cursors(repo.as_ref(), &root), vec![1, 2, 3]);
+  let res = repo.persist_data(&sample_data(branch, 2, None), 0).expect("persist tras truncar");
+  assert_eq!(res, PersistResult::Ok { new_version: 1 });
+}
+/// `list_children` devuelve las hijas directas por `parent_cursor`, y el
+/// grafo de `flow::lineage` (antepasados, árbol, antepasado común) se
+/// mantiene coherente tras `delete_branch` y `undelete`.
+pub fn check_lineage(repo: Arc<dyn FlowRepository>) {
+  let root = flow_with_steps(repo.as_ref(), 4);
+  let late = repo.create_branch(&root, 3, json!({})).expect("branch 3");
+  let early = repo.create_branch(&root, 1, json!({})).expect("branch 1");
+  repo.persist_data(&sample_data(early, 2, None), 0).expect("persist early");
+  let grandchild = repo.create_branch(&early, 2, json!({})).expect("branch early 2");
+  let children: Vec<Uuid> = repo.list_children(&root).expect("children").iter().map(|m| m.id).collect();
+  assert_eq!(children, vec![early, late]);
+  assert!(repo.list_children(&late).expect("children late").is_empty());
+  assert_not_found(repo.list_children(&Uuid::new_v4()), "list_children sin flow");
+  let chain: Vec<Uuid> = ancestors(repo.as_ref(), &grandchild).expect("ancestors").iter().map(|m| m.id).collect();
+  assert_eq!(chain, vec![early, root]);
+  assert!(ancestors(repo.as_ref(), &root).expect("ancestors raíz").is_empty());
+  assert_eq!(root_of(repo.as_ref(), &grandchild).expect("root_of").id, root);
+  let tree = lineage_tree(repo.as_ref(), &root).expect("tree");
+  assert_eq!(tree.flow_ids(), vec![root, early, grandchild, late]);
+  assert_eq!(tree.find(&grandchild).map(|n| n.flow.parent_cursor), Some(Some(2)));
+  let rendered = tree.render();
+  assert_eq!(rendered.lines().count(), 4);
+  let line = rendered.lines().nth(2).expect("línea nieto");
+  assert!(line.contains(&grandchild.to_string()) && line.ends_with("(cursor 2, desde 2)"),
+          "{}",
+          rendered);
+  let ancestor = |a: &Uuid, b: &Uuid| common_ancestor(repo.as_ref(), a, b).expect("common_ancestor");
+  assert_eq!(ancestor(&grandchild, &late), Some(CommonAncestor { flow_id: root, cursor: 1 }));
+  assert_eq!(ancestor(&late, &grandchild), Some(CommonAncestor { flow_id: root, cursor: 1 }));
+  assert_eq!(ancestor(&grandchild, &early),
+             Some(CommonAncestor { flow_id: early, cursor: 2 }));
+  assert_eq!(ancestor(&root, &root), Some(CommonAncestor { flow_id: root, cursor: 4 }));
+  repo.delete_branch(&early).expect("delete early");
+  assert!(ancestors(repo.as_ref(), &grandchild).expect("ancestors").is_empty(),
+          "la cadena se corta en la rama borrada");
+  assert_eq!(ancestor(&grandchild, &late), None);
+  assert_eq!(lineage_tree(repo.as_ref(), &root).expect("tree").flow_ids(), vec![root, late]);
+  repo.undelete(&early).expect("undelete early");
+  assert_eq!(ancestor(&grandchild, &late), Some(CommonAncestor { flow_id: root, cursor: 1 }));
+  assert_eq!(lineage_tree(repo.as_ref(), &root).expect("tree").flow_ids(), vec![root, early, grandchild, late]);
+}
+/// `cherry_pick` copia registros al final del destino con su procedencia,
+/// respeta `expected_version`, detecta conflictos de key (también entre los
+/// registros elegidos) y no repite copias.
+pub fn check_cherry_pick(repo: Arc<dyn FlowRepository>) {
+  let parent = flow_with_steps(repo.as_ref(), 2);
+  let branch = repo.create_branch(&parent, 1, json!({})).expect("branch");
+  let fix = FlowData { key: "step_state:fix".into(), ..sample_data(branch, 2, None) };
+  repo.persist_data(&fix, 0).expect("persist fix");
+  repo.persist_data(&sample_data(branch, 3, None), 1).expect("persist testkit");
+  let report = cherry_pick(repo.as_ref(), &branch, &parent, &[2], &CherryPickOptions::new(2)).expect("cherry_pick");
+  assert_eq!(report.new_version, 3);
+  assert_eq!(report.picked.iter().map(|p| (p.source_cursor, p.target_cursor)).collect::<Vec<_>>(),
+             vec![(2, 3)]);
+  let copied = repo.read_data(&parent, 2).expect("read_data");
+  assert_eq!(copied.len(), 1);
+  assert_eq!((copied[0].id, copied[0].key.as_str(), &copied[0].payload),
+             (report.picked[0].record_id, "step_state:fix", &fix.payload));
+  assert_eq!(copied[0].metadata[CHERRY_PICK_METADATA_KEY],
+             json!({"flow_id": branch, "cursor": 2, "record_id": fix.id}));
+  assert_eq!(repo.get_flow_meta(&parent).expect("meta").current_cursor, 3);
+  let again = cherry_pick(repo.as_ref(), &branch, &parent, &[2], &CherryPickOptions::new(3)).expect("repetido");
+  assert!(again.picked.is_empty());
+  assert_eq!(again.skipped[0].reason, SkipReason::AlreadyApplied);
+  assert_eq!(again.new_version, 3);
+  match cherry_pick(repo.as_ref(), &branch, &parent, &[3], &CherryPickOptions::new(2)) {
+    Err(CherryPickError { error: FlowError::Conflict(_), report }) => assert!(report.picked.is_empty()),
+    other => panic!("versión obsoleta: se esperaba Conflict, obtenido {:?}", other),
+  }
+  match cherry_pick(repo.as_ref(), &branch, &parent, &[2, 3], &CherryPickOptions::new(3)) {
+    Err(CherryPickError { error: FlowError::Conflict(_), .. }) => {
+      assert_eq!(cursors(repo.as_ref(), &parent), vec![1, 2, 3], "Abort no copia nada")
+    }
+    other => panic!("conflicto de key: se esperaba Conflict, obtenido {:?}", other),
+  }
+  let skip = CherryPickOptions::new(3).on_conflict(KeyConflictPolicy::Skip);
+  let report = cherry_pick(repo.as_ref(), &branch, &parent, &[3], &skip).expect("skip");
+  assert_eq!((report.picked.len(), report.skipped[0].reason), (0, SkipReason::KeyConflict));
+  let append = CherryPickOptions::new(3).on_conflict(KeyConflictPolicy::Append);
+  let report = cherry_pick(repo.as_ref(), &branch, &parent, &[3], &append).expect("append");
+  assert_eq!((report.picked[0].target_cursor, report.new_version), (4, 4));
+  // dos registros elegidos con la misma key, que el destino aún no tiene
+  for (cursor, version) in [(4, 2), (5, 3)] {
+    let twin = FlowData { key: "step_state:twin".into(), ..sample_data(branch, cursor, None) };
+    repo.persist_data(&twin, version).expect("persist twin");
+  }
+  match cherry_pick(repo.as_ref(), &branch, &parent, &[4, 5], &CherryPickOptions::new(4)) {
+    Err(CherryPickError { error: FlowError::Conflict(_), .. }) => {
+      assert_eq!(cursors(repo.as_ref(), &parent), vec![1, 2, 3, 4], "Abort no copia ninguno de los dos")
+    }
+    other => panic!("key repetida en la selección: se esperaba Conflict, obtenido {:?}", other),
+  }
+  let skip = CherryPickOptions::new(4).on_conflict(KeyConflictPolicy::Skip);
+  let report = cherry_pick(repo.as_ref(), &branch, &parent, &[4, 5], &skip).expect("skip twin");
+  assert_eq!(report.picked.iter().map(|p| (p.source_cursor, p.target_cursor)).collect::<Vec<_>>(), vec![(4, 5)]);
+  assert_eq!((report.skipped[0].source_cursor, report.skipped[0].reason), (5, SkipReason::KeyConflict));
+  let missing = cherry_pick(repo.as_ref(), &branch, &parent, &[9], &CherryPickOptions::new(5));
+  assert_not_found(missing.map_err(FlowError::from), "cherry_pick de un cursor inexistente");
+  let orphan = cherry_pick(repo.as_ref(), &branch, &Uuid::new_v4(), &[2], &CherryPickOptions::new(0));
+  assert_not_found(orphan.map_err(FlowError::from), "cherry_pick a un flow inexistente");
+}
+/// `delete_from_step` trunca datos y snapshots, recoloca `current_cursor` y
+/// borra las ramas que parten del tramo eliminado; `undelete_steps` lo
+/// deshace mientras el flow no haya persistido nada después.
+pub fn check_delete_from_step(repo: Arc<dyn FlowRepository>) {
+  let flow_id = flow_with_steps(repo.as_ref(), 4);
+  repo.save_snapshot(&flow_id, 2, "ptr-2", json!({})).expect("snap 2");
+  repo.save_snapshot(&flow_id, 4, "ptr-4", json!({})).expect("snap 4");
+  let keep = repo.create_branch(&flow_id, 1, json!({})).expect("branch 1");
+  let drop_child = repo.create_branch(&flow_id, 3, json!({})).expect("branch 3");
+  let nested = repo.create_branch(&drop_child, 3, json!({})).expect("branch de branch 3");
+  repo.delete_from_step(&flow_id, 3).expect("delete_from_step");
+  assert_eq!(cursors(repo.as_ref(), &flow_id), vec![1, 2]);
+  let meta = repo.get_flow_meta(&flow_id).expect("meta");
+  assert_eq!(meta.current_cursor, 2);
+  assert_eq!(meta.current_version, 4, "delete_from_step no cambia la versión");
+  assert_eq!(repo.count_steps(&flow_id).expect("count"), 2);
+  assert_eq!(repo.load_latest_snapshot(&flow_id).expect("snap").map(|s| s.cursor), Some(2));
+  assert!(repo.branch_exists(&keep).expect("keep"));
+  assert!(!repo.branch_exists(&drop_child).expect("drop_child"));
+  assert!(!repo.branch_exists(&nested).expect("nested"));
+  // la hija borrada leería pasos truncados: solo vuelve con undelete_steps
+  match repo.undelete(&drop_child) {
+    Err(FlowError::Conflict(_)) => {}
+    other => panic!("undelete de una rama truncada: se esperaba Conflict, obtenido {:?}", other),
+  }
+  repo.undelete_steps(&flow_id).expect("undelete_steps");
+  assert_eq!(cursors(repo.as_ref(), &flow_id), vec![1, 2, 3, 4]);
+  let meta = repo.get_flow_meta(&flow_id).expect("meta");
+  assert_eq!((meta.current_cursor, meta.current_version), (4, 4));
+  assert_eq!(repo.load_latest_snapshot(&flow_id).expect("snap").map(|s| s.cursor), Some(4));
+  let drop_meta = repo.get_flow_meta(&drop_child).expect("drop_child vuelve");
+  assert_eq!((drop_meta.parent_flow_id, drop_meta.parent_cursor), (Some(flow_id), Some(3)));
+  assert_eq!(cursors(repo.as_ref(), &drop_child), vec![1, 2, 3]);
+  assert_eq!(cursors(repo.as_ref(), &nested), vec![1, 2, 3]);
+  assert_not_found(repo.undelete_steps(&flow_id), "undelete_steps sin truncados");
+  // en una rama, también vuelve el tramo heredado
+  repo.delete_from_step(&drop_child, 2).expect("truncar tramo heredado");
+  assert_eq!(repo.get_flow_meta(&drop_child).expect("meta").parent_cursor, Some(1));
+  repo.undelete_steps(&drop_child).expect("undelete_steps rama");
+  assert_eq!(repo.get_flow_meta(&drop_child).expect("meta").parent_cursor, Some(3));
+  assert_eq!(cursors(repo.as_ref(), &drop_child), vec![1, 2, 3]);
+  // persistir después de truncar impide deshacerlo
+  repo.delete_from_step(&flow_id, 3).expect("delete_from_step de nuevo");
+  let res = repo.persist_data(&sample_data(flow_id, 3, None), 4).expect("persist tras truncar");
+  assert_eq!(res, PersistResult::Ok { new_version: 5 });
+  match repo.undelete_steps(&flow_id) {
+    Err(FlowError::Conflict(_)) => assert_eq!(cursors(repo.as_ref(), &flow_id), vec![1, 2, 3]),
+    other => panic!("undelete_steps tras persistir: se esperaba Conflict, obtenido {:?}", other),
+  }
+  repo.delete_from_step(&flow_id, 1).expect("delete todo");
+  assert_eq!(repo.get_flow_meta(&flow_id).expect("meta").current_cursor, 0);
+  assert_not_found(repo.delete_from_step(&Uuid::new_v4(), 1), "delete_from_step sin flow");
+  assert_not_found(repo.undelete_steps(&Uuid::new_v4()), "undelete_steps sin flow");
+}
+/// Borrado lógico: lo borrado con `delete_branch`/`delete_from_step` deja de
+/// verse, `undelete` devuelve el flow con su historial y `purge_deleted` lo
+/// elimina solo pasada la retención, copiando antes en las hijas que le
+/// sobreviven lo que heredaban de él.
+pub fn check_soft_delete(repo: Arc<dyn FlowRepository>) {
+  let root = flow_with_steps(repo.as_ref(), 3);
+  repo.save_snapshot(&root, 3, "ptr-3", json!({})).expect("snap 3");
+  let child = repo.create_branch(&root, 2, json!({})).expect("child");
+  repo.delete_branch(&root).expect("delete_branch");
+  assert_not_found(repo.get_flow_meta(&root), "meta de flow borrado");
+  assert!(repo.read_data(&root, 0).expect("read borrado").is_empty());
+  assert_eq!(repo.count_steps(&root).expect("count borrado"), -1);
+  assert!(repo.load_latest_snapshot(&root).expect("snap borrado").is_none());
+  assert!(!repo.list_flow_ids().expect("list").contains(&root));
+  assert_not_found(repo.persist_data(&sample_data(root, 4, None), 3), "persist en flow borrado");
+  assert_not_found(repo.create_branch(&root, 1, json!({})), "rama de flow borrado");
+  let tombstone = repo.list_deleted_flows()
+                      .expect("list_deleted_flows")
+                      .into_iter()
+                      .find(|m| m.id == root)
+                      .expect("el flow borrado aparece en list_deleted_flows");
+  assert!(tombstone.deleted_at.is_some());
+  assert_eq!(tombstone.current_cursor, 3);
+  assert_eq!(cursors(repo.as_ref(), &child), vec![1, 2], "la hija conserva su historial");
+  repo.undelete(&root).expect("undelete");
+  let meta = repo.get_flow_meta(&root).expect("meta tras undelete");
+  assert_eq!((meta.current_cursor, meta.current_version, meta.deleted_at), (3, 3, None));
+  assert_eq!(cursors(repo.as_ref(), &root), vec![1, 2, 3]);
+  assert_eq!(repo.load_latest_snapshot(&root).expect("snap").map(|s| s.cursor), Some(3));
+  assert!(repo.list_deleted_flows().expect("list_deleted_flows").iter().all(|m| m.id != root));
+  assert_eq!(repo.get_flow_meta(&child).expect("child").parent_flow_id,
+             Some(root),
+             "la hija sigue enlazada");
+  repo.undelete(&root).expect("undelete de un flow vivo");
+  assert_not_found(repo.undelete(&Uuid::new_v4()), "undelete sin flow");
+  // un paso truncado deja de contar para la idempotencia por command_id
+  let cmd = Uuid::new_v4();
+  let first = repo.persist_data(&sample_data(root, 4, Some(cmd)), 3).expect("persist 4");
+  assert_eq!(first, PersistResult::Ok { new_version: 4 });
+  repo.delete_from_step(&root, 4).expect("delete_from_step");
+  let again = repo.persist_data(&sample_data(root, 4, Some(cmd)), 4).expect("persist 4 de nuevo");
+  assert_eq!(again,
+             PersistResult::Ok { new_version: 5 },
+             "no es un reintento: el paso se truncó");
+  assert_eq!(cursors(repo.as_ref(), &root), vec![1, 2, 3, 4]);
+  // purga: nada reciente se elimina; pasada la retención, sí
+  repo.delete_branch(&child).expect("delete child");
+  repo.purge_deleted(Duration::from_secs(3600)).expect("purge con retención larga");
+  repo.undelete(&child).expect("undelete antes de purgar");
+  let heir = repo.create_branch(&child, 2, json!({})).expect("heir");
+  repo.delete_branch(&child).expect("delete child de nuevo");
+  std::thread::sleep(PURGE_RETENTION);
+  let report = repo.purge_deleted(PURGE_RETENTION).expect("purge");
+  assert!(report.flows >= 1 && report.records >= 1, "{:?}", report);
+  assert_not_found(repo.undelete(&child), "undelete tras purgar");
+  let heir_meta = repo.get_flow_meta(&heir).expect("heir sobrevive");
+  assert_eq!((heir_meta.parent_flow_id, heir_meta.parent_cursor), (None, None));
+  assert_eq!(cursors(repo.as_ref(), &heir), vec![1, 2], "la purga copia lo que heredaba");
+  assert!(repo.list_deleted_flows().expect("list_deleted_flows").iter().all(|m| m.id != child));
+  assert_eq!(cursors(repo.as_ref(), &root), vec![1, 2, 3, 4]);
+  assert_eq!(repo.persist_data(&sample_data(root, 5, Some(cmd)), 5).expect("replay"),
+             again,
+             "el paso vivo sigue deduplicando");
+}
+/// Recorre todas las páginas de `query` y devuelve sus flows en orden.
+fn query_all(repo: &dyn FlowRepository, query: FlowQuery) -> Vec<FlowMeta> {
+  let mut out = Vec::new();
+  let mut page = repo.query_flows(&query).expect("query_flows");
+  loop {
+    assert!(page.flows.len() <= query.page_size(), "página mayor que el límite");
+    out.extend(page.flows);
+    match page.next {
+      Some(cursor) => page = repo.query_flows(&query.clone().after(cursor)).expect("query_flows siguiente"),
+      None => return out,
+    }
+  }
+}
+/// `query_flows` aplica cada filtro, omite los flows borrados, ordena por
+/// fecha o nombre en ambos sentidos y pagina con el cursor sin repetir ni
+/// saltar flows.
+pub fn check_query_flows(repo: Arc<dyn FlowRepository>) {
+  // autor y prefijo únicos: la base puede tener flows de otros checks
+  let tag = Uuid::new_v4().simple().to_string();
+  let author = format!("qf-{}", tag);
+  let mk = |suffix: &str, status: &str, workflow_type: Option<&str>| {
+    let metadata = workflow_type.map_or(json!({}), |w| json!({ WORKFLOW_TYPE_META_KEY: w }));
+    repo.create_flow_as(Some(format!("{}-{}", tag, suffix)), Some(status.into()), metadata, Some(author.clone()))
+        .expect("create_flow_as")
+  };
+  let c = mk("c", "queued", Some("cadma"));
+  let a = mk("a", "running", Some("cadma"));
+  let e = mk("e", "queued", None);
+  let b = mk("b", "queued", Some("other"));
+  let d = mk("d", "running", None);
+  repo.set_meta(&d, WORKFLOW_TYPE_META_KEY, json!("cadma")).expect("set_meta");
+  let gone = mk("f", "queued", Some("cadma"));
+  repo.delete_branch(&gone).expect("delete_branch");
+  let ids = |q: FlowQuery| -> Vec<Uuid> { query_all(repo.as_ref(), q).iter().map(|m| m.id).collect() };
+  let mine = || FlowQuery::new().created_by(author.clone());
+  assert_eq!(ids(mine().sort(FlowSort::NameAsc)), vec![a, b, c, d, e]);
+  assert_eq!(ids(mine().sort(FlowSort::NameDesc)), vec![e, d, c, b, a]);
+  assert_eq!(ids(mine().sort(FlowSort::NameAsc).status("queued")), vec![b, c, e]);
+  assert_eq!(ids(mine().sort(FlowSort::NameAsc).workflow_type("cadma")), vec![a, c, d]);
+  assert_eq!(ids(mine().sort(FlowSort::NameAsc).status("running").workflow_type("cadma")), vec![a, d]);
+  assert_eq!(ids(FlowQuery::new().name_prefix(format!("{}-c", tag))), vec![c]);
+  assert_eq!(ids(FlowQuery::new().name_prefix(tag.to_uppercase())), Vec::<Uuid>::new(), "prefijo sensible a mayúsculas");
+  let hour = chrono::Duration::hours(1);
+  assert_eq!(ids(mine().created_since(Utc::now() - hour)).len(), 5);
+  assert!(ids(mine().created_since(Utc::now() + hour)).is_empty());
+  assert!(ids(mine().created_until(Utc::now() - hour)).is_empty());
+  assert_eq!(ids(mine().created_until(Utc::now() + hour)).len(), 5);
+  // paginación: páginas de 2 con el mismo resultado que una sola página
+  for sort in [FlowSort::CreatedAsc, FlowSort::CreatedDesc, FlowSort::NameAsc, FlowSort::NameDesc] {
+    let whole = query_all(repo.as_ref(), mine().sort(sort));
+    let paged = query_all(repo.as_ref(), mine().sort(sort).limit(2));
+    assert_eq!(paged.iter().map(|m| m.id).collect::<Vec<_>>(),
+               whole.iter().map(|m| m.id).collect::<Vec<_>>(),
+               "{:?}",
+               sort);
+    let dates: Vec<_> = whole.iter().map(|m| m.created_at).collect();
+    match sort {
+      FlowSort::CreatedAsc => assert!(dates.windows(2).all(|w| w[0] <= w[1])),
+      FlowSort::CreatedDesc => assert!(dates.windows(2).all(|w| w[0] >= w[1])),
+      _ => {}
+    }
+  }
+  let first = repo.query_flows(&mine().limit(5)).expect("página exacta");
+  assert_eq!((first.flows.len(), first.next), (5, None), "sin página vacía al final");
+  // ramas hijas
+  let child = repo.create_branch(&a, 0, json!({})).expect("create_branch");
+  assert_eq!(ids(FlowQuery::new().parent(a)), vec![child]);
+  assert!(ids(FlowQuery::new().parent(b)).is_empty());
+}
+/// `query_data` filtra el historial (heredados incluidos) por rango de
+/// cursores, key y prefijo, con orden y límite; `query_data_meta` devuelve
+/// los mismos registros sin payload y `latest_by_key` el último de una key.
+pub fn check_query_data(repo: Arc<dyn FlowRepository>) {
+  let keyed = |flow_id: Uuid, cursor: i64, key: &str| FlowData { key: key.into(),
+                                                                 metadata: json!({ "n": cursor }),
+                                                                 ..sample_data(flow_id, cursor, None) };
+  let keys = ["step_state:A", "step_state:B", "input", "step_state:A"];
+  let root = repo.create_flow(None, None, json!({})).expect("create_flow");
+  for (i, key) in keys.iter().enumerate() {
+    let c = i as i64 + 1;
+    repo.persist_data(&keyed(root, c, key), c - 1).expect("persist root");
+  }
+  let child = repo.create_branch(&root, 3, json!({})).expect("child");
+  repo.persist_data(&keyed(child, 4, "step_state:B"), 0).expect("persist child 4");
+  repo.persist_data(&keyed(child, 5, "input"), 1).expect("persist child 5");
+  let got = |flow_id: &Uuid, q: FlowDataQuery| -> Vec<i64> {
+    let rows = repo.query_data(flow_id, &q).expect("query_data");
+    assert!(rows.iter().all(|d| d.flow_id == *flow_id));
+    let metas = repo.query_data_meta(flow_id, &q).expect("query_data_meta");
+    assert_eq!(metas, rows.iter().map(FlowDataMeta::from).collect::<Vec<_>>(), "proyección de {:?}", q);
+    rows.iter().map(|d| d.cursor).collect()
+  };
+  assert_eq!(got(&child, FlowDataQuery::new()), cursors(repo.as_ref(), &child));
+  assert_eq!(got(&child, FlowDataQuery::new().after(1).up_to(4)), vec![2, 3, 4]);
+  assert_eq!(got(&child, FlowDataQuery::new().key("step_state:A")), vec![1], "el 4 de la raíz no se hereda");
+  assert_eq!(got(&child, FlowDataQuery::new().key("step_state:B")), vec![2, 4]);
+  assert_eq!(got(&root, FlowDataQuery::new().key("step_state:A")), vec![1, 4]);
+  assert_eq!(got(&child, FlowDataQuery::new().key_prefix("step_state:")), vec![1, 2, 4]);
+  assert_eq!(got(&child, FlowDataQuery::new().key_prefix("STEP_STATE:")), Vec::<i64>::new());
+  assert_eq!(got(&child, FlowDataQuery::new().latest_first().limit(3)), vec![5, 4, 3]);
+  assert_eq!(got(&child, FlowDataQuery::new().key("input").latest_first().up_to(4)), vec![3]);
+  assert_eq!(got(&Uuid::new_v4(), FlowDataQuery::new()), Vec::<i64>::new());
+  let latest = |flow_id: &Uuid, key: &str| repo.latest_by_key(flow_id, key).expect("latest_by_key").map(|d| d.cursor);
+  assert_eq!(latest(&child, "step_state:B"), Some(4));
+  assert_eq!(latest(&child, "step_state:A"), Some(1));
+  assert_eq!(latest(&root, "step_state:A"), Some(4));
+  assert_eq!(latest(&child, "missing"), None);
+  assert_eq!(latest(&Uuid::new_v4(), "input"), None);
+  let inherited = repo.latest_by_key(&child, "step_state:A").expect("latest_by_key").expect("heredado");
+  assert_eq!((inherited.flow_id, &inherited.payload), (child, &json!({ "cursor": 1 })));
+}
+/// Los predicados JSON de `query_flows` (sobre `metadata`) y `query_data`
+/// (sobre `payload` o `metadata`) comparan el valor exacto en la ruta: un
+/// array que contiene el valor no es igual, `null` solo es igual a `null` y
+/// `!=` incluye los documentos sin la ruta. Una columna no admitida es un
+/// error.
+pub fn check_json_predicates(repo: Arc<dyn FlowRepository>) {
+  let author = format!("jp-{}", Uuid::new_v4().simple());
+  let mk = |name: &str, metadata: serde_json::Value| {
+    repo.create_flow_as(Some(format!("{}-{}", author, name)), None, metadata, Some(author.clone()))
+        .expect("create_flow_as")
+  };
+  let a = mk("a",
+             json!({ WORKFLOW_TYPE_META_KEY: "cadma", "run": { "stage": "screening", "n": 2 }, "ok": true,
+                     "expr": "x==y" }));
+  let b = mk("b",
+             json!({ WORKFLOW_TYPE_META_KEY: "other", "run": { "stage": "screening", "n": 3 }, "ok": false, "note": null,
+                     "expr": "x!=y" }));
+  let c = mk("c", json!({}));
+  let d = mk("d", json!({ WORKFLOW_TYPE_META_KEY: "cadma", "run": { "stage": ["screening"] } }));
+  let flows = |q: FlowQuery| -> Vec<Uuid> { query_all(repo.as_ref(), q).iter().map(|m| m.id).collect() };
+  let mine = |expr: &str| FlowQuery::new().created_by(author.clone()).sort(FlowSort::NameAsc).json(pred(expr));
+  assert_eq!(flows(mine("metadata.workflow_type == \"cadma\"")), vec![a, d]);
+  assert_eq!(flows(mine("metadata.run.stage == \"screening\"")), vec![a, b], "el array no es igual al valor");
+  assert_eq!(flows(mine("metadata.run.n == 3")), vec![b]);
+  assert_eq!(flows(mine("metadata.run == {\"n\": 2, \"stage\": \"screening\"}")), vec![a]);
+  assert_eq!(flows(mine("metadata.ok == true")), vec![a]);
+  assert_eq!(flows(mine("metadata.ok == false")), vec![b]);
+  assert_eq!(flows(mine("metadata.note == null")), vec![b], "la clave ausente no es null");
+  assert_eq!(flows(mine("metadata.workflow_type != \"cadma\"")), vec![b, c]);
+  assert_eq!(flows(mine("metadata.run.stage == \"screening\"").workflow_type("cadma")), vec![a]);
+  // el operador es el primero de la expresión aunque el valor contenga otro
+  assert_eq!(flows(mine("metadata.expr == \"x!=y\"")), vec![b]);
+  assert_eq!(flows(mine("metadata.expr != \"x==y\"")), vec![b, c, d]);
+  assert_eq!(pred("payload.note != \"a==b\"").path(), ["note".to_string()]);
+  assert_eq!(pred("payload.note == \"a!=b\"").value(), &json!("a!=b"));
+  assert!(JsonPredicate::eq("metadata.x}' OR '1'='1", json!(1)).is_err());
+  assert!(repo.query_flows(&FlowQuery::new().json(pred("payload.x == 1"))).is_err(), "flows solo admite metadata");
+  // registros: heredados incluidos y la proyección sin payload filtra igual
+  let rec = |flow_id: Uuid, cursor: i64, payload: serde_json::Value, step: &str| {
+    FlowData { payload, metadata: json!({ "step": step }), ..sample_data(flow_id, cursor, None) }
+  };
+  let root = repo.create_flow(None, None, json!({})).expect("create_flow");
+  repo.persist_data(&rec(root, 1, json!({ "status": "ok", "score": 1 }), "A"), 0).expect("persist 1");
+  repo.persist_data(&rec(root, 2, json!({ "status": "failed" }), "B"), 1).expect("persist 2");
+  repo.persist_data(&rec(root, 3, json!({ "status": "ok", "score": 3 }), "B"), 2).expect("persist 3");
+  let child = repo.create_branch(&root, 2, json!({})).expect("create_branch");
+  repo.persist_data(&rec(child, 3, json!({ "status": "ok" }), "C"), 0).expect("persist child");
+  let got = |q: FlowDataQuery| -> Vec<i64> {
+    let rows = repo.query_data(&child, &q).expect("query_data");
+    let metas = repo.query_data_meta(&child, &q).expect("query_data_meta");
+    assert_eq!(metas, rows.iter().map(FlowDataMeta::from).collect::<Vec<_>>(), "proyección de {:?}", q);
+    rows.iter().map(|d| d.cursor).collect()
+  };
+  assert_eq!(got(FlowDataQuery::new().json(pred("payload.status == \"ok\""))), vec![1, 3]);
+  assert_eq!(got(FlowDataQuery::new().json(pred("payload.status != \"failed\""))), vec![1, 3]);
+  assert_eq!(got(FlowDataQuery::new().json(pred("payload.score == 1"))), vec![1]);
+  assert_eq!(got(FlowDataQuery::new().json(pred("metadata.step == \"B\""))), vec![2], "el 3 de la raíz no se hereda");
+  assert_eq!(got(FlowDataQuery::new().json(pred("payload.status == \"ok\"")).latest_first().limit(1)), vec![3]);
+  assert_eq!(got(FlowDataQuery::new().json(pred("payload.status == \"ok\"")).json(pred("metadata.step == \"A\""))),
+             vec![1]);
+  assert!(repo.query_data(&child, &FlowDataQuery::new().json(pred("value.x == 1"))).is_err());
+}
+fn pred(expr: &str) -> JsonPredicate {
+  expr.parse().expect("predicado JSON")
+}
+/// `rewrite_data` sustituye payload y metadata por id sin tocar cursor,
+/// versión ni orden; con un id desconocido no aplica nada del lote.
+pub fn check_rewrite_data(repo: Arc<dyn FlowRepository>) {
+  let flow_id = flow_with_steps(repo.as_ref(), 2);
+  let mut rows = repo.read_data(&flow_id, 0).expect("read_data");
+  for row in rows.iter_mut() {
+    row.payload = json!({ "migrated": row.cursor });
+    row.metadata = json!({ "schema_version": 2 });
+  }
+  assert_eq!(repo.rewrite_data(&rows).expect("rewrite_data"), 2);
+  let data = repo.read_data(&flow_id, 0).expect("read_data");
+  assert_eq!(data.iter().map(|d| (d.id, d.cursor)).collect::<Vec<_>>(),
+             rows.iter().map(|d| (d.id, d.cursor)).collect::<Vec<_>>());
+  assert_eq!(data[1].payload, json!({ "migrated": 2 }));
+  assert_eq!(data[1].metadata, json!({ "schema_version": 2 }));
+  assert_eq!(repo.get_flow_meta(&flow_id).expect("meta").current_version,
+             2,
+             "rewrite_data no cambia la versión");
+  let mut partial = vec![data[0].clone(), sample_data(flow_id, 9, None)];
+  partial[0].payload = json!({ "parcial": true });
+  assert_not_found(repo.rewrite_data(&partial), "rewrite_data con id desconocido");
+  assert_eq!(repo.read_data(&flow_id, 0).expect("read_data")[0].payload,
+             json!({ "migrated": 1 }),
+             "un lote con errores no se aplica");
+  assert_eq!(repo.rewrite_data(&[]).expect("lote vacío"), 0);
+}
+/// `lock_for_update` es exclusivo, respeta el timeout, se libera al soltar
+/// el guard, se puede renovar y verifica `expected_version`.
+pub fn check_lock_for_update(repo: Arc<dyn FlowRepository>) {
+  let flow_id = repo.create_flow(Some("lock".into()), None, json!({})).expect("create");
+  let guard = repo.lock_for_update(&flow_id, 0, LOCK_TIMEOUT).expect("lock").expect("version 0");
+  assert_eq!(guard.flow_id(), flow_id);
+  guard.renew().expect("renovar lock propio");
+  assert_eq!(guard.keep_alive(|| 7), 7);
+  let started = Instant::now();
+  match repo.lock_for_update(&flow_id, 0, LOCK_TIMEOUT) {
+    Err(FlowError::Conflict(_)) => assert!(started.elapsed() >= LOCK_TIMEOUT),
+    other => panic!("se esperaba timeout, obtenido {:?}", other),
+  }
+  let waiter = {
+    let repo = repo.clone();
+    std::thread::spawn(move || repo.lock_for_update(&flow_id, 0, Duration::from_secs(5)).map(|g| g.is_some()))
+  };
+  std::thread::sleep(Duration::from_millis(100));
+  drop(guard);
+  assert!(waiter.join().expect("join").expect("lock tras liberar"),
+          "el hilo en espera obtiene el lock");
+  assert!(repo.lock_for_update(&flow_id, 7, LOCK_TIMEOUT).expect("lock stale").is_none());
+  repo.lock_for_update(&flow_id, 0, LOCK_TIMEOUT).expect("lock libre").expect("guard").release();
+  assert_not_found(repo.lock_for_update(&Uuid::new_v4(), 0, LOCK_TIMEOUT),
+                   "lock_for_update sin flow");
+}
+/// Reclama todo lo disponible para `worker` y devuelve los items propios
+/// (de `mine`). Los ajenos (p. ej. de otros tests en una base compartida)
+/// se ignoran.
+fn claim_all_mine(repo: &dyn FlowRepository, worker: &str, mine: &[Uuid]) -> Vec<crate::domain::WorkItem> {
+  let mut out = Vec::new();
+  while let Some(item) = repo.claim_work(worker).expect("claim_work") {
+    if mine.contains(&item.flow_id) {
+      out.push(item);
+    }
+  }
+  out
+}
+/// Cola de trabajo con el lease por defecto: claims exclusivos en orden de
+/// encolado (también dentro del mismo segundo), heartbeat y complete sólo
+/// para el dueño, `enqueue_work` idempotente.
+pub fn check_work_queue(repo: Arc<dyn FlowRepository>) {
+  let f1 = repo.create_flow(Some("wq-1".into()), None, json!({})).expect("create f1");
+  let f2 = repo.create_flow(Some("wq-2".into()), None, json!({})).expect("create f2");
+  repo.enqueue_work(&f2).expect("enqueue f2");
+  std::thread::sleep(Duration::from_millis(2));
+  repo.enqueue_work(&f1).expect("enqueue f1");
+  repo.enqueue_work(&f2).expect("enqueue f2 de nuevo");
+  let mine = [f1, f2];
+  let a = claim_all_mine(repo.as_ref(), "w1", &mine);
+  assert_eq!(a.len(), 2, "cada flow se encola una sola vez");
+  assert_eq!((a[0].flow_id, a[1].flow_id), (f2, f1), "se reclaman en orden de encolado");
+  assert!(a.iter().all(|w| w.worker_id == "w1" && w.attempts == 1 && w.lease_expires_at > Utc::now()));
+  assert!(claim_all_mine(repo.as_ref(), "w2", &mine).is_empty(),
+          "con lease vigente nadie más los recibe");
+  assert!(repo.heartbeat_work(&f1, "w1").expect("heartbeat dueño"));
+  assert!(!repo.heartbeat_work(&f1, "w2").expect("heartbeat ajeno"));
+  assert!(!repo.complete_work(&f1, "w2").expect("complete ajeno"));
+  assert!(repo.complete_work(&f1, "w1").expect("complete dueño"));
+  assert!(repo.complete_work(&f2, "w1").expect("complete f2"));
+  assert!(!repo.complete_work(&f2, "w1").expect("complete repetido"));
+  assert_not_found(repo.enqueue_work(&Uuid::new_v4()), "enqueue_work sin flow");
+}
+/// `subscribe` entrega primero los registros con `cursor > from_cursor` y
+/// después los nuevos (escritos desde otro hilo), en orden, sin duplicados y
+/// sin mezclar flows.
+pub fn check_subscribe(repo: Arc<dyn FlowRepository>) {
+  let flow_id = flow_with_steps(repo.as_ref(), 2);
+  let other = flow_with_steps(repo.as_ref(), 0);
+  let mut sub = repo.subscribe(&flow_id, 1).expect("subscribe");
+  let mut full = repo.subscribe(&flow_id, 0).expect("subscribe desde 0");
+  assert_eq!(sub.recv_timeout(FEED_TIMEOUT).map(|d| d.cursor), Some(2), "backlog");
+  assert!(sub.try_next().is_none());
+  let writer = Arc::clone(&repo);
+  std::thread::spawn(move || {
+    writer.persist_data(&sample_data(other, 1, None), 0).expect("persist otro flow");
+    writer.persist_data(&sample_data(flow_id, 3, None), 2).expect("persist 3");
+  }).join()
+    .expect("writer");
+  let live = sub.recv_timeout(FEED_TIMEOUT).expect("registro en vivo");
+  assert_eq!((live.flow_id, live.cursor), (flow_id, 3));
+  assert!(sub.recv_timeout(Duration::from_millis(200)).is_none(),
+          "sin duplicados ni registros ajenos");
+  assert_eq!(sub.last_cursor(), 3);
+  let seen: Vec<i64> = (0..3).filter_map(|_| full.recv_timeout(FEED_TIMEOUT)).map(|d| d.cursor).collect();
+  assert_eq!(seen, vec![1, 2, 3]);
+  assert_not_found(repo.subscribe(&Uuid::new_v4(), 0), "subscribe sin flow");
+}
+/// Requiere un repositorio configurado con lease de duración cero y una cola
+/// sin items ajenos: un item reclamado por un worker caído vuelve a estar
+/// disponible y el worker original pierde la propiedad. No forma parte de
+/// `run_conformance_suite` porque la duración del lease es configuración de
+/// cada backend.
+pub fn check_expired_work_lease(repo: Arc<dyn FlowRepository>) {
+  let f = repo.create_flow(Some("wq-lease".into()), None, json!({})).expect("create");
+  repo.enqueue_work(&f).expect("enqueue");
+  let first = repo.claim_work("dead-worker").expect("claim").expect("item");
+  assert_eq!(first.flow_id, f);
+  let second = repo.claim_work("w2").expect("reclaim").expect("item expirado");
+  assert_eq!((second.flow_id, second.attempts), (f, 2));
+  assert!(!repo.heartbeat_work(&f, "dead-worker").expect("heartbeat lease perdido"));
+  assert!(!repo.complete_work(&f, "dead-worker").expect("complete lease perdido"));
+  assert!(repo.complete_work(&f, "w2").expect("complete"));
+}
+/// Política que espera `check_snapshot_retention`: conserva los 2 últimos
+/// snapshots, el último de cada tramo de 10 cursores y los puntos de rama; los
+/// blobs huérfanos se borran tras 2 s (margen para stores que guardan la
+/// fecha con precisión de segundos).
+pub fn retention_for_checks() -> SnapshotRetention {
+  SnapshotRetention { keep_last: 2, keep_every: Some(10), keep_branch_points: true, orphan_grace: ORPHAN_GRACE }
+}
+fn retention_state(cursor: i64) -> Vec<u8> {
+  serde_json::to_vec(&json!({ "cursor": cursor })).expect("json")
+}
+/// Requiere un repositorio configurado con `retention_for_checks()` y un
+/// `SnapshotStore` propio (no compartido con otras bases): `compact_snapshots`
+/// aplica la política sin romper los blobs que comparten las ramas y
+/// `gc_snapshots` borra los blobs huérfanos solo pasado `orphan_grace`. No
+/// forma parte de `run_conformance_suite` porque la política es configuración
+/// de cada backend y `gc_snapshots` actúa sobre todos los flows.
+pub fn check_snapshot_retention(repo: Arc<dyn FlowRepository>) {
+  let parent = flow_with_steps(repo.as_ref(), 1);
+  let ids: Vec<Uuid> =
+    (1..=25).map(|c| repo.save_snapshot_state(&parent, c, &retention_state(c), json!({})).expect("save")).collect();
+  let branch = repo.create_branch(&parent, 7, json!({})).expect("create_branch");
+  assert_eq!(repo.compact_snapshots(&parent).expect("compact"), 20);
+  let kept: Vec<i64> = (1..=25).filter(|c| repo.load_snapshot(&ids[*c as usize - 1]).is_ok()).collect();
+  assert_eq!(kept, vec![7, 9, 19, 24, 25], "últimos 2, uno cada 10 y punto de rama");
+  assert_eq!(repo.compact_snapshots(&parent).expect("compact de nuevo"), 0);
+  // la rama copió los snapshots 1..=7: sus blobs siguen vivos aunque el
+  // padre haya borrado sus filas
+  assert_eq!(repo.compact_snapshots(&branch).expect("compact rama"), 5);
+  repo.delete_from_step(&branch, 7).expect("delete_from_step");
+  let latest = repo.load_latest_snapshot(&branch).expect("latest").expect("snapshot 6 de la rama");
+  assert_eq!(latest.cursor, 6);
+  assert_eq!(repo.load_snapshot(&latest.id).expect("blob compartido").0, retention_state(6));
+  assert_not_found(repo.compact_snapshots(&Uuid::new_v4()), "compact_snapshots sin flow");
+  // un blob sin snapshot que lo referencie solo se borra pasado el margen
+  let solo = flow_with_steps(repo.as_ref(), 1);
+  let sid = repo.save_snapshot_state(&solo, 1, &retention_state(1), json!({})).expect("save solo");
+  let key = repo.load_snapshot(&sid).expect("load solo").1.state_ptr;
+  repo.delete_branch(&solo).expect("delete_branch");
+  let blob_alive = |alive: bool| {
+    let holder = repo.create_flow(None, None, json!({})).expect("create holder");
+    let sid = repo.save_snapshot(&holder, 1, &key, json!({})).expect("registrar key");
+    match repo.load_snapshot(&sid) {
+      Ok((bytes, _)) => assert!(alive && bytes == retention_state(1), "el blob huérfano debía estar borrado"),
+      Err(FlowError::NotFound(_)) => assert!(!alive, "el blob huérfano reciente no debe borrarse"),
+      Err(e) => panic!("load_snapshot: {:?}", e),
+    }
+    repo.delete_branch(&holder).expect("delete holder");
+  };
+  repo.gc_snapshots().expect("gc");
+  blob_alive(true);
+  std::thread::sleep(ORPHAN_GRACE.max(PURGE_RETENTION) + Duration::from_millis(100));
+  // los flows borrados siguen referenciando el blob hasta purgarse
+  repo.purge_deleted(PURGE_RETENTION).expect("purge");
+  let report = repo.gc_snapshots().expect("gc tras el margen");
+  assert!(report.blobs_removed >= 1, "{:?}", report);
+  blob_alive(false);
+  assert_eq!(repo.load_snapshot(&ids[24]).expect("snapshot 25 tras gc").0,
+             retention_state(25));
+}
+/// `ArtifactStore` de un repositorio: deduplicación por hash, retenciones
+/// de `copy_if_needed` y referencias desde `metadata.artifacts`, que se
+/// copian con las ramas y desaparecen al purgar lo borrado con
+/// `delete_branch`/`delete_from_step`.
+/// No forma parte de `run_conformance_suite` porque no todos los
+/// `FlowRepository` son `ArtifactStore`.
+pub fn check_artifact_store<R>(repo: Arc<R>)
+  where R: FlowRepository + ArtifactStore
+{
+  let blob = format!("artifact {}", Uuid::new_v4()).into_bytes();
+  let key = repo.put(&blob).expect("put");
+  assert_eq!(key, artifact_key(&blob), "la key es el SHA-256 del contenido");
+  assert_eq!(repo.put(&blob).expect("put repetido"), key);
+  assert_eq!(repo.get(&key).expect("get"), blob);
+  let missing = artifact_key(Uuid::new_v4().as_bytes());
+  assert_not_found(repo.get(&missing), "get sin artifact");
+  assert_not_found(repo.copy_if_needed(&missing), "copy_if_needed sin artifact");
+  assert_not_found(repo.release(&missing), "release sin artifact");
+  // una retención mantiene el blob; al liberarla queda sin dueño
+  assert_eq!(repo.copy_if_needed(&key).expect("copy_if_needed"),
+             key,
+             "no se copia: misma key");
+  repo.gc_artifacts(Duration::ZERO).expect("gc");
+  assert_eq!(repo.get(&key).expect("retenido"), blob);
+  repo.release(&key).expect("release");
+  repo.release(&key).expect("release sin retenciones no baja de cero");
+  // referencias desde flow_data, heredadas por las ramas
+  let flow_id = repo.create_flow(None, None, json!({})).expect("create");
+  let mut data = sample_data(flow_id, 1, None);
+  data.metadata = json!({"artifacts": [key.clone(), "no-es-una-key"]});
+  assert!(matches!(repo.persist_data(&data, 0).expect("persist"), PersistResult::Ok { .. }));
+  repo.gc_artifacts(Duration::ZERO).expect("gc");
+  assert_eq!(repo.get(&key).expect("referenciado"), blob);
+  let branch = repo.create_branch(&flow_id, 1, json!({})).expect("create_branch");
+  repo.delete_branch(&flow_id).expect("delete_branch");
+  repo.gc_artifacts(Duration::ZERO).expect("gc");
+  assert_eq!(repo.get(&key).expect("referenciado por la rama"), blob);
+  repo.delete_from_step(&branch, 1).expect("delete_from_step");
+  repo.gc_artifacts(Duration::ZERO).expect("gc");
+  assert_eq!(repo.get(&key).expect("referenciado por lo borrado sin purgar"), blob);
+  std::thread::sleep(PURGE_RETENTION);
+  repo.purge_deleted(PURGE_RETENTION).expect("purge");
+  repo.gc_artifacts(Duration::ZERO).expect("gc");
+  assert_not_found(repo.get(&key), "get tras gc sin referencias");
+  // un put reciente sobrevive al gc mientras dure el margen
+  let fresh = repo.put(&blob).expect("put de nuevo");
+  repo.gc_artifacts(Duration::from_secs(3600)).expect("gc con margen");
+  assert_eq!(repo.get(&fresh).expect("dentro del margen"), blob);
+  assert!(repo.gc_artifacts(Duration::ZERO).expect("gc sin margen") >= 1);
+  assert_not_found(repo.get(&fresh), "get tras gc sin margen");
+}
@@ -0,0 +1,16 @@
+// Conformidad de `InMemoryFlowRepository` con el contrato de
+// `FlowRepository` usando la batería de `flow::testkit`. Los backends Diesel
+// la ejecutan en `chem-persistence/tests/conformance.rs`.
+use flow::repository::FlowRepository;
+use flow::stubs::InMemoryFlowRepository;
+use flow::testkit::{check_expired_work_lease, run_conformance_suite};
+use std::sync::Arc;
+use std::time::Duration;
+#[test]
+fn in_memory_repository_conforms() {
+  run_conformance_suite(|| Arc::new(InMemoryFlowRepository::new()) as Arc<dyn FlowRepository>);
+}
+#[test]
+fn in_memory_expired_lease_is_reclaimed() {
+  check_expired_work_lease(Arc::new(InMemoryFlowRepository::new().with_lease_duration(Duration::ZERO)));
+}
@@ -24,6 +24,7 @@ fn print_menu() {
   println!("7) Mostrar mapa simple de flujos");
   println!("9) Ver/actualizar status de un flow");
   println!("10) Eliminar pasos a partir de un cursor (en una rama)");
+  println!("11) Comparar dos ramas (diff por cursor y key)");
   println!("8) Salir");
 }
 fn run_cli(repo: Arc<dyn FlowRepository>) -> Result<(), Box<dyn Error>> {
@@ -43,6 +44,7 @@ fn run_cli(repo: Arc<dyn FlowRepository>) -> Result<(), Box<dyn Error>> {
       "7" => print_flow_map(repo.as_ref())?,
       "10" => delete_steps_from_cursor_interactive(repo.as_ref())?,
       "9" => view_update_status_interactive(repo.as_ref())?,
+      "11" => diff_branches_interactive(repo.as_ref())?,
       "8" => {
         println!("Saliendo...");
         break;
@@ -298,3 +300,37 @@ fn delete_steps_from_cursor_interactive(repo: &dyn FlowRepository) -> Result<(),
   }
   Ok(())
 }
+fn diff_branches_interactive(repo: &dyn FlowRepository) -> Result<(), Box<dyn Error>> {
+  let (flows, _data) = get_flows_and_data(repo)?;
+  if flows.len() < 2 {
+    println!("Se necesitan al menos dos flujos para comparar");
+    return Ok(());
+  }
+  println!("Selecciona las dos ramas a comparar por número:");
+  for (i, (id, parent, name, _status)) in flows.iter().enumerate() {
+    println!("[{}] {} name={:?} parent={:?}", i, id, name, parent);
+  }
+  let mut selected = Vec::new();
+  for label in ["Número de la rama base (izquierda): ", "Número de la rama a comparar (derecha): "] {
+    let idx: usize = prompt(label)?.trim().parse().map_err(|_| "Índice inválido")?;
+    if idx >= flows.len() {
+      println!("Índice fuera de rango");
+      return Ok(());
+    }
+    selected.push(flows[idx].0);
+  }
+  let diff = match flow::diff_branches(repo, &selected[0], &selected[1]) {
+    Ok(diff) => diff,
+    Err(e) => {
+      eprintln!("Error comparando ramas: {}", e);
+      return Ok(());
+    }
+  };
+  let format = prompt("Formato (enter = texto, 'json' = diff estructurado): ")?;
+  if format.trim().eq_ignore_ascii_case("json") {
+    println!("{}", serde_json::to_string_pretty(&diff)?);
+  } else {
+    print!("{}", diff.render());
+  }
+  Ok(())
+}